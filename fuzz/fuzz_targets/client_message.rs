@@ -0,0 +1,10 @@
+#![no_main]
+
+use kawio::protocol::{parse_client_message, ClientMsg};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|text: &str| {
+    if let Ok(ClientMsg::Move { coord, .. }) = parse_client_message(text) {
+        assert!(coord.pos() < 64);
+    }
+});
@@ -0,0 +1,25 @@
+#![no_main]
+
+use kawio::api::MoveCoord;
+use kawio::game::{Game, Move};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        if let Ok(pos) = Game::coord_to_pos(text) {
+            assert!(pos < 64);
+            assert!(Game::pos_to_coord(pos).eq_ignore_ascii_case(text));
+        }
+        if let Ok(mv) = text.parse::<Move>() {
+            assert_eq!(mv.to_string().parse::<Move>(), Ok(mv));
+        }
+        if let Ok(coord) = text.parse::<MoveCoord>() {
+            assert_eq!(coord.to_string().parse::<MoveCoord>(), Ok(coord));
+        }
+    }
+    // Any JSON value a client may send as a coordinate.
+    if let Ok(coord) = serde_json::from_slice::<MoveCoord>(data) {
+        let json = serde_json::to_string(&coord).unwrap();
+        assert_eq!(serde_json::from_str::<MoveCoord>(&json).unwrap(), coord);
+    }
+});
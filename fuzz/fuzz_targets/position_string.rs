@@ -0,0 +1,13 @@
+#![no_main]
+
+use kawio::game::Game;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|text: &str| {
+    if let Ok(game) = Game::from_position_string(text) {
+        assert_eq!(game.black & game.white, 0);
+        let parsed = Game::from_position_string(&game.to_position_string()).unwrap();
+        assert_eq!(parsed, game);
+        let _ = game.legal_moves();
+    }
+});
@@ -0,0 +1,128 @@
+//! Achievements earned in finished games.
+//!
+//! Achievements are evaluated once a game ends, from its move history and result, and kept
+//! per player. Each one is awarded at most once, for the first game that earns it.
+
+use crate::ai::Difficulty;
+use crate::api::Achievement;
+use crate::game::{Game, GameReplay, Move, Player};
+use crate::storage::GameResult;
+
+/// Number of consecutive wins needed for [`Achievement::WinStreak`].
+pub const STREAK_LENGTH: i32 = 10;
+
+const CORNERS: [&str; 4] = ["A1", "H1", "A8", "H8"];
+
+/// Returns true if `color` placed a disc on every corner in the game.
+fn took_all_corners(moves: &[Move], color: Player) -> bool {
+    let Ok(replay) = GameReplay::from_moves(moves.to_vec()) else {
+        return false;
+    };
+    let corners: Vec<u8> = CORNERS
+        .iter()
+        .filter_map(|coord| Game::coord_to_pos(coord).ok())
+        .collect();
+    let taken = moves
+        .iter()
+        .enumerate()
+        .filter(|&(ply, mv)| {
+            let Move::Place(pos) = *mv else {
+                return false;
+            };
+            corners.contains(&pos)
+                && replay
+                    .board_at(ply)
+                    .is_some_and(|game| game.current_player == color)
+        })
+        .count();
+    taken == corners.len()
+}
+
+/// Returns the achievements the player of `color` earned in a finished game, given the
+/// game's moves from the standard starting position and the player's win streak after it.
+#[must_use]
+pub fn earned(moves: &[Move], result: &GameResult, color: Player, streak: i32) -> Vec<Achievement> {
+    if result.winner != Some(color) {
+        return Vec::new();
+    }
+    let (own, opponent, opponent_discs) = match color {
+        Player::Black => (result.black_discs, result.white, result.white_discs),
+        Player::White => (result.white_discs, result.black, result.black_discs),
+    };
+    let mut achievements = vec![Achievement::FirstWin];
+    if took_all_corners(moves, color) {
+        achievements.push(Achievement::CornerSweep);
+    }
+    if own == 64 && opponent_discs == 0 {
+        achievements.push(Achievement::PerfectGame);
+    }
+    if streak >= STREAK_LENGTH {
+        achievements.push(Achievement::WinStreak);
+    }
+    if opponent == Difficulty::Hard.player_name() {
+        achievements.push(Achievement::BeatHardAi);
+    }
+    achievements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::bitboard;
+
+    fn result(winner: Option<Player>, black_discs: u32, white_discs: u32) -> GameResult<'static> {
+        GameResult {
+            black: "Alice",
+            white: "AI-Hard",
+            winner,
+            black_discs,
+            white_discs,
+            moves: 60,
+        }
+    }
+
+    #[test]
+    fn test_losing_earns_nothing() {
+        assert!(earned(&[], &result(Some(Player::White), 20, 44), Player::Black, 0).is_empty());
+        assert!(earned(&[], &result(None, 32, 32), Player::Black, 0).is_empty());
+    }
+
+    #[test]
+    fn test_win_achievements() {
+        let earned = earned(&[], &result(Some(Player::Black), 64, 0), Player::Black, STREAK_LENGTH);
+        assert_eq!(
+            earned,
+            [
+                Achievement::FirstWin,
+                Achievement::PerfectGame,
+                Achievement::WinStreak,
+                Achievement::BeatHardAi
+            ]
+        );
+        for achievement in earned {
+            assert_eq!(achievement.as_str().parse(), Ok(achievement));
+        }
+    }
+
+    #[test]
+    fn test_corners_are_credited_to_the_mover() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut game = Game::new();
+            let mut moves = Vec::new();
+            while !game.is_game_over() {
+                let legal = game.legal_moves();
+                let pos = legal[rng.gen_range(0..legal.len())];
+                moves.push(Move::Place(pos));
+                game.make_move(pos).unwrap();
+            }
+            // Corners never flip, so whoever holds all four at the end placed them.
+            for (color, discs) in [(Player::Black, game.black), (Player::White, game.white)] {
+                assert_eq!(took_all_corners(&moves, color), discs & bitboard::CORNERS == bitboard::CORNERS);
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! Introspection of a running server for its admins.
+//!
+//! The admin endpoints list the games in memory, the matchmaking queue and the sockets open
+//! on the server from [`Sessions`](crate::state::Sessions) itself. Recent warnings and errors
+//! are kept by an [`ErrorLog`], installed as a tracing layer when the server starts, and the
+//! requests to the match endpoints can be logged in full while a [`RequestLog`] is switched on.
+
+use crate::jobs;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Warnings and errors an [`ErrorLog`] keeps, dropping the oldest first.
+pub const ERROR_LOG_CAPACITY: usize = 200;
+
+/// A warning or error logged by the server.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoggedError {
+    /// When it was logged, in milliseconds since the Unix epoch.
+    pub at_ms: i64,
+    /// `"WARN"` or `"ERROR"`.
+    pub level: String,
+    /// Module that logged it.
+    pub target: String,
+    pub message: String,
+}
+
+/// The latest warnings and errors logged by the server. Clones share the same log.
+#[derive(Clone, Debug, Default)]
+pub struct ErrorLog {
+    entries: Arc<Mutex<VecDeque<LoggedError>>>,
+}
+
+impl ErrorLog {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps an entry, dropping the oldest once [`ERROR_LOG_CAPACITY`] are kept.
+    pub fn record(&self, entry: LoggedError) {
+        let mut entries = self.entries.lock();
+        if entries.len() == ERROR_LOG_CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Returns up to `limit` of the latest entries, newest first.
+    #[must_use]
+    pub fn recent(&self, limit: usize) -> Vec<LoggedError> {
+        self.entries.lock().iter().rev().take(limit).cloned().collect()
+    }
+}
+
+/// Records the warnings and errors of every module.
+impl<S: Subscriber> Layer<S> for ErrorLog {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if *metadata.level() > Level::WARN {
+            return;
+        }
+        let mut message = MessageVisitor::default();
+        event.record(&mut message);
+        self.record(LoggedError {
+            at_ms: jobs::now_ms(),
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            message: message.0,
+        });
+    }
+}
+
+/// Reads the message of an event.
+#[derive(Default)]
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{value:?}");
+        }
+    }
+}
+
+/// Longest part of a request body that is logged, in bytes.
+pub const MAX_LOGGED_BODY: usize = 2048;
+
+/// Fields whose values are never logged, matched anywhere in a field's name in any case.
+const SECRET_FIELDS: [&str; 4] = ["token", "password", "secret", "authorization"];
+
+/// Switches the logging of every request to the match endpoints, with its body, status,
+/// latency and player, to diagnose clients falling out of step with the server. Clones share
+/// the same switch.
+#[derive(Clone, Debug, Default)]
+pub struct RequestLog {
+    enabled: Arc<AtomicBool>,
+}
+
+impl RequestLog {
+    #[must_use]
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled: Arc::new(AtomicBool::new(enabled)),
+        }
+    }
+
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+}
+
+/// Returns a request body as it may be logged: the values of secret fields of a JSON body
+/// hidden, and cut to [`MAX_LOGGED_BODY`] bytes.
+#[must_use]
+pub fn sanitize_body(body: &[u8]) -> String {
+    let mut text = match serde_json::from_slice::<Value>(body) {
+        Ok(mut json) => {
+            redact(&mut json);
+            json.to_string()
+        }
+        Err(_) => String::from_utf8_lossy(body).into_owned(),
+    };
+    if text.len() > MAX_LOGGED_BODY {
+        let mut end = MAX_LOGGED_BODY;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push('…');
+    }
+    text
+}
+
+fn redact(json: &mut Value) {
+    match json {
+        Value::Object(fields) => {
+            for (name, value) in fields {
+                let name = name.to_lowercase();
+                if SECRET_FIELDS.iter().any(|secret| name.contains(secret)) {
+                    *value = Value::String("[redacted]".to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn test_error_log_keeps_latest_warnings() {
+        let log = ErrorLog::new();
+        let subscriber = tracing_subscriber::registry().with(log.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("Server running");
+            tracing::warn!("Failed to save move {} of {}", 3, "g1");
+            tracing::error!("AI search failed");
+        });
+        let recent = log.recent(10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!((recent[0].level.as_str(), recent[0].message.as_str()), ("ERROR", "AI search failed"));
+        assert_eq!(recent[1].message, "Failed to save move 3 of g1");
+        assert_eq!(recent[1].target, module_path!());
+
+        for i in 0..ERROR_LOG_CAPACITY {
+            log.record(LoggedError {
+                at_ms: 0,
+                level: "WARN".to_string(),
+                target: "test".to_string(),
+                message: i.to_string(),
+            });
+        }
+        let recent = log.recent(usize::MAX);
+        assert_eq!(recent.len(), ERROR_LOG_CAPACITY);
+        assert_eq!(recent[0].message, (ERROR_LOG_CAPACITY - 1).to_string());
+        assert_eq!(log.recent(1).len(), 1);
+    }
+
+    #[test]
+    fn test_request_bodies_are_sanitized() {
+        let body = br#"{"player2":"AI","auth":{"Token":"abc","password":"hunter2"},"moves":[{"secret_note":1}]}"#;
+        assert_eq!(
+            sanitize_body(body),
+            r#"{"auth":{"Token":"[redacted]","password":"[redacted]"},"moves":[{"secret_note":"[redacted]"}],"player2":"AI"}"#
+        );
+        assert_eq!(sanitize_body(b"not json"), "not json");
+        let truncated = sanitize_body("é".repeat(MAX_LOGGED_BODY).as_bytes());
+        assert!(truncated.len() <= MAX_LOGGED_BODY + '…'.len_utf8());
+        assert!(truncated.ends_with('…'));
+
+        let log = RequestLog::new(false);
+        log.clone().set_enabled(true);
+        assert!(log.is_enabled());
+    }
+}
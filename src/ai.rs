@@ -1,13 +1,105 @@
-use crate::game::{Game, Move};
-use crate::mcts::MCTS;
+use crate::game::{Game, Move, Phase, Player};
+use crate::mcts::{CancelToken, MoveStats, RolloutConfig, SelectionPolicy, Telemetry, TreeSnapshot, MCTS};
+use crate::solver;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+pub mod ntuple;
+
+/// Version of the AI, stored with its games: the version of the server it ships in.
+pub const ENGINE_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// Configuration for the MCTS AI.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AiConfig {
     pub simulations: u32,
     pub exploration_constant: f64,
+    /// How the search picks the moves to explore.
+    #[serde(default)]
+    pub selection: SelectionPolicy,
+    /// Expands every child of a leaf in one simulation, as the search used to, instead of
+    /// one child per simulation; see [`MCTS::with_expand_all`].
+    #[serde(default)]
+    pub expand_all: bool,
     pub temperature: f64,
     pub rng_seed: Option<u64>,
+    /// How positions are played out during simulation.
+    #[serde(default)]
+    pub rollout: RolloutConfig,
+    /// How long the AI takes to reply in live games.
+    #[serde(default)]
+    pub pacing: Pacing,
+    /// Leaves the search expands before evaluating them together, see
+    /// [`MCTS::with_batch_size`].
+    #[serde(default = "default_batch_size")]
+    pub batch_size: u32,
+    /// Plays the endgame perfectly with the exact solver instead of searching it.
+    #[serde(default)]
+    pub solve_endgame: bool,
+    /// How much the AI varies its opening moves between games.
+    #[serde(default)]
+    pub opening: OpeningVariety,
+}
+
+fn default_batch_size() -> u32 {
+    1
+}
+
+/// Bounds on how long the AI takes to reply, so replies do not arrive instantly. The delay is
+/// picked at random between the bounds and includes the search time, so slow searches are
+/// not delayed further. Zero bounds reply as soon as the search finishes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pacing {
+    pub min_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Pacing {
+    /// Returns how much longer to wait before replying after a search that took `compute`.
+    #[must_use]
+    pub fn remaining_delay<R: Rng>(&self, compute: Duration, rng: &mut R) -> Duration {
+        let max = self.max_delay_ms.max(self.min_delay_ms);
+        let target = Duration::from_millis(rng.gen_range(self.min_delay_ms..=max));
+        target.saturating_sub(compute)
+    }
+}
+
+/// Variety in the AI's opening, so repeated games against it do not all follow the same
+/// lines. For the first `plies` discs placed in a game, the AI picks at random among the
+/// searched moves whose expected outcome is within `margin` of the move it would have played,
+/// each as often as the search visited it. Zero plies, the default, always plays the best
+/// move found.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct OpeningVariety {
+    pub plies: u32,
+    /// Largest drop in expected outcome, from 0 to 1, of a move picked instead.
+    pub margin: f64,
+}
+
+impl std::str::FromStr for Pacing {
+    type Err = String;
+
+    /// Parses `MIN-MAX` or a single fixed delay, in milliseconds.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |ms: &str| {
+            ms.trim()
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid delay '{ms}', expected milliseconds"))
+        };
+        let (min_delay_ms, max_delay_ms) = match s.split_once('-') {
+            Some((min, max)) => (parse(min)?, parse(max)?),
+            None => (parse(s)?, parse(s)?),
+        };
+        if min_delay_ms > max_delay_ms {
+            return Err(format!("Minimum delay {min_delay_ms} exceeds maximum {max_delay_ms}"));
+        }
+        Ok(Self {
+            min_delay_ms,
+            max_delay_ms,
+        })
+    }
 }
 
 impl Default for AiConfig {
@@ -15,27 +107,164 @@ impl Default for AiConfig {
         Self {
             simulations: 100,
             exploration_constant: 1.414,
+            selection: SelectionPolicy::Uct,
+            expand_all: false,
             temperature: 0.0,
             rng_seed: None,
+            rollout: RolloutConfig::Random,
+            pacing: Pacing::default(),
+            batch_size: default_batch_size(),
+            solve_endgame: false,
+            opening: OpeningVariety::default(),
+        }
+    }
+}
+
+/// Strength levels of the built-in AI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard];
+
+    /// Returns the player name the AI plays under at this difficulty, e.g. `AI-Easy`.
+    #[must_use]
+    pub fn player_name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "AI-Easy",
+            Difficulty::Medium => "AI-Medium",
+            Difficulty::Hard => "AI-Hard",
+        }
+    }
+
+    /// Returns the difficulty the AI plays at under `name`, if it is one of theirs.
+    #[must_use]
+    pub fn from_player_name(name: &str) -> Option<Difficulty> {
+        Difficulty::ALL.into_iter().find(|difficulty| difficulty.player_name() == name)
+    }
+
+    /// Returns the fixed rating of the AI at this difficulty, which anchors the ladder.
+    #[must_use]
+    pub fn anchor_rating(&self) -> f64 {
+        match self {
+            Difficulty::Easy => 1000.0,
+            Difficulty::Medium => 1400.0,
+            Difficulty::Hard => 1800.0,
         }
     }
+
+    /// Returns the search configuration for this difficulty. Weaker AIs take longer to reply,
+    /// as a beginner would, and only the hard AI solves the endgame.
+    #[must_use]
+    pub fn ai_config(&self) -> AiConfig {
+        let (simulations, min_delay_ms, max_delay_ms) = match self {
+            Difficulty::Easy => (25, 800, 2500),
+            Difficulty::Medium => (100, 600, 1800),
+            Difficulty::Hard => (400, 400, 1200),
+        };
+        AiConfig {
+            simulations,
+            pacing: Pacing {
+                min_delay_ms,
+                max_delay_ms,
+            },
+            solve_endgame: *self == Difficulty::Hard,
+            opening: self.opening_variety(),
+            ..AiConfig::default()
+        }
+    }
+
+    /// Returns how much the AI varies its openings at this difficulty: the further its
+    /// rating is below the hard AI's, the longer it varies them and the further from the
+    /// best move it strays. The hard AI only picks among moves about as good as its best.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn opening_variety(&self) -> OpeningVariety {
+        let gap = Difficulty::Hard.anchor_rating() - self.anchor_rating();
+        OpeningVariety {
+            plies: 6 + (gap / 80.0) as u32,
+            margin: 0.02 + gap / 8000.0,
+        }
+    }
+}
+
+/// Totals of the searches an AI ran over a game, stored with its result to follow the AI's
+/// strength across releases.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchSummary {
+    /// Moves searched for; forced moves and solved endgames are played without a search.
+    pub searches: u32,
+    /// Simulations behind the searched moves, including those of reused subtrees.
+    pub simulations: u64,
+    /// Time spent searching; left at 0 on wasm32, which has no clock to read.
+    pub search_ms: u64,
+    /// Sum of the expected outcomes of the searched moves, for the AI.
+    pub chosen_q: f64,
+}
+
+impl SearchSummary {
+    fn record(&mut self, telemetry: &Telemetry, elapsed: Option<Duration>) {
+        self.searches += 1;
+        self.simulations += u64::from(telemetry.total_simulations);
+        if let Some(elapsed) = elapsed {
+            self.search_ms += u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+        }
+        self.chosen_q += telemetry.chosen_q_value;
+    }
+
+    /// Returns the average expected outcome of the searched moves, or `None` before the first
+    /// search.
+    #[must_use]
+    pub fn mean_q(&self) -> Option<f64> {
+        (self.searches > 0).then(|| self.chosen_q / f64::from(self.searches))
+    }
 }
 
 /// MCTS-based AI that maintains state for tree reuse.
 pub struct MctsAi {
     config: AiConfig,
     mcts: Option<MCTS>,
+    summary: SearchSummary,
+    /// Picks among the opening moves, seeded like the search.
+    rng: StdRng,
 }
 
 impl MctsAi {
     /// Creates a new MCTS AI with the given configuration.
+    #[must_use]
     pub fn new(config: AiConfig) -> Self {
+        let rng = config.rng_seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
         Self {
             config,
             mcts: None,
+            summary: SearchSummary::default(),
+            rng,
         }
     }
 
+    /// Returns the configuration the AI searches with.
+    #[must_use]
+    pub fn config(&self) -> &AiConfig {
+        &self.config
+    }
+
+    /// Returns the totals of the searches the AI ran.
+    #[must_use]
+    pub fn summary(&self) -> &SearchSummary {
+        &self.summary
+    }
+
+    /// Changes the move-selection temperature used by subsequent searches.
+    /// The search tree is kept, so this can be adjusted every move.
+    pub fn set_temperature(&mut self, temperature: f64) {
+        self.config.temperature = temperature;
+    }
+
     /// Notifies the AI that a move was made, allowing tree reuse.
     pub fn make_move(&mut self, mv: Move) {
         if let Some(ref mut mcts) = self.mcts {
@@ -46,19 +275,167 @@ impl MctsAi {
         }
     }
 
+    /// Returns the statistics of the AI's search tree, with only the `top_k` most visited
+    /// moves at every level if given, or `None` before it has searched.
+    #[must_use]
+    pub fn snapshot(&self, top_k: Option<usize>) -> Option<TreeSnapshot> {
+        self.mcts.as_ref().map(|mcts| mcts.snapshot(top_k))
+    }
+
+    /// Replaces the AI's search tree with one rebuilt from a snapshot, so a search of the
+    /// snapshot's position carries on where it left off.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot is malformed.
+    pub fn restore(&mut self, snapshot: &TreeSnapshot) -> Result<(), String> {
+        let config = &self.config;
+        let mcts = MCTS::from_snapshot(snapshot, config.exploration_constant, config.rng_seed)?
+            .with_selection(config.selection)
+            .with_expand_all(config.expand_all)
+            .with_rollout_policy(config.rollout.build())
+            .with_batch_size(config.batch_size);
+        self.mcts = Some(mcts);
+        Ok(())
+    }
+
     /// Gets the best move for the current game state.
     /// Reuses the MCTS tree if possible.
     pub fn get_move(&mut self, game: &Game) -> Option<Move> {
-        let moves = game.legal_moves();
-        if moves.is_empty() {
-            Some(Move::Pass)
-        } else {
-            // Ensure MCTS exists and matches current game
-            if self.mcts.is_none() || *self.mcts.as_ref().unwrap().root_game() != *game {
-                self.mcts = Some(MCTS::new(game.clone(), self.config.exploration_constant, self.config.rng_seed));
+        self.get_move_cancellable(game, &CancelToken::new())
+    }
+
+    /// Like [`MctsAi::get_move`], but returns `None` if `cancel` is cancelled before the search
+    /// finishes.
+    ///
+    /// A forced move, either the only legal one or one that leaves the opponent without discs,
+    /// is played at once without searching. With [`AiConfig::solve_endgame`], so is the
+    /// solver's move once the game reaches the endgame.
+    pub fn get_move_cancellable(&mut self, game: &Game, cancel: &CancelToken) -> Option<Move> {
+        let moves = game.legal_move_bitboard();
+        if moves == 0 {
+            return Some(Move::Pass);
+        }
+        if let Some(pos) = Self::forced_move(game, moves) {
+            return Some(Move::Place(pos));
+        }
+        if self.config.solve_endgame && game.phase() == Phase::Endgame {
+            if let Some(mv) = solver::solve(game).best_move {
+                return Some(mv);
             }
-            Some(self.mcts.as_mut().unwrap().search(self.config.simulations, self.config.temperature).best_move)
         }
+        // Ensure MCTS exists and matches current game
+        if let Some(mcts) = &mut self.mcts {
+            // Games passing automatically do not report the pass, so the tree follows it.
+            if mcts.root_game() != game && mcts.root_game().legal_move_bitboard() == 0 {
+                mcts.advance_root(Move::Pass);
+            }
+            if mcts.root_game() != game {
+                self.mcts = None;
+            }
+        }
+        let config = &self.config;
+        let mcts = self
+            .mcts
+            .get_or_insert_with(|| {
+                MCTS::new(game.clone(), config.exploration_constant, config.rng_seed)
+                    .with_selection(config.selection)
+                    .with_expand_all(config.expand_all)
+                    .with_rollout_policy(config.rollout.build())
+                    .with_batch_size(config.batch_size)
+            });
+        // `Instant::now` panics on wasm32 outside WASI.
+        let started = (!cfg!(target_arch = "wasm32")).then(Instant::now);
+        let result = mcts.search_cancellable(config.simulations, config.temperature, cancel)?;
+        self.summary.record(&result.telemetry, started.map(|started| started.elapsed()));
+        Some(self.varied_opening_move(game).unwrap_or(result.best_move))
+    }
+
+    /// Picks a move at random among the searched moves nearly as good as the best, as
+    /// [`AiConfig::opening`] says, or returns `None` once the opening is over.
+    fn varied_opening_move(&mut self, game: &Game) -> Option<Move> {
+        let variety = self.config.opening;
+        if 60u32.saturating_sub(game.empties()) >= variety.plies {
+            return None;
+        }
+        let statistics = self.root_statistics();
+        let best = statistics.first()?.q;
+        let candidates: Vec<&MoveStats> = statistics
+            .iter()
+            .filter(|stats| stats.visits > 0 && stats.q >= best - variety.margin)
+            .collect();
+        let visits: u32 = candidates.iter().map(|stats| stats.visits).sum();
+        let mut pick = self.rng.gen_range(0..visits.max(1));
+        candidates.into_iter().find_map(|stats| {
+            if pick < stats.visits {
+                Some(stats.mv)
+            } else {
+                pick -= stats.visits;
+                None
+            }
+        })
+    }
+
+    /// Returns the statistics of the root moves of the kept search tree, most visited first, or
+    /// an empty list before the first search.
+    #[must_use]
+    pub fn root_statistics(&self) -> Vec<MoveStats> {
+        self.mcts.as_ref().map(MCTS::root_statistics).unwrap_or_default()
+    }
+
+    /// Returns the most visited root move of the kept search tree if it is searching `game`,
+    /// e.g. the best move so far of a search that was cancelled.
+    #[must_use]
+    pub fn best_move_so_far(&self, game: &Game) -> Option<Move> {
+        let mcts = self.mcts.as_ref().filter(|mcts| mcts.root_game() == game)?;
+        let best = mcts.root_statistics().into_iter().next()?;
+        (best.visits > 0).then_some(best.mv)
+    }
+
+    /// Picks the best of the given root moves, e.g. to answer "what if I play X?".
+    ///
+    /// Illegal moves in `moves` are ignored. The search uses a fresh tree and leaves the kept
+    /// one alone. Returns `None` if none of the moves is legal or `cancel` is cancelled.
+    pub fn get_move_among(&self, game: &Game, moves: &[u8], cancel: &CancelToken) -> Option<Move> {
+        let allowed = moves
+            .iter()
+            .filter(|&&pos| pos < 64)
+            .fold(0u64, |bits, &pos| bits | (1u64 << pos))
+            & game.legal_move_bitboard();
+        if allowed == 0 {
+            return None;
+        }
+        if let Some(pos) = Self::forced_move(game, allowed) {
+            return Some(Move::Place(pos));
+        }
+        let config = &self.config;
+        MCTS::new(game.clone(), config.exploration_constant, config.rng_seed)
+            .with_selection(config.selection)
+            .with_expand_all(config.expand_all)
+            .with_rollout_policy(config.rollout.build())
+            .with_batch_size(config.batch_size)
+            .with_root_moves(allowed)
+            .search_cancellable(config.simulations, config.temperature, cancel)
+            .map(|result| result.best_move)
+    }
+
+    /// Returns the move to play without searching among the legal `moves`: the only one, or
+    /// one that captures every opponent disc.
+    fn forced_move(game: &Game, moves: u64) -> Option<u8> {
+        let mut candidates = game.legal_moves_iter().filter(|&pos| moves & (1u64 << pos) != 0);
+        if moves.is_power_of_two() {
+            return candidates.next();
+        }
+        candidates.find(|&pos| {
+            let mut next = game.clone();
+            next.place(pos).is_ok() && {
+                let (black, white) = next.disc_count();
+                match game.current_player {
+                    Player::Black => white == 0,
+                    Player::White => black == 0,
+                }
+            }
+        })
     }
 }
 
@@ -66,8 +443,161 @@ impl MctsAi {
 pub struct AI;
 
 impl AI {
+    #[must_use]
     pub fn get_move(game: &Game) -> Option<Move> {
         let mut ai = MctsAi::new(AiConfig::default());
         ai.get_move(game)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ai() -> MctsAi {
+        MctsAi::new(AiConfig {
+            simulations: 20,
+            rng_seed: Some(3),
+            ..AiConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_plays_only_move_without_searching() {
+        // Black's only move is A1, capturing White's last disc at A2.
+        let mut game = Game::new();
+        game.black = 1u64 << Game::coord_to_pos("A3").unwrap();
+        game.white = 1u64 << Game::coord_to_pos("A2").unwrap();
+        let a1 = Game::coord_to_pos("A1").unwrap();
+        let mut ai = ai();
+        assert_eq!(ai.get_move(&game), Some(Move::Place(a1)));
+        // Even a cancelled search finds a forced move.
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert_eq!(ai.get_move_cancellable(&game, &cancel), Some(Move::Place(a1)));
+    }
+
+    #[test]
+    fn test_plays_wipeout() {
+        // D6 captures both White discs; C5 only takes D5.
+        let bits = |coords: &[&str]| {
+            coords
+                .iter()
+                .fold(0u64, |bits, coord| bits | (1u64 << Game::coord_to_pos(coord).unwrap()))
+        };
+        let mut game = Game::new();
+        game.black = bits(&["D3", "E5"]);
+        game.white = bits(&["D4", "D5"]);
+        assert!(game.legal_moves().len() > 1);
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let d6 = Game::coord_to_pos("D6").unwrap();
+        assert_eq!(ai().get_move_cancellable(&game, &cancel), Some(Move::Place(d6)));
+    }
+
+    #[test]
+    fn test_solves_endgame() {
+        let mut game = Game::new();
+        while game.phase() != Phase::Endgame || game.legal_moves().len() < 2 {
+            assert!(!game.is_game_over());
+            let moves = game.legal_moves();
+            if moves.is_empty() {
+                game.pass();
+            } else {
+                game.make_move(moves[moves.len() / 2]).unwrap();
+            }
+        }
+        let mut solver_ai = MctsAi::new(AiConfig {
+            solve_endgame: true,
+            ..ai().config
+        });
+        // The solver answers even when the search is cancelled.
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert_eq!(solver_ai.get_move_cancellable(&game, &cancel), solver::solve(&game).best_move);
+        assert!(Difficulty::Hard.ai_config().solve_endgame);
+        assert!(!Difficulty::Easy.ai_config().solve_endgame);
+    }
+
+    #[test]
+    fn test_tree_is_kept_across_passes() {
+        // After Black takes B1 with C1, White, left with B2 and H8, must pass.
+        let bits = |coords: &[&str]| {
+            coords
+                .iter()
+                .fold(0u64, |bits, coord| bits | (1u64 << Game::coord_to_pos(coord).unwrap()))
+        };
+        let black = bits(&["A1", "A8", "B8", "C8", "D8", "E8", "F8", "G8"]);
+        let mut game = Game::from_bitboards(black, bits(&["B1", "B2", "H8"]), Player::Black, 0);
+        let c1 = Game::coord_to_pos("C1").unwrap();
+        let mut ai = ai();
+        ai.get_move(&game);
+        ai.make_move(Move::Place(c1));
+        game.make_move(c1).unwrap();
+        assert_eq!(game.current_player, Player::Black);
+        // The pass was not reported, yet the search carries on from the subtree.
+        ai.get_move(&game);
+        let visits: u32 = ai.root_statistics().iter().map(|stats| stats.visits).sum();
+        assert!(visits > ai.config().simulations, "{visits}");
+    }
+
+    #[test]
+    fn test_restricted_root_moves() {
+        let game = Game::new();
+        let d3 = Game::coord_to_pos("D3").unwrap();
+        let c4 = Game::coord_to_pos("C4").unwrap();
+        let ai = ai();
+        let cancel = CancelToken::new();
+        assert_eq!(ai.get_move_among(&game, &[d3], &cancel), Some(Move::Place(d3)));
+        let choice = ai.get_move_among(&game, &[d3, c4, 0], &cancel);
+        assert!(matches!(choice, Some(Move::Place(pos)) if pos == d3 || pos == c4));
+        assert_eq!(ai.get_move_among(&game, &[0], &cancel), None);
+    }
+
+    #[test]
+    fn test_varied_openings() {
+        let easy = Difficulty::Easy.opening_variety();
+        let hard = Difficulty::Hard.opening_variety();
+        assert!(easy.plies > hard.plies && easy.margin > hard.margin);
+        assert!(hard.plies > 0);
+
+        let varied = |seed| {
+            MctsAi::new(AiConfig {
+                simulations: 40,
+                rng_seed: Some(seed),
+                opening: OpeningVariety { plies: 4, margin: 1.0 },
+                ..AiConfig::default()
+            })
+        };
+        let game = Game::new();
+        let openings: std::collections::HashSet<_> =
+            (0..12).filter_map(|seed| varied(seed).get_move(&game)).collect();
+        assert!(openings.len() > 1);
+        assert!(openings.iter().all(|&mv| matches!(mv, Move::Place(pos) if game.is_valid_move(pos))));
+
+        // Past the opening the AI plays the best move found.
+        let mut game = Game::new();
+        for coord in ["D3", "C5", "F6", "F5"] {
+            game.place(Game::coord_to_pos(coord).unwrap()).unwrap();
+        }
+        let mut ai = varied(1);
+        ai.get_move(&game);
+        assert_eq!(ai.varied_opening_move(&game), None);
+    }
+
+    #[test]
+    fn test_pacing() {
+        let mut rng = rand::thread_rng();
+        let pacing: Pacing = "200-400".parse().unwrap();
+        for _ in 0..20 {
+            let delay = pacing.remaining_delay(Duration::from_millis(50), &mut rng);
+            assert!((150..=350).contains(&delay.as_millis()));
+        }
+        // A slow search is not delayed any further.
+        assert!(pacing.remaining_delay(Duration::from_secs(1), &mut rng).is_zero());
+        assert!(Pacing::default().remaining_delay(Duration::ZERO, &mut rng).is_zero());
+        assert_eq!("300".parse::<Pacing>().unwrap().max_delay_ms, 300);
+        assert!("400-200".parse::<Pacing>().is_err());
+        assert!("soon".parse::<Pacing>().is_err());
+    }
+}
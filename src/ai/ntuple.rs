@@ -0,0 +1,419 @@
+//! Pattern-based (n-tuple) evaluation with trainable weights.
+//!
+//! A position is cut into small groups of squares — edges, corners, diagonals and 2x5 boxes —
+//! placed in every orientation the board's symmetries allow. Each placement reads its squares
+//! as a base-3 number (empty, own disc, opponent disc) and looks up a weight shared by all
+//! placements of the pattern. The weights are fitted by logistic regression on the outcomes
+//! of stored self-play games, see [`fit`].
+
+use crate::eval::Evaluator;
+use crate::game::{Game, GameReplay, Move, Player};
+#[cfg(feature = "storage")]
+use crate::storage::Storage;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Version of the weights file format.
+pub const WEIGHTS_VERSION: u32 = 1;
+
+/// A group of squares, as (row, column) with row 0 the top of the board, in one orientation.
+struct Pattern {
+    name: &'static str,
+    squares: &'static [(u8, u8)],
+}
+
+const PATTERNS: [Pattern; 8] = [
+    Pattern {
+        name: "edge_2x",
+        squares: &[(0, 0), (0, 1), (0, 2), (0, 3), (0, 4), (0, 5), (0, 6), (0, 7), (1, 1), (1, 6)],
+    },
+    Pattern {
+        name: "corner_3x3",
+        squares: &[(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (1, 2), (2, 0), (2, 1), (2, 2)],
+    },
+    Pattern {
+        name: "box_2x5",
+        squares: &[(0, 0), (0, 1), (0, 2), (0, 3), (0, 4), (1, 0), (1, 1), (1, 2), (1, 3), (1, 4)],
+    },
+    Pattern {
+        name: "diagonal_8",
+        squares: &[(0, 0), (1, 1), (2, 2), (3, 3), (4, 4), (5, 5), (6, 6), (7, 7)],
+    },
+    Pattern {
+        name: "diagonal_7",
+        squares: &[(0, 1), (1, 2), (2, 3), (3, 4), (4, 5), (5, 6), (6, 7)],
+    },
+    Pattern {
+        name: "diagonal_6",
+        squares: &[(0, 2), (1, 3), (2, 4), (3, 5), (4, 6), (5, 7)],
+    },
+    Pattern {
+        name: "diagonal_5",
+        squares: &[(0, 3), (1, 4), (2, 5), (3, 6), (4, 7)],
+    },
+    Pattern {
+        name: "diagonal_4",
+        squares: &[(0, 4), (1, 5), (2, 6), (3, 7)],
+    },
+];
+
+/// One placement of a pattern on the board: the pattern's index and its squares, in the
+/// pattern's order.
+type Placement = (usize, Vec<u8>);
+
+/// A symmetry of the board, mapping a (row, column) square to its image.
+type Symmetry = fn(u8, u8) -> (u8, u8);
+
+/// Returns every distinct placement of every pattern under the eight board symmetries.
+fn placements() -> &'static [Placement] {
+    static PLACEMENTS: OnceLock<Vec<Placement>> = OnceLock::new();
+    PLACEMENTS.get_or_init(|| {
+        let symmetries: [Symmetry; 8] = [
+            |r, c| (r, c),
+            |r, c| (r, 7 - c),
+            |r, c| (7 - r, c),
+            |r, c| (7 - r, 7 - c),
+            |r, c| (c, r),
+            |r, c| (c, 7 - r),
+            |r, c| (7 - c, r),
+            |r, c| (7 - c, 7 - r),
+        ];
+        let mut placements: Vec<Placement> = Vec::new();
+        for (index, pattern) in PATTERNS.iter().enumerate() {
+            for symmetry in symmetries {
+                let squares: Vec<u8> = pattern
+                    .squares
+                    .iter()
+                    .map(|&(r, c)| {
+                        let (r, c) = symmetry(r, c);
+                        r * 8 + c
+                    })
+                    .collect();
+                // A symmetric pattern maps onto itself, e.g. the main diagonal under
+                // transposition. The same squares read in another order are kept, so the
+                // evaluation stays symmetric whatever the weights.
+                let placement = (index, squares);
+                if !placements.contains(&placement) {
+                    placements.push(placement);
+                }
+            }
+        }
+        placements
+    })
+}
+
+/// Weights of an n-tuple evaluation, one table per pattern.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NTupleEvaluator {
+    pub version: u32,
+    /// Added to every position's score, e.g. for the advantage of moving.
+    pub bias: f64,
+    /// Weights of each pattern, indexed by the contents of its squares read as a base-3
+    /// number with the first square the lowest digit.
+    pub tables: Vec<Vec<f32>>,
+}
+
+impl Default for NTupleEvaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NTupleEvaluator {
+    /// Creates an evaluator with every weight zero, which rates every position even.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            version: WEIGHTS_VERSION,
+            bias: 0.0,
+            tables: PATTERNS
+                .iter()
+                .map(|pattern| vec![0.0; 3usize.pow(u32::try_from(pattern.squares.len()).unwrap_or(0))])
+                .collect(),
+        }
+    }
+
+    /// Loads weights from disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, is not a valid weights file, was written
+    /// by an unsupported format version, or does not match the patterns.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let evaluator: NTupleEvaluator = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if evaluator.version != WEIGHTS_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported weights version {}", evaluator.version),
+            ));
+        }
+        let expected = Self::new();
+        if evaluator.tables.len() != expected.tables.len()
+            || evaluator.tables.iter().zip(&expected.tables).any(|(a, b)| a.len() != b.len())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Weights do not match the evaluation patterns",
+            ));
+        }
+        Ok(evaluator)
+    }
+
+    /// Writes the weights to disk, replacing any previous file atomically.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(tmp, path)
+    }
+
+    /// Returns the table and index of the weight each placement reads, for the side to move.
+    fn features(game: &Game) -> Vec<(usize, usize)> {
+        let (own, other) = match game.current_player {
+            Player::Black => (game.black, game.white),
+            Player::White => (game.white, game.black),
+        };
+        placements()
+            .iter()
+            .map(|(pattern, squares)| {
+                let index = squares.iter().rev().fold(0, |index, &pos| {
+                    let bit = 1u64 << pos;
+                    let digit = if own & bit != 0 {
+                        1
+                    } else if other & bit != 0 {
+                        2
+                    } else {
+                        0
+                    };
+                    index * 3 + digit
+                });
+                (*pattern, index)
+            })
+            .collect()
+    }
+
+    fn score(&self, features: &[(usize, usize)]) -> f64 {
+        self.bias
+            + features
+                .iter()
+                .map(|&(table, index)| f64::from(self.tables[table][index]))
+                .sum::<f64>()
+    }
+
+    /// Returns the estimated probability that the side to move wins, counting a draw as half.
+    #[must_use]
+    pub fn win_probability(&self, game: &Game) -> f64 {
+        sigmoid(self.score(&Self::features(game)))
+    }
+
+    /// Returns the names of the patterns, in the order of [`NTupleEvaluator::tables`].
+    #[must_use]
+    pub fn pattern_names() -> Vec<&'static str> {
+        PATTERNS.iter().map(|pattern| pattern.name).collect()
+    }
+}
+
+impl Evaluator for NTupleEvaluator {
+    fn evaluate(&self, game: &Game) -> f64 {
+        if game.is_game_over() {
+            let (black, white) = game.disc_count();
+            let (own, other) = match game.current_player {
+                Player::Black => (black, white),
+                Player::White => (white, black),
+            };
+            return match own.cmp(&other) {
+                std::cmp::Ordering::Greater => 1.0,
+                std::cmp::Ordering::Less => -1.0,
+                std::cmp::Ordering::Equal => 0.0,
+            };
+        }
+        2.0 * self.win_probability(game) - 1.0
+    }
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// A position to learn from and how the game went for the side to move: 1 for a win, 0.5
+/// for a draw and 0 for a loss.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Sample {
+    pub game: Game,
+    pub target: f64,
+}
+
+/// Returns a sample for every position of a game where the side to move had a choice of
+/// moves.
+///
+/// # Errors
+///
+/// Returns an error if the moves are not a legal game.
+pub fn samples(moves: &[Move]) -> Result<Vec<Sample>, String> {
+    let replay = GameReplay::from_moves(moves.to_vec())?;
+    let winner = replay.last().winner();
+    Ok((0..replay.len())
+        .filter_map(|ply| replay.board_at(ply))
+        .filter(|game| !game.is_game_over() && game.legal_moves_iter().nth(1).is_some())
+        .map(|game| Sample {
+            game: game.clone(),
+            target: match winner {
+                Some(player) if player == game.current_player => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            },
+        })
+        .collect())
+}
+
+/// Returns the samples of every stored self-play game, skipping games that cannot be
+/// replayed.
+///
+/// # Errors
+///
+/// Returns an error if the games cannot be loaded.
+#[cfg(feature = "storage")]
+pub fn load_samples(storage: &Storage) -> rusqlite::Result<Vec<Sample>> {
+    let mut all = Vec::new();
+    for game_id in storage.self_play_game_ids()? {
+        match samples(&storage.load_moves(&game_id)?) {
+            Ok(game_samples) => all.extend(game_samples),
+            Err(e) => tracing::warn!("Skipping game {game_id} while loading samples: {e}"),
+        }
+    }
+    Ok(all)
+}
+
+/// Settings for fitting the weights.
+#[derive(Clone, Debug)]
+pub struct FitConfig {
+    /// Passes over the samples.
+    pub epochs: u32,
+    /// Step size of each stochastic gradient update.
+    pub learning_rate: f64,
+    /// Seed for the order the samples are visited in.
+    pub seed: u64,
+}
+
+impl Default for FitConfig {
+    fn default() -> Self {
+        Self {
+            epochs: 10,
+            learning_rate: 0.01,
+            seed: 0,
+        }
+    }
+}
+
+/// Fits the weights to the samples by logistic regression with stochastic gradient descent,
+/// starting from the current weights. Returns the mean log loss over the samples after each
+/// epoch.
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+pub fn fit(evaluator: &mut NTupleEvaluator, samples: &[Sample], config: &FitConfig) -> Vec<f64> {
+    let features: Vec<Vec<(usize, usize)>> = samples
+        .iter()
+        .map(|sample| NTupleEvaluator::features(&sample.game))
+        .collect();
+    let mut order: Vec<usize> = (0..samples.len()).collect();
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut losses = Vec::new();
+    for _ in 0..config.epochs {
+        order.shuffle(&mut rng);
+        for &i in &order {
+            let error = samples[i].target - sigmoid(evaluator.score(&features[i]));
+            let step = config.learning_rate * error;
+            evaluator.bias += step;
+            for &(table, index) in &features[i] {
+                evaluator.tables[table][index] += step as f32;
+            }
+        }
+        let loss: f64 = samples
+            .iter()
+            .zip(&features)
+            .map(|(sample, features)| {
+                let p = sigmoid(evaluator.score(features)).clamp(1e-12, 1.0 - 1e-12);
+                -(sample.target * p.ln() + (1.0 - sample.target) * (1.0 - p).ln())
+            })
+            .sum();
+        losses.push(loss / samples.len().max(1) as f64);
+    }
+    losses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::random_game;
+    use rand::Rng;
+
+    #[test]
+    fn test_placements_cover_every_orientation() {
+        let count = |name| {
+            let index = PATTERNS.iter().position(|pattern| pattern.name == name).unwrap();
+            placements().iter().filter(|(pattern, _)| *pattern == index).count()
+        };
+        assert_eq!(count("edge_2x"), 8);
+        assert_eq!(count("corner_3x3"), 8);
+        assert_eq!(count("box_2x5"), 8);
+        assert_eq!(count("diagonal_8"), 4);
+        assert_eq!(count("diagonal_4"), 8);
+    }
+
+    #[test]
+    fn test_evaluation_is_symmetric() {
+        let mut evaluator = NTupleEvaluator::new();
+        let mut rng = StdRng::seed_from_u64(1);
+        for table in &mut evaluator.tables {
+            for weight in table.iter_mut() {
+                *weight = rng.gen_range(-1.0..1.0);
+            }
+        }
+        let mut game = Game::new();
+        for mv in random_game(2).into_iter().take(20) {
+            game.make_move_enum(mv).unwrap();
+        }
+        // The same position reflected left to right.
+        let mirror = |bits: u64| (0..64).filter(|pos| bits & (1u64 << pos) != 0).fold(0u64, |acc, pos| {
+            acc | 1u64 << ((pos / 8) * 8 + 7 - pos % 8)
+        });
+        let mirrored = Game::from_bitboards(mirror(game.black), mirror(game.white), game.current_player, 0);
+        assert!((evaluator.evaluate(&game) - evaluator.evaluate(&mirrored)).abs() < 1e-6);
+        assert!(NTupleEvaluator::new().evaluate(&game).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_fit_lowers_the_loss() {
+        let samples: Vec<Sample> = (0..20).flat_map(|seed| samples(&random_game(seed)).unwrap()).collect();
+        assert!(samples.iter().all(|sample| [0.0, 0.5, 1.0].contains(&sample.target)));
+        let mut evaluator = NTupleEvaluator::new();
+        let losses = fit(&mut evaluator, &samples, &FitConfig::default());
+        assert_eq!(losses.len(), 10);
+        assert!(losses[9] < losses[0]);
+        // Untrained weights predict an even game, whose log loss is ln 2.
+        assert!(losses[0] < std::f64::consts::LN_2);
+    }
+
+    #[test]
+    fn test_weights_round_trip() {
+        let path = std::env::temp_dir().join(format!("kawio_ntuple_{}.json", std::process::id()));
+        let mut evaluator = NTupleEvaluator::new();
+        evaluator.bias = 0.25;
+        evaluator.tables[3][7] = 1.5;
+        evaluator.save(&path).unwrap();
+        assert_eq!(NTupleEvaluator::load(&path).unwrap(), evaluator);
+
+        evaluator.tables.pop();
+        evaluator.save(&path).unwrap();
+        assert!(NTupleEvaluator::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
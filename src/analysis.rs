@@ -0,0 +1,372 @@
+//! Post-game computer analysis.
+//!
+//! Every move of a finished game is compared with the best move in its position, giving how
+//! much the move lost in centidiscs (hundredths of a disc of final margin). Positions late
+//! enough are solved exactly; earlier ones are estimated with a shallow search over the
+//! heuristic evaluation, so midgame losses are approximate.
+//!
+//! Positions can also be scored on their own with [`evaluate_batch`], in bulk and spread over
+//! the available CPUs, for jobs that look at many positions at once.
+
+use crate::eval::{Evaluator, HeuristicEvaluator};
+use crate::game::{Game, GameReplay, Move, PackedGame, Player};
+use crate::solver::solve;
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Discs of final margin a heuristic value of 1 is taken to be worth.
+const MIDGAME_DISC_SCALE: f64 = 32.0;
+
+/// How thoroughly a game is analysed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnalysisConfig {
+    /// Positions with at most this many empty squares are solved exactly.
+    pub exact_empties: u32,
+    /// Plies searched from each move in earlier positions.
+    pub depth: u32,
+    /// Moves losing at least this many centidiscs are flagged as blunders.
+    pub blunder_loss: i32,
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> Self {
+        Self {
+            exact_empties: 12,
+            depth: 3,
+            blunder_loss: 600,
+        }
+    }
+}
+
+/// The analysis of one move.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoveAnalysis {
+    pub ply: usize,
+    pub player: Player,
+    pub played: String,
+    pub best: String,
+    /// Value of the position for the mover after the best move, in centidiscs.
+    pub score: i32,
+    /// Centidiscs lost by the move played compared with the best move.
+    pub loss: i32,
+    pub blunder: bool,
+    /// True if the position was solved exactly rather than estimated.
+    pub exact: bool,
+}
+
+/// Totals for one side of an analysed game.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SideSummary {
+    /// Mean loss per move, in centidiscs.
+    pub average_loss: f64,
+    pub blunders: u32,
+}
+
+/// The analysis of a whole game.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AnalysisReport {
+    pub game_id: String,
+    /// One entry per disc placed; passes are left out.
+    pub moves: Vec<MoveAnalysis>,
+    pub black: SideSummary,
+    pub white: SideSummary,
+}
+
+/// The value of a position on its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PositionEvaluation {
+    /// Best move for the side to move, a pass if it has no legal move, or `None` if the game
+    /// is over.
+    pub best: Option<Move>,
+    /// Value of the position for the side to move, in centidiscs.
+    pub score: i32,
+    /// True if the position was solved exactly rather than estimated.
+    pub exact: bool,
+}
+
+/// Scores a position for the side to move, solving it exactly if it is late enough and
+/// estimating it as [`analyze`] does otherwise.
+#[must_use]
+pub fn evaluate(game: &Game, config: &AnalysisConfig) -> PositionEvaluation {
+    if game.is_game_over() || game.empties() <= config.exact_empties {
+        let solution = solve(game);
+        return PositionEvaluation {
+            best: solution.best_move,
+            score: solution.score * 100,
+            exact: true,
+        };
+    }
+    let evaluator = HeuristicEvaluator::default();
+    let best = game
+        .legal_moves_iter()
+        .map(|pos| (pos, estimated_value(&evaluator, game, pos, config.depth)))
+        .max_by_key(|&(_, value)| value);
+    let Some((best, score)) = best else {
+        let mut next = game.clone();
+        next.pass();
+        let reply = evaluate(&next, config);
+        return PositionEvaluation {
+            best: Some(Move::Pass),
+            score: -reply.score,
+            exact: reply.exact,
+        };
+    };
+    PositionEvaluation {
+        best: Some(Move::Place(best)),
+        score,
+        exact: false,
+    }
+}
+
+/// Scores many positions like [`evaluate`], returning their values in the same order.
+///
+/// The positions are shared out between one thread per available CPU as each finishes its
+/// last, so a few slow exact solves do not hold up the rest. Where threads are not available,
+/// as on wasm32, they are scored one after another.
+#[must_use]
+pub fn evaluate_batch(games: &[Game], config: &AnalysisConfig) -> Vec<PositionEvaluation> {
+    let workers = thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(games.len());
+    if workers <= 1 {
+        return games.iter().map(|game| evaluate(game, config)).collect();
+    }
+    // Games cache their legal moves, so the workers are handed packed copies to share.
+    let positions: Vec<PackedGame> = games.iter().map(PackedGame::from).collect();
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut scored = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(&position) = positions.get(index) else {
+                            return scored;
+                        };
+                        scored.push((index, evaluate(&position.unpack(), config)));
+                    }
+                })
+            })
+            .collect();
+        let mut scored: Vec<(usize, PositionEvaluation)> = Vec::new();
+        for worker in workers {
+            scored.extend(worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)));
+        }
+        scored.sort_unstable_by_key(|&(index, _)| index);
+        scored.into_iter().map(|(_, evaluation)| evaluation).collect()
+    })
+}
+
+/// Analyses a game played from the standard starting position.
+///
+/// # Errors
+///
+/// Returns an error if a move is illegal.
+pub fn analyze(game_id: &str, moves: &[Move], config: &AnalysisConfig) -> Result<AnalysisReport, String> {
+    let replay = GameReplay::from_moves(moves.to_vec())?;
+    let evaluator = HeuristicEvaluator::default();
+    let mut analysed = Vec::new();
+    for (ply, &mv) in moves.iter().enumerate() {
+        let (Move::Place(pos), Some(game)) = (mv, replay.board_at(ply)) else {
+            continue;
+        };
+        analysed.push(analyze_placement(&evaluator, game, ply, pos, config));
+    }
+    Ok(AnalysisReport {
+        game_id: game_id.to_string(),
+        black: summarize(&analysed, Player::Black),
+        white: summarize(&analysed, Player::White),
+        moves: analysed,
+    })
+}
+
+/// Analyses the move at `ply` of a game played from the standard starting position, as
+/// [`analyze`] does, without the rest of the game. Returns `None` for a pass or a ply not
+/// played yet.
+///
+/// # Errors
+///
+/// Returns an error if a move is illegal.
+pub fn analyze_move(moves: &[Move], ply: usize, config: &AnalysisConfig) -> Result<Option<MoveAnalysis>, String> {
+    let Some(&Move::Place(pos)) = moves.get(ply) else {
+        return Ok(None);
+    };
+    let replay = GameReplay::from_moves(moves[..=ply].to_vec())?;
+    Ok(replay
+        .board_at(ply)
+        .map(|game| analyze_placement(&HeuristicEvaluator::default(), game, ply, pos, config)))
+}
+
+/// Compares the disc placed at `pos` in `game` with the best move there.
+fn analyze_placement(
+    evaluator: &dyn Evaluator,
+    game: &Game,
+    ply: usize,
+    pos: u8,
+    config: &AnalysisConfig,
+) -> MoveAnalysis {
+    let exact = game.empty().count_ones() <= config.exact_empties;
+    let value = |pos: u8| {
+        if exact {
+            exact_value(game, pos)
+        } else {
+            estimated_value(evaluator, game, pos, config.depth)
+        }
+    };
+    let (best, score) = game
+        .legal_moves_iter()
+        .map(|pos| (pos, value(pos)))
+        .max_by_key(|&(_, value)| value)
+        .unwrap_or((pos, 0));
+    let loss = if best == pos { 0 } else { (score - value(pos)).max(0) };
+    MoveAnalysis {
+        ply,
+        player: game.current_player,
+        played: Game::pos_to_coord(pos),
+        best: Game::pos_to_coord(best),
+        score,
+        loss,
+        blunder: loss >= config.blunder_loss,
+        exact,
+    }
+}
+
+/// Returns the value of playing `pos` for the side to move, in centidiscs, solved exactly.
+fn exact_value(game: &Game, pos: u8) -> i32 {
+    let mut child = game.clone();
+    let _ = child.make_move(pos);
+    let score = solve(&child).score * 100;
+    // After an automatic pass the mover is to play again.
+    if child.current_player == game.current_player {
+        score
+    } else {
+        -score
+    }
+}
+
+/// Returns the estimated value of playing `pos` for the side to move, in centidiscs.
+pub(crate) fn estimated_value(evaluator: &dyn Evaluator, game: &Game, pos: u8, depth: u32) -> i32 {
+    let mut child = game.clone();
+    let _ = child.make_move(pos);
+    let value = negamax(evaluator, &child, depth.saturating_sub(1));
+    let value = if child.current_player == game.current_player {
+        value
+    } else {
+        -value
+    };
+    #[allow(clippy::cast_possible_truncation)]
+    let centidiscs = (value * MIDGAME_DISC_SCALE * 100.0).round() as i32;
+    centidiscs
+}
+
+/// Returns the heuristic value of the position for the side to move, from -1 to 1, searched
+/// `depth` plies deep.
+fn negamax(evaluator: &dyn Evaluator, game: &Game, depth: u32) -> f64 {
+    if depth == 0 || game.is_game_over() {
+        return evaluator.evaluate(game);
+    }
+    game.legal_moves_iter()
+        .map(|pos| {
+            let mut child = game.clone();
+            let _ = child.make_move(pos);
+            let value = negamax(evaluator, &child, depth - 1);
+            if child.current_player == game.current_player {
+                value
+            } else {
+                -value
+            }
+        })
+        .fold(-1.0, f64::max)
+}
+
+fn summarize(moves: &[MoveAnalysis], player: Player) -> SideSummary {
+    let own: Vec<&MoveAnalysis> = moves.iter().filter(|analysis| analysis.player == player).collect();
+    let total: i64 = own.iter().map(|analysis| i64::from(analysis.loss)).sum();
+    #[allow(clippy::cast_precision_loss)]
+    let average_loss = if own.is_empty() {
+        0.0
+    } else {
+        total as f64 / own.len() as f64
+    };
+    SideSummary {
+        average_loss,
+        blunders: u32::try_from(own.iter().filter(|analysis| analysis.blunder).count()).unwrap_or(u32::MAX),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::random_game;
+
+    #[test]
+    fn test_report_covers_every_move() {
+        let moves = random_game(5);
+        let config = AnalysisConfig {
+            exact_empties: 8,
+            depth: 1,
+            ..AnalysisConfig::default()
+        };
+        let report = analyze("g1", &moves, &config).unwrap();
+        assert_eq!(report.moves.len(), moves.len());
+        for (analysis, mv) in report.moves.iter().zip(&moves) {
+            assert_eq!(analysis.played, mv.to_string());
+            assert!(analysis.loss >= 0);
+            assert_eq!(analysis.blunder, analysis.loss >= config.blunder_loss);
+        }
+        // Random play blunders the endgame somewhere.
+        let exact: Vec<_> = report.moves.iter().filter(|analysis| analysis.exact).collect();
+        assert!(!exact.is_empty());
+        assert!(report.black.blunders + report.white.blunders > 0);
+
+        // A single move is analysed as in the full report.
+        let last = moves.len() - 1;
+        assert_eq!(analyze_move(&moves, last, &config).unwrap().as_ref(), report.moves.last());
+        assert_eq!(analyze_move(&moves, moves.len(), &config), Ok(None));
+    }
+
+    #[test]
+    fn test_exact_value_of_wipeout() {
+        // A1 captures White's last disc, winning 64-0 with the empties.
+        let mut game = Game::new();
+        game.black = 1u64 << Game::coord_to_pos("A3").unwrap();
+        game.white = 1u64 << Game::coord_to_pos("A2").unwrap();
+        let a1 = Game::coord_to_pos("A1").unwrap();
+        assert_eq!(exact_value(&game, a1), 6400);
+    }
+
+    #[test]
+    fn test_batch_matches_single_evaluations() {
+        let config = AnalysisConfig {
+            exact_empties: 8,
+            depth: 2,
+            ..AnalysisConfig::default()
+        };
+        let replay = GameReplay::from_moves(random_game(3)).unwrap();
+        let games: Vec<Game> = (0..replay.len()).filter_map(|ply| replay.board_at(ply).cloned()).collect();
+        let evaluations = evaluate_batch(&games, &config);
+        assert_eq!(evaluations.len(), games.len());
+        for (game, evaluation) in games.iter().zip(&evaluations) {
+            assert_eq!(*evaluation, evaluate(game, &config));
+            assert_eq!(evaluation.exact, game.empties() <= config.exact_empties);
+        }
+        assert!(evaluate_batch(&[], &config).is_empty());
+
+        // A side without a legal move passes, and a finished game has no move.
+        let (a1, h8) = (Game::coord_to_pos("A1").unwrap(), Game::coord_to_pos("H8").unwrap());
+        let finished = Game::from_bitboards(1u64 << a1, 1u64 << h8, Player::White, 2);
+        assert_eq!(evaluate(&finished, &config).best, None);
+        let passing = Game::from_position_string(&format!("XO{} O", "-".repeat(62))).unwrap();
+        let evaluation = evaluate(&passing, &config);
+        assert_eq!(evaluation.best, Some(Move::Pass));
+        assert!(evaluation.score < 0);
+    }
+
+    #[test]
+    fn test_illegal_game_is_rejected() {
+        assert!(analyze("g1", &[Move::Place(0)], &AnalysisConfig::default()).is_err());
+    }
+}
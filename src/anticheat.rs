@@ -0,0 +1,292 @@
+//! Hooks analysing the moves people play, to flag suspected engine assistance for review.
+//!
+//! Every move a person places queues a [`Task::CheatCheck`](crate::api::Task::CheatCheck)
+//! job, so the analysis never holds up the game. The job hands the move to each
+//! [`MoveAnalyzer`], and whatever they find suspicious is stored as a [`CheatReport`] for the
+//! admins to review. A report only raises a question; nothing is done to the player.
+
+use crate::analysis::estimated_value;
+use crate::eval::HeuristicEvaluator;
+use crate::game::{GameReplay, Move};
+use crate::storage::MoveRecord;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A move placed by a person, with the game that led to it.
+pub struct SubmittedMove<'a> {
+    pub game_id: &'a str,
+    /// Index of the move in the history.
+    pub ply: usize,
+    /// The game's history up to and including the move.
+    pub history: &'a [MoveRecord],
+    /// The positions of the game, to look at the board before any move.
+    pub replay: &'a GameReplay,
+}
+
+impl SubmittedMove<'_> {
+    /// Returns the name of the player who made the move.
+    #[must_use]
+    pub fn player(&self) -> &str {
+        &self.history[self.ply].player
+    }
+
+    /// Returns the plies of the player's placed moves up to this one where they had more than
+    /// one legal move, latest first.
+    pub fn choices(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..=self.ply).rev().filter(move |&ply| {
+            let record = &self.history[ply];
+            record.player == self.player()
+                && matches!(record.mv, Move::Place(_))
+                && self.replay.board_at(ply).is_some_and(|game| game.legal_moves_iter().nth(1).is_some())
+        })
+    }
+}
+
+/// Why an analyzer finds a move suspicious.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Suspicion {
+    /// How strongly the analyzer suspects assistance, from 0 to 1.
+    pub score: f64,
+    /// What the analyzer saw, for the admin reviewing it.
+    pub details: String,
+}
+
+/// Looks at moves played by people for signs of engine assistance.
+pub trait MoveAnalyzer: Send + Sync {
+    /// Short name the reports of the analyzer are filed under.
+    fn name(&self) -> &'static str;
+
+    /// Returns why the move is suspicious, or `None` if it is not.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the move cannot be analysed; the job is then retried.
+    fn analyze(&self, mv: &SubmittedMove) -> Result<Option<Suspicion>, String>;
+}
+
+/// A suspicion raised about a player in a game, for admin review. Each analyzer keeps one
+/// report per player and game, updated as the game goes on.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CheatReport {
+    pub id: i64,
+    pub game_id: String,
+    pub player: String,
+    /// Ply of the move that last raised the suspicion.
+    pub ply: usize,
+    pub analyzer: String,
+    pub score: f64,
+    pub details: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// Returns the analyzers run when anti-cheat checks are enabled.
+#[must_use]
+pub fn default_analyzers() -> Vec<Arc<dyn MoveAnalyzer>> {
+    vec![Arc::new(EngineMatchRate::default()), Arc::new(ResponseTimes::default())]
+}
+
+/// Runs every analyzer on the move at `ply` of a game, returning the suspicions raised by
+/// name of analyzer.
+///
+/// # Errors
+///
+/// Returns an error if the history is illegal, `ply` is not in it, or an analyzer fails.
+pub fn check(
+    game_id: &str,
+    history: &[MoveRecord],
+    ply: usize,
+    analyzers: &[Arc<dyn MoveAnalyzer>],
+) -> Result<Vec<(String, Suspicion)>, String> {
+    if ply >= history.len() {
+        return Err(format!("Game {game_id} has no move {ply}"));
+    }
+    let replay = GameReplay::from_moves(history[..=ply].iter().map(|record| record.mv).collect())?;
+    let mv = SubmittedMove {
+        game_id,
+        ply,
+        history: &history[..=ply],
+        replay: &replay,
+    };
+    let mut suspicions = Vec::new();
+    for analyzer in analyzers {
+        if let Some(suspicion) = analyzer.analyze(&mv)? {
+            suspicions.push((analyzer.name().to_string(), suspicion));
+        }
+    }
+    Ok(suspicions)
+}
+
+/// Flags players whose recent moves almost all match the engine's choice.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EngineMatchRate {
+    /// Number of the player's latest moves with a choice that are compared.
+    pub window: usize,
+    /// Plies searched for the engine's choice.
+    pub depth: u32,
+    /// Share of matching moves from which the player is flagged.
+    pub threshold: f64,
+}
+
+impl Default for EngineMatchRate {
+    fn default() -> Self {
+        Self {
+            window: 12,
+            depth: 3,
+            threshold: 0.9,
+        }
+    }
+}
+
+impl MoveAnalyzer for EngineMatchRate {
+    fn name(&self) -> &'static str {
+        "engine_match_rate"
+    }
+
+    fn analyze(&self, mv: &SubmittedMove) -> Result<Option<Suspicion>, String> {
+        let plies: Vec<usize> = mv.choices().take(self.window).collect();
+        if plies.len() < self.window || self.window == 0 {
+            return Ok(None);
+        }
+        let evaluator = HeuristicEvaluator::default();
+        let matches = plies
+            .iter()
+            .filter(|&&ply| {
+                let (Move::Place(played), Some(game)) = (mv.history[ply].mv, mv.replay.board_at(ply)) else {
+                    return false;
+                };
+                let value = |pos| estimated_value(&evaluator, game, pos, self.depth);
+                let best = game.legal_moves_iter().map(value).max();
+                best == Some(value(played))
+            })
+            .count();
+        #[allow(clippy::cast_precision_loss)]
+        let rate = matches as f64 / plies.len() as f64;
+        Ok((rate >= self.threshold).then(|| Suspicion {
+            score: rate,
+            details: format!(
+                "{matches} of the last {} moves with a choice matched the engine at depth {}",
+                plies.len(),
+                self.depth
+            ),
+        }))
+    }
+}
+
+/// Flags players who take about the same time over every move, as relaying an engine's
+/// moves does, where people answer easy moves fast and think over hard ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResponseTimes {
+    /// Number of the player's latest timed moves with a choice that are compared.
+    pub window: usize,
+    /// Spread of the response times, as a share of their mean, below which the player is
+    /// flagged.
+    pub max_variation: f64,
+}
+
+impl Default for ResponseTimes {
+    fn default() -> Self {
+        Self {
+            window: 12,
+            max_variation: 0.15,
+        }
+    }
+}
+
+impl MoveAnalyzer for ResponseTimes {
+    fn name(&self) -> &'static str {
+        "response_times"
+    }
+
+    fn analyze(&self, mv: &SubmittedMove) -> Result<Option<Suspicion>, String> {
+        #[allow(clippy::cast_precision_loss)]
+        let times: Vec<f64> = mv
+            .choices()
+            .filter_map(|ply| mv.history[ply].elapsed_ms)
+            .take(self.window)
+            .map(|ms| ms as f64)
+            .collect();
+        if times.len() < self.window || self.window == 0 {
+            return Ok(None);
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let count = times.len() as f64;
+        let mean = times.iter().sum::<f64>() / count;
+        if mean <= 0.0 {
+            return Ok(None);
+        }
+        let deviation = (times.iter().map(|time| (time - mean).powi(2)).sum::<f64>() / count).sqrt();
+        let variation = deviation / mean;
+        Ok((variation < self.max_variation).then(|| Suspicion {
+            score: 1.0 - variation / self.max_variation,
+            details: format!(
+                "The last {} response times varied by {:.0}% around {mean:.0} ms",
+                times.len(),
+                variation * 100.0
+            ),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+
+    /// A game alternating between two players, each taking `elapsed` over every move.
+    fn history(moves: usize, elapsed: impl Fn(usize) -> u64) -> Vec<MoveRecord> {
+        let mut game = Game::new();
+        let mut history = Vec::new();
+        for ply in 0..moves {
+            let pos = game.legal_moves()[0];
+            game.make_move(pos).unwrap();
+            history.push(MoveRecord {
+                mv: Move::Place(pos),
+                player: if ply % 2 == 0 { "Alice" } else { "Bob" }.to_string(),
+                elapsed_ms: Some(elapsed(ply)),
+                simulations: None,
+            });
+        }
+        history
+    }
+
+    #[test]
+    fn test_response_times_flag_uniform_players() {
+        let analyzers: Vec<Arc<dyn MoveAnalyzer>> = vec![Arc::new(ResponseTimes {
+            window: 4,
+            max_variation: 0.15,
+        })];
+        let steady = history(16, |_| 2000);
+        let suspicions = check("g1", &steady, 15, &analyzers).unwrap();
+        assert_eq!(suspicions.len(), 1);
+        assert_eq!(suspicions[0].0, "response_times");
+        assert!((suspicions[0].1.score - 1.0).abs() < 1e-9);
+        // Too few moves to tell yet.
+        assert!(check("g1", &steady, 3, &analyzers).unwrap().is_empty());
+
+        let human = history(16, |ply| [500, 9000, 1500, 30000][ply % 4]);
+        assert!(check("g1", &human, 15, &analyzers).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_engine_match_rate_counts_choices() {
+        let history = history(20, |_| 1000);
+        let analyzer = EngineMatchRate {
+            window: 6,
+            depth: 1,
+            threshold: 0.0,
+        };
+        let replay = GameReplay::from_moves(history.iter().map(|record| record.mv).collect()).unwrap();
+        let mv = SubmittedMove {
+            game_id: "g1",
+            ply: 19,
+            history: &history,
+            replay: &replay,
+        };
+        assert!(mv.choices().all(|ply| history[ply].player == "Bob"));
+        let suspicion = analyzer.analyze(&mv).unwrap().unwrap();
+        assert!((0.0..=1.0).contains(&suspicion.score));
+        assert!(suspicion.details.ends_with("at depth 1"));
+        assert!(check("g1", &history, 20, &[Arc::new(analyzer) as Arc<dyn MoveAnalyzer>]).is_err());
+    }
+}
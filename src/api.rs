@@ -0,0 +1,1053 @@
+//! Request and response bodies of the REST API.
+//!
+//! These types are shared by the server handlers in [`crate::network`] and by the client
+//! library, so both sides agree on the wire format. Records the server stores and serves
+//! as they are, such as player statistics and jobs, are defined here too, so the client
+//! builds without the storage.
+
+use crate::ai::{Difficulty, SearchSummary};
+use crate::analysis::PositionEvaluation;
+use crate::game::{FlipLine, Game, Player};
+use crate::import::GameFormat;
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoginRequest {
+    pub player: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginResponse {
+    pub token: String,
+}
+
+/// A ticket to open one game socket with, as the `ticket` query parameter, instead of
+/// putting the session token in the URL.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TicketResponse {
+    pub ticket: String,
+    /// Seconds left to open the socket in.
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewMatchRequest {
+    pub player2: String,
+    /// Discs added to White's final count; the server's default if omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub komi: Option<i32>,
+    /// Whether the game counts towards ratings; ranked if omitted.
+    #[serde(default)]
+    pub game_type: GameType,
+    /// Hides the game from everyone but its players, the admins and holders of its invite.
+    #[serde(default)]
+    pub private: bool,
+    /// Tells each player how good every move they place was, as they play; as the creator's
+    /// preferences say if omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coach: Option<bool>,
+    /// Plays against the AI at this difficulty, under its own name and rating, when
+    /// `player2` is "AI".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub difficulty: Option<Difficulty>,
+    /// The color the creator plays; Black, who moves first, if omitted.
+    #[serde(default)]
+    pub color: ColorChoice,
+}
+
+/// The color the creator of a match asks to play.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorChoice {
+    #[default]
+    Black,
+    White,
+    /// Either color, drawn when the match is created.
+    Random,
+}
+
+impl ColorChoice {
+    /// Returns the color played, drawing it from `rng` for [`ColorChoice::Random`].
+    #[must_use]
+    pub fn resolve(self, rng: &mut impl rand::Rng) -> Player {
+        match self {
+            Self::Black => Player::Black,
+            Self::White => Player::White,
+            Self::Random => {
+                if rng.gen() {
+                    Player::Black
+                } else {
+                    Player::White
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewMatchResponse {
+    pub id: String,
+    /// The invite to a private game, to pass as the `invite` query parameter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invite: Option<String>,
+    /// Link to the game in the web UI with the invite, to share.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invite_url: Option<String>,
+    /// The color the creator plays, drawn by the server if they asked for a random one.
+    #[serde(default)]
+    pub color: Player,
+}
+
+/// External engines that can be challenged, besides the built-in AI.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnginesResponse {
+    pub engines: Vec<String>,
+}
+
+/// Identity providers players can log in with, by the names in their login URLs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuthProvidersResponse {
+    pub providers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MoveRequest {
+    pub coord: MoveCoord,
+    /// The number of moves the client saw played: the move is refused if another was played
+    /// since, e.g. by a request racing this one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ply: Option<usize>,
+}
+
+/// A square named in a request.
+///
+/// Clients may send algebraic notation in either case (`"D3"`, `"d3"`), the square's index as
+/// a number or a string (`43`, `"43"`, counting from 0 at A8 to 63 at H1), or an object with
+/// a 0-based `row` and `col` as laid out in the state's `board` (`{"row": 5, "col": 3}`). It
+/// is always written back as upper-case algebraic notation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MoveCoord(u8);
+
+impl MoveCoord {
+    /// Returns the square at `pos`, or `None` if it is off the board.
+    #[must_use]
+    pub fn new(pos: u8) -> Option<Self> {
+        (pos < 64).then_some(Self(pos))
+    }
+
+    /// Returns the square at `row` and `col`, counted from 0 at the top left.
+    #[must_use]
+    pub fn from_row_col(row: u8, col: u8) -> Option<Self> {
+        (row < 8 && col < 8).then_some(Self(row * 8 + col))
+    }
+
+    /// Returns the square's index, from 0 at A8 to 63 at H1.
+    #[must_use]
+    pub fn pos(self) -> u8 {
+        self.0
+    }
+}
+
+impl fmt::Display for MoveCoord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&Game::pos_to_coord(self.0))
+    }
+}
+
+impl FromStr for MoveCoord {
+    type Err = String;
+
+    /// Parses algebraic notation such as "D3" or a square index such as "43".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            return s
+                .parse()
+                .ok()
+                .and_then(MoveCoord::new)
+                .ok_or_else(|| format!("Square index '{s}' must be 0-63"));
+        }
+        Game::coord_to_pos(s).map(MoveCoord)
+    }
+}
+
+impl Serialize for MoveCoord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for MoveCoord {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(MoveCoordVisitor)
+    }
+}
+
+struct MoveCoordVisitor;
+
+impl<'de> Visitor<'de> for MoveCoordVisitor {
+    type Value = MoveCoord;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a square such as \"D3\", a square index 0-63, or {\"row\", \"col\"}")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<MoveCoord, E> {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<MoveCoord, E> {
+        u8::try_from(v)
+            .ok()
+            .and_then(MoveCoord::new)
+            .ok_or_else(|| E::custom(format!("Square index {v} must be 0-63")))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<MoveCoord, E> {
+        u64::try_from(v)
+            .map_err(|_| E::custom(format!("Square index {v} must be 0-63")))
+            .and_then(|v| self.visit_u64(v))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<MoveCoord, A::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct RowCol {
+            row: u8,
+            col: u8,
+        }
+
+        let RowCol { row, col } = RowCol::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        MoveCoord::from_row_col(row, col)
+            .ok_or_else(|| de::Error::custom(format!("Row {row} and column {col} must be 0-7")))
+    }
+}
+
+/// Result of a move submitted over REST, including any replies by the AI.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MoveResponse {
+    /// Colors that were passed for automatically because they had no legal moves, in order.
+    pub forced_passes: Vec<String>,
+}
+
+/// How a game ended when a player claimed it from an opponent out of time or inactive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClaimResponse {
+    /// The color that won, or `None` if the game was aborted or drawn.
+    pub winner: Option<String>,
+    /// True if the game was still in the opening and ended without a result.
+    pub aborted: bool,
+}
+
+/// Discs a move flipped in one direction, nearest the square played first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlippedLine {
+    /// `"N"`, `"NE"`, `"E"` and so on, north being towards rank 8.
+    pub direction: String,
+    pub discs: Vec<String>,
+}
+
+impl FlippedLine {
+    /// Lists the lines of discs a move flipped, as [`crate::game::flip_lines`] groups them.
+    #[must_use]
+    pub fn from_lines(lines: &[FlipLine]) -> Vec<FlippedLine> {
+        lines
+            .iter()
+            .map(|line| FlippedLine {
+                direction: line.direction.as_str().to_string(),
+                discs: line.discs.iter().copied().map(Game::pos_to_coord).collect(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinResponse {
+    pub matched: bool,
+    pub id: Option<String>,
+}
+
+/// The full state of a game, as returned by the state endpoint and pushed over WebSocket.
+// The flags are fields of the JSON clients read, not states to fold into an enum.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameStateResponse {
+    pub board: Vec<Vec<String>>,
+    /// The full position in the serialized form of [`Game`], for clients that play with the
+    /// crate's own types.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<Game>,
+    pub current_player: String,
+    pub legal_moves: Vec<String>,
+    pub game_over: bool,
+    /// The number of moves in the game's history, to send with the next move.
+    #[serde(default)]
+    pub ply: usize,
+    pub winner: Option<String>,
+    /// Color that resigned the game, if it ended by resignation or forfeit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resigned: Option<String>,
+    /// True if the game was aborted, without a winner.
+    #[serde(default)]
+    pub aborted: bool,
+    /// True if the game was drawn by a claim of inactivity.
+    #[serde(default)]
+    pub drawn: bool,
+    pub player1: String,
+    pub player2: String,
+    pub scores: HashMap<String, u32>,
+    pub scoring_rule: String,
+    pub final_scores: HashMap<String, HashMap<String, u32>>,
+    /// Discs added to White's final count to decide the winner.
+    #[serde(default)]
+    pub komi: i32,
+    /// `ranked`, or `casual` if the game changes no ratings.
+    #[serde(default)]
+    pub game_type: GameType,
+    /// True if each player is told how good every move they place was.
+    #[serde(default)]
+    pub coach: bool,
+    /// Stage of the game: `opening`, `midgame` or `endgame`.
+    #[serde(default)]
+    pub phase: String,
+    /// The last move played, a square or `"pass"`, or `None` before the first move.
+    #[serde(default)]
+    pub last_move: Option<String>,
+    /// Discs the last move flipped.
+    #[serde(default)]
+    pub flipped: Vec<String>,
+    /// The same discs line by line from the square played, to animate them in turn.
+    #[serde(default)]
+    pub flip_lines: Vec<FlippedLine>,
+    /// Colors passed for automatically after the latest move, in order.
+    #[serde(default)]
+    pub forced_passes: Vec<String>,
+    #[serde(default)]
+    pub presence: PresenceResponse,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanations: Option<Vec<MoveExplanationResponse>>,
+    /// The engine's preference for each legal move, summing to 1, when hints are requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hints: Option<HashMap<String, f64>>,
+}
+
+/// Who is connected to a game over WebSocket.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PresenceResponse {
+    /// Sockets open by anyone other than the players, including anonymous ones.
+    pub spectators: usize,
+    /// Whether player 1 has a socket open. The AI is always connected.
+    pub player1_connected: bool,
+    pub player2_connected: bool,
+    /// Players who left and will lose the game unless they reconnect in time.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub countdowns: Vec<CountdownResponse>,
+}
+
+/// Time left for a disconnected player to come back before the game is forfeited or aborted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CountdownResponse {
+    pub player: String,
+    pub seconds: u64,
+    /// True if the game will be aborted rather than forfeited, as it is still in the opening.
+    pub abort: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoveExplanationResponse {
+    pub coord: String,
+    pub legal: bool,
+    pub flips: Vec<String>,
+    pub directions: Vec<RayExplanationResponse>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RayExplanationResponse {
+    pub direction: String,
+    pub discs: Vec<String>,
+    pub outcome: String,
+}
+
+/// The move history of a game, as returned by the replay endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayResponse {
+    pub id: String,
+    pub player1: String,
+    pub player2: String,
+    pub moves: Vec<ReplayMoveResponse>,
+    /// Ply the board is shown at.
+    pub ply: usize,
+    pub board: Vec<Vec<String>>,
+    #[serde(default)]
+    pub annotations: Vec<AnnotationResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayMoveResponse {
+    pub ply: usize,
+    pub coord: String,
+    pub player: String,
+    pub elapsed_ms: Option<u64>,
+    pub simulations: Option<u32>,
+}
+
+/// An annotation to attach to a move, as sent to the annotations endpoint.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnnotationRequest {
+    pub ply: usize,
+    #[serde(default)]
+    pub comment: Option<String>,
+    /// One of `!!`, `!`, `!?`, `?!`, `?` and `??`.
+    #[serde(default)]
+    pub mark: Option<String>,
+    #[serde(default)]
+    pub score: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnnotationResponse {
+    pub ply: usize,
+    pub author: String,
+    pub comment: Option<String>,
+    pub mark: Option<String>,
+    pub score: Option<f64>,
+}
+
+/// Positions to score, as sent to the batch analysis endpoint.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchAnalysisRequest {
+    /// Positions as 64 squares from A1 to H8 plus the side to move, e.g.
+    /// `"---------------------------OX------XO--------------------------- X"`.
+    pub positions: Vec<String>,
+    /// Plies searched from each move of positions too early to solve.
+    #[serde(default)]
+    pub depth: Option<u32>,
+    /// Positions with at most this many empty squares are solved exactly.
+    #[serde(default)]
+    pub exact_empties: Option<u32>,
+}
+
+/// The values of the positions of a batch, in the order they were sent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchAnalysisResponse {
+    pub evaluations: Vec<PositionEvaluation>,
+}
+
+/// A game imported from a file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportResponse {
+    pub game_id: String,
+    pub format: GameFormat,
+    /// Discs placed in the game.
+    pub moves: usize,
+}
+
+/// Opens an analysis board, as sent to the boards endpoint.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewBoardRequest {
+    pub name: String,
+    /// Users to share the board with.
+    #[serde(default)]
+    pub invite: Vec<String>,
+}
+
+/// Shares an analysis board with one more user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoardInviteRequest {
+    pub player: String,
+}
+
+/// An analysis board as its members see it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BoardStateResponse {
+    pub id: String,
+    pub name: String,
+    pub owner: String,
+    /// Users the board is shared with, its owner included, in alphabetical order.
+    pub members: Vec<String>,
+    pub board: Vec<Vec<String>>,
+    /// The position as 64 squares from A1 to H8 plus the side to move.
+    pub position: String,
+    pub current_player: String,
+    pub legal_moves: Vec<String>,
+    pub scores: HashMap<String, u32>,
+    pub game_over: bool,
+    /// Changes that can be taken back.
+    pub undo_depth: usize,
+}
+
+/// A puzzle to solve, without its solution.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PuzzleResponse {
+    pub id: i64,
+    pub board: Vec<Vec<String>>,
+    /// The side to move, which has exactly one winning move.
+    pub current_player: String,
+    pub legal_moves: Vec<String>,
+    /// From 1 (easiest) to 5.
+    pub difficulty: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PuzzleAttemptResponse {
+    pub correct: bool,
+    pub solution: String,
+}
+
+/// A pre-game preview of two players.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchupResponse {
+    pub player1: String,
+    pub player2: String,
+    pub player1_rating: f64,
+    pub player2_rating: f64,
+    /// Player 1's expected score from the rating model, from 0 to 1.
+    pub expected_score: f64,
+    /// The record of player 1 against player 2.
+    pub head_to_head: HeadToHead,
+    /// Outcomes of each player's latest games, newest first.
+    pub player1_form: Vec<Outcome>,
+    pub player2_form: Vec<Outcome>,
+}
+
+/// What a running server is doing, as shown on the admin dashboard.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminDashboardResponse {
+    /// Id of the server, telling apart the servers sharing a database.
+    pub instance_id: String,
+    /// Games in progress in the server's memory.
+    pub games: Vec<AdminGameResponse>,
+    /// Players waiting for a match, longest waiting first.
+    pub matchmaking_queue: Vec<String>,
+    /// Sockets open on the server.
+    pub sockets: usize,
+    /// AI searches that may run at once, and those waiting for a slot.
+    pub ai_capacity: usize,
+    pub ai_queued: usize,
+}
+
+/// A game in progress, as shown on the admin dashboard.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminGameResponse {
+    pub id: String,
+    pub player1: String,
+    pub player2: String,
+    pub current_player: String,
+    /// Discs on the board.
+    pub discs: u32,
+    /// `"searching"` while the AI holds its lock to search, `"idle"` while it waits, or
+    /// `None` if no AI is kept for the game.
+    pub ai: Option<String>,
+    /// Stored version of the game, with shared storage.
+    pub version: Option<i64>,
+    pub presence: PresenceResponse,
+}
+
+/// The AI's results by release and number of simulations, and its latest games.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiGamesResponse {
+    pub strength: Vec<AiStrength>,
+    pub games: Vec<AiGameRecord>,
+}
+
+/// Turns maintenance of a server on or off, as sent to the maintenance endpoint.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceRequest {
+    pub enabled: bool,
+    /// Seconds after which games still being played no longer hold up a restart.
+    #[serde(default)]
+    pub deadline_secs: Option<u64>,
+    /// Whether the server stops by itself once drained, to be restarted.
+    #[serde(default)]
+    pub restart: bool,
+}
+
+/// Maintenance of the server answering.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MaintenanceResponse {
+    pub enabled: bool,
+    /// Seconds left until the deadline, if there is one.
+    pub deadline_secs: Option<u64>,
+    pub restart: bool,
+    /// Games in progress with a player connected, which hold up a restart.
+    pub live_games: Vec<String>,
+    /// True once nothing holds up a restart.
+    pub drained: bool,
+}
+
+/// Runs the retention policy now, as sent to the retention endpoint.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionRequest {
+    /// Whether to only list the games that would be deleted.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Days to keep finished games for, instead of the configured policy's.
+    #[serde(default)]
+    pub keep_days: Option<u32>,
+}
+
+/// The retention policy of the server answering, and its latest runs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetentionResponse {
+    /// Days finished games are kept for, or `None` if they are never deleted.
+    pub keep_days: Option<u32>,
+    /// Whether the scheduled runs only list the games they would delete.
+    pub dry_run: bool,
+    /// Latest runs, newest first.
+    pub runs: Vec<RetentionRun>,
+}
+
+/// Turns the debug logging of requests to the match endpoints on or off, as sent to the
+/// request log endpoint.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestLogRequest {
+    pub enabled: bool,
+}
+
+/// Whether requests to the match endpoints are being logged.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RequestLogResponse {
+    pub enabled: bool,
+}
+
+/// A notice for every socket, as sent to the broadcast endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BroadcastRequest {
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BroadcastResponse {
+    /// Games on this server whose sockets were told.
+    pub games: usize,
+}
+
+/// Something a player can earn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Achievement {
+    FirstWin,
+    /// Won a game after taking all four corners.
+    CornerSweep,
+    /// Won a game 64-0.
+    PerfectGame,
+    /// Won [`STREAK_LENGTH`](crate::achievements::STREAK_LENGTH) games in a row.
+    WinStreak,
+    /// Beat the AI at its hardest difficulty.
+    BeatHardAi,
+}
+
+impl Achievement {
+    pub const ALL: [Achievement; 5] = [
+        Achievement::FirstWin,
+        Achievement::CornerSweep,
+        Achievement::PerfectGame,
+        Achievement::WinStreak,
+        Achievement::BeatHardAi,
+    ];
+
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Achievement::FirstWin => "first_win",
+            Achievement::CornerSweep => "corner_sweep",
+            Achievement::PerfectGame => "perfect_game",
+            Achievement::WinStreak => "win_streak",
+            Achievement::BeatHardAi => "beat_hard_ai",
+        }
+    }
+}
+
+impl std::str::FromStr for Achievement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Achievement::ALL
+            .into_iter()
+            .find(|achievement| achievement.as_str() == s)
+            .ok_or_else(|| format!("Unknown achievement '{s}'"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub name: String,
+    pub elo: f64,
+    pub wins: i32,
+    pub losses: i32,
+    /// True while the player is still in the provisional period and their rating moves faster.
+    #[serde(default)]
+    pub provisional: bool,
+    #[serde(default)]
+    pub games_as_black: i32,
+    #[serde(default)]
+    pub games_as_white: i32,
+    /// Consecutive wins up to the latest game.
+    #[serde(default)]
+    pub current_streak: i32,
+    #[serde(default)]
+    pub best_streak: i32,
+    /// Average final disc differential, positive when the player finished ahead.
+    #[serde(default)]
+    pub avg_disc_diff: f64,
+    /// Average number of discs placed by both players per game.
+    #[serde(default)]
+    pub avg_moves: f64,
+    /// Only filled in for single-player profiles, not leaderboard entries.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub achievements: Vec<EarnedAchievement>,
+    /// Results of casual games, which count towards none of the above. Only filled in for
+    /// single-player profiles.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub casual: Option<Record>,
+}
+
+/// An achievement a player has earned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EarnedAchievement {
+    pub achievement: Achievement,
+    /// The game that earned it.
+    pub game_id: String,
+    pub earned_at: String,
+}
+
+/// Whose challenges a player accepts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChallengePolicy {
+    #[default]
+    Anyone,
+    /// Only players on the player's friends list.
+    Friends,
+    Nobody,
+}
+
+impl ChallengePolicy {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChallengePolicy::Anyone => "anyone",
+            ChallengePolicy::Friends => "friends",
+            ChallengePolicy::Nobody => "nobody",
+        }
+    }
+}
+
+impl std::str::FromStr for ChallengePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "anyone" => Ok(ChallengePolicy::Anyone),
+            "friends" => Ok(ChallengePolicy::Friends),
+            "nobody" => Ok(ChallengePolicy::Nobody),
+            _ => Err(format!("Unknown challenge policy '{s}'")),
+        }
+    }
+}
+
+/// Whether a game counts towards the players' ratings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameType {
+    #[default]
+    Ranked,
+    /// Archived like any other game, but changes no ratings, records or statistics.
+    Casual,
+}
+
+impl GameType {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameType::Ranked => "ranked",
+            GameType::Casual => "casual",
+        }
+    }
+}
+
+/// Wins, losses and draws in one kind of game.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+/// A player's privacy settings.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlayerSettings {
+    #[serde(default)]
+    pub accept_challenges: ChallengePolicy,
+    #[serde(default)]
+    pub friends: Vec<String>,
+    /// Players who can neither challenge nor be matched with this player.
+    #[serde(default)]
+    pub blocked: Vec<String>,
+}
+
+/// The result of a finished game for one player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Win,
+    Loss,
+    Draw,
+}
+
+/// Results of the games between two players, from the first player's side.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeadToHead {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+/// Activity over one day or week.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Activity {
+    /// The day as `YYYY-MM-DD`, or the week as `YYYY-WW`.
+    pub period: String,
+    pub games: u32,
+    /// Distinct human players who finished a game in the period.
+    pub active_players: u32,
+}
+
+/// Server-wide statistics over finished games, for the operator dashboard.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerStats {
+    /// The last [`Storage::ACTIVITY_DAYS`](crate::storage::Storage::ACTIVITY_DAYS) days with games, oldest first.
+    pub daily: Vec<Activity>,
+    /// The last [`Storage::ACTIVITY_WEEKS`](crate::storage::Storage::ACTIVITY_WEEKS) weeks with games, oldest first.
+    pub weekly: Vec<Activity>,
+    /// Distinct human players who finished a game in the last seven days.
+    pub active_players: u32,
+    pub total_games: u32,
+    /// Average number of discs placed per game.
+    pub avg_game_length: f64,
+    /// Number of games between a human and the AI.
+    pub ai_games: u32,
+    /// Share of games against the AI won by the AI; draws count for neither side.
+    pub ai_win_rate: f64,
+    pub human_win_rate: f64,
+}
+
+/// A finished game played by the built-in AI, with how it was set up and searched, to tell
+/// whether a release plays weaker than the one before.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AiGameRecord {
+    pub game_id: String,
+    /// Color the AI played.
+    pub ai_color: Player,
+    pub opponent: String,
+    /// Version of the server the AI ran in, e.g. `"0.1.0"`.
+    pub engine_version: String,
+    pub simulations: u32,
+    pub exploration_constant: f64,
+    /// The AI's whole configuration.
+    pub config: serde_json::Value,
+    pub summary: SearchSummary,
+    /// Result for the AI.
+    pub outcome: Outcome,
+    pub ai_discs: u32,
+    pub opponent_discs: u32,
+    pub finished_at: String,
+}
+
+/// One run of the retention policy, deleting finished games past their retention period or,
+/// in a dry run, only listing them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionRun {
+    pub id: i64,
+    /// Days a finished game was kept for.
+    pub keep_days: u32,
+    pub dry_run: bool,
+    /// Games deleted, or that would have been in a dry run.
+    pub games: Vec<String>,
+    pub ran_at: String,
+}
+
+/// How the AI of one release fared at one number of simulations.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AiStrength {
+    pub engine_version: String,
+    pub simulations: u32,
+    pub games: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+    /// Share of the points the AI won, a draw counting half.
+    pub score: f64,
+    /// Averages over the moves the AI searched for.
+    pub simulations_per_search: f64,
+    pub ms_per_search: f64,
+    pub mean_q: f64,
+}
+
+/// A unit of background work.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Task {
+    /// Analyse every move of a finished game and store the report.
+    Analysis { game_id: String },
+    /// Run the anti-cheat analyzers on a move placed by a person and store what they flag.
+    CheatCheck { game_id: String, ply: usize },
+    /// Assess a move placed by a person in a coached game and tell them how good it was.
+    Coach { game_id: String, ply: usize },
+    /// Delete the finished games kept for longer than `keep_days` days, or in a dry run only
+    /// list them.
+    Prune { keep_days: u32, dry_run: bool },
+}
+
+impl Task {
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Task::Analysis { .. } => "analysis",
+            Task::CheatCheck { .. } => "cheat_check",
+            Task::Coach { .. } => "coach",
+            Task::Prune { .. } => "prune",
+        }
+    }
+
+    /// Returns the game the task works on, if any.
+    #[must_use]
+    pub fn game_id(&self) -> Option<&str> {
+        match self {
+            Task::Analysis { game_id } | Task::CheatCheck { game_id, .. } | Task::Coach { game_id, .. } => Some(game_id),
+            Task::Prune { .. } => None,
+        }
+    }
+}
+
+/// Where a job is in its life.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Waiting for a worker, possibly until a retry delay has passed.
+    Queued,
+    Running,
+    Done,
+    /// Failed on its last attempt.
+    Failed,
+}
+
+impl JobStatus {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Done => "done",
+            JobStatus::Failed => "failed",
+        }
+    }
+}
+
+impl std::str::FromStr for JobStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queued" => Ok(JobStatus::Queued),
+            "running" => Ok(JobStatus::Running),
+            "done" => Ok(JobStatus::Done),
+            "failed" => Ok(JobStatus::Failed),
+            _ => Err(format!("Unknown job status '{s}'")),
+        }
+    }
+}
+
+/// A queued, running or finished job.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: i64,
+    pub task: Task,
+    pub status: JobStatus,
+    /// Attempts started so far.
+    pub attempts: u32,
+    pub max_attempts: u32,
+    /// Error of the latest failed attempt.
+    pub last_error: Option<String>,
+    /// Earliest time the job may run, in milliseconds since the Unix epoch.
+    pub run_at: i64,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(value: serde_json::Value) -> Result<MoveCoord, serde_json::Error> {
+        serde_json::from_value(value)
+    }
+
+    #[test]
+    fn test_move_coord_formats() {
+        let d3 = MoveCoord::new(Game::coord_to_pos("D3").unwrap()).unwrap();
+        for value in [json!("D3"), json!("d3"), json!(43), json!("43"), json!({ "row": 5, "col": 3 })] {
+            assert_eq!(parse(value.clone()).unwrap(), d3, "{value}");
+        }
+        assert_eq!(serde_json::to_value(d3).unwrap(), json!("D3"));
+        let request: MoveRequest = serde_json::from_str(r#"{"coord": {"col": 0, "row": 7}}"#).unwrap();
+        assert_eq!(request.coord.to_string(), "A1");
+    }
+
+    #[test]
+    fn test_move_coord_rejects_invalid() {
+        for value in [
+            json!("I1"),
+            json!("D 3"),
+            json!(" D3"),
+            json!("64"),
+            json!(""),
+            json!(64),
+            json!(-1),
+            json!(4.5),
+            json!(null),
+            json!(["D3"]),
+            json!({ "row": 8, "col": 0 }),
+            json!({ "row": 1 }),
+            json!({ "row": 1, "col": 2, "extra": 0 }),
+        ] {
+            assert!(parse(value.clone()).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn test_task_json() {
+        let task = Task::Analysis {
+            game_id: "game_1".to_string(),
+        };
+        let json = serde_json::to_string(&task).unwrap();
+        assert_eq!(json, r#"{"kind":"analysis","game_id":"game_1"}"#);
+        assert_eq!(serde_json::from_str::<Task>(&json).unwrap(), task);
+        let prune = Task::Prune {
+            keep_days: 30,
+            dry_run: true,
+        };
+        let json = serde_json::to_string(&prune).unwrap();
+        assert_eq!(json, r#"{"kind":"prune","keep_days":30,"dry_run":true}"#);
+        assert_eq!(prune.kind(), "prune");
+        for status in [JobStatus::Queued, JobStatus::Running, JobStatus::Done, JobStatus::Failed] {
+            assert_eq!(status.as_str().parse(), Ok(status));
+        }
+    }
+}
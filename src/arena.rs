@@ -0,0 +1,576 @@
+//! Engine matches behind `kawio arena`.
+//!
+//! Two AI configurations, A and B, play each other from a series of opening lines. Every
+//! line is played twice with the colors swapped, a mirrored pair, so that neither engine
+//! profits from a lopsided opening. Lines are generated at random or picked at random from
+//! an opening file. Results are reported per pair and as A's Elo difference to B, with a 95%
+//! error margin estimated from the spread of the pair scores.
+//!
+//! With a [`Sprt`], the match stops as soon as a sequential probability ratio test tells
+//! whether A is at least `elo1` or at most `elo0` stronger than B, which is how an engine
+//! change is usually validated without fixing the number of games up front.
+
+use crate::ai::{AiConfig, MctsAi};
+use crate::game::{Game, GameReplay, Move, Player};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fmt;
+
+/// Standard normal quantile of a two-sided 95% interval.
+const Z_95: f64 = 1.96;
+
+/// Configuration of an arena match.
+#[derive(Clone, Debug)]
+pub struct ArenaConfig {
+    /// Mirrored pairs to play, two games each, or most pairs to play with a SPRT.
+    pub pairs: u32,
+    pub engine_a: AiConfig,
+    pub engine_b: AiConfig,
+    /// Random moves in each generated opening line.
+    pub opening_plies: u32,
+    /// Base seed; pair `n` picks its opening and seeds its searches from `seed + n`.
+    pub seed: u64,
+    /// Stops the match early once the test is decided.
+    pub sprt: Option<Sprt>,
+}
+
+/// A sequential probability ratio test of H0, A is `elo0` stronger than B, against H1, A is
+/// `elo1` stronger, with false positive rate `alpha` and false negative rate `beta`.
+///
+/// The log-likelihood ratio is estimated from the mean and variance of the pair scores,
+/// approximated as normally distributed, which holds well after a few dozen pairs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Default for Sprt {
+    fn default() -> Self {
+        Self {
+            elo0: 0.0,
+            elo1: 5.0,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+}
+
+/// The hypothesis a SPRT accepted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SprtDecision {
+    /// A is no more than `elo0` stronger: the change failed.
+    H0,
+    /// A is at least `elo1` stronger: the change passed.
+    H1,
+}
+
+impl Sprt {
+    /// Checks that the hypotheses are ordered and the error rates are probabilities.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first invalid parameter.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.elo0.partial_cmp(&self.elo1) != Some(std::cmp::Ordering::Less) {
+            return Err(format!("elo0 ({}) must be below elo1 ({})", self.elo0, self.elo1));
+        }
+        for (name, rate) in [("alpha", self.alpha), ("beta", self.beta)] {
+            if rate.is_nan() || rate <= 0.0 || rate >= 0.5 {
+                return Err(format!("{name} must be between 0 and 0.5, got {rate}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the log-likelihood ratios below which H0 and above which H1 is accepted.
+    #[must_use]
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+
+    /// Returns the log-likelihood ratio of H1 to H0 given the pairs played so far, or 0
+    /// while the pair scores have not varied yet.
+    #[must_use]
+    pub fn llr(&self, report: &ArenaReport) -> f64 {
+        let pairs = report.pairs.len();
+        let Some(variance) = report.pair_variance().filter(|&variance| variance > 0.0) else {
+            return 0.0;
+        };
+        let (score0, score1) = (expected_score(self.elo0), expected_score(self.elo1));
+        let pairs = f64::from(u32::try_from(pairs).unwrap_or(u32::MAX));
+        pairs * (score1 - score0) * (2.0 * report.score() - score0 - score1) / (2.0 * variance)
+    }
+
+    /// Returns the accepted hypothesis, or `None` while the test goes on.
+    #[must_use]
+    pub fn decision(&self, report: &ArenaReport) -> Option<SprtDecision> {
+        let llr = self.llr(report);
+        let (lower, upper) = self.bounds();
+        if llr <= lower {
+            Some(SprtDecision::H0)
+        } else if llr >= upper {
+            Some(SprtDecision::H1)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for ArenaConfig {
+    fn default() -> Self {
+        Self {
+            pairs: 100,
+            engine_a: AiConfig::default(),
+            engine_b: AiConfig::default(),
+            opening_plies: 6,
+            seed: 0,
+            sprt: None,
+        }
+    }
+}
+
+/// The two games of one opening line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PairResult {
+    /// Number of the pair, from 1.
+    pub pair: u32,
+    pub opening: Vec<Move>,
+    /// A's score with Black: 1 for a win, 0.5 for a draw, 0 for a loss.
+    pub a_as_black: f64,
+    /// A's score with White.
+    pub a_as_white: f64,
+}
+
+impl PairResult {
+    /// Returns A's score over both games, from 0 to 2.
+    #[must_use]
+    pub fn score(&self) -> f64 {
+        self.a_as_black + self.a_as_white
+    }
+}
+
+/// The results of an arena match so far.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ArenaReport {
+    pub pairs: Vec<PairResult>,
+    /// The test the match was played under, if any.
+    pub sprt: Option<Sprt>,
+}
+
+impl ArenaReport {
+    /// Returns A's wins, draws and losses over all games.
+    #[must_use]
+    pub fn wins_draws_losses(&self) -> (u32, u32, u32) {
+        let mut tally = (0, 0, 0);
+        for score in self.pairs.iter().flat_map(|pair| [pair.a_as_black, pair.a_as_white]) {
+            if score > 0.5 {
+                tally.0 += 1;
+            } else if score < 0.5 {
+                tally.2 += 1;
+            } else {
+                tally.1 += 1;
+            }
+        }
+        tally
+    }
+
+    /// Returns how many pairs A scored 0, 0.5, 1, 1.5 and 2 points in.
+    #[must_use]
+    pub fn pair_tally(&self) -> [u32; 5] {
+        let mut tally = [0; 5];
+        for pair in &self.pairs {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let index = (pair.score() * 2.0).round() as usize;
+            tally[index.min(4)] += 1;
+        }
+        tally
+    }
+
+    /// Returns A's mean score per game, from 0 to 1.
+    #[must_use]
+    pub fn score(&self) -> f64 {
+        if self.pairs.is_empty() {
+            return 0.5;
+        }
+        let total: f64 = self.pairs.iter().map(PairResult::score).sum();
+        total / (2.0 * f64::from(u32::try_from(self.pairs.len()).unwrap_or(u32::MAX)))
+    }
+
+    /// Returns A's Elo difference to B with its 95% error margin, or `None` if there are
+    /// fewer than two pairs or one side scored every point, which has no finite Elo.
+    #[must_use]
+    pub fn elo(&self) -> Option<(f64, f64)> {
+        let pairs = f64::from(u32::try_from(self.pairs.len()).unwrap_or(u32::MAX));
+        let score = self.score();
+        if pairs < 2.0 || score <= 0.0 || score >= 1.0 {
+            return None;
+        }
+        let variance = self.pair_variance()?;
+        let margin = Z_95 * (variance / pairs).sqrt();
+        let low = elo_difference((score - margin).max(f64::EPSILON));
+        let high = elo_difference((score + margin).min(1.0 - f64::EPSILON));
+        Some((elo_difference(score), (high - low) / 2.0))
+    }
+
+    /// Returns the sample variance of A's score per game over the pairs, or `None` with
+    /// fewer than two pairs. Pairs, not games, are the independent samples.
+    fn pair_variance(&self) -> Option<f64> {
+        let pairs = f64::from(u32::try_from(self.pairs.len()).unwrap_or(u32::MAX));
+        if pairs < 2.0 {
+            return None;
+        }
+        let score = self.score();
+        let squares: f64 = self.pairs.iter().map(|pair| (pair.score() / 2.0 - score).powi(2)).sum();
+        Some(squares / (pairs - 1.0))
+    }
+}
+
+impl fmt::Display for ArenaReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (wins, draws, losses) = self.wins_draws_losses();
+        writeln!(f, "Pairs: {}", self.pairs.len())?;
+        writeln!(f, "A: {wins} wins, {draws} draws, {losses} losses ({:.1}%)", self.score() * 100.0)?;
+        let [zero, half, one, one_half, two] = self.pair_tally();
+        writeln!(f, "Pair scores 0/0.5/1/1.5/2: {zero}/{half}/{one}/{one_half}/{two}")?;
+        match self.elo() {
+            Some((elo, margin)) => writeln!(f, "Elo A - B: {elo:+.1} +/- {margin:.1}")?,
+            None => writeln!(f, "Elo A - B: undetermined")?,
+        }
+        if let Some(sprt) = &self.sprt {
+            let outcome = match sprt.decision(self) {
+                Some(SprtDecision::H0) => "H0 accepted, A is not stronger",
+                Some(SprtDecision::H1) => "H1 accepted, A is stronger",
+                None => "inconclusive",
+            };
+            writeln!(f, "SPRT [{:+}, {:+}]: {} ({outcome})", sprt.elo0, sprt.elo1, sprt_progress(sprt, self))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the Elo difference that gives the expected score `score`.
+#[must_use]
+pub fn elo_difference(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/// Returns the expected score of a side `elo` stronger than its opponent.
+#[must_use]
+pub fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// Returns the test's log-likelihood ratio with its bounds, e.g. `LLR 1.23 [-2.94, 2.94]`.
+fn sprt_progress(sprt: &Sprt, report: &ArenaReport) -> String {
+    let (lower, upper) = sprt.bounds();
+    format!("LLR {:.2} [{lower:.2}, {upper:.2}]", sprt.llr(report))
+}
+
+/// Parses an opening file: one line of moves per opening, written as coordinates with or
+/// without spaces, e.g. `f5d6c3` or `F5 D6 C3`. Blank lines and lines starting with `#` are
+/// skipped.
+///
+/// # Errors
+///
+/// Returns an error naming the offending line if a move is malformed or illegal.
+pub fn parse_openings(content: &str) -> Result<Vec<Vec<Move>>, String> {
+    let mut openings = Vec::new();
+    for (index, raw) in content.lines().enumerate() {
+        let line = index + 1;
+        let text: String = raw.split_whitespace().collect();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        if !text.is_ascii() || !text.len().is_multiple_of(2) {
+            return Err(format!("line {line}: expected coordinates such as f5d6, got '{}'", raw.trim()));
+        }
+        let moves = (0..text.len())
+            .step_by(2)
+            .map(|start| text[start..start + 2].parse::<Move>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("line {line}: {e}"))?;
+        let replay = GameReplay::from_moves(moves.clone()).map_err(|e| format!("line {line}: {e}"))?;
+        if replay.board_at(moves.len()).is_some_and(Game::is_game_over) {
+            return Err(format!("line {line}: the opening ends the game"));
+        }
+        openings.push(moves);
+    }
+    Ok(openings)
+}
+
+/// Returns `plies` random legal moves from the starting position, stopping early if the game
+/// would end.
+pub fn random_opening<R: Rng>(plies: u32, rng: &mut R) -> Vec<Move> {
+    let mut game = Game::new();
+    let mut moves = Vec::new();
+    for _ in 0..plies {
+        let legal = game.legal_moves();
+        let Some(&pos) = legal.get(rng.gen_range(0..legal.len().max(1))) else {
+            break;
+        };
+        let mut next = game.clone();
+        if next.make_move(pos).is_err() || next.is_game_over() {
+            break;
+        }
+        moves.push(Move::Place(pos));
+        game = next;
+    }
+    moves
+}
+
+/// Plays mirrored pair `pair`, from a line picked at random from `openings`, or from a
+/// random line if there are none.
+#[must_use]
+pub fn play_pair(config: &ArenaConfig, openings: &[Vec<Move>], pair: u32) -> PairResult {
+    let seed = config.seed.wrapping_add(u64::from(pair));
+    let mut rng = StdRng::seed_from_u64(seed);
+    let opening = if openings.is_empty() {
+        random_opening(config.opening_plies, &mut rng)
+    } else {
+        openings[rng.gen_range(0..openings.len())].clone()
+    };
+    let a_as_black = play_game(&config.engine_a, &config.engine_b, &opening, seed);
+    let a_as_white = play_game(&config.engine_b, &config.engine_a, &opening, seed);
+    PairResult {
+        pair,
+        opening,
+        a_as_black: score_for(a_as_black, Player::Black),
+        a_as_white: score_for(a_as_white, Player::White),
+    }
+}
+
+/// Plays a whole match, printing each pair as it finishes. With a SPRT, the progress of the
+/// test is printed too, and the match stops once it is decided.
+#[must_use]
+pub fn run(config: &ArenaConfig, openings: &[Vec<Move>]) -> ArenaReport {
+    let mut report = ArenaReport {
+        pairs: Vec::new(),
+        sprt: config.sprt,
+    };
+    for pair in 1..=config.pairs {
+        let result = play_pair(config, openings, pair);
+        let line = describe_pair(&result);
+        report.pairs.push(result);
+        let Some(sprt) = &config.sprt else {
+            println!("{line}");
+            continue;
+        };
+        println!("{line} {}", sprt_progress(sprt, &report));
+        if sprt.decision(&report).is_some() {
+            break;
+        }
+    }
+    report
+}
+
+/// Returns a one-line summary of a pair.
+#[must_use]
+pub fn describe_pair(result: &PairResult) -> String {
+    let opening: String = result.opening.iter().map(ToString::to_string).collect();
+    format!(
+        "Pair {:>4} {:<16} A as Black: {:<3} A as White: {:<3} Pair: {}",
+        result.pair,
+        if opening.is_empty() { "-" } else { &opening },
+        result.a_as_black,
+        result.a_as_white,
+        result.score()
+    )
+}
+
+/// Plays one game from `opening` and returns the winner.
+fn play_game(black: &AiConfig, white: &AiConfig, opening: &[Move], seed: u64) -> Option<Player> {
+    let mut game = Game::new();
+    for &mv in opening {
+        match mv {
+            Move::Place(pos) => {
+                let _ = game.make_move(pos);
+            }
+            Move::Pass => game.pass(),
+        }
+    }
+    let mut black = MctsAi::new(AiConfig {
+        rng_seed: Some(seed),
+        ..black.clone()
+    });
+    let mut white = MctsAi::new(AiConfig {
+        rng_seed: Some(seed.wrapping_add(1)),
+        ..white.clone()
+    });
+    while !game.is_game_over() {
+        let ai = match game.current_player {
+            Player::Black => &mut black,
+            Player::White => &mut white,
+        };
+        let mv = ai.get_move(&game).unwrap_or(Move::Pass);
+        match mv {
+            Move::Place(pos) if game.make_move(pos).is_ok() => {}
+            _ => game.pass(),
+        }
+        black.make_move(mv);
+        white.make_move(mv);
+    }
+    game.winner()
+}
+
+fn score_for(winner: Option<Player>, color: Player) -> f64 {
+    match winner {
+        Some(winner) if winner == color => 1.0,
+        Some(_) => 0.0,
+        None => 0.5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcts::SelectionPolicy;
+
+    fn pair(a_as_black: f64, a_as_white: f64) -> PairResult {
+        PairResult {
+            pair: 1,
+            opening: Vec::new(),
+            a_as_black,
+            a_as_white,
+        }
+    }
+
+    #[test]
+    fn test_parse_openings() {
+        let openings = parse_openings("# tiger\nf5d6c3\n\nF5 D6 C5\n").unwrap();
+        let f5: Move = "F5".parse().unwrap();
+        assert_eq!(openings.len(), 2);
+        assert_eq!(openings[0][0], f5);
+        assert_eq!(openings[1].len(), 3);
+        assert!(parse_openings("f5d").unwrap_err().starts_with("line 1"));
+        assert!(parse_openings("f5\na1").unwrap_err().starts_with("line 2"));
+    }
+
+    #[test]
+    fn test_elo_from_pairs() {
+        let even = ArenaReport {
+            pairs: vec![pair(1.0, 0.0), pair(0.0, 1.0), pair(1.0, 0.0)],
+            sprt: None,
+        };
+        let (elo, margin) = even.elo().unwrap();
+        assert!(elo.abs() < 1e-9);
+        // Color-swapped pairs that split evenly leave no doubt about the score.
+        assert!(margin.abs() < 1e-9);
+
+        let stronger = ArenaReport {
+            pairs: vec![pair(1.0, 1.0), pair(1.0, 0.0), pair(1.0, 0.5), pair(0.5, 1.0)],
+            sprt: None,
+        };
+        assert_eq!(stronger.wins_draws_losses(), (5, 2, 1));
+        assert_eq!(stronger.pair_tally(), [0, 0, 1, 2, 1]);
+        let (elo, margin) = stronger.elo().unwrap();
+        assert!((elo - elo_difference(0.75)).abs() < 1e-9);
+        assert!(margin > 0.0);
+        let sweep = ArenaReport {
+            pairs: vec![pair(1.0, 1.0); 3],
+            sprt: None,
+        };
+        assert_eq!(sweep.elo(), None);
+    }
+
+    #[test]
+    fn test_sprt_decides_clear_matches() {
+        let sprt = Sprt {
+            elo0: 0.0,
+            elo1: 50.0,
+            ..Sprt::default()
+        };
+        assert!(sprt.validate().is_ok());
+        assert!(Sprt { elo1: -5.0, ..sprt }.validate().is_err());
+        assert!(Sprt { alpha: 0.0, ..sprt }.validate().is_err());
+        let (lower, upper) = sprt.bounds();
+        assert!((upper - 19f64.ln()).abs() < 1e-9 && (lower + 19f64.ln()).abs() < 1e-9);
+
+        let mut report = ArenaReport::default();
+        assert!(sprt.llr(&report).abs() < f64::EPSILON);
+        // A wins three pairs in four: far above H1, so the test passes.
+        while sprt.decision(&report).is_none() {
+            let score = if report.pairs.len() % 4 == 3 { 0.0 } else { 1.0 };
+            report.pairs.push(pair(score, score));
+            assert!(report.pairs.len() < 100);
+        }
+        assert_eq!(sprt.decision(&report), Some(SprtDecision::H1));
+
+        let losing = ArenaReport {
+            pairs: report.pairs.iter().map(|p| pair(1.0 - p.a_as_black, 1.0 - p.a_as_white)).collect(),
+            sprt: None,
+        };
+        assert_eq!(sprt.decision(&losing), Some(SprtDecision::H0));
+        assert!((expected_score(elo_difference(0.6)) - 0.6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_sprt_stops_the_match() {
+        let config = ArenaConfig {
+            pairs: 50,
+            engine_a: AiConfig {
+                simulations: 40,
+                ..AiConfig::default()
+            },
+            engine_b: AiConfig {
+                simulations: 1,
+                ..AiConfig::default()
+            },
+            sprt: Some(Sprt {
+                elo0: -400.0,
+                elo1: 0.0,
+                alpha: 0.2,
+                beta: 0.2,
+            }),
+            ..ArenaConfig::default()
+        };
+        let report = run(&config, &[]);
+        assert!(report.pairs.len() < 50);
+        assert!(config.sprt.unwrap().decision(&report).is_some());
+    }
+
+    #[test]
+    fn test_more_simulations_play_stronger() {
+        for selection in [SelectionPolicy::Uct, SelectionPolicy::Puct, SelectionPolicy::Ucb1Tuned] {
+            let config = ArenaConfig {
+                pairs: 2,
+                engine_a: AiConfig {
+                    simulations: 40,
+                    selection,
+                    ..AiConfig::default()
+                },
+                engine_b: AiConfig {
+                    simulations: 2,
+                    ..AiConfig::default()
+                },
+                ..ArenaConfig::default()
+            };
+            let report = run(&config, &[]);
+            assert!(report.score() >= 0.75, "{selection:?} scored {}", report.score());
+        }
+    }
+
+    #[test]
+    fn test_pair_swaps_colors_over_the_same_opening() {
+        let engine = AiConfig {
+            simulations: 5,
+            ..AiConfig::default()
+        };
+        let config = ArenaConfig {
+            pairs: 1,
+            engine_a: engine.clone(),
+            engine_b: engine,
+            ..ArenaConfig::default()
+        };
+        let first = play_pair(&config, &[], 1);
+        assert_eq!(first.opening.len(), 6);
+        assert_eq!(play_pair(&config, &[], 1), first);
+        let openings = parse_openings("f5d6c3d3c4").unwrap();
+        assert_eq!(play_pair(&config, &openings, 2).opening, openings[0]);
+    }
+}
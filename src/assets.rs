@@ -0,0 +1,88 @@
+//! Static files of the web UI.
+//!
+//! The UI is served from a directory on disk, read on every request so edits show up without
+//! restarting the server. With the `embed-web` feature the UI is also compiled into the binary
+//! and served from there when the directory is missing.
+
+use axum::Router;
+use std::path::Path;
+use tower_http::services::ServeDir;
+
+/// Returns a router serving the web UI from `dir`, or from the embedded copy if `dir` does not
+/// exist and the `embed-web` feature is enabled.
+pub fn router(dir: &Path) -> Router {
+    if dir.is_dir() {
+        tracing::info!("Serving web UI from {}", dir.display());
+        return Router::new().fallback_service(ServeDir::new(dir));
+    }
+    embedded_router(dir)
+}
+
+#[cfg(feature = "embed-web")]
+fn embedded_router(dir: &Path) -> Router {
+    tracing::info!("{} not found, serving the embedded web UI", dir.display());
+    Router::new().fallback(embedded::serve)
+}
+
+#[cfg(not(feature = "embed-web"))]
+fn embedded_router(dir: &Path) -> Router {
+    tracing::warn!("{} not found, the web UI is unavailable", dir.display());
+    Router::new()
+}
+
+#[cfg(feature = "embed-web")]
+mod embedded {
+    use axum::http::{header, StatusCode, Uri};
+    use axum::response::{IntoResponse, Response};
+    use rust_embed::RustEmbed;
+
+    #[derive(RustEmbed)]
+    #[folder = "web/"]
+    struct WebAssets;
+
+    pub(super) async fn serve(uri: Uri) -> Response {
+        let path = uri.path().trim_start_matches('/');
+        let path = if path.is_empty() || path.ends_with('/') {
+            format!("{path}index.html")
+        } else {
+            path.to_string()
+        };
+        match WebAssets::get(&path) {
+            Some(file) => (
+                [(header::CONTENT_TYPE, file.metadata.mimetype().to_string())],
+                file.data,
+            )
+                .into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    async fn get(router: Router, path: &str) -> StatusCode {
+        let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+        router.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_serves_directory() {
+        let router = router(Path::new(env!("CARGO_MANIFEST_DIR")).join("web").as_path());
+        assert_eq!(get(router.clone(), "/index.html").await, StatusCode::OK);
+        assert_eq!(get(router, "/missing.js").await, StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(feature = "embed-web")]
+    #[tokio::test]
+    async fn test_falls_back_to_embedded() {
+        let router = router(Path::new("no-such-dir"));
+        assert_eq!(get(router.clone(), "/").await, StatusCode::OK);
+        assert_eq!(get(router.clone(), "/app.js").await, StatusCode::OK);
+        assert_eq!(get(router, "/missing.js").await, StatusCode::NOT_FOUND);
+    }
+}
@@ -8,17 +8,55 @@ pub struct Claims {
     pub exp: usize,  // expiration time
 }
 
+/// Claims of an invite to a private game. They share no field but `exp` with [`Claims`],
+/// so an invite cannot pass for a login or the other way round.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InviteClaims {
+    pub game: String,
+    pub exp: usize,
+}
+
+/// Claims of the state carried through an identity provider's consent page, tying the
+/// browser coming back to the login it started. They share no field but `exp` with the
+/// other claims.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OAuthStateClaims {
+    pub provider: String,
+    /// Also kept in a cookie of the browser that started the login.
+    pub nonce: String,
+    /// The logged-in player linking the identity to their name, if one started the login.
+    #[serde(default)]
+    pub player: Option<String>,
+    pub exp: usize,
+}
+
+/// Claims of a ticket opening a socket for a player, spent on first use. They share no field
+/// but `exp` with the other claims.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TicketClaims {
+    /// The player the socket is opened for.
+    pub holder: String,
+    /// Tells the ticket apart from the others, to spend it.
+    pub ticket: String,
+    pub exp: usize,
+}
+
 pub struct Auth;
 
 impl Auth {
     const SECRET: &'static str = "your-secret-key"; // In production, use env var
 
+    /// Issues a signed token for the given player, valid for one hour.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the token cannot be encoded.
     pub fn generate_token(player: &str) -> Result<String, jsonwebtoken::errors::Error> {
-        let expiration = SystemTime::now()
+        let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as usize
-            + 3600; // 1 hour
+            .unwrap_or_default()
+            .as_secs();
+        let expiration = usize::try_from(now).unwrap_or(usize::MAX) + 3600; // 1 hour
 
         let claims = Claims {
             sub: player.to_string(),
@@ -32,6 +70,131 @@ impl Auth {
         )
     }
 
+    /// How long an invite stays valid, in seconds: a week.
+    pub const INVITE_LIFETIME: u64 = 7 * 24 * 3600;
+
+    /// Issues a signed invite to watch or join the game, valid for [`Auth::INVITE_LIFETIME`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the invite cannot be encoded.
+    pub fn generate_invite(game: &str) -> Result<String, jsonwebtoken::errors::Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let claims = InviteClaims {
+            game: game.to_string(),
+            exp: usize::try_from(now + Self::INVITE_LIFETIME).unwrap_or(usize::MAX),
+        };
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(Self::SECRET.as_ref()),
+        )
+    }
+
+    /// Returns true if `invite` is a valid invite to the game.
+    #[must_use]
+    pub fn validate_invite(invite: &str, game: &str) -> bool {
+        decode::<InviteClaims>(
+            invite,
+            &DecodingKey::from_secret(Self::SECRET.as_ref()),
+            &Validation::default(),
+        )
+        .is_ok_and(|data| data.claims.game == game)
+    }
+
+    /// How long a login through an identity provider may take, in seconds: ten minutes.
+    pub const OAUTH_STATE_LIFETIME: u64 = 600;
+
+    /// Issues the signed state of a login through `provider`, valid for
+    /// [`Auth::OAUTH_STATE_LIFETIME`], started by `player` if they link an identity to their
+    /// name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state cannot be encoded.
+    pub fn generate_oauth_state(
+        provider: &str,
+        nonce: &str,
+        player: Option<&str>,
+    ) -> Result<String, jsonwebtoken::errors::Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let claims = OAuthStateClaims {
+            provider: provider.to_string(),
+            nonce: nonce.to_string(),
+            player: player.map(str::to_string),
+            exp: usize::try_from(now + Self::OAUTH_STATE_LIFETIME).unwrap_or(usize::MAX),
+        };
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(Self::SECRET.as_ref()),
+        )
+    }
+
+    /// Returns the claims of `state` if it is the valid state of a login through `provider`
+    /// started by the browser holding `nonce`.
+    #[must_use]
+    pub fn validate_oauth_state(state: &str, provider: &str, nonce: &str) -> Option<OAuthStateClaims> {
+        decode::<OAuthStateClaims>(
+            state,
+            &DecodingKey::from_secret(Self::SECRET.as_ref()),
+            &Validation::default(),
+        )
+        .ok()
+        .map(|data| data.claims)
+        .filter(|claims| claims.provider == provider && claims.nonce == nonce)
+    }
+
+    /// How long a socket ticket stays valid, in seconds.
+    pub const TICKET_LIFETIME: u64 = 30;
+
+    /// Issues a signed ticket opening a socket for the player, valid for
+    /// [`Auth::TICKET_LIFETIME`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ticket cannot be encoded.
+    pub fn generate_ticket(player: &str) -> Result<String, jsonwebtoken::errors::Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let claims = TicketClaims {
+            holder: player.to_string(),
+            ticket: format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>()),
+            exp: usize::try_from(now + Self::TICKET_LIFETIME).unwrap_or(usize::MAX),
+        };
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(Self::SECRET.as_ref()),
+        )
+    }
+
+    /// Validates a socket ticket and returns its claims, without spending it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ticket is malformed, has an invalid signature, or has expired.
+    pub fn validate_ticket(ticket: &str) -> Result<TicketClaims, jsonwebtoken::errors::Error> {
+        // Tickets live for seconds, so the usual minute of leeway would outlast them.
+        let mut validation = Validation::default();
+        validation.leeway = 0;
+        let data = decode::<TicketClaims>(ticket, &DecodingKey::from_secret(Self::SECRET.as_ref()), &validation)?;
+        Ok(data.claims)
+    }
+
+    /// Validates a token and returns its claims.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the token is malformed, has an invalid signature, or has expired.
     pub fn validate_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
         let token_data = decode::<Claims>(
             token,
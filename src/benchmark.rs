@@ -0,0 +1,178 @@
+//! Engine throughput benchmark behind `kawio bench`.
+//!
+//! Every measurement runs over a fixed set of seeds so that reports from two builds can be
+//! compared directly. Reports can be saved as JSON and used as the baseline of a later run.
+
+use crate::ai::AiConfig;
+use crate::game::Game;
+use crate::mcts::random_playout;
+use crate::training::{self, TrainingConfig};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Write as _};
+use std::hint::black_box;
+use std::time::Instant;
+
+/// Seeds used for every measurement.
+pub const SEEDS: [u64; 8] = [1, 2, 3, 5, 8, 13, 21, 34];
+
+/// How much work each measurement does.
+#[derive(Clone, Debug)]
+pub struct BenchConfig {
+    /// Random playouts per seed.
+    pub playouts: u32,
+    /// Number of legal-move generations per sampled position.
+    pub legal_move_rounds: u32,
+    /// Self-play games, cycling through the seed set.
+    pub games: u32,
+    /// MCTS simulations per move in the self-play games.
+    pub simulations: u32,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            playouts: 1000,
+            legal_move_rounds: 1000,
+            games: 4,
+            simulations: 100,
+        }
+    }
+}
+
+/// Throughput measured by a benchmark run.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BenchReport {
+    pub playouts_per_sec: f64,
+    pub legal_move_generations_per_sec: f64,
+    pub self_play_games_per_sec: f64,
+    pub self_play_simulations: u32,
+}
+
+/// Runs all measurements.
+#[must_use]
+pub fn run(config: &BenchConfig) -> BenchReport {
+    BenchReport {
+        playouts_per_sec: measure_playouts(config.playouts),
+        legal_move_generations_per_sec: measure_legal_moves(config.legal_move_rounds),
+        self_play_games_per_sec: measure_self_play(config.games, config.simulations),
+        self_play_simulations: config.simulations,
+    }
+}
+
+fn rate(count: u32, started: Instant) -> f64 {
+    f64::from(count) / started.elapsed().as_secs_f64().max(f64::EPSILON)
+}
+
+fn measure_playouts(playouts_per_seed: u32) -> f64 {
+    let started = Instant::now();
+    let mut total = 0;
+    for seed in SEEDS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        for _ in 0..playouts_per_seed {
+            black_box(random_playout(Game::new(), &mut rng));
+            total += 1;
+        }
+    }
+    rate(total, started)
+}
+
+/// Samples the positions of one random game per seed, then times legal-move generation on them.
+fn measure_legal_moves(rounds: u32) -> f64 {
+    let mut positions = Vec::new();
+    for seed in SEEDS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut game = Game::new();
+        while !game.is_game_over() {
+            positions.push(game.clone());
+            let moves = game.legal_moves();
+            match moves.get(rand::Rng::gen_range(&mut rng, 0..moves.len().max(1))) {
+                Some(&pos) => {
+                    let _ = game.make_move(pos);
+                }
+                None => game.pass(),
+            }
+        }
+    }
+
+    let started = Instant::now();
+    let mut total = 0;
+    for _ in 0..rounds {
+        for position in &positions {
+            black_box(black_box(position).legal_moves());
+            total += 1;
+        }
+    }
+    rate(total, started)
+}
+
+fn measure_self_play(games: u32, simulations: u32) -> f64 {
+    let side = AiConfig {
+        simulations,
+        ..AiConfig::default()
+    };
+    let config = TrainingConfig {
+        black: side.clone(),
+        white: side,
+        ..TrainingConfig::default()
+    };
+    let started = Instant::now();
+    for (game, seed) in (1..=games).zip(SEEDS.iter().cycle()) {
+        black_box(training::play_game(&config, game, *seed));
+    }
+    rate(games, started)
+}
+
+impl BenchReport {
+    /// Formats this report next to a baseline, with the relative change of each metric.
+    #[must_use]
+    pub fn compare(&self, baseline: &BenchReport) -> String {
+        let rows = [
+            ("Playouts/sec", baseline.playouts_per_sec, self.playouts_per_sec),
+            (
+                "Legal-move generations/sec",
+                baseline.legal_move_generations_per_sec,
+                self.legal_move_generations_per_sec,
+            ),
+            (
+                "Self-play games/sec",
+                baseline.self_play_games_per_sec,
+                self.self_play_games_per_sec,
+            ),
+        ];
+        let mut out = format!("{:<28} {:>14} {:>14} {:>9}\n", "Metric", "Baseline", "Current", "Change");
+        for (name, before, after) in rows {
+            let change = if before > 0.0 {
+                (after / before - 1.0) * 100.0
+            } else {
+                0.0
+            };
+            let _ = writeln!(out, "{name:<28} {before:>14.2} {after:>14.2} {change:>+8.1}%");
+        }
+        if baseline.self_play_simulations != self.self_play_simulations {
+            let _ = writeln!(
+                out,
+                "Note: self-play used {} simulations per move, baseline used {}",
+                self.self_play_simulations, baseline.self_play_simulations
+            );
+        }
+        out
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Playouts/sec: {:.2}", self.playouts_per_sec)?;
+        writeln!(
+            f,
+            "Legal-move generations/sec: {:.2}",
+            self.legal_move_generations_per_sec
+        )?;
+        writeln!(
+            f,
+            "Self-play games/sec ({} simulations/move): {:.2}",
+            self.self_play_simulations, self.self_play_games_per_sec
+        )
+    }
+}
@@ -0,0 +1,373 @@
+//! Analysis boards: sandboxes shared by a few invited users, who set up positions, play and
+//! take back moves for either color and ask the engine for evaluations, each seeing the
+//! others' changes live over the board's socket.
+//!
+//! Boards are kept in the memory of the server they were opened on and are never stored, so
+//! nothing played on them is rated, archived or mined. They are gone once their owner closes
+//! them or the server restarts.
+
+use crate::game::{Game, IllegalMove, Player};
+use crate::protocol::{BoardServerMsg, MessageCode, Notice};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use tokio::sync::broadcast;
+
+/// Longest name a board may have, in characters.
+pub const MAX_BOARD_NAME_LEN: usize = 64;
+/// Most members a board may have, its owner included.
+pub const MAX_BOARD_MEMBERS: usize = 16;
+/// Most boards one user may own at once.
+pub const MAX_BOARDS_PER_OWNER: usize = 10;
+/// Most changes that can be taken back; older ones are forgotten.
+pub const MAX_UNDO: usize = 256;
+/// Messages kept for each board's sockets before slow ones miss some.
+const EVENT_CAPACITY: usize = 64;
+
+/// Why a board could not be opened or changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BoardError {
+    /// There is no such board, or the user is not one of its members.
+    NotFound,
+    /// Only the board's owner may invite users or close it.
+    NotTheOwner,
+    /// The name is empty or too long.
+    InvalidName,
+    /// The board has [`MAX_BOARD_MEMBERS`] members already.
+    TooManyMembers,
+    /// The owner has [`MAX_BOARDS_PER_OWNER`] boards open already.
+    TooManyBoards,
+    /// The disc cannot be placed there; holds why.
+    InvalidMove(IllegalMove),
+    /// The position could not be read; holds the reason.
+    InvalidPosition(String),
+    /// No change is left to take back.
+    NothingToUndo,
+}
+
+impl BoardError {
+    /// Returns the error as a message clients can translate.
+    #[must_use]
+    pub fn notice(&self) -> Notice {
+        match self {
+            BoardError::NotFound => Notice::new(MessageCode::BoardNotFound),
+            BoardError::NotTheOwner => Notice::new(MessageCode::NotTheBoardOwner),
+            BoardError::InvalidName => {
+                Notice::new(MessageCode::InvalidBoardName).with("max", MAX_BOARD_NAME_LEN.to_string())
+            }
+            BoardError::TooManyMembers => {
+                Notice::new(MessageCode::TooManyBoardMembers).with("max", MAX_BOARD_MEMBERS.to_string())
+            }
+            BoardError::TooManyBoards => {
+                Notice::new(MessageCode::TooManyBoards).with("max", MAX_BOARDS_PER_OWNER.to_string())
+            }
+            BoardError::InvalidMove(reason) => Notice::illegal_move(*reason),
+            BoardError::InvalidPosition(reason) => {
+                Notice::new(MessageCode::InvalidPosition).with("reason", reason.as_str())
+            }
+            BoardError::NothingToUndo => Notice::new(MessageCode::NothingToUndo),
+        }
+    }
+}
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.notice())
+    }
+}
+
+impl std::error::Error for BoardError {}
+
+/// A board shared by its members, who may all change it.
+#[derive(Debug)]
+pub struct AnalysisBoard {
+    name: String,
+    owner: String,
+    /// Users who may see and change the board, its owner included.
+    members: BTreeSet<String>,
+    game: Game,
+    /// Positions before each change that can still be taken back, the latest last.
+    undo: Vec<Game>,
+    /// Messages for every socket open on the board.
+    events: broadcast::Sender<BoardServerMsg>,
+}
+
+impl AnalysisBoard {
+    fn new(name: String, owner: String) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            name,
+            members: BTreeSet::from([owner.clone()]),
+            owner,
+            game: Game::new(),
+            undo: Vec::new(),
+            events,
+        }
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    /// Returns the members in alphabetical order.
+    pub fn members(&self) -> impl Iterator<Item = &str> {
+        self.members.iter().map(String::as_str)
+    }
+
+    #[must_use]
+    pub fn is_member(&self, player: &str) -> bool {
+        self.members.contains(player)
+    }
+
+    /// Returns the position on the board.
+    #[must_use]
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    /// Returns how many changes can be taken back.
+    #[must_use]
+    pub fn undo_depth(&self) -> usize {
+        self.undo.len()
+    }
+
+    /// Lets `player` see and change the board. Returns false if they were a member already.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BoardError::TooManyMembers`] if the board is full.
+    pub fn invite(&mut self, player: &str) -> Result<bool, BoardError> {
+        if self.is_member(player) {
+            return Ok(false);
+        }
+        if self.members.len() >= MAX_BOARD_MEMBERS {
+            return Err(BoardError::TooManyMembers);
+        }
+        Ok(self.members.insert(player.to_string()))
+    }
+
+    /// Places a disc for `color`, or for the side to move if `None`. Turns are not enforced:
+    /// either color may play next, and the other color is left to move even if it has to
+    /// pass.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BoardError::InvalidMove`] if the disc would flip nothing there.
+    pub fn play(&mut self, pos: u8, color: Option<Player>) -> Result<(), BoardError> {
+        let mut next = self.game.clone();
+        if let Some(color) = color.filter(|&color| color != next.current_player) {
+            next.current_player = color;
+            next.passes = 0;
+        }
+        next.try_place(pos).map_err(BoardError::InvalidMove)?;
+        self.change(next);
+        Ok(())
+    }
+
+    /// Hands the turn to the other color, whether or not the side to move could play.
+    pub fn pass(&mut self) {
+        let mut next = self.game.clone();
+        next.pass();
+        self.change(next);
+    }
+
+    /// Replaces the board with a position in the form of [`Game::from_position_string`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BoardError::InvalidPosition`] if the position cannot be read.
+    pub fn set_position(&mut self, position: &str) -> Result<(), BoardError> {
+        let game = Game::from_position_string(position).map_err(BoardError::InvalidPosition)?;
+        self.change(game);
+        Ok(())
+    }
+
+    /// Takes back the latest change, be it a move, a pass or a new position.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BoardError::NothingToUndo`] if there is no change left to take back.
+    pub fn undo(&mut self) -> Result<(), BoardError> {
+        self.game = self.undo.pop().ok_or(BoardError::NothingToUndo)?;
+        Ok(())
+    }
+
+    fn change(&mut self, next: Game) {
+        if self.undo.len() == MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.undo.push(std::mem::replace(&mut self.game, next));
+    }
+
+    /// Returns a receiver of the messages for the board's sockets.
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<BoardServerMsg> {
+        self.events.subscribe()
+    }
+
+    /// Sends a message to every socket open on the board.
+    pub fn publish(&self, message: BoardServerMsg) {
+        // Nobody may be listening, which is fine.
+        let _ = self.events.send(message);
+    }
+}
+
+/// The analysis boards open on a server, by id.
+#[derive(Debug, Default)]
+pub struct Boards {
+    boards: HashMap<String, AnalysisBoard>,
+}
+
+impl Boards {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a board owned by `owner` with the starting position, with the users of `invite`
+    /// as its other members, and returns its id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is empty or too long, the owner has too many boards open,
+    /// or there are too many users to invite.
+    pub fn create(&mut self, owner: &str, name: &str, invite: &[String]) -> Result<String, BoardError> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > MAX_BOARD_NAME_LEN {
+            return Err(BoardError::InvalidName);
+        }
+        if self.boards.values().filter(|board| board.owner == owner).count() >= MAX_BOARDS_PER_OWNER {
+            return Err(BoardError::TooManyBoards);
+        }
+        let mut board = AnalysisBoard::new(name.to_string(), owner.to_string());
+        for player in invite {
+            board.invite(player)?;
+        }
+        let id = loop {
+            let id = format!("{:016x}", rand::random::<u64>());
+            if !self.boards.contains_key(&id) {
+                break id;
+            }
+        };
+        self.boards.insert(id.clone(), board);
+        Ok(id)
+    }
+
+    /// Returns the board `id` as seen by `player`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BoardError::NotFound`] if there is no such board or `player` is not a member.
+    pub fn get(&self, id: &str, player: &str) -> Result<&AnalysisBoard, BoardError> {
+        self.boards
+            .get(id)
+            .filter(|board| board.is_member(player))
+            .ok_or(BoardError::NotFound)
+    }
+
+    /// Returns the board `id` for `player` to change.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BoardError::NotFound`] if there is no such board or `player` is not a member.
+    pub fn get_mut(&mut self, id: &str, player: &str) -> Result<&mut AnalysisBoard, BoardError> {
+        self.boards
+            .get_mut(id)
+            .filter(|board| board.is_member(player))
+            .ok_or(BoardError::NotFound)
+    }
+
+    /// Returns the boards `player` is a member of, by id.
+    pub fn of<'a>(&'a self, player: &'a str) -> impl Iterator<Item = (&'a str, &'a AnalysisBoard)> {
+        self.boards
+            .iter()
+            .filter(move |(_, board)| board.is_member(player))
+            .map(|(id, board)| (id.as_str(), board))
+    }
+
+    /// Closes the board `id`, which only its owner may do. The sockets open on it are closed
+    /// with it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `player` cannot see the board or does not own it.
+    pub fn close(&mut self, id: &str, player: &str) -> Result<(), BoardError> {
+        if self.get(id, player)?.owner != player {
+            return Err(BoardError::NotTheOwner);
+        }
+        self.boards.remove(id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(coord: &str) -> u8 {
+        Game::coord_to_pos(coord).unwrap()
+    }
+
+    #[test]
+    fn test_boards_seen_by_members_only() {
+        let mut boards = Boards::new();
+        let id = boards.create("Alice", " Endgame study ", &["Bob".to_string()]).unwrap();
+        let board = boards.get(&id, "Bob").unwrap();
+        assert_eq!(board.name(), "Endgame study");
+        assert_eq!(board.members().collect::<Vec<_>>(), ["Alice", "Bob"]);
+        assert_eq!(boards.get(&id, "Carol").unwrap_err(), BoardError::NotFound);
+        assert_eq!(boards.of("Bob").count(), 1);
+        assert_eq!(boards.of("Carol").count(), 0);
+
+        assert!(boards.get_mut(&id, "Alice").unwrap().invite("Carol").unwrap());
+        assert!(!boards.get_mut(&id, "Alice").unwrap().invite("Carol").unwrap());
+        assert_eq!(boards.close(&id, "Bob").unwrap_err(), BoardError::NotTheOwner);
+        boards.close(&id, "Alice").unwrap();
+        assert_eq!(boards.get(&id, "Alice").unwrap_err(), BoardError::NotFound);
+
+        assert_eq!(boards.create("Alice", "  ", &[]).unwrap_err(), BoardError::InvalidName);
+        for _ in 0..MAX_BOARDS_PER_OWNER {
+            boards.create("Alice", "Study", &[]).unwrap();
+        }
+        assert_eq!(boards.create("Alice", "Study", &[]).unwrap_err(), BoardError::TooManyBoards);
+        let crowd: Vec<String> = (0..MAX_BOARD_MEMBERS).map(|i| format!("Player{i}")).collect();
+        assert_eq!(boards.create("Bob", "Crowd", &crowd).unwrap_err(), BoardError::TooManyMembers);
+    }
+
+    #[test]
+    fn test_moves_ignore_turns_and_undo() {
+        let mut boards = Boards::new();
+        let id = boards.create("Alice", "Study", &[]).unwrap();
+        let board = boards.get_mut(&id, "Alice").unwrap();
+        board.play(pos("D3"), None).unwrap();
+        assert_eq!(board.game().current_player, Player::White);
+        // Black plays again, out of turn.
+        board.play(pos("F5"), Some(Player::Black)).unwrap();
+        assert_eq!(board.game().disc_count(), (6, 0));
+        assert!(matches!(board.play(pos("A1"), None), Err(BoardError::InvalidMove(_))));
+        board.pass();
+        assert_eq!(board.game().current_player, Player::Black);
+        assert_eq!(board.undo_depth(), 3);
+
+        board.undo().unwrap();
+        board.undo().unwrap();
+        assert_eq!(board.game().disc_count(), (4, 1));
+        board.undo().unwrap();
+        assert_eq!(*board.game(), Game::new());
+        assert_eq!(board.undo(), Err(BoardError::NothingToUndo));
+
+        let position = "---------------------------OX------XO--------------------------- O";
+        board.set_position(position).unwrap();
+        assert_eq!(board.game().to_position_string(), position);
+        assert!(matches!(board.set_position("-- X"), Err(BoardError::InvalidPosition(_))));
+        for _ in 0..MAX_UNDO {
+            board.pass();
+        }
+        assert_eq!(board.undo_depth(), MAX_UNDO);
+    }
+}
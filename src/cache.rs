@@ -0,0 +1,168 @@
+//! Ephemeral state of the sessions: the matchmaking queue and the sockets open on each game.
+//!
+//! It is kept in memory by default. With the `redis` feature it can be kept in Redis
+//! instead, see [`redis::RedisCache`], where the queue survives restarts and servers serving
+//! the same games see each other's players and sockets.
+
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "redis")]
+pub mod redis;
+
+/// Where the ephemeral state of the sessions is kept.
+pub trait SessionCache: Send + Sync {
+    /// Returns the players waiting for a match, longest waiting first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache cannot be read.
+    fn waiting(&self) -> Result<Vec<String>, String>;
+
+    /// Adds a player to the end of the matchmaking queue.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache cannot be written.
+    fn push_waiting(&self, player: &str) -> Result<(), String>;
+
+    /// Removes a player from the matchmaking queue. Returns false if they were not waiting,
+    /// e.g. because another server paired them first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache cannot be written.
+    fn take_waiting(&self, player: &str) -> Result<bool, String>;
+
+    /// Records a socket opened on a game by a player, or by a spectator with `None`, and
+    /// returns how many sockets the player, or all spectators, now have open on it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache cannot be written.
+    fn add_socket(&self, id: &str, player: Option<&str>) -> Result<usize, String>;
+
+    /// Records a socket opened by [`add_socket`](Self::add_socket) closing, and returns how
+    /// many sockets the player, or all spectators, still have open on the game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache cannot be written.
+    fn remove_socket(&self, id: &str, player: Option<&str>) -> Result<usize, String>;
+
+    /// Returns the players with a socket open on the game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache cannot be read.
+    fn connected(&self, id: &str) -> Result<HashSet<String>, String>;
+
+    /// Returns the number of sockets open on the game by spectators.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache cannot be read.
+    fn spectators(&self, id: &str) -> Result<usize, String>;
+
+    /// Forgets the sockets open on a game, e.g. once it is deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache cannot be written.
+    fn forget(&self, id: &str) -> Result<(), String>;
+}
+
+/// Keeps the ephemeral state in memory, for a single server.
+#[derive(Debug, Default)]
+pub struct MemoryCache {
+    queue: Mutex<Vec<String>>,
+    presence: Mutex<HashMap<String, Presence>>,
+}
+
+/// Sockets open on one game.
+#[derive(Debug, Default)]
+struct Presence {
+    /// Sockets open by each player of the game, by name.
+    players: HashMap<String, usize>,
+    /// Sockets open by anyone else, including anonymous ones.
+    spectators: usize,
+}
+
+impl MemoryCache {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SessionCache for MemoryCache {
+    fn waiting(&self) -> Result<Vec<String>, String> {
+        Ok(self.queue.lock().clone())
+    }
+
+    fn push_waiting(&self, player: &str) -> Result<(), String> {
+        self.queue.lock().push(player.to_string());
+        Ok(())
+    }
+
+    fn take_waiting(&self, player: &str) -> Result<bool, String> {
+        let mut queue = self.queue.lock();
+        let Some(index) = queue.iter().position(|waiting| waiting == player) else {
+            return Ok(false);
+        };
+        queue.remove(index);
+        Ok(true)
+    }
+
+    fn add_socket(&self, id: &str, player: Option<&str>) -> Result<usize, String> {
+        let mut presence = self.presence.lock();
+        let presence = presence.entry(id.to_string()).or_default();
+        let sockets = match player {
+            Some(player) => presence.players.entry(player.to_string()).or_default(),
+            None => &mut presence.spectators,
+        };
+        *sockets += 1;
+        Ok(*sockets)
+    }
+
+    fn remove_socket(&self, id: &str, player: Option<&str>) -> Result<usize, String> {
+        let mut all = self.presence.lock();
+        let Some(presence) = all.get_mut(id) else {
+            return Ok(0);
+        };
+        let left = if let Some(player) = player {
+            let sockets = presence.players.entry(player.to_string()).or_default();
+            *sockets = sockets.saturating_sub(1);
+            let left = *sockets;
+            if left == 0 {
+                presence.players.remove(player);
+            }
+            left
+        } else {
+            presence.spectators = presence.spectators.saturating_sub(1);
+            presence.spectators
+        };
+        if presence.players.is_empty() && presence.spectators == 0 {
+            all.remove(id);
+        }
+        Ok(left)
+    }
+
+    fn connected(&self, id: &str) -> Result<HashSet<String>, String> {
+        Ok(self
+            .presence
+            .lock()
+            .get(id)
+            .map(|presence| presence.players.keys().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn spectators(&self, id: &str) -> Result<usize, String> {
+        Ok(self.presence.lock().get(id).map_or(0, |presence| presence.spectators))
+    }
+
+    fn forget(&self, id: &str) -> Result<(), String> {
+        self.presence.lock().remove(id);
+        Ok(())
+    }
+}
@@ -0,0 +1,508 @@
+//! Ephemeral state and updates between servers kept in Redis.
+//!
+//! Needs the `redis` feature. [`RedisCache`] keeps the matchmaking queue and the sockets open
+//! on each game, and [`RedisBus`] carries the updates of servers sharing a database over
+//! Redis pub/sub instead of the database itself.
+//!
+//! Each server counts its own sockets and refreshes a short-lived key while it runs, so the
+//! sockets of a server that stopped without closing them stop counting soon after.
+//!
+//! The sessions use the cache and the bus while holding their lock, so socket counts and
+//! updates never wait on Redis: each server keeps its own counts in memory and a background
+//! thread writes them out and reads the other servers' counts every [`REFRESH_INTERVAL`],
+//! while updates are published from a thread of their own. Only the matchmaking queue is
+//! read and written on the spot.
+
+use super::SessionCache;
+use crate::sync::{GameUpdate, UpdateBus, UPDATE_BUFFER};
+use futures_util::StreamExt;
+use parking_lot::Mutex;
+use redis::{Client, Commands, Connection, ErrorKind, RedisError, RedisResult};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// Prefix of every key kawio uses.
+const PREFIX: &str = "kawio";
+
+/// How long a server counts as running after it last said so.
+const SERVER_TTL_SECS: u64 = 30;
+
+/// How often a running server says so.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long the sockets of a game are remembered after they last changed, in case every
+/// server counting them stopped.
+const PRESENCE_TTL_SECS: i64 = 24 * 60 * 60;
+
+/// How often the sockets other servers have open are read again.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest a command may wait for Redis.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long Redis is left alone after it failed to answer, so an unreachable server holds
+/// up the matchmaking queue for one [`TIMEOUT`] at a time rather than on every call.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Channel the updates of servers sharing a database are published on.
+const UPDATES_CHANNEL: &str = "kawio:updates";
+
+/// Sockets open on each game, by player name with `None` for spectators.
+type Sockets = HashMap<String, HashMap<Option<String>, usize>>;
+
+/// A connection to Redis, opened again when it drops.
+struct Link {
+    client: Client,
+    conn: Mutex<Option<Connection>>,
+    /// Until when commands fail without trying Redis, after it failed to answer.
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Link {
+    fn open(url: &str) -> RedisResult<Self> {
+        let link = Self {
+            client: Client::open(url)?,
+            conn: Mutex::new(None),
+            down_until: Mutex::new(None),
+        };
+        link.run(|conn| redis::cmd("PING").query::<()>(conn))?;
+        Ok(link)
+    }
+
+    /// Runs commands on the connection, connecting again and retrying once if it dropped.
+    /// Fails at once for [`RETRY_DELAY`] after Redis could not be reached or timed out.
+    fn run<T>(&self, commands: impl FnMut(&mut Connection) -> RedisResult<T>) -> RedisResult<T> {
+        if self.down_until.lock().is_some_and(|until| Instant::now() < until) {
+            return Err(RedisError::from((ErrorKind::IoError, "Redis is unreachable")));
+        }
+        let result = self.try_run(commands);
+        match &result {
+            Err(e) if e.is_io_error() || e.is_timeout() || e.is_connection_dropped() => {
+                *self.conn.lock() = None;
+                *self.down_until.lock() = Some(Instant::now() + RETRY_DELAY);
+            }
+            _ => *self.down_until.lock() = None,
+        }
+        result
+    }
+
+    fn try_run<T>(&self, mut commands: impl FnMut(&mut Connection) -> RedisResult<T>) -> RedisResult<T> {
+        let mut conn = self.conn.lock();
+        if let Some(open) = conn.as_mut() {
+            match commands(open) {
+                Err(e) if (e.is_io_error() || e.is_connection_dropped()) && !e.is_timeout() => {}
+                result => return result,
+            }
+        }
+        let open = conn.insert(self.client.get_connection_with_timeout(TIMEOUT)?);
+        open.set_read_timeout(Some(TIMEOUT))?;
+        open.set_write_timeout(Some(TIMEOUT))?;
+        commands(open)
+    }
+}
+
+/// What a cache shares with the thread keeping its sockets in step with Redis.
+#[derive(Default)]
+struct Presence {
+    /// Sockets open on this server.
+    local: Mutex<Sockets>,
+    /// Sockets open on the other running servers, as last read from Redis.
+    remote: Mutex<Sockets>,
+    /// Games whose sockets on this server changed since they were last written.
+    changed: Mutex<HashSet<String>>,
+    /// Games whose sockets are to be removed from Redis.
+    forgotten: Mutex<HashSet<String>>,
+}
+
+impl Presence {
+    /// Returns the sockets `player` has open on the game on the other servers.
+    fn remote_count(&self, id: &str, player: Option<&str>) -> usize {
+        self.remote
+            .lock()
+            .get(id)
+            .and_then(|sockets| sockets.get(&player.map(str::to_string)))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Returns the sockets open on a game across the running servers.
+    fn sockets(&self, id: &str) -> HashMap<Option<String>, usize> {
+        let mut sockets = self.local.lock().get(id).cloned().unwrap_or_default();
+        if let Some(remote) = self.remote.lock().get(id) {
+            for (who, count) in remote {
+                *sockets.entry(who.clone()).or_default() += count;
+            }
+        }
+        sockets
+    }
+}
+
+/// Keeps the matchmaking queue and the sockets open on each game in Redis.
+pub struct RedisCache {
+    link: Arc<Link>,
+    /// Id of this server, which its socket counts are filed under.
+    server: String,
+    presence: Arc<Presence>,
+    /// Wakes the thread writing the sockets, which stops once the cache is dropped.
+    wake: mpsc::Sender<()>,
+}
+
+impl RedisCache {
+    /// Connects to the Redis server at `url`, e.g. `redis://127.0.0.1/`, for the server with
+    /// id `server`, and starts telling the other servers it runs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if Redis cannot be reached.
+    pub fn connect(url: &str, server: &str) -> Result<Self, String> {
+        let link = Arc::new(Link::open(url).map_err(|e| e.to_string())?);
+        let (wake, woken) = mpsc::channel();
+        let cache = Self {
+            link,
+            server: server.to_string(),
+            presence: Arc::default(),
+            wake,
+        };
+        cache.heartbeat().map_err(|e| e.to_string())?;
+        let link = Arc::downgrade(&cache.link);
+        let key = server_key(server);
+        std::thread::spawn(move || Self::keep_alive(&link, &key));
+        let link = cache.link.clone();
+        let server = cache.server.clone();
+        let presence = cache.presence.clone();
+        std::thread::spawn(move || Self::sync(&link, &server, &presence, &woken));
+        Ok(cache)
+    }
+
+    fn heartbeat(&self) -> RedisResult<()> {
+        let key = server_key(&self.server);
+        self.link.run(|conn| conn.set_ex(&key, 1, SERVER_TTL_SECS))
+    }
+
+    /// Refreshes the server's key until the cache is dropped.
+    fn keep_alive(link: &Weak<Link>, key: &str) {
+        loop {
+            std::thread::sleep(HEARTBEAT_INTERVAL);
+            let Some(link) = link.upgrade() else {
+                break;
+            };
+            if let Err(e) = link.run(|conn| conn.set_ex::<_, _, ()>(key, 1, SERVER_TTL_SECS)) {
+                tracing::warn!("Failed to refresh the server's key in Redis: {e}");
+            }
+        }
+    }
+
+    /// Writes the sockets of this server as they change and reads those of the others,
+    /// until the cache is dropped.
+    fn sync(link: &Link, server: &str, presence: &Presence, woken: &mpsc::Receiver<()>) {
+        let mut refreshed: Option<Instant> = None;
+        let mut failing = false;
+        while let Ok(()) | Err(RecvTimeoutError::Timeout) = woken.recv_timeout(REFRESH_INTERVAL) {
+            while woken.try_recv().is_ok() {}
+            if failing {
+                // Other servers may have dropped our counts meanwhile, taking us for stopped.
+                let games: Vec<String> = presence.local.lock().keys().cloned().collect();
+                presence.changed.lock().extend(games);
+            }
+            let mut result = Self::write(link, server, presence);
+            if result.is_ok() && refreshed.is_none_or(|at| at.elapsed() >= REFRESH_INTERVAL) {
+                result = Self::refresh(link, server, presence);
+                refreshed = Some(Instant::now());
+            }
+            match result {
+                Ok(()) => failing = false,
+                Err(e) if !failing => {
+                    tracing::warn!("Failed to keep the sockets in step with Redis: {e}");
+                    failing = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Writes the sockets of this server on the games that changed, keeping them to write
+    /// again if Redis fails.
+    fn write(link: &Link, server: &str, presence: &Presence) -> RedisResult<()> {
+        let forgotten = std::mem::take(&mut *presence.forgotten.lock());
+        let changed = std::mem::take(&mut *presence.changed.lock());
+        let result = forgotten
+            .iter()
+            .try_for_each(|id| link.run(|conn| conn.del(presence_key(id))))
+            .and_then(|()| {
+                changed.iter().try_for_each(|id| {
+                    let sockets = presence.local.lock().get(id).cloned().unwrap_or_default();
+                    link.run(|conn| write_sockets(conn, server, id, &sockets))
+                })
+            });
+        if result.is_err() {
+            presence.forgotten.lock().extend(forgotten);
+            presence.changed.lock().extend(changed);
+        }
+        result
+    }
+
+    /// Reads the sockets the other running servers have open. Counts left by servers that
+    /// stopped are dropped.
+    fn refresh(link: &Link, server: &str, presence: &Presence) -> RedisResult<()> {
+        let remote = link.run(|conn| {
+            let prefix = presence_key("");
+            let keys: Vec<String> = conn.scan_match(format!("{prefix}*"))?.collect();
+            let mut running = HashMap::from([(server.to_string(), true)]);
+            let mut remote = Sockets::new();
+            for key in keys {
+                let sockets = remote_sockets(conn, server, &key, &mut running)?;
+                if !sockets.is_empty() {
+                    remote.insert(key[prefix.len()..].to_string(), sockets);
+                }
+            }
+            Ok(remote)
+        })?;
+        *presence.remote.lock() = remote;
+        Ok(())
+    }
+
+    /// Counts a socket of `player` on the game opening or closing on this server, returning
+    /// the sockets they have open across the running servers.
+    fn count_socket(&self, id: &str, player: Option<&str>, opened: bool) -> usize {
+        let who = player.map(str::to_string);
+        let mine = {
+            let mut local = self.presence.local.lock();
+            let game = local.entry(id.to_string()).or_default();
+            let count = game.entry(who.clone()).or_default();
+            *count = if opened { *count + 1 } else { count.saturating_sub(1) };
+            let mine = *count;
+            if mine == 0 {
+                game.remove(&who);
+                if game.is_empty() {
+                    local.remove(id);
+                }
+            }
+            mine
+        };
+        self.presence.changed.lock().insert(id.to_string());
+        let _ = self.wake.send(());
+        mine + self.presence.remote_count(id, player)
+    }
+}
+
+impl SessionCache for RedisCache {
+    fn waiting(&self) -> Result<Vec<String>, String> {
+        self.link
+            .run(|conn| conn.lrange(queue_key(), 0, -1))
+            .map_err(|e| e.to_string())
+    }
+
+    fn push_waiting(&self, player: &str) -> Result<(), String> {
+        self.link
+            .run(|conn| conn.rpush(queue_key(), player))
+            .map_err(|e| e.to_string())
+    }
+
+    fn take_waiting(&self, player: &str) -> Result<bool, String> {
+        self.link
+            .run(|conn| conn.lrem::<_, _, usize>(queue_key(), 1, player))
+            .map(|removed| removed > 0)
+            .map_err(|e| e.to_string())
+    }
+
+    fn add_socket(&self, id: &str, player: Option<&str>) -> Result<usize, String> {
+        Ok(self.count_socket(id, player, true))
+    }
+
+    fn remove_socket(&self, id: &str, player: Option<&str>) -> Result<usize, String> {
+        Ok(self.count_socket(id, player, false))
+    }
+
+    fn connected(&self, id: &str) -> Result<HashSet<String>, String> {
+        Ok(self.presence.sockets(id).into_keys().flatten().collect())
+    }
+
+    fn spectators(&self, id: &str) -> Result<usize, String> {
+        Ok(self.presence.sockets(id).get(&None).copied().unwrap_or(0))
+    }
+
+    fn forget(&self, id: &str) -> Result<(), String> {
+        self.presence.local.lock().remove(id);
+        self.presence.remote.lock().remove(id);
+        self.presence.changed.lock().remove(id);
+        self.presence.forgotten.lock().insert(id.to_string());
+        let _ = self.wake.send(());
+        Ok(())
+    }
+}
+
+/// Replaces the sockets `server` has on the game with `sockets`.
+fn write_sockets(
+    conn: &mut Connection,
+    server: &str,
+    id: &str,
+    sockets: &HashMap<Option<String>, usize>,
+) -> RedisResult<()> {
+    let key = presence_key(id);
+    let own = format!("{server}/");
+    let fields: Vec<String> = conn.hkeys(&key)?;
+    let stale: Vec<String> = fields.into_iter().filter(|field| field.starts_with(&own)).collect();
+    let counts: Vec<(String, usize)> = sockets
+        .iter()
+        .map(|(who, count)| (socket_field(server, who.as_deref()), *count))
+        .collect();
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    if !stale.is_empty() {
+        pipe.hdel(&key, stale).ignore();
+    }
+    if !counts.is_empty() {
+        pipe.hset_multiple(&key, &counts)
+            .ignore()
+            .expire(&key, PRESENCE_TTL_SECS)
+            .ignore();
+    }
+    pipe.query(conn)
+}
+
+/// Returns the sockets servers other than `server` have open under `key`, by player name
+/// with `None` for spectators. Counts left by servers that stopped are dropped.
+fn remote_sockets(
+    conn: &mut Connection,
+    server: &str,
+    key: &str,
+    running: &mut HashMap<String, bool>,
+) -> RedisResult<HashMap<Option<String>, usize>> {
+    let fields: HashMap<String, i64> = conn.hgetall(key)?;
+    let mut sockets = HashMap::new();
+    let mut stale = Vec::new();
+    for (field, count) in fields {
+        let Some((owner, who)) = field.split_once('/') else {
+            continue;
+        };
+        if owner == server {
+            continue;
+        }
+        let alive = if let Some(&alive) = running.get(owner) {
+            alive
+        } else {
+            let alive = conn.exists(server_key(owner))?;
+            running.insert(owner.to_string(), alive);
+            alive
+        };
+        if !alive {
+            stale.push(field.clone());
+            continue;
+        }
+        let who = who.strip_prefix("player:").map(str::to_string);
+        *sockets.entry(who).or_default() += usize::try_from(count).unwrap_or(0);
+    }
+    if !stale.is_empty() {
+        conn.hdel::<_, _, ()>(key, stale)?;
+    }
+    Ok(sockets)
+}
+
+/// Carries the updates of servers sharing a database over Redis pub/sub.
+pub struct RedisBus {
+    client: Client,
+    sender: broadcast::Sender<GameUpdate>,
+    /// Updates waiting for the thread publishing them, which stops once the bus is dropped.
+    outgoing: mpsc::Sender<String>,
+}
+
+impl RedisBus {
+    /// Connects to the Redis server at `url` and starts listening for updates. Listening
+    /// resumes by itself if the connection drops, but updates published meanwhile are lost.
+    ///
+    /// Must be called from within a Tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if Redis cannot be reached.
+    pub fn connect(url: &str) -> Result<Arc<Self>, String> {
+        let link = Link::open(url).map_err(|e| e.to_string())?;
+        let (outgoing, pending) = mpsc::channel();
+        let bus = Arc::new(Self {
+            client: link.client.clone(),
+            sender: broadcast::channel(UPDATE_BUFFER).0,
+            outgoing,
+        });
+        std::thread::spawn(move || Self::publish_pending(&link, &pending));
+        let client = bus.client.clone();
+        let listening = Arc::downgrade(&bus);
+        tokio::spawn(async move {
+            while listening.strong_count() > 0 {
+                if let Err(e) = Self::listen(&client, &listening).await {
+                    tracing::warn!("Lost the Redis update channel: {e}");
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        });
+        Ok(bus)
+    }
+
+    /// Publishes the updates handed over in order, until the bus is dropped.
+    fn publish_pending(link: &Link, pending: &mpsc::Receiver<String>) {
+        for payload in pending {
+            if let Err(e) = link.run(|conn| conn.publish::<_, _, ()>(UPDATES_CHANNEL, &payload)) {
+                tracing::warn!("Failed to publish game update: {e}");
+            }
+        }
+    }
+
+    /// Forwards the updates published on Redis until the connection drops or the bus is
+    /// dropped.
+    async fn listen(client: &Client, bus: &Weak<Self>) -> RedisResult<()> {
+        let mut pubsub = client.get_async_pubsub().await?;
+        pubsub.subscribe(UPDATES_CHANNEL).await?;
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let Some(bus) = bus.upgrade() else {
+                break;
+            };
+            let payload: String = message.get_payload()?;
+            match serde_json::from_str(&payload) {
+                Ok(update) => {
+                    let _ = bus.sender.send(update);
+                }
+                Err(e) => tracing::warn!("Skipping malformed game update: {e}"),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl UpdateBus for RedisBus {
+    fn publish(&self, update: &GameUpdate) {
+        match serde_json::to_string(update) {
+            Ok(payload) => {
+                let _ = self.outgoing.send(payload);
+            }
+            Err(e) => tracing::warn!("Failed to encode game update: {e}"),
+        }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<GameUpdate> {
+        self.sender.subscribe()
+    }
+}
+
+fn queue_key() -> String {
+    format!("{PREFIX}:queue")
+}
+
+fn presence_key(id: &str) -> String {
+    format!("{PREFIX}:presence:{id}")
+}
+
+fn server_key(server: &str) -> String {
+    format!("{PREFIX}:server:{server}")
+}
+
+/// Returns the field the sockets `server` has open for `player`, or for spectators, are
+/// counted under.
+fn socket_field(server: &str, player: Option<&str>) -> String {
+    match player {
+        Some(player) => format!("{server}/player:{player}"),
+        None => format!("{server}/spectators"),
+    }
+}
@@ -0,0 +1,186 @@
+//! Faults injected on purpose, to test how the server copes with a slow network, lost socket
+//! frames and a failing database.
+//!
+//! Only built with the `chaos` feature, which is meant for tests: every [`Sessions`] then has
+//! its [`Faults`], switched off until a test turns them on. HTTP responses and socket frames
+//! are delayed by the configured latency, the frames a game socket sends can be dropped, the
+//! messages it receives can panic, and database statements fail while the storage is made to
+//! fail.
+//!
+//! [`Sessions`]: crate::state::Sessions
+
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use rusqlite::hooks::{AuthAction, AuthContext, Authorization};
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Faults injected into a server, shared by its clones.
+#[derive(Clone, Debug, Default)]
+pub struct Faults {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    latency_ms: AtomicU64,
+    dropped_frames: AtomicU32,
+    panicking_messages: AtomicU32,
+    /// Which statements fail, if any.
+    storage: RwLock<Option<StorageFault>>,
+}
+
+/// Database statements made to fail by [`Faults::fail_storage`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StorageFault {
+    /// Every statement fails.
+    All,
+    /// Statements reading or writing the table fail.
+    Table(String),
+}
+
+impl Faults {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns how long every HTTP response and socket frame is held back.
+    #[must_use]
+    pub fn latency(&self) -> Duration {
+        Duration::from_millis(self.inner.latency_ms.load(Ordering::Relaxed))
+    }
+
+    /// Holds back every HTTP response and socket frame by `latency`, or no longer with zero.
+    pub fn set_latency(&self, latency: Duration) {
+        let millis = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX);
+        self.inner.latency_ms.store(millis, Ordering::Relaxed);
+    }
+
+    /// Drops the next `frames` frames sent on game sockets, on top of any still to drop.
+    pub fn drop_frames(&self, frames: u32) {
+        self.inner.dropped_frames.fetch_add(frames, Ordering::Relaxed);
+    }
+
+    /// Returns the number of frames still to drop.
+    #[must_use]
+    pub fn frames_to_drop(&self) -> u32 {
+        self.inner.dropped_frames.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if a frame about to be sent is to be dropped, counting it as dropped.
+    fn take_dropped_frame(&self) -> bool {
+        self.inner
+            .dropped_frames
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |frames| frames.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Makes the next `messages` messages received on game sockets panic while they are
+    /// handled, as a bug would, on top of any still to panic.
+    pub fn panic_on_messages(&self, messages: u32) {
+        self.inner.panicking_messages.fetch_add(messages, Ordering::Relaxed);
+    }
+
+    /// Returns true if a message about to be handled is to panic, counting it as handled.
+    fn take_panicking_message(&self) -> bool {
+        self.inner
+            .panicking_messages
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |messages| messages.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Makes the database statements of `fault` fail until [`Faults::heal_storage`]. Only
+    /// storage set up with [`Storage::inject_faults`] is affected.
+    ///
+    /// [`Storage::inject_faults`]: crate::storage::Storage::inject_faults
+    pub fn fail_storage(&self, fault: StorageFault) {
+        *self.inner.storage.write().unwrap_or_else(std::sync::PoisonError::into_inner) = Some(fault);
+    }
+
+    /// Lets every database statement run again.
+    pub fn heal_storage(&self) {
+        *self.inner.storage.write().unwrap_or_else(std::sync::PoisonError::into_inner) = None;
+    }
+
+    /// Decides whether the database may take an action of a statement being prepared, as an
+    /// `SQLite` authorizer. A denied action fails the statement.
+    pub(crate) fn authorize(&self, context: &AuthContext<'_>) -> Authorization {
+        let storage = self.inner.storage.read().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let fails = match storage.as_ref() {
+            None => false,
+            Some(StorageFault::All) => true,
+            Some(StorageFault::Table(table)) => match context.action {
+                AuthAction::Read { table_name, .. }
+                | AuthAction::Insert { table_name }
+                | AuthAction::Update { table_name, .. }
+                | AuthAction::Delete { table_name } => table_name == table,
+                _ => false,
+            },
+        };
+        if fails {
+            Authorization::Deny
+        } else {
+            Authorization::Allow
+        }
+    }
+}
+
+tokio::task_local! {
+    /// Faults of the server whose game socket the current task relays.
+    static SOCKET_FAULTS: Faults;
+}
+
+/// Runs a socket's task, applying `faults` to the frames it sends.
+pub(crate) async fn with_socket_faults<F: Future>(faults: Faults, relay: F) -> F::Output {
+    SOCKET_FAULTS.scope(faults, relay).await
+}
+
+/// Applies the current socket's faults to a frame about to be sent: waits out the latency,
+/// and returns false if the frame is to be dropped instead.
+pub(crate) async fn deliver_frame() -> bool {
+    let Ok(faults) = SOCKET_FAULTS.try_with(Faults::clone) else {
+        return true;
+    };
+    let latency = faults.latency();
+    if !latency.is_zero() {
+        tokio::time::sleep(latency).await;
+    }
+    !faults.take_dropped_frame()
+}
+
+/// Applies the current socket's faults to a message it is handling: panics if the message is
+/// to panic.
+pub(crate) fn handle_message() {
+    let panics = SOCKET_FAULTS.try_with(Faults::take_panicking_message).unwrap_or(false);
+    assert!(!panics, "Injected fault: handling the message panicked");
+}
+
+/// Holds back the response to a request by the configured latency.
+pub(crate) async fn delay_response(State(faults): State<Faults>, request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let latency = faults.latency();
+    if !latency.is_zero() {
+        tokio::time::sleep(latency).await;
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_dropped_as_counted() {
+        let faults = Faults::new();
+        assert!(!faults.take_dropped_frame());
+        faults.drop_frames(2);
+        faults.clone().drop_frames(1);
+        assert_eq!(faults.frames_to_drop(), 3);
+        assert!((0..3).all(|_| faults.take_dropped_frame()));
+        assert!(!faults.take_dropped_frame());
+    }
+}
@@ -0,0 +1,600 @@
+//! Typed async client for a remote kawio server.
+//!
+//! Enabled with the `client` feature. It speaks the same REST and WebSocket protocol as the
+//! browser frontend, using the request and response types from [`crate::api`].
+//!
+//! ```no_run
+//! # async fn demo() -> Result<(), kawio::client::ClientError> {
+//! use kawio::client::Client;
+//! use kawio::game::Move;
+//! use kawio::protocol::ServerMsg;
+//!
+//! let mut client = Client::new("http://localhost:8080");
+//! client.login("my-bot").await?;
+//! let id = client.create_match("AI").await?;
+//! let mut stream = client.connect(&id).await?;
+//! while let Some(event) = stream.next_event().await {
+//!     if let ServerMsg::State(state) = event? {
+//!         if let Some(coord) = state.legal_moves.first() {
+//!             stream.send_move(coord.parse().unwrap_or(Move::Pass)).await?;
+//!         }
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::analysis::{AnalysisReport, PositionEvaluation};
+use crate::api::{
+    AnnotationRequest, BatchAnalysisRequest, BatchAnalysisResponse, ColorChoice, GameStateResponse, GameType, Job, JoinResponse,
+    LoginRequest, LoginResponse, MatchupResponse, MoveCoord, MoveRequest, MoveResponse, NewMatchRequest, NewMatchResponse,
+    PlayerSettings, PlayerStats, PuzzleAttemptResponse, PuzzleResponse, ReplayResponse, ServerStats,
+};
+use crate::game::Move;
+use crate::protocol::{ClientMsg, ServerMsg};
+use futures_util::{SinkExt, StreamExt};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::fmt;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// Errors returned by the client.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The request could not be sent or the response could not be read.
+    Http(reqwest::Error),
+    /// The WebSocket connection failed.
+    WebSocket(Box<tokio_tungstenite::tungstenite::Error>),
+    /// The server answered with a non-success status code.
+    Status(StatusCode),
+    /// A WebSocket message could not be decoded.
+    Json(serde_json::Error),
+    /// The endpoint requires a token; call [`Client::login`] first.
+    NotLoggedIn,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Http(e) => write!(f, "HTTP error: {e}"),
+            ClientError::WebSocket(e) => write!(f, "WebSocket error: {e}"),
+            ClientError::Status(status) => write!(f, "Server returned {status}"),
+            ClientError::Json(e) => write!(f, "Invalid message: {e}"),
+            ClientError::NotLoggedIn => write!(f, "Not logged in"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for ClientError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        ClientError::WebSocket(Box::new(e))
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(e: serde_json::Error) -> Self {
+        ClientError::Json(e)
+    }
+}
+
+/// A REST client bound to one server and, after login, one player.
+#[derive(Clone, Debug)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl Client {
+    /// Creates a client for the server at `base_url`, e.g. `http://localhost:8080`.
+    #[must_use]
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: None,
+        }
+    }
+
+    /// Returns the session token, if logged in.
+    #[must_use]
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    fn bearer(&self) -> Result<&str, ClientError> {
+        self.token.as_deref().ok_or(ClientError::NotLoggedIn)
+    }
+
+    /// Builds the body of a REST move. Passes can only be sent over WebSocket, so the server
+    /// would refuse them.
+    fn move_request(mv: Move) -> Result<MoveRequest, ClientError> {
+        match mv {
+            Move::Place(pos) => MoveCoord::new(pos)
+                .map(|coord| MoveRequest { coord, ply: None })
+                .ok_or(ClientError::Status(StatusCode::BAD_REQUEST)),
+            Move::Pass => Err(ClientError::Status(StatusCode::BAD_REQUEST)),
+        }
+    }
+
+    async fn decode<T: DeserializeOwned>(response: reqwest::Response) -> Result<T, ClientError> {
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ClientError::Status(status));
+        }
+        Ok(response.json().await?)
+    }
+
+    /// Logs in as `player` and stores the returned token for later requests.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the server rejects it.
+    pub async fn login(&mut self, player: &str) -> Result<(), ClientError> {
+        let response = self
+            .http
+            .post(self.url("/auth/login"))
+            .json(&LoginRequest {
+                player: player.to_string(),
+            })
+            .send()
+            .await?;
+        let login: LoginResponse = Self::decode(response).await?;
+        self.token = Some(login.token);
+        Ok(())
+    }
+
+    /// Creates a match against `player2` and returns its id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if not logged in, the request fails, or the server rejects it.
+    pub async fn create_match(&self, player2: &str) -> Result<String, ClientError> {
+        let response = self
+            .http
+            .post(self.url("/match/new"))
+            .bearer_auth(self.bearer()?)
+            .json(&NewMatchRequest {
+                player2: player2.to_string(),
+                komi: None,
+                game_type: GameType::Ranked,
+                private: false,
+                coach: None,
+                difficulty: None,
+                color: ColorChoice::Black,
+            })
+            .send()
+            .await?;
+        let created: NewMatchResponse = Self::decode(response).await?;
+        Ok(created.id)
+    }
+
+    /// Joins the matchmaking queue.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if not logged in, the request fails, or the server rejects it.
+    pub async fn join_matchmaking(&self) -> Result<JoinResponse, ClientError> {
+        let response = self
+            .http
+            .post(self.url("/match/join"))
+            .bearer_auth(self.bearer()?)
+            .send()
+            .await?;
+        Self::decode(response).await
+    }
+
+    /// Fetches the current state of a game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the game does not exist.
+    pub async fn state(&self, id: &str) -> Result<GameStateResponse, ClientError> {
+        let response = self
+            .http
+            .get(self.url(&format!("/match/{id}/state")))
+            .send()
+            .await?;
+        Self::decode(response).await
+    }
+
+    /// Fetches the move history of a game, with per-move timing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the game does not exist.
+    pub async fn replay(&self, id: &str) -> Result<ReplayResponse, ClientError> {
+        let response = self
+            .http
+            .get(self.url(&format!("/match/{id}/replay")))
+            .send()
+            .await?;
+        Self::decode(response).await
+    }
+
+    /// Attaches an annotation to a move of a game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if not logged in, the request fails, or the annotation is rejected.
+    pub async fn annotate(&self, id: &str, annotation: &AnnotationRequest) -> Result<(), ClientError> {
+        let response = self
+            .http
+            .post(self.url(&format!("/match/{id}/annotations")))
+            .bearer_auth(self.bearer()?)
+            .json(annotation)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ClientError::Status(status));
+        }
+        Ok(())
+    }
+
+    /// Queues the full computer analysis of a finished game and returns its job. Fetch the
+    /// result with [`Client::report`] once the job is done.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if not logged in, the request fails, or the game is not finished.
+    pub async fn analyze_full(&self, id: &str) -> Result<Job, ClientError> {
+        let response = self
+            .http
+            .post(self.url(&format!("/match/{id}/analyze-full")))
+            .bearer_auth(self.bearer()?)
+            .send()
+            .await?;
+        Self::decode(response).await
+    }
+
+    /// Scores many positions in one call, each given as 64 squares plus the side to move as
+    /// read by [`Game::from_position_string`](crate::game::Game::from_position_string).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if not logged in, the request fails, or a position cannot be read.
+    pub async fn analyze_batch(&self, request: &BatchAnalysisRequest) -> Result<Vec<PositionEvaluation>, ClientError> {
+        let response = self
+            .http
+            .post(self.url("/analyze/batch"))
+            .bearer_auth(self.bearer()?)
+            .json(request)
+            .send()
+            .await?;
+        let response: BatchAnalysisResponse = Self::decode(response).await?;
+        Ok(response.evaluations)
+    }
+
+    /// Fetches a background job.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if not logged in, the request fails, or the job does not exist or is
+    /// on a game the player may not see.
+    pub async fn job(&self, id: i64) -> Result<Job, ClientError> {
+        let response = self
+            .http
+            .get(self.url(&format!("/jobs/{id}")))
+            .bearer_auth(self.bearer()?)
+            .send()
+            .await?;
+        Self::decode(response).await
+    }
+
+    /// Fetches the computer analysis of a game, or `None` while it is still running.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the game has not been analysed.
+    pub async fn report(&self, id: &str) -> Result<Option<AnalysisReport>, ClientError> {
+        let response = self
+            .http
+            .get(self.url(&format!("/match/{id}/report")))
+            .send()
+            .await?;
+        if response.status() == StatusCode::ACCEPTED {
+            return Ok(None);
+        }
+        Self::decode(response).await.map(Some)
+    }
+
+    /// Fetches a game's metadata.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the game does not exist.
+    pub async fn metadata(&self, id: &str) -> Result<BTreeMap<String, String>, ClientError> {
+        let response = self
+            .http
+            .get(self.url(&format!("/match/{id}/metadata")))
+            .send()
+            .await?;
+        Self::decode(response).await
+    }
+
+    /// Sets entries of a game's metadata, removing those set to `None`, and returns the
+    /// updated metadata.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if not logged in, the request fails, the player is not in the game,
+    /// or the update breaks the metadata limits.
+    pub async fn update_metadata(
+        &self,
+        id: &str,
+        updates: &BTreeMap<String, Option<String>>,
+    ) -> Result<BTreeMap<String, String>, ClientError> {
+        let response = self
+            .http
+            .patch(self.url(&format!("/match/{id}/metadata")))
+            .bearer_auth(self.bearer()?)
+            .json(updates)
+            .send()
+            .await?;
+        Self::decode(response).await
+    }
+
+    /// Deletes a game, which only its players and admins may do.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if not logged in, the request fails, the game does not exist, or the
+    /// player may not delete it.
+    pub async fn delete_match(&self, id: &str) -> Result<(), ClientError> {
+        let response = self
+            .http
+            .delete(self.url(&format!("/match/{id}")))
+            .bearer_auth(self.bearer()?)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ClientError::Status(status));
+        }
+        Ok(())
+    }
+
+    /// Submits a move over REST, returning the passes it forced, including after the AI's reply.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if not logged in, the request fails, or the move is rejected.
+    pub async fn submit_move(&self, id: &str, mv: Move) -> Result<MoveResponse, ClientError> {
+        let response = self
+            .http
+            .post(self.url(&format!("/match/{id}/move")))
+            .bearer_auth(self.bearer()?)
+            .json(&Self::move_request(mv)?)
+            .send()
+            .await?;
+        Self::decode(response).await
+    }
+
+    /// Fetches the leaderboard.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn leaderboard(&self) -> Result<Vec<PlayerStats>, ClientError> {
+        let response = self.http.get(self.url("/leaderboard")).send().await?;
+        Self::decode(response).await
+    }
+
+    /// Fetches the server-wide activity statistics.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn server_stats(&self) -> Result<ServerStats, ClientError> {
+        let response = self.http.get(self.url("/stats/server")).send().await?;
+        Self::decode(response).await
+    }
+
+    /// Fetches a pre-game preview of two players.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn matchup(&self, player1: &str, player2: &str) -> Result<MatchupResponse, ClientError> {
+        let response = self
+            .http
+            .get(self.url("/matchup"))
+            .query(&[("player1", player1), ("player2", player2)])
+            .send()
+            .await?;
+        Self::decode(response).await
+    }
+
+    /// Fetches one player's profile statistics.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the player has not played yet.
+    pub async fn profile(&self, name: &str) -> Result<PlayerStats, ClientError> {
+        let response = self
+            .http
+            .get(self.url(&format!("/player/{name}")))
+            .send()
+            .await?;
+        Self::decode(response).await
+    }
+
+    /// Fetches the logged-in player's privacy settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if not logged in or the request fails.
+    pub async fn settings(&self) -> Result<PlayerSettings, ClientError> {
+        let response = self
+            .http
+            .get(self.url("/me/settings"))
+            .bearer_auth(self.bearer()?)
+            .send()
+            .await?;
+        Self::decode(response).await
+    }
+
+    /// Replaces the logged-in player's privacy settings, returning them as saved.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if not logged in or the request fails.
+    pub async fn update_settings(&self, settings: &PlayerSettings) -> Result<PlayerSettings, ClientError> {
+        let response = self
+            .http
+            .put(self.url("/me/settings"))
+            .bearer_auth(self.bearer()?)
+            .json(settings)
+            .send()
+            .await?;
+        Self::decode(response).await
+    }
+
+    /// Fetches today's puzzle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the server has no puzzles yet.
+    pub async fn daily_puzzle(&self) -> Result<PuzzleResponse, ClientError> {
+        let response = self.http.get(self.url("/puzzle/daily")).send().await?;
+        Self::decode(response).await
+    }
+
+    /// Submits a solution to a puzzle, returning whether it was right and the solution.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the puzzle does not exist.
+    pub async fn attempt_puzzle(&self, id: i64, mv: Move) -> Result<PuzzleAttemptResponse, ClientError> {
+        let response = self
+            .http
+            .post(self.url(&format!("/puzzle/{id}/attempt")))
+            .json(&Self::move_request(mv)?)
+            .send()
+            .await?;
+        Self::decode(response).await
+    }
+
+    /// Opens the WebSocket stream of a game. When logged in, the stream can also resign and
+    /// chat for the player.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection cannot be established.
+    pub async fn connect(&self, id: &str) -> Result<GameStream, ClientError> {
+        let ws_base = if let Some(rest) = self.base_url.strip_prefix("https://") {
+            format!("wss://{rest}")
+        } else if let Some(rest) = self.base_url.strip_prefix("http://") {
+            format!("ws://{rest}")
+        } else {
+            self.base_url.clone()
+        };
+        let url = match &self.token {
+            Some(token) => format!("{ws_base}/match/{id}/ws?token={token}"),
+            None => format!("{ws_base}/match/{id}/ws"),
+        };
+        let (socket, _) = tokio_tungstenite::connect_async(url).await?;
+        Ok(GameStream { socket })
+    }
+}
+
+/// A live WebSocket connection to one game.
+pub struct GameStream {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl GameStream {
+    /// Waits for the next server message. Returns `None` once the connection is closed.
+    pub async fn next_event(&mut self) -> Option<Result<ServerMsg, ClientError>> {
+        while let Some(message) = self.socket.next().await {
+            match message {
+                Ok(Message::Text(text)) => return Some(serde_json::from_str(&text).map_err(ClientError::from)),
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+        None
+    }
+
+    /// Sends a message to the server.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be sent.
+    pub async fn send(&mut self, message: &ClientMsg) -> Result<(), ClientError> {
+        let text = serde_json::to_string(message)?;
+        self.socket.send(Message::Text(text)).await?;
+        Ok(())
+    }
+
+    /// Plays a move, or passes for [`Move::Pass`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be sent.
+    pub async fn send_move(&mut self, mv: Move) -> Result<(), ClientError> {
+        let message = match mv {
+            Move::Place(pos) => ClientMsg::Move {
+                coord: MoveCoord::new(pos).ok_or(ClientError::Status(StatusCode::BAD_REQUEST))?,
+                ply: None,
+            },
+            Move::Pass => ClientMsg::Pass { ply: None },
+        };
+        self.send(&message).await
+    }
+
+    /// Resigns the game. The stream must have been opened while logged in.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be sent.
+    pub async fn resign(&mut self) -> Result<(), ClientError> {
+        self.send(&ClientMsg::Resign).await
+    }
+
+    /// Says something to everyone watching the game. The stream must have been opened while
+    /// logged in.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be sent.
+    pub async fn chat(&mut self, text: &str) -> Result<(), ClientError> {
+        self.send(&ClientMsg::Chat { text: text.to_string() }).await
+    }
+
+    /// Asks the server for a [`ServerMsg::Pong`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be sent.
+    pub async fn ping(&mut self) -> Result<(), ClientError> {
+        self.send(&ClientMsg::Ping).await
+    }
+
+    /// Closes the connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the close frame cannot be sent.
+    pub async fn close(mut self) -> Result<(), ClientError> {
+        self.socket.close(None).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+//! Where the server reads the time from.
+//!
+//! Turn times, reconnection deadlines, maintenance deadlines and the expiry of socket tickets
+//! are measured with the [`Clock`] the [`Sessions`] were created with, so tests can move time
+//! on at will instead of sleeping.
+//!
+//! [`Sessions`]: crate::state::Sessions
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A source of the current time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Returns the time elapsed since the Unix epoch, to compare with the expiry of tokens.
+    fn unix_time(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+}
+
+/// The system's monotonic clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+    /// When the clock was stopped, on both clocks, to tell the Unix time it shows.
+    started: (Instant, Duration),
+}
+
+impl ManualClock {
+    /// Creates a clock stopped at the current time.
+    #[must_use]
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            now: Arc::new(Mutex::new(now)),
+            started: (now, SystemClock.unix_time()),
+        }
+    }
+
+    /// Moves the clock on by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock() += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock()
+    }
+
+    fn unix_time(&self) -> Duration {
+        let (instant, unix_time) = self.started;
+        unix_time + self.now().duration_since(instant)
+    }
+}
@@ -0,0 +1,347 @@
+//! External engines offered as opponents, spoken to over the `NBoard` protocol.
+//!
+//! Operators list engines such as Edax in a JSON file of [`EngineConfig`]s, named by the
+//! `ENGINES` variable. Each engine is offered as an opponent under its own name and played by
+//! an [`EnginePool`] of processes started on demand. A process is pinged before each move and
+//! replaced if it does not answer, and one that fails or runs out of time over a move is
+//! killed, so a misbehaving engine never holds up the server.
+
+use crate::game::{Game, Move, Player};
+use crate::state::is_builtin_ai;
+use parking_lot::{Condvar, Mutex};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// Longest a process may take to start and answer its first ping.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest an idle process may take to answer the ping before a move.
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// An external engine offered as an opponent.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EngineConfig {
+    /// Player name the engine plays under, e.g. `"Edax"`.
+    pub name: String,
+    /// Executable speaking the `NBoard` protocol.
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Plies the engine searches.
+    #[serde(default = "default_depth")]
+    pub depth: u32,
+    /// Most processes of the engine running at once, i.e. moves searched at once.
+    #[serde(default = "default_processes")]
+    pub processes: usize,
+    /// Longest the engine may take over a move, in seconds.
+    #[serde(default = "default_move_timeout_secs")]
+    pub move_timeout_secs: u64,
+}
+
+fn default_depth() -> u32 {
+    12
+}
+
+fn default_processes() -> usize {
+    1
+}
+
+fn default_move_timeout_secs() -> u64 {
+    30
+}
+
+/// Reads the engines listed in a JSON file.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or parsed, or if an engine has no name, takes
+/// a name of the built-in AI or another engine's, or runs no process.
+pub fn load_configs(path: &str) -> Result<Vec<EngineConfig>, String> {
+    let json = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+    let configs: Vec<EngineConfig> = serde_json::from_str(&json).map_err(|e| format!("Invalid {path}: {e}"))?;
+    let mut names = HashSet::new();
+    for config in &configs {
+        if config.name.trim().is_empty() || is_builtin_ai(&config.name) {
+            return Err(format!("Invalid engine name {:?}", config.name));
+        }
+        if !names.insert(config.name.as_str()) {
+            return Err(format!("Engine {} is listed twice", config.name));
+        }
+        if config.processes == 0 {
+            return Err(format!("Engine {} runs no process", config.name));
+        }
+    }
+    Ok(configs)
+}
+
+/// A running engine process.
+struct EngineProcess {
+    child: Child,
+    stdin: ChildStdin,
+    /// Lines the engine wrote, read on their own thread so reads can time out.
+    lines: Receiver<String>,
+    /// Number of the latest ping sent.
+    pings: u32,
+}
+
+impl EngineProcess {
+    /// Starts the engine and waits for it to answer a ping.
+    fn spawn(config: &EngineConfig) -> Result<Self, String> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Failed to start {}: {e}", config.command))?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(format!("Failed to open the pipes of {}", config.command));
+        };
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut process = Self {
+            child,
+            stdin,
+            lines,
+            pings: 0,
+        };
+        process.send("nboard 2")?;
+        process.send(&format!("set depth {}", config.depth))?;
+        process.ping(START_TIMEOUT)?;
+        Ok(process)
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{command}")
+            .and_then(|()| self.stdin.flush())
+            .map_err(|e| format!("Failed to write to the engine: {e}"))
+    }
+
+    /// Reads lines until one is accepted, skipping the rest, e.g. status lines.
+    fn read_until<T>(&self, timeout: Duration, mut accept: impl FnMut(&str) -> Option<T>) -> Result<T, String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(line) => {
+                    if let Some(value) = accept(line.trim()) {
+                        return Ok(value);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return Err("The engine timed out".to_string()),
+                Err(RecvTimeoutError::Disconnected) => return Err("The engine exited".to_string()),
+            }
+        }
+    }
+
+    /// Checks the engine still answers.
+    fn ping(&mut self, timeout: Duration) -> Result<(), String> {
+        self.pings += 1;
+        let pong = format!("pong {}", self.pings);
+        self.send(&format!("ping {}", self.pings))?;
+        self.read_until(timeout, |line| (line == pong).then_some(()))
+    }
+
+    /// Asks the engine for its move in the position.
+    fn best_move(&mut self, game: &Game, timeout: Duration) -> Result<Move, String> {
+        self.send(&format!("set game {}", ggf(game)))?;
+        self.send("go")?;
+        let reply = self.read_until(timeout, |line| line.strip_prefix("===").map(str::to_string))?;
+        parse_move(game, &reply)
+    }
+}
+
+impl Drop for EngineProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Writes the position as a GGF game starting from it, as `NBoard` engines read positions.
+fn ggf(game: &Game) -> String {
+    let mut board = String::with_capacity(64);
+    for rank in 1..=8 {
+        for file in 'A'..='H' {
+            let pos = Game::coord_to_pos(&format!("{file}{rank}")).expect("Squares are on the board");
+            let bit = 1u64 << pos;
+            board.push(if game.black & bit != 0 {
+                '*'
+            } else if game.white & bit != 0 {
+                'O'
+            } else {
+                '-'
+            });
+        }
+    }
+    let side = match game.current_player {
+        Player::Black => '*',
+        Player::White => 'O',
+    };
+    format!("(;GM[Othello]PC[kawio]TY[8]BO[8 {board} {side}];)")
+}
+
+/// Reads the move of an engine's `===` reply, e.g. `F5/1.50/0.3` or `PA`, checking it is legal.
+fn parse_move(game: &Game, reply: &str) -> Result<Move, String> {
+    let coord = reply.trim().split('/').next().unwrap_or_default().trim();
+    let mv = if coord.eq_ignore_ascii_case("PA") || coord.eq_ignore_ascii_case("pass") {
+        Move::Pass
+    } else {
+        Move::Place(Game::coord_to_pos(coord).map_err(|e| format!("The engine played {coord:?}: {e}"))?)
+    };
+    let legal = match mv {
+        Move::Place(pos) => game.legal_moves_iter().any(|legal| legal == pos),
+        Move::Pass => game.legal_moves_iter().next().is_none(),
+    };
+    if !legal {
+        return Err(format!("The engine played the illegal move {coord}"));
+    }
+    Ok(mv)
+}
+
+/// Processes of an engine, started when a move needs one and kept between moves.
+pub struct EnginePool {
+    config: EngineConfig,
+    state: Mutex<PoolState>,
+    /// Notified whenever a process is handed back or given up.
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct PoolState {
+    idle: Vec<EngineProcess>,
+    busy: usize,
+}
+
+impl EnginePool {
+    #[must_use]
+    pub fn new(config: EngineConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(PoolState::default()),
+            released: Condvar::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Returns the number of processes running, idle or busy.
+    #[must_use]
+    pub fn running(&self) -> usize {
+        let state = self.state.lock();
+        state.idle.len() + state.busy
+    }
+
+    /// Asks one of the engine's processes for its move in the position, waiting for one to
+    /// be free if all are busy. Blocks, so it is run on the blocking thread pool.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no process can be started, or if the engine fails, times out or
+    /// plays an illegal move; the process is then killed.
+    pub fn best_move(&self, game: &Game) -> Result<Move, String> {
+        let mut process = self.check_out()?;
+        let timeout = Duration::from_secs(self.config.move_timeout_secs);
+        let result = process.best_move(game, timeout);
+        let mut state = self.state.lock();
+        state.busy -= 1;
+        if result.is_ok() {
+            state.idle.push(process);
+        }
+        self.released.notify_one();
+        result.map_err(|e| format!("{}: {e}", self.config.name))
+    }
+
+    /// Takes an idle process that still answers, or starts one if fewer than the configured
+    /// processes run.
+    fn check_out(&self) -> Result<EngineProcess, String> {
+        let idle = {
+            let mut state = self.state.lock();
+            loop {
+                if let Some(process) = state.idle.pop() {
+                    state.busy += 1;
+                    break Some(process);
+                }
+                if state.busy < self.config.processes {
+                    state.busy += 1;
+                    break None;
+                }
+                self.released.wait(&mut state);
+            }
+        };
+        if let Some(mut process) = idle {
+            match process.ping(PING_TIMEOUT) {
+                Ok(()) => return Ok(process),
+                Err(e) => tracing::warn!("Restarting a process of {}: {e}", self.config.name),
+            }
+        }
+        EngineProcess::spawn(&self.config).map_err(|e| {
+            self.state.lock().busy -= 1;
+            self.released.notify_one();
+            format!("{}: {e}", self.config.name)
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// A pool of a shell script answering pings, and `go` with `reply`.
+    fn fake_engine(reply: &str) -> EnginePool {
+        let script = format!(
+            "while read cmd arg rest; do case $cmd in ping) echo \"pong $arg\";; go) echo 'status thinking'; {reply};; esac; done"
+        );
+        EnginePool::new(EngineConfig {
+            name: "Fake".to_string(),
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script],
+            depth: 1,
+            processes: 1,
+            move_timeout_secs: 1,
+        })
+    }
+
+    #[test]
+    fn test_pool_plays_engine_moves() {
+        let game = Game::new();
+        let pos = game.legal_moves()[0];
+        let pool = fake_engine(&format!("echo '=== {}/0.00/0.1'", Game::pos_to_coord(pos)));
+        assert_eq!(pool.best_move(&game), Ok(Move::Place(pos)));
+        assert_eq!(pool.best_move(&game), Ok(Move::Place(pos)));
+        assert_eq!(pool.running(), 1);
+        assert!(ggf(&game).ends_with(" *];)"));
+        assert_eq!(ggf(&game).matches('*').count(), 3);
+    }
+
+    #[test]
+    fn test_pool_kills_failing_engines() {
+        let game = Game::new();
+        let silent = fake_engine(":");
+        assert!(silent.best_move(&game).unwrap_err().contains("timed out"));
+        assert_eq!(silent.running(), 0);
+
+        let illegal = fake_engine("echo '=== PA'");
+        assert!(illegal.best_move(&game).unwrap_err().contains("illegal move PA"));
+        assert_eq!(illegal.running(), 0);
+
+        let missing = EnginePool::new(EngineConfig {
+            command: "/nonexistent/engine".to_string(),
+            ..fake_engine("").config().clone()
+        });
+        assert!(missing.best_move(&game).unwrap_err().starts_with("Fake: Failed to start"));
+        assert_eq!(missing.running(), 0);
+    }
+}
@@ -0,0 +1,225 @@
+//! Static position evaluation.
+//!
+//! Evaluators estimate how good a position is without searching it. They are used by the
+//! rollout policies in [`crate::mcts`] to steer or cut short simulations.
+
+use crate::game::{bitboard, Game, Phase, Player};
+use std::sync::Arc;
+
+#[cfg(feature = "onnx")]
+pub mod onnx;
+
+/// Estimates the value of a position.
+pub trait Evaluator: Send + Sync {
+    /// Returns the value of the position for the side to move, from -1 (certain loss) to
+    /// 1 (certain win).
+    fn evaluate(&self, game: &Game) -> f64;
+
+    /// Returns the value of each position for its side to move. Evaluators that run faster
+    /// on many positions at once, such as neural networks, override this.
+    fn evaluate_batch(&self, games: &[Game]) -> Vec<f64> {
+        games.iter().map(|game| self.evaluate(game)).collect()
+    }
+}
+
+/// Shares one evaluator, e.g. a loaded network, between several searches.
+impl<E: Evaluator + ?Sized> Evaluator for Arc<E> {
+    fn evaluate(&self, game: &Game) -> f64 {
+        (**self).evaluate(game)
+    }
+
+    fn evaluate_batch(&self, games: &[Game]) -> Vec<f64> {
+        (**self).evaluate_batch(games)
+    }
+}
+
+/// Square weights favouring corners and edges and penalising the squares next to corners.
+/// Indexed by bitboard position (row 0 is rank 8); the table is symmetric.
+#[rustfmt::skip]
+const SQUARE_WEIGHTS: [i32; 64] = [
+    100, -20, 10,  5,  5, 10, -20, 100,
+    -20, -50, -2, -2, -2, -2, -50, -20,
+     10,  -2, -1, -1, -1, -1,  -2,  10,
+      5,  -2, -1, -1, -1, -1,  -2,   5,
+      5,  -2, -1, -1, -1, -1,  -2,   5,
+     10,  -2, -1, -1, -1, -1,  -2,  10,
+    -20, -50, -2, -2, -2, -2, -50, -20,
+    100, -20, 10,  5,  5, 10, -20, 100,
+];
+
+/// A hand-tuned evaluator combining square weights and mobility, with frontier discs before
+/// the endgame and the disc count in it.
+#[derive(Clone, Debug)]
+pub struct HeuristicEvaluator {
+    /// Weight of each legal move the side to move has over its opponent.
+    pub mobility_weight: f64,
+    /// Weight of each frontier disc the opponent has over the side to move, before the
+    /// endgame.
+    pub frontier_weight: f64,
+    /// Weight of each disc the side to move has over its opponent in the endgame, where the
+    /// discs are about to be counted.
+    pub disc_weight: f64,
+    /// Raw scores are divided by this before squashing into [-1, 1].
+    pub scale: f64,
+}
+
+impl Default for HeuristicEvaluator {
+    fn default() -> Self {
+        Self {
+            mobility_weight: 5.0,
+            frontier_weight: 2.0,
+            disc_weight: 4.0,
+            scale: 100.0,
+        }
+    }
+}
+
+impl HeuristicEvaluator {
+    fn square_score(bitboard: u64) -> i32 {
+        bitboard::iterate_bits(bitboard)
+            .map(|pos| SQUARE_WEIGHTS[usize::from(pos)])
+            .sum()
+    }
+}
+
+impl Evaluator for HeuristicEvaluator {
+    fn evaluate(&self, game: &Game) -> f64 {
+        let (own, other) = match game.current_player {
+            Player::Black => (game.black, game.white),
+            Player::White => (game.white, game.black),
+        };
+        if game.is_game_over() {
+            return match own.count_ones().cmp(&other.count_ones()) {
+                std::cmp::Ordering::Greater => 1.0,
+                std::cmp::Ordering::Less => -1.0,
+                std::cmp::Ordering::Equal => 0.0,
+            };
+        }
+        let squares = f64::from(Self::square_score(own) - Self::square_score(other));
+        let own_moves = f64::from(game.legal_move_bitboard().count_ones());
+        let mut opponent_view = game.clone();
+        opponent_view.current_player = game.current_player.opponent();
+        let other_moves = f64::from(opponent_view.legal_move_bitboard().count_ones());
+        let mut score = squares + self.mobility_weight * (own_moves - other_moves);
+        let count = |discs: u64| f64::from(discs.count_ones());
+        score += match game.phase() {
+            Phase::Endgame => self.disc_weight * (count(own) - count(other)),
+            Phase::Opening | Phase::Midgame => {
+                let (own_color, other_color) = (game.current_player, game.current_player.opponent());
+                self.frontier_weight * (count(game.frontier(other_color)) - count(game.frontier(own_color)))
+            }
+        };
+        (score / self.scale).tanh()
+    }
+}
+
+/// How strongly [`move_preferences`] favours better moves; higher values concentrate more of
+/// the preference on the best move.
+const PREFERENCE_SHARPNESS: f64 = 4.0;
+
+/// Rates each legal move of the side to move with a one-ply look-ahead, for hint overlays.
+///
+/// Returns `(move, preference)` pairs in board order, with preferences normalized to sum to 1.
+/// Returns an empty list if the side to move has no legal move.
+#[must_use]
+pub fn move_preferences(evaluator: &dyn Evaluator, game: &Game) -> Vec<(u8, f64)> {
+    let side = game.current_player;
+    let values: Vec<(u8, f64)> = game
+        .legal_moves_iter()
+        .map(|pos| {
+            let mut child = game.clone();
+            let _ = child.make_move(pos);
+            let value = if child.is_game_over() {
+                // Finished positions are scored for the mover by disc count.
+                let (black, white) = child.scores();
+                let (own, other) = match side {
+                    Player::Black => (black, white),
+                    Player::White => (white, black),
+                };
+                match own.cmp(&other) {
+                    std::cmp::Ordering::Greater => 1.0,
+                    std::cmp::Ordering::Less => -1.0,
+                    std::cmp::Ordering::Equal => 0.0,
+                }
+            } else if child.current_player == side {
+                // After an automatic pass the mover is to play again.
+                evaluator.evaluate(&child)
+            } else {
+                -evaluator.evaluate(&child)
+            };
+            (pos, value)
+        })
+        .collect();
+    let weights: Vec<f64> = values
+        .iter()
+        .map(|&(_, value)| (PREFERENCE_SHARPNESS * value).exp())
+        .collect();
+    let total: f64 = weights.iter().sum();
+    values
+        .iter()
+        .zip(weights)
+        .map(|(&(pos, _), weight)| (pos, weight / total))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heuristic_prefers_corners() {
+        let evaluator = HeuristicEvaluator::default();
+        let start = Game::new();
+        assert!(evaluator.evaluate(&start).abs() < 1e-9);
+
+        let mut corner = Game::new();
+        corner.black |= 1; // Black also owns the A8 corner
+        assert!(evaluator.evaluate(&corner) > 0.0);
+        corner.current_player = Player::White;
+        assert!(evaluator.evaluate(&corner) < 0.0);
+    }
+
+    #[test]
+    fn test_weights_follow_the_phase() {
+        let plain = HeuristicEvaluator {
+            frontier_weight: 0.0,
+            disc_weight: 0.0,
+            ..HeuristicEvaluator::default()
+        };
+        let phased = HeuristicEvaluator::default();
+
+        // An extra Black disc at A8 is one more frontier disc, which counts against Black.
+        let mut opening = Game::new();
+        opening.black |= 1u64 << Game::coord_to_pos("A8").unwrap();
+        assert_eq!(opening.phase(), Phase::Opening);
+        assert!(phased.evaluate(&opening) < plain.evaluate(&opening));
+
+        // In the endgame only the disc count is added: Black owns most of the board.
+        let mut endgame = Game::new();
+        endgame.black = !0xFFF & !(0xFFu64 << 56);
+        endgame.white = 0xFFu64 << 56;
+        assert_eq!(endgame.phase(), Phase::Endgame);
+        assert!(phased.evaluate(&endgame) > plain.evaluate(&endgame));
+    }
+
+    #[test]
+    fn test_move_preferences() {
+        let evaluator = HeuristicEvaluator::default();
+        let start = Game::new();
+        let preferences = move_preferences(&evaluator, &start);
+        assert_eq!(preferences.len(), 4);
+        let total: f64 = preferences.iter().map(|&(_, p)| p).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        // The opening moves are symmetric.
+        assert!(preferences.iter().all(|&(_, p)| (p - 0.25).abs() < 1e-9));
+
+        // Taking the A1 corner beats the alternatives.
+        let mut game = Game::new();
+        game.black = 1u64 << Game::coord_to_pos("A3").unwrap() | 1u64 << Game::coord_to_pos("E4").unwrap();
+        game.white = 1u64 << Game::coord_to_pos("A2").unwrap() | 1u64 << Game::coord_to_pos("D4").unwrap();
+        let a1 = Game::coord_to_pos("A1").unwrap();
+        let preferences = move_preferences(&evaluator, &game);
+        let best = preferences.iter().max_by(|a, b| a.1.total_cmp(&b.1)).unwrap();
+        assert_eq!(best.0, a1);
+    }
+}
@@ -0,0 +1,140 @@
+//! Neural network evaluation through ONNX Runtime.
+//!
+//! Needs the `onnx` feature. The runtime library is loaded when the first model is, from the
+//! path in the `ORT_DYLIB_PATH` environment variable or the system's library search path, so
+//! the crate builds without it.
+//!
+//! The network takes a float tensor named by its first input, shaped `[batch, 2, 8, 8]`:
+//! plane 0 holds the discs of the side to move and plane 1 the opponent's, one value per
+//! square in board order (row 0 is rank 8). It must have an output named `value`, shaped
+//! `[batch, 1]`, estimating the result for the side to move from -1 to 1, and may have an
+//! output named `policy`, shaped `[batch, 64]`, with one logit per square.
+
+use super::Evaluator;
+use crate::game::{Game, Player};
+use ort::execution_providers::CUDAExecutionProvider;
+use ort::session::Session;
+use ort::value::Tensor;
+use parking_lot::Mutex;
+use std::path::Path;
+
+/// Where the network runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Device {
+    #[default]
+    Cpu,
+    /// The CUDA GPU with this index, falling back to the CPU if it cannot be used.
+    Cuda(i32),
+}
+
+/// What the network makes of one position.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetOutput {
+    /// Value for the side to move, from -1 to 1.
+    pub value: f64,
+    /// Logits of playing on each square, in board order, if the network has a policy head.
+    pub policy: Option<Vec<f32>>,
+}
+
+/// Evaluates positions with a policy/value network loaded from an ONNX file.
+pub struct OnnxEvaluator {
+    session: Mutex<Session>,
+    input: String,
+    has_policy: bool,
+}
+
+impl OnnxEvaluator {
+    /// Loads a network from an ONNX file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the runtime cannot be loaded, the file is not a valid model, or
+    /// the model has no `value` output.
+    pub fn load(path: &Path, device: Device) -> ort::Result<Self> {
+        let mut builder = Session::builder()?;
+        if let Device::Cuda(id) = device {
+            builder = builder.with_execution_providers([CUDAExecutionProvider::default().with_device_id(id).build()])?;
+        }
+        let session = builder.commit_from_file(path)?;
+        let Some(input) = session.inputs.first().map(|input| input.name.clone()) else {
+            return Err(ort::Error::new("Model has no input"));
+        };
+        if !session.outputs.iter().any(|output| output.name == "value") {
+            return Err(ort::Error::new("Model has no `value` output"));
+        }
+        let has_policy = session.outputs.iter().any(|output| output.name == "policy");
+        Ok(Self {
+            session: Mutex::new(session),
+            input,
+            has_policy,
+        })
+    }
+
+    /// Runs the network on a batch of positions.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the network fails or its outputs have the wrong shape.
+    pub fn infer(&self, games: &[Game]) -> ort::Result<Vec<NetOutput>> {
+        if games.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut planes = vec![0.0f32; games.len() * 128];
+        for (game, planes) in games.iter().zip(planes.chunks_mut(128)) {
+            let (own, other) = match game.current_player {
+                Player::Black => (game.black, game.white),
+                Player::White => (game.white, game.black),
+            };
+            for pos in 0..64 {
+                if own & (1u64 << pos) != 0 {
+                    planes[pos] = 1.0;
+                } else if other & (1u64 << pos) != 0 {
+                    planes[64 + pos] = 1.0;
+                }
+            }
+        }
+        let batch = i64::try_from(games.len()).map_err(ort::Error::wrap)?;
+        let tensor = Tensor::from_array(([batch, 2, 8, 8], planes))?;
+        let mut session = self.session.lock();
+        let outputs = session.run(ort::inputs![self.input.as_str() => tensor])?;
+        let (_, values) = outputs["value"].try_extract_tensor::<f32>()?;
+        if values.len() != games.len() {
+            return Err(ort::Error::new("`value` output does not hold one value per position"));
+        }
+        let policies = if self.has_policy {
+            let (_, logits) = outputs["policy"].try_extract_tensor::<f32>()?;
+            if logits.len() != games.len() * 64 {
+                return Err(ort::Error::new("`policy` output does not hold 64 logits per position"));
+            }
+            Some(logits.chunks(64).map(<[f32]>::to_vec).collect::<Vec<_>>())
+        } else {
+            None
+        };
+        Ok(values
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| NetOutput {
+                value: f64::from(value).clamp(-1.0, 1.0),
+                policy: policies.as_ref().map(|policies| policies[i].clone()),
+            })
+            .collect())
+    }
+}
+
+impl Evaluator for OnnxEvaluator {
+    fn evaluate(&self, game: &Game) -> f64 {
+        self.evaluate_batch(std::slice::from_ref(game))[0]
+    }
+
+    /// Scores the positions in one run of the network. If it fails, the failure is logged
+    /// and every position is scored as even, so a search can carry on.
+    fn evaluate_batch(&self, games: &[Game]) -> Vec<f64> {
+        match self.infer(games) {
+            Ok(outputs) => outputs.iter().map(|output| output.value).collect(),
+            Err(e) => {
+                tracing::warn!("Network evaluation failed: {e}");
+                vec![0.0; games.len()]
+            }
+        }
+    }
+}
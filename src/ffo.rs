@@ -0,0 +1,158 @@
+//! Runner for the FFO endgame test suite.
+//!
+//! Positions are read in the common one-line-per-position format used to distribute the suite:
+//! a 64-character board, the side to move, and optionally the scores of the best moves, e.g.
+//!
+//! ```text
+//! --XXXXX--OOOXX-O-OOOXXOX-OXOXOXXOXXXOXXX--XOXOXX-XXXOOO--OOOO--- X; G8:+18; H1:+12;
+//! ```
+//!
+//! Lines starting with `%` or `#` and blank lines are ignored.
+
+use crate::game::{Game, Move};
+use crate::solver::{self, Solution};
+use std::time::{Duration, Instant};
+
+/// A position from the suite together with the scores it lists.
+#[derive(Clone, Debug)]
+pub struct FfoPosition {
+    /// Line number in the suite file, starting at 1.
+    pub line: usize,
+    pub game: Game,
+    /// Move scores from the file, as disc differentials for the side to move.
+    pub expected: Vec<(Move, i32)>,
+}
+
+impl FfoPosition {
+    /// Returns the best score listed for the position, if any.
+    #[must_use]
+    pub fn best_score(&self) -> Option<i32> {
+        self.expected.iter().map(|(_, score)| *score).max()
+    }
+}
+
+/// The outcome of solving one suite position.
+#[derive(Clone, Debug)]
+pub struct FfoResult {
+    pub line: usize,
+    pub empties: u32,
+    pub solution: Solution,
+    pub expected_score: Option<i32>,
+    /// Whether the solver's score and move agree with the file; `None` if the file lists no scores.
+    pub correct: Option<bool>,
+    pub elapsed: Duration,
+}
+
+/// Parses a suite file.
+///
+/// # Errors
+///
+/// Returns an error naming the offending line if a position or move score is malformed.
+pub fn parse_suite(content: &str) -> Result<Vec<FfoPosition>, String> {
+    let mut positions = Vec::new();
+    for (index, raw) in content.lines().enumerate() {
+        let line = index + 1;
+        let text = raw.trim();
+        if text.is_empty() || text.starts_with('%') || text.starts_with('#') {
+            continue;
+        }
+        let mut fields = text.split(';').map(str::trim).filter(|f| !f.is_empty());
+        let board = fields.next().unwrap_or_default();
+        let game = Game::from_position_string(board).map_err(|e| format!("line {line}: {e}"))?;
+        let expected = fields
+            .map(|field| parse_move_score(field).map_err(|e| format!("line {line}: {e}")))
+            .collect::<Result<_, _>>()?;
+        positions.push(FfoPosition {
+            line,
+            game,
+            expected,
+        });
+    }
+    Ok(positions)
+}
+
+fn parse_move_score(field: &str) -> Result<(Move, i32), String> {
+    let (mv, score) = field
+        .split_once(':')
+        .ok_or_else(|| format!("expected MOVE:SCORE, got '{field}'"))?;
+    let mv = mv.trim().parse::<Move>()?;
+    let score = score
+        .trim()
+        .trim_start_matches('+')
+        .parse()
+        .map_err(|_| format!("invalid score in '{field}'"))?;
+    Ok((mv, score))
+}
+
+/// Solves a single suite position and checks it against the listed scores.
+#[must_use]
+pub fn run_position(position: &FfoPosition) -> FfoResult {
+    let started = Instant::now();
+    let solution = solver::solve(&position.game);
+    let elapsed = started.elapsed();
+    let expected_score = position.best_score();
+    let correct = expected_score.map(|best| {
+        let move_ok = match solution.best_move {
+            Some(mv) => position
+                .expected
+                .iter()
+                .find(|(listed, _)| *listed == mv)
+                .is_none_or(|(_, score)| *score == best),
+            None => true,
+        };
+        solution.score == best && move_ok
+    });
+    FfoResult {
+        line: position.line,
+        empties: position.game.empty().count_ones(),
+        solution,
+        expected_score,
+        correct,
+        elapsed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_suite() {
+        let suite = "% comment\n\
+            \n\
+            ---------------------------OX------XO--------------------------- X; D3:+0; c4:-2;\n\
+            ---------------------------OX------XO--------------------------- O\n";
+        let positions = parse_suite(suite).unwrap();
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions[0].line, 3);
+        assert_eq!(positions[0].game, Game::new());
+        assert_eq!(
+            positions[0].expected,
+            vec![
+                (Move::Place(Game::coord_to_pos("D3").unwrap()), 0),
+                (Move::Place(Game::coord_to_pos("C4").unwrap()), -2)
+            ]
+        );
+        assert_eq!(positions[0].best_score(), Some(0));
+        assert!(positions[1].expected.is_empty());
+
+        assert!(parse_suite("XO X").is_err());
+        assert!(parse_suite(&format!("{} X; D3", "-".repeat(64))).is_err());
+    }
+
+    #[test]
+    fn test_run_position_checks_score() {
+        // Black to move with a single empty square at H8; playing it wins every disc.
+        let mut board = "X".repeat(63);
+        board.push('-');
+        board.replace_range(62..63, "O");
+        let solved = parse_suite(&format!("{board} X; H8:+64;")).unwrap();
+        let result = run_position(&solved[0]);
+        assert_eq!(result.empties, 1);
+        assert_eq!(result.solution.score, 64);
+        assert_eq!(result.correct, Some(true));
+
+        let wrong = parse_suite(&format!("{board} X; H8:+10;")).unwrap();
+        assert_eq!(run_position(&wrong[0]).correct, Some(false));
+    }
+}
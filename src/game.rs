@@ -3,25 +3,289 @@
 //! The board is represented as a 64-bit bitboard, with bit 0 = A8 (top-left), bit 63 = H1 (bottom-right).
 //! Coordinates use standard Othello notation: A1 = bottom-left (56), H8 = top-right (7).
 
+pub mod bitboard;
+
+use bitboard::shift;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::Cell;
 use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 
-/// Represents a player in the Othello game.
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// Version of the serialized form of [`Game`].
+pub const GAME_FORMAT_VERSION: u32 = 1;
+
+/// Represents a player in the Othello game. Serialized as `"Black"` or `"White"`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Serialize, Deserialize)]
 pub enum Player {
     #[default]
     Black,
     White,
 }
 
-/// Represents a move in the Othello game.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Represents a move in the Othello game. Serialized as its coordinate, e.g. `"D3"`, or
+/// `"pass"`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Move {
     Place(u8),
     Pass,
 }
 
+/// Why a disc cannot be placed on a square.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum IllegalMove {
+    /// The square is not on the board.
+    OffBoard,
+    /// A disc is already on the square.
+    Occupied,
+    /// The disc would flip none of the opponent's.
+    NoFlips,
+}
+
+impl fmt::Display for IllegalMove {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            IllegalMove::OffBoard => "Position out of bounds",
+            IllegalMove::Occupied => "Square is already occupied",
+            IllegalMove::NoFlips => "Move does not flip any discs",
+        })
+    }
+}
+
+/// How discs and empty squares are counted once the game is over.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ScoringRule {
+    /// Only the discs on the board are counted.
+    #[default]
+    DiscCount,
+    /// Tournament scoring: empty squares left at the end go to the winner, or are split on a draw.
+    EmptiesToWinner,
+}
+
+impl ScoringRule {
+    /// All supported rules, in a stable order.
+    pub const ALL: [ScoringRule; 2] = [ScoringRule::DiscCount, ScoringRule::EmptiesToWinner];
+
+    /// Returns the configuration name of the rule.
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScoringRule::DiscCount => "disc_count",
+            ScoringRule::EmptiesToWinner => "empties_to_winner",
+        }
+    }
+}
+
+impl FromStr for ScoringRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disc_count" => Ok(ScoringRule::DiscCount),
+            "empties_to_winner" => Ok(ScoringRule::EmptiesToWinner),
+            _ => Err(format!("Unknown scoring rule: {s}")),
+        }
+    }
+}
+
+/// Stage of a game, judged from the discs on the board.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Phase {
+    /// The first [`Phase::OPENING_MOVES`] discs placed.
+    Opening,
+    Midgame,
+    /// The last [`Phase::ENDGAME_EMPTIES`] empty squares, few enough to solve exactly.
+    Endgame,
+}
+
+impl Phase {
+    /// Discs placed after the starting four before the opening ends.
+    pub const OPENING_MOVES: u32 = 20;
+    /// Empty squares left when the endgame starts.
+    pub const ENDGAME_EMPTIES: u32 = 12;
+
+    /// Returns the name of the phase, e.g. `"midgame"`.
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Opening => "opening",
+            Phase::Midgame => "midgame",
+            Phase::Endgame => "endgame",
+        }
+    }
+}
+
+/// One of the eight compass directions a line of flips can run in.
+///
+/// North is towards row 8 (the top of the board), east is towards column H.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+/// Row and column deltas for each direction, in bitboard index space (row 0 is rank 8).
+const DIRECTIONS: [(Direction, i16, i16); 8] = [
+    (Direction::NorthWest, -1, -1),
+    (Direction::North, -1, 0),
+    (Direction::NorthEast, -1, 1),
+    (Direction::West, 0, -1),
+    (Direction::East, 0, 1),
+    (Direction::SouthWest, 1, -1),
+    (Direction::South, 1, 0),
+    (Direction::SouthEast, 1, 1),
+];
+
+impl Direction {
+    /// Returns the direction's compass abbreviation, e.g. `"NE"`.
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::North => "N",
+            Direction::NorthEast => "NE",
+            Direction::East => "E",
+            Direction::SouthEast => "SE",
+            Direction::South => "S",
+            Direction::SouthWest => "SW",
+            Direction::West => "W",
+            Direction::NorthWest => "NW",
+        }
+    }
+}
+
+/// The discs a move flips in one direction, nearest the placed disc first, so they can be
+/// turned over one after the other as the flip ripples outwards.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FlipLine {
+    pub direction: Direction,
+    pub discs: Vec<u8>,
+}
+
+/// Groups the discs `flipped` by a disc placed at `pos` into the lines they lie on from it,
+/// in a fixed order of directions. Discs of `flipped` not on a line running from `pos` are
+/// left out.
+#[must_use]
+pub fn flip_lines(pos: u8, flipped: u64) -> Vec<FlipLine> {
+    if pos >= 64 {
+        return Vec::new();
+    }
+    DIRECTIONS
+        .iter()
+        .filter_map(|&(direction, _, _)| {
+            let mut square = shift(1u64 << pos, direction);
+            let mut discs = Vec::new();
+            while square & flipped != 0 {
+                discs.extend(bitboard::iterate_bits(square));
+                square = shift(square, direction);
+            }
+            (!discs.is_empty()).then_some(FlipLine { direction, discs })
+        })
+        .collect()
+}
+
+/// Why a line from a candidate square does or does not flip discs.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RayOutcome {
+    /// The opponent discs are bracketed by one of the mover's discs and would be flipped.
+    Flips,
+    /// The adjacent square holds one of the mover's own discs, so there is nothing to flip.
+    OwnDiscAdjacent,
+    /// The adjacent square is empty.
+    EmptyAdjacent,
+    /// The candidate square is on the edge of the board in this direction.
+    Edge,
+    /// A run of opponent discs ends on an empty square instead of one of the mover's discs.
+    Unbracketed,
+    /// A run of opponent discs reaches the edge of the board without being bracketed.
+    RunsOffBoard,
+}
+
+/// Flip information for a single direction from a candidate square.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RayExplanation {
+    pub direction: Direction,
+    /// Opponent discs walked over in this direction; they are flipped only if `outcome` is `Flips`.
+    pub discs: u64,
+    pub outcome: RayOutcome,
+}
+
+/// A per-direction breakdown of why a move is or isn't legal for the current player.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MoveExplanation {
+    pub pos: u8,
+    /// True if the square is already occupied; `rays` is empty in that case.
+    pub occupied: bool,
+    pub rays: Vec<RayExplanation>,
+}
+
+impl MoveExplanation {
+    /// Returns true if the explained move is legal.
+    #[must_use]
+    pub fn is_legal(&self) -> bool {
+        self.flips() != 0
+    }
+
+    /// Returns the bitboard of all discs the move would flip.
+    #[must_use]
+    pub fn flips(&self) -> u64 {
+        self.rays
+            .iter()
+            .filter(|ray| ray.outcome == RayOutcome::Flips)
+            .fold(0, |acc, ray| acc | ray.discs)
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Move::Place(pos) => write!(f, "{}", Game::pos_to_coord(*pos)),
+            Move::Pass => write!(f, "pass"),
+        }
+    }
+}
+
+impl FromStr for Move {
+    type Err = String;
+
+    /// Parses a coordinate such as "D3" or the word "pass".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("pass") {
+            Ok(Move::Pass)
+        } else {
+            Game::coord_to_pos(s).map(Move::Place)
+        }
+    }
+}
+
+impl Serialize for Move {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Move {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 impl Player {
+    /// Returns the color's name, `"Black"` or `"White"`.
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Player::Black => "Black",
+            Player::White => "White",
+        }
+    }
+
     /// Returns the opponent of the current player.
     #[must_use]
     pub fn opponent(&self) -> Player {
@@ -32,13 +296,116 @@ impl Player {
     }
 }
 
+/// Returns the bitboard of squares where the side owning `own` may play.
+fn legal_move_bitboard(own: u64, opponent: u64) -> u64 {
+    let empty = !(own | opponent);
+    let mut moves = 0;
+    for &(direction, _, _) in &DIRECTIONS {
+        let mut run = shift(own, direction) & opponent;
+        for _ in 0..5 {
+            run |= shift(run, direction) & opponent;
+        }
+        moves |= shift(run, direction) & empty;
+    }
+    moves
+}
+
+/// Legal moves computed for one position.
+#[derive(Clone, Copy)]
+struct LegalCache {
+    black: u64,
+    white: u64,
+    player: Player,
+    moves: u64,
+}
+
 /// Represents the state of an Othello game.
-#[derive(Clone, Default, PartialEq)]
+///
+/// Serialized as a versioned object, e.g.
+/// `{"version": 1, "board": "---...--- ", "current_player": "Black", "passes": 0}`, where
+/// `board` lists the squares from A1 to H8 as in [`Game::to_position_string`].
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(into = "GameRepr", try_from = "GameRepr")]
 pub struct Game {
     pub black: u64,  // Bitboard for black discs
     pub white: u64,  // Bitboard for white discs
     pub current_player: Player,
     pub passes: u8,  // Number of consecutive passes
+    /// Legal moves of the last position they were computed for. It is keyed on the position, so
+    /// it is never stale even when the public fields are assigned directly.
+    legal_cache: Cell<Option<LegalCache>>,
+}
+
+impl PartialEq for Game {
+    fn eq(&self, other: &Self) -> bool {
+        self.black == other.black
+            && self.white == other.white
+            && self.current_player == other.current_player
+            && self.passes == other.passes
+    }
+}
+
+impl Eq for Game {}
+
+/// Hashes the position like [`PartialEq`] compares it, leaving out the legal-move cache.
+impl Hash for Game {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.black.hash(state);
+        self.white.hash(state);
+        self.current_player.hash(state);
+        self.passes.hash(state);
+    }
+}
+
+/// The serialized form of [`Game`].
+#[derive(Serialize, Deserialize)]
+struct GameRepr {
+    version: u32,
+    board: String,
+    current_player: Player,
+    passes: u8,
+}
+
+impl From<Game> for GameRepr {
+    fn from(game: Game) -> Self {
+        let mut board = game.to_position_string();
+        board.truncate(64);
+        GameRepr {
+            version: GAME_FORMAT_VERSION,
+            board,
+            current_player: game.current_player,
+            passes: game.passes,
+        }
+    }
+}
+
+impl TryFrom<GameRepr> for Game {
+    type Error = String;
+
+    fn try_from(repr: GameRepr) -> Result<Self, Self::Error> {
+        if repr.version != GAME_FORMAT_VERSION {
+            return Err(format!("Unsupported game version {}", repr.version));
+        }
+        if repr.passes > 2 {
+            return Err(format!("Invalid number of passes {}", repr.passes));
+        }
+        if repr.board.chars().any(char::is_whitespace) {
+            return Err("Board must not contain whitespace".to_string());
+        }
+        let game = Game::from_position_string(&repr.board)?;
+        Ok(Game::from_bitboards(game.black, game.white, repr.current_player, repr.passes))
+    }
+}
+
+impl fmt::Debug for Game {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Game")
+            .field("black", &self.black)
+            .field("white", &self.white)
+            .field("current_player", &self.current_player)
+            .field("passes", &self.passes)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Game {
@@ -48,11 +415,19 @@ impl Game {
         // Initial position: Black at E4 (36) and D5 (27), White at D4 (35) and E5 (28)
         let black = (1u64 << 36) | (1u64 << 27); // E4, D5
         let white = (1u64 << 35) | (1u64 << 28); // D4, E5
+        Game::from_bitboards(black, white, Player::Black, 0)
+    }
+
+    /// Creates a game from the disc bitboards, the side to move and the number of consecutive
+    /// passes.
+    #[must_use]
+    pub fn from_bitboards(black: u64, white: u64, current_player: Player, passes: u8) -> Self {
         Game {
             black,
             white,
-            current_player: Player::Black,
-            passes: 0,
+            current_player,
+            passes,
+            legal_cache: Cell::new(None),
         }
     }
 
@@ -68,13 +443,41 @@ impl Game {
         !self.occupied()
     }
 
+    /// Returns the number of empty squares.
+    #[must_use]
+    pub fn empties(&self) -> u32 {
+        self.empty().count_ones()
+    }
+
+    /// Returns the bitboard of the player's frontier discs, those next to an empty square.
+    /// Frontier discs give the opponent moves, so fewer is usually better.
+    #[must_use]
+    pub fn frontier(&self, player: Player) -> u64 {
+        let discs = match player {
+            Player::Black => self.black,
+            Player::White => self.white,
+        };
+        discs & bitboard::neighbours(self.empty())
+    }
+
+    /// Returns the stage of the game: the endgame once few enough squares are empty, and
+    /// otherwise the opening while few discs have been placed.
+    #[must_use]
+    pub fn phase(&self) -> Phase {
+        let empties = self.empties();
+        if empties <= Phase::ENDGAME_EMPTIES {
+            Phase::Endgame
+        } else if self.occupied().count_ones().saturating_sub(4) < Phase::OPENING_MOVES {
+            Phase::Opening
+        } else {
+            Phase::Midgame
+        }
+    }
+
     /// Checks if a move at the given position is valid for the current player.
     #[must_use]
     pub fn is_valid_move(&self, pos: u8) -> bool {
-        if pos >= 64 || (self.occupied() & (1u64 << pos)) != 0 {
-            return false;
-        }
-        self.flips(pos) != 0
+        pos < 64 && self.legal_move_bitboard() & (1u64 << pos) != 0
     }
 
     /// Calculates the bitboard of discs that would be flipped by placing a disc at the given position.
@@ -91,25 +494,13 @@ impl Game {
             (self.white, self.black)
         };
 
-        // Directions: (dr, dc) for row and column deltas
-        let directions = [
-            (-1, -1),
-            (-1, 0),
-            (-1, 1),
-            (0, -1),
-            (0, 1),
-            (1, -1),
-            (1, 0),
-            (1, 1),
-        ];
-
-        for &(dr, dc) in &directions {
-            let mut r = (pos / 8) as i16 + dr;
-            let mut c = (pos % 8) as i16 + dc;
+        for &(_, dr, dc) in &DIRECTIONS {
+            let mut r = i16::from(pos / 8) + dr;
+            let mut c = i16::from(pos % 8) + dc;
             let mut temp_flips = 0u64;
 
             while (0..8).contains(&r) && (0..8).contains(&c) {
-                let bit = 1u64 << (r as u64 * 8 + c as u64);
+                let bit = 1u64 << (r * 8 + c);
                 if (opponent_bb & bit) != 0 {
                     temp_flips |= bit;
                 } else if (player_bb & bit) != 0 {
@@ -125,6 +516,87 @@ impl Game {
         flips
     }
 
+    /// Returns the discs placing a disc at `pos` would flip, line by line as [`flip_lines`]
+    /// groups them, or nothing if the move is illegal.
+    #[must_use]
+    pub fn flip_details(&self, pos: u8) -> Vec<FlipLine> {
+        if !self.is_valid_move(pos) {
+            return Vec::new();
+        }
+        flip_lines(pos, self.flips(pos))
+    }
+
+    /// Explains, direction by direction, why placing a disc at `pos` would or would not be legal
+    /// for the current player.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the position is out of bounds.
+    pub fn explain_move(&self, pos: u8) -> Result<MoveExplanation, String> {
+        if pos >= 64 {
+            return Err("Position out of bounds".to_string());
+        }
+        if (self.occupied() & (1u64 << pos)) != 0 {
+            return Ok(MoveExplanation {
+                pos,
+                occupied: true,
+                rays: Vec::new(),
+            });
+        }
+        let (player_bb, opponent_bb) = if self.current_player == Player::Black {
+            (self.black, self.white)
+        } else {
+            (self.white, self.black)
+        };
+
+        let rays = DIRECTIONS
+            .iter()
+            .map(|&(direction, dr, dc)| {
+                let mut r = i16::from(pos / 8) + dr;
+                let mut c = i16::from(pos % 8) + dc;
+                let mut discs = 0u64;
+                let outcome = loop {
+                    if !(0..8).contains(&r) || !(0..8).contains(&c) {
+                        break if discs == 0 {
+                            RayOutcome::Edge
+                        } else {
+                            RayOutcome::RunsOffBoard
+                        };
+                    }
+                    let bit = 1u64 << (r * 8 + c);
+                    if (opponent_bb & bit) != 0 {
+                        discs |= bit;
+                    } else if (player_bb & bit) != 0 {
+                        break if discs == 0 {
+                            RayOutcome::OwnDiscAdjacent
+                        } else {
+                            RayOutcome::Flips
+                        };
+                    } else {
+                        break if discs == 0 {
+                            RayOutcome::EmptyAdjacent
+                        } else {
+                            RayOutcome::Unbracketed
+                        };
+                    }
+                    r += dr;
+                    c += dc;
+                };
+                RayExplanation {
+                    direction,
+                    discs,
+                    outcome,
+                }
+            })
+            .collect();
+
+        Ok(MoveExplanation {
+            pos,
+            occupied: false,
+            rays,
+        })
+    }
+
     /// Previews the flip mask for a potential move without mutating the game state.
     /// Returns the bitboard of discs that would be flipped.
     ///
@@ -132,17 +604,21 @@ impl Game {
     ///
     /// Returns an error if the move is invalid.
     pub fn preview_move(&self, pos: u8) -> Result<u64, String> {
+        self.checked_flips(pos).map_err(|e| e.to_string())
+    }
+
+    /// Returns the discs a disc placed at `pos` would flip, or why it cannot be placed there.
+    fn checked_flips(&self, pos: u8) -> Result<u64, IllegalMove> {
         if pos >= 64 {
-            return Err("Position out of bounds".to_string());
+            return Err(IllegalMove::OffBoard);
         }
         if (self.occupied() & (1u64 << pos)) != 0 {
-            return Err("Square is already occupied".to_string());
+            return Err(IllegalMove::Occupied);
         }
-        let flips = self.flips(pos);
-        if flips == 0 {
-            return Err("Move does not flip any discs".to_string());
+        match self.flips(pos) {
+            0 => Err(IllegalMove::NoFlips),
+            flips => Ok(flips),
         }
-        Ok(flips)
     }
 
     /// Places a disc at the given position and flips the appropriate opponent discs.
@@ -170,16 +646,28 @@ impl Game {
     }
 
     fn make_move_internal(&mut self, pos: u8) -> Result<(), String> {
-        if pos >= 64 {
-            return Err("Position out of bounds".to_string());
-        }
-        if (self.occupied() & (1u64 << pos)) != 0 {
-            return Err("Square is already occupied".to_string());
-        }
-        let flips = self.flips(pos);
-        if flips == 0 {
-            return Err("Move does not flip any discs".to_string());
-        }
+        self.place(pos)?;
+        self.auto_pass();
+        Ok(())
+    }
+
+    /// Places a disc like [`Game::make_move`], but leaves the turn with the opponent even if
+    /// they have no legal moves, so the pass has to be played explicitly.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the move is invalid.
+    pub fn place(&mut self, pos: u8) -> Result<(), String> {
+        self.try_place(pos).map_err(|e| e.to_string())
+    }
+
+    /// Places a disc like [`Game::place`], saying why if it cannot be placed.
+    ///
+    /// # Errors
+    ///
+    /// Returns why the move is illegal.
+    pub fn try_place(&mut self, pos: u8) -> Result<(), IllegalMove> {
+        let flips = self.checked_flips(pos)?;
         let pos_bit = 1u64 << pos;
         if self.current_player == Player::Black {
             self.black |= pos_bit | flips;
@@ -190,27 +678,76 @@ impl Game {
         }
         self.current_player = self.current_player.opponent();
         self.passes = 0;
-        // Auto-pass if current player has no legal moves
-        if !self.has_legal_move(self.current_player) {
+        Ok(())
+    }
+
+    /// Passes for the side to move while it has no legal moves, returning the players that
+    /// were passed for in order.
+    ///
+    /// If neither player can move, both pass and the game is over.
+    pub fn auto_pass(&mut self) -> Vec<Player> {
+        let mut passed = Vec::new();
+        while passed.len() < 2 && !self.has_legal_move(self.current_player) {
+            passed.push(self.current_player);
             self.pass();
-            // If still no moves after pass, pass again (game over after two passes)
-            if !self.has_legal_move(self.current_player) {
-                self.pass();
-            }
         }
-        Ok(())
+        passed
     }
 
     /// Passes the turn to the opponent and increments the pass counter.
     pub fn pass(&mut self) {
         self.current_player = self.current_player.opponent();
-        self.passes += 1;
+        self.passes = self.passes.saturating_add(1);
     }
 
     /// Returns a list of all legal move positions for the current player.
     #[must_use]
     pub fn legal_moves(&self) -> Vec<u8> {
-        (0..64).filter(|&pos| self.is_valid_move(pos)).collect()
+        self.legal_moves_iter().collect()
+    }
+
+    /// Iterates over the legal move positions for the current player in ascending order,
+    /// without allocating.
+    pub fn legal_moves_iter(&self) -> impl Iterator<Item = u8> {
+        bitboard::iterate_bits(self.legal_move_bitboard())
+    }
+
+    /// Returns the bitboard of squares the current player may play on.
+    ///
+    /// The result is cached, so asking again for the same position is free.
+    #[must_use]
+    pub fn legal_move_bitboard(&self) -> u64 {
+        if let Some(cache) = self.legal_cache.get() {
+            if cache.black == self.black
+                && cache.white == self.white
+                && cache.player == self.current_player
+            {
+                return cache.moves;
+            }
+        }
+        let moves = match self.current_player {
+            Player::Black => legal_move_bitboard(self.black, self.white),
+            Player::White => legal_move_bitboard(self.white, self.black),
+        };
+        self.legal_cache.set(Some(LegalCache {
+            black: self.black,
+            white: self.white,
+            player: self.current_player,
+            moves,
+        }));
+        moves
+    }
+
+    /// Returns the bitboard of squares the current player may play on, found by trying every
+    /// empty square with [`Game::flips`].
+    ///
+    /// This is far slower than [`Game::legal_move_bitboard`] and only serves as a simple
+    /// reference to check the bitboard generator against.
+    #[must_use]
+    pub fn naive_legal_move_bitboard(&self) -> u64 {
+        (0..64u8)
+            .filter(|&pos| self.occupied() & (1u64 << pos) == 0 && self.flips(pos) != 0)
+            .fold(0, |moves, pos| moves | (1u64 << pos))
     }
 
     /// Checks if the game is over (two consecutive passes have occurred, a player has no pieces, or the board is full).
@@ -249,16 +786,117 @@ impl Game {
         self.disc_count()
     }
 
+    /// Returns the scores for black and white under the given scoring rule.
+    ///
+    /// While the game is still in progress the empty squares are not assigned to anyone,
+    /// so every rule reports the plain disc count.
+    #[must_use]
+    pub fn final_scores(&self, rule: ScoringRule) -> (u32, u32) {
+        let (black, white) = self.disc_count();
+        if rule == ScoringRule::DiscCount || !self.is_game_over() {
+            return (black, white);
+        }
+        let empties = 64 - black - white;
+        match black.cmp(&white) {
+            Ordering::Greater => (black + empties, white),
+            Ordering::Less => (black, white + empties),
+            Ordering::Equal => (black + empties / 2, white + empties / 2),
+        }
+    }
+
+    /// Returns the winner under the given scoring rule, or None if it's a tie or not over.
+    #[must_use]
+    pub fn winner_with(&self, rule: ScoringRule) -> Option<Player> {
+        self.winner_with_komi(rule, 0)
+    }
+
+    /// Returns the winner under the given scoring rule once `komi` discs are added to White's
+    /// final count, or None if it's a tie or not over. A negative komi goes to Black.
+    #[must_use]
+    pub fn winner_with_komi(&self, rule: ScoringRule, komi: i32) -> Option<Player> {
+        if !self.is_game_over() {
+            return None;
+        }
+        let (black, white) = self.final_scores(rule);
+        match i64::from(black).cmp(&(i64::from(white) + i64::from(komi))) {
+            Ordering::Greater => Some(Player::Black),
+            Ordering::Less => Some(Player::White),
+            Ordering::Equal => None,
+        }
+    }
+
     /// Checks if the given player has any legal moves.
     #[must_use]
     pub fn has_legal_move(&self, player: Player) -> bool {
-        let temp_game = Game {
-            black: self.black,
-            white: self.white,
-            current_player: player,
-            passes: self.passes,
+        if player == self.current_player {
+            return self.legal_move_bitboard() != 0;
+        }
+        match player {
+            Player::Black => legal_move_bitboard(self.black, self.white) != 0,
+            Player::White => legal_move_bitboard(self.white, self.black) != 0,
+        }
+    }
+
+    /// Parses a 64-character board string followed by an optional side to move.
+    ///
+    /// Squares are listed rank by rank from A1 to H8, the order used by standard Othello
+    /// position strings such as the FFO test suite. `X`, `x`, `B`, `b` and `*` mark black discs; `O`, `o`, `W`, `w` mark white discs; `-`, `.`
+    /// and `_` mark empty squares. The side to move (`X`/`B` for black, `O`/`W` for white) may
+    /// follow after whitespace and defaults to black.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the board has the wrong length or contains an unknown character.
+    pub fn from_position_string(s: &str) -> Result<Game, String> {
+        let s = s.trim();
+        let (board, side) = match s.split_once(char::is_whitespace) {
+            Some((board, side)) => (board, side.trim()),
+            None => (s, ""),
         };
-        !temp_game.legal_moves().is_empty()
+        if board.chars().count() != 64 {
+            return Err("Board must be exactly 64 squares".to_string());
+        }
+        let mut game = Game::from_bitboards(0, 0, Player::Black, 0);
+        for (index, square) in board.chars().enumerate() {
+            // String index 0 is A1, which is bit 56; flipping the row bits maps one to the other.
+            let bit = 1u64 << (index ^ 0b11_1000);
+            match square {
+                'X' | 'x' | 'B' | 'b' | '*' => game.black |= bit,
+                'O' | 'o' | 'W' | 'w' => game.white |= bit,
+                '-' | '.' | '_' => {}
+                _ => return Err(format!("Unknown square character '{square}'")),
+            }
+        }
+        game.current_player = match side {
+            "" | "X" | "x" | "B" | "b" | "*" => Player::Black,
+            "O" | "o" | "W" | "w" => Player::White,
+            _ => return Err(format!("Unknown side to move '{side}'")),
+        };
+        Ok(game)
+    }
+
+    /// Formats the board as a 64-character string plus the side to move, e.g. `"---...--- X"`.
+    /// This is the inverse of [`Game::from_position_string`].
+    #[must_use]
+    pub fn to_position_string(&self) -> String {
+        let mut out: String = (0..64)
+            .map(|index| {
+                let bit = 1u64 << (index ^ 0b11_1000);
+                if self.black & bit != 0 {
+                    'X'
+                } else if self.white & bit != 0 {
+                    'O'
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        out.push(' ');
+        out.push(match self.current_player {
+            Player::Black => 'X',
+            Player::White => 'O',
+        });
+        out
     }
 
     /// Converts a position (0-63) to a coordinate string, e.g., 56 -> "A1" (bottom-left).
@@ -272,6 +910,15 @@ impl Game {
         format!("{col}{row}")
     }
 
+    /// Converts the squares set in a bitboard to coordinate strings, in position order.
+    #[must_use]
+    pub fn bitboard_to_coords(bitboard: u64) -> Vec<String> {
+        (0..64)
+            .filter(|pos| bitboard & (1u64 << pos) != 0)
+            .map(Self::pos_to_coord)
+            .collect()
+    }
+
     /// Converts a coordinate string to a position (0-63), e.g., "A1" -> 56.
     /// Uses standard Othello notation where A1 is bottom-left, H8 is top-right.
     /// Accepts lowercase and validates input.
@@ -283,9 +930,9 @@ impl Game {
         if coord.len() != 2 {
             return Err("Coordinate must be exactly 2 characters".to_string());
         }
-        let upper = coord.to_uppercase();
-        let bytes = upper.as_bytes();
-        let col = bytes[0];
+        // Only ASCII is case-folded: some non-ASCII letters shrink when upper-cased.
+        let bytes = coord.as_bytes();
+        let col = bytes[0].to_ascii_uppercase();
         let row = bytes[1];
         if !(b'A'..=b'H').contains(&col) {
             return Err("Column must be A-H".to_string());
@@ -300,6 +947,52 @@ impl Game {
     }
 }
 
+/// A game position packed into 17 bytes, for storing many positions such as search tree nodes.
+///
+/// It holds the same state as [`Game`] without the legal-move cache.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(C, packed)]
+pub struct PackedGame {
+    black: u64,
+    white: u64,
+    /// The side to move in the low bit and the consecutive passes above it.
+    meta: u8,
+}
+
+impl PackedGame {
+    /// Unpacks the position into a playable game.
+    #[must_use]
+    pub fn unpack(self) -> Game {
+        Game::from_bitboards(self.black, self.white, self.current_player(), self.meta >> 1)
+    }
+
+    /// Returns the side to move, without unpacking the position.
+    #[must_use]
+    pub fn current_player(self) -> Player {
+        if self.meta & 1 == 0 {
+            Player::Black
+        } else {
+            Player::White
+        }
+    }
+}
+
+impl From<&Game> for PackedGame {
+    fn from(game: &Game) -> Self {
+        PackedGame {
+            black: game.black,
+            white: game.white,
+            meta: (game.passes << 1) | u8::from(game.current_player == Player::White),
+        }
+    }
+}
+
+impl fmt::Debug for PackedGame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.unpack(), f)
+    }
+}
+
 impl fmt::Display for Game {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "  A B C D E F G H")?;
@@ -323,6 +1016,154 @@ impl fmt::Display for Game {
     }
 }
 
+/// A recorded game that can be stepped through move by move.
+///
+/// Holds the starting position and the moves played from it, along with the position after
+/// every ply, so seeking anywhere in the game is cheap.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameReplay {
+    moves: Vec<Move>,
+    positions: Vec<Game>,
+    ply: usize,
+}
+
+impl GameReplay {
+    /// Creates a replay of `moves` played from `initial`, positioned at the start.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first move that is illegal in its position.
+    pub fn new(initial: Game, moves: Vec<Move>) -> Result<Self, String> {
+        let mut positions = Vec::with_capacity(moves.len() + 1);
+        positions.push(initial);
+        for (ply, &mv) in moves.iter().enumerate() {
+            let mut next = positions[ply].clone();
+            next.make_move_enum(mv)
+                .map_err(|e| format!("Illegal move {mv} at ply {ply}: {e}"))?;
+            positions.push(next);
+        }
+        Ok(Self {
+            moves,
+            positions,
+            ply: 0,
+        })
+    }
+
+    /// Creates a replay of `moves` played from the standard starting position.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first move that is illegal in its position.
+    pub fn from_moves(moves: Vec<Move>) -> Result<Self, String> {
+        Self::new(Game::new(), moves)
+    }
+
+    /// Returns the moves of the game.
+    #[must_use]
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    /// Returns the number of moves in the game.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    /// Returns true if no moves were played.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    /// Returns the current ply, the number of moves played to reach the current position.
+    #[must_use]
+    pub fn ply(&self) -> usize {
+        self.ply
+    }
+
+    /// Returns the current position.
+    #[must_use]
+    pub fn game(&self) -> &Game {
+        &self.positions[self.ply]
+    }
+
+    /// Returns the starting position.
+    #[must_use]
+    pub fn initial(&self) -> &Game {
+        &self.positions[0]
+    }
+
+    /// Returns the final position.
+    #[must_use]
+    pub fn last(&self) -> &Game {
+        &self.positions[self.moves.len()]
+    }
+
+    /// Returns the position after `ply` moves, or `None` past the end of the game.
+    #[must_use]
+    pub fn board_at(&self, ply: usize) -> Option<&Game> {
+        self.positions.get(ply)
+    }
+
+    /// Moves to the position after `ply` moves.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `ply` is past the end of the game.
+    pub fn seek(&mut self, ply: usize) -> Result<&Game, String> {
+        if ply > self.moves.len() {
+            return Err(format!("Ply {ply} is past the end of the game ({} moves)", self.moves.len()));
+        }
+        self.ply = ply;
+        Ok(self.game())
+    }
+
+    /// Steps forward one move (redo), returning the new position, or `None` at the end.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<&Game> {
+        if self.ply < self.moves.len() {
+            self.ply += 1;
+            Some(self.game())
+        } else {
+            None
+        }
+    }
+
+    /// Steps back one move (undo), returning the new position, or `None` at the start.
+    pub fn prev(&mut self) -> Option<&Game> {
+        if self.ply > 0 {
+            self.ply -= 1;
+            Some(self.game())
+        } else {
+            None
+        }
+    }
+}
+
+/// Plays random legal moves to the end of a game, passing when there are none, and returns
+/// them. The same seed always plays the same game.
+#[cfg(test)]
+pub(crate) fn random_game(seed: u64) -> Vec<Move> {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut game = Game::new();
+    let mut moves = Vec::new();
+    while !game.is_game_over() {
+        let legal = game.legal_moves();
+        let mv = if legal.is_empty() {
+            Move::Pass
+        } else {
+            Move::Place(legal[rng.gen_range(0..legal.len())])
+        };
+        game.make_move_enum(mv).unwrap();
+        moves.push(mv);
+    }
+    moves
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -347,6 +1188,36 @@ mod tests {
         assert!(Game::coord_to_pos("I1").is_err());
         assert!(Game::coord_to_pos("A9").is_err());
         assert!(Game::coord_to_pos("A").is_err());
+        assert!(Game::coord_to_pos("\u{131}").is_err()); // dotless i upper-cases to "I"
+        assert!(Game::coord_to_pos("\u{e9}").is_err());
+    }
+
+    #[test]
+    fn test_move_text_round_trip() {
+        assert_eq!(Move::Place(43).to_string(), "D3");
+        assert_eq!(Move::Pass.to_string(), "pass");
+        assert_eq!("d3".parse(), Ok(Move::Place(43)));
+        assert_eq!("PASS".parse(), Ok(Move::Pass));
+        assert!("Z9".parse::<Move>().is_err());
+    }
+
+    #[test]
+    fn test_position_string_round_trip() {
+        let game = Game::new();
+        let text = game.to_position_string();
+        assert_eq!(
+            text,
+            "---------------------------OX------XO--------------------------- X"
+        );
+        assert_eq!(Game::from_position_string(&text), Ok(game));
+
+        let white_to_move = Game::from_position_string(&text.replace(" X", " O")).unwrap();
+        assert_eq!(white_to_move.current_player, Player::White);
+        assert_eq!(Game::from_position_string(&text[..64]).unwrap().current_player, Player::Black);
+
+        assert!(Game::from_position_string("XO").is_err());
+        assert!(Game::from_position_string(&text.replace('-', "?")).is_err());
+        assert!(Game::from_position_string(&text.replace(" X", " Z")).is_err());
     }
 
     #[test]
@@ -407,6 +1278,85 @@ mod tests {
         assert!(game.preview_move(0).is_err()); // no flips
     }
 
+    #[test]
+    fn test_explain_move() {
+        let game = Game::new();
+        // D3 is legal for Black: it flips D4 by bracketing it against D5.
+        let d3 = Game::coord_to_pos("D3").unwrap();
+        let explanation = game.explain_move(d3).unwrap();
+        assert!(explanation.is_legal());
+        assert_eq!(explanation.flips(), game.flips(d3));
+        let north = explanation
+            .rays
+            .iter()
+            .find(|ray| ray.direction == Direction::North)
+            .unwrap();
+        assert_eq!(north.outcome, RayOutcome::Flips);
+        assert_eq!(north.discs, 1u64 << Game::coord_to_pos("D4").unwrap());
+
+        // A8 touches nothing, so every ray is either off the board or empty.
+        let a8 = game.explain_move(0).unwrap();
+        assert!(!a8.is_legal());
+        assert!(a8
+            .rays
+            .iter()
+            .all(|ray| matches!(ray.outcome, RayOutcome::Edge | RayOutcome::EmptyAdjacent)));
+
+        // Occupied squares are reported without rays.
+        let occupied = game.explain_move(27).unwrap();
+        assert!(occupied.occupied);
+        assert!(!occupied.is_legal());
+
+        assert!(game.explain_move(64).is_err());
+    }
+
+    #[test]
+    fn test_final_scores() {
+        let mut game = Game::new();
+        game.black = 0xFF; // 8 discs on the top row
+        game.white = 0x0F00; // 4 discs on the second row
+        // Still in progress: both rules report raw counts.
+        assert_eq!(game.final_scores(ScoringRule::EmptiesToWinner), (8, 4));
+        game.pass();
+        game.pass();
+        assert_eq!(game.final_scores(ScoringRule::DiscCount), (8, 4));
+        assert_eq!(game.final_scores(ScoringRule::EmptiesToWinner), (60, 4));
+        assert_eq!(game.winner_with(ScoringRule::EmptiesToWinner), Some(Player::Black));
+
+        // Draws split the empty squares.
+        game.white = 0xFF00;
+        assert_eq!(game.final_scores(ScoringRule::EmptiesToWinner), (32, 32));
+        assert_eq!(game.winner_with(ScoringRule::EmptiesToWinner), None);
+        // Komi decides a drawn board, and goes to Black when negative.
+        assert_eq!(game.winner_with_komi(ScoringRule::DiscCount, 2), Some(Player::White));
+        assert_eq!(game.winner_with_komi(ScoringRule::DiscCount, -2), Some(Player::Black));
+        game.black = 0x03FF;
+        assert_eq!(game.winner_with_komi(ScoringRule::DiscCount, 2), None);
+
+        assert_eq!("empties_to_winner".parse(), Ok(ScoringRule::EmptiesToWinner));
+        assert!("nope".parse::<ScoringRule>().is_err());
+    }
+
+    #[test]
+    fn test_phase_and_frontier() {
+        let mut game = Game::new();
+        assert_eq!((game.empties(), game.phase()), (60, Phase::Opening));
+        // Every starting disc touches an empty square.
+        assert_eq!(game.frontier(Player::Black), game.black);
+        assert_eq!(game.frontier(Player::White), game.white);
+
+        // The top three rows are full: only the third row borders the empties.
+        game.black = 0x00FF_FFFF;
+        game.white = 0;
+        assert_eq!(game.phase(), Phase::Midgame);
+        assert_eq!(game.frontier(Player::Black), 0x00FF_0000);
+        assert_eq!(game.frontier(Player::White), 0);
+
+        game.black = !0xFFF;
+        assert_eq!((game.empties(), game.phase()), (12, Phase::Endgame));
+        assert_eq!(Phase::Endgame.as_str(), "endgame");
+    }
+
     #[test]
     fn test_full_board() {
         let mut game = Game::new();
@@ -421,4 +1371,174 @@ mod tests {
         }
         assert!(game.is_game_over());
     }
+
+    #[test]
+    fn test_game_replay_navigation() {
+        let moves: Vec<Move> = ["F5", "D6", "C3"].iter().map(|mv| mv.parse().unwrap()).collect();
+        let mut replay = GameReplay::from_moves(moves.clone()).unwrap();
+        assert_eq!(replay.len(), 3);
+        assert_eq!(replay.game(), &Game::new());
+        assert!(replay.prev().is_none());
+
+        let mut expected = Game::new();
+        for &mv in &moves {
+            expected.make_move_enum(mv).unwrap();
+            assert_eq!(replay.next(), Some(&expected));
+        }
+        assert!(replay.next().is_none());
+        assert_eq!(replay.last(), &expected);
+
+        let after_one = replay.board_at(1).unwrap().clone();
+        assert_eq!(replay.seek(1).unwrap(), &after_one);
+        assert_eq!(replay.prev(), Some(&Game::new()));
+        assert!(replay.seek(4).is_err());
+        assert!(replay.board_at(4).is_none());
+
+        let illegal = GameReplay::from_moves(vec![Move::Place(0)]);
+        assert!(illegal.unwrap_err().contains("ply 0"));
+    }
+
+    #[test]
+    fn test_place_and_auto_pass() {
+        // White's only disc is flanked at A2; once Black takes it White cannot move.
+        let mut game = Game::new();
+        game.black = 1u64 << Game::coord_to_pos("A3").unwrap();
+        game.white = 1u64 << Game::coord_to_pos("A2").unwrap();
+        let mut manual = game.clone();
+
+        let a1 = Game::coord_to_pos("A1").unwrap();
+        manual.place(a1).unwrap();
+        assert_eq!(manual.current_player, Player::White);
+        assert!(manual.legal_moves().is_empty());
+        assert_eq!(manual.auto_pass(), vec![Player::White, Player::Black]);
+        assert!(manual.is_game_over());
+
+        game.make_move(a1).unwrap();
+        assert_eq!(game, manual);
+    }
+
+    #[test]
+    fn test_legal_move_bitboard_matches_flips() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut passes = 0;
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut game = Game::new();
+            while !game.is_game_over() {
+                let expected: Vec<u8> = (0..64)
+                    .filter(|&pos| game.occupied() & (1u64 << pos) == 0 && game.flips(pos) != 0)
+                    .collect();
+                assert_eq!(game.legal_moves(), expected);
+                assert_eq!(game.legal_moves_iter().count(), expected.len());
+                // Placing leaves the turn with a player who cannot move, so they pass here.
+                if expected.is_empty() {
+                    game.pass();
+                    passes += 1;
+                } else {
+                    game.place(expected[rng.gen_range(0..expected.len())]).unwrap();
+                }
+            }
+        }
+        // Positions without legal moves were checked too.
+        assert!(passes > 0);
+    }
+
+    #[test]
+    fn test_flip_details_ripple_outwards() {
+        let pos = |coord: &str| Game::coord_to_pos(coord).unwrap();
+        let squares = |coords: &[&str]| coords.iter().fold(0u64, |bits, &coord| bits | (1u64 << pos(coord)));
+        let game = Game::from_bitboards(
+            squares(&["D7", "F4", "B4"]),
+            squares(&["D5", "D6", "E4", "C3"]),
+            Player::Black,
+            0,
+        );
+        let details = game.flip_details(pos("D4"));
+        assert_eq!(
+            details,
+            [
+                FlipLine {
+                    direction: Direction::North,
+                    discs: vec![pos("D5"), pos("D6")],
+                },
+                FlipLine {
+                    direction: Direction::East,
+                    discs: vec![pos("E4")],
+                },
+            ]
+        );
+        let flipped = details.iter().flat_map(|line| &line.discs).fold(0u64, |bits, &disc| bits | (1u64 << disc));
+        assert_eq!(flipped, game.flips(pos("D4")));
+        // C3 is not bracketed, and nothing is flipped by an illegal move.
+        assert!(game.flip_details(pos("A1")).is_empty());
+        assert!(game.flip_details(pos("D5")).is_empty());
+        assert!(flip_lines(64, !0).is_empty());
+    }
+
+    #[test]
+    fn test_legal_move_cache_follows_field_changes() {
+        let mut game = Game::new();
+        assert_eq!(game.legal_moves().len(), 4);
+        game.current_player = Player::White;
+        let mut white_moves: Vec<u8> = ["C5", "D6", "E3", "F4"]
+            .iter()
+            .map(|coord| Game::coord_to_pos(coord).unwrap())
+            .collect();
+        white_moves.sort_unstable();
+        assert_eq!(game.legal_moves(), white_moves);
+        game.white = 0;
+        assert!(game.legal_moves().is_empty());
+    }
+
+    #[test]
+    fn test_packed_game_round_trip() {
+        assert_eq!(std::mem::size_of::<PackedGame>(), 17);
+        let mut game = Game::new();
+        game.make_move(Game::coord_to_pos("D3").unwrap()).unwrap();
+        game.pass();
+        assert_eq!(PackedGame::from(&game).unpack(), game);
+        assert_eq!(PackedGame::from(&Game::new()).unpack(), Game::new());
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let mut game = Game::new();
+        game.make_move(Game::coord_to_pos("D3").unwrap()).unwrap();
+        game.pass();
+        let json = serde_json::to_value(&game).unwrap();
+        assert_eq!(json["version"], GAME_FORMAT_VERSION);
+        assert_eq!(json["current_player"], "Black");
+        assert_eq!(json["passes"], 1);
+        assert_eq!(json["board"], game.to_position_string()[..64]);
+        assert_eq!(serde_json::from_value::<Game>(json).unwrap(), game);
+
+        // Every bit survives, unlike a round trip through a float.
+        let full = Game::from_bitboards(!1, 1, Player::White, 0);
+        let json = serde_json::to_string(&full).unwrap();
+        assert_eq!(serde_json::from_str::<Game>(&json).unwrap(), full);
+
+        let moves = vec![Move::Place(Game::coord_to_pos("F5").unwrap()), Move::Pass];
+        let json = serde_json::to_string(&moves).unwrap();
+        assert_eq!(json, r#"["F5","pass"]"#);
+        assert_eq!(serde_json::from_str::<Vec<Move>>(&json).unwrap(), moves);
+        assert!(serde_json::from_str::<Move>(r#""Z9""#).is_err());
+
+        for bad in [
+            r#"{"version": 2, "board": "", "current_player": "Black", "passes": 0}"#,
+            r#"{"version": 1, "board": "X", "current_player": "Black", "passes": 0}"#,
+            r#"{"version": 1, "board": "", "current_player": "Red", "passes": 0}"#,
+        ] {
+            assert!(serde_json::from_str::<Game>(bad).is_err(), "{bad}");
+        }
+        let board = "-".repeat(64);
+        let bad_passes = format!(r#"{{"version": 1, "board": "{board}", "current_player": "Black", "passes": 3}}"#);
+        assert!(serde_json::from_str::<Game>(&bad_passes).is_err());
+
+        // The legal-move cache is not part of the hash, so games are safe keys.
+        #[allow(clippy::mutable_key_type)]
+        let set: std::collections::HashSet<Game> = [game.clone(), game, Game::new()].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
 }
@@ -0,0 +1,209 @@
+//! Helpers for working on bitboards directly, shared by the game logic, the evaluators and
+//! the search.
+//!
+//! A bitboard is a `u64` with one bit per square, laid out as in [`Game`]: bit 0 is A8, at
+//! the top left, bit 7 is H8 and bit 63 is H1. North is towards rank 8 and east towards
+//! file H, so moving north or south shifts by a row of 8 bits and moving east or west by 1.
+//!
+//! [`Game`]: super::Game
+
+use super::{Direction, DIRECTIONS};
+
+/// Squares of file A, the left edge.
+pub const FILE_A: u64 = 0x0101_0101_0101_0101;
+/// Squares of file H, the right edge.
+pub const FILE_H: u64 = 0x8080_8080_8080_8080;
+/// Every square but those of file A, which a shift east would wrap onto from file H.
+pub const NOT_FILE_A: u64 = !FILE_A;
+/// Every square but those of file H, which a shift west would wrap onto from file A.
+pub const NOT_FILE_H: u64 = !FILE_H;
+/// Squares of rank 8, the top edge.
+pub const RANK_8: u64 = 0x0000_0000_0000_00ff;
+/// Squares of rank 1, the bottom edge.
+pub const RANK_1: u64 = 0xff00_0000_0000_0000;
+
+/// The four corners: A1, H1, A8 and H8.
+pub const CORNERS: u64 = 0x8100_0000_0000_0081;
+/// Every square on the rim of the board, corners included.
+pub const EDGES: u64 = FILE_A | FILE_H | RANK_1 | RANK_8;
+/// The squares diagonally next to a corner: B2, G2, B7 and G7. Taking one while the corner is
+/// empty usually hands the corner to the opponent.
+pub const X_SQUARES: u64 = 0x0042_0000_0000_4200;
+/// The edge squares next to a corner: A2, B1, G1, H2, A7, B8, G8 and H7.
+pub const C_SQUARES: u64 = 0x4281_0000_0000_8142;
+
+/// Moves every disc one square north, towards rank 8.
+#[must_use]
+pub const fn shift_n(bitboard: u64) -> u64 {
+    bitboard >> 8
+}
+
+/// Moves every disc one square south, towards rank 1.
+#[must_use]
+pub const fn shift_s(bitboard: u64) -> u64 {
+    bitboard << 8
+}
+
+/// Moves every disc one square east, towards file H. Discs on file H fall off the board
+/// instead of wrapping onto file A.
+#[must_use]
+pub const fn shift_e(bitboard: u64) -> u64 {
+    (bitboard << 1) & NOT_FILE_A
+}
+
+/// Moves every disc one square west, towards file A. Discs on file A fall off the board
+/// instead of wrapping onto file H.
+#[must_use]
+pub const fn shift_w(bitboard: u64) -> u64 {
+    (bitboard >> 1) & NOT_FILE_H
+}
+
+/// Moves every disc one square in `direction`, dropping those that would leave the board.
+#[must_use]
+pub const fn shift(bitboard: u64, direction: Direction) -> u64 {
+    match direction {
+        Direction::North => shift_n(bitboard),
+        Direction::NorthEast => shift_n(shift_e(bitboard)),
+        Direction::East => shift_e(bitboard),
+        Direction::SouthEast => shift_s(shift_e(bitboard)),
+        Direction::South => shift_s(bitboard),
+        Direction::SouthWest => shift_s(shift_w(bitboard)),
+        Direction::West => shift_w(bitboard),
+        Direction::NorthWest => shift_n(shift_w(bitboard)),
+    }
+}
+
+/// Returns the squares next to any square of `bitboard`, in any of the eight directions,
+/// leaving out the squares of `bitboard` themselves unless they are next to another.
+#[must_use]
+pub fn neighbours(bitboard: u64) -> u64 {
+    DIRECTIONS
+        .iter()
+        .fold(0, |squares, &(direction, _, _)| squares | shift(bitboard, direction))
+}
+
+/// Iterates over the squares set in a bitboard, in ascending order.
+#[must_use]
+pub const fn iterate_bits(bitboard: u64) -> Bits {
+    Bits(bitboard)
+}
+
+/// Iterator over the squares set in a bitboard, returned by [`iterate_bits`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bits(u64);
+
+impl Iterator for Bits {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.0 == 0 {
+            return None;
+        }
+        let pos = self.0.trailing_zeros();
+        self.0 &= self.0 - 1;
+        u8::try_from(pos).ok()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.0.count_ones() as usize;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Bits {}
+
+impl std::iter::FusedIterator for Bits {}
+
+/// Returns the number of squares of `bitboard` in `region`, e.g. the corners a player holds.
+#[must_use]
+pub const fn count_in(bitboard: u64, region: u64) -> u32 {
+    (bitboard & region).count_ones()
+}
+
+/// How many squares of a bitboard lie in each region of the board that matters to strategy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegionCounts {
+    pub corners: u32,
+    pub x_squares: u32,
+    pub c_squares: u32,
+    /// Edge squares other than corners and C-squares.
+    pub other_edges: u32,
+    /// Squares off the rim of the board, X-squares included.
+    pub interior: u32,
+}
+
+impl RegionCounts {
+    /// Counts the squares of `bitboard` in each region.
+    #[must_use]
+    pub const fn of(bitboard: u64) -> Self {
+        Self {
+            corners: count_in(bitboard, CORNERS),
+            x_squares: count_in(bitboard, X_SQUARES),
+            c_squares: count_in(bitboard, C_SQUARES),
+            other_edges: count_in(bitboard, EDGES & !CORNERS & !C_SQUARES),
+            interior: count_in(bitboard, !EDGES),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+
+    fn squares(coords: &[&str]) -> u64 {
+        coords
+            .iter()
+            .fold(0, |bits, coord| bits | (1u64 << Game::coord_to_pos(coord).unwrap()))
+    }
+
+    #[test]
+    fn test_masks_name_their_squares() {
+        assert_eq!(CORNERS, squares(&["A1", "H1", "A8", "H8"]));
+        assert_eq!(X_SQUARES, squares(&["B2", "G2", "B7", "G7"]));
+        assert_eq!(C_SQUARES, squares(&["A2", "B1", "G1", "H2", "A7", "B8", "G8", "H7"]));
+        assert_eq!(FILE_A & RANK_1, squares(&["A1"]));
+        assert_eq!(FILE_H & RANK_8, squares(&["H8"]));
+        assert_eq!(EDGES.count_ones(), 28);
+    }
+
+    #[test]
+    fn test_shifts_drop_discs_leaving_the_board() {
+        let d4 = squares(&["D4"]);
+        assert_eq!(shift_n(d4), squares(&["D5"]));
+        assert_eq!(shift_s(d4), squares(&["D3"]));
+        assert_eq!(shift_e(d4), squares(&["E4"]));
+        assert_eq!(shift_w(d4), squares(&["C4"]));
+        assert_eq!(shift(d4, Direction::SouthWest), squares(&["C3"]));
+        assert_eq!(shift_e(FILE_H), 0);
+        assert_eq!(shift_w(FILE_A), 0);
+        assert_eq!(shift_n(RANK_8), 0);
+        assert_eq!(shift_s(RANK_1), 0);
+        assert_eq!(shift(squares(&["H4"]), Direction::NorthEast), 0);
+        assert_eq!(neighbours(squares(&["A1"])), squares(&["A2", "B1", "B2"]));
+        assert_eq!(neighbours(d4).count_ones(), 8);
+    }
+
+    #[test]
+    fn test_bits_and_regions_counted() {
+        let bits = squares(&["A8", "D4", "H1"]);
+        let mut iter = iterate_bits(bits);
+        assert_eq!(iter.len(), 3);
+        assert_eq!(iter.next(), Some(0));
+        assert_eq!(iter.collect::<Vec<_>>(), [Game::coord_to_pos("D4").unwrap(), 63]);
+        assert_eq!(iterate_bits(0).next(), None);
+
+        let counts = RegionCounts::of(bits | squares(&["B2", "B1", "D1"]));
+        assert_eq!(
+            counts,
+            RegionCounts {
+                corners: 2,
+                x_squares: 1,
+                c_squares: 1,
+                other_edges: 1,
+                interior: 2,
+            }
+        );
+        assert_eq!(count_in(!0, EDGES), 28);
+    }
+}
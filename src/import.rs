@@ -0,0 +1,325 @@
+//! Reads games recorded elsewhere, in SGF or GGF files, so they can be kept in the archive.
+//!
+//! Both formats write a game as a tree of nodes holding properties, such as `PB[Alice]` for
+//! the Black player or `B[f5]` for a move. SGF files are told apart by `GM[2]`, GGF files by
+//! `GM[Othello]`. Only a single line of play from the standard starting position, played to
+//! the end of the game, can be read: variations, set-up positions and unfinished games are
+//! refused. Every move is checked against the rules, and passes the rules force are
+//! accepted whether the file writes them or not.
+
+use crate::game::{Game, Move, Player};
+use serde::{Deserialize, Serialize};
+
+/// Longest file read, in bytes.
+pub const MAX_GAME_FILE_LEN: usize = 64 * 1024;
+
+/// Longest player name read from a file, in bytes.
+pub const MAX_PLAYER_NAME_LEN: usize = 64;
+
+/// Format a game was recorded in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GameFormat {
+    /// Smart Game Format, with `GM[2]` for Othello.
+    Sgf,
+    /// Generic Game Format, as used by GGS and `NBoard`.
+    Ggf,
+}
+
+impl GameFormat {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameFormat::Sgf => "sgf",
+            GameFormat::Ggf => "ggf",
+        }
+    }
+}
+
+/// A game read from a file, checked against the rules.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedGame {
+    pub format: GameFormat,
+    /// Name of the Black player, if the file gives one.
+    pub black: Option<String>,
+    /// Name of the White player, if the file gives one.
+    pub white: Option<String>,
+    /// Discs placed, in order, leaving out the passes.
+    pub moves: Vec<Move>,
+    /// The final position.
+    pub game: Game,
+}
+
+/// Reads a game from the contents of an SGF or GGF file.
+///
+/// # Errors
+///
+/// Returns an error if the file is too long, malformed, records another game than Othello
+/// or more than one line of play, does not start from the standard position, or holds a
+/// move breaking the rules or stops before the game ends.
+pub fn parse_game(content: &str) -> Result<ImportedGame, String> {
+    if content.len() > MAX_GAME_FILE_LEN {
+        return Err(format!("The file is longer than {MAX_GAME_FILE_LEN} bytes"));
+    }
+    let properties = properties(content)?;
+    let value = |name: &str| {
+        properties
+            .iter()
+            .find(|(property, _)| property == name)
+            .map(|(_, value)| value.trim())
+    };
+    let format = match value("GM") {
+        Some("2") => GameFormat::Sgf,
+        Some(game) if game.eq_ignore_ascii_case("othello") => GameFormat::Ggf,
+        Some(game) => return Err(format!("Not an Othello game: GM[{game}]")),
+        None => return Err("The file does not say which game it records".to_string()),
+    };
+    let size = match format {
+        GameFormat::Sgf => value("SZ"),
+        GameFormat::Ggf => value("TY"),
+    };
+    if size.is_some_and(|size| size != "8") {
+        return Err("Only games on an 8x8 board can be read".to_string());
+    }
+    if properties.iter().any(|(name, _)| matches!(name.as_str(), "AB" | "AW" | "AE")) {
+        return Err("Games set up from another position cannot be read".to_string());
+    }
+    if let Some(board) = value("BO") {
+        check_start(board)?;
+    }
+    let black = player_name(value("PB"))?;
+    let white = player_name(value("PW"))?;
+
+    let mut game = Game::new();
+    let mut moves = Vec::new();
+    let written = properties.iter().filter_map(|(name, value)| match name.as_str() {
+        "B" => Some((Player::Black, name, value)),
+        "W" => Some((Player::White, name, value)),
+        _ => None,
+    });
+    for (index, (color, name, value)) in written.enumerate() {
+        let mv = parse_move(value)
+            .and_then(|mv| play(&mut game, color, mv).map(|()| mv))
+            .map_err(|e| format!("Move {} ({name}[{value}]): {e}", index + 1))?;
+        if let Move::Place(_) = mv {
+            moves.push(mv);
+        }
+    }
+    if !game.is_game_over() {
+        return Err(format!("The game stops after {} moves, before its end", moves.len()));
+    }
+    Ok(ImportedGame {
+        format,
+        black,
+        white,
+        moves,
+        game,
+    })
+}
+
+/// Plays a move of `color`, or checks a pass it writes was forced and already played.
+fn play(game: &mut Game, color: Player, mv: Move) -> Result<(), String> {
+    match mv {
+        // Placing a disc passes for the opponent when they cannot move, and for both players
+        // at the end.
+        Move::Pass if game.is_game_over() || (game.current_player != color && game.passes > 0) => Ok(()),
+        Move::Pass if game.current_player != color => {
+            Err(format!("{} passes on {}'s turn", color.as_str(), color.opponent().as_str()))
+        }
+        Move::Pass => Err(format!("{} passes with a legal move", color.as_str())),
+        Move::Place(_) if game.current_player != color => {
+            Err(format!("{} plays on {}'s turn", color.as_str(), color.opponent().as_str()))
+        }
+        Move::Place(_) => game.make_move_enum(mv),
+    }
+}
+
+/// Reads a move: a square such as `f5`, an SGF point such as `fe`, or a pass, written as
+/// `PA`, `pass`, `tt` or nothing. A GGF move may be followed by `/` and an evaluation.
+fn parse_move(value: &str) -> Result<Move, String> {
+    let coord = value.split('/').next().unwrap_or_default().trim();
+    if coord.is_empty()
+        || coord.eq_ignore_ascii_case("pa")
+        || coord.eq_ignore_ascii_case("pass")
+        || coord == "tt"
+    {
+        return Ok(Move::Pass);
+    }
+    match coord.as_bytes() {
+        // SGF points count rows from the top as letters, as rows 1 to 8 are listed.
+        &[file, row] if row.is_ascii_lowercase() && (b'a'..=b'h').contains(&row) => {
+            let square = format!("{}{}", char::from(file), row - b'a' + 1);
+            Game::coord_to_pos(&square).map(Move::Place)
+        }
+        _ => Game::coord_to_pos(coord).map(Move::Place),
+    }
+}
+
+/// Checks a GGF board, such as `8 ---...--- *`, is the standard starting position with Black
+/// to move. Squares are listed row by row from A1.
+fn check_start(board: &str) -> Result<(), String> {
+    let fields: Vec<&str> = board.split_whitespace().collect();
+    let [size, squares @ .., to_move] = fields.as_slice() else {
+        return Err("The starting position is malformed".to_string());
+    };
+    let squares: String = squares.concat();
+    if *size != "8" || squares.len() != 64 {
+        return Err("The starting position is malformed".to_string());
+    }
+    let start = Game::new();
+    let mut squares = squares.bytes().map(|square| square.to_ascii_uppercase());
+    let standard = (1..=8).all(|rank| {
+        ('A'..='H').all(|file| {
+            let bit = Game::coord_to_pos(&format!("{file}{rank}")).map_or(0, |pos| 1u64 << pos);
+            let expected = if start.black & bit != 0 {
+                b'*'
+            } else if start.white & bit != 0 {
+                b'O'
+            } else {
+                b'-'
+            };
+            squares.next() == Some(expected)
+        })
+    });
+    if standard && *to_move == "*" {
+        Ok(())
+    } else {
+        Err("Only games from the standard starting position can be read".to_string())
+    }
+}
+
+fn player_name(name: Option<&str>) -> Result<Option<String>, String> {
+    match name {
+        Some(name) if name.len() > MAX_PLAYER_NAME_LEN => {
+            Err(format!("Player names are at most {MAX_PLAYER_NAME_LEN} bytes"))
+        }
+        Some(name) if !name.is_empty() => Ok(Some(name.to_string())),
+        _ => Ok(None),
+    }
+}
+
+/// Reads every property of the game in order, as `(name, value)` pairs, with a name given
+/// to each of the values listed after it. The nodes the properties belong to carry nothing a
+/// single line of play needs.
+fn properties(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut properties: Vec<(String, String)> = Vec::new();
+    let mut name = String::new();
+    let mut trees = 0;
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            'A'..='Z' => name.push(c),
+            // Long names in old SGF files, such as `GaMe`, are read by their capitals.
+            'a'..='z' => {}
+            '[' => {
+                if name.is_empty() {
+                    let (last, _) = properties.last().ok_or("A value has no property name")?;
+                    name.clone_from(last);
+                }
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some('\\') => value.extend(chars.next()),
+                        Some(c) => value.push(c),
+                        None => return Err(format!("The value of {name} is not closed")),
+                    }
+                }
+                properties.push((std::mem::take(&mut name), value));
+            }
+            '(' => {
+                trees += 1;
+                if trees > 1 {
+                    return Err("Only a single game without variations can be read".to_string());
+                }
+            }
+            ';' | ')' if name.is_empty() => {}
+            c if c.is_whitespace() => {}
+            c => return Err(format!("Unexpected {c:?} in the file")),
+        }
+    }
+    if trees == 0 {
+        return Err("The file holds no game".to_string());
+    }
+    Ok(properties)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write;
+
+    /// A whole game, with the passes forced near its end.
+    fn finished_moves() -> Vec<Move> {
+        let mut game = Game::new();
+        let mut moves = Vec::new();
+        while !game.is_game_over() {
+            let mv = Move::Place(game.legal_moves()[0]);
+            game.make_move_enum(mv).unwrap();
+            moves.push(mv);
+        }
+        moves
+    }
+
+    /// Writes `moves` as the properties of alternate colors, passing where the rules force it.
+    fn move_properties(moves: &[Move], notation: impl Fn(Move) -> String) -> String {
+        let mut game = Game::new();
+        let mut text = String::new();
+        for &mv in moves {
+            let color = if game.current_player == Player::Black { "B" } else { "W" };
+            write!(text, ";{color}[{}]", notation(mv)).unwrap();
+            game.make_move_enum(mv).unwrap();
+        }
+        text
+    }
+
+    #[test]
+    fn test_sgf_and_ggf_games_read() {
+        let moves = finished_moves();
+        let sgf_point = |mv: Move| {
+            let coord = mv.to_string().to_ascii_lowercase();
+            let (file, row) = coord.split_at(1);
+            format!("{file}{}", char::from(b'a' + row.parse::<u8>().unwrap() - 1))
+        };
+        let sgf = format!("(;GM[2]FF[4]SZ[8]PB[Alice]PW[Bob \\] Jr]{})", move_properties(&moves, sgf_point));
+        let game = parse_game(&sgf).unwrap();
+        assert_eq!(game.format, GameFormat::Sgf);
+        assert_eq!((game.black.as_deref(), game.white.as_deref()), (Some("Alice"), Some("Bob ] Jr")));
+        assert_eq!(game.moves, moves);
+        assert!(game.game.is_game_over());
+
+        let start = "8 ---------------------------O*------*O--------------------------- *";
+        let ggf = format!(
+            "(;GM[Othello]PC[GGS/os]TY[8]BO[{start}]{};)",
+            move_properties(&moves, |mv| format!("{mv}/1.50/0.01")).replace(';', "")
+        );
+        let game = parse_game(&ggf).unwrap();
+        assert_eq!((game.format, game.black), (GameFormat::Ggf, None));
+        assert_eq!(game.moves, moves);
+    }
+
+    #[test]
+    fn test_broken_games_refused() {
+        let moves = finished_moves();
+        let sgf = |body: &str| format!("(;GM[2]SZ[8]{body})");
+        let whole = move_properties(&moves, |mv| mv.to_string());
+        assert!(parse_game(&sgf(&whole)).is_ok());
+        // Written passes are checked, not required.
+        assert_eq!(parse_game(&sgf(";B[f5];B[pass]")).unwrap_err(), "Move 2 (B[pass]): Black passes on White's turn");
+        assert!(parse_game(&sgf(";B[]")).unwrap_err().contains("Black passes with a legal move"));
+
+        let half = move_properties(&moves[..20], |mv| mv.to_string());
+        assert!(parse_game(&sgf(&half)).unwrap_err().contains("before its end"));
+        assert!(parse_game(&sgf(";B[a1]")).unwrap_err().contains("does not flip"));
+        assert!(parse_game(&sgf(";W[f5]")).unwrap_err().contains("on Black's turn"));
+        assert!(parse_game(&sgf("AB[aa];B[f5]")).unwrap_err().contains("another position"));
+        assert!(parse_game(&sgf(";B[f5](;W[f6])(;W[d6])")).unwrap_err().contains("variations"));
+        assert!(parse_game("(;GM[1]SZ[19];B[pd])").unwrap_err().contains("Not an Othello game"));
+        assert!(parse_game("(;GM[2]SZ[10])").unwrap_err().contains("8x8"));
+        assert!(parse_game("(;GM[2];B[f5").unwrap_err().contains("not closed"));
+        assert!(parse_game("GM[2]").unwrap_err().contains("no game"));
+        let flipped = "8 ---------------------------*O------O*--------------------------- *";
+        assert!(parse_game(&format!("(;GM[Othello]BO[{flipped}];)")).unwrap_err().contains("standard"));
+        assert!(parse_game(&" ".repeat(MAX_GAME_FILE_LEN + 1)).is_err());
+    }
+}
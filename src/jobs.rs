@@ -0,0 +1,272 @@
+//! Persistent background jobs.
+//!
+//! Work too slow to do while answering a request, such as analysing a whole game, is queued
+//! as a job in the database and picked up by a small pool of worker tasks. A failed job is
+//! retried after an exponentially growing delay until it runs out of attempts. Jobs left
+//! running by a server that stopped are queued again when the workers start. The workers
+//! come with the `server` feature; without it, jobs can only be stored.
+
+#[cfg(feature = "server")]
+use crate::analysis::{self, AnalysisConfig};
+#[cfg(feature = "server")]
+use crate::anticheat;
+#[cfg(feature = "server")]
+use crate::api::Task;
+#[cfg(feature = "server")]
+use crate::state::Sessions;
+#[cfg(feature = "server")]
+use parking_lot::Mutex;
+#[cfg(feature = "server")]
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Number of times a job is tried before it is marked as failed.
+pub const MAX_ATTEMPTS: u32 = 3;
+
+/// How often idle workers look for jobs whose retry delay has passed.
+#[cfg(feature = "server")]
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the retention policy is run.
+#[cfg(feature = "server")]
+pub const RETENTION_INTERVAL: Duration = Duration::from_hours(24);
+
+/// Delay before retrying a failed job, doubling with every attempt.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    /// Delay after the first failed attempt.
+    pub base: Duration,
+    /// Longest delay between attempts.
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_secs(5),
+            max: Duration::from_mins(5),
+        }
+    }
+}
+
+impl Backoff {
+    /// Returns the delay before the next try of a job that has failed `attempts` times.
+    #[must_use]
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let factor = 1u32 << attempts.saturating_sub(1).min(16);
+        self.base.saturating_mul(factor).min(self.max)
+    }
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+#[must_use]
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| i64::try_from(elapsed.as_millis()).unwrap_or(i64::MAX))
+}
+
+/// Starts `count` workers running the queued jobs, after queuing again the jobs a previous
+/// server left running.
+///
+/// # Panics
+///
+/// Panics if the interrupted jobs cannot be queued again.
+#[cfg(feature = "server")]
+pub fn spawn_workers(sessions: &Arc<Mutex<Sessions>>, count: usize, backoff: &Backoff) {
+    let requeued = sessions
+        .lock()
+        .storage
+        .requeue_running_jobs()
+        .expect("Failed to requeue interrupted jobs");
+    if requeued > 0 {
+        tracing::info!("Requeued {requeued} interrupted jobs");
+    }
+    for _ in 0..count {
+        let sessions = sessions.clone();
+        let backoff = backoff.clone();
+        tokio::spawn(async move {
+            let signal = sessions.lock().job_signal();
+            loop {
+                if !run_next(&sessions, &backoff).await {
+                    // Woken early when a job is queued.
+                    let _ = tokio::time::timeout(POLL_INTERVAL, signal.notified()).await;
+                }
+            }
+        });
+    }
+}
+
+/// Queues a run of the retention policy now and then every [`RETENTION_INTERVAL`], if
+/// finished games are ever deleted.
+#[cfg(feature = "server")]
+pub fn spawn_retention(sessions: &Arc<Mutex<Sessions>>) {
+    let Some(policy) = sessions.lock().retention() else {
+        return;
+    };
+    let sessions = sessions.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+        loop {
+            interval.tick().await;
+            let task = Task::Prune {
+                keep_days: policy.keep_days,
+                dry_run: policy.dry_run,
+            };
+            if let Err(e) = sessions.lock().enqueue_job(&task) {
+                tracing::error!("Failed to queue the retention run: {e}");
+            }
+        }
+    });
+}
+
+/// Runs the next job that is due, if any, and records its outcome. Returns false if no job
+/// was due.
+#[cfg(feature = "server")]
+pub async fn run_next(sessions: &Arc<Mutex<Sessions>>, backoff: &Backoff) -> bool {
+    let claimed = sessions.lock().storage.claim_job(now_ms());
+    let job = match claimed {
+        Ok(Some(job)) => job,
+        Ok(None) => return false,
+        Err(e) => {
+            tracing::error!("Failed to claim a job: {e}");
+            return false;
+        }
+    };
+    let result = execute(sessions, &job.task).await;
+    let storage = &sessions.lock().storage;
+    let recorded = match result {
+        Ok(()) => storage.complete_job(job.id),
+        Err(e) => {
+            let retry_at = (job.attempts < job.max_attempts).then(|| {
+                let delay = i64::try_from(backoff.delay(job.attempts).as_millis()).unwrap_or(i64::MAX);
+                now_ms().saturating_add(delay)
+            });
+            tracing::warn!(
+                "Job {} ({}) failed on attempt {}: {e}",
+                job.id,
+                job.task.kind(),
+                job.attempts
+            );
+            storage.fail_job(job.id, &e, retry_at)
+        }
+    };
+    if let Err(e) = recorded {
+        tracing::error!("Failed to record the outcome of job {}: {e}", job.id);
+    }
+    true
+}
+
+#[cfg(feature = "server")]
+async fn execute(sessions: &Arc<Mutex<Sessions>>, task: &Task) -> Result<(), String> {
+    match task {
+        Task::Analysis { game_id } => {
+            let (moves, scheduler) = {
+                let sessions = sessions.lock();
+                let moves = sessions.storage.load_moves(game_id).map_err(|e| e.to_string())?;
+                (moves, sessions.ai_scheduler())
+            };
+            let id = game_id.clone();
+            // Analyses queue behind every live game's search.
+            let report = scheduler
+                .run(u32::MAX, move || {
+                    analysis::analyze(&id, &moves, &AnalysisConfig::default())
+                })
+                .await
+                .map_err(|e| e.to_string())??;
+            sessions
+                .lock()
+                .storage
+                .save_analysis_report(&report)
+                .map_err(|e| e.to_string())
+        }
+        Task::CheatCheck { game_id, ply } => {
+            let (history, analyzers, scheduler) = {
+                let sessions = sessions.lock();
+                let history = sessions.storage.load_move_records(game_id).map_err(|e| e.to_string())?;
+                (history, sessions.move_analyzers().to_vec(), sessions.ai_scheduler())
+            };
+            let (id, ply) = (game_id.clone(), *ply);
+            let player = history.get(ply).map(|record| record.player.clone()).unwrap_or_default();
+            let suspicions = scheduler
+                .run(u32::MAX, move || anticheat::check(&id, &history, ply, &analyzers))
+                .await
+                .map_err(|e| e.to_string())??;
+            let storage = &sessions.lock().storage;
+            for (analyzer, suspicion) in suspicions {
+                storage
+                    .save_cheat_report(game_id, &player, ply, &analyzer, &suspicion)
+                    .map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        }
+        Task::Coach { game_id, ply } => {
+            let (history, scheduler) = {
+                let sessions = sessions.lock();
+                let history = sessions.storage.load_move_records(game_id).map_err(|e| e.to_string())?;
+                (history, sessions.ai_scheduler())
+            };
+            let ply = *ply;
+            let player = history.get(ply).map(|record| record.player.clone()).unwrap_or_default();
+            let moves: Vec<_> = history.into_iter().map(|record| record.mv).collect();
+            // The player is waiting for it, and a single move is quick to assess, so it goes
+            // ahead of the AI's searches.
+            let analysis = scheduler
+                .run(0, move || analysis::analyze_move(&moves, ply, &AnalysisConfig::default()))
+                .await
+                .map_err(|e| e.to_string())??;
+            if let Some(analysis) = analysis {
+                sessions.lock().coach(game_id, &player, analysis);
+            }
+            Ok(())
+        }
+        Task::Prune { keep_days, dry_run } => prune(sessions, *keep_days, *dry_run).await,
+    }
+}
+
+/// Deletes the finished games that ended at least `keep_days` days ago, except those the
+/// retention policy always keeps, and records the run. A dry run deletes nothing and only
+/// records the games it would have deleted.
+///
+/// The sessions are locked for one game at a time, so a long run holds up no one's moves.
+#[cfg(feature = "server")]
+async fn prune(sessions: &Arc<Mutex<Sessions>>, keep_days: u32, dry_run: bool) -> Result<(), String> {
+    let games = sessions
+        .lock()
+        .storage
+        .expired_games(keep_days)
+        .map_err(|e| e.to_string())?;
+    if !dry_run {
+        for id in &games {
+            sessions.lock().expire_game(id).map_err(|e| e.to_string())?;
+            tokio::task::yield_now().await;
+        }
+    }
+    sessions
+        .lock()
+        .storage
+        .save_retention_run(keep_days, dry_run, &games)
+        .map_err(|e| e.to_string())?;
+    tracing::info!(
+        "Retention run over {keep_days} days {} {} games",
+        if dry_run { "would delete" } else { "deleted" },
+        games.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let backoff = Backoff {
+            base: Duration::from_secs(2),
+            max: Duration::from_secs(10),
+        };
+        let delays: Vec<u64> = (1..=5).map(|attempts| backoff.delay(attempts).as_secs()).collect();
+        assert_eq!(delays, [2, 4, 8, 10, 10]);
+        assert_eq!(backoff.delay(u32::MAX), backoff.max);
+    }
+}
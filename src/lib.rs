@@ -1,10 +1,59 @@
 #![warn(clippy::pedantic)]
 #![warn(clippy::all)]
 
+#[cfg(feature = "storage")]
+pub mod achievements;
+#[cfg(feature = "server")]
+pub mod admin;
 pub mod ai;
+pub mod analysis;
+#[cfg(feature = "storage")]
+pub mod anticheat;
+pub mod arena;
+#[cfg(any(feature = "storage", feature = "client"))]
+pub mod api;
+#[cfg(feature = "server")]
+pub mod assets;
+#[cfg(feature = "server")]
 pub mod auth;
+pub mod benchmark;
+#[cfg(feature = "server")]
+pub mod boards;
+#[cfg(feature = "server")]
+pub mod cache;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+#[cfg(feature = "server")]
+pub mod clock;
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "server")]
+pub mod engine;
+pub mod eval;
+pub mod ffo;
 pub mod game;
+pub mod import;
+#[cfg(feature = "storage")]
+pub mod jobs;
+#[cfg(feature = "server")]
+pub mod logging;
 pub mod mcts;
+#[cfg(feature = "server")]
 pub mod network;
+#[cfg(feature = "oauth")]
+pub mod oauth;
+#[cfg(any(feature = "server", feature = "client"))]
+pub mod protocol;
+#[cfg(feature = "storage")]
+pub mod puzzles;
+#[cfg(feature = "server")]
+pub mod scheduler;
+pub mod solver;
+#[cfg(feature = "server")]
 pub mod state;
+#[cfg(feature = "storage")]
 pub mod storage;
+#[cfg(feature = "server")]
+pub mod sync;
+pub mod tournaments;
+pub mod training;
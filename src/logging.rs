@@ -0,0 +1,253 @@
+//! Where the server's logs go and how they are written.
+//!
+//! Logs go to stdout as text unless configured otherwise by environment variables:
+//! `LOG_FORMAT=json` writes one JSON object per event, `LOG_FILE` writes to a file instead,
+//! started afresh every `LOG_ROTATION` (`daily` by default, or `hourly`, `minutely` or
+//! `never`), and `LOG_FILTER` sets the level of each module with directives such as
+//! `info,kawio::mcts=debug`. Warnings and errors always reach the [`ErrorLog`], whatever the
+//! filter.
+
+use crate::admin::ErrorLog;
+use std::env;
+use std::path::{Path, PathBuf};
+use tracing::Subscriber;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Levels logged when no filter is configured.
+pub const DEFAULT_LOG_FILTER: &str = "info";
+
+/// A layer writing logs, whatever its format and destination.
+pub type LogLayer<S> = Box<dyn Layer<S> + Send + Sync>;
+
+/// How each event is written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unknown log format '{s}', expected text or json")),
+        }
+    }
+}
+
+/// How often a log file is started afresh. Each file is named after the configured one with
+/// the date and time it was started appended.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    /// Keeps writing to the one file, named exactly as configured.
+    Never,
+}
+
+impl std::str::FromStr for LogRotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "minutely" => Ok(LogRotation::Minutely),
+            "hourly" => Ok(LogRotation::Hourly),
+            "daily" => Ok(LogRotation::Daily),
+            "never" => Ok(LogRotation::Never),
+            _ => Err(format!(
+                "Unknown log rotation '{s}', expected minutely, hourly, daily or never"
+            )),
+        }
+    }
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Minutely => Rotation::MINUTELY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+/// Where logs go and how they are written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// File to write to instead of stdout.
+    pub file: Option<PathBuf>,
+    pub rotation: LogRotation,
+    /// Directives choosing the levels logged, as in `RUST_LOG`.
+    pub filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Text,
+            file: None,
+            rotation: LogRotation::Daily,
+            filter: DEFAULT_LOG_FILTER.to_string(),
+        }
+    }
+}
+
+impl LogConfig {
+    /// Reads the configuration from `LOG_FORMAT`, `LOG_FILE`, `LOG_ROTATION` and
+    /// `LOG_FILTER`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a variable is set to an unknown value.
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Reads the configuration from the variables `var` returns.
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let mut config = Self::default();
+        if let Some(format) = var("LOG_FORMAT") {
+            config.format = format.parse()?;
+        }
+        config.file = var("LOG_FILE").filter(|file| !file.is_empty()).map(PathBuf::from);
+        if let Some(rotation) = var("LOG_ROTATION") {
+            config.rotation = rotation.parse()?;
+        }
+        if let Some(filter) = var("LOG_FILTER") {
+            EnvFilter::try_new(&filter).map_err(|e| format!("Invalid LOG_FILTER '{filter}': {e}"))?;
+            config.filter = filter;
+        }
+        Ok(config)
+    }
+
+    /// Builds the layer writing the logs, and the guard of its background writer if it
+    /// writes to a file. Logs still buffered are written when the guard is dropped, so it
+    /// is kept until the program ends.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the filter is invalid or the log file cannot be opened.
+    pub fn layer<S>(&self) -> Result<(LogLayer<S>, Option<WorkerGuard>), String>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let filter = EnvFilter::try_new(&self.filter).map_err(|e| format!("Invalid log filter: {e}"))?;
+        let (writer, guard) = match &self.file {
+            Some(file) => {
+                let (writer, guard) = tracing_appender::non_blocking(self.appender(file)?);
+                (BoxMakeWriter::new(writer), Some(guard))
+            }
+            None => (BoxMakeWriter::new(std::io::stdout), None),
+        };
+        // Colours would end up as escape codes in files.
+        let layer = tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(self.file.is_none());
+        let layer = match self.format {
+            LogFormat::Text => layer.with_filter(filter).boxed(),
+            LogFormat::Json => layer.json().with_filter(filter).boxed(),
+        };
+        Ok((layer, guard))
+    }
+
+    fn appender(&self, file: &Path) -> Result<RollingFileAppender, String> {
+        let directory = file.parent().filter(|parent| !parent.as_os_str().is_empty());
+        let name = file
+            .file_name()
+            .ok_or_else(|| format!("Invalid log file {}", file.display()))?;
+        RollingFileAppender::builder()
+            .rotation(self.rotation.into())
+            .filename_prefix(name.to_string_lossy())
+            .build(directory.unwrap_or_else(|| Path::new(".")))
+            .map_err(|e| format!("Failed to open log file {}: {e}", file.display()))
+    }
+}
+
+/// Installs the logging of the process, with the warnings and errors also kept by
+/// `error_log`, returning the guard of [`LogConfig::layer`].
+///
+/// # Errors
+///
+/// Returns an error if the logs cannot be set up as configured.
+pub fn init(config: &LogConfig, error_log: ErrorLog) -> Result<Option<WorkerGuard>, String> {
+    let (layer, guard) = config.layer()?;
+    tracing_subscriber::registry()
+        .with(layer)
+        .with(error_log.with_filter(LevelFilter::WARN))
+        .try_init()
+        .map_err(|e| format!("Failed to set up logging: {e}"))?;
+    Ok(guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_config_from_vars() {
+        let vars = |pairs: &[(&str, &str)]| {
+            let vars: HashMap<String, String> =
+                pairs.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect();
+            LogConfig::from_vars(|name| vars.get(name).cloned())
+        };
+        assert_eq!(vars(&[]), Ok(LogConfig::default()));
+        let config = vars(&[
+            ("LOG_FORMAT", "json"),
+            ("LOG_FILE", "logs/kawio.log"),
+            ("LOG_ROTATION", "hourly"),
+            ("LOG_FILTER", "warn,kawio::mcts=debug"),
+        ])
+        .unwrap();
+        assert_eq!(config.format, LogFormat::Json);
+        assert_eq!(config.file, Some(PathBuf::from("logs/kawio.log")));
+        assert_eq!(config.rotation, LogRotation::Hourly);
+        assert_eq!(config.filter, "warn,kawio::mcts=debug");
+        assert!(vars(&[("LOG_FORMAT", "xml")]).unwrap_err().contains("expected text or json"));
+        assert!(vars(&[("LOG_ROTATION", "weekly")]).is_err());
+        assert!(vars(&[("LOG_FILTER", "kawio=loud")]).is_err());
+    }
+
+    #[test]
+    fn test_json_logs_written_to_file() {
+        let directory = env::temp_dir().join(format!("kawio_logs_{}", std::process::id()));
+        let config = LogConfig {
+            format: LogFormat::Json,
+            file: Some(directory.join("server.log")),
+            rotation: LogRotation::Never,
+            filter: "info,kawio::logging=warn".to_string(),
+        };
+        let (layer, guard) = config.layer().unwrap();
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "kawio::network", game = "g1", "Game started");
+            tracing::info!("Filtered out");
+            tracing::warn!("Kept");
+        });
+        drop(guard);
+
+        let logs = std::fs::read_to_string(directory.join("server.log")).unwrap();
+        let events: Vec<serde_json::Value> = logs.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["fields"]["message"], "Game started");
+        assert_eq!(events[0]["fields"]["game"], "g1");
+        assert_eq!(events[1]["level"], "WARN");
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,109 +1,488 @@
 use kawio::*;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use parking_lot::Mutex;
 use std::env;
 use std::fs;
-use std::sync::{Arc, Mutex};
-use tower_http::services::ServeDir;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::game::{Game, Move, Player};
+use crate::admin::ErrorLog;
+use crate::ai::{ntuple, AiConfig};
+use crate::logging::LogConfig;
+use crate::mcts::SelectionPolicy;
+use crate::sync::UpdateBus;
+use crate::training::{Checkpoint, TrainingConfig};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
-struct Args {
-    /// Run in training mode
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the game server (the default)
+    Serve,
+    /// Run self-play training
+    Train(TrainArgs),
+    /// Measure engine throughput over a fixed seed set
+    Bench(BenchArgs),
+    /// Play two engine configurations against each other in mirrored pairs
+    Arena(ArenaArgs),
+    /// Solve the positions of an FFO endgame suite file and check the results
+    SolveFfo(SolveFfoArgs),
+    /// Find puzzles in the finished games of a database
+    MinePuzzles(MinePuzzlesArgs),
+    /// Store a game from an SGF or GGF file in a database
+    Import(ImportArgs),
+}
+
+#[derive(Args)]
+struct ImportArgs {
+    /// SGF or GGF file holding the game
+    file: PathBuf,
+
+    /// Player the game is imported for
+    #[arg(long)]
+    uploader: String,
+
+    /// Database to store the game in (defaults to DB_PATH or kawio.db)
     #[arg(long)]
-    train: bool,
+    db: Option<String>,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt::init();
+#[derive(Args)]
+struct MinePuzzlesArgs {
+    /// Database to mine and store the puzzles in (defaults to DB_PATH or kawio.db)
+    #[arg(long)]
+    db: Option<String>,
+}
+
+#[derive(Args)]
+struct SolveFfoArgs {
+    /// Suite file with one position per line
+    file: PathBuf,
+
+    /// Skip positions with more empty squares than this
+    #[arg(long)]
+    max_empties: Option<u32>,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    /// Random playouts per seed
+    #[arg(long, default_value_t = 1000)]
+    playouts: u32,
+
+    /// Legal-move generation rounds over the sampled positions
+    #[arg(long, default_value_t = 1000)]
+    legal_move_rounds: u32,
+
+    /// Self-play games to time
+    #[arg(long, default_value_t = 4)]
+    games: u32,
+
+    /// MCTS simulations per move in the self-play games
+    #[arg(long, default_value_t = 100)]
+    simulations: u32,
+
+    /// Write the report as JSON to this file
+    #[arg(long)]
+    save: Option<PathBuf>,
+
+    /// Compare against a report previously written with --save
+    #[arg(long)]
+    baseline: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ArenaArgs {
+    /// Mirrored pairs to play, two games each
+    #[arg(long, default_value_t = 100)]
+    pairs: u32,
+
+    /// MCTS simulations per move for engine A
+    #[arg(long, default_value_t = 100)]
+    a_simulations: u32,
 
-    let args = Args::parse();
+    /// MCTS simulations per move for engine B
+    #[arg(long, default_value_t = 100)]
+    b_simulations: u32,
 
-    if args.train {
-        run_training();
-    } else {
-        run_server().await?;
+    /// UCT exploration constant for engine A (defaults to the AI's)
+    #[arg(long)]
+    a_exploration: Option<f64>,
+
+    /// UCT exploration constant for engine B (defaults to the AI's)
+    #[arg(long)]
+    b_exploration: Option<f64>,
+
+    /// Selection policy of engine A: uct, puct or ucb1_tuned
+    #[arg(long, default_value = "uct")]
+    a_selection: SelectionPolicy,
+
+    /// Selection policy of engine B: uct, puct or ucb1_tuned
+    #[arg(long, default_value = "uct")]
+    b_selection: SelectionPolicy,
+
+    /// Expand every child of a leaf at once in engine A's search
+    #[arg(long)]
+    a_expand_all: bool,
+
+    /// Expand every child of a leaf at once in engine B's search
+    #[arg(long)]
+    b_expand_all: bool,
+
+    /// File of opening lines to pick from, one per line such as f5d6c3
+    #[arg(long)]
+    openings: Option<PathBuf>,
+
+    /// Random moves in each opening line when no file is given
+    #[arg(long, default_value_t = 6)]
+    opening_plies: u32,
+
+    /// Base seed for the match (random if omitted)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Stop early once a sequential probability ratio test is decided; --pairs is the limit
+    #[arg(long)]
+    sprt: bool,
+
+    /// Elo difference of A over B under the null hypothesis of the SPRT
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    elo0: f64,
+
+    /// Elo difference of A over B under the alternative hypothesis of the SPRT
+    #[arg(long, default_value_t = 5.0, allow_negative_numbers = true)]
+    elo1: f64,
+
+    /// Probability of the SPRT accepting the alternative hypothesis when it is false
+    #[arg(long, default_value_t = 0.05)]
+    alpha: f64,
+
+    /// Probability of the SPRT accepting the null hypothesis when it is false
+    #[arg(long, default_value_t = 0.05)]
+    beta: f64,
+}
+
+impl ArenaArgs {
+    fn arena_config(&self) -> Result<arena::ArenaConfig, String> {
+        let engine = |simulations, exploration: Option<f64>, selection, expand_all| {
+            let default = AiConfig::default();
+            AiConfig {
+                simulations,
+                exploration_constant: exploration.unwrap_or(default.exploration_constant),
+                selection,
+                expand_all,
+                ..default
+            }
+        };
+        let sprt = self
+            .sprt
+            .then_some(arena::Sprt {
+                elo0: self.elo0,
+                elo1: self.elo1,
+                alpha: self.alpha,
+                beta: self.beta,
+            })
+            .map(|sprt| sprt.validate().map(|()| sprt))
+            .transpose()?;
+        Ok(arena::ArenaConfig {
+            pairs: self.pairs,
+            engine_a: engine(self.a_simulations, self.a_exploration, self.a_selection, self.a_expand_all),
+            engine_b: engine(self.b_simulations, self.b_exploration, self.b_selection, self.b_expand_all),
+            opening_plies: self.opening_plies,
+            seed: self.seed.unwrap_or_else(rand::random),
+            sprt,
+        })
     }
-    Ok(())
 }
 
-fn run_training() {
-    let num_games = 1000;
-    let stats_file = "training_stats.txt";
-    let mut start_game = 1;
-    let mut black_wins = 0;
-    let mut white_wins = 0;
-    let mut draws = 0;
-    let mut total_moves = 0;
-
-    if let Ok(content) = fs::read_to_string(stats_file) {
-        let lines: Vec<&str> = content.lines().collect();
-        if lines.len() >= 5 {
-            start_game = lines[0].parse().unwrap_or(1) + 1; // start from next
-            black_wins = lines[1].parse().unwrap_or(0);
-            white_wins = lines[2].parse().unwrap_or(0);
-            draws = lines[3].parse().unwrap_or(0);
-            total_moves = lines[4].parse().unwrap_or(0);
+#[derive(Args)]
+#[command(args_conflicts_with_subcommands = true)]
+struct TrainArgs {
+    #[command(subcommand)]
+    action: Option<TrainAction>,
+
+    /// Checkpoint file recording every game of the run
+    #[arg(long, default_value = "training_checkpoint.json")]
+    checkpoint: PathBuf,
+
+    /// Continue the run stored in the checkpoint file, using its configuration
+    #[arg(long)]
+    resume: bool,
+
+    /// Number of self-play games to play
+    #[arg(long, default_value_t = 1000)]
+    games: u32,
+
+    /// MCTS simulations per move for Black
+    #[arg(long, default_value_t = 100)]
+    black_simulations: u32,
+
+    /// MCTS simulations per move for White
+    #[arg(long, default_value_t = 100)]
+    white_simulations: u32,
+
+    /// Move-selection temperature for Black (0 plays the most visited move)
+    #[arg(long, default_value_t = 0.0)]
+    black_temperature: f64,
+
+    /// Move-selection temperature for White (0 plays the most visited move)
+    #[arg(long, default_value_t = 0.0)]
+    white_temperature: f64,
+
+    /// Number of moves over which the temperature decays to zero (0 disables decay)
+    #[arg(long, default_value_t = 0)]
+    temperature_decay_moves: u32,
+
+    /// Base seed for the run (random if omitted)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Also store every self-play game (moves and final position) in this database
+    #[arg(long)]
+    db: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum TrainAction {
+    /// Print aggregate statistics from a checkpoint file
+    Report {
+        /// Checkpoint file to read
+        #[arg(long, default_value = "training_checkpoint.json")]
+        checkpoint: PathBuf,
+    },
+    /// Fit n-tuple evaluation weights to the stored self-play games
+    Ntuple(NtupleArgs),
+}
+
+#[derive(Args)]
+struct NtupleArgs {
+    /// Database holding the self-play games (defaults to DB_PATH or kawio.db)
+    #[arg(long)]
+    db: Option<String>,
+
+    /// File to write the weights to
+    #[arg(long, default_value = "ntuple_weights.json")]
+    out: PathBuf,
+
+    /// Start from the weights in this file instead of zero
+    #[arg(long)]
+    init: Option<PathBuf>,
+
+    /// Passes over the training positions
+    #[arg(long, default_value_t = 10)]
+    epochs: u32,
+
+    /// Step size of each gradient update
+    #[arg(long, default_value_t = 0.01)]
+    learning_rate: f64,
+
+    /// Seed for the order positions are visited in
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+impl TrainArgs {
+    fn training_config(&self) -> TrainingConfig {
+        TrainingConfig {
+            games: self.games,
+            black: AiConfig {
+                simulations: self.black_simulations,
+                temperature: self.black_temperature,
+                ..AiConfig::default()
+            },
+            white: AiConfig {
+                simulations: self.white_simulations,
+                temperature: self.white_temperature,
+                ..AiConfig::default()
+            },
+            temperature_decay_moves: self.temperature_decay_moves,
+            seed: self.seed.unwrap_or_else(rand::random),
         }
     }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Warnings and errors are also kept for the admin endpoints.
+    let error_log = ErrorLog::new();
+    // Buffered logs are flushed when the guard is dropped, as main returns.
+    let _log_guard = logging::init(&LogConfig::from_env()?, error_log.clone())?;
 
-    for game_num in start_game..=num_games {
-        let mut game = Game::new();
-        let mut moves_count = 0;
-
-        while !game.is_game_over() {
-            match ai::AI::get_move(&game) {
-                Some(Move::Place(pos)) => {
-                    game.make_move(pos).unwrap();
-                    moves_count += 1;
-                }
-                Some(Move::Pass) => {
-                    game.pass();
-                }
-                None => {
-                    game.pass();
-                }
+    let cli = Cli::parse();
+
+    match cli.command {
+        None | Some(Command::Serve) => run_server(error_log).await?,
+        Some(Command::Train(args)) => match args.action {
+            Some(TrainAction::Report { checkpoint }) => {
+                print!("{}", Checkpoint::load(&checkpoint)?.report());
+            }
+            Some(TrainAction::Ntuple(args)) => run_train_ntuple(&args)?,
+            None => {
+                let storage = args.db.as_deref().map(storage::Storage::new).transpose()?;
+                training::run(
+                    args.training_config(),
+                    &args.checkpoint,
+                    args.resume,
+                    storage.as_ref(),
+                )?;
             }
+        },
+        Some(Command::Bench(args)) => run_bench(&args)?,
+        Some(Command::Arena(args)) => {
+            let openings = match &args.openings {
+                Some(path) => arena::parse_openings(&fs::read_to_string(path)?)?,
+                None => Vec::new(),
+            };
+            print!("{}", arena::run(&args.arena_config()?, &openings));
         }
+        Some(Command::SolveFfo(args)) => run_solve_ffo(&args)?,
+        Some(Command::MinePuzzles(args)) => {
+            let db = args
+                .db
+                .unwrap_or_else(|| env::var("DB_PATH").unwrap_or_else(|_| "kawio.db".to_string()));
+            let found = puzzles::mine_stored_games(&storage::Storage::new(&db)?)?;
+            println!("Found {found} new puzzles");
+        }
+        Some(Command::Import(args)) => {
+            let db = args
+                .db
+                .unwrap_or_else(|| env::var("DB_PATH").unwrap_or_else(|_| "kawio.db".to_string()));
+            let imported = import::parse_game(&fs::read_to_string(&args.file)?)?;
+            let id = storage::Storage::new(&db)?.import_game(&args.uploader, &imported)?;
+            println!("Imported {} moves as game {id}", imported.moves.len());
+        }
+    }
+    Ok(())
+}
 
-        total_moves += moves_count;
-
-        match game.winner() {
-            Some(Player::Black) => black_wins += 1,
-            Some(Player::White) => white_wins += 1,
-            None => draws += 1,
+fn run_bench(args: &BenchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let report = benchmark::run(&benchmark::BenchConfig {
+        playouts: args.playouts,
+        legal_move_rounds: args.legal_move_rounds,
+        games: args.games,
+        simulations: args.simulations,
+    });
+    match &args.baseline {
+        Some(path) => {
+            let baseline: benchmark::BenchReport = serde_json::from_str(&fs::read_to_string(path)?)?;
+            print!("{}", report.compare(&baseline));
         }
+        None => print!("{report}"),
+    }
+    if let Some(path) = &args.save {
+        fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    }
+    Ok(())
+}
+
+fn run_train_ntuple(args: &NtupleArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let db = args
+        .db
+        .clone()
+        .unwrap_or_else(|| env::var("DB_PATH").unwrap_or_else(|_| "kawio.db".to_string()));
+    let samples = ntuple::load_samples(&storage::Storage::new(&db)?)?;
+    if samples.is_empty() {
+        return Err("No self-play games to train on; run `train --db` first".into());
+    }
+    let mut evaluator = match &args.init {
+        Some(path) => ntuple::NTupleEvaluator::load(path)?,
+        None => ntuple::NTupleEvaluator::new(),
+    };
+    println!("Training on {} positions", samples.len());
+    let config = ntuple::FitConfig {
+        epochs: args.epochs,
+        learning_rate: args.learning_rate,
+        seed: args.seed,
+    };
+    for (epoch, loss) in ntuple::fit(&mut evaluator, &samples, &config).iter().enumerate() {
+        println!("Epoch {}: log loss {loss:.4}", epoch + 1);
+    }
+    evaluator.save(&args.out)?;
+    println!("Weights written to {}", args.out.display());
+    Ok(())
+}
 
-        let content = format!("{}\n{}\n{}\n{}\n{}", game_num, black_wins, white_wins, draws, total_moves);
-        fs::write(stats_file, content).unwrap();
+#[allow(clippy::cast_precision_loss)]
+fn run_solve_ffo(args: &SolveFfoArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let positions = ffo::parse_suite(&fs::read_to_string(&args.file)?)?;
+    let mut solved = 0;
+    let mut correct = 0;
+    let mut nodes = 0;
+    let mut elapsed = std::time::Duration::ZERO;
 
-        if game_num % 100 == 0 {
-            let avg_moves = total_moves as f64 / game_num as f64;
-            let black_win_rate = black_wins as f64 / game_num as f64;
-            let white_win_rate = white_wins as f64 / game_num as f64;
-            let draw_rate = draws as f64 / game_num as f64;
-            println!("Games: {}, Black wins: {:.2}%, White wins: {:.2}%, Draws: {:.2}%, Avg moves: {:.2}",
-                     game_num, black_win_rate * 100.0, white_win_rate * 100.0, draw_rate * 100.0, avg_moves);
+    println!(
+        "{:>5} {:>7} {:>5} {:>6} {:>8} {:>7} {:>12} {:>10}",
+        "Line", "Empties", "Move", "Score", "Expected", "Result", "Nodes", "Time (s)"
+    );
+    for position in &positions {
+        if args.max_empties.is_some_and(|max| position.game.empty().count_ones() > max) {
+            continue;
+        }
+        let result = ffo::run_position(position);
+        solved += 1;
+        nodes += result.solution.nodes;
+        elapsed += result.elapsed;
+        if result.correct == Some(true) {
+            correct += 1;
         }
+        println!(
+            "{:>5} {:>7} {:>5} {:>+6} {:>8} {:>7} {:>12} {:>10.3}",
+            result.line,
+            result.empties,
+            result.solution.best_move.map_or_else(|| "-".to_string(), |mv| mv.to_string()),
+            result.solution.score,
+            result.expected_score.map_or_else(|| "?".to_string(), |score| format!("{score:+}")),
+            match result.correct {
+                Some(true) => "ok",
+                Some(false) => "WRONG",
+                None => "-",
+            },
+            result.solution.nodes,
+            result.elapsed.as_secs_f64()
+        );
     }
-
-    println!("Training complete. Total games: {}", num_games);
-    let _ = fs::remove_file(stats_file);
+    println!(
+        "Solved {solved} positions, {correct} correct, in {:.3} s ({:.0} nodes/s)",
+        elapsed.as_secs_f64(),
+        nodes as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+    );
+    Ok(())
 }
 
-async fn run_server() -> Result<(), Box<dyn std::error::Error>> {
+async fn run_server(error_log: ErrorLog) -> Result<(), Box<dyn std::error::Error>> {
     let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string());
     let address = format!("0.0.0.0:{}", port);
 
-    let sessions = Arc::new(Mutex::new(state::Sessions::new()));
-    let api_router = network::create_router(sessions);
-    let app = api_router.fallback_service(ServeDir::new("web"));
+    let db_path = env::var("DB_PATH").unwrap_or_else(|_| "kawio.db".to_string());
+    let storage = storage::Storage::new(&db_path)?;
+    let sessions = Arc::new(Mutex::new(state::Sessions::new(storage)));
+    sessions.lock().set_error_log(error_log);
+    if sessions.lock().is_shared() {
+        let bus: Arc<dyn UpdateBus> = match env::var("REDIS_URL") {
+            #[cfg(feature = "redis")]
+            Ok(url) => cache::redis::RedisBus::connect(&url)?,
+            _ => sync::DatabaseBus::open(&db_path)?,
+        };
+        sessions.lock().set_update_bus(bus.clone());
+        sync::spawn_listener(&sessions, &bus);
+    }
+    let workers = env::var("JOB_WORKERS").map_or(1, |workers| workers.parse().expect("Invalid JOB_WORKERS"));
+    jobs::spawn_workers(&sessions, workers, &jobs::Backoff::default());
+    jobs::spawn_retention(&sessions);
+    let web_dir = env::var("WEB_DIR").unwrap_or_else(|_| "web".to_string());
+    let api_router = network::create_router(sessions.clone());
+    let app = api_router.fallback_service(assets::router(Path::new(&web_dir)));
 
     let listener = tokio::net::TcpListener::bind(&address).await?;
     tracing::info!("Server running on http://{}", address);
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(network::shutdown_signal(sessions))
+        .await?;
     Ok(())
 }
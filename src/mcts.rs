@@ -1,5 +1,14 @@
-use crate::game::{Game, Player, Move};
+use crate::eval::{Evaluator, HeuristicEvaluator};
+use crate::game::{bitboard, Game, Move, PackedGame, Player};
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::Arc;
+
+mod snapshot;
+
+pub use snapshot::{SnapshotNode, TreeSnapshot, SNAPSHOT_VERSION};
 
 /// Telemetry data from MCTS search.
 #[derive(Debug, Clone)]
@@ -10,6 +19,20 @@ pub struct Telemetry {
     pub visit_distribution: Vec<u32>,
 }
 
+/// Search statistics of one root move, as reported by [`MCTS::root_statistics`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveStats {
+    pub mv: Move,
+    pub visits: u32,
+    /// Mean outcome of the simulations through the move, from 0 to 1; 0 if it was not visited.
+    pub q: f64,
+    /// Prior probability of the move before searching: an even share of the root moves, or
+    /// with [`SelectionPolicy::Puct`] the share the heuristic evaluation gives it.
+    pub prior: f64,
+    /// Principal variation: the move followed by the most visited reply at each level.
+    pub pv: Vec<Move>,
+}
+
 /// Result of MCTS search.
 #[derive(Debug, Clone)]
 pub struct SearchResult {
@@ -17,158 +40,602 @@ pub struct SearchResult {
     pub telemetry: Telemetry,
 }
 
+/// Aborts a running search from another thread.
+///
+/// Clones share the same flag, so the server can keep one while a search runs with the other.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+    /// Flags of the tokens this one was made from, whose cancellation cancels it too.
+    parents: Vec<Arc<AtomicBool>>,
+}
 
+impl CancelToken {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a token cancelled along with this one, which can also be cancelled on its own,
+    /// e.g. to cut one search short without aborting everything the token covers.
+    #[must_use]
+    pub fn child(&self) -> Self {
+        let mut parents = self.parents.clone();
+        parents.push(self.flag.clone());
+        Self {
+            flag: Arc::default(),
+            parents,
+        }
+    }
+
+    /// Asks every search using this token to stop after its current simulation.
+    pub fn cancel(&self) {
+        self.flag.store(true, AtomicOrdering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(AtomicOrdering::Relaxed)
+            || self.parents.iter().any(|parent| parent.load(AtomicOrdering::Relaxed))
+    }
+}
 
 struct Node {
     visits: u32,
-    wins: u32,
+    /// Sum of the outcomes of the simulations through the node, for the player who moved into
+    /// it.
+    wins: f64,
+    /// Sum of the squared outcomes, for the variance [`SelectionPolicy::Ucb1Tuned`] uses.
+    wins_squared: f64,
+    /// Share of the parent's visits the node is expected to deserve before searching it.
+    prior: f64,
     parent: Option<usize>,
     children: Vec<usize>,
-    game: Game,
+    /// Bitboard of the legal moves that have no child yet.
+    untried: u64,
+    /// The position, packed so large trees stay small; unpacked when the node is expanded or
+    /// simulated.
+    game: PackedGame,
     move_from_parent: Option<Move>,
 }
 
 impl Node {
-    fn new(game: Game, parent: Option<usize>, move_from_parent: Option<Move>) -> Self {
+    fn new(game: &Game, parent: Option<usize>, move_from_parent: Option<Move>) -> Self {
         Node {
             visits: 0,
-            wins: 0,
+            wins: 0.0,
+            wins_squared: 0.0,
+            prior: 1.0,
             parent,
             children: Vec::new(),
-            game,
+            untried: game.legal_move_bitboard(),
+            game: PackedGame::from(game),
             move_from_parent,
         }
     }
 
-    fn uct_value(&self, parent_visits: u32, exploration_constant: f64) -> f64 {
+    /// Returns how promising the node looks to its parent under `policy`: its mean outcome
+    /// plus an exploration bonus. Unvisited nodes come first.
+    fn selection_value(&self, parent_visits: u32, exploration_constant: f64, policy: SelectionPolicy) -> f64 {
         if self.visits == 0 {
-            f64::INFINITY
+            return f64::INFINITY;
+        }
+        let visits = f64::from(self.visits);
+        let parent_visits = f64::from(parent_visits);
+        let q = self.wins / visits;
+        let bonus = match policy {
+            SelectionPolicy::Uct => (parent_visits.ln() / visits).sqrt(),
+            SelectionPolicy::Puct => self.prior * parent_visits.sqrt() / (1.0 + visits),
+            SelectionPolicy::Ucb1Tuned => {
+                let variance = self.wins_squared / visits - q * q + (2.0 * parent_visits.ln() / visits).sqrt();
+                (parent_visits.ln() / visits * variance.min(0.25)).sqrt()
+            }
+        };
+        q + exploration_constant * bonus
+    }
+}
+
+/// How the search picks the child of a node to descend into, used in
+/// [`crate::ai::AiConfig`]. Each rule adds an exploration bonus, scaled by the exploration
+/// constant `c`, to the mean outcome `q` of a child visited `n` times out of the `N` visits
+/// of its parent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionPolicy {
+    /// UCB1 applied to trees: `q + c * sqrt(ln(N) / n)`.
+    #[default]
+    Uct,
+    /// Predictor UCT, `q + c * p * sqrt(N) / (1 + n)`, which favours the moves with a high
+    /// prior `p`. Priors come from the heuristic evaluation of the moves.
+    Puct,
+    /// UCB1-Tuned, `q + c * sqrt(ln(N) / n * min(1/4, v))`, which explores less the moves
+    /// whose outcomes vary little; `v` is the variance of the outcomes plus
+    /// `sqrt(2 * ln(N) / n)`.
+    Ucb1Tuned,
+}
+
+impl std::str::FromStr for SelectionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uct" => Ok(SelectionPolicy::Uct),
+            "puct" => Ok(SelectionPolicy::Puct),
+            "ucb1_tuned" => Ok(SelectionPolicy::Ucb1Tuned),
+            _ => Err(format!("Unknown selection policy '{s}', expected uct, puct or ucb1_tuned")),
+        }
+    }
+}
+
+/// Sharpness of the softmax turning the heuristic values of moves, from -1 to 1, into PUCT
+/// priors.
+const PRIOR_SHARPNESS: f64 = 4.0;
+
+/// Plays uniformly random moves from the given position until the game is over and returns
+/// the final position.
+pub fn random_playout<R: Rng + ?Sized>(mut game: Game, rng: &mut R) -> Game {
+    while !game.is_game_over() {
+        let count = game.legal_moves_iter().count();
+        if count == 0 {
+            game.pass();
         } else {
-            (self.wins as f64 / self.visits as f64)
-                + exploration_constant * (parent_visits as f64).ln() / (self.visits as f64)
+            let index = rng.gen_range(0..count);
+            if let Some(mv) = game.legal_moves_iter().nth(index) {
+                let _ = game.make_move(mv);
+            }
+        }
+    }
+    game
+}
+
+/// Returns 1 for a win, 0.5 for a draw and 0 for a loss of `player` in a finished game.
+fn game_outcome(game: &Game, player: Player) -> f64 {
+    let (black, white) = game.disc_count();
+    let (own, other) = match player {
+        Player::Black => (black, white),
+        Player::White => (white, black),
+    };
+    match own.cmp(&other) {
+        Ordering::Greater => 1.0,
+        Ordering::Less => 0.0,
+        Ordering::Equal => 0.5,
+    }
+}
+
+/// Plays out a position during the simulation step of the search.
+pub trait RolloutPolicy: Send + Sync {
+    /// Estimates the outcome of `game` for `player`, from 0 (loss) to 1 (win).
+    fn rollout(&self, game: Game, player: Player, rng: &mut StdRng) -> f64;
+
+    /// Estimates the outcome of each position for its side to move. The search plays out
+    /// all the children of an expanded node in one call, so policies scoring positions with
+    /// an evaluator can evaluate them together.
+    fn rollout_batch(&self, games: Vec<Game>, rng: &mut StdRng) -> Vec<f64> {
+        games
+            .into_iter()
+            .map(|game| {
+                let player = game.current_player;
+                self.rollout(game, player, rng)
+            })
+            .collect()
+    }
+}
+
+/// Plays uniformly random moves to the end of the game.
+#[derive(Clone, Debug, Default)]
+pub struct RandomRollout;
+
+impl RolloutPolicy for RandomRollout {
+    fn rollout(&self, game: Game, player: Player, rng: &mut StdRng) -> f64 {
+        game_outcome(&random_playout(game, rng), player)
+    }
+}
+
+/// Plays to the end of the game, choosing the move the evaluator likes best except for a
+/// random move with probability `epsilon`.
+pub struct EpsilonGreedyRollout<E> {
+    pub epsilon: f64,
+    pub evaluator: E,
+}
+
+impl<E: Evaluator> RolloutPolicy for EpsilonGreedyRollout<E> {
+    fn rollout(&self, mut game: Game, player: Player, rng: &mut StdRng) -> f64 {
+        while !game.is_game_over() {
+            let moves = game.legal_moves();
+            if moves.is_empty() {
+                game.pass();
+                continue;
+            }
+            let mv = if rng.gen::<f64>() < self.epsilon {
+                moves[rng.gen_range(0..moves.len())]
+            } else {
+                let side = game.current_player;
+                moves
+                    .iter()
+                    .map(|&pos| {
+                        let mut child = game.clone();
+                        let _ = child.make_move(pos);
+                        let value = self.evaluator.evaluate(&child);
+                        let value = if child.current_player == side {
+                            value
+                        } else {
+                            -value
+                        };
+                        (pos, value)
+                    })
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .map_or(moves[0], |(pos, _)| pos)
+            };
+            let _ = game.make_move(mv);
+        }
+        game_outcome(&game, player)
+    }
+}
+
+/// Plays `depth` random moves, then scores the reached position with the evaluator instead
+/// of playing on to the end.
+pub struct CutoffRollout<E> {
+    pub depth: u32,
+    pub evaluator: E,
+}
+
+impl<E> CutoffRollout<E> {
+    fn play_random_moves(&self, game: &mut Game, rng: &mut StdRng) {
+        for _ in 0..self.depth {
+            if game.is_game_over() {
+                break;
+            }
+            let moves = game.legal_moves();
+            if moves.is_empty() {
+                game.pass();
+            } else {
+                let _ = game.make_move(moves[rng.gen_range(0..moves.len())]);
+            }
+        }
+    }
+}
+
+/// Converts an evaluator's value of `game` for its side to move into an outcome for
+/// `player`, from 0 to 1.
+fn value_outcome(game: &Game, value: f64, player: Player) -> f64 {
+    let value = if game.current_player == player {
+        value
+    } else {
+        -value
+    };
+    f64::midpoint(value, 1.0)
+}
+
+impl<E: Evaluator> RolloutPolicy for CutoffRollout<E> {
+    fn rollout(&self, mut game: Game, player: Player, rng: &mut StdRng) -> f64 {
+        self.play_random_moves(&mut game, rng);
+        if game.is_game_over() {
+            return game_outcome(&game, player);
+        }
+        value_outcome(&game, self.evaluator.evaluate(&game), player)
+    }
+
+    fn rollout_batch(&self, games: Vec<Game>, rng: &mut StdRng) -> Vec<f64> {
+        let players: Vec<Player> = games.iter().map(|game| game.current_player).collect();
+        let reached: Vec<Game> = games
+            .into_iter()
+            .map(|mut game| {
+                self.play_random_moves(&mut game, rng);
+                game
+            })
+            .collect();
+        let unfinished: Vec<Game> = reached.iter().filter(|game| !game.is_game_over()).cloned().collect();
+        let mut values = self.evaluator.evaluate_batch(&unfinished).into_iter();
+        reached
+            .iter()
+            .zip(players)
+            .map(|(game, player)| {
+                if game.is_game_over() {
+                    game_outcome(game, player)
+                } else {
+                    value_outcome(game, values.next().unwrap_or(0.0), player)
+                }
+            })
+            .collect()
+    }
+}
+
+/// Serializable choice of rollout policy, used in [`crate::ai::AiConfig`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RolloutConfig {
+    /// Uniformly random playouts to the end of the game.
+    #[default]
+    Random,
+    /// Greedy playouts over the heuristic evaluation with random moves mixed in.
+    EpsilonGreedy { epsilon: f64 },
+    /// Random playouts cut off after `depth` moves and scored by the heuristic evaluation.
+    Cutoff { depth: u32 },
+}
+
+impl RolloutConfig {
+    /// Builds the configured policy.
+    #[must_use]
+    pub fn build(&self) -> Box<dyn RolloutPolicy> {
+        match self {
+            RolloutConfig::Random => Box::new(RandomRollout),
+            RolloutConfig::EpsilonGreedy { epsilon } => Box::new(EpsilonGreedyRollout {
+                epsilon: *epsilon,
+                evaluator: HeuristicEvaluator::default(),
+            }),
+            RolloutConfig::Cutoff { depth } => Box::new(CutoffRollout {
+                depth: *depth,
+                evaluator: HeuristicEvaluator::default(),
+            }),
         }
     }
 }
 
 pub struct MCTS {
     nodes: Vec<Node>,
+    /// The unpacked position at the root.
+    root_game: Game,
     exploration_constant: f64,
+    selection: SelectionPolicy,
     root_index: usize,
     rng: StdRng,
+    rollout: Box<dyn RolloutPolicy>,
+    /// Whether a leaf gets all its children at once, each played out, rather than one child
+    /// per simulation.
+    expand_all: bool,
+    /// Leaves expanded before their children are played out together.
+    batch_size: u32,
 }
 
 impl MCTS {
+    /// Creates a search of `game`. Its random playouts follow `seed`, or are seeded from the
+    /// OS's entropy if none is given, which on wasm32-unknown-unknown needs the `js` feature.
+    #[must_use]
     pub fn new(game: Game, exploration_constant: f64, seed: Option<u64>) -> Self {
         let rng = if let Some(s) = seed {
             StdRng::seed_from_u64(s)
         } else {
             StdRng::from_entropy()
         };
-        let root_node = Node::new(game, None, None);
+        let root_node = Node::new(&game, None, None);
         MCTS {
             nodes: vec![root_node],
+            root_game: game,
             exploration_constant,
+            selection: SelectionPolicy::Uct,
             root_index: 0,
             rng,
+            rollout: Box::new(RandomRollout),
+            expand_all: false,
+            batch_size: 1,
+        }
+    }
+
+    /// Restricts the search to the root moves in the `moves` bitboard, e.g. to analyse what
+    /// happens after a move the player is considering. Applies to a fresh tree only, and at
+    /// least one of the moves must be legal.
+    #[must_use]
+    pub fn with_root_moves(mut self, moves: u64) -> Self {
+        self.nodes[self.root_index].untried &= moves;
+        self
+    }
+
+    /// Expands every child of a leaf at once and plays them all out in one simulation, as
+    /// the search used to, instead of adding one child per simulation. Kept to compare the
+    /// two; it spends more playouts on shallow moves than the budget says.
+    #[must_use]
+    pub fn with_expand_all(mut self, expand_all: bool) -> Self {
+        self.expand_all = expand_all;
+        self
+    }
+
+    /// Expands up to `batch_size` leaves before playing out their children in one batch,
+    /// which lets evaluators such as neural networks score many positions at once. While a
+    /// leaf waits, its path counts as visited but not won, a virtual loss that steers the
+    /// next selections elsewhere.
+    #[must_use]
+    pub fn with_batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Replaces the rule picking the child to descend into, UCT by default.
+    #[must_use]
+    pub fn with_selection(mut self, selection: SelectionPolicy) -> Self {
+        self.selection = selection;
+        for index in 0..self.nodes.len() {
+            self.set_priors(index);
         }
+        self
+    }
+
+    /// Replaces the policy used to play out positions during simulation.
+    #[must_use]
+    pub fn with_rollout_policy(mut self, rollout: Box<dyn RolloutPolicy>) -> Self {
+        self.rollout = rollout;
+        self
     }
 
     pub fn search(&mut self, iterations: u32, temperature: f64) -> SearchResult {
-        for _ in 0..iterations {
-            let leaf_index = self.select_leaf();
-            let expanded_children = self.expand_node(leaf_index);
-            for child_index in expanded_children {
-                let outcome = self.simulate(child_index);
-                self.backpropagate(child_index, outcome);
+        self.run_simulations(iterations, None);
+        self.result(temperature)
+    }
+
+    /// Like [`MCTS::search`], but checks `cancel` before every simulation and returns `None`
+    /// as soon as it is cancelled. The tree built so far is kept for later searches.
+    pub fn search_cancellable(
+        &mut self,
+        iterations: u32,
+        temperature: f64,
+        cancel: &CancelToken,
+    ) -> Option<SearchResult> {
+        self.run_simulations(iterations, Some(cancel))
+            .then(|| self.result(temperature))
+    }
+
+    /// Runs up to `iterations` simulations, returning false if cancelled before the end. Each
+    /// simulation plays out one new node, or every child of a leaf with
+    /// [`MCTS::with_expand_all`].
+    fn run_simulations(&mut self, iterations: u32, cancel: Option<&CancelToken>) -> bool {
+        let mut remaining = iterations;
+        while remaining > 0 {
+            let mut pending = Vec::new();
+            let mut leaves = 0;
+            while remaining > 0 && leaves < self.batch_size {
+                if cancel.is_some_and(CancelToken::is_cancelled) {
+                    self.evaluate_pending(pending);
+                    return false;
+                }
+                let leaf_index = self.select_leaf();
+                // The leaf is already waiting to be played out; selection will not move on
+                // until the batch has been evaluated.
+                if pending.contains(&leaf_index) {
+                    break;
+                }
+                for child_index in self.expand(leaf_index) {
+                    self.add_virtual_loss(child_index);
+                    pending.push(child_index);
+                }
+                remaining -= 1;
+                leaves += 1;
             }
+            self.evaluate_pending(pending);
         }
+        true
+    }
+
+    /// Plays out the pending nodes in one batch and backpropagates the outcomes.
+    fn evaluate_pending(&mut self, pending: Vec<usize>) {
+        let games = pending.iter().map(|&index| self.nodes[index].game.unpack()).collect();
+        let outcomes = self.rollout.rollout_batch(games, &mut self.rng);
+        for (index, outcome) in pending.into_iter().zip(outcomes) {
+            self.backpropagate(index, outcome);
+        }
+    }
+
+    fn result(&mut self, temperature: f64) -> SearchResult {
         let best_move = self.best_move(temperature);
         let telemetry = self.compute_telemetry();
         SearchResult { best_move, telemetry }
     }
 
+    /// Descends from the root through fully expanded nodes to one with untried moves, a
+    /// position where the side to move must pass and has no pass child yet, or a game over.
     fn select_leaf(&self) -> usize {
         let mut current_index = self.root_index;
-        while !self.nodes[current_index].children.is_empty() {
+        while self.nodes[current_index].untried == 0 && !self.nodes[current_index].children.is_empty() {
             let parent_visits = self.nodes[current_index].visits;
+            let value = |child: usize| {
+                self.nodes[child].selection_value(parent_visits, self.exploration_constant, self.selection)
+            };
             current_index = *self.nodes[current_index]
                 .children
                 .iter()
-                .max_by(|a, b| {
-                    self.nodes[**a]
-                        .uct_value(parent_visits, self.exploration_constant)
-                        .partial_cmp(&self.nodes[**b].uct_value(parent_visits, self.exploration_constant))
-                        .unwrap()
-                })
+                .max_by(|a, b| value(**a).partial_cmp(&value(**b)).unwrap())
                 .unwrap();
         }
         current_index
     }
 
-    fn expand_node(&mut self, node_index: usize) -> Vec<usize> {
-        if self.nodes[node_index].game.is_game_over() {
-            return Vec::new();
+    /// Returns the nodes to play out from a selected leaf: a child for one of its untried
+    /// moves picked at random, every untried child with [`MCTS::with_expand_all`], the pass
+    /// if the side to move has no moves, or the leaf itself if the game is over there.
+    fn expand(&mut self, node_index: usize) -> Vec<usize> {
+        let untried = self.nodes[node_index].untried;
+        if untried == 0 {
+            let mut game = self.nodes[node_index].game.unpack();
+            if !self.nodes[node_index].children.is_empty() || game.is_game_over() {
+                return vec![node_index];
+            }
+            game.pass();
+            let pass_index = self.nodes.len();
+            self.nodes.push(Node::new(&game, Some(node_index), Some(Move::Pass)));
+            self.nodes[node_index].children.push(pass_index);
+            return vec![pass_index];
         }
-
-        let game_clone = self.nodes[node_index].game.clone();
-        let moves = game_clone.legal_moves();
-        let mut new_children = Vec::new();
-        for &mv in &moves {
-            let mut new_game = game_clone.clone();
-            let _ = new_game.make_move(mv);
-            let new_node = Node::new(new_game, Some(node_index), Some(Move::Place(mv)));
+        let moves: Vec<u8> = if self.expand_all {
+            bitboard::iterate_bits(untried).collect()
+        } else {
+            let pick = self.rng.gen_range(0..untried.count_ones());
+            bitboard::iterate_bits(untried).nth(pick as usize).into_iter().collect()
+        };
+        let game = self.nodes[node_index].game.unpack();
+        let mut new_children = Vec::with_capacity(moves.len());
+        for pos in moves {
+            // Passes are left to the side that has to play them, as children of their own.
+            let mut new_game = game.clone();
+            let _ = new_game.place(pos);
             let new_node_index = self.nodes.len();
-            self.nodes.push(new_node);
-            self.nodes[node_index].children.push(new_node_index);
+            self.nodes.push(Node::new(&new_game, Some(node_index), Some(Move::Place(pos))));
+            let node = &mut self.nodes[node_index];
+            node.children.push(new_node_index);
+            node.untried &= !(1u64 << pos);
             new_children.push(new_node_index);
         }
+        if self.nodes[node_index].untried == 0 {
+            self.set_priors(node_index);
+        }
         new_children
     }
 
-    fn simulate(&mut self, node_index: usize) -> f64 {
-        let mut game = self.nodes[node_index].game.clone();
-        while !game.is_game_over() {
-            let moves = game.legal_moves();
-            if moves.is_empty() {
-                game.pass();
-            } else {
-                let mv = moves[self.rng.gen_range(0..moves.len())];
-                let _ = game.make_move(mv);
-            }
+    /// Shares out the priors of the children of a node: evenly, or with
+    /// [`SelectionPolicy::Puct`] by a softmax over their heuristic values for the player
+    /// choosing between them.
+    fn set_priors(&mut self, node_index: usize) {
+        let children = self.nodes[node_index].children.clone();
+        if children.is_empty() {
+            return;
         }
-        let (black, white) = game.disc_count();
-        let current_player = self.nodes[node_index].game.current_player;
-        match current_player {
-            Player::Black => {
-                if black > white {
-                    1.0
-                } else if white > black {
-                    0.0
-                } else {
-                    0.5
-                }
-            }
-            Player::White => {
-                if white > black {
-                    1.0
-                } else if black > white {
-                    0.0
-                } else {
-                    0.5
-                }
-            }
+        let weights: Vec<f64> = if self.selection == SelectionPolicy::Puct {
+            let mover = self.nodes[node_index].game.current_player();
+            let evaluator = HeuristicEvaluator::default();
+            children
+                .iter()
+                .map(|&child| {
+                    let game = self.nodes[child].game.unpack();
+                    let value = evaluator.evaluate(&game);
+                    let value = if game.current_player == mover { value } else { -value };
+                    (PRIOR_SHARPNESS * value).exp()
+                })
+                .collect()
+        } else {
+            vec![1.0; children.len()]
+        };
+        let total: f64 = weights.iter().sum();
+        for (child, weight) in children.into_iter().zip(weights) {
+            self.nodes[child].prior = weight / total;
         }
     }
 
-    fn backpropagate(&mut self, node_index: usize, outcome: f64) {
+    /// Counts a visit of the node and its ancestors before the node is played out, as if it
+    /// were lost; [`MCTS::backpropagate`] adds the outcome once it is known.
+    fn add_virtual_loss(&mut self, node_index: usize) {
         let mut current_index = Some(node_index);
         while let Some(index) = current_index {
             self.nodes[index].visits += 1;
-            self.nodes[index].wins += outcome as u32;
             current_index = self.nodes[index].parent;
         }
     }
 
+    /// Adds the outcome of a played-out node, for its side to move, to the node and its
+    /// ancestors. Each node keeps the outcomes of the player who moved into it, who is the one
+    /// choosing it during selection.
+    fn backpropagate(&mut self, node_index: usize, outcome: f64) {
+        let player = self.nodes[node_index].game.current_player();
+        let mut current_index = Some(node_index);
+        while let Some(index) = current_index {
+            let parent = self.nodes[index].parent;
+            let mover = match parent {
+                Some(parent) => self.nodes[parent].game.current_player(),
+                None => self.nodes[index].game.current_player().opponent(),
+            };
+            let value = if mover == player { outcome } else { 1.0 - outcome };
+            self.nodes[index].wins += value;
+            self.nodes[index].wins_squared += value * value;
+            current_index = parent;
+        }
+    }
+
     fn best_move(&mut self, temperature: f64) -> Move {
         let root = &self.nodes[self.root_index];
         if temperature == 0.0 || root.children.is_empty() {
@@ -180,7 +647,7 @@ impl MCTS {
             self.nodes[*best_child].move_from_parent.unwrap()
         } else {
             // Sample proportionally to visits^(1/temperature)
-            let weights: Vec<f64> = root.children.iter().map(|&c| (self.nodes[c].visits as f64).powf(1.0 / temperature)).collect();
+            let weights: Vec<f64> = root.children.iter().map(|&c| f64::from(self.nodes[c].visits).powf(1.0 / temperature)).collect();
             let total_weight: f64 = weights.iter().sum();
             let mut rand_val = self.rng.gen::<f64>() * total_weight;
             for (i, &weight) in weights.iter().enumerate() {
@@ -196,13 +663,18 @@ impl MCTS {
         }
     }
 
-    /// Advances the root to the child corresponding to the given move.
+    /// Advances the root to the child corresponding to the given move, adding the pass child
+    /// if the side to move must pass and it has not been searched yet.
     /// Returns true if successful, false if no such child exists.
     pub fn advance_root(&mut self, mv: Move) -> bool {
+        if mv == Move::Pass && self.nodes[self.root_index].untried == 0 {
+            self.expand(self.root_index);
+        }
         let root = &self.nodes[self.root_index];
         for &child_index in &root.children {
             if self.nodes[child_index].move_from_parent == Some(mv) {
                 self.root_index = child_index;
+                self.root_game = self.nodes[child_index].game.unpack();
                 return true;
             }
         }
@@ -210,14 +682,52 @@ impl MCTS {
     }
 
     /// Returns a reference to the root game state.
+    #[must_use]
     pub fn root_game(&self) -> &Game {
-        &self.nodes[self.root_index].game
+        &self.root_game
+    }
+
+    /// Returns the statistics of every move searched at the root, most visited first.
+    #[must_use]
+    pub fn root_statistics(&self) -> Vec<MoveStats> {
+        let children = &self.nodes[self.root_index].children;
+        let mut stats: Vec<MoveStats> = children
+            .iter()
+            .filter_map(|&child| {
+                let node = &self.nodes[child];
+                Some(MoveStats {
+                    mv: node.move_from_parent?,
+                    visits: node.visits,
+                    q: if node.visits == 0 {
+                        0.0
+                    } else {
+                        node.wins / f64::from(node.visits)
+                    },
+                    prior: node.prior,
+                    pv: self.principal_variation(child),
+                })
+            })
+            .collect();
+        stats.sort_by_key(|stats| std::cmp::Reverse(stats.visits));
+        stats
+    }
+
+    /// Follows the most visited child from `node_index` down to a leaf, collecting the moves.
+    fn principal_variation(&self, mut node_index: usize) -> Vec<Move> {
+        let mut pv = Vec::new();
+        loop {
+            let node = &self.nodes[node_index];
+            pv.extend(node.move_from_parent);
+            match node.children.iter().max_by_key(|&&child| self.nodes[child].visits) {
+                Some(&child) if self.nodes[child].visits > 0 => node_index = child,
+                _ => return pv,
+            }
+        }
     }
 
     fn compute_telemetry(&self) -> Telemetry {
         let root = &self.nodes[self.root_index];
         let total_simulations = root.visits;
-        let _total_depth = 0u32;
         let mut visit_distribution = Vec::new();
         for &child in &root.children {
             let child_node = &self.nodes[child];
@@ -229,7 +739,7 @@ impl MCTS {
             0.0
         } else {
             let best_child = root.children.iter().max_by_key(|c| self.nodes[**c].visits).unwrap();
-            self.nodes[*best_child].wins as f64 / self.nodes[*best_child].visits as f64
+            self.nodes[*best_child].wins / f64::from(self.nodes[*best_child].visits)
         };
         Telemetry {
             total_simulations,
@@ -239,3 +749,281 @@ impl MCTS {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    fn policies() -> Vec<RolloutConfig> {
+        vec![
+            RolloutConfig::Random,
+            RolloutConfig::EpsilonGreedy { epsilon: 0.1 },
+            RolloutConfig::Cutoff { depth: 4 },
+        ]
+    }
+
+    #[test]
+    fn test_rollout_outcomes_in_range() {
+        let mut rng = StdRng::seed_from_u64(11);
+        for config in policies() {
+            let policy = config.build();
+            for _ in 0..5 {
+                let outcome = policy.rollout(Game::new(), Player::Black, &mut rng);
+                assert!((0.0..=1.0).contains(&outcome), "{config:?} gave {outcome}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_with_each_rollout_policy() {
+        let game = Game::new();
+        for config in policies() {
+            let mut mcts = MCTS::new(game.clone(), 1.414, Some(5)).with_rollout_policy(config.build());
+            let result = mcts.search(10, 0.0);
+            let Move::Place(pos) = result.best_move else {
+                panic!("expected a placement from the opening position");
+            };
+            assert!(game.is_valid_move(pos));
+        }
+    }
+
+    /// Scores every position as even, counting the batches it is asked for.
+    struct CountingEvaluator {
+        batches: Arc<AtomicUsize>,
+        positions: Arc<AtomicUsize>,
+    }
+
+    impl Evaluator for CountingEvaluator {
+        fn evaluate(&self, game: &Game) -> f64 {
+            self.evaluate_batch(std::slice::from_ref(game))[0]
+        }
+
+        fn evaluate_batch(&self, games: &[Game]) -> Vec<f64> {
+            self.batches.fetch_add(1, AtomicOrdering::Relaxed);
+            self.positions.fetch_add(games.len(), AtomicOrdering::Relaxed);
+            vec![0.0; games.len()]
+        }
+    }
+
+    #[test]
+    fn test_leaves_are_evaluated_in_batches() {
+        let (batches, positions) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let evaluator = CountingEvaluator {
+            batches: batches.clone(),
+            positions: positions.clone(),
+        };
+        let mut mcts = MCTS::new(Game::new(), 1.414, Some(5))
+            .with_rollout_policy(Box::new(CutoffRollout { depth: 0, evaluator }))
+            .with_expand_all(true);
+        let result = mcts.search(10, 0.0);
+        // One batch per expansion, holding all the new children.
+        assert_eq!(batches.load(AtomicOrdering::Relaxed), 10);
+        assert_eq!(
+            positions.load(AtomicOrdering::Relaxed),
+            usize::try_from(result.telemetry.total_simulations).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_one_child_is_expanded_per_simulation() {
+        let mut mcts = MCTS::new(Game::new(), 1.414, Some(5));
+        let result = mcts.search(3, 0.0);
+        // The budget is spent exactly, and the root moves are tried before going deeper.
+        assert_eq!(result.telemetry.total_simulations, 3);
+        assert_eq!(mcts.nodes.len(), 4);
+        assert_eq!(mcts.nodes[mcts.root_index].untried.count_ones(), 1);
+        mcts.search(97, 0.0);
+        let root = &mcts.nodes[mcts.root_index];
+        assert_eq!(root.visits, 100);
+        assert_eq!(root.untried, 0);
+        assert_eq!(mcts.nodes.len(), 101);
+
+        let mut all = MCTS::new(Game::new(), 1.414, Some(5)).with_expand_all(true);
+        assert!(all.search(3, 0.0).telemetry.total_simulations > 3);
+
+        // Games that are over are played out where they end.
+        let over = Game::from_bitboards(u64::MAX, 0, Player::White, 0);
+        let mut mcts = MCTS::new(Game::new(), 1.414, Some(5));
+        mcts.nodes[mcts.root_index] = Node::new(&over, None, None);
+        assert!(mcts.run_simulations(5, None));
+        // Black won, and moved into the root.
+        assert_eq!(mcts.nodes[mcts.root_index].visits, 5);
+        assert!((mcts.nodes[mcts.root_index].wins - 5.0).abs() < f64::EPSILON);
+    }
+
+    /// Black to move with A1 taking B1, after which White, left with B2 and H8, must pass.
+    fn pass_position() -> Game {
+        let bits = |coords: &[&str]| {
+            coords
+                .iter()
+                .fold(0u64, |bits, coord| bits | (1u64 << Game::coord_to_pos(coord).unwrap()))
+        };
+        let black = bits(&["A1", "A8", "B8", "C8", "D8", "E8", "F8", "G8"]);
+        Game::from_bitboards(black, bits(&["B1", "B2", "H8"]), Player::Black, 0)
+    }
+
+    #[test]
+    fn test_passes_are_nodes() {
+        let c1 = Move::Place(Game::coord_to_pos("C1").unwrap());
+        let mut mcts = MCTS::new(pass_position(), 1.414, Some(2));
+        mcts.search(50, 0.0);
+        assert!(mcts.nodes.iter().any(|node| node.move_from_parent == Some(Move::Pass)));
+        let restored = MCTS::from_snapshot(&mcts.snapshot(None), 1.414, None).unwrap();
+        assert!(restored.nodes.iter().any(|node| node.move_from_parent == Some(Move::Pass)));
+        assert!(mcts.advance_root(c1));
+        assert_eq!(mcts.root_game().current_player, Player::White);
+        assert_eq!(mcts.search(20, 0.0).best_move, Move::Pass);
+        assert_eq!(mcts.root_statistics().len(), 1);
+        assert!(mcts.advance_root(Move::Pass));
+        assert_eq!((mcts.root_game().current_player, mcts.root_game().passes), (Player::Black, 1));
+        assert!(mcts.nodes[mcts.root_index].visits > 0);
+
+        // A pass is followed even before the position has been searched.
+        let mut game = pass_position();
+        game.place(Game::coord_to_pos("C1").unwrap()).unwrap();
+        let mut mcts = MCTS::new(game, 1.414, Some(2));
+        assert!(!mcts.advance_root(c1));
+        assert!(mcts.advance_root(Move::Pass));
+        assert!(mcts.search(20, 0.0).telemetry.total_simulations >= 20);
+    }
+
+    #[test]
+    fn test_batched_search() {
+        let (batches, positions) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let evaluator = CountingEvaluator {
+            batches: batches.clone(),
+            positions: positions.clone(),
+        };
+        let game = Game::new();
+        let mut mcts = MCTS::new(game.clone(), 1.414, Some(5))
+            .with_rollout_policy(Box::new(CutoffRollout { depth: 0, evaluator }))
+            .with_batch_size(8);
+        let result = mcts.search(40, 0.0);
+        assert!(batches.load(AtomicOrdering::Relaxed) < 40);
+        // Every virtual loss was settled by a played-out position.
+        let root = &mcts.nodes[mcts.root_index];
+        assert_eq!(usize::try_from(root.visits).unwrap(), positions.load(AtomicOrdering::Relaxed));
+        assert_eq!(
+            root.visits,
+            root.children.iter().map(|&child| mcts.nodes[child].visits).sum::<u32>()
+        );
+        let Move::Place(pos) = result.best_move else {
+            panic!("expected a placement from the opening position");
+        };
+        assert!(game.is_valid_move(pos));
+
+        // A batch of one searches exactly like the unbatched search.
+        let mut single = MCTS::new(game.clone(), 1.414, Some(5)).with_batch_size(1);
+        let mut plain = MCTS::new(game, 1.414, Some(5));
+        single.search(30, 0.0);
+        plain.search(30, 0.0);
+        assert_eq!(single.root_statistics(), plain.root_statistics());
+    }
+
+    #[test]
+    fn test_cancelled_search_stops() {
+        let mut mcts = MCTS::new(Game::new(), 1.414, Some(5));
+        let cancel = CancelToken::new();
+        cancel.clone().cancel();
+        assert!(mcts.search_cancellable(1_000_000, 0.0, &cancel).is_none());
+        // The interrupted tree can still be searched to completion.
+        assert!(mcts.search_cancellable(10, 0.0, &CancelToken::new()).is_some());
+
+        let parent = CancelToken::new();
+        let child = parent.child();
+        let grandchild = child.child();
+        child.cancel();
+        assert!(grandchild.is_cancelled() && !parent.is_cancelled());
+        let sibling = parent.child();
+        parent.cancel();
+        assert!(sibling.is_cancelled());
+    }
+
+    #[test]
+    fn test_selection_values() {
+        let mut node = Node::new(&Game::new(), Some(0), None);
+        assert!(node.selection_value(10, 1.0, SelectionPolicy::Uct).is_infinite());
+        node.visits = 4;
+        node.wins = 3.0;
+        node.wins_squared = 3.0;
+        node.prior = 0.5;
+        let ln = 100f64.ln();
+        let uct = node.selection_value(100, 2.0, SelectionPolicy::Uct);
+        assert!((uct - (0.75 + 2.0 * (ln / 4.0).sqrt())).abs() < 1e-12);
+        let puct = node.selection_value(100, 2.0, SelectionPolicy::Puct);
+        assert!((puct - (0.75 + 2.0 * 0.5 * 10.0 / 5.0)).abs() < 1e-12);
+        // The variance bound exceeds 1/4, so UCB1-Tuned explores like UCT with half the
+        // constant.
+        let tuned = node.selection_value(100, 2.0, SelectionPolicy::Ucb1Tuned);
+        assert!((tuned - (0.75 + (ln / 4.0).sqrt())).abs() < 1e-12);
+        assert_eq!("ucb1_tuned".parse(), Ok(SelectionPolicy::Ucb1Tuned));
+        assert!("ucb2".parse::<SelectionPolicy>().is_err());
+    }
+
+    /// Returns the final disc margin of the move for the player making it, with perfect play.
+    fn solved_margin(game: &Game, pos: u8) -> i32 {
+        let mut next = game.clone();
+        next.make_move(pos).unwrap();
+        let score = crate::solver::solve(&next).score;
+        if next.current_player == game.current_player { score } else { -score }
+    }
+
+    #[test]
+    fn test_search_finds_winning_endgame_moves() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut checked = 0;
+        while checked < 3 {
+            let mut game = Game::new();
+            while game.occupied().count_ones() < 56 && !game.is_game_over() {
+                let moves = game.legal_moves();
+                if moves.is_empty() {
+                    game.pass();
+                } else {
+                    game.make_move(moves[rng.gen_range(0..moves.len())]).unwrap();
+                }
+            }
+            let margins: Vec<(u8, i32)> = game.legal_moves_iter().map(|pos| (pos, solved_margin(&game, pos))).collect();
+            // Only positions where a move wins and another loses tell the sides apart.
+            if !(margins.iter().any(|m| m.1 > 0) && margins.iter().any(|m| m.1 < 0)) {
+                continue;
+            }
+            let mut mcts = MCTS::new(game.clone(), 1.414, Some(checked));
+            let Move::Place(pos) = mcts.search(1000, 0.0).best_move else {
+                panic!("expected a placement");
+            };
+            assert!(solved_margin(&game, pos) > 0, "{game:?} lost with {}", Game::pos_to_coord(pos));
+            checked += 1;
+        }
+    }
+
+    #[test]
+    fn test_puct_priors_follow_the_evaluation() {
+        // Black can take the A1 corner or play E5 in the centre.
+        let board = format!("{}-O------{}{}", "-".repeat(8), "--*-----", "---O----");
+        let game = Game::from_position_string(&format!("{board:-<64} X")).unwrap();
+        let mut mcts = MCTS::new(game, 1.414, Some(1)).with_selection(SelectionPolicy::Puct);
+        mcts.search(5, 0.0);
+        let stats = mcts.root_statistics();
+        assert_eq!(stats.len(), 2);
+        assert!((stats.iter().map(|s| s.prior).sum::<f64>() - 1.0).abs() < 1e-9);
+        let corner = stats.iter().find(|s| s.mv == "A1".parse().unwrap()).unwrap();
+        assert!(corner.prior > 0.5);
+    }
+
+    #[test]
+    fn test_root_statistics() {
+        let mut mcts = MCTS::new(Game::new(), 1.414, Some(9));
+        let result = mcts.search(40, 0.0);
+        let stats = mcts.root_statistics();
+        assert_eq!(stats.len(), 4);
+        assert_eq!(stats[0].mv, result.best_move);
+        assert_eq!(stats.iter().map(|s| s.visits).sum::<u32>(), result.telemetry.total_simulations);
+        for s in &stats {
+            assert_eq!(s.pv[0], s.mv);
+            assert!((0.0..=1.0).contains(&s.q));
+            assert!((s.prior - 0.25).abs() < f64::EPSILON);
+        }
+        assert!(stats.windows(2).all(|pair| pair[0].visits >= pair[1].visits));
+    }
+}
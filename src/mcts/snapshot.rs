@@ -0,0 +1,323 @@
+//! Saving search trees to disk.
+//!
+//! A [`TreeSnapshot`] holds the statistics of a search tree, optionally trimmed to the most
+//! visited moves at every level, so a long analysis can be resumed later and trees from many
+//! runs over the same position can be merged into one, e.g. to build an opening book.
+//! Positions are not stored; they are replayed from the root when the tree is rebuilt.
+
+use super::{Node, MCTS};
+use crate::game::{Game, Move};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Version of the snapshot file format. Version 2 keeps the outcomes of each node for the
+/// player who moved into it, and their squares; version 3 has passes as nodes of their own.
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+/// The statistics of a search tree.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TreeSnapshot {
+    pub version: u32,
+    /// The root position, as written by [`Game::to_position_string`].
+    pub position: String,
+    /// The root first, then every node after its parent.
+    pub nodes: Vec<SnapshotNode>,
+}
+
+/// One node of a [`TreeSnapshot`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotNode {
+    /// Index of the parent node, `None` for the root.
+    pub parent: Option<usize>,
+    /// The move leading to the node from its parent, e.g. `"D3"`.
+    pub mv: Option<String>,
+    pub visits: u32,
+    /// Sum of the outcomes through the node, for the player who moved into it.
+    pub wins: f64,
+    pub wins_squared: f64,
+}
+
+impl TreeSnapshot {
+    /// Loads a snapshot from disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, is not a valid snapshot, or was written
+    /// by an unsupported format version.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let snapshot: TreeSnapshot = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported snapshot version {}", snapshot.version),
+            ));
+        }
+        Ok(snapshot)
+    }
+
+    /// Writes the snapshot to disk, replacing any previous file atomically.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(tmp, path)
+    }
+
+    /// Returns the root position.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored position is malformed.
+    pub fn root_game(&self) -> Result<Game, String> {
+        Game::from_position_string(&self.position)
+    }
+
+    /// Adds the statistics of `other`, a tree searched from the same position, to this one.
+    /// Nodes reached by the same moves are summed and the others are copied over.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the trees have different roots or `other` is malformed.
+    pub fn merge(&mut self, other: &TreeSnapshot) -> Result<(), String> {
+        if self.root_game()? != other.root_game()? {
+            return Err("Cannot merge trees searched from different positions".to_string());
+        }
+        let mut index_of: HashMap<(usize, &str), usize> = HashMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if let (Some(parent), Some(mv)) = (node.parent, &node.mv) {
+                index_of.insert((parent, mv), index);
+            }
+        }
+        let mut merged = self.clone();
+        // Where each node of `other` ended up in the merged tree.
+        let mut mapped = Vec::with_capacity(other.nodes.len());
+        for (index, node) in other.nodes.iter().enumerate() {
+            let target = match (node.parent, &node.mv) {
+                (None, _) if index == 0 => 0,
+                (Some(parent), Some(mv)) if parent < index => {
+                    let parent = mapped[parent];
+                    if let Some(&existing) = index_of.get(&(parent, mv.as_str())) {
+                        existing
+                    } else {
+                        merged.nodes.push(SnapshotNode {
+                            parent: Some(parent),
+                            mv: Some(mv.clone()),
+                            visits: 0,
+                            wins: 0.0,
+                            wins_squared: 0.0,
+                        });
+                        merged.nodes.len() - 1
+                    }
+                }
+                _ => return Err(format!("Malformed snapshot node {index}")),
+            };
+            let Some(target_node) = merged.nodes.get_mut(target) else {
+                return Err("Snapshot has no root".to_string());
+            };
+            target_node.visits = target_node.visits.saturating_add(node.visits);
+            target_node.wins += node.wins;
+            target_node.wins_squared += node.wins_squared;
+            mapped.push(target);
+        }
+        *self = merged;
+        Ok(())
+    }
+}
+
+impl MCTS {
+    /// Returns the statistics of the tree below the current root. With `top_k`, only the
+    /// most visited moves at every level are kept; unvisited nodes are always left out.
+    #[must_use]
+    pub fn snapshot(&self, top_k: Option<usize>) -> TreeSnapshot {
+        let root = &self.nodes[self.root_index];
+        let mut nodes = vec![SnapshotNode {
+            parent: None,
+            mv: None,
+            visits: root.visits,
+            wins: root.wins,
+            wins_squared: root.wins_squared,
+        }];
+        // Pairs of a node in the tree and its index in the snapshot, parents first.
+        let mut pending = vec![(self.root_index, 0)];
+        while let Some((index, snapshot_index)) = pending.pop() {
+            let mut children: Vec<usize> = self.nodes[index]
+                .children
+                .iter()
+                .copied()
+                .filter(|&child| self.nodes[child].visits > 0)
+                .collect();
+            children.sort_by_key(|&child| std::cmp::Reverse(self.nodes[child].visits));
+            children.truncate(top_k.unwrap_or(usize::MAX));
+            for child in children {
+                let node = &self.nodes[child];
+                nodes.push(SnapshotNode {
+                    parent: Some(snapshot_index),
+                    mv: node.move_from_parent.map(|mv| mv.to_string()),
+                    visits: node.visits,
+                    wins: node.wins,
+                    wins_squared: node.wins_squared,
+                });
+                pending.push((child, nodes.len() - 1));
+            }
+        }
+        TreeSnapshot {
+            version: SNAPSHOT_VERSION,
+            position: self.root_game.to_position_string(),
+            nodes,
+        }
+    }
+
+    /// Rebuilds a tree from a snapshot, to carry on searching it. Moves trimmed from the
+    /// snapshot come back unvisited, so the search can still explore them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot is malformed or contains an illegal move.
+    pub fn from_snapshot(snapshot: &TreeSnapshot, exploration_constant: f64, seed: Option<u64>) -> Result<Self, String> {
+        let mut mcts = MCTS::new(snapshot.root_game()?, exploration_constant, seed);
+        let Some(root) = snapshot.nodes.first().filter(|root| root.parent.is_none()) else {
+            return Err("Snapshot has no root".to_string());
+        };
+        mcts.nodes[0].visits = root.visits;
+        mcts.nodes[0].wins = root.wins;
+        mcts.nodes[0].wins_squared = root.wins_squared;
+        let mut games = vec![mcts.root_game.clone()];
+        for (index, node) in snapshot.nodes.iter().enumerate().skip(1) {
+            let (Some(parent), Some(mv)) = (node.parent.filter(|&parent| parent < index), &node.mv) else {
+                return Err(format!("Malformed snapshot node {index}"));
+            };
+            let mv: Move = mv.parse()?;
+            let mut game = games[parent].clone();
+            match mv {
+                Move::Place(pos) if game.is_valid_move(pos) => {
+                    let _ = game.place(pos);
+                }
+                Move::Pass if game.legal_move_bitboard() == 0 && !game.is_game_over() => game.pass(),
+                _ => return Err(format!("Illegal move {mv} in snapshot node {index}")),
+            }
+            let mut child = Node::new(&game, Some(parent), Some(mv));
+            child.visits = node.visits;
+            child.wins = node.wins;
+            child.wins_squared = node.wins_squared;
+            mcts.nodes.push(child);
+            mcts.nodes[parent].children.push(index);
+            games.push(game);
+        }
+        // Expanded nodes must list every legal move, or the search never tries the others.
+        for (index, game) in games.iter().enumerate() {
+            if mcts.nodes[index].children.is_empty() {
+                continue;
+            }
+            for pos in game.legal_moves_iter() {
+                let mv = Move::Place(pos);
+                let known = mcts.nodes[index]
+                    .children
+                    .iter()
+                    .any(|&child| mcts.nodes[child].move_from_parent == Some(mv));
+                if !known {
+                    let mut next = game.clone();
+                    let _ = next.place(pos);
+                    let child = mcts.nodes.len();
+                    mcts.nodes.push(Node::new(&next, Some(index), Some(mv)));
+                    mcts.nodes[index].children.push(child);
+                }
+            }
+            mcts.nodes[index].untried = 0;
+            mcts.set_priors(index);
+        }
+        Ok(mcts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut mcts = MCTS::new(Game::new(), 1.414, Some(3));
+        mcts.search(200, 0.0);
+        let snapshot = mcts.snapshot(None);
+        assert_eq!(snapshot.nodes[0].visits, mcts.nodes[mcts.root_index].visits);
+        let restored = MCTS::from_snapshot(&snapshot, 1.414, Some(3)).unwrap();
+        let by_move = |mcts: &MCTS| -> HashMap<String, (u32, f64)> {
+            mcts.root_statistics()
+                .into_iter()
+                .map(|stats| (stats.mv.to_string(), (stats.visits, stats.q)))
+                .collect()
+        };
+        assert_eq!(by_move(&restored), by_move(&mcts));
+        assert_eq!(restored.snapshot(None).nodes.len(), snapshot.nodes.len());
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(serde_json::from_str::<TreeSnapshot>(&json).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_trimmed_snapshot_can_be_searched() {
+        let mut mcts = MCTS::new(Game::new(), 1.414, Some(4));
+        mcts.search(200, 0.0);
+        let snapshot = mcts.snapshot(Some(1));
+        assert!(snapshot.nodes[1..].iter().all(|node| node.visits > 0));
+        // One line of moves, each node the only child of the previous one.
+        assert!(snapshot.nodes.iter().skip(1).enumerate().all(|(i, node)| node.parent == Some(i)));
+
+        let mut restored = MCTS::from_snapshot(&snapshot, 1.414, Some(4)).unwrap();
+        assert_eq!(restored.root_statistics().len(), 4);
+        restored.search(50, 0.0);
+        assert!(restored.snapshot(None).nodes[0].visits > snapshot.nodes[0].visits);
+    }
+
+    #[test]
+    fn test_merge_sums_shared_moves() {
+        let snapshot = |seed| {
+            let mut mcts = MCTS::new(Game::new(), 1.414, Some(seed));
+            mcts.search(100, 0.0);
+            mcts.snapshot(Some(2))
+        };
+        let (mut merged, other) = (snapshot(1), snapshot(2));
+        let first = merged.clone();
+        merged.merge(&other).unwrap();
+        assert_eq!(merged.nodes[0].visits, first.nodes[0].visits + other.nodes[0].visits);
+        let root_moves = |tree: &TreeSnapshot| -> HashMap<String, u32> {
+            tree.nodes
+                .iter()
+                .filter(|node| node.parent == Some(0))
+                .map(|node| (node.mv.clone().unwrap(), node.visits))
+                .collect()
+        };
+        let (a, b, sum) = (root_moves(&first), root_moves(&other), root_moves(&merged));
+        for (mv, visits) in &sum {
+            assert_eq!(*visits, a.get(mv).unwrap_or(&0) + b.get(mv).unwrap_or(&0));
+        }
+        assert!(MCTS::from_snapshot(&merged, 1.414, None).is_ok());
+
+        let mut game = Game::new();
+        game.make_move(game.legal_moves()[0]).unwrap();
+        let elsewhere = MCTS::new(game, 1.414, Some(1)).snapshot(None);
+        assert!(merged.merge(&elsewhere).is_err());
+    }
+
+    #[test]
+    fn test_malformed_snapshots_are_rejected() {
+        let mut snapshot = MCTS::new(Game::new(), 1.414, Some(1)).snapshot(None);
+        snapshot.nodes.push(SnapshotNode {
+            parent: Some(0),
+            mv: Some("A1".to_string()),
+            visits: 1,
+            wins: 1.0,
+            wins_squared: 1.0,
+        });
+        assert!(MCTS::from_snapshot(&snapshot, 1.414, None).is_err());
+        snapshot.nodes[1].parent = Some(5);
+        assert!(MCTS::from_snapshot(&snapshot, 1.414, None).is_err());
+        snapshot.nodes.clear();
+        assert!(MCTS::from_snapshot(&snapshot, 1.414, None).is_err());
+    }
+}
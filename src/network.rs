@@ -1,20 +1,55 @@
-use crate::ai::AI;
+use crate::admin::{sanitize_body, LoggedError, RequestLog, ERROR_LOG_CAPACITY};
+use crate::ai::MctsAi;
+use crate::analysis::{self, AnalysisConfig, AnalysisReport};
+use crate::anticheat::CheatReport;
+use crate::api::{
+    AdminDashboardResponse, AdminGameResponse, AiGamesResponse, AnnotationRequest, AnnotationResponse,
+    BatchAnalysisRequest, BatchAnalysisResponse, BoardInviteRequest, BoardStateResponse, BroadcastRequest,
+    BroadcastResponse, ClaimResponse, EnginesResponse, FlippedLine, GameStateResponse, GameType, ImportResponse, Job,
+    JobStatus, JoinResponse, LoginRequest, LoginResponse, MaintenanceRequest, MaintenanceResponse, MatchupResponse,
+    MoveCoord, MoveExplanationResponse, MoveRequest, MoveResponse, NewBoardRequest, NewMatchRequest, NewMatchResponse,
+    PlayerSettings, PlayerStats, PresenceResponse, PuzzleAttemptResponse, PuzzleResponse, RayExplanationResponse,
+    ReplayMoveResponse, ReplayResponse, RequestLogRequest, RequestLogResponse, RetentionRequest, RetentionResponse,
+    ServerStats, Task, TicketResponse,
+};
+#[cfg(feature = "oauth")]
+use crate::api::OAuthProvidersResponse;
 use crate::auth::Auth;
-use crate::game::{Game, Move};
-use crate::state::Sessions;
-use crate::storage::PlayerStats;
-use axum::extract::ws::{WebSocket, WebSocketUpgrade};
+use crate::boards::{AnalysisBoard, BoardError};
+use crate::eval::{self, HeuristicEvaluator};
+use crate::game::{flip_lines, Game, GameReplay, IllegalMove, Move, MoveExplanation, Player, RayOutcome, ScoringRule};
+use crate::import::{self, MAX_GAME_FILE_LEN};
+use crate::mcts::CancelToken;
+#[cfg(feature = "oauth")]
+use crate::oauth;
+use crate::protocol::{
+    parse_board_message, parse_client_message, BoardClientMsg, BoardServerMsg, ClientMsg, GameEvent, MessageCatalog,
+    MessageCode, MoveRejection, Notice, RejectionReason, ServerMsg, MAX_CHAT_LEN,
+};
+use crate::scheduler::Timed;
+use crate::state::{is_builtin_ai, Abandonment, SessionError, Sessions, AI_PLAYER};
+use crate::storage::{Annotation, GameOptions, PlayerPreferences, RatingConfig, StarredGame, Storage};
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade};
+#[cfg(feature = "oauth")]
+use axum::response::Redirect;
 use axum::{
     async_trait,
-    extract::{FromRequestParts, Path, State},
-    http::{header, request::Parts, StatusCode},
-    response::Json,
-    routing::{get, post},
+    body::{self, Body},
+    extract::{rejection::JsonRejection, FromRequestParts, MatchedPath, Path, Query, Request, State},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post, put},
     Router,
 };
+use parking_lot::{Mutex, MutexGuard};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tower_http::catch_panic::CatchPanicLayer;
 
 #[derive(Debug)]
 pub struct AuthenticatedPlayer(pub String);
@@ -27,100 +62,565 @@ where
     type Rejection = StatusCode;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let auth_header = parts
-            .headers
-            .get(header::AUTHORIZATION)
-            .and_then(|h| h.to_str().ok())
-            .and_then(|h| h.strip_prefix("Bearer "));
-
-        if let Some(token) = auth_header {
-            match Auth::validate_token(token) {
-                Ok(claims) => Ok(AuthenticatedPlayer(claims.sub)),
-                Err(_) => Err(StatusCode::UNAUTHORIZED),
-            }
-        } else {
-            Err(StatusCode::UNAUTHORIZED)
-        }
+        bearer_player(&parts.headers)
+            .map(AuthenticatedPlayer)
+            .ok_or(StatusCode::UNAUTHORIZED)
     }
 }
 
+/// Who asks for a game, besides a bearer token.
 #[derive(Deserialize)]
-struct LoginRequest {
-    player: String,
+struct AccessQuery {
+    /// The token a socket is opened with.
+    token: Option<String>,
+    /// The ticket a socket is opened with, spent by the socket handler.
+    ticket: Option<String>,
+    /// An invite to a private game.
+    invite: Option<String>,
+}
+
+/// Answers 404 for a private game unless the request comes from one of its players or an
+/// admin, by bearer token or socket token, or carries an invite to it. The game is passed on
+/// as is when it does not exist, for the handler to answer.
+async fn guard_private_games(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    matched: MatchedPath,
+    params: Option<Path<HashMap<String, String>>>,
+    Query(query): Query<AccessQuery>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let id = match params.as_ref().and_then(|Path(params)| params.get("id")) {
+        Some(id) if matched.as_str().starts_with("/match/:id") => id,
+        _ => return next.run(request).await,
+    };
+    if query.invite.is_some_and(|invite| Auth::validate_invite(&invite, id)) {
+        return next.run(request).await;
+    }
+    let name = bearer_player(request.headers())
+        .or_else(|| {
+            query
+                .token
+                .and_then(|token| Auth::validate_token(&token).ok())
+                .map(|claims| claims.sub)
+        })
+        // Tickets only open sockets, which spend them; they authenticate no other request.
+        .or_else(|| {
+            query
+                .ticket
+                .filter(|_| matched.as_str() == "/match/:id/ws")
+                .and_then(|ticket| Auth::validate_ticket(&ticket).ok())
+                .map(|claims| claims.holder)
+        });
+    let hidden = {
+        let mut sessions = sessions.lock();
+        sessions.load_game(id).is_ok()
+            && sessions.is_private(id)
+            && !name.is_some_and(|name| sessions.may_see(id, &name))
+    };
+    if hidden {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    next.run(request).await
+}
+
+/// Returns the player named by a valid bearer token in the headers.
+fn bearer_player(headers: &HeaderMap) -> Option<String> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))?;
+    Auth::validate_token(token).ok().map(|claims| claims.sub)
 }
 
-#[derive(Serialize)]
-struct LoginResponse {
-    token: String,
+#[derive(Deserialize, Default)]
+struct ReplayQuery {
+    /// Ply to show the board at; the latest position if omitted.
+    ply: Option<usize>,
 }
 
+/// Who opened a socket, for resigning and chatting.
 #[derive(Deserialize)]
-struct NewMatchRequest {
-    player2: String,
+struct SocketQuery {
+    token: Option<String>,
+    /// A ticket from `/auth/ws-ticket`, which browsers pass instead of their token.
+    ticket: Option<String>,
 }
 
-#[derive(Serialize)]
-struct NewMatchResponse {
-    id: String,
+#[derive(Deserialize)]
+struct JobsQuery {
+    status: Option<String>,
+    limit: Option<u32>,
+}
+
+/// Jobs listed when no limit is given, and the most that can be asked for.
+const DEFAULT_JOBS: u32 = 50;
+const MAX_JOBS: u32 = 200;
+
+#[derive(Deserialize)]
+struct CheatReportsQuery {
+    player: Option<String>,
+    limit: Option<u32>,
+}
+
+/// Cheat reports listed when no limit is given, and the most that can be asked for.
+const DEFAULT_CHEAT_REPORTS: u32 = 50;
+const MAX_CHEAT_REPORTS: u32 = 200;
+
+#[derive(Deserialize)]
+struct AiGamesQuery {
+    engine_version: Option<String>,
+    limit: Option<u32>,
 }
 
+/// AI games listed when no limit is given, and the most that can be asked for.
+const DEFAULT_AI_GAMES: u32 = 50;
+const MAX_AI_GAMES: u32 = 200;
+
 #[derive(Deserialize)]
-struct MoveRequest {
-    coord: String,
+struct ErrorsQuery {
+    limit: Option<usize>,
 }
 
-#[derive(Serialize)]
-struct GameStateResponse {
-    board: Vec<Vec<String>>,
-    current_player: String,
-    legal_moves: Vec<String>,
-    game_over: bool,
-    winner: Option<String>,
+/// Logged errors listed when no limit is given.
+const DEFAULT_ERRORS: usize = 50;
+
+/// Latest runs of the retention policy listed by the retention endpoint.
+const RETENTION_RUNS: u32 = 20;
+
+/// Most positions a batch analysis may score, and the most plies and empty squares it may
+/// search and solve them with.
+const MAX_BATCH_POSITIONS: usize = 1000;
+const MAX_BATCH_DEPTH: u32 = 4;
+const MAX_BATCH_EXACT_EMPTIES: u32 = 14;
+
+/// Largest request body accepted by most endpoints.
+const MAX_REQUEST_BODY: usize = 16 * 1024;
+/// Largest batch analysis request, with room for [`MAX_BATCH_POSITIONS`] positions.
+const MAX_BATCH_BODY: usize = 128 * 1024;
+/// Media types a game file may be imported as, besides any `text/*` type.
+const GAME_FILE_TYPES: [&str; 2] = ["application/octet-stream", "application/x-go-sgf"];
+
+/// How often a server under maintenance checks whether it is drained.
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+struct MatchupQuery {
     player1: String,
     player2: String,
-    scores: HashMap<String, u32>,
 }
 
+/// Number of latest games shown as each player's form in a matchup.
+const FORM_GAMES: u32 = 5;
+
 #[derive(Deserialize)]
-struct JoinRequest {
-    player: String,
+struct LeaderboardQuery {
+    /// Whether the AI and engines are listed; they are if omitted.
+    bots: Option<bool>,
 }
 
-#[derive(Serialize)]
-struct JoinResponse {
-    matched: bool,
-    id: Option<String>,
+/// The ply a move is sent for, see [`Sessions::apply_move`].
+#[derive(Deserialize)]
+struct PlyQuery {
+    ply: Option<usize>,
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+struct StateQuery {
+    #[serde(default)]
+    explain: bool,
+    #[serde(default)]
+    hints: bool,
 }
 
 pub fn create_router(sessions: Arc<Mutex<Sessions>>) -> Router {
-    Router::new()
+    let request_log = sessions.lock().request_log().clone();
+    #[cfg(feature = "chaos")]
+    let faults = sessions.lock().faults().clone();
+    let router = Router::new()
         .route("/auth/login", post(login))
+        .route("/auth/ws-ticket", post(issue_ticket))
         .route("/match/new", post(create_match))
         .route("/match/join", post(join_matchmaking))
+        .route("/match/:id", delete(delete_match))
         .route("/match/:id/move", post(make_move))
+        // Only PUT plays here: a GET, e.g. from a link preview, is refused with 405.
+        .route("/match/:id/move/:coord", put(put_move))
+        .route("/match/:id/claim-win", post(claim_win))
+        .route("/match/:id/claim-draw", post(claim_draw))
         .route("/match/:id/state", get(get_state))
+        .route("/match/:id/replay", get(get_replay))
+        .route("/match/:id/annotations", post(annotate))
+        .route("/match/:id/metadata", get(get_metadata).patch(update_metadata))
+        .route("/match/:id/analyze-full", post(analyze_full))
+        .route("/match/:id/report", get(get_report))
+        .route("/match/:id/star", post(star_match).delete(unstar_match))
+        // Only the match endpoints above are logged while debugging requests.
+        .route_layer(middleware::from_fn_with_state(request_log, log_request))
+        .route("/analyze/batch", post(analyze_batch))
+        .route("/import", post(import_game))
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/:id", get(get_job))
+        .route("/admin/cheat-reports", get(list_cheat_reports))
+        .route("/admin/ai-games", get(list_ai_games))
+        .route("/admin/dashboard", get(get_dashboard))
+        .route("/admin/errors", get(list_errors))
+        .route("/admin/broadcast", post(broadcast))
+        .route("/admin/maintenance", get(get_maintenance).post(set_maintenance))
+        .route("/admin/request-log", get(get_request_log).post(set_request_log))
+        .route("/admin/retention", get(get_retention).post(run_retention))
         .route("/match/:id/ws", get(ws_handler))
+        .route_layer(middleware::from_fn_with_state(sessions.clone(), guard_private_games))
+        .route("/messages", get(get_messages))
+        .route("/engines", get(get_engines))
         .route("/leaderboard", get(get_leaderboard))
-        .with_state(sessions)
+        .route("/stats/server", get(get_server_stats))
+        .route("/matchup", get(get_matchup))
+        .route("/player/:name", get(get_player))
+        .route("/me/settings", get(get_settings).put(put_settings))
+        .route("/me/preferences", get(get_preferences).put(put_preferences))
+        .route("/me/starred", get(list_starred))
+        .route("/boards", get(list_boards).post(create_board))
+        .route("/boards/:id", get(get_board).delete(close_board))
+        .route("/boards/:id/invite", post(invite_to_board))
+        .route("/boards/:id/ws", get(board_ws_handler))
+        .route("/puzzle/daily", get(get_daily_puzzle))
+        .route("/puzzle/:id/attempt", post(attempt_puzzle));
+    #[cfg(feature = "oauth")]
+    let router = router
+        .route("/auth/oauth", get(list_oauth_providers))
+        .route("/auth/oauth/:provider", get(start_oauth_login))
+        .route("/auth/oauth/:provider/callback", get(finish_oauth_login));
+    let router = router
+        // Every body is checked before any other layer or handler reads it.
+        .route_layer(middleware::from_fn(check_request_body))
+        .with_state(sessions);
+    #[cfg(feature = "chaos")]
+    let router = router.layer(middleware::from_fn_with_state(faults, crate::chaos::delay_response));
+    // A panicking handler answers 500 instead of dropping the connection.
+    router.layer(CatchPanicLayer::new())
+}
+
+/// Returns the largest body a request to `route` may have.
+fn body_limit(route: &str) -> usize {
+    match route {
+        "/import" => MAX_GAME_FILE_LEN,
+        "/analyze/batch" => MAX_BATCH_BODY,
+        _ => MAX_REQUEST_BODY,
+    }
+}
+
+/// Returns true if a body sent to `route` may be of `media_type`, lower-cased and without
+/// parameters: JSON everywhere but for game files.
+fn accepts_media_type(route: &str, media_type: &str) -> bool {
+    if route == "/import" {
+        return media_type.starts_with("text/") || GAME_FILE_TYPES.contains(&media_type);
+    }
+    media_type == "application/json" || media_type.ends_with("+json")
+}
+
+/// Rejects request bodies larger than their endpoint accepts or of a type it does not read,
+/// and turns the plain-text rejections of the extractors, e.g. for a JSON body with an
+/// unknown field, into a [`Notice`] with the reason. Bodies are read in full here, so one
+/// sent in chunks is held to the same limit as one with a length.
+async fn check_request_body(route: MatchedPath, request: Request, next: Next) -> Response {
+    if !matches!(request.method().as_str(), "POST" | "PUT" | "PATCH") {
+        return next.run(request).await;
+    }
+    let limit = body_limit(route.as_str());
+    let too_large = || {
+        let notice = Notice::new(MessageCode::BodyTooLarge).with("max", limit.to_string());
+        (StatusCode::PAYLOAD_TOO_LARGE, Json(notice)).into_response()
+    };
+    let (parts, body) = request.into_parts();
+    let declared = parts
+        .headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<usize>().ok());
+    if declared.is_some_and(|length| length > limit) {
+        return too_large();
+    }
+    let Ok(bytes) = body::to_bytes(body, limit).await else {
+        return too_large();
+    };
+    if !bytes.is_empty() {
+        let media_type = parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.split(';').next().unwrap_or_default().trim().to_ascii_lowercase());
+        if !media_type.is_some_and(|media_type| accepts_media_type(route.as_str(), &media_type)) {
+            let expected = if route.as_str() == "/import" { "text/plain" } else { "application/json" };
+            let notice = Notice::new(MessageCode::UnsupportedMediaType).with("expected", expected);
+            return (StatusCode::UNSUPPORTED_MEDIA_TYPE, Json(notice)).into_response();
+        }
+    }
+    let response = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+    structured_rejection(response).await
+}
+
+/// Turns the plain-text body of a request rejected by an extractor into a [`Notice`], leaving
+/// any other response as it is.
+async fn structured_rejection(response: Response) -> Response {
+    let rejected = matches!(
+        response.status(),
+        StatusCode::BAD_REQUEST
+            | StatusCode::PAYLOAD_TOO_LARGE
+            | StatusCode::UNSUPPORTED_MEDIA_TYPE
+            | StatusCode::UNPROCESSABLE_ENTITY
+    );
+    let plain_text = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/plain"));
+    if !rejected || !plain_text {
+        return response;
+    }
+    let status = response.status();
+    let Ok(reason) = body::to_bytes(response.into_body(), MAX_REQUEST_BODY).await else {
+        return status.into_response();
+    };
+    let notice = Notice::new(MessageCode::InvalidRequest).with("reason", String::from_utf8_lossy(&reason));
+    (status, Json(notice)).into_response()
+}
+
+/// Logs a request with its sanitized body, the player making it, and the status and latency
+/// of the response, while the request log is switched on.
+async fn log_request(State(request_log): State<RequestLog>, request: Request, next: Next) -> Response {
+    if !request_log.is_enabled() {
+        return next.run(request).await;
+    }
+    let started = Instant::now();
+    let (parts, body) = request.into_parts();
+    let Ok(bytes) = body::to_bytes(body, MAX_REQUEST_BODY).await else {
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    };
+    let player = bearer_player(&parts.headers);
+    let method = parts.method.clone();
+    let path = parts.uri.path().to_string();
+    let request_body = sanitize_body(&bytes);
+    let response = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+    tracing::info!(
+        target: "kawio::requests",
+        %method,
+        path,
+        player = player.as_deref().unwrap_or("-"),
+        status = response.status().as_u16(),
+        latency_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+        body = request_body,
+        "Request"
+    );
+    response
 }
 
-async fn login(Json(req): Json<LoginRequest>) -> Result<Json<LoginResponse>, StatusCode> {
+async fn login(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Json(req): Json<LoginRequest>,
+) -> Result<Json<LoginResponse>, StatusCode> {
+    // Names linked to an identity at a provider are only logged in as through it.
+    let linked = sessions
+        .lock()
+        .storage
+        .is_linked(&req.player)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if linked {
+        return Err(StatusCode::FORBIDDEN);
+    }
     match Auth::generate_token(&req.player) {
         Ok(token) => Ok(Json(LoginResponse { token })),
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
 }
 
+async fn issue_ticket(AuthenticatedPlayer(player): AuthenticatedPlayer) -> Result<Json<TicketResponse>, StatusCode> {
+    let ticket = Auth::generate_ticket(&player).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(TicketResponse {
+        ticket,
+        expires_in: Auth::TICKET_LIFETIME,
+    }))
+}
+
+#[cfg(feature = "oauth")]
+async fn list_oauth_providers(State(sessions): State<Arc<Mutex<Sessions>>>) -> Json<OAuthProvidersResponse> {
+    let sessions = sessions.lock();
+    Json(OAuthProvidersResponse {
+        providers: sessions.oauth_providers().into_iter().map(str::to_string).collect(),
+    })
+}
+
+#[cfg(feature = "oauth")]
+#[derive(Deserialize)]
+struct OAuthStartQuery {
+    /// A ticket from `/auth/ws-ticket`, given by a logged-in player linking the identity to
+    /// their name, as browsers cannot send their token when following a link.
+    ticket: Option<String>,
+}
+
+/// Sends the browser to the provider's consent page, with a state signed for the nonce in a
+/// cookie only this browser holds, so the callback cannot be replayed from another. The nonce
+/// also goes into the ID tokens of `OpenID` Connect providers.
+#[cfg(feature = "oauth")]
+async fn start_oauth_login(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(name): Path<String>,
+    Query(query): Query<OAuthStartQuery>,
+) -> Result<Response, StatusCode> {
+    let (provider, player) = {
+        let mut sessions = sessions.lock();
+        let provider = sessions.oauth_provider(&name).ok_or(StatusCode::NOT_FOUND)?;
+        let player = match query.ticket {
+            Some(ticket) => Some(sessions.redeem_ticket(&ticket).ok_or(StatusCode::UNAUTHORIZED)?),
+            None => None,
+        };
+        (provider, player)
+    };
+    let nonce = format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>());
+    let state = Auth::generate_oauth_state(&name, &nonce, player.as_deref())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let authorize_url = provider.authorize_url(&state, &nonce).await.map_err(|e| {
+        tracing::warn!("Login through {name} failed: {e}");
+        StatusCode::BAD_GATEWAY
+    })?;
+    let secure = if provider.config().redirect_url.starts_with("https:") {
+        "; Secure"
+    } else {
+        ""
+    };
+    let cookie = format!(
+        "{}={nonce}; Path=/auth/oauth; Max-Age={}; HttpOnly; SameSite=Lax{secure}",
+        oauth::STATE_COOKIE,
+        Auth::OAUTH_STATE_LIFETIME,
+    );
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Redirect::to(&authorize_url),
+    )
+        .into_response())
+}
+
+#[cfg(feature = "oauth")]
+#[derive(Deserialize)]
+struct OAuthCallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+}
+
+/// Finishes a login through a provider: checks the state, asks the provider who the user is,
+/// and sends the browser to the web client with a session token for the player linked to
+/// them. A login started by a player links the identity to them, unless it is linked to
+/// someone else already.
+#[cfg(feature = "oauth")]
+async fn finish_oauth_login(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(name): Path<String>,
+    Query(query): Query<OAuthCallbackQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let provider = sessions.lock().oauth_provider(&name).ok_or(StatusCode::NOT_FOUND)?;
+    let nonce = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| pair.trim().strip_prefix(oauth::STATE_COOKIE)?.strip_prefix('='))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    // The provider sends no code when the user declines.
+    let (Some(code), Some(state)) = (query.code, query.state) else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    let state = Auth::validate_oauth_state(&state, &name, nonce).ok_or(StatusCode::BAD_REQUEST)?;
+    let identity = provider.identify(&code, nonce).await.map_err(|e| {
+        tracing::warn!("Login through {name} failed: {e}");
+        StatusCode::BAD_GATEWAY
+    })?;
+    let player = if let Some(player) = state.player {
+        let linked = sessions
+            .lock()
+            .storage
+            .link_identity_to(&identity.provider, &identity.subject, &player)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        if linked != player {
+            return Err(StatusCode::CONFLICT);
+        }
+        linked
+    } else {
+        let sessions = sessions.lock();
+        let mut suggested = identity.suggested_name();
+        // The AI and engines cannot be logged in as.
+        if sessions.is_bot(&suggested) {
+            suggested = identity.fallback_name();
+        }
+        sessions
+            .storage
+            .link_identity(&identity.provider, &identity.subject, &suggested)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    };
+    let token = Auth::generate_token(&player).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let cookie = format!("{}=; Path=/auth/oauth; Max-Age=0", oauth::STATE_COOKIE);
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Redirect::to(&format!("/#{}", oauth::login_fragment(&token, &player))),
+    )
+        .into_response())
+}
+
 async fn create_match(
     State(sessions): State<Arc<Mutex<Sessions>>>,
     AuthenticatedPlayer(player1): AuthenticatedPlayer,
-    Json(req): Json<NewMatchRequest>,
+    Json(mut req): Json<NewMatchRequest>,
 ) -> Result<Json<NewMatchResponse>, StatusCode> {
-    if (player1 == "AI" && req.player2 != "AI") || (player1 != "AI" && req.player2 == "AI") {
-        let mut sessions = sessions.lock().unwrap();
-        let id = sessions.create_game(player1, &req.player2);
+    if let Some(difficulty) = req.difficulty {
+        if req.player2 != AI_PLAYER {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        req.player2 = difficulty.player_name().to_string();
+    }
+    // The AI and engines cannot be logged in as, and players cannot challenge themselves.
+    let mut guard = sessions.lock();
+    if !guard.is_bot(&player1) && player1 != req.player2 {
+        let preferences = guard
+            .storage
+            .player_preferences(&player1)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        // Preferring coaching does not stop the player from creating ranked games against
+        // people, where it is not allowed.
+        let coach = req.coach.unwrap_or_else(|| {
+            preferences.coach && (req.game_type == GameType::Casual || guard.is_bot(&req.player2))
+        });
+        let options = GameOptions {
+            komi: req.komi.unwrap_or(guard.default_komi()),
+            game_type: req.game_type,
+            private: req.private,
+            coach,
+            auto_pass: preferences.auto_pass,
+        };
+        let color = req.color.resolve(&mut rand::thread_rng());
+        let id = guard
+            .challenge_as(player1, color, &req.player2, options)
+            .map_err(|e| error_status(&e))?;
         tracing::info!("Created game: {}", id);
-        return Ok(Json(NewMatchResponse { id }));
+        // The AI or engine opens the game when the creator plays White.
+        if guard.is_ai_turn(&id) {
+            let (sessions, id) = (sessions.clone(), id.clone());
+            tokio::spawn(async move {
+                if let Err(e) = play_ai_reply(&sessions, &id).await {
+                    tracing::warn!("Failed to play the opening move of {id}: {e}");
+                }
+            });
+        }
+        let invite = if req.private {
+            Some(Auth::generate_invite(&id).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?)
+        } else {
+            None
+        };
+        let invite_url = invite.as_ref().map(|invite| format!("/?game={id}&invite={invite}"));
+        return Ok(Json(NewMatchResponse {
+            id,
+            invite,
+            invite_url,
+            color,
+        }));
     }
     Err(StatusCode::BAD_REQUEST)
 }
@@ -129,241 +629,1810 @@ async fn make_move(
     State(sessions): State<Arc<Mutex<Sessions>>>,
     Path(id): Path<String>,
     AuthenticatedPlayer(player): AuthenticatedPlayer,
-    Json(req): Json<MoveRequest>,
-) -> Result<(), StatusCode> {
-    let Ok(pos) = Game::coord_to_pos(&req.coord) else {
+    req: Result<Json<MoveRequest>, JsonRejection>,
+) -> Result<Json<MoveResponse>, StatusCode> {
+    let Ok(Json(req)) = req else {
         return Err(StatusCode::BAD_REQUEST);
     };
-    let mut sessions = sessions.lock().unwrap();
-    sessions.make_move(&id, pos, &player).map_err(|_| StatusCode::BAD_REQUEST)?;
-    let (p1, p2) = sessions.get_players(&id).unwrap();
-    let game = sessions.get_game(&id).unwrap();
-    let current_player_name = match game.current_player {
-        crate::game::Player::Black => p1,
-        crate::game::Player::White => p2,
-    };
-    if current_player_name == "AI" {
-        match AI::get_move(game) {
-            Some(Move::Place(pos)) => {
-                sessions.make_move(&id, pos, "AI").map_err(|_| StatusCode::BAD_REQUEST)?;
+    play_move(&sessions, &id, req.coord.pos(), &player, req.ply)
+        .await
+        .map(Json)
+        .map_err(|e| error_status(&e))
+}
+
+/// Plays the move at the square in the path, as `POST /match/:id/move` does but with the
+/// error described in the body. Repeating the request once the move is played succeeds
+/// without playing anything, so bots can retry it safely.
+async fn put_move(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path((id, coord)): Path<(String, String)>,
+    Query(query): Query<PlyQuery>,
+    headers: HeaderMap,
+) -> Result<Json<MoveResponse>, (StatusCode, Json<Notice>)> {
+    let player = bearer_player(&headers).ok_or_else(|| error_response(&SessionError::NotLoggedIn))?;
+    let pos = coord
+        .parse::<MoveCoord>()
+        .map_err(|_| error_response(&SessionError::InvalidMove(IllegalMove::OffBoard)))?
+        .pos();
+    match play_move(&sessions, &id, pos, &player, query.ply).await {
+        Ok(response) => Ok(Json(response)),
+        // A square is only ever played once in a game, so finding it in the player's moves
+        // means this is a retry.
+        Err(
+            SessionError::NotYourTurn
+            | SessionError::InvalidMove(_)
+            | SessionError::GameOver
+            | SessionError::Superseded { .. },
+        )
+            if sessions.lock().history(&id).is_ok_and(|history| {
+                history
+                    .iter()
+                    .any(|record| record.mv == Move::Place(pos) && record.player == player)
+            }) =>
+        {
+            Ok(Json(MoveResponse { forced_passes: Vec::new() }))
+        }
+        Err(e) => Err(error_response(&e)),
+    }
+}
+
+/// Ends the game in the player's favour if their opponent left it and has run out of time
+/// to reconnect, checked against the deadline kept in the sessions.
+async fn claim_win(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+) -> Result<Json<ClaimResponse>, (StatusCode, Json<Notice>)> {
+    let response = match sessions.lock().claim_win(&id, &player) {
+        Ok(Abandonment::Forfeited(color)) => ClaimResponse {
+            winner: Some(color_name(color.opponent())),
+            aborted: false,
+        },
+        Ok(Abandonment::Aborted) => ClaimResponse {
+            winner: None,
+            aborted: true,
+        },
+        Err(e) => return Err(error_response(&e)),
+    };
+    Ok(Json(response))
+}
+
+/// Ends the game as a draw if nobody has moved in it for the inactivity period, checked
+/// against the start of the turn kept in the sessions.
+async fn claim_draw(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+) -> Result<Json<ClaimResponse>, (StatusCode, Json<Notice>)> {
+    sessions.lock().claim_draw(&id, &player).map_err(|e| error_response(&e))?;
+    Ok(Json(ClaimResponse {
+        winner: None,
+        aborted: false,
+    }))
+}
+
+/// Plays a move for the player, then the AI's or engine's reply if it is their turn.
+async fn play_move(
+    sessions: &Mutex<Sessions>,
+    id: &str,
+    pos: u8,
+    player: &str,
+    ply: Option<usize>,
+) -> Result<MoveResponse, SessionError> {
+    let mut forced = submit_move(&mut sessions.lock(), id, player, Move::Place(pos), ply)?;
+    forced.extend(play_ai_reply(sessions, id).await?);
+    Ok(MoveResponse {
+        forced_passes: forced.into_iter().map(color_name).collect(),
+    })
+}
+
+/// Plays a move for the player, checked against the ply the client sent if it sent one.
+fn submit_move(
+    sessions: &mut Sessions,
+    id: &str,
+    player: &str,
+    mv: Move,
+    ply: Option<usize>,
+) -> Result<Vec<Player>, SessionError> {
+    if let Some(ply) = ply {
+        return sessions.apply_move(id, player, mv, ply);
+    }
+    sessions.load_game(id)?;
+    match mv {
+        Move::Place(pos) => sessions.make_move(id, pos, player),
+        Move::Pass => sessions.pass(id, player),
+    }
+}
+
+/// Plays the moves of the AI or engine for as long as it is their turn in the game, returning
+/// the passes they forced.
+///
+/// The search runs on the game's own AI, reusing the tree from earlier turns. It is queued on
+/// the AI scheduler and runs on the blocking thread pool, with the sessions lock released, so
+/// other games and requests are not blocked while it thinks. The reply is then held back until
+/// the AI's pacing delay has passed. The search is cancelled when the game ends or the request
+/// is dropped, e.g. because the client disconnected, and the AI then stops without moving.
+///
+/// An engine is asked for its move on the blocking thread pool too. If it fails, the AI plays
+/// the move in its place so the game goes on.
+async fn play_ai_reply(sessions: &Mutex<Sessions>, id: &str) -> Result<Vec<Player>, SessionError> {
+    let mut forced = Vec::new();
+    loop {
+        let (player, game, engine, cancel) = {
+            let mut sessions = sessions.lock();
+            if !sessions.is_ai_turn(id) {
+                return Ok(forced);
+            }
+            let game = sessions.get_game(id).ok_or(SessionError::GameNotFound)?.clone();
+            let player = sessions.player_to_move(id).unwrap_or_default().to_string();
+            (player, game, sessions.engine_for(id), sessions.start_search(id))
+        };
+        let guard = CancelOnDrop(cancel.clone());
+        let mut mv = None;
+        if let Some(engine) = engine {
+            let position = game.clone();
+            let result = tokio::task::spawn_blocking(move || engine.best_move(&position))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()));
+            match result {
+                Ok(engine_move) => mv = Some(engine_move),
+                Err(e) => tracing::warn!("Engine failed in {id}, the AI plays instead: {e}"),
             }
-            Some(Move::Pass) => {
-                sessions.pass(&id).map_err(|_| StatusCode::BAD_REQUEST)?;
+        }
+        if mv.is_none() {
+            mv = search_ai(sessions, id, game, &cancel).await?;
+        }
+        // The game may have ended while the reply was held back.
+        let cancelled = cancel.is_cancelled();
+        drop(guard);
+        if cancelled {
+            return Ok(forced);
+        }
+        let passes = {
+            let mut sessions = sessions.lock();
+            match mv {
+                Some(Move::Place(pos)) => sessions.make_move(id, pos, &player)?,
+                Some(Move::Pass) => sessions.pass(id, &player)?,
+                None => return Ok(forced),
             }
-            None => {
-                // Should not happen, but pass just in case
-                sessions.pass(&id).map_err(|_| StatusCode::BAD_REQUEST)?;
+        };
+        forced.extend(passes);
+    }
+}
+
+/// Longest to wait for the AI of a game before taking it for still held by a search that was
+/// cut short.
+const AI_BUSY_WAIT: Duration = Duration::from_millis(50);
+
+/// Searches for the AI's move in the game and waits out its pacing delay, returning `None` if
+/// the search was cancelled. A search running past the scheduler's move timeout is cut short
+/// and a fallback move played instead, see [`fallback_move`].
+async fn search_ai(
+    sessions: &Mutex<Sessions>,
+    id: &str,
+    game: Game,
+    cancel: &CancelToken,
+) -> Result<Option<Move>, SessionError> {
+    let (ai, unseen, scheduler) = {
+        let mut sessions = sessions.lock();
+        (sessions.ai_for(id), sessions.take_unseen_moves(id), sessions.ai_scheduler())
+    };
+    let Some((simulations, pacing)) = ai
+        .try_lock_for(AI_BUSY_WAIT)
+        .map(|ai| (ai.config().simulations, ai.config().pacing))
+    else {
+        tracing::warn!(game = id, "AI still held by a search that timed out");
+        return Ok(Some(fallback_move(id, &ai, &game)));
+    };
+    let started = Instant::now();
+    // Cutting the search short must not cancel the game's search as a whole.
+    let search_cancel = cancel.child();
+    let job = {
+        let (ai, game, search_cancel) = (ai.clone(), game.clone(), search_cancel.clone());
+        move || {
+            let mut ai = ai.lock();
+            for mv in unseen {
+                ai.make_move(mv);
             }
+            ai.get_move_cancellable(&game, &search_cancel)
+        }
+    };
+    let outcome = scheduler
+        .run_timed(simulations, &search_cancel, job)
+        .await
+        .map_err(|e| SessionError::Ai(e.to_string()))?;
+    let mv = match outcome {
+        Timed::Finished(mv) => mv,
+        Timed::TimedOut if cancel.is_cancelled() => None,
+        Timed::TimedOut => {
+            tracing::warn!(
+                game = id,
+                timeout_ms = scheduler.move_timeout().map_or(0, |timeout| timeout.as_millis()),
+                "AI search timed out"
+            );
+            Some(fallback_move(id, &ai, &game))
         }
+    };
+    let compute = started.elapsed();
+    let delay = pacing.remaining_delay(compute, &mut rand::thread_rng());
+    tracing::debug!(
+        game = id,
+        compute_ms = u64::try_from(compute.as_millis()).unwrap_or(u64::MAX),
+        delay_ms = u64::try_from(delay.as_millis()).unwrap_or(u64::MAX),
+        "AI search finished"
+    );
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    Ok(mv)
+}
+
+/// Picks the move played when the AI's search timed out: the search's best move so far if
+/// the AI is free to tell it, or else the move the heuristic evaluation prefers.
+fn fallback_move(id: &str, ai: &Mutex<MctsAi>, game: &Game) -> Move {
+    if let Some(mv) = ai.try_lock().and_then(|ai| ai.best_move_so_far(game)) {
+        tracing::warn!(game = id, "Playing the AI's best move so far, {mv}");
+        return mv;
+    }
+    let mv = eval::move_preferences(&HeuristicEvaluator::default(), game)
+        .into_iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(Move::Pass, |(pos, _)| Move::Place(pos));
+    tracing::warn!(game = id, "Playing the heuristic move {mv} for the AI");
+    mv
+}
+
+/// Cancels a search if the future waiting on it is dropped before it finishes.
+struct CancelOnDrop(CancelToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+fn error_status(error: &SessionError) -> StatusCode {
+    match error {
+        SessionError::GameNotFound => StatusCode::NOT_FOUND,
+        SessionError::GameInProgress
+        | SessionError::GameOver
+        | SessionError::Conflict
+        | SessionError::Superseded { .. }
+        | SessionError::PlyNotReached(_)
+        | SessionError::NothingToClaim
+        | SessionError::StillActive(_) => StatusCode::CONFLICT,
+        SessionError::NotLoggedIn => StatusCode::UNAUTHORIZED,
+        SessionError::ChallengeRefused | SessionError::NotAParticipant => StatusCode::FORBIDDEN,
+        SessionError::NotYourTurn
+        | SessionError::InvalidMove(_)
+        | SessionError::PassNotAllowed
+        | SessionError::PlyNotPlayed(_)
+        | SessionError::InvalidMetadataKey
+        | SessionError::MetadataValueTooLong(_)
+        | SessionError::TooManyMetadataEntries
+        | SessionError::EmptyChat
+        | SessionError::ChatTooLong
+        | SessionError::InvalidKomi
+        | SessionError::CoachNotAllowed => StatusCode::BAD_REQUEST,
+        SessionError::UnderMaintenance => StatusCode::SERVICE_UNAVAILABLE,
+        SessionError::Storage(_) | SessionError::Ai(_) => {
+            tracing::error!("{error}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Returns true if the client sent `If-None-Match` with `etag`, meaning it already has the
+/// version of the resource tagged so.
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Tells the client its copy of the resource tagged `etag` is still current.
+fn not_modified(etag: &str) -> Response {
+    tagged(etag, StatusCode::NOT_MODIFIED)
+}
+
+/// Tags a response with `etag`, and asks browsers to check it is current before reusing it.
+fn tagged(etag: &str, response: impl IntoResponse) -> Response {
+    let mut response = response.into_response();
+    let headers = response.headers_mut();
+    if let Ok(etag) = etag.parse() {
+        headers.insert(header::ETAG, etag);
+    }
+    headers.insert(header::CACHE_CONTROL, header::HeaderValue::from_static("no-cache"));
+    response
+}
+
+/// Answers with the error's status and the error as a message clients can translate.
+fn error_response(error: &SessionError) -> (StatusCode, Json<Notice>) {
+    (error_status(error), Json(error.notice()))
+}
+
+fn color_name(player: Player) -> String {
+    match player {
+        Player::Black => "Black".to_string(),
+        Player::White => "White".to_string(),
     }
-    Ok(())
 }
 
 async fn get_state(
     State(sessions): State<Arc<Mutex<Sessions>>>,
     Path(id): Path<String>,
-) -> Result<Json<GameStateResponse>, StatusCode> {
-    let sessions = sessions.lock().unwrap();
-    let game = sessions.get_game(&id).ok_or(StatusCode::NOT_FOUND)?;
-    let (player1, player2) = sessions.get_players(&id).ok_or(StatusCode::NOT_FOUND)?;
-    let board = game_to_board(game);
-    let legal_moves = game
-        .legal_moves()
+    Query(query): Query<StateQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let (etag, state) = {
+        let sessions = lock_game(&sessions, &id)?;
+        let etag = state_etag(&sessions, &id, query).ok_or(StatusCode::NOT_FOUND)?;
+        if etag_matches(&headers, &etag) {
+            return Ok(not_modified(&etag));
+        }
+        (etag, build_state(&sessions, &id, query).ok_or(StatusCode::NOT_FOUND)?)
+    };
+    Ok(tagged(&etag, Json(state)))
+}
+
+/// Tags the state of a game with what changes it, so a client's copy is checked without
+/// building the state: the moves played, how the game ended, who is watching it and the
+/// options of the query. Presence and countdowns change between moves, so they are covered.
+fn state_etag(sessions: &Sessions, id: &str, query: StateQuery) -> Option<String> {
+    let (player1, player2) = sessions.get_players(id)?;
+    let ply = sessions.ply(id).ok()?;
+    let ending = match sessions.resigned(id) {
+        _ if sessions.is_aborted(id) => "aborted",
+        _ if sessions.is_drawn(id) => "drawn",
+        Some(Player::Black) => "black-resigned",
+        Some(Player::White) => "white-resigned",
+        None if sessions.is_over(id) => "over",
+        None => "playing",
+    };
+    // Names may not fit in a header, so countdowns are told apart by seat.
+    let countdowns: Vec<String> = sessions
+        .countdowns(id)
         .iter()
-        .map(|p| Game::pos_to_coord(*p))
+        .map(|countdown| {
+            let seat = if countdown.player == *player1 { 1 } else { 2 };
+            format!("{seat}:{}:{}", countdown.seconds, countdown.abort)
+        })
         .collect();
-    let current_player = match game.current_player {
-        crate::game::Player::Black => "Black".to_string(),
-        crate::game::Player::White => "White".to_string(),
-    };
-    let winner = game.winner().map(|p| match p {
-        crate::game::Player::Black => "Black".to_string(),
-        crate::game::Player::White => "White".to_string(),
-    });
-    let scores = game.scores();
-    let mut scores_map = HashMap::new();
-    scores_map.insert("B".to_string(), scores.0);
-    scores_map.insert("W".to_string(), scores.1);
-    Ok(Json(GameStateResponse {
+    Some(format!(
+        "\"{id}-{ply}-{ending}-{}-{}-{}-{}-{}-{}\"",
+        sessions.spectators(id),
+        u8::from(sessions.is_connected(id, player1)),
+        u8::from(sessions.is_connected(id, player2)),
+        countdowns.join(","),
+        u8::from(query.explain),
+        u8::from(query.hints),
+    ))
+}
+
+async fn get_replay(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
+    Query(query): Query<ReplayQuery>,
+) -> Result<Json<ReplayResponse>, StatusCode> {
+    let sessions = lock_game(&sessions, &id)?;
+    let history = sessions.history(&id).map_err(|e| error_status(&e))?;
+    let (player1, player2) = sessions.get_players(&id).ok_or(StatusCode::NOT_FOUND)?.clone();
+    let mut replay = GameReplay::from_moves(history.iter().map(|record| record.mv).collect())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let ply = query.ply.unwrap_or(replay.len());
+    let board = game_to_board(replay.seek(ply).map_err(|_| StatusCode::BAD_REQUEST)?);
+    let annotations = sessions
+        .annotations(&id)
+        .map_err(|e| error_status(&e))?
+        .into_iter()
+        .map(|annotation| AnnotationResponse {
+            ply: annotation.ply,
+            author: annotation.author,
+            comment: annotation.comment,
+            mark: annotation.mark.map(|mark| mark.as_str().to_string()),
+            score: annotation.score,
+        })
+        .collect();
+    let moves = history
+        .into_iter()
+        .enumerate()
+        .map(|(ply, record)| ReplayMoveResponse {
+            ply,
+            coord: record.mv.to_string(),
+            player: record.player,
+            elapsed_ms: record.elapsed_ms,
+            simulations: record.simulations,
+        })
+        .collect();
+    Ok(Json(ReplayResponse {
+        id,
+        player1,
+        player2,
+        moves,
+        ply,
         board,
-        current_player,
-        legal_moves,
-        game_over: game.is_game_over(),
-        winner,
-        player1: player1.clone(),
-        player2: player2.clone(),
-        scores: scores_map,
+        annotations,
     }))
 }
 
-async fn join_matchmaking(
+async fn annotate(
     State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
     AuthenticatedPlayer(player): AuthenticatedPlayer,
-) -> Result<Json<JoinResponse>, StatusCode> {
-    let mut sessions = sessions.lock().unwrap();
-    if let Some(id) = sessions.join_matchmaking(player) {
-        Ok(Json(JoinResponse {
-            matched: true,
-            id: Some(id),
-        }))
-    } else {
-        Ok(Json(JoinResponse {
-            matched: false,
-            id: None,
-        }))
+    Json(req): Json<AnnotationRequest>,
+) -> Result<StatusCode, StatusCode> {
+    let mark = req
+        .mark
+        .as_deref()
+        .map(str::parse)
+        .transpose()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let annotation = Annotation {
+        ply: req.ply,
+        author: player,
+        comment: req.comment,
+        mark,
+        score: req.score,
+    };
+    lock_game(&sessions, &id)?
+        .annotate(&id, &annotation)
+        .map_err(|e| error_status(&e))?;
+    Ok(StatusCode::CREATED)
+}
+
+/// Queues the full analysis of a finished game, returning its job. The report becomes
+/// available from the report endpoint once the job is done.
+async fn analyze_full(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
+    AuthenticatedPlayer(_): AuthenticatedPlayer,
+) -> Result<(StatusCode, Json<Job>), StatusCode> {
+    let sessions = lock_game(&sessions, &id)?;
+    let job_id = sessions.start_analysis(&id).map_err(|e| error_status(&e))?;
+    match sessions.storage.job(job_id) {
+        Ok(Some(job)) => Ok((StatusCode::ACCEPTED, Json(job))),
+        Ok(None) | Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
 }
 
-async fn ws_handler(
-    ws: WebSocketUpgrade,
+async fn get_report(
     State(sessions): State<Arc<Mutex<Sessions>>>,
     Path(id): Path<String>,
-) -> impl axum::response::IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, sessions, id))
+) -> Result<Json<AnalysisReport>, StatusCode> {
+    let sessions = lock_game(&sessions, &id)?;
+    if let Some(report) = sessions.analysis_report(&id).map_err(|e| error_status(&e))? {
+        return Ok(Json(report));
+    }
+    match sessions.analysis_job(&id).map_err(|e| error_status(&e))? {
+        Some(_) => Err(StatusCode::ACCEPTED),
+        None => Err(StatusCode::NOT_FOUND),
+    }
 }
 
-async fn handle_socket(mut socket: WebSocket, sessions: Arc<Mutex<Sessions>>, id: String) {
-    // Send initial state right after connection
-    send_state(&mut socket, &sessions, &id).await;
-
-    while let Some(Ok(msg)) = socket.recv().await {
-        if let axum::extract::ws::Message::Text(text) = msg {
-            #[derive(Deserialize)]
-            struct ClientMessage {
-                r#type: String,
-                coord: Option<String>,
-            }
+/// Scores many positions in one call, on the blocking thread pool so the server keeps
+/// answering while it runs.
+async fn analyze_batch(
+    AuthenticatedPlayer(_): AuthenticatedPlayer,
+    Json(req): Json<BatchAnalysisRequest>,
+) -> Result<Json<BatchAnalysisResponse>, StatusCode> {
+    let defaults = AnalysisConfig::default();
+    let config = AnalysisConfig {
+        depth: req.depth.unwrap_or(defaults.depth),
+        exact_empties: req.exact_empties.unwrap_or(defaults.exact_empties),
+        ..defaults
+    };
+    if req.positions.len() > MAX_BATCH_POSITIONS
+        || config.depth > MAX_BATCH_DEPTH
+        || config.exact_empties > MAX_BATCH_EXACT_EMPTIES
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let games = req
+        .positions
+        .iter()
+        .map(|position| Game::from_position_string(position))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let evaluations = tokio::task::spawn_blocking(move || analysis::evaluate_batch(&games, &config))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(BatchAnalysisResponse { evaluations }))
+}
 
-            if let Ok(client_msg) = serde_json::from_str::<ClientMessage>(&text) {
-                {
-                    let mut sessions_guard = sessions.lock().unwrap();
-                    let (p1, p2) = sessions_guard.get_players(&id).unwrap().clone();
-
-                    let player_name = if sessions_guard.get_game(&id).unwrap().current_player == crate::game::Player::Black {
-                        p1.clone()
-                    } else {
-                        p2.clone()
-                    };
-
-                    if client_msg.r#type == "move" {
-                        if let Some(coord) = client_msg.coord {
-                            let Ok(pos) = Game::coord_to_pos(&coord) else {
-                                continue; // Invalid coord
-                            };
-
-                            if sessions_guard.make_move(&id, pos, &player_name).is_ok() {
-                                let game = sessions_guard.get_game(&id).unwrap();
-                                let current_player_name = if game.current_player == crate::game::Player::Black {
-                                    &p1
-                                } else {
-                                    &p2
-                                };
-
-                                if current_player_name == "AI" {
-                                    match AI::get_move(game) {
-                                        Some(Move::Place(pos)) => {
-                                            sessions_guard.make_move(&id, pos, "AI").unwrap();
-                                        }
-                                        Some(Move::Pass) => {
-                                            sessions_guard.pass(&id).unwrap();
-                                        }
-                                        None => {
-                                            sessions_guard.pass(&id).unwrap();
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    } else if client_msg.r#type == "pass" {
-                        if sessions_guard.pass(&id).is_ok() {
-                            let game = sessions_guard.get_game(&id).unwrap();
-                            let current_player_name = if game.current_player == crate::game::Player::Black {
-                                &p1
-                            } else {
-                                &p2
-                            };
-
-                            if current_player_name == "AI" {
-                                match AI::get_move(game) {
-                                    Some(Move::Place(pos)) => {
-                                        sessions_guard.make_move(&id, pos, "AI").unwrap();
-                                    }
-                                    Some(Move::Pass) => {
-                                        sessions_guard.pass(&id).unwrap();
-                                    }
-                                    None => {
-                                        sessions_guard.pass(&id).unwrap();
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                send_state(&mut socket, &sessions, &id).await;
-            }
-        }
+/// Stores a game uploaded as an SGF or GGF file, once checked against the rules, for analysis
+/// and puzzle mining.
+async fn import_game(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+    body: String,
+) -> Result<(StatusCode, Json<ImportResponse>), StatusCode> {
+    let imported = import::parse_game(&body).map_err(|e| {
+        tracing::debug!("{player} uploaded a game that cannot be imported: {e}");
+        StatusCode::UNPROCESSABLE_ENTITY
+    })?;
+    let game_id = sessions
+        .lock()
+        .storage
+        .import_game(&player, &imported)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tracing::info!("{player} imported game {game_id} from {}", imported.format.as_str());
+    Ok((
+        StatusCode::CREATED,
+        Json(ImportResponse {
+            game_id,
+            format: imported.format,
+            moves: imported.moves.len(),
+        }),
+    ))
+}
+
+/// Lists the background jobs, which name private games, for admins only.
+async fn list_jobs(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(requester): AuthenticatedPlayer,
+    Query(query): Query<JobsQuery>,
+) -> Result<Json<Vec<Job>>, StatusCode> {
+    let status = query
+        .status
+        .map(|status| status.parse::<JobStatus>())
+        .transpose()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let sessions = sessions.lock();
+    if !sessions.is_admin(&requester) {
+        return Err(StatusCode::FORBIDDEN);
     }
+    sessions
+        .storage
+        .jobs(status, query.limit.unwrap_or(DEFAULT_JOBS).min(MAX_JOBS))
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
-async fn send_state(socket: &mut WebSocket, sessions: &Arc<Mutex<Sessions>>, id: &str) {
-    let (state, legal_moves_empty) = {
-        let sessions = sessions.lock().unwrap();
-        let mut data = None;
-        let mut legal_moves: Vec<String> = Vec::new();
-        if let Some(game) = sessions.get_game(id) {
-            legal_moves = game.legal_moves().iter().map(|p| Game::pos_to_coord(*p)).collect();
-            let (player1, player2) = sessions.get_players(id).unwrap();
-            let board = game_to_board(game);
-            let current_player = match game.current_player {
-                crate::game::Player::Black => "Black".to_string(),
-                crate::game::Player::White => "White".to_string(),
-            };
-            let winner = game.winner().map(|p| match p {
-                crate::game::Player::Black => "Black".to_string(),
-                crate::game::Player::White => "White".to_string(),
-            });
-            data = Some(serde_json::json!({
-                "board": board,
-                "current_player": current_player,
-                "legal_moves": legal_moves,
-                "game_over": game.is_game_over(),
-                "winner": winner,
-                "player1": player1.clone(),
-                "player2": player2.clone(),
-                "scores": { "B": game.scores().0, "W": game.scores().1 }
-            }));
-        }
-        (data, legal_moves.is_empty())
-    };
 
-    if let Some(state) = state {
-        if socket.send(axum::extract::ws::Message::Text(state.to_string())).await.is_err() {
-            return;
-        }
-        if legal_moves_empty
-            && socket
-                .send(axum::extract::ws::Message::Text(
-                    serde_json::json!({
-                        "type": "status",
-                        "message": "No legal moves available, you must pass."
-                    })
-                    .to_string(),
-                ))
-                .await
-                .is_err()
-        {
-        }
+/// Fetches a background job for admins, or for those who may see the game it works on.
+async fn get_job(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(requester): AuthenticatedPlayer,
+    Path(id): Path<i64>,
+) -> Result<Json<Job>, StatusCode> {
+    let mut sessions = sessions.lock();
+    let job = match sessions.storage.job(id) {
+        Ok(Some(job)) => job,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    // Jobs on games the requester may not see are hidden as if they did not exist.
+    let visible = sessions.is_admin(&requester)
+        || job.task.game_id().is_some_and(|game| {
+            sessions.load_game(game).is_ok() && (!sessions.is_private(game) || sessions.may_see(game, &requester))
+        });
+    if !visible {
+        return Err(StatusCode::NOT_FOUND);
     }
+    Ok(Json(job))
 }
 
-async fn get_leaderboard(
+/// Lists the suspicions raised by the anti-cheat analyzers, for admins only.
+async fn list_cheat_reports(
     State(sessions): State<Arc<Mutex<Sessions>>>,
-) -> Result<Json<Vec<PlayerStats>>, StatusCode> {
-    let sessions = sessions.lock().unwrap();
-    let stats = sessions
+    AuthenticatedPlayer(requester): AuthenticatedPlayer,
+    Query(query): Query<CheatReportsQuery>,
+) -> Result<Json<Vec<CheatReport>>, StatusCode> {
+    let sessions = sessions.lock();
+    if !sessions.is_admin(&requester) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    sessions
         .storage
-        .get_leaderboard()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(Json(stats))
+        .cheat_reports(
+            query.player.as_deref(),
+            query.limit.unwrap_or(DEFAULT_CHEAT_REPORTS).min(MAX_CHEAT_REPORTS),
+        )
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Lists the AI's latest games and its results by release, for admins only.
+async fn list_ai_games(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(requester): AuthenticatedPlayer,
+    Query(query): Query<AiGamesQuery>,
+) -> Result<Json<AiGamesResponse>, StatusCode> {
+    let sessions = sessions.lock();
+    if !sessions.is_admin(&requester) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_AI_GAMES).min(MAX_AI_GAMES);
+    let games = sessions.storage.ai_games(query.engine_version.as_deref(), limit);
+    match (sessions.storage.ai_strength(), games) {
+        (Ok(strength), Ok(games)) => Ok(Json(AiGamesResponse { strength, games })),
+        _ => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn get_dashboard(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(requester): AuthenticatedPlayer,
+) -> Result<Json<AdminDashboardResponse>, StatusCode> {
+    let sessions = sessions.lock();
+    if !sessions.is_admin(&requester) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let games = sessions
+        .active_games()
+        .into_iter()
+        .filter_map(|id| {
+            let game = sessions.get_game(id)?;
+            let (player1, player2) = sessions.get_players(id)?;
+            Some(AdminGameResponse {
+                id: id.to_string(),
+                player1: player1.clone(),
+                player2: player2.clone(),
+                current_player: color_name(game.current_player),
+                discs: game.occupied().count_ones(),
+                ai: sessions.ai_activity(id).map(|activity| activity.as_str().to_string()),
+                version: sessions.version(id),
+                presence: PresenceResponse {
+                    spectators: sessions.spectators(id),
+                    player1_connected: sessions.is_connected(id, player1),
+                    player2_connected: sessions.is_connected(id, player2),
+                    countdowns: sessions.countdowns(id),
+                },
+            })
+        })
+        .collect();
+    let scheduler = sessions.ai_scheduler();
+    Ok(Json(AdminDashboardResponse {
+        instance_id: sessions.instance_id().to_string(),
+        games,
+        matchmaking_queue: sessions.matchmaking_queue().map_err(|e| error_status(&e))?,
+        sockets: sessions.open_sockets(),
+        ai_capacity: scheduler.capacity(),
+        ai_queued: scheduler.queued(),
+    }))
+}
+
+async fn list_errors(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(requester): AuthenticatedPlayer,
+    Query(query): Query<ErrorsQuery>,
+) -> Result<Json<Vec<LoggedError>>, StatusCode> {
+    let sessions = sessions.lock();
+    if !sessions.is_admin(&requester) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_ERRORS).min(ERROR_LOG_CAPACITY);
+    Ok(Json(sessions.error_log().recent(limit)))
+}
+
+async fn broadcast(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(requester): AuthenticatedPlayer,
+    Json(req): Json<BroadcastRequest>,
+) -> Result<Json<BroadcastResponse>, StatusCode> {
+    let mut sessions = sessions.lock();
+    if !sessions.is_admin(&requester) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let message = req.message.trim();
+    if message.is_empty() || message.chars().count() > MAX_CHAT_LEN {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let notice = Notice::new(MessageCode::Maintenance).with("message", message);
+    let games = sessions.broadcast(&notice);
+    tracing::info!("{requester} broadcast a notice to {games} games: {message}");
+    Ok(Json(BroadcastResponse { games }))
+}
+
+async fn get_maintenance(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(requester): AuthenticatedPlayer,
+) -> Result<Json<MaintenanceResponse>, StatusCode> {
+    let sessions = sessions.lock();
+    if !sessions.is_admin(&requester) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(Json(maintenance_response(&sessions)))
+}
+
+async fn set_maintenance(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(requester): AuthenticatedPlayer,
+    Json(req): Json<MaintenanceRequest>,
+) -> Result<Json<MaintenanceResponse>, StatusCode> {
+    let mut sessions = sessions.lock();
+    if !sessions.is_admin(&requester) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if req.enabled {
+        sessions.start_maintenance(req.deadline_secs.map(Duration::from_secs), req.restart);
+    } else {
+        sessions.end_maintenance();
+    }
+    tracing::info!("{requester} turned maintenance {}", if req.enabled { "on" } else { "off" });
+    Ok(Json(maintenance_response(&sessions)))
+}
+
+async fn get_request_log(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(requester): AuthenticatedPlayer,
+) -> Result<Json<RequestLogResponse>, StatusCode> {
+    let sessions = sessions.lock();
+    if !sessions.is_admin(&requester) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(Json(RequestLogResponse {
+        enabled: sessions.request_log().is_enabled(),
+    }))
+}
+
+async fn set_request_log(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(requester): AuthenticatedPlayer,
+    Json(req): Json<RequestLogRequest>,
+) -> Result<Json<RequestLogResponse>, StatusCode> {
+    let sessions = sessions.lock();
+    if !sessions.is_admin(&requester) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    sessions.request_log().set_enabled(req.enabled);
+    tracing::info!("{requester} turned request logging {}", if req.enabled { "on" } else { "off" });
+    Ok(Json(RequestLogResponse { enabled: req.enabled }))
+}
+
+async fn get_retention(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(requester): AuthenticatedPlayer,
+) -> Result<Json<RetentionResponse>, StatusCode> {
+    let sessions = sessions.lock();
+    if !sessions.is_admin(&requester) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let runs = sessions
+        .storage
+        .retention_runs(RETENTION_RUNS)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let policy = sessions.retention();
+    Ok(Json(RetentionResponse {
+        keep_days: policy.map(|policy| policy.keep_days),
+        dry_run: policy.is_some_and(|policy| policy.dry_run),
+        runs,
+    }))
+}
+
+/// Queues a run of the retention policy, over the days given or else the configured ones.
+async fn run_retention(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(requester): AuthenticatedPlayer,
+    Json(req): Json<RetentionRequest>,
+) -> Result<(StatusCode, Json<Job>), StatusCode> {
+    let sessions = sessions.lock();
+    if !sessions.is_admin(&requester) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let keep_days = req
+        .keep_days
+        .or(sessions.retention().map(|policy| policy.keep_days))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let task = Task::Prune {
+        keep_days,
+        dry_run: req.dry_run,
+    };
+    let job_id = sessions.enqueue_job(&task).map_err(|e| error_status(&e))?;
+    tracing::info!(
+        "{requester} started a retention run over {keep_days} days{}",
+        if req.dry_run { ", as a dry run" } else { "" }
+    );
+    match sessions.storage.job(job_id) {
+        Ok(Some(job)) => Ok((StatusCode::ACCEPTED, Json(job))),
+        Ok(None) | Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+fn maintenance_response(sessions: &Sessions) -> MaintenanceResponse {
+    let Some(maintenance) = sessions.maintenance() else {
+        return MaintenanceResponse::default();
+    };
+    MaintenanceResponse {
+        enabled: true,
+        deadline_secs: sessions.maintenance_time_left().map(|left| left.as_secs()),
+        restart: maintenance.restart,
+        live_games: sessions.live_games().into_iter().map(str::to_string).collect(),
+        drained: sessions.is_drained(),
+    }
+}
+
+/// Resolves once the server should stop: on Ctrl-C or SIGTERM, or once maintenance asking
+/// for a restart has drained it. The sockets still open are then closed, so the server can
+/// shut down gracefully.
+pub async fn shutdown_signal(sessions: Arc<Mutex<Sessions>>) {
+    let drained = async {
+        let mut interval = tokio::time::interval(DRAIN_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let sessions = sessions.lock();
+            if sessions.maintenance().is_some_and(|maintenance| maintenance.restart) && sessions.is_drained() {
+                break;
+            }
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        () = drained => tracing::info!("Drained for maintenance, shutting down"),
+        _ = tokio::signal::ctrl_c() => tracing::info!("Interrupted, shutting down"),
+        () = terminate => tracing::info!("Terminated, shutting down"),
+    }
+    sessions.lock().close_sockets();
+}
+
+async fn get_metadata(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
+) -> Result<Json<BTreeMap<String, String>>, StatusCode> {
+    lock_game(&sessions, &id)?
+        .metadata(&id)
+        .map(Json)
+        .map_err(|e| error_status(&e))
+}
+
+async fn update_metadata(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+    Json(updates): Json<BTreeMap<String, Option<String>>>,
+) -> Result<Json<BTreeMap<String, String>>, StatusCode> {
+    lock_game(&sessions, &id)?
+        .update_metadata(&id, &player, &updates)
+        .map(Json)
+        .map_err(|e| error_status(&e))
+}
+
+async fn delete_match(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+) -> Result<StatusCode, StatusCode> {
+    lock_game(&sessions, &id)?
+        .remove_game(&id, &player)
+        .map(|()| StatusCode::NO_CONTENT)
+        .map_err(|e| error_status(&e))
+}
+
+async fn star_match(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+) -> Result<StatusCode, StatusCode> {
+    lock_game(&sessions, &id)?
+        .star_game(&id, &player, true)
+        .map(|_| StatusCode::NO_CONTENT)
+        .map_err(|e| error_status(&e))
+}
+
+async fn unstar_match(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+) -> Result<StatusCode, StatusCode> {
+    lock_game(&sessions, &id)?
+        .star_game(&id, &player, false)
+        .map(|_| StatusCode::NO_CONTENT)
+        .map_err(|e| error_status(&e))
+}
+
+/// Locks the sessions with the game in memory, loading it if it had finished before the
+/// server started.
+fn lock_game<'a>(sessions: &'a Mutex<Sessions>, id: &str) -> Result<MutexGuard<'a, Sessions>, StatusCode> {
+    let mut sessions = sessions.lock();
+    sessions.load_game(id).map_err(|e| error_status(&e))?;
+    Ok(sessions)
+}
+
+fn build_state(sessions: &Sessions, id: &str, query: StateQuery) -> Option<GameStateResponse> {
+    let game = sessions.get_game(id)?;
+    let (player1, player2) = sessions.get_players(id)?;
+    let board = game_to_board(game);
+    let resigned = sessions.resigned(id);
+    let legal_moves = if sessions.is_over(id) {
+        Vec::new()
+    } else {
+        game.legal_moves().iter().map(|p| Game::pos_to_coord(*p)).collect()
+    };
+    let current_player = color_name(game.current_player);
+    let scoring_rule = sessions.scoring_rule();
+    let winner = sessions.winner(id).map(color_name);
+    let final_scores = ScoringRule::ALL
+        .iter()
+        .map(|rule| (rule.as_str().to_string(), scores_map(game.final_scores(*rule))))
+        .collect();
+    let last_move = sessions.last_move(id);
+    let explanations = query.explain.then(|| explain_empty_squares(game));
+    let hints = query.hints.then(|| {
+        eval::move_preferences(&HeuristicEvaluator::default(), game)
+            .into_iter()
+            .map(|(pos, preference)| (Game::pos_to_coord(pos), preference))
+            .collect()
+    });
+    Some(GameStateResponse {
+        board,
+        position: Some(game.clone()),
+        current_player,
+        legal_moves,
+        game_over: sessions.is_over(id),
+        ply: sessions.ply(id).unwrap_or_default(),
+        winner,
+        resigned: resigned.map(color_name),
+        aborted: sessions.is_aborted(id),
+        drawn: sessions.is_drawn(id),
+        player1: player1.clone(),
+        player2: player2.clone(),
+        scores: scores_map(game.scores()),
+        scoring_rule: scoring_rule.as_str().to_string(),
+        final_scores,
+        komi: sessions.komi(id),
+        game_type: sessions.game_type(id),
+        coach: sessions.is_coached(id),
+        phase: game.phase().as_str().to_string(),
+        last_move: last_move.map(|(mv, _)| mv.to_string()),
+        flipped: last_move.map(|(_, flipped)| Game::bitboard_to_coords(flipped)).unwrap_or_default(),
+        flip_lines: match last_move {
+            Some((Move::Place(pos), flipped)) => FlippedLine::from_lines(&flip_lines(pos, flipped)),
+            _ => Vec::new(),
+        },
+        forced_passes: sessions.forced_passes(id).iter().copied().map(color_name).collect(),
+        presence: PresenceResponse {
+            spectators: sessions.spectators(id),
+            player1_connected: sessions.is_connected(id, player1),
+            player2_connected: sessions.is_connected(id, player2),
+            countdowns: sessions.countdowns(id),
+        },
+        explanations,
+        hints,
+    })
+}
+
+fn scores_map((black, white): (u32, u32)) -> HashMap<String, u32> {
+    let mut scores = HashMap::new();
+    scores.insert("B".to_string(), black);
+    scores.insert("W".to_string(), white);
+    scores
+}
+
+/// Explains every empty square for the player to move, for the teaching UI.
+fn explain_empty_squares(game: &Game) -> Vec<MoveExplanationResponse> {
+    (0..64)
+        .filter_map(|pos| game.explain_move(pos).ok())
+        .filter(|explanation| !explanation.occupied)
+        .map(|explanation| explanation_response(&explanation))
+        .collect()
+}
+
+fn explanation_response(explanation: &MoveExplanation) -> MoveExplanationResponse {
+    let directions = explanation
+        .rays
+        .iter()
+        .map(|ray| RayExplanationResponse {
+            direction: ray.direction.as_str().to_string(),
+            discs: Game::bitboard_to_coords(ray.discs),
+            outcome: match ray.outcome {
+                RayOutcome::Flips => "flips",
+                RayOutcome::OwnDiscAdjacent => "own_disc_adjacent",
+                RayOutcome::EmptyAdjacent => "empty_adjacent",
+                RayOutcome::Edge => "edge",
+                RayOutcome::Unbracketed => "unbracketed",
+                RayOutcome::RunsOffBoard => "runs_off_board",
+            }
+            .to_string(),
+        })
+        .collect();
+    MoveExplanationResponse {
+        coord: Game::pos_to_coord(explanation.pos),
+        legal: explanation.is_legal(),
+        flips: Game::bitboard_to_coords(explanation.flips()),
+        directions,
+    }
+}
+
+async fn join_matchmaking(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+) -> Result<Json<JoinResponse>, StatusCode> {
+    let matched = Sessions::join_shared_matchmaking(&sessions, player).map_err(|e| error_status(&e))?;
+    if let Some(id) = matched {
+        Ok(Json(JoinResponse {
+            matched: true,
+            id: Some(id),
+        }))
+    } else {
+        Ok(Json(JoinResponse {
+            matched: false,
+            id: None,
+        }))
+    }
+}
+
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
+    Query(query): Query<StateQuery>,
+    Query(auth): Query<SocketQuery>,
+) -> Result<axum::response::Response, StatusCode> {
+    let player = match (auth.ticket, auth.token) {
+        (Some(ticket), _) => Some(
+            sessions
+                .lock()
+                .redeem_ticket(&ticket)
+                .ok_or(StatusCode::UNAUTHORIZED)?,
+        ),
+        (None, Some(token)) => Some(
+            Auth::validate_token(&token)
+                .map_err(|_| StatusCode::UNAUTHORIZED)?
+                .sub,
+        ),
+        (None, None) => None,
+    };
+    #[cfg(feature = "chaos")]
+    let faults = sessions.lock().faults().clone();
+    Ok(ws.on_upgrade(move |socket| async move {
+        let relay = handle_socket(socket, sessions, id, query, player);
+        #[cfg(feature = "chaos")]
+        crate::chaos::with_socket_faults(faults, relay).await;
+        #[cfg(not(feature = "chaos"))]
+        relay.await;
+    }))
+}
+
+/// Relays a game over a socket: plays the moves sent on it, and pushes every change to the
+/// game, with the extras requested in `query`, and the game's chat. `player` is who the
+/// socket was opened by, if it gave a token.
+async fn handle_socket(
+    mut socket: WebSocket,
+    sessions: Arc<Mutex<Sessions>>,
+    id: String,
+    query: StateQuery,
+    player: Option<String>,
+) {
+    let joined = {
+        let mut sessions = sessions.lock();
+        sessions.load_game(&id).and_then(|()| sessions.join(&id, player.as_deref()))
+    };
+    let mut events = match joined {
+        Ok(events) => events,
+        Err(e) => {
+            close_with_error(socket, close_code::POLICY, e.notice()).await;
+            return;
+        }
+    };
+    let _presence = LeaveOnDrop {
+        sessions: sessions.clone(),
+        id: id.clone(),
+        player: player.clone(),
+    };
+    // Send initial state right after connection
+    send_state(&mut socket, &sessions, &id, query).await;
+
+    loop {
+        tokio::select! {
+            message = socket.recv() => {
+                let Some(Ok(message)) = message else {
+                    return;
+                };
+                let Message::Text(text) = message else {
+                    continue;
+                };
+                let message = match parse_client_message(&text) {
+                    Ok(ClientMsg::Ping) => {
+                        if send_message(&mut socket, ServerMsg::Pong).await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                    Ok(message) => message,
+                    Err(e) => {
+                        if send_message(&mut socket, ServerMsg::Error(e)).await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                };
+                let attempt = message.played();
+                let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+                    #[cfg(feature = "chaos")]
+                    crate::chaos::handle_message();
+                    apply_client_message(&sessions, &id, player.as_deref(), message)
+                }));
+                let Ok(mut result) = outcome else {
+                    tracing::error!("Handling a message for {id} panicked, closing the socket");
+                    close_with_error(socket, close_code::ERROR, Notice::new(MessageCode::InternalError)).await;
+                    return;
+                };
+                // Errors of the AI's reply are not the move's.
+                let rejected = attempt.filter(|_| result.is_err());
+                if let Ok(true) = result {
+                    result = play_ai_reply(&sessions, &id).await.map(|_| true);
+                }
+                match result {
+                    // The new state arrives with the game's events.
+                    Ok(_) => {}
+                    Err(SessionError::Ai(e)) => {
+                        tracing::error!("AI search for {id} failed, closing the socket: {e}");
+                        close_with_error(socket, close_code::ERROR, Notice::new(MessageCode::InternalError)).await;
+                        return;
+                    }
+                    Err(e) => {
+                        let error = rejected
+                            .map_or_else(|| ServerMsg::Error(e.notice()), |attempt| rejection(&sessions, &id, attempt, &e));
+                        if send_message(&mut socket, error).await.is_err() {
+                            return;
+                        }
+                        send_state(&mut socket, &sessions, &id, query).await;
+                    }
+                }
+            }
+            event = events.recv() => {
+                let message = match event {
+                    Ok(event) => match event_message(&sessions, player.as_deref(), event) {
+                        Some(message) => message,
+                        None => continue,
+                    },
+                    // Missed events are caught up on by the latest state.
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        send_state(&mut socket, &sessions, &id, query).await;
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                // Everything but chat, notices and coaching changes the state, countdowns
+                // included.
+                let changed = !matches!(
+                    message,
+                    ServerMsg::Chat { .. } | ServerMsg::Status(_) | ServerMsg::Coach(_)
+                );
+                if send_message(&mut socket, message).await.is_err() {
+                    return;
+                }
+                if changed {
+                    send_state(&mut socket, &sessions, &id, query).await;
+                }
+            }
+        }
+    }
+}
+
+/// Records a socket leaving its game when dropped, however the socket ends.
+struct LeaveOnDrop {
+    sessions: Arc<Mutex<Sessions>>,
+    id: String,
+    player: Option<String>,
+}
+
+impl Drop for LeaveOnDrop {
+    fn drop(&mut self) {
+        let grace = self.sessions.lock().leave(&self.id, self.player.as_deref());
+        let (Some(grace), Some(player)) = (grace, self.player.take()) else {
+            return;
+        };
+        // The player loses the game unless they reconnect in time.
+        let (sessions, id) = (self.sessions.clone(), std::mem::take(&mut self.id));
+        tokio::spawn(async move {
+            tokio::time::sleep(grace).await;
+            match sessions.lock().expire_disconnection(&id, &player) {
+                Ok(Some(abandonment)) => tracing::info!("{player} stayed disconnected from {id}: {abandonment:?}"),
+                Ok(None) => {}
+                Err(e) => tracing::error!("Failed to end {id} after {player} disconnected: {e}"),
+            }
+        });
+    }
+}
+
+/// Turns a game event into the message for a socket opened by `player`, or `None` if the
+/// event is not for them: chat from someone they blocked, or coaching meant for someone else.
+fn event_message(sessions: &Mutex<Sessions>, player: Option<&str>, event: GameEvent) -> Option<ServerMsg> {
+    let message = match event {
+        GameEvent::Delta(delta) => ServerMsg::Delta(delta),
+        GameEvent::Joined(watcher) => ServerMsg::Joined(watcher),
+        GameEvent::Left(watcher) => ServerMsg::Left(watcher),
+        GameEvent::Countdown(countdown) => ServerMsg::Countdown(countdown),
+        GameEvent::Notice(notice) => ServerMsg::Status(notice),
+        GameEvent::Coach { player: coached, analysis } => {
+            if player != Some(coached.as_str()) {
+                return None;
+            }
+            ServerMsg::Coach(analysis)
+        }
+        GameEvent::Chat { from, text } => {
+            if hides_chat_from(sessions, player, &from) {
+                return None;
+            }
+            ServerMsg::Chat { from, text }
+        }
+    };
+    Some(message)
+}
+
+/// Returns true if the socket's player has blocked the sender of a chat message, or been
+/// blocked by them.
+fn hides_chat_from(sessions: &Mutex<Sessions>, player: Option<&str>, from: &str) -> bool {
+    player.is_some_and(|player| {
+        sessions
+            .lock()
+            .storage
+            .is_blocked(player, from)
+            .unwrap_or(false)
+    })
+}
+
+/// Applies a message sent over a socket. Returns true if it changed the game, so the AI may
+/// have to reply.
+fn apply_client_message(
+    sessions: &Mutex<Sessions>,
+    id: &str,
+    player: Option<&str>,
+    message: ClientMsg,
+) -> Result<bool, SessionError> {
+    let mut sessions = sessions.lock();
+    // Sockets of the game's players move for them. Other sockets move for whoever is to
+    // move, unless the AI or an engine is.
+    let mover = |sessions: &Sessions| match player {
+        Some(player) if sessions.get_players(id).is_some_and(|(p1, p2)| player == p1 || player == p2) => {
+            Ok(player.to_string())
+        }
+        _ => match sessions.player_to_move(id) {
+            Some(bot) if sessions.is_bot(bot) => Err(SessionError::NotYourTurn),
+            to_move => Ok(to_move.unwrap_or_default().to_string()),
+        },
+    };
+    match message {
+        ClientMsg::Move { coord, ply } => {
+            let player = mover(&sessions)?;
+            submit_move(&mut sessions, id, &player, Move::Place(coord.pos()), ply).map(|_| true)
+        }
+        ClientMsg::Pass { ply } => {
+            let player = mover(&sessions)?;
+            submit_move(&mut sessions, id, &player, Move::Pass, ply).map(|_| true)
+        }
+        ClientMsg::Resign => {
+            let player = player.ok_or(SessionError::NotLoggedIn)?;
+            sessions.resign(id, player).map(|_| false)
+        }
+        ClientMsg::Chat { text } => {
+            let player = player.ok_or(SessionError::NotLoggedIn)?;
+            sessions.chat(id, player, &text).map(|()| false)
+        }
+        ClientMsg::Ping => Ok(false),
+    }
+}
+
+/// Tells a socket why the move or pass it sent was not played, or passes the error on as is
+/// when the move is not to blame, e.g. the database failing.
+fn rejection(sessions: &Mutex<Sessions>, id: &str, played: Move, error: &SessionError) -> ServerMsg {
+    let (reason, notice) = match error {
+        // Moves in a finished game are refused as out of turn.
+        SessionError::NotYourTurn if sessions.lock().is_over(id) => {
+            (RejectionReason::GameOver, SessionError::GameOver.notice())
+        }
+        SessionError::NotYourTurn => (RejectionReason::NotYourTurn, error.notice()),
+        SessionError::InvalidMove(_) | SessionError::PassNotAllowed => (RejectionReason::Illegal, error.notice()),
+        SessionError::GameOver => (RejectionReason::GameOver, error.notice()),
+        SessionError::Conflict => (RejectionReason::Conflict, error.notice()),
+        SessionError::Superseded { .. } => (RejectionReason::Superseded, error.notice()),
+        SessionError::PlyNotReached(_) => (RejectionReason::OutOfSync, error.notice()),
+        _ => return ServerMsg::Error(error.notice()),
+    };
+    let coord = match played {
+        Move::Place(pos) => MoveCoord::new(pos),
+        Move::Pass => None,
+    };
+    ServerMsg::Rejected(MoveRejection { coord, reason, notice })
+}
+
+fn board_error_status(error: &BoardError) -> StatusCode {
+    match error {
+        BoardError::NotFound => StatusCode::NOT_FOUND,
+        BoardError::NotTheOwner => StatusCode::FORBIDDEN,
+        BoardError::InvalidName
+        | BoardError::TooManyMembers
+        | BoardError::TooManyBoards
+        | BoardError::InvalidMove(_)
+        | BoardError::InvalidPosition(_)
+        | BoardError::NothingToUndo => StatusCode::BAD_REQUEST,
+    }
+}
+
+fn board_state(id: &str, board: &AnalysisBoard) -> BoardStateResponse {
+    let game = board.game();
+    BoardStateResponse {
+        id: id.to_string(),
+        name: board.name().to_string(),
+        owner: board.owner().to_string(),
+        members: board.members().map(str::to_string).collect(),
+        board: game_to_board(game),
+        position: game.to_position_string(),
+        current_player: color_name(game.current_player),
+        legal_moves: game.legal_moves_iter().map(Game::pos_to_coord).collect(),
+        scores: scores_map(game.scores()),
+        game_over: game.is_game_over(),
+        undo_depth: board.undo_depth(),
+    }
+}
+
+/// Lists the analysis boards shared with the player, by name.
+async fn list_boards(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+) -> Json<Vec<BoardStateResponse>> {
+    let sessions = sessions.lock();
+    let mut boards: Vec<_> = sessions
+        .boards()
+        .of(&player)
+        .map(|(id, board)| board_state(id, board))
+        .collect();
+    boards.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+    Json(boards)
+}
+
+/// Opens an analysis board with the starting position, shared with the users invited.
+async fn create_board(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+    Json(req): Json<NewBoardRequest>,
+) -> Result<(StatusCode, Json<BoardStateResponse>), StatusCode> {
+    let mut sessions = sessions.lock();
+    let boards = sessions.boards_mut();
+    let id = boards
+        .create(&player, &req.name, &req.invite)
+        .map_err(|e| board_error_status(&e))?;
+    let board = boards.get(&id, &player).map_err(|e| board_error_status(&e))?;
+    tracing::info!("{player} opened analysis board {id}");
+    Ok((StatusCode::CREATED, Json(board_state(&id, board))))
+}
+
+async fn get_board(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+) -> Result<Json<BoardStateResponse>, StatusCode> {
+    let sessions = sessions.lock();
+    let board = sessions.boards().get(&id, &player).map_err(|e| board_error_status(&e))?;
+    Ok(Json(board_state(&id, board)))
+}
+
+/// Shares an analysis board with one more user, which only its owner may do.
+async fn invite_to_board(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+    Json(req): Json<BoardInviteRequest>,
+) -> Result<StatusCode, StatusCode> {
+    let mut sessions = sessions.lock();
+    let board = sessions
+        .boards_mut()
+        .get_mut(&id, &player)
+        .map_err(|e| board_error_status(&e))?;
+    if board.owner() != player {
+        return Err(board_error_status(&BoardError::NotTheOwner));
+    }
+    if board.invite(&req.player).map_err(|e| board_error_status(&e))? {
+        board.publish(BoardServerMsg::State(Box::new(board_state(&id, board))));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Closes an analysis board, and the sockets open on it.
+async fn close_board(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+) -> Result<StatusCode, StatusCode> {
+    sessions
+        .lock()
+        .boards_mut()
+        .close(&id, &player)
+        .map_err(|e| board_error_status(&e))?;
+    tracing::info!("{player} closed analysis board {id}");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Opens a socket on an analysis board. Unlike a game's, it needs the token of one of the
+/// board's members.
+async fn board_ws_handler(
+    ws: WebSocketUpgrade,
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<String>,
+    Query(auth): Query<SocketQuery>,
+) -> Result<axum::response::Response, StatusCode> {
+    let player = auth
+        .token
+        .and_then(|token| Auth::validate_token(&token).ok())
+        .ok_or(StatusCode::UNAUTHORIZED)?
+        .sub;
+    let events = sessions
+        .lock()
+        .boards()
+        .get(&id, &player)
+        .map_err(|e| board_error_status(&e))?
+        .subscribe();
+    Ok(ws.on_upgrade(move |socket| handle_board_socket(socket, sessions, id, player, events)))
+}
+
+/// Relays an analysis board over a member's socket, then tells the other members they left.
+async fn handle_board_socket(
+    mut socket: WebSocket,
+    sessions: Arc<Mutex<Sessions>>,
+    id: String,
+    player: String,
+    events: broadcast::Receiver<BoardServerMsg>,
+) {
+    let state = {
+        let sessions = sessions.lock();
+        let Ok(board) = sessions.boards().get(&id, &player) else {
+            return;
+        };
+        board.publish(BoardServerMsg::Joined { name: player.clone() });
+        board_state(&id, board)
+    };
+    if send_message(&mut socket, BoardServerMsg::State(Box::new(state))).await.is_ok() {
+        relay_board(&mut socket, &sessions, &id, &player, events).await;
+    }
+    if let Ok(board) = sessions.lock().boards().get(&id, &player) {
+        board.publish(BoardServerMsg::Left { name: player });
+    }
+}
+
+/// Applies the changes sent on the socket and pushes every member's changes and evaluations,
+/// until the socket or the board is closed.
+async fn relay_board(
+    socket: &mut WebSocket,
+    sessions: &Arc<Mutex<Sessions>>,
+    id: &str,
+    player: &str,
+    mut events: broadcast::Receiver<BoardServerMsg>,
+) {
+    loop {
+        tokio::select! {
+            message = socket.recv() => {
+                let Some(Ok(message)) = message else {
+                    return;
+                };
+                let Message::Text(text) = message else {
+                    continue;
+                };
+                let reply = match parse_board_message(&text) {
+                    Ok(BoardClientMsg::Ping) => Some(BoardServerMsg::Pong),
+                    // The new state arrives with the board's events.
+                    Ok(message) => apply_board_message(sessions, id, player, message)
+                        .err()
+                        .map(|e| BoardServerMsg::Error(e.notice())),
+                    Err(e) => Some(BoardServerMsg::Error(e)),
+                };
+                if let Some(reply) = reply {
+                    if send_message(socket, reply).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            event = events.recv() => {
+                let message = match event {
+                    Ok(message) => message,
+                    // Missed changes are caught up on by the latest state.
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        let sessions = sessions.lock();
+                        let Ok(board) = sessions.boards().get(id, player) else {
+                            return;
+                        };
+                        BoardServerMsg::State(Box::new(board_state(id, board)))
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        let frame = CloseFrame {
+                            code: close_code::NORMAL,
+                            reason: Notice::new(MessageCode::BoardNotFound).to_string().into(),
+                        };
+                        let _ = socket.send(Message::Close(Some(frame))).await;
+                        return;
+                    }
+                };
+                if send_message(socket, message).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Applies a change sent by a member over a board's socket, and sends the new state to every
+/// member. Evaluations run in the background and are sent to every member once done.
+fn apply_board_message(
+    sessions: &Arc<Mutex<Sessions>>,
+    id: &str,
+    player: &str,
+    message: BoardClientMsg,
+) -> Result<(), BoardError> {
+    let mut guard = sessions.lock();
+    let board = guard.boards_mut().get_mut(id, player)?;
+    match message {
+        BoardClientMsg::Move { coord, color } => board.play(coord.pos(), color)?,
+        BoardClientMsg::Pass => board.pass(),
+        BoardClientMsg::Undo => board.undo()?,
+        BoardClientMsg::Setup { position } => board.set_position(&position)?,
+        BoardClientMsg::Evaluate => {
+            let game = board.game().clone();
+            tokio::spawn(evaluate_board(sessions.clone(), id.to_string(), player.to_string(), game));
+            return Ok(());
+        }
+        BoardClientMsg::Ping => return Ok(()),
+    }
+    board.publish(BoardServerMsg::State(Box::new(board_state(id, board))));
+    Ok(())
+}
+
+/// Evaluates a board's position on the blocking thread pool, so the server keeps answering
+/// while it runs, and sends the evaluation to every member if the board is still open.
+async fn evaluate_board(sessions: Arc<Mutex<Sessions>>, id: String, player: String, game: Game) {
+    let position = game.to_position_string();
+    let evaluation = tokio::task::spawn_blocking(move || analysis::evaluate(&game, &AnalysisConfig::default())).await;
+    let Ok(evaluation) = evaluation else {
+        tracing::error!("Evaluating a position on analysis board {id} panicked");
+        return;
+    };
+    if let Ok(board) = sessions.lock().boards().get(&id, &player) {
+        board.publish(BoardServerMsg::Evaluation { position, evaluation });
+    }
+}
+
+/// Sends a message. It is taken by value: a reference to a game held across the send would
+/// keep the socket's future from being `Send`, as games cache their legal moves in a `Cell`.
+async fn send_message(socket: &mut WebSocket, message: impl Serialize) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(&message).expect("server messages always serialize");
+    #[cfg(feature = "chaos")]
+    if !crate::chaos::deliver_frame().await {
+        return Ok(());
+    }
+    socket.send(Message::Text(text)).await
+}
+
+/// Sends an error message and closes the socket, with the error in English as the reason.
+async fn close_with_error(mut socket: WebSocket, code: u16, notice: Notice) {
+    let reason = notice.to_string();
+    if send_message(&mut socket, ServerMsg::Error(notice)).await.is_ok() {
+        let frame = CloseFrame {
+            code,
+            reason: reason.into(),
+        };
+        let _ = socket.send(Message::Close(Some(frame))).await;
+    }
+}
+
+async fn send_state(socket: &mut WebSocket, sessions: &Arc<Mutex<Sessions>>, id: &str, query: StateQuery) {
+    let Some(state) = build_state(&sessions.lock(), id, query) else {
+        return;
+    };
+    let must_pass = state.legal_moves.is_empty();
+    if send_message(socket, ServerMsg::State(Box::new(state))).await.is_err() {
+        return;
+    }
+    if must_pass {
+        let status = ServerMsg::Status(Notice::new(MessageCode::MustPass));
+        let _ = send_message(socket, status).await;
+    }
+}
+
+async fn get_messages() -> Json<MessageCatalog> {
+    Json(MessageCatalog::english())
+}
+
+async fn get_engines(State(sessions): State<Arc<Mutex<Sessions>>>) -> Json<EnginesResponse> {
+    let sessions = sessions.lock();
+    Json(EnginesResponse {
+        engines: sessions.engines().into_iter().map(str::to_string).collect(),
+    })
+}
+
+async fn get_leaderboard(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Query(query): Query<LeaderboardQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let bots = query.bots.unwrap_or(true);
+    let (stats, version, engines) = {
+        let mut sessions = sessions.lock();
+        let stats = sessions.leaderboard().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let engines: HashSet<String> = sessions.engines().into_iter().map(str::to_string).collect();
+        (stats, sessions.leaderboard_version(), engines)
+    };
+    // The two listings of the same leaderboard are told apart.
+    let etag = if bots {
+        format!("\"{version}\"")
+    } else {
+        format!("\"{version}-people\"")
+    };
+    if etag_matches(&headers, &etag) {
+        return Ok(not_modified(&etag));
+    }
+    // Copied once the lock is released, so polling dashboards hold it up no longer than a
+    // cache hit takes.
+    let stats: Vec<PlayerStats> = stats
+        .iter()
+        .filter(|stats| bots || !(is_builtin_ai(&stats.name) || engines.contains(&stats.name)))
+        .cloned()
+        .collect();
+    Ok(tagged(&etag, Json(stats)))
+}
+
+async fn get_server_stats(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+) -> Result<Json<ServerStats>, StatusCode> {
+    let sessions = sessions.lock();
+    let stats = sessions
+        .storage
+        .server_stats()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(stats))
+}
+
+async fn get_matchup(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Query(query): Query<MatchupQuery>,
+) -> Result<Json<MatchupResponse>, StatusCode> {
+    let sessions = sessions.lock();
+    build_matchup(&sessions.storage, query)
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn build_matchup(storage: &Storage, query: MatchupQuery) -> rusqlite::Result<MatchupResponse> {
+    let player1_rating = storage.current_rating(&query.player1)?;
+    let player2_rating = storage.current_rating(&query.player2)?;
+    Ok(MatchupResponse {
+        expected_score: RatingConfig::expected_score(player1_rating, player2_rating),
+        head_to_head: storage.head_to_head(&query.player1, &query.player2)?,
+        player1_form: storage.recent_form(&query.player1, FORM_GAMES)?,
+        player2_form: storage.recent_form(&query.player2, FORM_GAMES)?,
+        player1: query.player1,
+        player2: query.player2,
+        player1_rating,
+        player2_rating,
+    })
+}
+
+async fn get_player(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(name): Path<String>,
+) -> Result<Json<PlayerStats>, StatusCode> {
+    let sessions = sessions.lock();
+    sessions
+        .storage
+        .get_player(&name)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn get_settings(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+) -> Result<Json<PlayerSettings>, StatusCode> {
+    let sessions = sessions.lock();
+    sessions
+        .storage
+        .player_settings(&player)
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn put_settings(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+    Json(settings): Json<PlayerSettings>,
+) -> Result<Json<PlayerSettings>, StatusCode> {
+    let sessions = sessions.lock();
+    sessions
+        .storage
+        .save_player_settings(&player, &settings)
+        .and_then(|()| sessions.storage.player_settings(&player))
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn get_preferences(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+) -> Result<Json<PlayerPreferences>, StatusCode> {
+    let sessions = sessions.lock();
+    sessions
+        .storage
+        .player_preferences(&player)
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn put_preferences(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+    Json(preferences): Json<PlayerPreferences>,
+) -> Result<Json<PlayerPreferences>, StatusCode> {
+    let sessions = sessions.lock();
+    sessions
+        .storage
+        .save_player_preferences(&player, &preferences)
+        .map(|()| Json(preferences))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn list_starred(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    AuthenticatedPlayer(player): AuthenticatedPlayer,
+) -> Result<Json<Vec<StarredGame>>, StatusCode> {
+    let sessions = sessions.lock();
+    sessions
+        .storage
+        .starred_games(&player)
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn get_daily_puzzle(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+) -> Result<Json<PuzzleResponse>, StatusCode> {
+    let day = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() / 86_400);
+    let stored = sessions
+        .lock()
+        .storage
+        .puzzle_for_day(day)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let game = &stored.puzzle.game;
+    Ok(Json(PuzzleResponse {
+        id: stored.id,
+        board: game_to_board(game),
+        current_player: color_name(game.current_player),
+        legal_moves: game.legal_moves_iter().map(Game::pos_to_coord).collect(),
+        difficulty: stored.puzzle.difficulty,
+    }))
+}
+
+async fn attempt_puzzle(
+    State(sessions): State<Arc<Mutex<Sessions>>>,
+    Path(id): Path<i64>,
+    req: Result<Json<MoveRequest>, JsonRejection>,
+) -> Result<Json<PuzzleAttemptResponse>, StatusCode> {
+    let Ok(Json(req)) = req else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    let pos = req.coord.pos();
+    let sessions = sessions.lock();
+    let stored = sessions
+        .storage
+        .load_puzzle(id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let correct = pos == stored.puzzle.solution;
+    sessions
+        .storage
+        .record_puzzle_attempt(id, correct)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(PuzzleAttemptResponse {
+        correct,
+        solution: Game::pos_to_coord(stored.puzzle.solution),
+    }))
 }
 
 fn game_to_board(game: &Game) -> Vec<Vec<String>> {
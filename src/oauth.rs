@@ -0,0 +1,443 @@
+//! Logging in through an external identity provider, e.g. Google over `OpenID` Connect or
+//! GitHub over plain OAuth 2.0.
+//!
+//! Only built with the `oauth` feature. The server sends the browser to the provider's
+//! consent page and trades the code it comes back with for who the user is. Providers with
+//! an issuer are `OpenID` Connect providers: their endpoints and signing keys are discovered
+//! from the issuer, and the user is taken from the ID token, once its signature, issuer,
+//! audience, expiry and nonce are verified. Other providers are read from their user info
+//! endpoint with the access token. The first time an identity logs in it is linked to a
+//! player name, or to the player who started the login, which it logs in as from then on,
+//! with the same session tokens as a login by name. Names linked to an identity can no
+//! longer be logged in as by name.
+
+use openidconnect::core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata};
+use openidconnect::{
+    AuthorizationCode, ClientId, ClientSecret, CsrfToken, EndpointMaybeSet, EndpointNotSet, EndpointSet, IssuerUrl,
+    Nonce, RedirectUrl, Scope, TokenResponse as _,
+};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// Longest player name suggested from a provider's user info, in characters.
+pub const MAX_SUGGESTED_NAME_LEN: usize = 32;
+/// Cookie tying the state of a login to the browser that started it.
+pub const STATE_COOKIE: &str = "kawio_oauth";
+
+/// An identity provider to log in with, as listed in the file `OAUTH_PROVIDERS` names.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderConfig {
+    /// Name of the provider in the login URLs, e.g. `github`.
+    pub name: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// The issuer of an `OpenID` Connect provider, e.g. `https://accounts.google.com`, which
+    /// its endpoints and signing keys are discovered from. Left out for plain OAuth 2.0
+    /// providers, which give the URLs below instead.
+    #[serde(default)]
+    pub issuer_url: Option<String>,
+    /// The provider's consent page.
+    #[serde(default)]
+    pub authorize_url: Option<String>,
+    /// Where the code is traded for an access token.
+    #[serde(default)]
+    pub token_url: Option<String>,
+    /// Where the access token is traded for who the user is.
+    #[serde(default)]
+    pub userinfo_url: Option<String>,
+    /// Where the provider sends the browser back to, as registered with it: the server's
+    /// `/auth/oauth/{name}/callback`.
+    pub redirect_url: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Field of the user info that identifies the user for good, e.g. `id` on GitHub. Only
+    /// used without an issuer: ID tokens always give the `sub` claim.
+    #[serde(default = "default_subject_field")]
+    pub subject_field: String,
+    /// Field of the user info holding the name suggested for the player, e.g. `login` on
+    /// GitHub. ID tokens give the `preferred_username` claim, or else `name`.
+    #[serde(default = "default_name_field")]
+    pub name_field: String,
+}
+
+fn default_subject_field() -> String {
+    "sub".to_string()
+}
+
+fn default_name_field() -> String {
+    "name".to_string()
+}
+
+/// Reads the providers listed in a JSON file.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or parsed, if a provider has no name or
+/// another provider's, if it has both or neither of an issuer and the URLs of plain OAuth
+/// 2.0, or if one of its URLs is invalid.
+pub fn load_configs(path: &str) -> Result<Vec<ProviderConfig>, String> {
+    let json = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+    let configs: Vec<ProviderConfig> = serde_json::from_str(&json).map_err(|e| format!("Invalid {path}: {e}"))?;
+    let mut names = HashSet::new();
+    for config in &configs {
+        if config.name.is_empty() || !config.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(format!("Invalid provider name {:?}", config.name));
+        }
+        if !names.insert(config.name.as_str()) {
+            return Err(format!("Provider {} is listed twice", config.name));
+        }
+        let endpoints = [&config.authorize_url, &config.token_url, &config.userinfo_url];
+        let plain = endpoints.iter().all(|url| url.is_some());
+        if config.issuer_url.is_some() == plain || !plain && endpoints.iter().any(|url| url.is_some()) {
+            return Err(format!(
+                "Provider {} needs either an issuer_url or an authorize_url, token_url and userinfo_url",
+                config.name
+            ));
+        }
+        let urls = endpoints.into_iter().chain([&config.issuer_url]).flatten();
+        for url in urls.chain([&config.redirect_url]) {
+            Url::parse(url).map_err(|e| format!("Invalid URL {url} for provider {}: {e}", config.name))?;
+        }
+    }
+    Ok(configs)
+}
+
+/// Who a provider says the user is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub provider: String,
+    /// The user's id at the provider, which never changes.
+    pub subject: String,
+    /// The name the provider gives the user, if any.
+    pub name: Option<String>,
+}
+
+impl Identity {
+    /// Returns a player name made from the name the provider gives: letters, digits, `-`,
+    /// `_` and `.` kept, spaces turned into `_`, and at most [`MAX_SUGGESTED_NAME_LEN`]
+    /// characters. Falls back on [`Identity::fallback_name`].
+    #[must_use]
+    pub fn suggested_name(&self) -> String {
+        let name: String = self
+            .name
+            .as_deref()
+            .unwrap_or_default()
+            .trim()
+            .chars()
+            .filter_map(|c| match c {
+                c if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') => Some(c),
+                c if c.is_whitespace() => Some('_'),
+                _ => None,
+            })
+            .take(MAX_SUGGESTED_NAME_LEN)
+            .collect();
+        let name = name.trim_matches('_');
+        if name.is_empty() {
+            return self.fallback_name();
+        }
+        name.to_string()
+    }
+
+    /// Returns a player name made from the provider's name and the subject, for users the
+    /// provider gives no usable name.
+    #[must_use]
+    pub fn fallback_name(&self) -> String {
+        format!("{}-{}", self.provider, self.subject)
+            .chars()
+            .take(MAX_SUGGESTED_NAME_LEN)
+            .collect()
+    }
+}
+
+/// Returns the URL fragment handing a session token to the web client once a login is done,
+/// e.g. `token=...&player=Alice`.
+#[must_use]
+pub fn login_fragment(token: &str, player: &str) -> String {
+    Url::parse_with_params("http://localhost/", [("token", token), ("player", player)])
+        .ok()
+        .and_then(|url| url.query().map(String::from))
+        .unwrap_or_default()
+}
+
+/// The part of a token response that is used.
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// A client of an `OpenID` Connect provider, set up from its discovered metadata.
+type DiscoveredClient =
+    CoreClient<EndpointSet, EndpointNotSet, EndpointNotSet, EndpointNotSet, EndpointMaybeSet, EndpointMaybeSet>;
+
+/// A provider users can log in with.
+#[derive(Debug)]
+pub struct OAuthProvider {
+    config: ProviderConfig,
+    http: reqwest::Client,
+}
+
+impl OAuthProvider {
+    #[must_use]
+    pub fn new(config: ProviderConfig) -> Self {
+        // GitHub refuses requests without a user agent. Redirects are not followed, so a
+        // provider cannot point the server at other hosts.
+        let http = reqwest::Client::builder()
+            .user_agent(concat!("kawio/", env!("CARGO_PKG_VERSION")))
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap_or_default();
+        Self { config, http }
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    #[must_use]
+    pub fn config(&self) -> &ProviderConfig {
+        &self.config
+    }
+
+    /// Returns the URL of the provider's consent page, which sends the browser back to the
+    /// redirect URL with a code and `state`. An `OpenID` Connect provider puts `nonce` in the
+    /// ID token it issues.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metadata of an `OpenID` Connect provider cannot be discovered.
+    pub async fn authorize_url(&self, state: &str, nonce: &str) -> Result<String, String> {
+        if self.config.issuer_url.is_some() {
+            let client = self.discover().await?;
+            let (state, nonce) = (state.to_string(), nonce.to_string());
+            let (url, _, _) = client
+                .authorize_url(
+                    CoreAuthenticationFlow::AuthorizationCode,
+                    move || CsrfToken::new(state),
+                    move || Nonce::new(nonce),
+                )
+                .add_scopes(self.config.scopes.iter().cloned().map(Scope::new))
+                .url();
+            return Ok(url.into());
+        }
+        let authorize_url = self.config.authorize_url.as_deref().unwrap_or_default();
+        let scope = self.config.scopes.join(" ");
+        let params = [
+            ("response_type", "code"),
+            ("client_id", self.config.client_id.as_str()),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("scope", scope.as_str()),
+            ("state", state),
+        ];
+        Url::parse_with_params(authorize_url, params)
+            .map(String::from)
+            .map_err(|e| format!("Invalid authorize URL of {}: {e}", self.config.name))
+    }
+
+    /// Trades the code the browser came back with for the identity of the user. `nonce` is
+    /// the one the consent page was opened with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the provider cannot be reached, refuses the code, or gives no
+    /// subject for the user, or if the ID token of an `OpenID` Connect provider is missing or
+    /// fails verification.
+    pub async fn identify(&self, code: &str, nonce: &str) -> Result<Identity, String> {
+        if self.config.issuer_url.is_some() {
+            return self.identify_by_id_token(code, nonce).await;
+        }
+        let params = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("client_secret", self.config.client_secret.as_str()),
+        ];
+        let token: TokenResponse = self
+            .http
+            .post(self.config.token_url.as_deref().unwrap_or_default())
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&params)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| format!("Token request to {} failed: {e}", self.config.name))?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid token response from {}: {e}", self.config.name))?;
+        let info: Value = self
+            .http
+            .get(self.config.userinfo_url.as_deref().unwrap_or_default())
+            .bearer_auth(&token.access_token)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| format!("User info request to {} failed: {e}", self.config.name))?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid user info from {}: {e}", self.config.name))?;
+        let subject = field_text(&info, &self.config.subject_field)
+            .ok_or_else(|| format!("User info from {} has no {}", self.config.name, self.config.subject_field))?;
+        Ok(Identity {
+            provider: self.config.name.clone(),
+            subject,
+            name: field_text(&info, &self.config.name_field),
+        })
+    }
+
+    /// Trades the code for tokens at an `OpenID` Connect provider and reads the user from the
+    /// ID token, verified against the provider's signing keys, its issuer, the client id as
+    /// audience, and `nonce`.
+    async fn identify_by_id_token(&self, code: &str, nonce: &str) -> Result<Identity, String> {
+        let client = self.discover().await?;
+        let response = client
+            .exchange_code(AuthorizationCode::new(code.to_string()))
+            .map_err(|e| format!("No token endpoint for {}: {e}", self.config.name))?
+            .request_async(&self.http)
+            .await
+            .map_err(|e| format!("Token request to {} failed: {e}", self.config.name))?;
+        let id_token = response
+            .id_token()
+            .ok_or_else(|| format!("Token response from {} has no ID token", self.config.name))?;
+        let claims = id_token
+            .claims(&client.id_token_verifier(), &Nonce::new(nonce.to_string()))
+            .map_err(|e| format!("Invalid ID token from {}: {e}", self.config.name))?;
+        let name = claims
+            .preferred_username()
+            .map(|name| name.to_string())
+            .or_else(|| claims.name().and_then(|name| name.get(None)).map(|name| name.to_string()));
+        Ok(Identity {
+            provider: self.config.name.clone(),
+            subject: claims.subject().to_string(),
+            name,
+        })
+    }
+
+    /// Fetches the metadata and signing keys of an `OpenID` Connect provider. They are fetched
+    /// for every login, so keys the provider rotates are picked up.
+    async fn discover(&self) -> Result<DiscoveredClient, String> {
+        let issuer = self.config.issuer_url.clone().unwrap_or_default();
+        let issuer = IssuerUrl::new(issuer).map_err(|e| format!("Invalid issuer of {}: {e}", self.config.name))?;
+        let metadata = CoreProviderMetadata::discover_async(issuer, &self.http)
+            .await
+            .map_err(|e| format!("Discovery of {} failed: {e}", self.config.name))?;
+        let redirect_url = RedirectUrl::new(self.config.redirect_url.clone())
+            .map_err(|e| format!("Invalid redirect URL of {}: {e}", self.config.name))?;
+        Ok(CoreClient::from_provider_metadata(
+            metadata,
+            ClientId::new(self.config.client_id.clone()),
+            Some(ClientSecret::new(self.config.client_secret.clone())),
+        )
+        .set_redirect_uri(redirect_url))
+    }
+}
+
+/// Returns a field of the user info as text, numbers included, e.g. GitHub's ids.
+fn field_text(info: &Value, field: &str) -> Option<String> {
+    match info.get(field)? {
+        Value::String(text) if !text.is_empty() => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config() -> ProviderConfig {
+        serde_json::from_value(config_json()).unwrap()
+    }
+
+    fn config_json() -> Value {
+        json!({
+            "name": "github",
+            "client_id": "abc",
+            "client_secret": "shh",
+            "authorize_url": "https://github.com/login/oauth/authorize",
+            "token_url": "https://github.com/login/oauth/access_token",
+            "userinfo_url": "https://api.github.com/user",
+            "redirect_url": "https://kawio.example/auth/oauth/github/callback",
+            "scopes": ["read:user"],
+            "subject_field": "id",
+            "name_field": "login"
+        })
+    }
+
+    #[tokio::test]
+    async fn test_authorize_url_carries_the_state() {
+        let provider = OAuthProvider::new(config());
+        let url = Url::parse(&provider.authorize_url("xyz", "123").await.unwrap()).unwrap();
+        let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        assert!(params.contains(&("client_id".to_string(), "abc".to_string())));
+        assert!(params.contains(&("state".to_string(), "xyz".to_string())));
+        assert!(params.contains(&("scope".to_string(), "read:user".to_string())));
+        assert!(!url.as_str().contains("shh"));
+
+        let defaults: ProviderConfig = serde_json::from_value(json!({
+            "name": "google",
+            "client_id": "abc",
+            "client_secret": "shh",
+            "authorize_url": "https://accounts.google.com/o/oauth2/v2/auth",
+            "token_url": "https://oauth2.googleapis.com/token",
+            "userinfo_url": "https://openidconnect.googleapis.com/v1/userinfo",
+            "redirect_url": "https://kawio.example/auth/oauth/google/callback"
+        }))
+        .unwrap();
+        assert_eq!((defaults.subject_field.as_str(), defaults.name_field.as_str()), ("sub", "name"));
+    }
+
+    #[test]
+    fn test_providers_need_an_issuer_or_endpoints() {
+        let path = std::env::temp_dir().join(format!("kawio_oauth_{}.json", std::process::id()));
+        let load = |provider: Value| {
+            std::fs::write(&path, json!([provider]).to_string()).unwrap();
+            load_configs(path.to_str().unwrap())
+        };
+        let github = config_json();
+        assert_eq!(load(github.clone()).unwrap(), [config()]);
+        let google = json!({
+            "name": "google",
+            "client_id": "abc",
+            "client_secret": "shh",
+            "issuer_url": "https://accounts.google.com",
+            "redirect_url": "https://kawio.example/auth/oauth/google/callback",
+            "scopes": ["profile"]
+        });
+        assert!(load(google.clone()).unwrap()[0].userinfo_url.is_none());
+
+        let mut both = github.clone();
+        both["issuer_url"] = json!("https://github.com");
+        let mut partial = google.clone();
+        partial["userinfo_url"] = json!("https://openidconnect.googleapis.com/v1/userinfo");
+        let mut neither = github;
+        neither.as_object_mut().unwrap().remove("token_url");
+        let mut bad_issuer = google;
+        bad_issuer["issuer_url"] = json!("accounts.google.com");
+        for provider in [both, partial, neither, bad_issuer] {
+            assert!(load(provider.clone()).is_err(), "{provider}");
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_names_suggested_from_user_info() {
+        let info = json!({ "id": 583_231, "login": "The Octo-Cat! 🐙", "email": "" });
+        let identity = Identity {
+            provider: "github".to_string(),
+            subject: field_text(&info, "id").unwrap(),
+            name: field_text(&info, "login"),
+        };
+        assert_eq!(identity.subject, "583231");
+        assert_eq!(identity.suggested_name(), "The_Octo-Cat");
+        assert_eq!(field_text(&info, "email"), None);
+        let nameless = Identity { name: None, ..identity };
+        assert_eq!(nameless.suggested_name(), "github-583231");
+        assert_eq!(login_fragment("a.b", "Zoë Q"), "token=a.b&player=Zo%C3%AB+Q");
+    }
+}
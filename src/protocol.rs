@@ -0,0 +1,628 @@
+//! Messages exchanged over a game's WebSocket.
+//!
+//! Every message is a JSON object whose `type` field names the variant, e.g.
+//! `{"type": "move", "coord": "D3"}`. The server and the client library both use these types,
+//! so they cannot drift apart.
+//!
+//! Status and error messages carry a [`MessageCode`] with parameters rather than text, so
+//! clients can show them in their own language with a [`MessageCatalog`].
+//!
+//! Analysis boards have sockets of their own, which exchange [`BoardClientMsg`] and
+//! [`BoardServerMsg`] in the same form.
+
+use crate::analysis::{MoveAnalysis, PositionEvaluation};
+use crate::api::{BoardStateResponse, CountdownResponse, FlippedLine, GameStateResponse, MoveCoord};
+use crate::game::{IllegalMove, Move, Player};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Longest chat message accepted, in characters.
+pub const MAX_CHAT_LEN: usize = 500;
+
+/// A message sent by a client.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMsg {
+    /// Place a disc. With `ply`, the number of moves the client saw played, the move is
+    /// refused if another was played since.
+    Move {
+        coord: MoveCoord,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ply: Option<usize>,
+    },
+    /// Pass, refused like a move if `ply` is given and another move was played since.
+    Pass {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ply: Option<usize>,
+    },
+    /// Resign the game. Only a socket opened with a player's token can resign for them.
+    Resign,
+    /// Say something to everyone watching the game. Needs a token, like resigning.
+    Chat { text: String },
+    /// Ask for a [`ServerMsg::Pong`], e.g. to keep an idle connection open.
+    Ping,
+}
+
+impl ClientMsg {
+    /// Returns the move or pass the message plays, if it plays one.
+    #[must_use]
+    pub fn played(&self) -> Option<Move> {
+        match self {
+            ClientMsg::Move { coord, .. } => Some(Move::Place(coord.pos())),
+            ClientMsg::Pass { .. } => Some(Move::Pass),
+            _ => None,
+        }
+    }
+}
+
+/// A message sent by the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMsg {
+    /// The full game state, sent on connecting and after every change.
+    State(Box<GameStateResponse>),
+    /// What changed in the game, sent just before the new state.
+    Delta(Delta),
+    /// A notice for the player, e.g. that they must pass.
+    Status(Notice),
+    Chat { from: String, text: String },
+    /// Someone opened a socket on the game, or a player reconnected.
+    Joined(Watcher),
+    /// Someone closed their socket, or a player lost their last connection to the game.
+    Left(Watcher),
+    /// A player left a game against another person and must reconnect in time.
+    Countdown(CountdownResponse),
+    /// How good the player's last move was, in a game they are coached in.
+    Coach(MoveAnalysis),
+    Pong,
+    /// The move or pass just sent was not played, followed by the unchanged state.
+    Rejected(MoveRejection),
+    /// The last message was rejected, or the socket is being closed, with the reason.
+    Error(Notice),
+}
+
+/// A move or pass sent over a socket that was not played.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoveRejection {
+    /// The square sent, or `None` for a pass.
+    pub coord: Option<MoveCoord>,
+    pub reason: RejectionReason,
+    /// The error, as an `error` message would give it.
+    #[serde(flatten)]
+    pub notice: Notice,
+}
+
+/// Why a move or pass was not played, for clients to react to without reading the notice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionReason {
+    /// The other side is to move.
+    NotYourTurn,
+    /// The square is not a legal move, or the player may not pass while they have one.
+    Illegal,
+    /// The game has ended.
+    GameOver,
+    /// Another server sharing the database changed the game first.
+    Conflict,
+    /// Another move was played since the ply the client sent, e.g. by a submission racing
+    /// this one for the same turn.
+    Superseded,
+    /// The client sent a ply the game has not reached, so its view of the game is out of
+    /// sync with the server's.
+    OutOfSync,
+}
+
+/// A message sent by a member of an analysis board. Any member may change the board, for
+/// either color.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BoardClientMsg {
+    /// Place a disc for `color`, or for the side to move if it is left out.
+    Move {
+        coord: MoveCoord,
+        #[serde(default)]
+        color: Option<Player>,
+    },
+    /// Hand the turn to the other color.
+    Pass,
+    /// Take back the latest move, pass or setup.
+    Undo,
+    /// Replace the board with a position, as 64 squares from A1 to H8 plus the side to move.
+    Setup { position: String },
+    /// Ask the engine to evaluate the position, for every member to see.
+    Evaluate,
+    Ping,
+}
+
+/// A message sent by the server on an analysis board's socket.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BoardServerMsg {
+    /// The full board, sent on connecting and after every change.
+    State(Box<BoardStateResponse>),
+    /// The engine's evaluation of the position in `position`, asked for by a member.
+    Evaluation {
+        position: String,
+        evaluation: PositionEvaluation,
+    },
+    /// A member opened a socket on the board.
+    Joined { name: String },
+    /// A member closed their socket on the board.
+    Left { name: String },
+    Pong,
+    /// The last message was rejected, or the socket is being closed, with the reason.
+    Error(Notice),
+}
+
+/// What a status or error message says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageCode {
+    /// The player has no legal moves and must pass.
+    MustPass,
+    /// The client's message could not be read; `reason` is the parser's explanation, in
+    /// English.
+    InvalidMessage,
+    GameNotFound,
+    NotYourTurn,
+    /// The square is not on the board.
+    OffBoard,
+    /// A disc is already on the square.
+    SquareOccupied,
+    /// The disc would flip none of the opponent's.
+    NoFlips,
+    /// The move was sent for ply `ply`, which the game has not reached.
+    PlyNotReached,
+    PassNotAllowed,
+    /// Ply `ply` has not been played, so it cannot be annotated.
+    PlyNotPlayed,
+    ChallengeRefused,
+    NotAParticipant,
+    /// Metadata keys are 1 to `max` letters, digits, `_`, `-` or `.`.
+    InvalidMetadataKey,
+    /// The value of `key` is longer than `max` bytes.
+    MetadataValueTooLong,
+    /// A game holds at most `max` metadata entries.
+    TooManyMetadataEntries,
+    GameInProgress,
+    GameOver,
+    NotLoggedIn,
+    EmptyChat,
+    /// Chat messages are at most `max` characters long.
+    ChatTooLong,
+    Conflict,
+    /// `max` is the largest komi allowed either way.
+    InvalidKomi,
+    /// The database could not be read or written; `reason` is the database's explanation,
+    /// in English.
+    StorageError,
+    InternalError,
+    /// An admin's notice to everyone connected; `message` is their text.
+    Maintenance,
+    /// No new games can start while the server is drained for maintenance.
+    UnderMaintenance,
+    CoachNotAllowed,
+    /// The server is drained for a restart once the games being played end.
+    MaintenanceStarted,
+    /// The server is drained for a restart, in at most `seconds` seconds.
+    MaintenanceDeadline,
+    /// The server is stopping and closing the socket.
+    Restarting,
+    NothingToClaim,
+    /// The game cannot be claimed drawn: it is the claimant's turn, or a move was played in
+    /// the last `seconds` seconds.
+    StillActive,
+    /// There is no such analysis board, or it is not shared with the user.
+    BoardNotFound,
+    NotTheBoardOwner,
+    /// Analysis board names have 1 to `max` characters.
+    InvalidBoardName,
+    /// An analysis board has at most `max` members.
+    TooManyBoardMembers,
+    /// A user may have at most `max` analysis boards open.
+    TooManyBoards,
+    /// The position could not be read; `reason` is the parser's explanation, in English.
+    InvalidPosition,
+    NothingToUndo,
+    /// An HTTP request was rejected before it was handled; `reason` is the explanation of
+    /// the parser, in English.
+    InvalidRequest,
+    /// `max` is the largest body the endpoint accepts, in bytes.
+    BodyTooLarge,
+    /// `expected` is the media type the endpoint reads.
+    UnsupportedMediaType,
+    /// The move sent for ply `ply` lost to `move`, played there by `player`.
+    Superseded,
+}
+
+impl MessageCode {
+    pub const ALL: [MessageCode; 43] = [
+        MessageCode::MustPass,
+        MessageCode::InvalidMessage,
+        MessageCode::GameNotFound,
+        MessageCode::NotYourTurn,
+        MessageCode::OffBoard,
+        MessageCode::SquareOccupied,
+        MessageCode::NoFlips,
+        MessageCode::PlyNotReached,
+        MessageCode::PassNotAllowed,
+        MessageCode::PlyNotPlayed,
+        MessageCode::ChallengeRefused,
+        MessageCode::NotAParticipant,
+        MessageCode::InvalidMetadataKey,
+        MessageCode::MetadataValueTooLong,
+        MessageCode::TooManyMetadataEntries,
+        MessageCode::GameInProgress,
+        MessageCode::GameOver,
+        MessageCode::NotLoggedIn,
+        MessageCode::EmptyChat,
+        MessageCode::ChatTooLong,
+        MessageCode::Conflict,
+        MessageCode::InvalidKomi,
+        MessageCode::StorageError,
+        MessageCode::InternalError,
+        MessageCode::Maintenance,
+        MessageCode::UnderMaintenance,
+        MessageCode::MaintenanceStarted,
+        MessageCode::MaintenanceDeadline,
+        MessageCode::Restarting,
+        MessageCode::NothingToClaim,
+        MessageCode::StillActive,
+        MessageCode::CoachNotAllowed,
+        MessageCode::BoardNotFound,
+        MessageCode::NotTheBoardOwner,
+        MessageCode::InvalidBoardName,
+        MessageCode::TooManyBoardMembers,
+        MessageCode::TooManyBoards,
+        MessageCode::InvalidPosition,
+        MessageCode::NothingToUndo,
+        MessageCode::InvalidRequest,
+        MessageCode::BodyTooLarge,
+        MessageCode::UnsupportedMediaType,
+        MessageCode::Superseded,
+    ];
+
+    /// Returns the English template of the message, with its parameters in braces.
+    #[must_use]
+    pub fn english(self) -> &'static str {
+        match self {
+            MessageCode::MustPass => "No legal moves available, you must pass.",
+            MessageCode::InvalidMessage => "Invalid message: {reason}",
+            MessageCode::GameNotFound => "Game not found",
+            MessageCode::NotYourTurn => "Not your turn",
+            MessageCode::OffBoard => "That square is not on the board",
+            MessageCode::SquareOccupied => "That square is already occupied",
+            MessageCode::NoFlips => "A move must flip at least one disc",
+            MessageCode::PlyNotReached => "The game has not reached ply {ply} yet",
+            MessageCode::PassNotAllowed => "You have a legal move and cannot pass",
+            MessageCode::PlyNotPlayed => "Ply {ply} has not been played yet",
+            MessageCode::ChallengeRefused => "The player does not accept your challenges",
+            MessageCode::NotAParticipant => "You are not playing in this game",
+            MessageCode::InvalidMetadataKey => "Metadata keys must be 1 to {max} letters, digits, '_', '-' or '.'",
+            MessageCode::MetadataValueTooLong => "The value of {key} is longer than {max} bytes",
+            MessageCode::TooManyMetadataEntries => "A game holds at most {max} metadata entries",
+            MessageCode::GameInProgress => "The game is not finished yet",
+            MessageCode::GameOver => "The game is already over",
+            MessageCode::NotLoggedIn => "Log in to do that",
+            MessageCode::EmptyChat => "Chat messages cannot be empty",
+            MessageCode::ChatTooLong => "Chat messages are limited to {max} characters",
+            MessageCode::Conflict => "The game was changed meanwhile, try again",
+            MessageCode::InvalidKomi => "Komi must be between -{max} and {max} discs",
+            MessageCode::StorageError => "Storage error: {reason}",
+            MessageCode::InternalError => "Internal server error",
+            MessageCode::Maintenance => "Maintenance notice: {message}",
+            MessageCode::UnderMaintenance => "The server is under maintenance, no new games can start",
+            MessageCode::MaintenanceStarted => {
+                "The server will restart for maintenance once the games being played end. No new games can start."
+            }
+            MessageCode::MaintenanceDeadline => {
+                "The server will restart for maintenance in {seconds} seconds at the latest. No new games can start."
+            }
+            MessageCode::Restarting => "The server is restarting, reconnect in a moment",
+            MessageCode::NothingToClaim => "Your opponent is connected or still has time to reconnect",
+            MessageCode::StillActive => "It is your turn, or a move was played less than {seconds} seconds ago",
+            MessageCode::CoachNotAllowed => "Coaching is only for casual games and games against a bot",
+            MessageCode::BoardNotFound => "Analysis board not found",
+            MessageCode::NotTheBoardOwner => "Only the owner of the board can do that",
+            MessageCode::InvalidBoardName => "Analysis board names must have 1 to {max} characters",
+            MessageCode::TooManyBoardMembers => "An analysis board has at most {max} members",
+            MessageCode::TooManyBoards => "You can have at most {max} analysis boards open",
+            MessageCode::InvalidPosition => "Invalid position: {reason}",
+            MessageCode::NothingToUndo => "There is nothing to undo",
+            MessageCode::InvalidRequest => "Invalid request: {reason}",
+            MessageCode::BodyTooLarge => "The request body is larger than {max} bytes",
+            MessageCode::UnsupportedMediaType => "The request body must be sent as {expected}",
+            MessageCode::Superseded => "Too late: {player} already played {move} at ply {ply}",
+        }
+    }
+}
+
+/// A status or error message, as a code with the parameters filling in its template.
+/// Displays in English.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notice {
+    pub code: MessageCode,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, String>,
+}
+
+impl Notice {
+    #[must_use]
+    pub fn new(code: MessageCode) -> Self {
+        Self {
+            code,
+            params: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the message saying why a disc cannot be placed.
+    #[must_use]
+    pub fn illegal_move(reason: IllegalMove) -> Self {
+        Self::new(match reason {
+            IllegalMove::OffBoard => MessageCode::OffBoard,
+            IllegalMove::Occupied => MessageCode::SquareOccupied,
+            IllegalMove::NoFlips => MessageCode::NoFlips,
+        })
+    }
+
+    /// Sets a parameter of the message.
+    #[must_use]
+    pub fn with(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.params.insert(name.to_string(), value.into());
+        self
+    }
+}
+
+impl fmt::Display for Notice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&render(self.code.english(), &self.params))
+    }
+}
+
+/// Templates of the messages in one language, falling back to English for codes without a
+/// translation.
+///
+/// Templates name parameters in braces, e.g. `"Coup invalide : {reason}"`. Catalogs load
+/// from JSON objects mapping codes to templates, the form `GET /messages` serves the English
+/// ones in.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MessageCatalog {
+    templates: HashMap<MessageCode, String>,
+}
+
+impl MessageCatalog {
+    /// Creates a catalog without translations, which shows every message in English.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the catalog of every English template.
+    #[must_use]
+    pub fn english() -> Self {
+        Self {
+            templates: MessageCode::ALL
+                .iter()
+                .map(|&code| (code, code.english().to_string()))
+                .collect(),
+        }
+    }
+
+    /// Parses a catalog from a JSON object mapping codes to templates.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the JSON is malformed or names an unknown code.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid message catalog: {e}"))
+    }
+
+    /// Sets the template of a message.
+    #[must_use]
+    pub fn with(mut self, code: MessageCode, template: impl Into<String>) -> Self {
+        self.templates.insert(code, template.into());
+        self
+    }
+
+    /// Returns the template of a message.
+    #[must_use]
+    pub fn template(&self, code: MessageCode) -> &str {
+        self.templates.get(&code).map_or(code.english(), String::as_str)
+    }
+
+    /// Returns the text of a notice, with its parameters filled in. Parameters missing from
+    /// the notice are left in braces.
+    #[must_use]
+    pub fn render(&self, notice: &Notice) -> String {
+        render(self.template(notice.code), &notice.params)
+    }
+}
+
+/// Fills the parameters into a template.
+fn render(template: &str, params: &BTreeMap<String, String>) -> String {
+    params.iter().fold(template.to_string(), |text, (name, value)| {
+        text.replace(&format!("{{{name}}}"), value)
+    })
+}
+
+/// A move, pass or early end of the game, as announced to everyone watching it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delta {
+    /// Color that acted, `"Black"` or `"White"`.
+    pub player: String,
+    /// The square played, `"pass"`, `"resign"`, `"forfeit"` or `"abort"` after staying
+    /// disconnected, or `"draw"` when the game is claimed after no move for too long.
+    pub action: String,
+    /// Discs flipped by the square played, empty for any other action.
+    #[serde(default)]
+    pub flipped: Vec<String>,
+    /// The same discs line by line from the square played, to animate them in turn.
+    #[serde(default)]
+    pub flip_lines: Vec<FlippedLine>,
+    /// Colors passed for automatically after the action, in order.
+    pub forced_passes: Vec<String>,
+}
+
+/// Someone watching a game over a socket.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Watcher {
+    /// Name of the logged-in user, or `None` for an anonymous socket.
+    pub name: Option<String>,
+    /// False if the watcher is one of the game's players.
+    pub spectator: bool,
+}
+
+/// Something that happened in a game, for every socket watching it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameEvent {
+    Delta(Delta),
+    Chat { from: String, text: String },
+    Joined(Watcher),
+    Left(Watcher),
+    Countdown(CountdownResponse),
+    /// A notice for every socket, e.g. an admin's maintenance message.
+    Notice(Notice),
+    /// How good a move was, only for the sockets of the player who placed it.
+    Coach { player: String, analysis: MoveAnalysis },
+}
+
+/// Parses a text message received on a game's socket.
+///
+/// # Errors
+///
+/// Returns an error, fit to send back to the client, if the message is not valid JSON, has
+/// an unknown `type`, or names an invalid square.
+pub fn parse_client_message(text: &str) -> Result<ClientMsg, Notice> {
+    serde_json::from_str(text).map_err(|e| Notice::new(MessageCode::InvalidMessage).with("reason", e.to_string()))
+}
+
+/// Parses a text message received on an analysis board's socket.
+///
+/// # Errors
+///
+/// Returns an error, fit to send back to the client, as [`parse_client_message`] does.
+pub fn parse_board_message(text: &str) -> Result<BoardClientMsg, Notice> {
+    serde_json::from_str(text).map_err(|e| Notice::new(MessageCode::InvalidMessage).with("reason", e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+
+    #[test]
+    fn test_client_messages() {
+        let d3 = MoveCoord::new(Game::coord_to_pos("D3").unwrap()).unwrap();
+        assert_eq!(
+            parse_client_message(r#"{"type": "move", "coord": "d3"}"#),
+            Ok(ClientMsg::Move { coord: d3, ply: None })
+        );
+        assert_eq!(
+            parse_client_message(r#"{"type": "move", "coord": "D3", "ply": 4}"#),
+            Ok(ClientMsg::Move { coord: d3, ply: Some(4) })
+        );
+        assert_eq!(parse_client_message(r#"{"type": "pass"}"#), Ok(ClientMsg::Pass { ply: None }));
+        assert_eq!(parse_client_message(r#"{"type": "resign"}"#), Ok(ClientMsg::Resign));
+        assert_eq!(
+            parse_client_message(r#"{"type": "chat", "text": "gg"}"#),
+            Ok(ClientMsg::Chat { text: "gg".to_string() })
+        );
+        assert_eq!(parse_client_message(r#"{"type": "ping"}"#), Ok(ClientMsg::Ping));
+        for bad in [
+            r#"{"type": "move"}"#,
+            r#"{"type": "move", "coord": "Z9"}"#,
+            r#"{"type": "dance"}"#,
+            r#"{"coord": "D3"}"#,
+            "not json",
+        ] {
+            assert!(parse_client_message(bad).is_err(), "{bad}");
+        }
+        let json = serde_json::to_string(&ClientMsg::Move { coord: d3, ply: None }).unwrap();
+        assert_eq!(json, r#"{"type":"move","coord":"D3"}"#);
+
+        assert_eq!(
+            parse_board_message(r#"{"type": "move", "coord": "D3", "color": "White"}"#),
+            Ok(BoardClientMsg::Move {
+                coord: d3,
+                color: Some(Player::White)
+            })
+        );
+        assert_eq!(
+            parse_board_message(r#"{"type": "move", "coord": "D3"}"#),
+            Ok(BoardClientMsg::Move { coord: d3, color: None })
+        );
+        assert_eq!(parse_board_message(r#"{"type": "undo"}"#), Ok(BoardClientMsg::Undo));
+        assert!(parse_board_message(r#"{"type": "resign"}"#).is_err());
+    }
+
+    #[test]
+    fn test_server_messages() {
+        let delta = ServerMsg::Delta(Delta {
+            player: "Black".to_string(),
+            action: "D3".to_string(),
+            flipped: vec!["D4".to_string()],
+            flip_lines: vec![FlippedLine {
+                direction: "N".to_string(),
+                discs: vec!["D4".to_string()],
+            }],
+            forced_passes: Vec::new(),
+        });
+        let json = serde_json::to_value(&delta).unwrap();
+        assert_eq!(json["type"], "delta");
+        assert_eq!(json["action"], "D3");
+        assert_eq!(json["flipped"][0], "D4");
+        assert_eq!(json["flip_lines"][0]["direction"], "N");
+        assert_eq!(serde_json::from_value::<ServerMsg>(json).unwrap(), delta);
+        let error = ServerMsg::Error(Notice::new(MessageCode::GameNotFound));
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"type":"error","code":"game_not_found"}"#
+        );
+        let rejected = ServerMsg::Rejected(MoveRejection {
+            coord: MoveCoord::new(19),
+            reason: RejectionReason::Illegal,
+            notice: Notice::illegal_move(IllegalMove::Occupied),
+        });
+        let json = serde_json::to_string(&rejected).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"rejected","coord":"D6","reason":"illegal","code":"square_occupied"}"#
+        );
+        assert_eq!(serde_json::from_str::<ServerMsg>(&json).unwrap(), rejected);
+        let status = ServerMsg::Status(Notice::new(MessageCode::InvalidKomi).with("max", "32"));
+        assert_eq!(
+            serde_json::to_string(&status).unwrap(),
+            r#"{"type":"status","code":"invalid_komi","params":{"max":"32"}}"#
+        );
+        let left = ServerMsg::Left(Watcher {
+            name: Some("Bob".to_string()),
+            spectator: false,
+        });
+        assert_eq!(
+            serde_json::to_string(&left).unwrap(),
+            r#"{"type":"left","name":"Bob","spectator":false}"#
+        );
+    }
+
+    #[test]
+    fn test_message_catalog() {
+        let notice = Notice::new(MessageCode::PlyNotPlayed).with("ply", "12");
+        assert_eq!(notice.to_string(), "Ply 12 has not been played yet");
+        assert_eq!(MessageCatalog::new().render(&notice), "Ply 12 has not been played yet");
+
+        let french = MessageCatalog::from_json(r#"{"ply_not_played": "Le coup {ply} n'a pas encore été joué"}"#).unwrap();
+        assert_eq!(french.render(&notice), "Le coup 12 n'a pas encore été joué");
+        assert_eq!(french.template(MessageCode::NotYourTurn), "Not your turn");
+        assert!(MessageCatalog::from_json(r#"{"dance": "Dansez"}"#).is_err());
+
+        let komi = Notice::new(MessageCode::InvalidKomi).with("max", "32");
+        let custom = MessageCatalog::new().with(MessageCode::InvalidKomi, "|komi| <= {max}, {unknown}");
+        assert_eq!(custom.render(&komi), "|komi| <= 32, {unknown}");
+
+        let english = serde_json::to_value(MessageCatalog::english()).unwrap();
+        assert_eq!(english.as_object().unwrap().len(), MessageCode::ALL.len());
+        assert_eq!(english["must_pass"], "No legal moves available, you must pass.");
+    }
+}
@@ -0,0 +1,142 @@
+//! Tactics puzzles mined from finished games.
+//!
+//! A puzzle is a position from a stored game, late enough to be solved exactly, where the side
+//! to move has exactly one winning move. Mining replays each finished game once, so running it
+//! again only looks at games finished since.
+
+use crate::game::{Game, GameReplay, Move};
+use crate::solver::solve;
+use crate::storage::Storage;
+
+/// Positions with more empty squares than this are not solved, to keep mining fast.
+pub const MAX_PUZZLE_EMPTIES: u32 = 10;
+
+/// A position with a unique winning move.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Puzzle {
+    /// Number of moves played in the source game before the position.
+    pub ply: usize,
+    pub game: Game,
+    pub solution: u8,
+    /// From 1 to 5, rising with the number of legal alternatives and empty squares left.
+    pub difficulty: u8,
+}
+
+/// A puzzle as kept in the database.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredPuzzle {
+    pub id: i64,
+    /// The game the position was taken from.
+    pub game_id: String,
+    pub puzzle: Puzzle,
+}
+
+/// Returns the only move that wins the position for the side to move with perfect play, if
+/// there is exactly one and at least one other legal move to choose from.
+#[must_use]
+pub fn unique_winning_move(game: &Game) -> Option<u8> {
+    if game.empty().count_ones() > MAX_PUZZLE_EMPTIES {
+        return None;
+    }
+    let moves = game.legal_moves();
+    if moves.len() < 2 {
+        return None;
+    }
+    let side = game.current_player;
+    let mut winning = moves.into_iter().filter(|&pos| {
+        let mut child = game.clone();
+        let _ = child.make_move(pos);
+        let score = solve(&child).score;
+        // After an automatic pass the mover is to play again, so the score keeps its sign.
+        let score = if child.current_player == side { score } else { -score };
+        score > 0
+    });
+    let solution = winning.next()?;
+    winning.next().is_none().then_some(solution)
+}
+
+fn difficulty(game: &Game) -> u8 {
+    let alternatives = game.legal_move_bitboard().count_ones() - 1;
+    let deep = u32::from(game.empty().count_ones() > MAX_PUZZLE_EMPTIES / 2);
+    u8::try_from((1 + alternatives / 2 + deep).min(5)).unwrap_or(5)
+}
+
+/// Finds the puzzles in a game played from the standard opening position.
+///
+/// # Errors
+///
+/// Returns an error if a move is illegal.
+pub fn mine(moves: &[Move]) -> Result<Vec<Puzzle>, String> {
+    let replay = GameReplay::from_moves(moves.to_vec())?;
+    Ok((0..replay.len())
+        .filter_map(|ply| {
+            let game = replay.board_at(ply)?;
+            let solution = unique_winning_move(game)?;
+            Some(Puzzle {
+                ply,
+                game: game.clone(),
+                solution,
+                difficulty: difficulty(game),
+            })
+        })
+        .collect())
+}
+
+/// Mines every finished game in the database that has not been mined yet, returning the
+/// number of new puzzles.
+///
+/// # Errors
+///
+/// Returns an error if the games cannot be read or the puzzles cannot be saved.
+pub fn mine_stored_games(storage: &Storage) -> rusqlite::Result<usize> {
+    let mut found = 0;
+    for game_id in storage.unmined_game_ids()? {
+        let moves = storage.load_moves(&game_id)?;
+        match mine(&moves) {
+            Ok(puzzles) => {
+                for puzzle in &puzzles {
+                    if storage.save_puzzle(&game_id, puzzle)? {
+                        found += 1;
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("Skipping game {game_id} while mining puzzles: {e}"),
+        }
+        storage.mark_game_mined(&game_id)?;
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::random_game;
+
+    #[test]
+    fn test_mined_puzzles_have_one_winning_move() {
+        let puzzles: Vec<Puzzle> = (0..6).flat_map(|seed| mine(&random_game(seed)).unwrap()).collect();
+        assert!(!puzzles.is_empty());
+        for puzzle in &puzzles {
+            let game = &puzzle.game;
+            assert!(game.is_valid_move(puzzle.solution));
+            assert!((1..=5).contains(&puzzle.difficulty));
+            assert!(game.empty().count_ones() <= MAX_PUZZLE_EMPTIES);
+            // Playing anything else lets the opponent avoid losing.
+            for pos in game.legal_moves().into_iter().filter(|&pos| pos != puzzle.solution) {
+                let mut child = game.clone();
+                child.make_move(pos).unwrap();
+                let score = solve(&child).score;
+                let score = if child.current_player == game.current_player { score } else { -score };
+                assert!(score <= 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_single_move_is_no_puzzle() {
+        let mut game = Game::new();
+        game.black = 1u64 << Game::coord_to_pos("A3").unwrap();
+        game.white = 1u64 << Game::coord_to_pos("A2").unwrap();
+        assert_eq!(unique_winning_move(&game), None);
+    }
+}
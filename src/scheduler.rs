@@ -0,0 +1,331 @@
+//! Throttling of AI searches.
+//!
+//! Every AI move is a CPU-bound search. The scheduler runs at most a fixed number of them at
+//! once on the blocking thread pool, so many simultaneous AI games cannot oversubscribe the
+//! machine. Waiting searches are started cheapest first, so quick searches are not stuck
+//! behind long ones, and in arrival order among equals.
+//!
+//! A search that runs past the move timeout is cut short, so a search that misbehaves cannot
+//! stall its game: the caller then plays the best move found so far instead.
+
+use crate::mcts::CancelToken;
+use parking_lot::Mutex;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Longest a search may run before it is cut short, unless configured otherwise.
+pub const DEFAULT_MOVE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a search that was cut short is given to stop.
+const CANCEL_GRACE: Duration = Duration::from_millis(200);
+
+/// How a job run by [`AiScheduler::run_timed`] ended.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Timed<T> {
+    Finished(T),
+    /// The job ran past the move timeout and was cancelled.
+    TimedOut,
+}
+
+/// Runs AI searches with bounded concurrency.
+pub struct AiScheduler {
+    inner: Arc<Inner>,
+    move_timeout: Option<Duration>,
+}
+
+struct Inner {
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    running: usize,
+    next_seq: u64,
+    queue: BinaryHeap<Waiter>,
+}
+
+struct Waiter {
+    cost: u32,
+    seq: u64,
+    tx: oneshot::Sender<Permit>,
+}
+
+impl Waiter {
+    fn key(&self) -> (Reverse<u32>, Reverse<u64>) {
+        (Reverse(self.cost), Reverse(self.seq))
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// A running slot. Dropping it hands the slot to the next waiter.
+struct Permit {
+    inner: Option<Arc<Inner>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            Inner::release(&inner);
+        }
+    }
+}
+
+impl Inner {
+    fn release(inner: &Arc<Inner>) {
+        loop {
+            let waiter = {
+                let mut state = inner.state.lock();
+                let Some(waiter) = state.queue.pop() else {
+                    state.running -= 1;
+                    return;
+                };
+                waiter
+            };
+            let permit = Permit {
+                inner: Some(inner.clone()),
+            };
+            match waiter.tx.send(permit) {
+                Ok(()) => return,
+                // The waiter gave up; disarm the permit and try the next one.
+                Err(mut permit) => permit.inner = None,
+            }
+        }
+    }
+}
+
+impl AiScheduler {
+    /// Creates a scheduler running at most `capacity` searches at once, each for at most
+    /// [`DEFAULT_MOVE_TIMEOUT`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "AI scheduler needs a capacity of at least 1");
+        Self {
+            inner: Arc::new(Inner {
+                capacity,
+                state: Mutex::new(State::default()),
+            }),
+            move_timeout: Some(DEFAULT_MOVE_TIMEOUT),
+        }
+    }
+
+    /// Sets how long a search run by [`AiScheduler::run_timed`] may take, or lets searches
+    /// run for as long as they take with `None`.
+    #[must_use]
+    pub fn with_move_timeout(mut self, move_timeout: Option<Duration>) -> Self {
+        self.move_timeout = move_timeout;
+        self
+    }
+
+    /// Returns how long a search may run before it is cut short.
+    #[must_use]
+    pub fn move_timeout(&self) -> Option<Duration> {
+        self.move_timeout
+    }
+
+    /// Returns the number of searches that may run at once.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity
+    }
+
+    /// Returns the number of searches waiting for a slot.
+    #[must_use]
+    pub fn queued(&self) -> usize {
+        self.inner.state.lock().queue.len()
+    }
+
+    async fn acquire(&self, cost: u32) -> Permit {
+        let rx = {
+            let mut state = self.inner.state.lock();
+            if state.running < self.inner.capacity && state.queue.is_empty() {
+                state.running += 1;
+                return Permit {
+                    inner: Some(self.inner.clone()),
+                };
+            }
+            let (tx, rx) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.queue.push(Waiter { cost, seq, tx });
+            rx
+        };
+        rx.await.expect("scheduler dropped a queued search")
+    }
+
+    /// Runs `job` on the blocking thread pool once a slot is free, returning its result.
+    ///
+    /// `cost` estimates how long the job takes, such as its number of simulations; cheaper
+    /// jobs are started first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the job panics.
+    pub async fn run<T, F>(&self, cost: u32, job: F) -> Result<T, tokio::task::JoinError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = self.acquire(cost).await;
+        let result = tokio::task::spawn_blocking(job).await;
+        drop(permit);
+        result
+    }
+
+    /// Like [`AiScheduler::run`], but cuts the job short once it has run for the move
+    /// timeout: `cancel` is cancelled and the job is given a moment to stop before
+    /// [`Timed::TimedOut`] is returned, whether it stopped or not.
+    ///
+    /// A job that does not stop keeps its slot until it ends, as it still keeps a thread busy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the job panics before the timeout.
+    pub async fn run_timed<T, F>(
+        &self,
+        cost: u32,
+        cancel: &CancelToken,
+        job: F,
+    ) -> Result<Timed<T>, tokio::task::JoinError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = self.acquire(cost).await;
+        let mut handle = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            job()
+        });
+        let Some(move_timeout) = self.move_timeout else {
+            return handle.await.map(Timed::Finished);
+        };
+        if let Ok(result) = tokio::time::timeout(move_timeout, &mut handle).await {
+            return result.map(Timed::Finished);
+        }
+        cancel.cancel();
+        let _ = tokio::time::timeout(CANCEL_GRACE, handle).await;
+        Ok(Timed::TimedOut)
+    }
+}
+
+impl Default for AiScheduler {
+    /// Creates a scheduler with one slot per available CPU.
+    fn default() -> Self {
+        Self::new(std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_limits_concurrency() {
+        let scheduler = Arc::new(AiScheduler::new(2));
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let jobs: Vec<_> = (0..8)
+            .map(|_| {
+                let (scheduler, running, peak) = (scheduler.clone(), running.clone(), peak.clone());
+                tokio::spawn(async move {
+                    scheduler
+                        .run(1, move || {
+                            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                            peak.fetch_max(now, Ordering::SeqCst);
+                            std::thread::sleep(Duration::from_millis(20));
+                            running.fetch_sub(1, Ordering::SeqCst);
+                        })
+                        .await
+                        .unwrap();
+                })
+            })
+            .collect();
+        for job in jobs {
+            job.await.unwrap();
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(scheduler.queued(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cheapest_waiter_first() {
+        let scheduler = Arc::new(AiScheduler::new(1));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        // Hold the only slot while the others queue up.
+        let held = scheduler.acquire(0).await;
+        let mut jobs = Vec::new();
+        for (name, cost) in [("slow", 400), ("fast", 25), ("fast2", 25)] {
+            let (queue, order) = (scheduler.clone(), order.clone());
+            jobs.push(tokio::spawn(async move {
+                queue.run(cost, move || order.lock().push(name)).await.unwrap();
+            }));
+            while scheduler.queued() < jobs.len() {
+                tokio::task::yield_now().await;
+            }
+        }
+        drop(held);
+        for job in jobs {
+            job.await.unwrap();
+        }
+        assert_eq!(*order.lock(), ["fast", "fast2", "slow"]);
+    }
+
+    #[tokio::test]
+    async fn test_job_panic_is_an_error() {
+        let scheduler = AiScheduler::new(1);
+        assert!(scheduler.run(1, || panic!("search failed")).await.is_err());
+        // The slot is released for the next search.
+        assert_eq!(scheduler.run(1, || 7).await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_job_is_cancelled() {
+        let scheduler = AiScheduler::new(1).with_move_timeout(Some(Duration::from_millis(50)));
+        let cancel = CancelToken::new();
+        assert_eq!(scheduler.run_timed(1, &cancel, || 7).await.unwrap(), Timed::Finished(7));
+        assert!(!cancel.is_cancelled());
+
+        let search = cancel.clone();
+        let spin = move || {
+            while !search.is_cancelled() {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        };
+        assert_eq!(scheduler.run_timed(1, &cancel, spin).await.unwrap(), Timed::TimedOut);
+        assert!(cancel.is_cancelled());
+
+        // A job ignoring the cancellation holds its slot until it ends.
+        let hang = || std::thread::sleep(Duration::from_millis(400));
+        assert_eq!(scheduler.run_timed(1, &CancelToken::new(), hang).await.unwrap(), Timed::TimedOut);
+        assert_eq!(scheduler.inner.state.lock().running, 1);
+        assert_eq!(scheduler.run(1, || 8).await.unwrap(), 8);
+    }
+}
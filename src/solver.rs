@@ -0,0 +1,173 @@
+//! Exact endgame solver.
+//!
+//! Searches the game tree to the end with negamax and alpha-beta pruning, ordering moves so
+//! that replies leaving the opponent the fewest options are tried first. Scores are final disc
+//! differentials from the point of view of the side to move, with empty squares going to the
+//! winner as in tournament scoring.
+
+use crate::game::{Game, Move, Player, ScoringRule};
+
+/// Below this many empty squares, move ordering costs more than it saves.
+const ORDERING_MIN_EMPTIES: u32 = 6;
+
+/// The exact result of an endgame search.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Solution {
+    /// The best move for the side to move, or `None` if the game is already over.
+    pub best_move: Option<Move>,
+    /// Final disc differential for the side to move with perfect play.
+    pub score: i32,
+    /// Number of positions visited.
+    pub nodes: u64,
+}
+
+/// Solves the position exactly.
+#[must_use]
+pub fn solve(game: &Game) -> Solution {
+    let mut solver = Solver { nodes: 0 };
+    let (score, best_move) = solver.negamax(game, -65, 65);
+    Solution {
+        best_move,
+        score,
+        nodes: solver.nodes,
+    }
+}
+
+/// Returns the final disc differential of a finished game for the given player.
+fn final_score(game: &Game, player: Player) -> i32 {
+    let (black, white) = game.final_scores(ScoringRule::EmptiesToWinner);
+    let diff = i32::try_from(black).unwrap_or(64) - i32::try_from(white).unwrap_or(64);
+    match player {
+        Player::Black => diff,
+        Player::White => -diff,
+    }
+}
+
+struct Solver {
+    nodes: u64,
+}
+
+impl Solver {
+    /// Returns the score for the side to move in `game` and the move achieving it.
+    fn negamax(&mut self, game: &Game, mut alpha: i32, beta: i32) -> (i32, Option<Move>) {
+        self.nodes += 1;
+        let side = game.current_player;
+        if game.is_game_over() {
+            return (final_score(game, side), None);
+        }
+
+        let moves = game.legal_moves();
+        if moves.is_empty() {
+            // Only reachable if a position with a forced pass is handed to the solver,
+            // since make_move passes automatically.
+            let mut next = game.clone();
+            next.pass();
+            let (score, _) = self.negamax(&next, -beta, -alpha);
+            return (-score, Some(Move::Pass));
+        }
+
+        let mut children: Vec<(u8, Game)> = moves
+            .into_iter()
+            .map(|pos| {
+                let mut child = game.clone();
+                let _ = child.make_move(pos);
+                (pos, child)
+            })
+            .collect();
+        if game.empty().count_ones() > ORDERING_MIN_EMPTIES {
+            children.sort_by_cached_key(|(_, child)| child.legal_move_bitboard().count_ones());
+        }
+
+        let mut best = (-65, None);
+        for (pos, child) in children {
+            // After an automatic pass the mover is to play again, so the score keeps its sign.
+            let score = if child.current_player == side {
+                self.negamax(&child, alpha, beta).0
+            } else {
+                -self.negamax(&child, -beta, -alpha).0
+            };
+            if score > best.0 {
+                best = (score, Some(Move::Place(pos)));
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcts::random_playout;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Plain minimax without pruning or ordering, as a reference.
+    fn minimax(game: &Game) -> i32 {
+        let side = game.current_player;
+        if game.is_game_over() {
+            return final_score(game, side);
+        }
+        let moves = game.legal_moves();
+        if moves.is_empty() {
+            let mut next = game.clone();
+            next.pass();
+            return -minimax(&next);
+        }
+        moves
+            .into_iter()
+            .map(|pos| {
+                let mut child = game.clone();
+                child.make_move(pos).unwrap();
+                if child.current_player == side {
+                    minimax(&child)
+                } else {
+                    -minimax(&child)
+                }
+            })
+            .max()
+            .unwrap()
+    }
+
+    /// Plays random moves until `empties` squares are left (or the game ends).
+    fn random_position(seed: u64, empties: u32) -> Game {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut game = Game::new();
+        while !game.is_game_over() && game.empty().count_ones() > empties {
+            let moves = game.legal_moves();
+            game.make_move(moves[rng.gen_range(0..moves.len())]).unwrap();
+        }
+        game
+    }
+
+    #[test]
+    fn test_solver_matches_minimax() {
+        for seed in 0..10 {
+            let game = random_position(seed, 7);
+            let solution = solve(&game);
+            assert_eq!(solution.score, minimax(&game), "seed {seed}");
+            if let Some(Move::Place(pos)) = solution.best_move {
+                // The chosen move must actually achieve the reported score.
+                let mut child = game.clone();
+                child.make_move(pos).unwrap();
+                let score = if child.current_player == game.current_player {
+                    minimax(&child)
+                } else {
+                    -minimax(&child)
+                };
+                assert_eq!(score, solution.score, "seed {seed}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_solver_finished_game() {
+        let game = random_playout(Game::new(), &mut StdRng::seed_from_u64(1));
+        let solution = solve(&game);
+        assert_eq!(solution.best_move, None);
+        assert_eq!(solution.score, final_score(&game, game.current_player));
+    }
+}
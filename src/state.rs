@@ -1,158 +1,2519 @@
-use crate::game::{Game, Player};
-use crate::storage::Storage;
-use std::collections::HashMap;
+use crate::admin::{ErrorLog, RequestLog};
+use crate::ai::{AiConfig, Difficulty, MctsAi, SearchSummary, ENGINE_VERSION};
+use crate::analysis::{AnalysisReport, MoveAnalysis};
+use crate::anticheat::{self, MoveAnalyzer};
+use crate::boards::Boards;
+use crate::api::{CountdownResponse, FlippedLine, GameType, Job, PlayerStats, Task};
+use crate::auth::Auth;
+#[cfg(feature = "chaos")]
+use crate::chaos::Faults;
+use crate::cache::{MemoryCache, SessionCache};
+use crate::clock::{Clock, SystemClock};
+use crate::engine::{self, EngineConfig, EnginePool};
+use crate::game::{Game, GameReplay, IllegalMove, Move, Player, ScoringRule};
+use crate::jobs;
+use crate::mcts::CancelToken;
+#[cfg(feature = "oauth")]
+use crate::oauth::{self, OAuthProvider, ProviderConfig};
+use crate::protocol::{Delta, GameEvent, MessageCode, Notice, Watcher, MAX_CHAT_LEN};
+use crate::scheduler::{AiScheduler, DEFAULT_MOVE_TIMEOUT};
+use crate::storage::{Annotation, GameOptions, GameResult, MoveRecord, Storage, SELF_PLAY_PREFIX};
+use crate::sync::{Change, GameUpdate, UpdateBus};
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, Notify};
+
+/// Player name that makes the server play a side with the built-in AI.
+pub const AI_PLAYER: &str = "AI";
+
+/// Returns true if `name` is played by the built-in AI: [`AI_PLAYER`], searching as the server
+/// is configured to, or the name of a [`Difficulty`], each rated on its own.
+#[must_use]
+pub fn is_builtin_ai(name: &str) -> bool {
+    name == AI_PLAYER || Difficulty::from_player_name(name).is_some()
+}
+
+/// Most entries a game's metadata can hold.
+pub const MAX_METADATA_ENTRIES: usize = 32;
+/// Longest metadata key, in bytes.
+pub const MAX_METADATA_KEY_LEN: usize = 64;
+/// Longest metadata value, in bytes.
+pub const MAX_METADATA_VALUE_LEN: usize = 1024;
+
+/// Most discs a komi can give either side.
+pub const MAX_KOMI: i32 = 32;
+
+/// Discs played before which a game is aborted, rather than forfeited, when a player stays
+/// disconnected.
+pub const ABORT_PLIES: u32 = 2;
+
+/// How long a player may stay disconnected from a game against another person, by default.
+const DEFAULT_DISCONNECT_GRACE: Duration = Duration::from_mins(1);
+
+/// How long a game against another person may go without a move before it can be claimed
+/// drawn, by default.
+const DEFAULT_INACTIVITY_DRAW: Duration = Duration::from_hours(24);
+
+/// Events a slow socket may fall behind by before it skips to the latest state.
+const EVENT_BUFFER: usize = 64;
+
+/// How long the leaderboard is served from memory before it is read again, to catch games
+/// finished by other servers sharing the database.
+pub const LEADERBOARD_TTL: Duration = Duration::from_secs(5);
+
+/// Why a move or pass was rejected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SessionError {
+    GameNotFound,
+    NotYourTurn,
+    /// The move is illegal; holds why.
+    InvalidMove(IllegalMove),
+    /// The move was sent for a ply the game has not reached; holds the ply.
+    PlyNotReached(usize),
+    /// The player tried to pass while they had a legal move.
+    PassNotAllowed,
+    /// The annotated ply has not been played; holds the ply.
+    PlyNotPlayed(usize),
+    /// The database or cache could not be read or written; the game is left as it was.
+    Storage(String),
+    /// The AI search failed; the game is left as it was.
+    Ai(String),
+    /// The challenged player does not accept challenges from the challenger.
+    ChallengeRefused,
+    /// Only the players of a game may change it.
+    NotAParticipant,
+    /// A metadata key is empty, too long or has characters other than letters, digits,
+    /// `_`, `-` and `.`.
+    InvalidMetadataKey,
+    /// The value set for the key held is longer than [`MAX_METADATA_VALUE_LEN`] bytes.
+    MetadataValueTooLong(String),
+    /// The update leaves more than [`MAX_METADATA_ENTRIES`] entries.
+    TooManyMetadataEntries,
+    /// The request needs a finished game.
+    GameInProgress,
+    /// The game has already ended.
+    GameOver,
+    /// The request needs to know who the player is.
+    NotLoggedIn,
+    /// The chat message is empty.
+    EmptyChat,
+    /// The chat message is longer than [`MAX_CHAT_LEN`] characters.
+    ChatTooLong,
+    /// Another server sharing the database changed the game first; the change was not made.
+    Conflict,
+    /// The komi gives more than [`MAX_KOMI`] discs.
+    InvalidKomi,
+    /// No new games start while the server is drained for maintenance.
+    UnderMaintenance,
+    /// Coaching was asked for in a ranked game between two people.
+    CoachNotAllowed,
+    /// The opponent is connected, or still has time to reconnect, so the game cannot be
+    /// claimed.
+    NothingToClaim,
+    /// A move was played in the game within the inactivity period, or it is the claimant's
+    /// turn, so it cannot be claimed drawn; holds the period in seconds.
+    StillActive(u64),
+    /// Another move was played at the ply the move was sent for; holds the ply, the move
+    /// played there and who played it.
+    Superseded { ply: usize, played: Move, by: String },
+}
+
+impl From<rusqlite::Error> for SessionError {
+    fn from(error: rusqlite::Error) -> Self {
+        SessionError::Storage(error.to_string())
+    }
+}
+
+impl SessionError {
+    /// Returns the error as a message clients can translate. AI failures are internal
+    /// errors, without their details.
+    #[must_use]
+    pub fn notice(&self) -> Notice {
+        match self {
+            SessionError::GameNotFound => Notice::new(MessageCode::GameNotFound),
+            SessionError::NotYourTurn => Notice::new(MessageCode::NotYourTurn),
+            SessionError::InvalidMove(reason) => Notice::illegal_move(*reason),
+            SessionError::PlyNotReached(ply) => Notice::new(MessageCode::PlyNotReached).with("ply", ply.to_string()),
+            SessionError::PassNotAllowed => Notice::new(MessageCode::PassNotAllowed),
+            SessionError::PlyNotPlayed(ply) => Notice::new(MessageCode::PlyNotPlayed).with("ply", ply.to_string()),
+            SessionError::Storage(e) => Notice::new(MessageCode::StorageError).with("reason", e.as_str()),
+            SessionError::Ai(_) => Notice::new(MessageCode::InternalError),
+            SessionError::ChallengeRefused => Notice::new(MessageCode::ChallengeRefused),
+            SessionError::NotAParticipant => Notice::new(MessageCode::NotAParticipant),
+            SessionError::InvalidMetadataKey => {
+                Notice::new(MessageCode::InvalidMetadataKey).with("max", MAX_METADATA_KEY_LEN.to_string())
+            }
+            SessionError::MetadataValueTooLong(key) => Notice::new(MessageCode::MetadataValueTooLong)
+                .with("key", key.as_str())
+                .with("max", MAX_METADATA_VALUE_LEN.to_string()),
+            SessionError::TooManyMetadataEntries => {
+                Notice::new(MessageCode::TooManyMetadataEntries).with("max", MAX_METADATA_ENTRIES.to_string())
+            }
+            SessionError::GameInProgress => Notice::new(MessageCode::GameInProgress),
+            SessionError::GameOver => Notice::new(MessageCode::GameOver),
+            SessionError::NotLoggedIn => Notice::new(MessageCode::NotLoggedIn),
+            SessionError::EmptyChat => Notice::new(MessageCode::EmptyChat),
+            SessionError::ChatTooLong => Notice::new(MessageCode::ChatTooLong).with("max", MAX_CHAT_LEN.to_string()),
+            SessionError::Conflict => Notice::new(MessageCode::Conflict),
+            SessionError::InvalidKomi => Notice::new(MessageCode::InvalidKomi).with("max", MAX_KOMI.to_string()),
+            SessionError::UnderMaintenance => Notice::new(MessageCode::UnderMaintenance),
+            SessionError::CoachNotAllowed => Notice::new(MessageCode::CoachNotAllowed),
+            SessionError::NothingToClaim => Notice::new(MessageCode::NothingToClaim),
+            SessionError::StillActive(secs) => Notice::new(MessageCode::StillActive).with("seconds", secs.to_string()),
+            SessionError::Superseded { ply, played, by } => Notice::new(MessageCode::Superseded)
+                .with("ply", ply.to_string())
+                .with("move", played.to_string())
+                .with("player", by.as_str()),
+        }
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            // Logged, so the details are kept.
+            SessionError::Ai(e) => write!(f, "AI error: {e}"),
+            _ => self.notice().fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
 
 pub struct Sessions {
     games: HashMap<String, Game>,
     players: HashMap<String, (String, String)>,
-    next_id: u64,
     pub storage: Storage,
-    queue: Vec<String>,
+    scoring_rule: ScoringRule,
+    /// Discs added to White's final count in new games, unless the match sets its own.
+    default_komi: i32,
+    /// Komi of each game in memory that has one.
+    komi: HashMap<String, i32>,
+    /// Search state of the AI in each game it plays, kept between turns so the tree is reused.
+    ais: HashMap<String, KeptAi>,
+    /// When the player to move in each game started their turn.
+    turn_started: HashMap<String, Instant>,
+    /// Number of moves in the stored history of each game in memory.
+    plies: HashMap<String, usize>,
+    /// Time turns and deadlines are measured with.
+    clock: Arc<dyn Clock>,
+    /// Whether players left without legal moves are passed for automatically.
+    auto_pass: bool,
+    /// Whether players are passed for automatically in each game in memory set up otherwise
+    /// than `auto_pass`.
+    auto_passes: HashMap<String, bool>,
+    /// Passes forced by the latest move in each game.
+    forced_passes: HashMap<String, Vec<Player>>,
+    /// Whether passing is refused while the player has a legal move.
+    strict_passes: bool,
+    /// Limits how many AI searches run at once across all games.
+    ai_scheduler: Arc<AiScheduler>,
+    /// Cancels the latest AI search started in each game.
+    searches: HashMap<String, CancelToken>,
+    /// Configuration of the AI in new games.
+    ai_config: AiConfig,
+    /// Woken whenever a background job is queued.
+    job_signal: Arc<Notify>,
+    /// Color that resigned each resigned game in memory.
+    resigned: HashMap<String, Player>,
+    /// Events of each game with sockets watching it.
+    events: HashMap<String, broadcast::Sender<GameEvent>>,
+    /// The matchmaking queue and the sockets open on each game.
+    cache: Arc<dyn SessionCache>,
+    /// How long a player may stay disconnected from a game against another person before
+    /// losing it, or `None` to wait forever.
+    disconnect_grace: Option<Duration>,
+    /// When each player who left a game they can lose that way runs out of time, by game and
+    /// name.
+    reconnect_deadlines: HashMap<String, HashMap<String, Instant>>,
+    /// Games in memory that ended without a result.
+    aborted: HashSet<String>,
+    /// How long a game against another person may go without a move before its players can
+    /// claim a draw.
+    inactivity_draw: Option<Duration>,
+    /// Games in memory drawn by a claim of inactivity.
+    drawn: HashSet<String>,
+    /// Casual games in memory, which change no ratings.
+    casual: HashSet<String>,
+    /// Private games in memory, hidden from everyone but their players, the admins and
+    /// holders of an invite.
+    private: HashSet<String>,
+    /// Games in memory whose players are told how good each move they place was.
+    coached: HashSet<String>,
+    /// Users who may manage any game, e.g. delete it.
+    admins: HashSet<String>,
+    /// Whether the database is the source of truth for games, shared with other servers.
+    shared: bool,
+    /// Stored version of each game in memory, with shared storage.
+    versions: HashMap<String, i64>,
+    /// Tells this server's updates apart from the others' on the update bus.
+    instance_id: String,
+    /// Carries changes to and from other servers sharing the database.
+    update_bus: Option<Arc<dyn UpdateBus>>,
+    /// Run in the background on every move placed by a person, to flag suspected cheating.
+    move_analyzers: Vec<Arc<dyn MoveAnalyzer>>,
+    /// Sockets open on this server.
+    open_sockets: usize,
+    /// Recent warnings and errors, for the admins.
+    error_log: ErrorLog,
+    /// Switches the debug logging of requests to the match endpoints.
+    request_log: RequestLog,
+    /// Faults injected into the server by tests.
+    #[cfg(feature = "chaos")]
+    faults: Faults,
+    /// Maintenance under way, if any.
+    maintenance: Option<Maintenance>,
+    /// How long finished games are kept, if they are ever deleted.
+    retention: Option<RetentionPolicy>,
+    /// External engines offered as opponents, by player name.
+    engines: HashMap<String, Arc<EnginePool>>,
+    /// The leaderboard as last read, until a game ends here or it is too old.
+    leaderboard: Option<CachedLeaderboard>,
+    /// Counts the changes to the leaderboard seen by this server.
+    leaderboard_generation: u64,
+    /// Analysis boards open on this server, which are never stored.
+    boards: Boards,
+    /// Socket tickets spent on this server, with when they expire.
+    spent_tickets: HashMap<String, usize>,
+    /// Identity providers players can log in with, by name.
+    #[cfg(feature = "oauth")]
+    oauth_providers: HashMap<String, Arc<OAuthProvider>>,
 }
 
-impl Default for Sessions {
-    /// Creates a new `Sessions` instance.
+/// The leaderboard as read from the storage, shared by the requests served from it.
+struct CachedLeaderboard {
+    read_at: Instant,
+    players: Arc<Vec<PlayerStats>>,
+}
+
+/// The AI kept for a game, with the moves played since its tree last caught up with it.
+struct KeptAi {
+    ai: Arc<Mutex<MctsAi>>,
+    unseen: Vec<Move>,
+}
+
+/// Maintenance of a server, which starts no new games while the games being played on it
+/// end, so it can restart without interrupting them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Maintenance {
+    /// When the games still being played stop holding up a restart, if ever.
+    pub deadline: Option<Instant>,
+    /// Whether the server stops by itself once drained, to be restarted.
+    pub restart: bool,
+}
+
+/// How long finished games are kept before the scheduled retention job deletes them. Games
+/// someone starred, games flagged by the anti-cheat checks and rated games of a tournament
+/// are always kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Days a finished game is kept after it ended.
+    pub keep_days: u32,
+    /// Whether the scheduled runs only list the games they would delete.
+    pub dry_run: bool,
+}
+
+/// What the AI of a game is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AiActivity {
+    /// The AI is waiting for its turn.
+    Idle,
+    /// The AI is searching for a move, holding its lock.
+    Searching,
+}
+
+impl AiActivity {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AiActivity::Idle => "idle",
+            AiActivity::Searching => "searching",
+        }
+    }
+}
+
+/// How a game ended after a player stayed disconnected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Abandonment {
+    /// The player lost the game, having played the given color.
+    Forfeited(Player),
+    /// The game was still in the opening and ended without a result.
+    Aborted,
+}
+
+impl Sessions {
+    /// Creates the sessions of a server keeping its games in `storage`, configured by the
+    /// environment variables documented in the README. Unfinished games are loaded from the
+    /// storage. `ADMINS` lists the admins' names, separated by commas.
     ///
     /// # Panics
     ///
-    /// Panics if the database cannot be opened or if games cannot be loaded.
-    fn default() -> Self {
-        let db_path = env::var("DB_PATH").unwrap_or_else(|_| "kawio.db".to_string());
-        let storage = Storage::new(&db_path).expect("Failed to open database");
+    /// Panics if games cannot be loaded, or if:
+    /// - `SCORING_RULE` names an unknown rule;
+    /// - `AUTO_PASS`, `STRICT_PASSES`, `SHARED_STORAGE`, `ANTI_CHEAT`, `LOG_REQUESTS` or
+    ///   `RETENTION_DRY_RUN` is not `true` or `false`;
+    /// - `ELO_FLOOR` is not a number;
+    /// - `AI_CONCURRENCY` is not a positive number;
+    /// - `AI_MOVE_TIMEOUT_SECS`, `DISCONNECT_GRACE_SECS` or `INACTIVITY_DRAW_SECS` is not a
+    ///   number of seconds;
+    /// - `AI_DELAY_MS` is not a delay or range of delays;
+    /// - `KOMI` is not a number of discs up to [`MAX_KOMI`] either way;
+    /// - `RETENTION_DAYS` is not a number of days;
+    /// - `ENGINES` does not name a valid list of engines, see [`engine::load_configs`];
+    /// - Redis cannot be reached at `REDIS_URL`, with the `redis` feature;
+    /// - `OAUTH_PROVIDERS` does not name a valid list of identity providers, with the `oauth`
+    ///   feature, see [`oauth::load_configs`].
+    #[must_use]
+    pub fn new(storage: Storage) -> Self {
+        Self::with_clock(storage, Arc::new(SystemClock))
+    }
+
+    /// Like [`Sessions::new`], but measures turns and deadlines with `clock`, e.g. a
+    /// [`ManualClock`](crate::clock::ManualClock) in tests.
+    ///
+    /// # Panics
+    ///
+    /// Panics as [`Sessions::new`] does.
+    #[must_use]
+    pub fn with_clock(mut storage: Storage, clock: Arc<dyn Clock>) -> Self {
+        let scoring_rule = env::var("SCORING_RULE")
+            .map(|rule| rule.parse().expect("Invalid SCORING_RULE"))
+            .unwrap_or_default();
+        let auto_pass = env::var("AUTO_PASS")
+            .map_or(true, |auto_pass| auto_pass.parse().expect("Invalid AUTO_PASS"));
+        let strict_passes = env::var("STRICT_PASSES")
+            .map_or(true, |strict| strict.parse().expect("Invalid STRICT_PASSES"));
+        let default_komi = env_komi();
+        let shared = env_flag("SHARED_STORAGE");
+        let ai_scheduler = ai_scheduler();
+        let mut ai_config = AiConfig {
+            pacing: env::var("AI_DELAY_MS")
+                .map(|delay| delay.parse().expect("Invalid AI_DELAY_MS"))
+                .unwrap_or_default(),
+            ..AiConfig::default()
+        };
+        if env_flag("AI_VARIED_OPENINGS") {
+            ai_config.opening = Difficulty::Medium.opening_variety();
+        }
+        #[cfg(feature = "chaos")]
+        let faults = Faults::new();
+        #[cfg(feature = "chaos")]
+        storage.inject_faults(&faults);
+        if let Ok(floor) = env::var("ELO_FLOOR") {
+            let mut rating = storage.rating_config().clone();
+            rating.floor = floor.parse().expect("Invalid ELO_FLOOR");
+            storage.set_rating_config(rating);
+        }
+        let disconnect_grace = env_secs("DISCONNECT_GRACE_SECS", DEFAULT_DISCONNECT_GRACE);
+        let inactivity_draw = env_secs("INACTIVITY_DRAW_SECS", DEFAULT_INACTIVITY_DRAW);
         let (games, players) = storage.load_all_games().expect("Failed to load games");
-        let next_id = games.len() as u64 + 1;
+        let komi = storage.komis().expect("Failed to load games");
+        let casual = storage.casual_games().expect("Failed to load games");
+        let private = storage.private_games().expect("Failed to load games");
+        let coached = storage.coached_games().expect("Failed to load games");
+        let auto_passes = storage.auto_passes().expect("Failed to load games");
+        let admins = env_names("ADMINS");
+        let move_analyzers = if env_flag("ANTI_CHEAT") {
+            anticheat::default_analyzers()
+        } else {
+            Vec::new()
+        };
+        let engines = env::var("ENGINES").map_or_else(
+            |_| HashMap::new(),
+            |path| engine_pools(engine::load_configs(&path).expect("Invalid ENGINES")),
+        );
+        let instance_id = format!("{:016x}", rand::random::<u64>());
+        let cache = session_cache(&instance_id);
         Sessions {
             games,
             players,
-            next_id,
             storage,
-            queue: Vec::new(),
+            scoring_rule,
+            default_komi,
+            komi,
+            ais: HashMap::new(),
+            turn_started: HashMap::new(),
+            plies: HashMap::new(),
+            clock,
+            auto_pass,
+            auto_passes,
+            forced_passes: HashMap::new(),
+            strict_passes,
+            ai_scheduler: Arc::new(ai_scheduler),
+            searches: HashMap::new(),
+            ai_config,
+            job_signal: Arc::new(Notify::new()),
+            resigned: HashMap::new(),
+            events: HashMap::new(),
+            cache,
+            disconnect_grace,
+            reconnect_deadlines: HashMap::new(),
+            aborted: HashSet::new(),
+            inactivity_draw,
+            drawn: HashSet::new(),
+            casual,
+            private,
+            coached,
+            admins,
+            shared,
+            versions: HashMap::new(),
+            instance_id,
+            update_bus: None,
+            move_analyzers,
+            open_sockets: 0,
+            error_log: ErrorLog::new(),
+            request_log: RequestLog::new(env_flag("LOG_REQUESTS")),
+            #[cfg(feature = "chaos")]
+            faults,
+            maintenance: None,
+            retention: env_retention(),
+            engines,
+            leaderboard: None,
+            leaderboard_generation: 0,
+            boards: Boards::new(),
+            spent_tickets: HashMap::new(),
+            #[cfg(feature = "oauth")]
+            oauth_providers: env_oauth_providers(),
         }
     }
 }
 
-impl Sessions {
-    /// Creates a new `Sessions` instance.
+/// Reads an environment variable set to `true` or `false`, false if it is not set.
+fn env_flag(name: &str) -> bool {
+    env::var(name).is_ok_and(|value| value.parse().unwrap_or_else(|_| panic!("Invalid {name}")))
+}
+
+/// Reads the retention policy from `RETENTION_DAYS` and `RETENTION_DRY_RUN`, none if
+/// `RETENTION_DAYS` is not set.
+fn env_retention() -> Option<RetentionPolicy> {
+    let keep_days = env::var("RETENTION_DAYS").ok()?;
+    Some(RetentionPolicy {
+        keep_days: keep_days.parse().expect("Invalid RETENTION_DAYS"),
+        dry_run: env_flag("RETENTION_DRY_RUN"),
+    })
+}
+
+/// Reads a comma-separated list of names from the variable, empty if it is not set.
+fn env_names(name: &str) -> HashSet<String> {
+    env::var(name).map_or_else(
+        |_| HashSet::new(),
+        |names| {
+            names
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect()
+        },
+    )
+}
+
+/// Reads the discs added to White's final count in new games from `KOMI`, 0 if it is not
+/// set.
+fn env_komi() -> i32 {
+    env::var("KOMI").map_or(0, |komi| {
+        let komi: i32 = komi.parse().expect("Invalid KOMI");
+        assert!(komi.abs() <= MAX_KOMI, "Invalid KOMI");
+        komi
+    })
+}
+
+/// Reads a number of seconds from the variable, `default` if it is not set, where 0 stands
+/// for no time limit.
+fn env_secs(name: &str, default: Duration) -> Option<Duration> {
+    env::var(name).map_or(Some(default), |secs| {
+        let secs = secs.parse().unwrap_or_else(|_| panic!("Invalid {name}"));
+        (secs > 0).then(|| Duration::from_secs(secs))
+    })
+}
+
+/// Makes the cache shared with other servers through Redis at `REDIS_URL`, with the `redis`
+/// feature, or kept in memory otherwise.
+#[cfg_attr(not(feature = "redis"), allow(unused_variables))]
+fn session_cache(instance_id: &str) -> Arc<dyn SessionCache> {
+    match env::var("REDIS_URL") {
+        #[cfg(feature = "redis")]
+        Ok(url) => Arc::new(
+            crate::cache::redis::RedisCache::connect(&url, instance_id).expect("Failed to connect to Redis"),
+        ),
+        _ => Arc::new(MemoryCache::new()),
+    }
+}
+
+/// Takes the first of `opponents` still waiting out of the matchmaking queue and starts a
+/// game between them and `player` with `create`, or queues `player` if none is waiting.
+fn take_opponent(
+    cache: &dyn SessionCache,
+    player: String,
+    opponents: Vec<String>,
+    create: impl FnOnce(String, &str) -> Result<String, SessionError>,
+) -> Result<Option<String>, SessionError> {
+    for opponent in opponents {
+        // Another request, or another server sharing the queue, may have paired them meanwhile.
+        if !cache.take_waiting(&opponent).map_err(SessionError::Storage)? {
+            continue;
+        }
+        return match create(player, &opponent) {
+            Ok(id) => Ok(Some(id)),
+            Err(e) => {
+                cache.push_waiting(&opponent).map_err(SessionError::Storage)?;
+                Err(e)
+            }
+        };
+    }
+    cache.push_waiting(&player).map_err(SessionError::Storage)?;
+    Ok(None)
+}
+
+/// Makes the scheduler of AI searches from `AI_CONCURRENCY` and `AI_MOVE_TIMEOUT_SECS`.
+fn ai_scheduler() -> AiScheduler {
+    let scheduler = env::var("AI_CONCURRENCY").map_or_else(
+        |_| AiScheduler::default(),
+        |limit| AiScheduler::new(limit.parse().expect("Invalid AI_CONCURRENCY")),
+    );
+    scheduler.with_move_timeout(env_secs("AI_MOVE_TIMEOUT_SECS", DEFAULT_MOVE_TIMEOUT))
+}
+
+/// Makes a pool of processes for each engine, by player name.
+fn engine_pools(configs: Vec<EngineConfig>) -> HashMap<String, Arc<EnginePool>> {
+    configs
+        .into_iter()
+        .map(|config| (config.name.clone(), Arc::new(EnginePool::new(config))))
+        .collect()
+}
+
+/// Reads the identity providers listed in the file `OAUTH_PROVIDERS` names, by name, none if
+/// it is not set.
+#[cfg(feature = "oauth")]
+fn env_oauth_providers() -> HashMap<String, Arc<OAuthProvider>> {
+    env::var("OAUTH_PROVIDERS").map_or_else(
+        |_| HashMap::new(),
+        |path| {
+            oauth::load_configs(&path)
+                .expect("Invalid OAUTH_PROVIDERS")
+                .into_iter()
+                .map(|config| (config.name.clone(), Arc::new(OAuthProvider::new(config))))
+                .collect()
+        },
+    )
+}
+
+impl Default for Sessions {
+    /// Creates the sessions of a server keeping its games in the `SQLite` database at
+    /// `DB_PATH`, `kawio.db` by default.
     ///
     /// # Panics
     ///
-    /// Panics if the database cannot be opened or if games cannot be loaded.
+    /// Panics if the database cannot be opened, or as [`Sessions::new`] does.
+    fn default() -> Self {
+        let db_path = env::var("DB_PATH").unwrap_or_else(|_| "kawio.db".to_string());
+        Self::new(Storage::new(&db_path).expect("Failed to open database"))
+    }
+}
+
+impl Sessions {
+    /// Returns the rule used to score finished games.
     #[must_use]
-    pub fn new() -> Self {
-        Self::default()
+    pub fn scoring_rule(&self) -> ScoringRule {
+        self.scoring_rule
     }
 
-    pub fn join_matchmaking(&mut self, player: String) -> Option<String> {
-        if self.queue.is_empty() {
-            self.queue.push(player);
-            None
+    /// Sets the rule used to score finished games.
+    pub fn set_scoring_rule(&mut self, rule: ScoringRule) {
+        self.scoring_rule = rule;
+    }
+
+    /// Returns the komi of new games whose match does not set one.
+    #[must_use]
+    pub fn default_komi(&self) -> i32 {
+        self.default_komi
+    }
+
+    /// Sets the komi of new games whose match does not set one, e.g. for the rated games
+    /// paired by matchmaking.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the komi gives more than [`MAX_KOMI`] discs.
+    pub fn set_default_komi(&mut self, komi: i32) -> Result<(), SessionError> {
+        if komi.abs() > MAX_KOMI {
+            return Err(SessionError::InvalidKomi);
+        }
+        self.default_komi = komi;
+        Ok(())
+    }
+
+    /// Returns the discs added to White's final count in a game.
+    #[must_use]
+    pub fn komi(&self, id: &str) -> i32 {
+        self.komi.get(id).copied().unwrap_or_default()
+    }
+
+    /// Returns true if the game is hidden from everyone but its players, the admins and
+    /// holders of an invite.
+    #[must_use]
+    pub fn is_private(&self, id: &str) -> bool {
+        self.private.contains(id)
+    }
+
+    /// Returns true if `name` may see a private game without an invite: one of its players
+    /// or an admin.
+    #[must_use]
+    pub fn may_see(&self, id: &str, name: &str) -> bool {
+        self.is_player(id, Some(name)) || self.is_admin(name)
+    }
+
+    /// Returns true if the players of the game are told how good each move they place was.
+    #[must_use]
+    pub fn is_coached(&self, id: &str) -> bool {
+        self.coached.contains(id)
+    }
+
+    /// Returns whether a game counts towards the players' ratings.
+    #[must_use]
+    pub fn game_type(&self, id: &str) -> GameType {
+        if self.casual.contains(id) {
+            GameType::Casual
         } else {
-            let opponent = self.queue.remove(0);
-            Some(self.create_game(player, &opponent))
+            GameType::Ranked
+        }
+    }
+
+    /// Returns true if players without legal moves are passed for automatically.
+    #[must_use]
+    pub fn auto_pass(&self) -> bool {
+        self.auto_pass
+    }
+
+    /// Sets whether players without legal moves are passed for automatically, or have to
+    /// send the pass themselves.
+    pub fn set_auto_pass(&mut self, auto_pass: bool) {
+        self.auto_pass = auto_pass;
+    }
+
+    /// Returns true if players without legal moves are passed for automatically in the game,
+    /// as it was set up or else as the server is.
+    #[must_use]
+    pub fn auto_passes(&self, id: &str) -> bool {
+        self.auto_passes.get(id).copied().unwrap_or(self.auto_pass)
+    }
+
+    /// Returns true if players may only pass when they have no legal move.
+    #[must_use]
+    pub fn strict_passes(&self) -> bool {
+        self.strict_passes
+    }
+
+    /// Sets whether players may only pass when they have no legal move. Casual games can turn
+    /// this off to allow passing at any time.
+    pub fn set_strict_passes(&mut self, strict: bool) {
+        self.strict_passes = strict;
+    }
+
+    /// Returns how long a player may stay disconnected from a game against another person
+    /// before losing it, or `None` if they never do.
+    #[must_use]
+    pub fn disconnect_grace(&self) -> Option<Duration> {
+        self.disconnect_grace
+    }
+
+    /// Sets how long a player may stay disconnected from a game against another person before
+    /// losing it, or `None` to wait forever.
+    pub fn set_disconnect_grace(&mut self, grace: Option<Duration>) {
+        self.disconnect_grace = grace;
+    }
+
+    /// Sets how long a game against another person may go without a move before its players
+    /// can claim a draw, or `None` to never let them.
+    pub fn set_inactivity_draw(&mut self, period: Option<Duration>) {
+        self.inactivity_draw = period;
+    }
+
+    /// Returns true if `name` may manage any game.
+    #[must_use]
+    pub fn is_admin(&self, name: &str) -> bool {
+        self.admins.contains(name)
+    }
+
+    /// Sets the users who may manage any game.
+    pub fn set_admins(&mut self, admins: HashSet<String>) {
+        self.admins = admins;
+    }
+
+    /// Spends a socket ticket, returning the player it was issued to, or `None` if it is
+    /// invalid, expired or already spent on this server.
+    pub fn redeem_ticket(&mut self, ticket: &str) -> Option<String> {
+        let claims = Auth::validate_ticket(ticket).ok()?;
+        let now = usize::try_from(self.clock.unix_time().as_secs()).unwrap_or(usize::MAX);
+        if claims.exp < now {
+            return None;
+        }
+        // Expired tickets are refused, so they need not be remembered.
+        self.spent_tickets.retain(|_, exp| *exp >= now);
+        if self.spent_tickets.insert(claims.ticket, claims.exp).is_some() {
+            return None;
         }
+        Some(claims.holder)
     }
 
-    /// Creates a new game and saves it to the database.
+    /// Returns the log of recent warnings and errors shown to the admins.
+    #[must_use]
+    pub fn error_log(&self) -> &ErrorLog {
+        &self.error_log
+    }
+
+    /// Sets the log of recent warnings and errors shown to the admins, e.g. the one installed
+    /// as a tracing layer.
+    pub fn set_error_log(&mut self, error_log: ErrorLog) {
+        self.error_log = error_log;
+    }
+
+    /// Returns the leaderboard, read from the storage only when a game has ended here since
+    /// the last read or that read is older than [`LEADERBOARD_TTL`].
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panics if the game cannot be saved.
-    pub fn create_game(&mut self, player1: String, player2: &str) -> String {
-        let id = format!("game_{}", self.next_id);
-        self.next_id += 1;
-        let game = Game::new();
-        self.games.insert(id.clone(), game.clone());
-        self.players
-            .insert(id.clone(), (player1.clone(), player2.to_string()));
-        self.storage
-            .save_game(&id, &game, &player1, player2)
-            .expect("Failed to save game");
-        id
+    /// Returns an error if the leaderboard cannot be read.
+    pub fn leaderboard(&mut self) -> Result<Arc<Vec<PlayerStats>>, SessionError> {
+        let now = self.clock.now();
+        if let Some(cached) = &self.leaderboard {
+            if now.duration_since(cached.read_at) < LEADERBOARD_TTL {
+                return Ok(cached.players.clone());
+            }
+        }
+        let players = Arc::new(self.storage.get_leaderboard()?);
+        if self.leaderboard.as_ref().is_some_and(|cached| cached.players != players) {
+            self.leaderboard_generation += 1;
+        }
+        self.leaderboard = Some(CachedLeaderboard {
+            read_at: now,
+            players: players.clone(),
+        });
+        Ok(players)
     }
 
+    /// Returns a tag naming the leaderboard [`Sessions::leaderboard`] last returned, which
+    /// changes whenever the leaderboard does. Tags of different servers never match.
     #[must_use]
-    pub fn get_game(&self, id: &str) -> Option<&Game> {
-        self.games.get(id)
+    pub fn leaderboard_version(&self) -> String {
+        format!("{}-{}", self.instance_id, self.leaderboard_generation)
     }
 
-    pub fn get_game_mut(&mut self, id: &str) -> Option<&mut Game> {
-        self.games.get_mut(id)
+    /// Forgets the leaderboard once a game ending here has changed it, unless the game was
+    /// casual.
+    fn invalidate_leaderboard(&mut self, id: &str) {
+        if self.casual.contains(id) {
+            return;
+        }
+        self.leaderboard = None;
+        self.leaderboard_generation += 1;
     }
 
+    /// Returns the switch of the debug logging of requests, which the router shares.
     #[must_use]
-    pub fn get_players(&self, id: &str) -> Option<&(String, String)> {
-        self.players.get(id)
+    pub fn request_log(&self) -> &RequestLog {
+        &self.request_log
     }
 
-    /// Makes a move in a game.
+    /// Returns the faults injected into the server, which the router shares. They reach the
+    /// storage the sessions were created with; storage swapped in later needs
+    /// [`Storage::inject_faults`].
+    #[cfg(feature = "chaos")]
+    #[must_use]
+    pub fn faults(&self) -> &Faults {
+        &self.faults
+    }
+
+    /// Returns the analyzers run on every move placed by a person.
+    #[must_use]
+    pub fn move_analyzers(&self) -> &[Arc<dyn MoveAnalyzer>] {
+        &self.move_analyzers
+    }
+
+    /// Sets the analyzers run on every move placed by a person, which queue a background job
+    /// per move unless there are none.
+    pub fn set_move_analyzers(&mut self, analyzers: Vec<Arc<dyn MoveAnalyzer>>) {
+        self.move_analyzers = analyzers;
+    }
+
+    /// Returns true if games are shared with other servers through the database.
+    #[must_use]
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// Sets whether games are shared with other servers through the database. With shared
+    /// storage, a game is reloaded whenever another server changed it, and a change to a game
+    /// changed meanwhile elsewhere is refused with [`SessionError::Conflict`].
+    pub fn set_shared(&mut self, shared: bool) {
+        self.shared = shared;
+    }
+
+    /// Returns the id of this server on the update bus.
+    #[must_use]
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Sets the bus announcing changes to the other servers sharing the database. Their
+    /// changes are applied with [`apply_update`](Self::apply_update).
+    pub fn set_update_bus(&mut self, bus: Arc<dyn UpdateBus>) {
+        self.update_bus = Some(bus);
+    }
+
+    /// Sets where the matchmaking queue and the sockets open on each game are kept.
+    pub fn set_cache(&mut self, cache: Box<dyn SessionCache>) {
+        self.cache = Arc::from(cache);
+    }
+
+    /// Returns the configuration of the AI in new games.
+    #[must_use]
+    pub fn ai_config(&self) -> &AiConfig {
+        &self.ai_config
+    }
+
+    /// Sets the configuration of the AI in games it has not played a move in yet.
+    pub fn set_ai_config(&mut self, config: AiConfig) {
+        self.ai_config = config;
+    }
+
+    /// Returns the passes forced by the latest move in a game, in order.
+    #[must_use]
+    pub fn forced_passes(&self, id: &str) -> &[Player] {
+        self.forced_passes.get(id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Adds a player to the matchmaking queue, or starts a game with the player waiting in it.
     ///
     /// # Errors
     ///
-    /// Returns an error if the game is not found, it's not the player's turn, or the move is invalid.
+    /// Returns an error if the queue cannot be read or written, or the game cannot be saved,
+    /// in which case the waiting player is queued again.
+    pub fn join_matchmaking(&mut self, player: String) -> Result<Option<String>, SessionError> {
+        let cache = self.matchmaking_cache()?;
+        let waiting = cache.waiting().map_err(SessionError::Storage)?;
+        let opponents = self.unblocked(&player, waiting)?;
+        take_opponent(&*cache, player, opponents, |player, opponent| self.create_game(player, opponent))
+    }
+
+    /// Adds a player to the matchmaking queue as [`join_matchmaking`](Self::join_matchmaking)
+    /// does, but locks the sessions only to check the players and create the game, so a slow
+    /// queue, e.g. in Redis, holds up no other requests.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panics if the game cannot be saved or if player stats cannot be updated.
-    pub fn make_move(&mut self, id: &str, pos: u8, player: &str) -> Result<(), String> {
-        let (p1, p2) = self.players.get(id).ok_or("Game not found".to_string())?;
-        if let Some(game) = self.games.get_mut(id) {
-            let current_player_name = match game.current_player {
-                Player::Black => p1,
-                Player::White => p2,
-            };
-            if player != current_player_name {
-                return Err("Not your turn".to_string());
-            }
-            if game.is_valid_move(pos) {
-                game.make_move(pos)?;
-                if game.is_game_over() {
-                    if let Some(winner) = game.winner() {
-                        let player_won = winner == Player::Black;
-                        self.storage
-                            .update_player(p1, p2, player_won)
-                            .expect("Failed to update player");
-                    }
-                }
-                self.storage
-                    .save_game(id, game, p1, p2)
-                    .expect("Failed to save game");
-                Ok(())
-            } else {
-                Err("Invalid move".to_string())
+    /// Returns an error as [`join_matchmaking`](Self::join_matchmaking) does.
+    pub fn join_shared_matchmaking(sessions: &Mutex<Self>, player: String) -> Result<Option<String>, SessionError> {
+        let cache = sessions.lock().matchmaking_cache()?;
+        let waiting = cache.waiting().map_err(SessionError::Storage)?;
+        let opponents = sessions.lock().unblocked(&player, waiting)?;
+        take_opponent(&*cache, player, opponents, |player, opponent| {
+            sessions.lock().create_game(player, opponent)
+        })
+    }
+
+    /// Returns the matchmaking queue, unless the server is under maintenance.
+    fn matchmaking_cache(&self) -> Result<Arc<dyn SessionCache>, SessionError> {
+        if self.maintenance.is_some() {
+            return Err(SessionError::UnderMaintenance);
+        }
+        Ok(self.cache.clone())
+    }
+
+    /// Returns the players `waiting` that `player` may be paired with, as players who blocked
+    /// each other are never paired.
+    fn unblocked(&self, player: &str, waiting: Vec<String>) -> Result<Vec<String>, SessionError> {
+        let mut opponents = Vec::with_capacity(waiting.len());
+        for opponent in waiting {
+            if !self.storage.is_blocked(player, &opponent)? {
+                opponents.push(opponent);
             }
-        } else {
-            Err("Game not found".to_string())
         }
+        Ok(opponents)
     }
 
-    /// Passes a turn in a game.
+    /// Creates a game in which `challenger` plays `opponent`, if the opponent accepts their
+    /// challenges.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the opponent refuses the challenge or the game cannot be saved.
+    pub fn challenge(&mut self, challenger: String, opponent: &str) -> Result<String, SessionError> {
+        self.challenge_with_komi(challenger, opponent, self.default_komi)
+    }
+
+    /// Creates a game in which `challenger` plays `opponent` with `komi` discs added to
+    /// White's final count, if the opponent accepts their challenges.
     ///
     /// # Errors
     ///
-    /// Returns an error if the game is not found.
+    /// Returns an error if the komi gives more than [`MAX_KOMI`] discs, the opponent refuses
+    /// the challenge or the game cannot be saved.
+    pub fn challenge_with_komi(&mut self, challenger: String, opponent: &str, komi: i32) -> Result<String, SessionError> {
+        let options = GameOptions {
+            komi,
+            ..GameOptions::default()
+        };
+        self.challenge_with(challenger, opponent, options)
+    }
+
+    /// Creates a game set up with `options` in which `challenger` plays `opponent`, if the
+    /// opponent accepts their challenges.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panics if the game cannot be saved or if player stats cannot be updated.
-    pub fn pass(&mut self, id: &str) -> Result<(), String> {
-        if let Some(game) = self.games.get_mut(id) {
-            game.pass();
-            if game.is_game_over() {
-                let (p1, p2) = self.players.get(id).unwrap();
-                if let Some(winner) = game.winner() {
-                    let player_won = winner == Player::Black;
-                    self.storage
-                        .update_player(p1, p2, player_won)
-                        .expect("Failed to update player");
-                }
-                self.storage
-                    .save_game(id, game, p1, p2)
-                    .expect("Failed to save game");
+    /// Returns an error as [`Sessions::challenge_with_komi`] does.
+    pub fn challenge_with(&mut self, challenger: String, opponent: &str, options: GameOptions) -> Result<String, SessionError> {
+        self.challenge_as(challenger, Player::Black, opponent, options)
+    }
+
+    /// Creates a game set up with `options` in which `challenger` plays `opponent` as
+    /// `color`, if the opponent accepts their challenges. The challenger is player 1 when
+    /// playing Black and player 2 when playing White.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error as [`Sessions::challenge_with_komi`] does.
+    pub fn challenge_as(
+        &mut self,
+        challenger: String,
+        color: Player,
+        opponent: &str,
+        options: GameOptions,
+    ) -> Result<String, SessionError> {
+        if !self.storage.accepts_challenge(&challenger, opponent)? {
+            return Err(SessionError::ChallengeRefused);
+        }
+        match color {
+            Player::Black => self.create_game_with(challenger, opponent, options),
+            Player::White => self.create_game_with(opponent.to_string(), &challenger, options),
+        }
+    }
+
+    /// Creates a new game with the default komi and saves it to the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game cannot be saved.
+    pub fn create_game(&mut self, player1: String, player2: &str) -> Result<String, SessionError> {
+        self.create_game_with_komi(player1, player2, self.default_komi)
+    }
+
+    /// Creates a new game with `komi` discs added to White's final count, to offset Black's
+    /// first move, and saves it to the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the komi gives more than [`MAX_KOMI`] discs, the server is under
+    /// maintenance or the game cannot be saved.
+    pub fn create_game_with_komi(&mut self, player1: String, player2: &str, komi: i32) -> Result<String, SessionError> {
+        let options = GameOptions {
+            komi,
+            ..GameOptions::default()
+        };
+        self.create_game_with(player1, player2, options)
+    }
+
+    /// Creates a new game set up with `options` and saves it to the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error as [`Sessions::create_game_with_komi`] does, or if coaching is asked
+    /// for in a ranked game between two people.
+    pub fn create_game_with(&mut self, player1: String, player2: &str, options: GameOptions) -> Result<String, SessionError> {
+        if options.komi.abs() > MAX_KOMI {
+            return Err(SessionError::InvalidKomi);
+        }
+        // Coaching a ranked game between two people would be playing with an engine's help.
+        if options.coach && options.game_type == GameType::Ranked && !self.is_bot(&player1) && !self.is_bot(player2) {
+            return Err(SessionError::CoachNotAllowed);
+        }
+        if self.maintenance.is_some() {
+            return Err(SessionError::UnderMaintenance);
+        }
+        let id = self.storage.next_game_id()?;
+        let game = Game::new();
+        self.storage.create_game(&id, &game, &player1, player2, options)?;
+        self.set_options(&id, options);
+        if self.shared {
+            self.versions.insert(id.clone(), 0);
+        }
+        self.games.insert(id.clone(), game);
+        self.plies.insert(id.clone(), 0);
+        self.players.insert(id.clone(), (player1, player2.to_string()));
+        self.turn_started.insert(id.clone(), self.clock.now());
+        Ok(id)
+    }
+
+    /// Loads a game from the database if it is not in memory. Only games still being played
+    /// are loaded at startup, so finished ones are brought back when someone asks for them.
+    /// With shared storage, a game another server changed is reloaded too.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found or cannot be loaded.
+    pub fn load_game(&mut self, id: &str) -> Result<(), SessionError> {
+        if !self.shared && self.games.contains_key(id) {
+            return Ok(());
+        }
+        if id.starts_with(SELF_PLAY_PREFIX) {
+            return Err(SessionError::GameNotFound);
+        }
+        if self.shared {
+            let Some(version) = self.storage.game_version(id)? else {
+                // Deleted by another server.
+                self.forget(id);
+                return Err(SessionError::GameNotFound);
+            };
+            if self.games.contains_key(id) && self.versions.get(id) == Some(&version) {
+                return Ok(());
             }
-            Ok(())
+            self.versions.insert(id.to_string(), version);
+        }
+        let (game, player1, player2) = self.storage.load_game(id)?.ok_or(SessionError::GameNotFound)?;
+        self.set_options(id, self.storage.game_options(id)?);
+        match self.storage.resignation(id)? {
+            Some(color) => self.resigned.insert(id.to_string(), color),
+            None => self.resigned.remove(id),
+        };
+        if self.storage.is_aborted(id)? {
+            self.aborted.insert(id.to_string());
         } else {
-            Err("Game not found".to_string())
+            self.aborted.remove(id);
+        }
+        if self.storage.is_drawn(id)? {
+            self.drawn.insert(id.to_string());
+        } else {
+            self.drawn.remove(id);
+        }
+        self.plies.insert(id.to_string(), self.storage.move_count(id)?);
+        self.players.insert(id.to_string(), (player1, player2));
+        let previous = self.games.insert(id.to_string(), game);
+        if previous.as_ref() != self.games.get(id) {
+            // Changed elsewhere, so the next turn started about now.
+            self.forced_passes.remove(id);
+            if self.is_over(id) {
+                self.turn_started.remove(id);
+                self.cancel_search(id);
+                self.ais.remove(id);
+            } else {
+                self.turn_started.insert(id.to_string(), self.clock.now());
+            }
+        }
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn get_game(&self, id: &str) -> Option<&Game> {
+        self.games.get(id)
+    }
+
+    pub fn get_game_mut(&mut self, id: &str) -> Option<&mut Game> {
+        self.games.get_mut(id)
+    }
+
+    #[must_use]
+    pub fn get_players(&self, id: &str) -> Option<&(String, String)> {
+        self.players.get(id)
+    }
+
+    /// Makes a move in a game.
+    ///
+    /// With automatic passing enabled, returns the passes the move forced on players left
+    /// without legal moves.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found, it's not the player's turn, the move is
+    /// invalid, or the result cannot be saved.
+    pub fn make_move(&mut self, id: &str, pos: u8, player: &str) -> Result<Vec<Player>, SessionError> {
+        let game = self.turn_of(id, player)?;
+        let before = game.clone();
+        game.try_place(pos).map_err(SessionError::InvalidMove)?;
+        self.finish_move(id, before, Move::Place(pos), player.to_string(), true)
+    }
+
+    /// Passes a turn in a game.
+    ///
+    /// With automatic passing enabled, returns the passes this forced on players left without
+    /// legal moves.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found, it's not the player's turn, the player has
+    /// a legal move while passes are strict, or the result cannot be saved.
+    pub fn pass(&mut self, id: &str, player: &str) -> Result<Vec<Player>, SessionError> {
+        let strict = self.strict_passes;
+        let game = self.turn_of(id, player)?;
+        // A pass with no legal moves is implied by the move before it, as it is in games
+        // played with automatic passing, so it is kept out of the history.
+        let voluntary = game.has_legal_move(game.current_player);
+        if voluntary && strict {
+            return Err(SessionError::PassNotAllowed);
+        }
+        let before = game.clone();
+        game.pass();
+        self.finish_move(id, before, Move::Pass, player.to_string(), voluntary)
+    }
+
+    /// Plays a move or pass for `player` if the game is still at `expected_ply`, the number
+    /// of moves they saw played. The ply is checked and the move applied under the same
+    /// borrow of the sessions, so of several submissions for the same turn exactly one is
+    /// played, the first to get the sessions, and the others learn which move beat them.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SessionError::Superseded`] if a move was played at `expected_ply` already,
+    /// and otherwise the errors of [`Sessions::make_move`] and [`Sessions::pass`].
+    pub fn apply_move(
+        &mut self,
+        id: &str,
+        player: &str,
+        mv: Move,
+        expected_ply: usize,
+    ) -> Result<Vec<Player>, SessionError> {
+        self.load_game(id)?;
+        let ply = self.ply(id)?;
+        if expected_ply < ply {
+            let record = self
+                .storage
+                .load_move_records(id)?
+                .into_iter()
+                .nth(expected_ply)
+                .ok_or(SessionError::Conflict)?;
+            return Err(SessionError::Superseded {
+                ply: expected_ply,
+                played: record.mv,
+                by: record.player,
+            });
+        }
+        if expected_ply > ply {
+            return Err(SessionError::PlyNotReached(expected_ply));
+        }
+        match mv {
+            Move::Place(pos) => self.make_move(id, pos, player),
+            Move::Pass => self.pass(id, player),
+        }
+    }
+
+    /// Returns the number of moves in a game's history, the ply the next move is stored at.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not in memory.
+    pub fn ply(&self, id: &str) -> Result<usize, SessionError> {
+        self.plies.get(id).copied().ok_or(SessionError::GameNotFound)
+    }
+
+    /// Returns the game if it is `player`'s turn in it.
+    fn turn_of(&mut self, id: &str, player: &str) -> Result<&mut Game, SessionError> {
+        self.load_game(id)?;
+        if self.is_over(id) {
+            return Err(SessionError::NotYourTurn);
+        }
+        let (p1, p2) = self.players.get(id).ok_or(SessionError::GameNotFound)?;
+        let game = self.games.get_mut(id).ok_or(SessionError::GameNotFound)?;
+        let current_player_name = match game.current_player {
+            Player::Black => p1,
+            Player::White => p2,
+        };
+        if player != current_player_name {
+            return Err(SessionError::NotYourTurn);
+        }
+        Ok(game)
+    }
+
+    /// Applies automatic passes after a move, then stores the result and updates ratings and
+    /// the game's AI.
+    ///
+    /// If the result cannot be saved, the game is put back to `before` so memory and the
+    /// database stay in step.
+    fn finish_move(
+        &mut self,
+        id: &str,
+        before: Game,
+        mv: Move,
+        player: String,
+        record: bool,
+    ) -> Result<Vec<Player>, SessionError> {
+        let (Some(game), Some((p1, p2))) = (self.games.get_mut(id), self.players.get(id)) else {
+            return Err(SessionError::GameNotFound);
+        };
+        let forced = if self.auto_passes.get(id).copied().unwrap_or(self.auto_pass) {
+            game.auto_pass()
+        } else {
+            Vec::new()
+        };
+        let game_over = game.is_game_over();
+        let (game, p1, p2) = (game.clone(), p1.clone(), p2.clone());
+        let (scoring_rule, komi) = (self.scoring_rule, self.komi(id));
+        let saved = self.write_game(id, |storage| {
+            if game_over {
+                storage.finish_game(id, &game, &GameResult::of(&game, scoring_rule, komi, &p1, &p2))
+            } else {
+                storage.save_game(id, &game, &p1, &p2)
+            }
+        });
+        if let Err(e) = saved {
+            if let Some(game) = self.games.get_mut(id) {
+                *game = before;
+            }
+            return Err(e);
+        }
+        let elapsed_ms = self.end_turn(id, game_over);
+        if record {
+            self.record_move(id, mv, player, elapsed_ms);
+        }
+        if game_over {
+            self.invalidate_leaderboard(id);
+            self.award_achievements(id);
+            self.record_ai_game(id);
+        }
+        self.notify_ai(id, mv, game_over);
+        self.forced_passes.insert(id.to_string(), forced.clone());
+        self.announce(
+            id,
+            GameEvent::Delta(Delta {
+                player: before.current_player.as_str().to_string(),
+                action: mv.to_string(),
+                flipped: match mv {
+                    Move::Place(pos) => Game::bitboard_to_coords(before.flips(pos)),
+                    Move::Pass => Vec::new(),
+                },
+                flip_lines: match mv {
+                    Move::Place(pos) => FlippedLine::from_lines(&before.flip_details(pos)),
+                    Move::Pass => Vec::new(),
+                },
+                forced_passes: forced.iter().map(|player| player.as_str().to_string()).collect(),
+            }),
+        );
+        Ok(forced)
+    }
+
+    /// Returns true if the game has ended, on the board, by resignation or by being aborted.
+    #[must_use]
+    pub fn is_over(&self, id: &str) -> bool {
+        self.resigned.contains_key(id)
+            || self.aborted.contains(id)
+            || self.drawn.contains(id)
+            || self.games.get(id).is_some_and(Game::is_game_over)
+    }
+
+    /// Returns true if the game was aborted.
+    #[must_use]
+    pub fn is_aborted(&self, id: &str) -> bool {
+        self.aborted.contains(id)
+    }
+
+    /// Returns the color that resigned the game, if it was resigned.
+    #[must_use]
+    pub fn resigned(&self, id: &str) -> Option<Player> {
+        self.resigned.get(id).copied()
+    }
+
+    /// Returns the winner of a finished game, or `None` for a draw or a game still in
+    /// progress.
+    #[must_use]
+    pub fn winner(&self, id: &str) -> Option<Player> {
+        if self.is_aborted(id) || self.drawn.contains(id) {
+            return None;
+        }
+        match self.resigned(id) {
+            Some(resigned) => Some(resigned.opponent()),
+            None => self.games.get(id)?.winner_with_komi(self.scoring_rule, self.komi(id)),
+        }
+    }
+
+    /// Returns the result of a finished game, taking a resignation or a claimed draw into
+    /// account.
+    fn result(&self, id: &str) -> Option<GameResult<'_>> {
+        let (game, (p1, p2)) = (self.games.get(id)?, self.players.get(id)?);
+        let mut result = GameResult::of(game, self.scoring_rule, self.komi(id), p1, p2);
+        if let Some(resigned) = self.resigned(id) {
+            result.winner = Some(resigned.opponent());
+        }
+        if self.drawn.contains(id) {
+            result.winner = None;
+        }
+        Some(result)
+    }
+
+    /// Returns true if the game was drawn by a claim of inactivity.
+    #[must_use]
+    pub fn is_drawn(&self, id: &str) -> bool {
+        self.drawn.contains(id)
+    }
+
+    /// Ends a game in progress by `player` resigning it, and returns the color they played.
+    /// The opponent wins, whatever the discs on the board.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found or already over, `player` is not playing
+    /// in it, or the result cannot be saved.
+    pub fn resign(&mut self, id: &str, player: &str) -> Result<Player, SessionError> {
+        self.load_game(id)?;
+        let Some((p1, p2)) = self.players.get(id) else {
+            return Err(SessionError::GameNotFound);
+        };
+        let color = if player == p1 {
+            Player::Black
+        } else if player == p2 {
+            Player::White
+        } else {
+            return Err(SessionError::NotAParticipant);
+        };
+        if self.is_over(id) {
+            return Err(SessionError::GameOver);
+        }
+        self.concede(id, color, "resign")?;
+        Ok(color)
+    }
+
+    /// Ends a game in progress as lost by `color`, announcing it as `action`.
+    fn concede(&mut self, id: &str, color: Player, action: &str) -> Result<(), SessionError> {
+        let (Some(game), Some((p1, p2))) = (self.games.get(id), self.players.get(id)) else {
+            return Err(SessionError::GameNotFound);
+        };
+        let (game, p1, p2) = (game.clone(), p1.clone(), p2.clone());
+        let mut result = GameResult::of(&game, self.scoring_rule, self.komi(id), &p1, &p2);
+        result.winner = Some(color.opponent());
+        self.write_game(id, |storage| storage.resign_game(id, &game, color, &result))?;
+        self.resigned.insert(id.to_string(), color);
+        self.end_turn(id, true);
+        self.invalidate_leaderboard(id);
+        self.award_achievements(id);
+        self.record_ai_game(id);
+        self.cancel_search(id);
+        self.ais.remove(id);
+        self.reconnect_deadlines.remove(id);
+        self.announce(
+            id,
+            GameEvent::Delta(Delta {
+                player: color.as_str().to_string(),
+                action: action.to_string(),
+                flipped: Vec::new(),
+                flip_lines: Vec::new(),
+                forced_passes: Vec::new(),
+            }),
+        );
+        Ok(())
+    }
+
+    /// Sends a chat message from `from` to everyone watching the game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found or the message is empty or too long.
+    pub fn chat(&mut self, id: &str, from: &str, text: &str) -> Result<(), SessionError> {
+        if !self.games.contains_key(id) {
+            return Err(SessionError::GameNotFound);
+        }
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(SessionError::EmptyChat);
+        }
+        if text.chars().count() > MAX_CHAT_LEN {
+            return Err(SessionError::ChatTooLong);
+        }
+        self.announce(
+            id,
+            GameEvent::Chat {
+                from: from.to_string(),
+                text: text.to_string(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Returns a receiver of the game's events, for a socket watching it.
+    pub fn subscribe(&mut self, id: &str) -> broadcast::Receiver<GameEvent> {
+        self.events
+            .entry(id.to_string())
+            .or_insert_with(|| broadcast::channel(EVENT_BUFFER).0)
+            .subscribe()
+    }
+
+    /// Records a socket opened on a game by `name`, or anonymously, announces it to the other
+    /// sockets and returns a receiver of the game's events.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found or the socket cannot be recorded.
+    pub fn join(&mut self, id: &str, name: Option<&str>) -> Result<broadcast::Receiver<GameEvent>, SessionError> {
+        if !self.games.contains_key(id) {
+            return Err(SessionError::GameNotFound);
+        }
+        let spectator = !self.is_player(id, name);
+        let player = name.filter(|_| !spectator);
+        let sockets = self.cache.add_socket(id, player).map_err(SessionError::Storage)?;
+        self.open_sockets += 1;
+        // A player is only announced on their first connection, e.g. not on opening a tab.
+        let announce = match player {
+            Some(player) => {
+                if let Some(deadlines) = self.reconnect_deadlines.get_mut(id) {
+                    deadlines.remove(player);
+                }
+                sockets == 1
+            }
+            None => true,
+        };
+        // Announced before subscribing, so the socket is not told about itself.
+        if announce {
+            let watcher = Watcher {
+                name: name.map(str::to_string),
+                spectator,
+            };
+            self.announce(id, GameEvent::Joined(watcher));
+        }
+        Ok(self.subscribe(id))
+    }
+
+    /// Records a socket opened by [`join`](Self::join) closing, and announces it.
+    ///
+    /// When a player loses their last connection to a game against another person, they
+    /// have the grace period to reconnect before [`expire_disconnection`] ends the game, and
+    /// the period is returned.
+    ///
+    /// [`expire_disconnection`]: Self::expire_disconnection
+    pub fn leave(&mut self, id: &str, name: Option<&str>) -> Option<Duration> {
+        self.open_sockets = self.open_sockets.saturating_sub(1);
+        if !self.games.contains_key(id) {
+            return None;
+        }
+        let spectator = !self.is_player(id, name);
+        let player = name.filter(|_| !spectator);
+        let sockets = match self.cache.remove_socket(id, player) {
+            Ok(sockets) => sockets,
+            Err(e) => {
+                tracing::warn!("Failed to record a socket leaving game {id}: {e}");
+                return None;
+            }
+        };
+        let announce = player.is_none() || sockets == 0;
+        if announce {
+            let watcher = Watcher {
+                name: name.map(str::to_string),
+                spectator,
+            };
+            self.announce(id, GameEvent::Left(watcher));
+        }
+        let name = name.filter(|_| announce && !spectator)?;
+        let grace = self.disconnect_grace.filter(|_| self.can_abandon(id))?;
+        self.reconnect_deadlines
+            .entry(id.to_string())
+            .or_default()
+            .insert(name.to_string(), self.clock.now() + grace);
+        let countdown = CountdownResponse {
+            player: name.to_string(),
+            seconds: grace.as_secs(),
+            abort: self.in_opening(id),
+        };
+        self.publish(id, GameEvent::Countdown(countdown));
+        Some(grace)
+    }
+
+    /// Ends the game if `player` left it and has not come back within the grace period:
+    /// the game is aborted if still in the opening, and forfeited otherwise. Returns how it
+    /// ended, or `None` if the player is not out of time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found or its end cannot be saved.
+    pub fn expire_disconnection(&mut self, id: &str, player: &str) -> Result<Option<Abandonment>, SessionError> {
+        let Some((p1, _)) = self.players.get(id) else {
+            return Err(SessionError::GameNotFound);
+        };
+        let color = if player == p1 { Player::Black } else { Player::White };
+        let expired = self
+            .reconnect_deadlines
+            .get(id)
+            .and_then(|deadlines| deadlines.get(player))
+            .is_some_and(|&deadline| deadline <= self.clock.now());
+        if !expired || self.is_connected(id, player) || self.is_over(id) {
+            return Ok(None);
+        }
+        if self.in_opening(id) {
+            self.abort(id, color)?;
+            Ok(Some(Abandonment::Aborted))
+        } else {
+            self.concede(id, color, "forfeit")?;
+            Ok(Some(Abandonment::Forfeited(color)))
+        }
+    }
+
+    /// Ends the game on behalf of `claimant` if their opponent left it and is out of time,
+    /// without waiting for the timer that would end it anyway. Returns how it ended, as
+    /// [`expire_disconnection`] does.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found or already over, `claimant` is not playing
+    /// in it, the opponent still has time to reconnect, or the end cannot be saved.
+    ///
+    /// [`expire_disconnection`]: Self::expire_disconnection
+    pub fn claim_win(&mut self, id: &str, claimant: &str) -> Result<Abandonment, SessionError> {
+        self.load_game(id)?;
+        let Some((p1, p2)) = self.players.get(id) else {
+            return Err(SessionError::GameNotFound);
+        };
+        let opponent = if claimant == p1 {
+            p2.clone()
+        } else if claimant == p2 {
+            p1.clone()
+        } else {
+            return Err(SessionError::NotAParticipant);
+        };
+        if self.is_over(id) {
+            return Err(SessionError::GameOver);
+        }
+        self.expire_disconnection(id, &opponent)?.ok_or(SessionError::NothingToClaim)
+    }
+
+    /// Ends a game against another person as a draw on behalf of `claimant` once nobody has
+    /// moved in it for the inactivity period. The player to move cannot claim it, or they
+    /// could stall a lost game into a draw. The period runs from the start of the turn, or
+    /// from the first claim for a turn this server has not seen start.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found or already over, `claimant` is not playing
+    /// in it, it is not between two people or draws are never claimed, a move was played
+    /// within the period or it is the claimant's turn, or the draw cannot be saved.
+    pub fn claim_draw(&mut self, id: &str, claimant: &str) -> Result<(), SessionError> {
+        self.load_game(id)?;
+        let Some((p1, p2)) = self.players.get(id) else {
+            return Err(SessionError::GameNotFound);
+        };
+        if claimant != p1 && claimant != p2 {
+            return Err(SessionError::NotAParticipant);
+        }
+        let color = if claimant == p1 { Player::Black } else { Player::White };
+        if self.is_over(id) {
+            return Err(SessionError::GameOver);
+        }
+        let Some(period) = self.inactivity_draw.filter(|_| self.can_abandon(id)) else {
+            return Err(SessionError::NothingToClaim);
+        };
+        let now = self.clock.now();
+        let started = *self.turn_started.entry(id.to_string()).or_insert(now);
+        let to_move = self.games.get(id).map(|game| game.current_player);
+        if to_move == Some(color) || now.duration_since(started) < period {
+            return Err(SessionError::StillActive(period.as_secs()));
+        }
+        let (Some(game), Some((p1, p2))) = (self.games.get(id), self.players.get(id)) else {
+            return Err(SessionError::GameNotFound);
+        };
+        let (game, p1, p2) = (game.clone(), p1.clone(), p2.clone());
+        let mut result = GameResult::of(&game, self.scoring_rule, self.komi(id), &p1, &p2);
+        result.winner = None;
+        self.write_game(id, |storage| storage.draw_game(id, &game, &result))?;
+        self.drawn.insert(id.to_string());
+        self.end_turn(id, true);
+        self.invalidate_leaderboard(id);
+        self.award_achievements(id);
+        self.reconnect_deadlines.remove(id);
+        self.announce(
+            id,
+            GameEvent::Delta(Delta {
+                player: color.as_str().to_string(),
+                action: "draw".to_string(),
+                flipped: Vec::new(),
+                flip_lines: Vec::new(),
+                forced_passes: Vec::new(),
+            }),
+        );
+        Ok(())
+    }
+
+    /// Returns the players out of a game who will lose it unless they reconnect, with the
+    /// time they have left.
+    #[must_use]
+    pub fn countdowns(&self, id: &str) -> Vec<CountdownResponse> {
+        let now = self.clock.now();
+        let abort = self.in_opening(id);
+        let mut countdowns: Vec<_> = self
+            .reconnect_deadlines
+            .get(id)
+            .into_iter()
+            .flatten()
+            .map(|(player, deadline)| CountdownResponse {
+                player: player.clone(),
+                seconds: deadline.saturating_duration_since(now).as_secs(),
+                abort,
+            })
+            .collect();
+        countdowns.sort_by(|a, b| a.player.cmp(&b.player));
+        countdowns
+    }
+
+    /// Returns true if a player leaving the game can lose it: it is in progress and between
+    /// two people.
+    fn can_abandon(&self, id: &str) -> bool {
+        self.players.get(id).is_some_and(|(p1, p2)| !self.is_bot(p1) && !self.is_bot(p2)) && !self.is_over(id)
+    }
+
+    /// Returns true if fewer than [`ABORT_PLIES`] discs have been played in the game.
+    fn in_opening(&self, id: &str) -> bool {
+        self.games
+            .get(id)
+            .is_some_and(|game| game.occupied().count_ones() < 4 + ABORT_PLIES)
+    }
+
+    /// Ends a game in progress without a result, after `color` left it.
+    fn abort(&mut self, id: &str, color: Player) -> Result<(), SessionError> {
+        self.write_game(id, |storage| storage.abort_game(id))?;
+        self.aborted.insert(id.to_string());
+        self.end_turn(id, true);
+        self.reconnect_deadlines.remove(id);
+        self.announce(
+            id,
+            GameEvent::Delta(Delta {
+                player: color.as_str().to_string(),
+                action: "abort".to_string(),
+                flipped: Vec::new(),
+                flip_lines: Vec::new(),
+                forced_passes: Vec::new(),
+            }),
+        );
+        Ok(())
+    }
+
+    /// Returns true if `player` has a socket open on the game. The AI and engines are always
+    /// connected.
+    #[must_use]
+    pub fn is_connected(&self, id: &str, player: &str) -> bool {
+        if self.is_bot(player) {
+            return true;
+        }
+        match self.cache.connected(id) {
+            Ok(connected) => connected.contains(player),
+            Err(e) => {
+                tracing::warn!("Failed to read the sockets of game {id}: {e}");
+                false
+            }
+        }
+    }
+
+    /// Returns the number of sockets open on the game by anyone but its players.
+    #[must_use]
+    pub fn spectators(&self, id: &str) -> usize {
+        self.cache.spectators(id).unwrap_or_else(|e| {
+            tracing::warn!("Failed to read the sockets of game {id}: {e}");
+            0
+        })
+    }
+
+    fn is_player(&self, id: &str, name: Option<&str>) -> bool {
+        match (self.players.get(id), name) {
+            (Some((p1, p2)), Some(name)) => name == p1 || name == p2,
+            _ => false,
+        }
+    }
+
+    /// Sends an event to the sockets watching the game, forgetting the game's channel once
+    /// nobody is watching.
+    fn publish(&mut self, id: &str, event: GameEvent) {
+        if let Some(sender) = self.events.get(id) {
+            if sender.send(event).is_err() {
+                self.events.remove(id);
+            }
+        }
+    }
+
+    /// Publishes an event, and announces it to the other servers sharing the database unless
+    /// it is a countdown, which only runs on the server the player left, or a notice, which
+    /// [`broadcast`](Self::broadcast) announces once for every game.
+    fn announce(&mut self, id: &str, event: GameEvent) {
+        match &event {
+            GameEvent::Delta(delta) => self.send_update(id, Change::Delta(delta.clone())),
+            GameEvent::Chat { from, text } => self.send_update(
+                id,
+                Change::Chat {
+                    from: from.clone(),
+                    text: text.clone(),
+                },
+            ),
+            GameEvent::Joined(watcher) => self.send_update(id, Change::Joined(watcher.clone())),
+            GameEvent::Left(watcher) => self.send_update(id, Change::Left(watcher.clone())),
+            GameEvent::Coach { player, analysis } => self.send_update(
+                id,
+                Change::Coach {
+                    player: player.clone(),
+                    analysis: analysis.clone(),
+                },
+            ),
+            GameEvent::Countdown(_) | GameEvent::Notice(_) => {}
+        }
+        self.publish(id, event);
+    }
+
+    /// Tells the sockets `player` has open on the game how good the move they placed was, on
+    /// this server and the others sharing the database.
+    pub fn coach(&mut self, id: &str, player: &str, analysis: MoveAnalysis) {
+        let event = GameEvent::Coach {
+            player: player.to_string(),
+            analysis,
+        };
+        self.announce(id, event);
+    }
+
+    /// Sends a notice to every socket open on a game, on this server and the others sharing
+    /// the database, and returns the number of games told here.
+    pub fn broadcast(&mut self, notice: &Notice) -> usize {
+        self.send_update("", Change::Broadcast(notice.clone()));
+        self.publish_to_all(notice)
+    }
+
+    /// Sends a notice to every socket open on a game on this server.
+    fn publish_to_all(&mut self, notice: &Notice) -> usize {
+        let ids: Vec<String> = self.events.keys().cloned().collect();
+        for id in &ids {
+            self.publish(id, GameEvent::Notice(notice.clone()));
+        }
+        // Games whose sockets all closed are dropped on publishing.
+        ids.iter().filter(|id| self.events.contains_key(*id)).count()
+    }
+
+    /// Returns the number of sockets open on this server.
+    #[must_use]
+    pub fn open_sockets(&self) -> usize {
+        self.open_sockets
+    }
+
+    /// Returns the ids of the games in memory that are still in progress, sorted.
+    #[must_use]
+    pub fn active_games(&self) -> Vec<&str> {
+        let mut ids: Vec<&str> = self
+            .games
+            .keys()
+            .map(String::as_str)
+            .filter(|id| !self.is_over(id))
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Returns what the AI playing in the game is doing, or `None` if no AI is kept for it.
+    #[must_use]
+    pub fn ai_activity(&self, id: &str) -> Option<AiActivity> {
+        let kept = self.ais.get(id)?;
+        Some(if kept.ai.try_lock().is_some() {
+            AiActivity::Idle
+        } else {
+            AiActivity::Searching
+        })
+    }
+
+    /// Returns the stored version of the game, with shared storage.
+    #[must_use]
+    pub fn version(&self, id: &str) -> Option<i64> {
+        self.versions.get(id).copied().filter(|_| self.shared)
+    }
+
+    /// Returns the players waiting for a match, longest waiting first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the queue cannot be read.
+    pub fn matchmaking_queue(&self) -> Result<Vec<String>, SessionError> {
+        self.cache.waiting().map_err(SessionError::Storage)
+    }
+
+    /// Returns the maintenance under way, if any.
+    #[must_use]
+    pub fn maintenance(&self) -> Option<Maintenance> {
+        self.maintenance
+    }
+
+    /// Returns the time left, by the sessions' clock, until the deadline of the maintenance
+    /// under way, if it has one.
+    #[must_use]
+    pub fn maintenance_time_left(&self) -> Option<Duration> {
+        let deadline = self.maintenance?.deadline?;
+        Some(deadline.saturating_duration_since(self.clock.now()))
+    }
+
+    /// Starts draining the server for maintenance: no new games start, and the sockets open
+    /// on it are warned. Games being played hold up a restart until they end, or until
+    /// `deadline` has passed. With `restart`, the server stops by itself once drained.
+    pub fn start_maintenance(&mut self, deadline: Option<Duration>, restart: bool) {
+        self.maintenance = Some(Maintenance {
+            deadline: deadline.map(|deadline| self.clock.now() + deadline),
+            restart,
+        });
+        let notice = match deadline {
+            Some(deadline) => {
+                Notice::new(MessageCode::MaintenanceDeadline).with("seconds", deadline.as_secs().to_string())
+            }
+            None => Notice::new(MessageCode::MaintenanceStarted),
+        };
+        self.publish_to_all(&notice);
+        tracing::info!("Maintenance started, {} games being played", self.live_games().len());
+    }
+
+    /// Ends maintenance, so new games start again.
+    pub fn end_maintenance(&mut self) {
+        if self.maintenance.take().is_some() {
+            tracing::info!("Maintenance ended");
+        }
+    }
+
+    /// Returns the games in progress with one of their players connected, sorted.
+    #[must_use]
+    pub fn live_games(&self) -> Vec<&str> {
+        self.active_games()
+            .into_iter()
+            .filter(|id| {
+                self.players.get(*id).is_some_and(|(p1, p2)| {
+                    [p1, p2]
+                        .into_iter()
+                        .any(|player| !self.is_bot(player) && self.is_connected(id, player))
+                })
+            })
+            .collect()
+    }
+
+    /// Returns true if the server is under maintenance and nothing holds up a restart: no
+    /// game is being played, or the deadline has passed.
+    #[must_use]
+    pub fn is_drained(&self) -> bool {
+        self.maintenance.is_some_and(|maintenance| {
+            maintenance.deadline.is_some_and(|deadline| self.clock.now() >= deadline) || self.live_games().is_empty()
+        })
+    }
+
+    /// Closes every socket open on the server as it stops, telling them it restarts. Players
+    /// do not lose their games for leaving now, as the games are saved and resume with the
+    /// server.
+    pub fn close_sockets(&mut self) {
+        self.disconnect_grace = None;
+        self.publish_to_all(&Notice::new(MessageCode::Restarting));
+        // Dropping the senders ends the event stream of every socket.
+        self.events.clear();
+    }
+
+    /// Tells the other servers about a change to a game, if there is an update bus.
+    fn send_update(&self, id: &str, change: Change) {
+        if let Some(bus) = &self.update_bus {
+            bus.publish(&GameUpdate {
+                origin: self.instance_id.clone(),
+                game_id: id.to_string(),
+                change,
+            });
+        }
+    }
+
+    /// Applies a change announced by another server: the game is reloaded and the sockets
+    /// watching it here are told. Updates of games not in memory, and this server's own, are
+    /// ignored; the games are loaded when someone asks for them.
+    pub fn apply_update(&mut self, update: GameUpdate) {
+        let id = update.game_id.as_str();
+        if update.origin == self.instance_id {
+            return;
+        }
+        if let Change::Broadcast(notice) = &update.change {
+            self.publish_to_all(notice);
+            return;
+        }
+        if !self.games.contains_key(id) {
+            return;
+        }
+        match update.change {
+            Change::Delta(delta) => match self.load_game(id) {
+                Ok(()) => self.publish(id, GameEvent::Delta(delta)),
+                Err(e) => tracing::warn!("Failed to reload game {id}: {e}"),
+            },
+            Change::Chat { from, text } => self.publish(id, GameEvent::Chat { from, text }),
+            Change::Joined(watcher) => self.publish(id, GameEvent::Joined(watcher)),
+            Change::Left(watcher) => self.publish(id, GameEvent::Left(watcher)),
+            Change::Coach { player, analysis } => self.publish(id, GameEvent::Coach { player, analysis }),
+            Change::Removed => self.forget(id),
+            Change::Broadcast(_) => {}
+        }
+    }
+
+    /// Runs a write to the stored state of a game. With shared storage, it only happens if no
+    /// other server changed the game since it was loaded.
+    fn write_game(
+        &mut self,
+        id: &str,
+        write: impl FnOnce(&Storage) -> rusqlite::Result<()>,
+    ) -> Result<(), SessionError> {
+        if !self.shared {
+            return Ok(write(&self.storage)?);
+        }
+        let version = self.versions.get(id).copied().unwrap_or_default();
+        if !self.storage.write_game_version(id, version, write)? {
+            // The other server's change is loaded on the next request.
+            return Err(SessionError::Conflict);
+        }
+        self.versions.insert(id.to_string(), version + 1);
+        Ok(())
+    }
+
+    /// Returns the moves played so far in a game, with their timing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found or its history cannot be loaded.
+    pub fn history(&self, id: &str) -> Result<Vec<MoveRecord>, SessionError> {
+        if !self.games.contains_key(id) {
+            return Err(SessionError::GameNotFound);
+        }
+        Ok(self.storage.load_move_records(id)?)
+    }
+
+    /// Returns the last move stored in a game's history with the discs it flipped, or `None`
+    /// before the first move or if the history cannot be read.
+    #[must_use]
+    pub fn last_move(&self, id: &str) -> Option<(Move, u64)> {
+        let moves = match self.storage.load_moves(id) {
+            Ok(moves) => moves,
+            Err(e) => {
+                tracing::warn!("Failed to load the history of game {id}: {e}");
+                return None;
+            }
+        };
+        let replay = match GameReplay::from_moves(moves) {
+            Ok(replay) => replay,
+            Err(e) => {
+                tracing::warn!("Failed to replay game {id}: {e}");
+                return None;
+            }
+        };
+        let &mv = replay.moves().last()?;
+        let flipped = match mv {
+            Move::Place(pos) => replay.board_at(replay.len() - 1)?.flips(pos),
+            Move::Pass => 0,
+        };
+        Some((mv, flipped))
+    }
+
+    /// Queues a background job and wakes a worker to run it, returning the job's id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the job cannot be saved.
+    pub fn enqueue_job(&self, task: &Task) -> Result<i64, SessionError> {
+        let id = self.storage.enqueue_job(task, jobs::MAX_ATTEMPTS, jobs::now_ms())?;
+        self.job_signal.notify_one();
+        Ok(id)
+    }
+
+    /// Returns the signal woken whenever a job is queued.
+    #[must_use]
+    pub fn job_signal(&self) -> Arc<Notify> {
+        self.job_signal.clone()
+    }
+
+    /// Queues the full analysis of a finished game, returning the id of its job. If an
+    /// analysis of the game is already queued or running, its job is returned instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found or not finished, or the job cannot be
+    /// queued.
+    pub fn start_analysis(&self, id: &str) -> Result<i64, SessionError> {
+        if !self.games.contains_key(id) {
+            return Err(SessionError::GameNotFound);
+        }
+        if !self.is_over(id) {
+            return Err(SessionError::GameInProgress);
+        }
+        match self.analysis_job(id)? {
+            Some(job) => Ok(job.id),
+            None => self.enqueue_job(&Self::analysis_task(id)),
+        }
+    }
+
+    /// Returns the queued or running analysis job of a game, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the jobs cannot be loaded.
+    pub fn analysis_job(&self, id: &str) -> Result<Option<Job>, SessionError> {
+        Ok(self.storage.active_job(&Self::analysis_task(id))?)
+    }
+
+    fn analysis_task(id: &str) -> Task {
+        Task::Analysis {
+            game_id: id.to_string(),
+        }
+    }
+
+    /// Returns the stored analysis of a game, or `None` if it has not been analysed. A game
+    /// being analysed again counts as not analysed yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found or the report cannot be loaded.
+    pub fn analysis_report(&self, id: &str) -> Result<Option<AnalysisReport>, SessionError> {
+        if !self.games.contains_key(id) {
+            return Err(SessionError::GameNotFound);
+        }
+        if self.analysis_job(id)?.is_some() {
+            return Ok(None);
+        }
+        Ok(self.storage.load_analysis_report(id)?)
+    }
+
+    /// Attaches an annotation to a move already played in a game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found, the ply has not been played yet, or the
+    /// annotation cannot be saved.
+    pub fn annotate(&self, id: &str, annotation: &Annotation) -> Result<(), SessionError> {
+        let played = self.history(id)?.len();
+        if annotation.ply >= played {
+            return Err(SessionError::PlyNotPlayed(annotation.ply));
+        }
+        Ok(self.storage.add_annotation(id, annotation)?)
+    }
+
+    /// Returns the annotations of a game, ordered by ply.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found or its annotations cannot be loaded.
+    pub fn annotations(&self, id: &str) -> Result<Vec<Annotation>, SessionError> {
+        if !self.games.contains_key(id) {
+            return Err(SessionError::GameNotFound);
+        }
+        Ok(self.storage.load_annotations(id)?)
+    }
+
+    /// Returns the metadata of a game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found or its metadata cannot be loaded.
+    pub fn metadata(&self, id: &str) -> Result<BTreeMap<String, String>, SessionError> {
+        if !self.games.contains_key(id) {
+            return Err(SessionError::GameNotFound);
+        }
+        Ok(self.storage.load_metadata(id)?)
+    }
+
+    /// Sets metadata entries of a game on behalf of one of its players, removing the entries
+    /// set to `None`, and returns the updated metadata. The update is applied entirely or not
+    /// at all.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found, `player` is not playing in it, the update
+    /// breaks the metadata limits, or it cannot be saved.
+    pub fn update_metadata(
+        &self,
+        id: &str,
+        player: &str,
+        updates: &BTreeMap<String, Option<String>>,
+    ) -> Result<BTreeMap<String, String>, SessionError> {
+        let (p1, p2) = self.players.get(id).ok_or(SessionError::GameNotFound)?;
+        if player != p1 && player != p2 {
+            return Err(SessionError::NotAParticipant);
+        }
+        let mut metadata = self.storage.load_metadata(id)?;
+        for (key, value) in updates {
+            if key.is_empty()
+                || key.len() > MAX_METADATA_KEY_LEN
+                || !key.chars().all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c))
+            {
+                return Err(SessionError::InvalidMetadataKey);
+            }
+            match value {
+                Some(value) if value.len() > MAX_METADATA_VALUE_LEN => {
+                    return Err(SessionError::MetadataValueTooLong(key.clone()));
+                }
+                Some(value) => metadata.insert(key.clone(), value.clone()),
+                None => metadata.remove(key),
+            };
+        }
+        if metadata.len() > MAX_METADATA_ENTRIES {
+            return Err(SessionError::TooManyMetadataEntries);
+        }
+        self.storage.save_metadata(id, &metadata)?;
+        Ok(metadata)
+    }
+
+    /// Deletes a game on behalf of `requester` and forgets everything about it: the running
+    /// AI search is cancelled, sockets watching it are closed, and its state, moves,
+    /// annotations and metadata are deleted from storage. Results already recorded, with
+    /// their rating changes, are kept.
+    ///
+    /// Admins may delete any game. Players may delete their own games, except one still being
+    /// played against another person, which would take the result from the opponent.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found, `requester` may not delete it, or it cannot
+    /// be deleted from storage, in which case it is kept.
+    pub fn remove_game(&mut self, id: &str, requester: &str) -> Result<(), SessionError> {
+        let (p1, p2) = self.players.get(id).ok_or(SessionError::GameNotFound)?;
+        if !self.is_admin(requester) {
+            if requester != p1 && requester != p2 {
+                return Err(SessionError::NotAParticipant);
+            }
+            if self.can_abandon(id) {
+                return Err(SessionError::GameInProgress);
+            }
+        }
+        self.storage.delete_game(id)?;
+        self.forget(id);
+        self.send_update(id, Change::Removed);
+        tracing::info!("{requester} deleted game {id}");
+        Ok(())
+    }
+
+    /// Stars a game in memory for `player`, who need not be playing it, or takes their star
+    /// off it. Returns false if it already was as asked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game is not found, or the star cannot be saved.
+    pub fn star_game(&self, id: &str, player: &str, starred: bool) -> Result<bool, SessionError> {
+        if !self.games.contains_key(id) {
+            return Err(SessionError::GameNotFound);
+        }
+        Ok(self.storage.set_starred(player, id, starred)?)
+    }
+
+    /// Returns the analysis boards open on this server.
+    #[must_use]
+    pub fn boards(&self) -> &Boards {
+        &self.boards
+    }
+
+    /// Returns the analysis boards open on this server, to open, change or close one.
+    pub fn boards_mut(&mut self) -> &mut Boards {
+        &mut self.boards
+    }
+
+    /// Returns how long finished games are kept, if they are ever deleted.
+    #[must_use]
+    pub fn retention(&self) -> Option<RetentionPolicy> {
+        self.retention
+    }
+
+    /// Deletes a game past its retention period, keeping only the players' results, and
+    /// tells the other servers it is gone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game cannot be deleted.
+    pub fn expire_game(&mut self, id: &str) -> Result<(), SessionError> {
+        self.storage.delete_game(id)?;
+        self.forget(id);
+        self.send_update(id, Change::Removed);
+        Ok(())
+    }
+
+    /// Keeps how a game in memory was set up.
+    fn set_options(&mut self, id: &str, options: GameOptions) {
+        match options.komi {
+            0 => self.komi.remove(id),
+            komi => self.komi.insert(id.to_string(), komi),
+        };
+        match options.game_type {
+            GameType::Ranked => self.casual.remove(id),
+            GameType::Casual => self.casual.insert(id.to_string()),
+        };
+        if options.private {
+            self.private.insert(id.to_string());
+        } else {
+            self.private.remove(id);
+        }
+        if options.coach {
+            self.coached.insert(id.to_string());
+        } else {
+            self.coached.remove(id);
+        }
+        match options.auto_pass {
+            Some(auto_pass) => self.auto_passes.insert(id.to_string(), auto_pass),
+            None => self.auto_passes.remove(id),
+        };
+    }
+
+    /// Drops everything kept in memory about a game, closing the sockets watching it.
+    fn forget(&mut self, id: &str) {
+        if let Some(search) = self.searches.remove(id) {
+            search.cancel();
+        }
+        self.games.remove(id);
+        self.players.remove(id);
+        self.komi.remove(id);
+        self.casual.remove(id);
+        self.private.remove(id);
+        self.coached.remove(id);
+        self.auto_passes.remove(id);
+        self.ais.remove(id);
+        self.turn_started.remove(id);
+        self.plies.remove(id);
+        self.forced_passes.remove(id);
+        self.resigned.remove(id);
+        // Dropping the sender ends the event stream of every socket on the game.
+        self.events.remove(id);
+        if let Err(e) = self.cache.forget(id) {
+            tracing::warn!("Failed to forget the sockets of game {id}: {e}");
+        }
+        self.reconnect_deadlines.remove(id);
+        self.aborted.remove(id);
+        self.drawn.remove(id);
+        self.versions.remove(id);
+    }
+
+    /// Starts the next turn of a game, returning how long the turn that ended took.
+    fn end_turn(&mut self, id: &str, game_over: bool) -> Option<u64> {
+        let now = self.clock.now();
+        let elapsed_ms = self
+            .turn_started
+            .insert(id.to_string(), now)
+            .map(|start| u64::try_from(now.duration_since(start).as_millis()).unwrap_or(u64::MAX));
+        if game_over {
+            self.turn_started.remove(id);
+        }
+        elapsed_ms
+    }
+
+    /// Stores a move in the game's history.
+    fn record_move(&mut self, id: &str, mv: Move, player: String, elapsed_ms: Option<u64>) {
+        let simulations = if is_builtin_ai(&player) {
+            self.ais
+                .get(id)
+                .and_then(|kept| kept.ai.try_lock().map(|ai| ai.config().simulations))
+        } else {
+            None
+        };
+        let record = MoveRecord {
+            mv,
+            player,
+            elapsed_ms,
+            simulations,
+        };
+        // The position is already saved, so a lost history entry only affects replays.
+        let ply = match self.storage.append_move(id, &record) {
+            Ok(ply) => ply,
+            Err(e) => {
+                tracing::warn!("Failed to save move {} of {id}: {e}", record.mv);
+                return;
+            }
+        };
+        self.plies.insert(id.to_string(), ply + 1);
+        let by_person = !self.is_bot(&record.player) && !record.player.is_empty();
+        if by_person && matches!(record.mv, Move::Place(_)) && !self.move_analyzers.is_empty() {
+            let task = Task::CheatCheck {
+                game_id: id.to_string(),
+                ply,
+            };
+            if let Err(e) = self.enqueue_job(&task) {
+                tracing::warn!("Failed to queue the cheat check of move {ply} of {id}: {e}");
+            }
+        }
+        if by_person && matches!(record.mv, Move::Place(_)) && self.is_coached(id) {
+            let task = Task::Coach {
+                game_id: id.to_string(),
+                ply,
+            };
+            if let Err(e) = self.enqueue_job(&task) {
+                tracing::warn!("Failed to queue the coaching of move {ply} of {id}: {e}");
+            }
+        }
+    }
+
+    /// Awards the achievements earned in a finished game.
+    fn award_achievements(&self, id: &str) {
+        let Some(result) = self.result(id) else {
+            return;
+        };
+        // The result is already saved, so a failure only costs the achievements.
+        match self
+            .storage
+            .load_moves(id)
+            .and_then(|moves| self.storage.award_achievements(id, &moves, &result))
+        {
+            Ok(awarded) => {
+                for (player, achievement) in awarded {
+                    tracing::info!("{player} earned {} in {id}", achievement.as_str());
+                }
+            }
+            Err(e) => tracing::warn!("Failed to award achievements for {id}: {e}"),
+        }
+    }
+
+    /// Stores the result of a finished game of the built-in AI with its configuration and
+    /// search totals.
+    fn record_ai_game(&self, id: &str) {
+        let Some(result) = self.result(id) else {
+            return;
+        };
+        let ai_color = if is_builtin_ai(result.black) {
+            Player::Black
+        } else if is_builtin_ai(result.white) {
+            Player::White
+        } else {
+            return;
+        };
+        // A game resigned before the AI searched has no AI kept.
+        let (config, summary) = self
+            .ais
+            .get(id)
+            .and_then(|kept| kept.ai.try_lock().map(|ai| (ai.config().clone(), ai.summary().clone())))
+            .unwrap_or_else(|| (self.ai_config_for(id), SearchSummary::default()));
+        if let Err(e) = self
+            .storage
+            .save_ai_game(id, ai_color, ENGINE_VERSION, &config, &summary, &result)
+        {
+            tracing::warn!("Failed to save the AI's record of {id}: {e}");
+        }
+    }
+
+    /// Returns the name of the player to move, or `None` if the game is not found or over.
+    #[must_use]
+    pub fn player_to_move(&self, id: &str) -> Option<&str> {
+        let game = self.games.get(id).filter(|_| !self.is_over(id))?;
+        let (p1, p2) = self.players.get(id)?;
+        match game.current_player {
+            Player::Black => Some(p1),
+            Player::White => Some(p2),
+        }
+    }
+
+    /// Returns true if the side to move in the game is played by the AI or an engine.
+    #[must_use]
+    pub fn is_ai_turn(&self, id: &str) -> bool {
+        self.player_to_move(id).is_some_and(|player| self.is_bot(player))
+    }
+
+    /// Returns true if `player` is played by the server: the AI or an engine.
+    #[must_use]
+    pub fn is_bot(&self, player: &str) -> bool {
+        is_builtin_ai(player) || self.engines.contains_key(player)
+    }
+
+    /// Offers an external engine as an opponent under its name.
+    pub fn add_engine(&mut self, config: EngineConfig) {
+        self.engines.extend(engine_pools(vec![config]));
+    }
+
+    /// Returns the names of the engines offered as opponents, sorted.
+    #[must_use]
+    pub fn engines(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.engines.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Lets players log in with an identity provider, under its name.
+    #[cfg(feature = "oauth")]
+    pub fn add_oauth_provider(&mut self, config: ProviderConfig) {
+        self.oauth_providers
+            .insert(config.name.clone(), Arc::new(OAuthProvider::new(config)));
+    }
+
+    /// Returns the names of the identity providers players can log in with, sorted.
+    #[cfg(feature = "oauth")]
+    #[must_use]
+    pub fn oauth_providers(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.oauth_providers.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Returns the identity provider with the name, if players can log in with it.
+    #[cfg(feature = "oauth")]
+    #[must_use]
+    pub fn oauth_provider(&self, name: &str) -> Option<Arc<OAuthProvider>> {
+        self.oauth_providers.get(name).cloned()
+    }
+
+    /// Returns the engine playing the side to move in the game, if it is played by one.
+    #[must_use]
+    pub fn engine_for(&self, id: &str) -> Option<Arc<EnginePool>> {
+        self.engines.get(self.player_to_move(id)?).cloned()
+    }
+
+    /// Returns the AI playing in the game, creating it on first use.
+    ///
+    /// The AI sits behind its own lock so a search can run without holding the sessions lock.
+    pub fn ai_for(&mut self, id: &str) -> Arc<Mutex<MctsAi>> {
+        if let Some(kept) = self.ais.get(id) {
+            return kept.ai.clone();
+        }
+        let ai = Arc::new(Mutex::new(MctsAi::new(self.ai_config_for(id))));
+        let kept = KeptAi {
+            ai: ai.clone(),
+            unseen: Vec::new(),
+        };
+        self.ais.insert(id.to_string(), kept);
+        ai
+    }
+
+    /// Returns the moves played in the game since its AI's tree last caught up, for the
+    /// caller to advance the tree past once the sessions lock is released.
+    pub fn take_unseen_moves(&mut self, id: &str) -> Vec<Move> {
+        self.ais
+            .get_mut(id)
+            .map(|kept| std::mem::take(&mut kept.unseen))
+            .unwrap_or_default()
+    }
+
+    /// Returns how the AI searches in the game: as its difficulty says if it plays under the
+    /// name of one, and as the server is configured to otherwise.
+    fn ai_config_for(&self, id: &str) -> AiConfig {
+        self.players
+            .get(id)
+            .and_then(|(black, white)| {
+                Difficulty::from_player_name(black).or_else(|| Difficulty::from_player_name(white))
+            })
+            .map_or_else(|| self.ai_config.clone(), |difficulty| difficulty.ai_config())
+    }
+
+    /// Returns the scheduler AI searches must run through.
+    ///
+    /// Each game's AI is behind its own lock, so a game never runs more than one search at a
+    /// time; the scheduler additionally bounds the searches running across all games.
+    #[must_use]
+    pub fn ai_scheduler(&self) -> Arc<AiScheduler> {
+        self.ai_scheduler.clone()
+    }
+
+    /// Registers a new AI search in the game and returns the token it must check. A search
+    /// still registered for the game is cancelled.
+    pub fn start_search(&mut self, id: &str) -> CancelToken {
+        let cancel = CancelToken::new();
+        if let Some(previous) = self.searches.insert(id.to_string(), cancel.clone()) {
+            previous.cancel();
+        }
+        cancel
+    }
+
+    /// Aborts the AI search running in the game, if any, e.g. because its time was revoked.
+    pub fn cancel_search(&mut self, id: &str) {
+        if let Some(cancel) = self.searches.remove(id) {
+            cancel.cancel();
+        }
+    }
+
+    /// Returns true if an AI search tree is being kept for the game.
+    #[must_use]
+    pub fn has_ai(&self, id: &str) -> bool {
+        self.ais.contains_key(id)
+    }
+
+    /// Queues a move for the game's AI tree to advance past, or drops the AI once the game
+    /// is over. The AI itself is never touched while the sessions are held; its tree catches
+    /// up before its next search, or is rebuilt if it missed a move.
+    fn notify_ai(&mut self, id: &str, mv: Move, game_over: bool) {
+        if game_over {
+            self.cancel_search(id);
+            self.ais.remove(id);
+        } else if let Some(kept) = self.ais.get_mut(id) {
+            kept.unseen.push(mv);
         }
     }
 
@@ -1,23 +1,347 @@
-use crate::game::{Game, Player};
-use rusqlite::{Connection, Result};
-use serde::Serialize;
-use std::collections::HashMap;
+use crate::achievements;
+use crate::ai::{AiConfig, Difficulty, SearchSummary};
+use crate::analysis::AnalysisReport;
+use crate::api::{
+    Achievement, Activity, AiGameRecord, AiStrength, ChallengePolicy, EarnedAchievement, GameType, HeadToHead, Job, JobStatus,
+    Outcome, PlayerSettings, PlayerStats, Record, RetentionRun, ServerStats, Task,
+};
+use crate::anticheat::{CheatReport, Suspicion};
+use crate::game::{Game, Move, Player, ScoringRule};
+use crate::import::ImportedGame;
+use crate::puzzles::{Puzzle, StoredPuzzle};
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
 
 type GameId = String;
 type PlayerName = String;
 type GamesMap = HashMap<GameId, Game>;
 type PlayersMap = HashMap<GameId, (PlayerName, PlayerName)>;
 
-#[derive(Serialize)]
-pub struct PlayerStats {
-    pub name: String,
-    pub elo: f64,
-    pub wins: i32,
-    pub losses: i32,
+/// Columns read into [`PlayerStats`], in the order `Storage::player_stats` expects.
+const PLAYER_STATS_COLUMNS: &str = "name, elo, wins, losses, games_black, games_white, \
+     current_streak, best_streak, total_disc_diff, total_moves";
+
+/// Columns read into [`Job`], in the order `Storage::job_from_row` expects.
+const JOB_COLUMNS: &str = "id, payload, status, attempts, max_attempts, last_error, run_at, created_at, updated_at";
+
+/// Prefix of the ids of games created by the server.
+pub const GAME_ID_PREFIX: &str = "game_";
+
+/// Game id prefix reserved for self-play training games.
+///
+/// Games in this namespace are kept out of the live sessions loaded at startup.
+pub const SELF_PLAY_PREFIX: &str = "selfplay_";
+
+/// A move in a game's history, with who played it and how long it took.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoveRecord {
+    pub mv: Move,
+    /// Name of the player who made the move; empty for self-play games.
+    pub player: String,
+    /// Wall-clock time the player took, if the start of their turn is known.
+    pub elapsed_ms: Option<u64>,
+    /// Number of simulations the AI ran, for AI moves.
+    pub simulations: Option<u32>,
+}
+
+/// A conventional mark judging a move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveMark {
+    /// `!!`
+    Brilliant,
+    /// `!`
+    Good,
+    /// `!?`
+    Interesting,
+    /// `?!`
+    Dubious,
+    /// `?`
+    Mistake,
+    /// `??`
+    Blunder,
+}
+
+impl MoveMark {
+    /// Returns the mark's symbol, e.g. `!?`.
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MoveMark::Brilliant => "!!",
+            MoveMark::Good => "!",
+            MoveMark::Interesting => "!?",
+            MoveMark::Dubious => "?!",
+            MoveMark::Mistake => "?",
+            MoveMark::Blunder => "??",
+        }
+    }
+}
+
+impl std::str::FromStr for MoveMark {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "!!" => Ok(MoveMark::Brilliant),
+            "!" => Ok(MoveMark::Good),
+            "!?" => Ok(MoveMark::Interesting),
+            "?!" => Ok(MoveMark::Dubious),
+            "?" => Ok(MoveMark::Mistake),
+            "??" => Ok(MoveMark::Blunder),
+            _ => Err(format!("Unknown move mark '{s}'")),
+        }
+    }
+}
+
+/// A note attached to one ply of a game by a player or the analysis engine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    /// Index of the annotated move in the game's history.
+    pub ply: usize,
+    pub author: String,
+    pub comment: Option<String>,
+    pub mark: Option<MoveMark>,
+    /// Engine evaluation of the position after the move.
+    pub score: Option<f64>,
+}
+
+/// How a game is set up when it is created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GameOptions {
+    /// Discs added to White's final count.
+    pub komi: i32,
+    pub game_type: GameType,
+    /// Hidden from everyone but the players, the admins and holders of an invite.
+    pub private: bool,
+    /// Each move a person places is assessed against the best move while the game goes on.
+    pub coach: bool,
+    /// Whether players without legal moves are passed for automatically; the server's
+    /// setting if `None`.
+    pub auto_pass: Option<bool>,
+}
+
+/// How a player likes to play, kept for every client they use. The server applies
+/// `auto_pass` and `coach` to the games the player creates; the rest is for clients.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlayerPreferences {
+    /// Whether players without legal moves are passed for automatically in the games the
+    /// player creates; the server's setting if `None`.
+    pub auto_pass: Option<bool>,
+    /// Whether the games the player creates are coached when they do not say, wherever
+    /// coaching is allowed.
+    pub coach: bool,
+    /// The time control the player would rather play with.
+    pub clock: Option<TimeControl>,
+    pub orientation: BoardOrientation,
+    pub notifications: Notifications,
+}
+
+/// Time given to each player for the whole game, plus an increment per move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeControl {
+    pub minutes: u32,
+    pub increment_secs: u32,
+}
+
+/// Which way up the board is shown.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BoardOrientation {
+    /// As the rows of a game's `board` are listed, the first at the top.
+    #[default]
+    Standard,
+    /// Turned half a turn.
+    Flipped,
+    /// Flipped when the player plays White, so their own discs start nearest to them.
+    OwnColor,
+}
+
+/// What a player wants to be told about when not looking at the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Notifications {
+    /// It is the player's turn in one of their games.
+    pub your_turn: bool,
+    /// Someone chatted in one of their games.
+    pub chat: bool,
+    /// Play a sound along with the notification.
+    pub sound: bool,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self {
+            your_turn: true,
+            chat: true,
+            sound: true,
+        }
+    }
+}
+
+/// The outcome of a finished game, as recorded in both players' statistics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameResult<'a> {
+    pub black: &'a str,
+    pub white: &'a str,
+    /// The winner, or `None` for a draw.
+    pub winner: Option<Player>,
+    pub black_discs: u32,
+    pub white_discs: u32,
+    /// Number of discs placed in the game.
+    pub moves: u32,
+}
+
+impl GameResult<'_> {
+    /// Builds the result of a finished game scored with `rule`, with `komi` discs added to
+    /// White's count to decide the winner. The disc counts are the ones on the board.
+    #[must_use]
+    pub fn of<'a>(game: &Game, rule: ScoringRule, komi: i32, black: &'a str, white: &'a str) -> GameResult<'a> {
+        let (black_discs, white_discs) = game.final_scores(rule);
+        GameResult {
+            black,
+            white,
+            winner: game.winner_with_komi(rule, komi),
+            black_discs,
+            white_discs,
+            moves: game.occupied().count_ones().saturating_sub(4),
+        }
+    }
+}
+
+/// A player's rating change from one game.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RatingChange {
+    pub game_id: GameId,
+    pub elo_before: f64,
+    pub elo_after: f64,
+}
+
+/// A game a player starred to keep it at hand, and out of reach of the retention policy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StarredGame {
+    pub game_id: GameId,
+    pub player1: PlayerName,
+    pub player2: PlayerName,
+    pub finished: bool,
+    /// The winner's color once finished, or `None` for a draw or a game still in progress.
+    pub winner: Option<Player>,
+    pub starred_at: String,
+}
+
+/// Metadata key marking a game as part of a tournament, whose value names it. Rated games so
+/// marked are never deleted by the retention policy.
+pub const TOURNAMENT_METADATA_KEY: &str = "tournament";
+
+/// Metadata key naming the player who imported a game from a file.
+pub const IMPORTED_BY_METADATA_KEY: &str = "imported_by";
+
+/// Metadata key giving the format of the file a game was imported from.
+pub const FORMAT_METADATA_KEY: &str = "format";
+
+/// How ratings are updated after a game.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RatingConfig {
+    /// K-factor for established players.
+    pub k_factor: f64,
+    /// K-factor while a player has played fewer than `provisional_games` rated games.
+    pub provisional_k_factor: f64,
+    pub provisional_games: u32,
+    /// Ratings never drop below this.
+    pub floor: f64,
+    /// Players whose rating is fixed, so the rest of the ladder stays calibrated against them.
+    pub anchors: HashMap<PlayerName, f64>,
+}
+
+impl Default for RatingConfig {
+    fn default() -> Self {
+        let mut anchors: HashMap<_, _> = Difficulty::ALL
+            .iter()
+            .map(|difficulty| (difficulty.player_name().to_string(), difficulty.anchor_rating()))
+            .collect();
+        // The server's default AI searches like the medium difficulty.
+        anchors.insert("AI".to_string(), Difficulty::Medium.anchor_rating());
+        Self {
+            k_factor: 32.0,
+            provisional_k_factor: 64.0,
+            provisional_games: 10,
+            floor: 100.0,
+            anchors,
+        }
+    }
+}
+
+impl RatingConfig {
+    /// Returns the rating a player starts with.
+    #[must_use]
+    pub fn initial_rating(&self, name: &str) -> f64 {
+        self.anchors.get(name).copied().unwrap_or(1200.0)
+    }
+
+    /// Returns the K-factor for a player with the given number of rated games.
+    #[must_use]
+    pub fn k_factor_for(&self, games: u32) -> f64 {
+        if games < self.provisional_games {
+            self.provisional_k_factor
+        } else {
+            self.k_factor
+        }
+    }
+
+    /// Returns the expected score, from 0 to 1, of a player rated `rating` against an
+    /// opponent rated `opponent`; also their win probability with draws counted as half.
+    #[must_use]
+    pub fn expected_score(rating: f64, opponent: f64) -> f64 {
+        1.0 / (1.0 + 10.0_f64.powf((opponent - rating) / 400.0))
+    }
+
+    /// Returns a player's new rating after a game against an opponent rated `opponent`.
+    #[must_use]
+    pub fn updated_rating(&self, name: &str, rating: f64, games: u32, opponent: f64, won: bool) -> f64 {
+        if let Some(&anchor) = self.anchors.get(name) {
+            return anchor;
+        }
+        let expected = Self::expected_score(rating, opponent);
+        let score = if won { 1.0 } else { 0.0 };
+        (rating + self.k_factor_for(games) * (score - expected)).max(self.floor)
+    }
 }
 
 pub struct Storage {
     conn: Connection,
+    rating: RatingConfig,
+}
+
+/// Writes kept or undone together, started by `Storage::savepoint`. They are undone when
+/// the savepoint is dropped without being committed.
+struct Savepoint<'a> {
+    conn: &'a Connection,
+    committed: bool,
+}
+
+impl Savepoint<'_> {
+    fn commit(mut self) -> Result<()> {
+        self.committed = true;
+        self.conn.execute_batch("RELEASE storage")
+    }
+}
+
+impl std::ops::Deref for Savepoint<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+    }
+}
+
+impl Drop for Savepoint<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = self.conn.execute_batch("ROLLBACK TO storage; RELEASE storage");
+        }
+    }
 }
 
 impl Storage {
@@ -28,6 +352,43 @@ impl Storage {
     /// Returns an error if the database cannot be opened or if the tables cannot be created.
     pub fn new(db_path: &str) -> Result<Self> {
         let conn = Connection::open(db_path)?;
+        // Servers sharing the database wait for each other's writes instead of failing.
+        conn.busy_timeout(Duration::from_secs(5))?;
+        Self::create_game_tables(&conn)?;
+        Self::create_ending_tables(&conn)?;
+        Self::create_player_tables(&conn)?;
+        Self::create_puzzle_tables(&conn)?;
+        Self::create_job_tables(&conn)?;
+        Self::create_archive_tables(&conn)?;
+        Self::create_ai_tables(&conn)?;
+        Self::create_account_tables(&conn)?;
+        // Only unfinished games are loaded at startup. Games stored before the column existed
+        // are finished if they have a result or were aborted.
+        if Self::add_column_if_missing(&conn, "games", "finished INTEGER NOT NULL DEFAULT 0")? {
+            conn.execute(
+                "UPDATE games SET finished = 1
+                 WHERE id IN (SELECT game_id FROM game_results) OR id IN (SELECT game_id FROM aborted_games)",
+                [],
+            )?;
+        }
+        conn.execute("CREATE INDEX IF NOT EXISTS games_finished ON games (finished)", [])?;
+        Ok(Storage {
+            conn,
+            rating: RatingConfig::default(),
+        })
+    }
+
+    /// Makes the statements `faults` picks fail, to test how the server copes.
+    #[cfg(feature = "chaos")]
+    pub fn inject_faults(&self, faults: &crate::chaos::Faults) {
+        let faults = faults.clone();
+        self.conn
+            .authorizer(Some(move |context: rusqlite::hooks::AuthContext<'_>| faults.authorize(&context)));
+    }
+
+    /// Creates the tables holding games, their moves, annotations, analyses and metadata,
+    /// and the id sequence.
+    fn create_game_tables(conn: &Connection) -> Result<()> {
         conn.execute(
             "CREATE TABLE IF NOT EXISTS games (
                 id TEXT PRIMARY KEY,
@@ -40,130 +401,1704 @@ impl Storage {
             )",
             [],
         )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS moves (
+                game_id TEXT NOT NULL,
+                ply INTEGER NOT NULL,
+                coord TEXT NOT NULL,
+                player TEXT,
+                elapsed_ms INTEGER,
+                simulations INTEGER,
+                played_at TEXT,
+                PRIMARY KEY (game_id, ply)
+            )",
+            [],
+        )?;
+        // `played_at` is unknown for moves stored before it existed or saved as a whole list.
+        for column in ["player TEXT", "elapsed_ms INTEGER", "simulations INTEGER", "played_at TEXT"] {
+            Self::add_column_if_missing(conn, "moves", column)?;
+        }
+        // The serialized game. The bitboard columns are kept for older rows, which have no
+        // state, but they are floats and lose the low bits of a full board.
+        Self::add_column_if_missing(conn, "games", "state TEXT")?;
+        // Discs added to White's final count, set when the game is created.
+        Self::add_column_if_missing(conn, "games", "komi INTEGER NOT NULL DEFAULT 0")?;
+        // 0 for casual games, which change no ratings.
+        Self::add_column_if_missing(conn, "games", "ranked INTEGER NOT NULL DEFAULT 1")?;
+        // 1 for games only their players and holders of an invite may see.
+        Self::add_column_if_missing(conn, "games", "private INTEGER NOT NULL DEFAULT 0")?;
+        // 1 for games whose players are coached move by move.
+        Self::add_column_if_missing(conn, "games", "coach INTEGER NOT NULL DEFAULT 0")?;
+        // Whether players are passed for automatically, NULL for the server's setting.
+        Self::add_column_if_missing(conn, "games", "auto_pass INTEGER")?;
+        // Moved on by every write made through `write_game_version`, so servers sharing the
+        // database notice each other's changes.
+        Self::add_column_if_missing(conn, "games", "version INTEGER NOT NULL DEFAULT 0")?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS annotations (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                game_id TEXT NOT NULL,
+                ply INTEGER NOT NULL,
+                author TEXT NOT NULL,
+                comment TEXT,
+                mark TEXT,
+                score REAL,
+                created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS analysis_reports (
+                game_id TEXT PRIMARY KEY,
+                report TEXT NOT NULL,
+                created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS game_metadata (
+                game_id TEXT NOT NULL,
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                PRIMARY KEY (game_id, key)
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS id_sequences (
+                name TEXT PRIMARY KEY,
+                next INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        // Start after any ids handed out before the sequence existed.
+        conn.execute(
+            "INSERT OR IGNORE INTO id_sequences (name, next)
+             SELECT 'game', COALESCE(MAX(CAST(SUBSTR(id, ?1) AS INTEGER)), 0) + 1 FROM games
+             WHERE id GLOB ?2 || '[0-9]*'",
+            rusqlite::params![GAME_ID_PREFIX.len() + 1, GAME_ID_PREFIX],
+        )?;
+        Ok(())
+    }
+
+    /// Creates the tables holding player statistics, settings, preferences, results,
+    /// achievements, rating history and cheat reports.
+    fn create_player_tables(conn: &Connection) -> Result<()> {
         conn.execute(
             "CREATE TABLE IF NOT EXISTS players (
                 name TEXT PRIMARY KEY,
                 elo REAL NOT NULL DEFAULT 1200,
                 wins INTEGER NOT NULL DEFAULT 0,
-                losses INTEGER NOT NULL DEFAULT 0
+                losses INTEGER NOT NULL DEFAULT 0,
+                games_black INTEGER NOT NULL DEFAULT 0,
+                games_white INTEGER NOT NULL DEFAULT 0,
+                current_streak INTEGER NOT NULL DEFAULT 0,
+                best_streak INTEGER NOT NULL DEFAULT 0,
+                total_disc_diff INTEGER NOT NULL DEFAULT 0,
+                total_moves INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )?;
+        for column in [
+            "games_black INTEGER NOT NULL DEFAULT 0",
+            "games_white INTEGER NOT NULL DEFAULT 0",
+            "current_streak INTEGER NOT NULL DEFAULT 0",
+            "best_streak INTEGER NOT NULL DEFAULT 0",
+            "total_disc_diff INTEGER NOT NULL DEFAULT 0",
+            "total_moves INTEGER NOT NULL DEFAULT 0",
+        ] {
+            Self::add_column_if_missing(conn, "players", column)?;
+        }
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS player_settings (
+                name TEXT PRIMARY KEY,
+                accept_challenges TEXT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS player_preferences (
+                name TEXT PRIMARY KEY,
+                preferences TEXT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS player_relations (
+                owner TEXT NOT NULL,
+                other TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                PRIMARY KEY (owner, other, kind)
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS game_results (
+                game_id TEXT PRIMARY KEY,
+                black TEXT NOT NULL,
+                white TEXT NOT NULL,
+                winner TEXT,
+                black_discs INTEGER NOT NULL,
+                white_discs INTEGER NOT NULL,
+                moves INTEGER NOT NULL,
+                finished_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS achievements (
+                player TEXT NOT NULL,
+                achievement TEXT NOT NULL,
+                game_id TEXT NOT NULL,
+                earned_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (player, achievement)
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS rating_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                player TEXT NOT NULL,
+                game_id TEXT NOT NULL,
+                elo_before REAL NOT NULL,
+                elo_after REAL NOT NULL,
+                recorded_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
+        // Reports outlive the games they are about, so deleting a game keeps them.
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS cheat_reports (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                game_id TEXT NOT NULL,
+                player TEXT NOT NULL,
+                ply INTEGER NOT NULL,
+                analyzer TEXT NOT NULL,
+                score REAL NOT NULL,
+                details TEXT NOT NULL,
+                created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                UNIQUE (game_id, player, analyzer)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Creates the tables holding puzzles and the games already mined for them.
+    fn create_puzzle_tables(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS puzzles (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                game_id TEXT NOT NULL,
+                ply INTEGER NOT NULL,
+                position TEXT NOT NULL UNIQUE,
+                solution TEXT NOT NULL,
+                difficulty INTEGER NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                solves INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS mined_games (
+                game_id TEXT PRIMARY KEY
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Creates the table holding background jobs.
+    fn create_job_tables(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS jobs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                kind TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                status TEXT NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                max_attempts INTEGER NOT NULL,
+                last_error TEXT,
+                run_at INTEGER NOT NULL,
+                created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS jobs_due ON jobs (status, run_at)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Creates the tables holding the games players starred and the runs of the retention
+    /// policy.
+    fn create_archive_tables(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS starred_games (
+                player TEXT NOT NULL,
+                game_id TEXT NOT NULL,
+                starred_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (player, game_id)
+            )",
+            [],
+        )?;
+        conn.execute("CREATE INDEX IF NOT EXISTS starred_games_game ON starred_games (game_id)", [])?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS retention_runs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                keep_days INTEGER NOT NULL,
+                dry_run INTEGER NOT NULL,
+                games TEXT NOT NULL,
+                ran_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Creates the tables recording games that ended before the board was settled:
+    /// resignations, aborts and draws claimed for inactivity.
+    fn create_ending_tables(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS resignations (
+                game_id TEXT PRIMARY KEY,
+                player TEXT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS aborted_games (
+                game_id TEXT PRIMARY KEY,
+                aborted_at TEXT
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS drawn_games (
+                game_id TEXT PRIMARY KEY,
+                drawn_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
+        // Games aborted before the column existed count as aborted when it was added.
+        if Self::add_column_if_missing(conn, "aborted_games", "aborted_at TEXT")? {
+            conn.execute("UPDATE aborted_games SET aborted_at = CURRENT_TIMESTAMP", [])?;
+        }
+        Ok(())
+    }
+
+    /// Creates the table linking identities at external providers to player names.
+    fn create_account_tables(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS linked_identities (
+                provider TEXT NOT NULL,
+                subject TEXT NOT NULL,
+                player TEXT NOT NULL,
+                linked_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (provider, subject)
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS linked_identities_player ON linked_identities (player)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Creates the table holding the AI's games, with its configuration and search totals.
+    fn create_ai_tables(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS ai_games (
+                game_id TEXT PRIMARY KEY,
+                ai_color TEXT NOT NULL,
+                opponent TEXT NOT NULL,
+                engine_version TEXT NOT NULL,
+                simulations INTEGER NOT NULL,
+                exploration_constant REAL NOT NULL,
+                config TEXT NOT NULL,
+                searches INTEGER NOT NULL,
+                search_simulations INTEGER NOT NULL,
+                search_ms INTEGER NOT NULL,
+                chosen_q REAL NOT NULL,
+                outcome TEXT NOT NULL,
+                ai_discs INTEGER NOT NULL,
+                opponent_discs INTEGER NOT NULL,
+                finished_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
             )",
             [],
         )?;
-        Ok(Storage { conn })
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS ai_games_version ON ai_games (engine_version, simulations)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Returns how ratings are updated.
+    #[must_use]
+    pub fn rating_config(&self) -> &RatingConfig {
+        &self.rating
+    }
+
+    /// Sets how ratings are updated.
+    pub fn set_rating_config(&mut self, rating: RatingConfig) {
+        self.rating = rating;
+    }
+
+    /// Adds a column to a table created by an older version of the schema, returning true if
+    /// it was missing.
+    fn add_column_if_missing(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+        let name = column.split_whitespace().next().unwrap_or(column);
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<_>>>()?
+            .iter()
+            .any(|existing| existing == name);
+        if !exists {
+            conn.execute(&format!("ALTER TABLE {table} ADD COLUMN {column}"), [])?;
+        }
+        Ok(!exists)
+    }
+
+    /// Starts a group of writes that are kept or undone together. Groups nest, so a method
+    /// grouping its own writes can run inside a larger group.
+    fn savepoint(&self) -> Result<Savepoint<'_>> {
+        self.conn.execute_batch("SAVEPOINT storage")?;
+        Ok(Savepoint {
+            conn: &self.conn,
+            committed: false,
+        })
+    }
+
+    /// Allocates a new game id.
+    ///
+    /// Ids come from a sequence in the database, so they stay unique across restarts and
+    /// between server instances sharing the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sequence cannot be advanced.
+    pub fn next_game_id(&self) -> Result<GameId> {
+        let id: i64 = self.conn.query_row(
+            "UPDATE id_sequences SET next = next + 1 WHERE name = 'game' RETURNING next - 1",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(format!("{GAME_ID_PREFIX}{id}"))
+    }
+
+    /// Saves a game to the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game cannot be saved.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn save_game(&self, id: &str, game: &Game, player1: &str, player2: &str) -> Result<()> {
+        let state = serde_json::to_string(game).map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+        self.conn.execute(
+            "INSERT INTO games (id, black, white, current_player, passes, player1, player2, state, finished)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+             ON CONFLICT (id) DO UPDATE SET black = excluded.black, white = excluded.white,
+                 current_player = excluded.current_player, passes = excluded.passes, player1 = excluded.player1,
+                 player2 = excluded.player2, state = excluded.state, finished = excluded.finished",
+            rusqlite::params![id, game.black as f64, game.white as f64, game.current_player.as_str(), i64::from(game.passes), player1, player2, state, game.is_game_over()],
+        )?;
+        Ok(())
+    }
+
+    /// Saves a new game set up with `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game cannot be saved; nothing is saved then.
+    pub fn create_game(&self, id: &str, game: &Game, player1: &str, player2: &str, options: GameOptions) -> Result<()> {
+        let tx = self.savepoint()?;
+        self.save_game(id, game, player1, player2)?;
+        tx.execute(
+            "UPDATE games SET komi = ?2, ranked = ?3, private = ?4, coach = ?5, auto_pass = ?6 WHERE id = ?1",
+            rusqlite::params![
+                id,
+                options.komi,
+                options.game_type == GameType::Ranked,
+                options.private,
+                options.coach,
+                options.auto_pass
+            ],
+        )?;
+        tx.commit()
+    }
+
+    /// Returns how a game was set up, the defaults if there is no such game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game cannot be read.
+    pub fn game_options(&self, id: &str) -> Result<GameOptions> {
+        self.conn
+            .query_row(
+                "SELECT komi, ranked, private, coach, auto_pass FROM games WHERE id = ?1",
+                [id],
+                |row| {
+                    Ok(GameOptions {
+                        komi: row.get(0)?,
+                        game_type: if row.get(1)? { GameType::Ranked } else { GameType::Casual },
+                        private: row.get(2)?,
+                        coach: row.get(3)?,
+                        auto_pass: row.get(4)?,
+                    })
+                },
+            )
+            .optional()
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Returns the ids of the unfinished private games.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the games cannot be read.
+    pub fn private_games(&self) -> Result<HashSet<GameId>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id FROM games WHERE finished = 0 AND private = 1")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// Returns the ids of the unfinished games whose players are coached.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the games cannot be read.
+    pub fn coached_games(&self) -> Result<HashSet<GameId>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id FROM games WHERE finished = 0 AND coach = 1")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// Returns the type of a game, ranked if there is no such game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game cannot be read.
+    pub fn game_type(&self, id: &str) -> Result<GameType> {
+        self.game_options(id).map(|options| options.game_type)
+    }
+
+    /// Returns the ids of the unfinished casual games.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the games cannot be read.
+    pub fn casual_games(&self) -> Result<HashSet<GameId>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id FROM games WHERE finished = 0 AND ranked = 0")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// Returns the komi of a game, 0 if there is no such game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game cannot be read.
+    pub fn komi(&self, id: &str) -> Result<i32> {
+        self.conn
+            .query_row("SELECT komi FROM games WHERE id = ?1", [id], |row| row.get(0))
+            .optional()
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Returns the komi of every unfinished game that has one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the games cannot be read.
+    pub fn komis(&self) -> Result<HashMap<GameId, i32>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, komi FROM games WHERE finished = 0 AND komi != 0")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    /// Returns whether players are passed for automatically in every unfinished game that
+    /// does not follow the server's setting.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the games cannot be read.
+    pub fn auto_passes(&self) -> Result<HashMap<GameId, bool>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, auto_pass FROM games WHERE finished = 0 AND auto_pass IS NOT NULL")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    /// Reads the game from the `state, black, white, current_player, passes` columns of a
+    /// row, starting at column `first`.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn game_from_row(row: &rusqlite::Row, first: usize) -> Result<Game> {
+        if let Some(state) = row.get::<_, Option<String>>(first)? {
+            return serde_json::from_str(&state).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(first, rusqlite::types::Type::Text, e.into())
+            });
+        }
+        let black: f64 = row.get(first + 1)?;
+        let white: f64 = row.get(first + 2)?;
+        let current_player: String = row.get(first + 3)?;
+        let passes: u8 = row.get(first + 4)?;
+        let player = if current_player == "Black" {
+            Player::Black
+        } else {
+            Player::White
+        };
+        Ok(Game::from_bitboards(black as u64, white as u64, player, passes))
+    }
+
+    /// Loads a game from the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game cannot be loaded.
+    pub fn load_game(&self, id: &str) -> Result<Option<(Game, String, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT state, black, white, current_player, passes, player1, player2 FROM games WHERE id = ?1",
+        )?;
+        let mut rows = stmt.query_map([id], |row| Ok((Self::game_from_row(row, 0)?, row.get(5)?, row.get(6)?)))?;
+        rows.next().transpose()
+    }
+
+    /// Returns the version of a stored game, or `None` if there is no such game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game cannot be read.
+    pub fn game_version(&self, id: &str) -> Result<Option<i64>> {
+        self.conn
+            .query_row("SELECT version FROM games WHERE id = ?1", [id], |row| row.get(0))
+            .optional()
+    }
+
+    /// Runs `write` if the game is still at `version`, moving it to the next version. The
+    /// check and the writes are one transaction. Returns false without writing anything if
+    /// the game has moved on, e.g. because another server changed it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game cannot be read or `write` fails; nothing is written then.
+    pub fn write_game_version(
+        &self,
+        id: &str,
+        version: i64,
+        write: impl FnOnce(&Self) -> Result<()>,
+    ) -> Result<bool> {
+        let tx = self.savepoint()?;
+        let claimed = tx.execute(
+            "UPDATE games SET version = version + 1 WHERE id = ?1 AND version = ?2",
+            rusqlite::params![id, version],
+        )?;
+        if claimed == 0 {
+            return Ok(false);
+        }
+        write(self)?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Loads the games still being played from the database, except self-play training
+    /// games. Finished games are left out; load them one at a time with
+    /// [`Storage::load_game`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the games cannot be loaded.
+    pub fn load_all_games(&self) -> Result<(GamesMap, PlayersMap)> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, state, black, white, current_player, passes, player1, player2 FROM games
+             WHERE finished = 0 AND id NOT GLOB ?1 || '*'",
+        )?;
+        let rows = stmt.query_map([SELF_PLAY_PREFIX], |row| {
+            let id: String = row.get(0)?;
+            Ok((id, Self::game_from_row(row, 1)?, row.get(6)?, row.get(7)?))
+        })?;
+        let mut games = HashMap::new();
+        let mut players = HashMap::new();
+        for row in rows {
+            let (id, game, p1, p2) = row?;
+            games.insert(id.clone(), game);
+            players.insert(id, (p1, p2));
+        }
+        Ok((games, players))
+    }
+
+    /// Deletes a game with its moves, annotations, resignation, abort, analysis and metadata.
+    /// Its recorded result, rating changes, achievements and puzzles are kept. Returns false
+    /// if there was no such game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game cannot be deleted; nothing is deleted then.
+    pub fn delete_game(&self, id: &str) -> Result<bool> {
+        let tx = self.savepoint()?;
+        for table in [
+            "moves",
+            "annotations",
+            "resignations",
+            "aborted_games",
+            "drawn_games",
+            "analysis_reports",
+            "game_metadata",
+            "starred_games",
+        ] {
+            tx.execute(&format!("DELETE FROM {table} WHERE game_id = ?1"), [id])?;
+        }
+        let deleted = tx.execute("DELETE FROM games WHERE id = ?1", [id])? > 0;
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    /// Returns the finished games that ended at least `keep_days` days ago, oldest first,
+    /// leaving out those the retention policy always keeps: games starred by anyone, games
+    /// flagged by the anti-cheat checks, and rated games of a tournament. An aborted game,
+    /// which has no result, ended with its last move, or when it was aborted if it has none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the games cannot be read.
+    pub fn expired_games(&self, keep_days: u32) -> Result<Vec<GameId>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id FROM (
+                SELECT g.id, COALESCE(
+                    r.finished_at,
+                    (SELECT MAX(m.played_at) FROM moves m WHERE m.game_id = g.id),
+                    a.aborted_at
+                ) AS ended_at
+                FROM games g
+                LEFT JOIN game_results r ON r.game_id = g.id
+                LEFT JOIN aborted_games a ON a.game_id = g.id
+                WHERE g.finished = 1 AND (r.game_id IS NOT NULL OR a.game_id IS NOT NULL)
+                    AND g.id NOT IN (SELECT game_id FROM starred_games)
+                    AND g.id NOT IN (SELECT game_id FROM cheat_reports)
+                    AND NOT (g.ranked = 1 AND g.id IN (SELECT game_id FROM game_metadata WHERE key = ?2))
+             )
+             WHERE ended_at <= datetime('now', ?1)
+             ORDER BY ended_at, id",
+        )?;
+        let rows = stmt.query_map(
+            rusqlite::params![format!("-{keep_days} days"), TOURNAMENT_METADATA_KEY],
+            |row| row.get(0),
+        )?;
+        rows.collect()
+    }
+
+    /// Stars a game for a player, or takes their star off it. Returns false if nothing
+    /// changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the star cannot be saved.
+    pub fn set_starred(&self, player: &str, game_id: &str, starred: bool) -> Result<bool> {
+        let changed = if starred {
+            self.conn.execute(
+                "INSERT OR IGNORE INTO starred_games (player, game_id) VALUES (?1, ?2)",
+                [player, game_id],
+            )?
+        } else {
+            self.conn.execute(
+                "DELETE FROM starred_games WHERE player = ?1 AND game_id = ?2",
+                [player, game_id],
+            )?
+        };
+        Ok(changed > 0)
+    }
+
+    /// Returns the games a player starred, most recently starred first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the games cannot be read.
+    pub fn starred_games(&self, player: &str) -> Result<Vec<StarredGame>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT s.game_id, g.player1, g.player2, g.finished, r.winner, s.starred_at
+             FROM starred_games s JOIN games g ON g.id = s.game_id
+             LEFT JOIN game_results r ON r.game_id = s.game_id
+             WHERE s.player = ?1
+             ORDER BY s.starred_at DESC, s.rowid DESC",
+        )?;
+        let rows = stmt.query_map([player], |row| {
+            let winner: Option<String> = row.get(4)?;
+            Ok(StarredGame {
+                game_id: row.get(0)?,
+                player1: row.get(1)?,
+                player2: row.get(2)?,
+                finished: row.get(3)?,
+                winner: winner.map(|winner| if winner == "Black" { Player::Black } else { Player::White }),
+                starred_at: row.get(5)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Returns the player an identity at an external provider logs in as, linking it to a
+    /// name the first time: `name` if nobody uses it yet, or else `name` followed by the first
+    /// number making it free, e.g. `Alice2`. A name is in use once another identity is linked
+    /// to it or someone has played, rated or set preferences under it, so an identity never
+    /// takes over a player's games and rating.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the link cannot be read or saved.
+    pub fn link_identity(&self, provider: &str, subject: &str, name: &str) -> Result<PlayerName> {
+        let tx = self.savepoint()?;
+        let linked: Option<PlayerName> = self
+            .conn
+            .query_row(
+                "SELECT player FROM linked_identities WHERE provider = ?1 AND subject = ?2",
+                [provider, subject],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(player) = linked {
+            return Ok(player);
+        }
+        let mut player = name.to_string();
+        let mut suffix = 1;
+        while self.is_name_in_use(&player)? {
+            suffix += 1;
+            player = format!("{name}{suffix}");
+        }
+        self.conn.execute(
+            "INSERT INTO linked_identities (provider, subject, player) VALUES (?1, ?2, ?3)",
+            [provider, subject, player.as_str()],
+        )?;
+        tx.commit()?;
+        Ok(player)
+    }
+
+    /// Links an identity at an external provider to an existing player, who logs in through
+    /// it from then on, unless it is linked already. Returns the player the identity logs in
+    /// as, another one if it was linked to them before.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the link cannot be read or saved.
+    pub fn link_identity_to(&self, provider: &str, subject: &str, player: &str) -> Result<PlayerName> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO linked_identities (provider, subject, player) VALUES (?1, ?2, ?3)",
+            [provider, subject, player],
+        )?;
+        self.conn.query_row(
+            "SELECT player FROM linked_identities WHERE provider = ?1 AND subject = ?2",
+            [provider, subject],
+            |row| row.get(0),
+        )
+    }
+
+    /// Returns true if an identity is linked to the name or anything is recorded under it.
+    fn is_name_in_use(&self, name: &str) -> Result<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM linked_identities WHERE player = ?1)
+                OR EXISTS (SELECT 1 FROM players WHERE name = ?1)
+                OR EXISTS (SELECT 1 FROM player_settings WHERE name = ?1)
+                OR EXISTS (SELECT 1 FROM player_preferences WHERE name = ?1)
+                OR EXISTS (SELECT 1 FROM games WHERE player1 = ?1 OR player2 = ?1)",
+            [name],
+            |row| row.get(0),
+        )
+    }
+
+    /// Returns true if an identity at an external provider is linked to the player, who can
+    /// then only log in through it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the links cannot be read.
+    pub fn is_linked(&self, player: &str) -> Result<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM linked_identities WHERE player = ?1)",
+            [player],
+            |row| row.get(0),
+        )
+    }
+
+    /// Records a run of the retention policy, returning its id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the run cannot be saved.
+    pub fn save_retention_run(&self, keep_days: u32, dry_run: bool, games: &[GameId]) -> Result<i64> {
+        let games = serde_json::to_string(games).map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+        self.conn.execute(
+            "INSERT INTO retention_runs (keep_days, dry_run, games) VALUES (?1, ?2, ?3)",
+            rusqlite::params![keep_days, dry_run, games],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Returns the latest runs of the retention policy, newest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the runs cannot be read.
+    pub fn retention_runs(&self, limit: u32) -> Result<Vec<RetentionRun>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, keep_days, dry_run, games, ran_at FROM retention_runs ORDER BY id DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map([limit], |row| {
+            let games: String = row.get(3)?;
+            Ok(RetentionRun {
+                id: row.get(0)?,
+                keep_days: row.get(1)?,
+                dry_run: row.get(2)?,
+                games: serde_json::from_str(&games).unwrap_or_default(),
+                ran_at: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Replaces the stored move list of a game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the moves cannot be saved.
+    pub fn save_moves(&self, game_id: &str, moves: &[Move]) -> Result<()> {
+        let tx = self.savepoint()?;
+        tx.execute("DELETE FROM moves WHERE game_id = ?1", [game_id])?;
+        {
+            let mut stmt =
+                tx.prepare("INSERT INTO moves (game_id, ply, coord) VALUES (?1, ?2, ?3)")?;
+            for (ply, mv) in moves.iter().enumerate() {
+                stmt.execute(rusqlite::params![game_id, ply, mv.to_string()])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Loads the move list of a game in play order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the moves cannot be loaded or a stored move is malformed.
+    pub fn load_moves(&self, game_id: &str) -> Result<Vec<Move>> {
+        let records = self.load_move_records(game_id)?;
+        Ok(records.into_iter().map(|record| record.mv).collect())
+    }
+
+    /// Appends a move to the end of a game's stored history, returning its ply.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the move cannot be saved.
+    pub fn append_move(&self, game_id: &str, record: &MoveRecord) -> Result<usize> {
+        self.conn.query_row(
+            "INSERT INTO moves (game_id, ply, coord, player, elapsed_ms, simulations, played_at)
+             VALUES (?1, (SELECT COUNT(*) FROM moves WHERE game_id = ?1), ?2, ?3, ?4, ?5, CURRENT_TIMESTAMP)
+             RETURNING ply",
+            rusqlite::params![
+                game_id,
+                record.mv.to_string(),
+                record.player,
+                record.elapsed_ms,
+                record.simulations
+            ],
+            |row| row.get(0),
+        )
+    }
+
+    /// Returns the number of moves stored in a game's history.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the moves cannot be counted.
+    pub fn move_count(&self, game_id: &str) -> Result<usize> {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM moves WHERE game_id = ?1", [game_id], |row| row.get(0))
+    }
+
+    /// Loads the history of a game in play order, including timing metadata.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the moves cannot be loaded or a stored move is malformed.
+    pub fn load_move_records(&self, game_id: &str) -> Result<Vec<MoveRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT coord, COALESCE(player, ''), elapsed_ms, simulations FROM moves
+             WHERE game_id = ?1 ORDER BY ply",
+        )?;
+        let rows = stmt.query_map([game_id], |row| {
+            let coord: String = row.get(0)?;
+            let mv = coord.parse().map_err(|e: String| {
+                rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+            })?;
+            Ok(MoveRecord {
+                mv,
+                player: row.get(1)?,
+                elapsed_ms: row.get(2)?,
+                simulations: row.get(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Attaches an annotation to a ply of a game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the annotation cannot be saved.
+    pub fn add_annotation(&self, game_id: &str, annotation: &Annotation) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO annotations (game_id, ply, author, comment, mark, score)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![
+                game_id,
+                annotation.ply,
+                annotation.author,
+                annotation.comment,
+                annotation.mark.map(|mark| mark.as_str()),
+                annotation.score
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Loads the annotations of a game, ordered by ply and then by when they were added.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the annotations cannot be loaded or a stored mark is unknown.
+    pub fn load_annotations(&self, game_id: &str) -> Result<Vec<Annotation>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT ply, author, comment, mark, score FROM annotations
+             WHERE game_id = ?1 ORDER BY ply, id",
+        )?;
+        let rows = stmt.query_map([game_id], |row| {
+            let mark = row
+                .get::<_, Option<String>>(3)?
+                .map(|mark| mark.parse())
+                .transpose()
+                .map_err(|e: String| {
+                    rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, e.into())
+                })?;
+            Ok(Annotation {
+                ply: row.get(0)?,
+                author: row.get(1)?,
+                comment: row.get(2)?,
+                mark,
+                score: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Stores the analysis of a game, replacing any earlier one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the report cannot be saved.
+    pub fn save_analysis_report(&self, report: &AnalysisReport) -> Result<()> {
+        let json = serde_json::to_string(report).map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO analysis_reports (game_id, report) VALUES (?1, ?2)",
+            [&report.game_id, &json],
+        )?;
+        Ok(())
+    }
+
+    /// Loads the analysis of a game, if it has been analysed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the report cannot be loaded or is malformed.
+    pub fn load_analysis_report(&self, game_id: &str) -> Result<Option<AnalysisReport>> {
+        let json: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT report FROM analysis_reports WHERE game_id = ?1",
+                [game_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        json.map(|json| {
+            serde_json::from_str(&json).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+            })
+        })
+        .transpose()
+    }
+
+    /// Stores a suspicion raised by `analyzer` about the move at `ply`, replacing the one it
+    /// raised earlier about the player in the same game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the report cannot be saved.
+    pub fn save_cheat_report(
+        &self,
+        game_id: &str,
+        player: &str,
+        ply: usize,
+        analyzer: &str,
+        suspicion: &Suspicion,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO cheat_reports (game_id, player, ply, analyzer, score, details)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (game_id, player, analyzer) DO UPDATE SET
+                 ply = excluded.ply, score = excluded.score, details = excluded.details,
+                 updated_at = CURRENT_TIMESTAMP",
+            rusqlite::params![game_id, player, ply, analyzer, suspicion.score, suspicion.details],
+        )?;
+        Ok(())
+    }
+
+    /// Lists the most recently updated cheat reports, newest first, optionally only those
+    /// about `player`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the reports cannot be loaded.
+    pub fn cheat_reports(&self, player: Option<&str>, limit: u32) -> Result<Vec<CheatReport>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, game_id, player, ply, analyzer, score, details, created_at, updated_at
+             FROM cheat_reports
+             WHERE ?1 IS NULL OR player = ?1
+             ORDER BY updated_at DESC, id DESC LIMIT ?2",
+        )?;
+        let reports = stmt
+            .query_map(rusqlite::params![player, limit], |row| {
+                Ok(CheatReport {
+                    id: row.get(0)?,
+                    game_id: row.get(1)?,
+                    player: row.get(2)?,
+                    ply: row.get(3)?,
+                    analyzer: row.get(4)?,
+                    score: row.get(5)?,
+                    details: row.get(6)?,
+                    created_at: row.get(7)?,
+                    updated_at: row.get(8)?,
+                })
+            })?
+            .collect();
+        reports
+    }
+
+    /// Stores a finished game of the AI, played as `ai_color` by `engine_version` of the server
+    /// with `config`, replacing any stored earlier for the game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game cannot be saved.
+    pub fn save_ai_game(
+        &self,
+        game_id: &str,
+        ai_color: Player,
+        engine_version: &str,
+        config: &AiConfig,
+        summary: &SearchSummary,
+        result: &GameResult,
+    ) -> Result<()> {
+        let json = serde_json::to_string(config).map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+        let (opponent, ai_discs, opponent_discs) = match ai_color {
+            Player::Black => (result.white, result.black_discs, result.white_discs),
+            Player::White => (result.black, result.white_discs, result.black_discs),
+        };
+        let outcome = match result.winner {
+            None => "draw",
+            Some(winner) if winner == ai_color => "win",
+            Some(_) => "loss",
+        };
+        self.conn.execute(
+            "INSERT OR REPLACE INTO ai_games (
+                game_id, ai_color, opponent, engine_version, simulations, exploration_constant, config,
+                searches, search_simulations, search_ms, chosen_q, outcome, ai_discs, opponent_discs
+             ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            rusqlite::params![
+                game_id,
+                ai_color.as_str(),
+                opponent,
+                engine_version,
+                config.simulations,
+                config.exploration_constant,
+                json,
+                summary.searches,
+                i64::try_from(summary.simulations).unwrap_or(i64::MAX),
+                i64::try_from(summary.search_ms).unwrap_or(i64::MAX),
+                summary.chosen_q,
+                outcome,
+                ai_discs,
+                opponent_discs
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Lists the latest finished games of the AI, newest first, optionally only those played
+    /// by `engine_version`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the games cannot be loaded.
+    pub fn ai_games(&self, engine_version: Option<&str>, limit: u32) -> Result<Vec<AiGameRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT game_id, ai_color, opponent, engine_version, simulations, exploration_constant, config,
+                searches, search_simulations, search_ms, chosen_q, outcome, ai_discs, opponent_discs,
+                finished_at
+             FROM ai_games
+             WHERE ?1 IS NULL OR engine_version = ?1
+             ORDER BY finished_at DESC, rowid DESC LIMIT ?2",
+        )?;
+        let games = stmt
+            .query_map(rusqlite::params![engine_version, limit], |row| {
+                let ai_color: String = row.get(1)?;
+                let config: String = row.get(6)?;
+                let outcome: String = row.get(11)?;
+                Ok(AiGameRecord {
+                    game_id: row.get(0)?,
+                    ai_color: if ai_color == "Black" { Player::Black } else { Player::White },
+                    opponent: row.get(2)?,
+                    engine_version: row.get(3)?,
+                    simulations: row.get(4)?,
+                    exploration_constant: row.get(5)?,
+                    config: serde_json::from_str(&config).unwrap_or_default(),
+                    summary: SearchSummary {
+                        searches: row.get(7)?,
+                        simulations: u64::try_from(row.get::<_, i64>(8)?).unwrap_or_default(),
+                        search_ms: u64::try_from(row.get::<_, i64>(9)?).unwrap_or_default(),
+                        chosen_q: row.get(10)?,
+                    },
+                    outcome: match outcome.as_str() {
+                        "win" => Outcome::Win,
+                        "loss" => Outcome::Loss,
+                        _ => Outcome::Draw,
+                    },
+                    ai_discs: row.get(12)?,
+                    opponent_discs: row.get(13)?,
+                    finished_at: row.get(14)?,
+                })
+            })?
+            .collect();
+        games
+    }
+
+    /// Sums up the AI's games by version of the server and number of simulations, the
+    /// versions that played last first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the games cannot be read.
+    pub fn ai_strength(&self) -> Result<Vec<AiStrength>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT engine_version, simulations, COUNT(*),
+                SUM(outcome = 'win'), SUM(outcome = 'loss'), SUM(outcome = 'draw'),
+                SUM(searches), SUM(search_simulations), SUM(search_ms), SUM(chosen_q)
+             FROM ai_games
+             GROUP BY engine_version, simulations
+             ORDER BY MAX(finished_at) DESC, simulations",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let (games, wins, draws): (u32, u32, u32) = (row.get(2)?, row.get(3)?, row.get(5)?);
+            let searches: u32 = row.get(6)?;
+            #[allow(clippy::cast_precision_loss)]
+            let per_search = |total: i64| {
+                if searches == 0 {
+                    0.0
+                } else {
+                    total as f64 / f64::from(searches)
+                }
+            };
+            let chosen_q: f64 = row.get(9)?;
+            Ok(AiStrength {
+                engine_version: row.get(0)?,
+                simulations: row.get(1)?,
+                games,
+                wins,
+                losses: row.get(4)?,
+                draws,
+                score: (f64::from(wins) + f64::from(draws) / 2.0) / f64::from(games),
+                simulations_per_search: per_search(row.get(7)?),
+                ms_per_search: per_search(row.get(8)?),
+                mean_q: if searches == 0 {
+                    0.0
+                } else {
+                    chosen_q / f64::from(searches)
+                },
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Queues a job to run from `run_at`, in milliseconds since the Unix epoch, and returns
+    /// its id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the job cannot be saved.
+    pub fn enqueue_job(&self, task: &Task, max_attempts: u32, run_at: i64) -> Result<i64> {
+        let payload = serde_json::to_string(task).map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+        self.conn.execute(
+            "INSERT INTO jobs (kind, payload, status, max_attempts, run_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![task.kind(), payload, JobStatus::Queued.as_str(), max_attempts, run_at],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Returns a queued or running job doing `task`, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the jobs cannot be loaded.
+    pub fn active_job(&self, task: &Task) -> Result<Option<Job>> {
+        let payload = serde_json::to_string(task).map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+        self.conn
+            .query_row(
+                &format!(
+                    "SELECT {JOB_COLUMNS} FROM jobs
+                     WHERE payload = ?1 AND status IN ('queued', 'running')
+                     ORDER BY id LIMIT 1"
+                ),
+                [payload],
+                Self::job_from_row,
+            )
+            .optional()
+    }
+
+    /// Loads a job.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the job cannot be loaded.
+    pub fn job(&self, id: i64) -> Result<Option<Job>> {
+        self.conn
+            .query_row(
+                &format!("SELECT {JOB_COLUMNS} FROM jobs WHERE id = ?1"),
+                [id],
+                Self::job_from_row,
+            )
+            .optional()
+    }
+
+    /// Lists the most recent jobs, newest first, optionally only those with `status`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the jobs cannot be loaded.
+    pub fn jobs(&self, status: Option<JobStatus>, limit: u32) -> Result<Vec<Job>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {JOB_COLUMNS} FROM jobs
+             WHERE ?1 IS NULL OR status = ?1
+             ORDER BY id DESC LIMIT ?2"
+        ))?;
+        let jobs = stmt
+            .query_map(
+                rusqlite::params![status.map(|status| status.as_str()), limit],
+                Self::job_from_row,
+            )?
+            .collect();
+        jobs
+    }
+
+    /// Marks the queued job that has been due longest as running and returns it, or `None`
+    /// if no job is due at `now`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the job cannot be updated.
+    pub fn claim_job(&self, now: i64) -> Result<Option<Job>> {
+        self.conn
+            .query_row(
+                &format!(
+                    "UPDATE jobs SET status = 'running', attempts = attempts + 1,
+                         updated_at = CURRENT_TIMESTAMP
+                     WHERE id = (
+                         SELECT id FROM jobs WHERE status = 'queued' AND run_at <= ?1
+                         ORDER BY run_at, id LIMIT 1
+                     )
+                     RETURNING {JOB_COLUMNS}"
+                ),
+                [now],
+                Self::job_from_row,
+            )
+            .optional()
+    }
+
+    /// Marks a running job as done.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the job cannot be updated.
+    pub fn complete_job(&self, id: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE jobs SET status = 'done', updated_at = CURRENT_TIMESTAMP WHERE id = ?1",
+            [id],
+        )?;
+        Ok(())
+    }
+
+    /// Records a failed attempt of a running job. The job is queued again to run at
+    /// `retry_at`, or marked as failed if `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the job cannot be updated.
+    pub fn fail_job(&self, id: i64, error: &str, retry_at: Option<i64>) -> Result<()> {
+        let status = if retry_at.is_some() {
+            JobStatus::Queued
+        } else {
+            JobStatus::Failed
+        };
+        self.conn.execute(
+            "UPDATE jobs SET status = ?2, last_error = ?3, run_at = COALESCE(?4, run_at),
+                 updated_at = CURRENT_TIMESTAMP
+             WHERE id = ?1",
+            rusqlite::params![id, status.as_str(), error, retry_at],
+        )?;
+        Ok(())
+    }
+
+    /// Queues again every job marked as running that has attempts left, returning how many
+    /// there were, and marks those interrupted in their last attempt as failed, so a job that
+    /// brings the server down is not run forever. Only call this before any worker has started.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the jobs cannot be updated.
+    pub fn requeue_running_jobs(&self) -> Result<usize> {
+        let tx = self.savepoint()?;
+        self.conn.execute(
+            "UPDATE jobs SET status = 'failed', last_error = 'Interrupted by a restart in its last attempt',
+                 updated_at = CURRENT_TIMESTAMP
+             WHERE status = 'running' AND attempts >= max_attempts",
+            [],
+        )?;
+        let requeued = self.conn.execute(
+            "UPDATE jobs SET status = 'queued', updated_at = CURRENT_TIMESTAMP WHERE status = 'running'",
+            [],
+        )?;
+        tx.commit()?;
+        Ok(requeued)
+    }
+
+    fn job_from_row(row: &rusqlite::Row) -> Result<Job> {
+        let conversion = |index, e: Box<dyn std::error::Error + Send + Sync>| {
+            rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, e)
+        };
+        let payload: String = row.get(1)?;
+        let status: String = row.get(2)?;
+        Ok(Job {
+            id: row.get(0)?,
+            task: serde_json::from_str(&payload).map_err(|e| conversion(1, e.into()))?,
+            status: status.parse().map_err(|e: String| conversion(2, e.into()))?,
+            attempts: row.get(3)?,
+            max_attempts: row.get(4)?,
+            last_error: row.get(5)?,
+            run_at: row.get(6)?,
+            created_at: row.get(7)?,
+            updated_at: row.get(8)?,
+        })
+    }
+
+    /// Loads the metadata of a game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metadata cannot be loaded.
+    pub fn load_metadata(&self, game_id: &str) -> Result<BTreeMap<String, String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT key, value FROM game_metadata WHERE game_id = ?1")?;
+        let rows = stmt.query_map([game_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    /// Replaces the metadata of a game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metadata cannot be saved, in which case nothing is changed.
+    pub fn save_metadata(&self, game_id: &str, metadata: &BTreeMap<String, String>) -> Result<()> {
+        let tx = self.savepoint()?;
+        tx.execute("DELETE FROM game_metadata WHERE game_id = ?1", [game_id])?;
+        {
+            let mut stmt =
+                tx.prepare("INSERT INTO game_metadata (game_id, key, value) VALUES (?1, ?2, ?3)")?;
+            for (key, value) in metadata {
+                stmt.execute([game_id, key, value])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Returns the ids of finished games, including self-play and imported games, not yet
+    /// mined for puzzles.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the games cannot be listed.
+    pub fn unmined_game_ids(&self) -> Result<Vec<GameId>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT game_id FROM game_results
+             UNION SELECT id FROM games WHERE id GLOB ?1 || '*'
+             UNION SELECT game_id FROM game_metadata WHERE key = ?2
+             EXCEPT SELECT game_id FROM mined_games
+             ORDER BY 1",
+        )?;
+        let rows = stmt.query_map([SELF_PLAY_PREFIX, IMPORTED_BY_METADATA_KEY], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// Records that a game has been mined for puzzles.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game cannot be recorded.
+    pub fn mark_game_mined(&self, game_id: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO mined_games (game_id) VALUES (?1)",
+            [game_id],
+        )?;
+        Ok(())
+    }
+
+    /// Stores a puzzle found in a game. Returns false if the position is already a puzzle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the puzzle cannot be saved.
+    pub fn save_puzzle(&self, game_id: &str, puzzle: &Puzzle) -> Result<bool> {
+        let inserted = self.conn.execute(
+            "INSERT OR IGNORE INTO puzzles (game_id, ply, position, solution, difficulty)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![
+                game_id,
+                puzzle.ply,
+                puzzle.game.to_position_string(),
+                Game::pos_to_coord(puzzle.solution),
+                puzzle.difficulty
+            ],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    /// Loads a puzzle by id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the puzzle cannot be loaded or is malformed.
+    pub fn load_puzzle(&self, id: i64) -> Result<Option<StoredPuzzle>> {
+        self.query_puzzle("WHERE id = ?1", id)
+    }
+
+    /// Returns the puzzle of the given day, counted in days since the Unix epoch. The puzzles
+    /// take turns, so every day has one as long as any exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the puzzle cannot be loaded or is malformed.
+    pub fn puzzle_for_day(&self, day: u64) -> Result<Option<StoredPuzzle>> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM puzzles", [], |row| row.get(0))?;
+        if count == 0 {
+            return Ok(None);
+        }
+        let offset = i64::try_from(day).unwrap_or(i64::MAX) % count;
+        self.query_puzzle("ORDER BY id LIMIT 1 OFFSET ?1", offset)
     }
 
-    /// Saves a game to the database.
+    fn query_puzzle(&self, clause: &str, param: i64) -> Result<Option<StoredPuzzle>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT id, game_id, ply, position, solution, difficulty FROM puzzles {clause}"
+        ))?;
+        let mut rows = stmt.query_map([param], |row| {
+            let invalid = |column, e: String| {
+                rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, e.into())
+            };
+            let position: String = row.get(3)?;
+            let solution: String = row.get(4)?;
+            Ok(StoredPuzzle {
+                id: row.get(0)?,
+                game_id: row.get(1)?,
+                puzzle: Puzzle {
+                    ply: row.get(2)?,
+                    game: Game::from_position_string(&position).map_err(|e| invalid(3, e))?,
+                    solution: Game::coord_to_pos(&solution).map_err(|e| invalid(4, e))?,
+                    difficulty: row.get(5)?,
+                },
+            })
+        })?;
+        rows.next().transpose()
+    }
+
+    /// Counts an attempt at a puzzle, and whether it was solved.
     ///
     /// # Errors
     ///
-    /// Returns an error if the game cannot be saved.
-    pub fn save_game(&self, id: &str, game: &Game, player1: &str, player2: &str) -> Result<()> {
-        let current_player = match game.current_player {
-            Player::Black => "Black",
-            Player::White => "White",
-        };
+    /// Returns an error if the attempt cannot be saved.
+    pub fn record_puzzle_attempt(&self, id: i64, solved: bool) -> Result<()> {
         self.conn.execute(
-            "INSERT OR REPLACE INTO games (id, black, white, current_player, passes, player1, player2) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            rusqlite::params![id, game.black as f64, game.white as f64, current_player, i64::from(game.passes), player1, player2],
+            "UPDATE puzzles SET attempts = attempts + 1, solves = solves + ?2 WHERE id = ?1",
+            rusqlite::params![id, i32::from(solved)],
         )?;
         Ok(())
     }
 
-    /// Loads a game from the database.
+    /// Stores a finished self-play game and its moves under the self-play namespace.
     ///
     /// # Errors
     ///
-    /// Returns an error if the game cannot be loaded.
-    pub fn load_game(&self, id: &str) -> Result<Option<(Game, String, String)>> {
-        let mut stmt = self.conn.prepare("SELECT black, white, current_player, passes, player1, player2 FROM games WHERE id = ?1")?;
-        let mut rows = stmt.query_map([id], |row| {
-            let black: f64 = row.get(0)?;
-            let white: f64 = row.get(1)?;
-            let current_player: String = row.get(2)?;
-            let passes: u8 = row.get(3)?;
-            let player1: String = row.get(4)?;
-            let player2: String = row.get(5)?;
-            let player = if current_player == "Black" {
-                Player::Black
-            } else {
-                Player::White
-            };
-            Ok((
-                Game {
-                    black: black as u64,
-                    white: white as u64,
-                    current_player: player,
-                    passes,
-                },
-                player1,
-                player2,
-            ))
-        })?;
-        if let Some(row) = rows.next() {
-            let (game, p1, p2) = row?;
-            Ok(Some((game, p1, p2)))
-        } else {
-            Ok(None)
-        }
+    /// Returns an error if the game or its moves cannot be saved.
+    pub fn save_self_play_game(
+        &self,
+        name: &str,
+        game: &Game,
+        moves: &[Move],
+        black: &str,
+        white: &str,
+    ) -> Result<String> {
+        let id = format!("{SELF_PLAY_PREFIX}{name}");
+        self.save_game(&id, game, black, white)?;
+        self.save_moves(&id, moves)?;
+        Ok(id)
     }
 
-    /// Loads all games from the database.
+    /// Stores a game read from a file as a finished casual game, under a new id it returns.
+    /// Players the file does not name are called `Black` and `White`. Who imported it and
+    /// from what format are kept in its metadata. No result is recorded, so nobody's rating,
+    /// record or statistics change.
     ///
     /// # Errors
     ///
-    /// Returns an error if the games cannot be loaded.
-    pub fn load_all_games(&self) -> Result<(GamesMap, PlayersMap)> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, black, white, current_player, passes, player1, player2 FROM games",
-        )?;
-        let rows = stmt.query_map([], |row| {
-            let id: String = row.get(0)?;
-            let black: f64 = row.get(1)?;
-            let white: f64 = row.get(2)?;
-            let current_player: String = row.get(3)?;
-            let passes: u8 = row.get(4)?;
-            let player1: String = row.get(5)?;
-            let player2: String = row.get(6)?;
-            let player = if current_player == "Black" {
-                Player::Black
-            } else {
-                Player::White
-            };
-            Ok((
-                id,
-                Game {
-                    black: black as u64,
-                    white: white as u64,
-                    current_player: player,
-                    passes,
-                },
-                player1,
-                player2,
-            ))
-        })?;
-        let mut games = HashMap::new();
-        let mut players = HashMap::new();
-        for row in rows {
-            let (id, game, p1, p2) = row?;
-            games.insert(id.clone(), game);
-            players.insert(id, (p1, p2));
-        }
-        Ok((games, players))
+    /// Returns an error if the game cannot be saved; nothing is saved then.
+    pub fn import_game(&self, uploader: &str, imported: &ImportedGame) -> Result<GameId> {
+        let tx = self.savepoint()?;
+        let id = self.next_game_id()?;
+        let options = GameOptions {
+            game_type: GameType::Casual,
+            ..GameOptions::default()
+        };
+        let black = imported.black.as_deref().unwrap_or("Black");
+        let white = imported.white.as_deref().unwrap_or("White");
+        self.create_game(&id, &imported.game, black, white, options)?;
+        self.save_moves(&id, &imported.moves)?;
+        let metadata = BTreeMap::from([
+            (IMPORTED_BY_METADATA_KEY.to_string(), uploader.to_string()),
+            (FORMAT_METADATA_KEY.to_string(), imported.format.as_str().to_string()),
+        ]);
+        self.save_metadata(&id, &metadata)?;
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// Returns the ids of all stored self-play games.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ids cannot be loaded.
+    pub fn self_play_game_ids(&self) -> Result<Vec<GameId>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id FROM games WHERE id GLOB ?1 || '*' ORDER BY id")?;
+        let rows = stmt.query_map([SELF_PLAY_PREFIX], |row| row.get(0))?;
+        rows.collect()
     }
 
     fn ensure_player(&self, name: &str) -> Result<()> {
         self.conn.execute(
-            "INSERT OR IGNORE INTO players (name, elo, wins, losses) VALUES (?1, 1200, 0, 0)",
-            [name],
+            "INSERT OR IGNORE INTO players (name, elo, wins, losses) VALUES (?1, ?2, 0, 0)",
+            rusqlite::params![name, self.rating.initial_rating(name)],
         )?;
         Ok(())
     }
 
-    fn get_elo(&self, name: &str) -> Result<f64> {
+    /// Returns a player's rating and number of rated games.
+    /// Returns a player's current rating, or the rating they would start with if they have
+    /// not played yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the rating cannot be read.
+    pub fn current_rating(&self, name: &str) -> Result<f64> {
+        let elo: Option<f64> = self
+            .conn
+            .query_row("SELECT elo FROM players WHERE name = ?1", [name], |row| row.get(0))
+            .optional()?;
+        Ok(self
+            .rating
+            .anchors
+            .get(name)
+            .copied()
+            .or(elo)
+            .unwrap_or_else(|| self.rating.initial_rating(name)))
+    }
+
+    /// Returns the record of `player` against `opponent` over their finished games.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the results cannot be read.
+    pub fn head_to_head(&self, player: &str, opponent: &str) -> Result<HeadToHead> {
+        let mut record = HeadToHead::default();
+        for outcome in self.outcomes(player, Some(opponent), None)? {
+            match outcome {
+                Outcome::Win => record.wins += 1,
+                Outcome::Loss => record.losses += 1,
+                Outcome::Draw => record.draws += 1,
+            }
+        }
+        Ok(record)
+    }
+
+    /// Returns the outcomes of a player's latest `games` finished games, newest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the results cannot be read.
+    pub fn recent_form(&self, player: &str, games: u32) -> Result<Vec<Outcome>> {
+        self.outcomes(player, None, Some(games))
+    }
+
+    /// Returns a player's outcomes, newest first, optionally only against `opponent` and
+    /// limited to the latest `limit` games.
+    fn outcomes(&self, player: &str, opponent: Option<&str>, limit: Option<u32>) -> Result<Vec<Outcome>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT CASE
+                WHEN winner IS NULL THEN 'draw'
+                WHEN (winner = 'Black') = (black = ?1) THEN 'win'
+                ELSE 'loss'
+             END
+             FROM game_results
+             WHERE (black = ?1 AND white = COALESCE(?2, white))
+                OR (white = ?1 AND black = COALESCE(?2, black))
+             ORDER BY finished_at DESC, rowid DESC
+             LIMIT COALESCE(?3, -1)",
+        )?;
+        let rows = stmt.query_map(rusqlite::params![player, opponent, limit], |row| {
+            Ok(match row.get::<_, String>(0)?.as_str() {
+                "win" => Outcome::Win,
+                "loss" => Outcome::Loss,
+                _ => Outcome::Draw,
+            })
+        })?;
+        rows.collect()
+    }
+
+    fn get_rating(&self, name: &str) -> Result<(f64, u32)> {
         let mut stmt = self
             .conn
-            .prepare("SELECT elo FROM players WHERE name = ?1")?;
-        stmt.query_row([name], |row| row.get(0)).or(Ok(1200.0))
+            .prepare("SELECT elo, wins + losses FROM players WHERE name = ?1")?;
+        let (elo, games) = stmt.query_row([name], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok((self.rating.anchors.get(name).copied().unwrap_or(elo), games))
     }
 
     fn update_elo(&self, name: &str, elo: f64) -> Result<()> {
@@ -185,34 +2120,345 @@ impl Storage {
         Ok(())
     }
 
-    fn calculate_elo(rating_a: f64, rating_b: f64, a_won: bool) -> (f64, f64) {
-        let k = 32.0;
-        let expected_a = 1.0 / (1.0 + 10.0_f64.powf((rating_b - rating_a) / 400.0));
-        let score_a = if a_won { 1.0 } else { 0.0 };
-        let new_a = rating_a + k * (score_a - expected_a);
-        let new_b = rating_b + k * ((1.0 - score_a) - (1.0 - expected_a));
-        (new_a, new_b)
-    }
-
     /// Updates the player's ELO and wins/losses.
     ///
+    /// New players get a higher K-factor during their provisional games, ratings are kept
+    /// above the configured floor, and anchored players keep their fixed rating.
+    ///
     /// # Errors
     ///
-    /// Returns an error if the player cannot be updated.
+    /// Returns an error if the player cannot be updated, in which case nothing is changed.
     pub fn update_player(&self, player: &str, opponent: &str, player_won: bool) -> Result<()> {
+        let tx = self.savepoint()?;
+        self.rate(player, opponent, player_won)?;
+        tx.commit()
+    }
+
+    /// Updates both players' ratings and records, returning each one's rating before and
+    /// after the game.
+    fn rate(&self, player: &str, opponent: &str, player_won: bool) -> Result<[(f64, f64); 2]> {
         self.ensure_player(player)?;
         self.ensure_player(opponent)?;
-        let player_elo = self.get_elo(player)?;
-        let opponent_elo = self.get_elo(opponent)?;
-        let (new_player_elo, new_opponent_elo) =
-            Self::calculate_elo(player_elo, opponent_elo, player_won);
+        let (player_elo, player_games) = self.get_rating(player)?;
+        let (opponent_elo, opponent_games) = self.get_rating(opponent)?;
+        let new_player_elo =
+            self.rating
+                .updated_rating(player, player_elo, player_games, opponent_elo, player_won);
+        let new_opponent_elo =
+            self.rating
+                .updated_rating(opponent, opponent_elo, opponent_games, player_elo, !player_won);
         self.update_elo(player, new_player_elo)?;
         self.update_elo(opponent, new_opponent_elo)?;
         self.update_wins_losses(player, player_won)?;
         self.update_wins_losses(opponent, !player_won)?;
+        Ok([(player_elo, new_player_elo), (opponent_elo, new_opponent_elo)])
+    }
+
+    /// Processes a finished game atomically: saves its final position, archives the result,
+    /// and unless it was casual updates both players' ratings (unless it was a draw), records
+    /// and statistics, and appends the rating changes to the history.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any step fails, in which case nothing is changed.
+    pub fn finish_game(&self, id: &str, game: &Game, result: &GameResult) -> Result<()> {
+        let tx = self.savepoint()?;
+        self.record_result(id, game, result)?;
+        tx.commit()
+    }
+
+    /// Stores the result of a game `resigned` gave up, like [`Storage::finish_game`], and
+    /// remembers the resignation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any step fails, in which case nothing is changed.
+    pub fn resign_game(&self, id: &str, game: &Game, resigned: Player, result: &GameResult) -> Result<()> {
+        let tx = self.savepoint()?;
+        self.record_result(id, game, result)?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO resignations (game_id, player) VALUES (?1, ?2)",
+            [id, resigned.as_str()],
+        )?;
+        tx.commit()
+    }
+
+    /// Stores the result of a game drawn by a claim of inactivity, like
+    /// [`Storage::finish_game`], and remembers the claim.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any step fails, in which case nothing is changed.
+    pub fn draw_game(&self, id: &str, game: &Game, result: &GameResult) -> Result<()> {
+        let tx = self.savepoint()?;
+        self.record_result(id, game, result)?;
+        self.conn
+            .execute("INSERT OR IGNORE INTO drawn_games (game_id) VALUES (?1)", [id])?;
+        tx.commit()
+    }
+
+    /// Returns true if the game was drawn by a claim of inactivity.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the drawn games cannot be read.
+    pub fn is_drawn(&self, id: &str) -> Result<bool> {
+        self.conn
+            .query_row("SELECT 1 FROM drawn_games WHERE game_id = ?1", [id], |_| Ok(()))
+            .optional()
+            .map(|row| row.is_some())
+    }
+
+    /// Returns the color that resigned a game, if it was resigned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the resignation cannot be loaded.
+    pub fn resignation(&self, id: &str) -> Result<Option<Player>> {
+        self.conn
+            .query_row("SELECT player FROM resignations WHERE game_id = ?1", [id], |row| {
+                row.get::<_, String>(0)
+            })
+            .optional()
+            .map(|player| {
+                player.map(|player| if player == "Black" { Player::Black } else { Player::White })
+            })
+    }
+
+    /// Loads the color that resigned each resigned game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the resignations cannot be loaded.
+    pub fn resignations(&self) -> Result<HashMap<GameId, Player>> {
+        let mut stmt = self.conn.prepare("SELECT game_id, player FROM resignations")?;
+        let rows = stmt.query_map([], |row| {
+            let player: String = row.get(1)?;
+            let player = if player == "Black" {
+                Player::Black
+            } else {
+                Player::White
+            };
+            Ok((row.get(0)?, player))
+        })?;
+        rows.collect()
+    }
+
+    /// Remembers that a game was aborted. An aborted game has no result and changes no
+    /// ratings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the abort cannot be stored.
+    pub fn abort_game(&self, id: &str) -> Result<()> {
+        let tx = self.savepoint()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO aborted_games (game_id, aborted_at) VALUES (?1, CURRENT_TIMESTAMP)",
+            [id],
+        )?;
+        tx.execute("UPDATE games SET finished = 1 WHERE id = ?1", [id])?;
+        tx.commit()
+    }
+
+    /// Returns true if the game was aborted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the aborted games cannot be read.
+    pub fn is_aborted(&self, id: &str) -> Result<bool> {
+        self.conn
+            .query_row("SELECT 1 FROM aborted_games WHERE game_id = ?1", [id], |_| Ok(()))
+            .optional()
+            .map(|row| row.is_some())
+    }
+
+    /// Loads the ids of the aborted games.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the aborted games cannot be loaded.
+    pub fn aborted_games(&self) -> Result<HashSet<GameId>> {
+        let mut stmt = self.conn.prepare("SELECT game_id FROM aborted_games")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    fn record_result(&self, id: &str, game: &Game, result: &GameResult) -> Result<()> {
+        self.save_game(id, game, result.black, result.white)?;
+        // A resigned game ends before its board does.
+        self.conn.execute("UPDATE games SET finished = 1 WHERE id = ?1", [id])?;
+        let winner = result.winner.map(|winner| match winner {
+            Player::Black => "Black",
+            Player::White => "White",
+        });
+        self.conn.execute(
+            "INSERT OR REPLACE INTO game_results (game_id, black, white, winner, black_discs, white_discs, moves)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            rusqlite::params![
+                id,
+                result.black,
+                result.white,
+                winner,
+                result.black_discs,
+                result.white_discs,
+                result.moves
+            ],
+        )?;
+        if self.game_type(id)? == GameType::Casual {
+            return Ok(());
+        }
+        if let Some(winner) = result.winner {
+            let changes = self.rate(result.black, result.white, winner == Player::Black)?;
+            for (name, (before, after)) in [result.black, result.white].into_iter().zip(changes) {
+                self.conn.execute(
+                    "INSERT INTO rating_history (player, game_id, elo_before, elo_after)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    rusqlite::params![name, id, before, after],
+                )?;
+            }
+        }
+        let black_diff = i64::from(result.black_discs) - i64::from(result.white_discs);
+        self.update_game_stats(result.black, Player::Black, result.winner, black_diff, result.moves)?;
+        self.update_game_stats(result.white, Player::White, result.winner, -black_diff, result.moves)?;
+        Ok(())
+    }
+
+    /// Returns a player's rating changes, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the history cannot be loaded.
+    pub fn rating_history(&self, name: &str) -> Result<Vec<RatingChange>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT game_id, elo_before, elo_after FROM rating_history WHERE player = ?1 ORDER BY id",
+        )?;
+        let rows = stmt.query_map([name], |row| {
+            Ok(RatingChange {
+                game_id: row.get(0)?,
+                elo_before: row.get(1)?,
+                elo_after: row.get(2)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    fn update_game_stats(
+        &self,
+        name: &str,
+        color: Player,
+        winner: Option<Player>,
+        disc_diff: i64,
+        moves: u32,
+    ) -> Result<()> {
+        self.ensure_player(name)?;
+        let color_column = match color {
+            Player::Black => "games_black",
+            Player::White => "games_white",
+        };
+        let streak = if winner == Some(color) {
+            "current_streak + 1"
+        } else {
+            "0"
+        };
+        self.conn.execute(
+            &format!(
+                "UPDATE players SET {color_column} = {color_column} + 1,
+                    current_streak = {streak},
+                    best_streak = MAX(best_streak, {streak}),
+                    total_disc_diff = total_disc_diff + ?1,
+                    total_moves = total_moves + ?2
+                 WHERE name = ?3"
+            ),
+            rusqlite::params![disc_diff, moves, name],
+        )?;
         Ok(())
     }
 
+    /// Number of days covered by [`ServerStats::daily`].
+    pub const ACTIVITY_DAYS: u32 = 14;
+    /// Number of weeks covered by [`ServerStats::weekly`].
+    pub const ACTIVITY_WEEKS: u32 = 8;
+
+    /// Aggregates server-wide statistics from the finished games.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the results cannot be read.
+    pub fn server_stats(&self) -> Result<ServerStats> {
+        // Players with anchor ratings are the AIs.
+        let ai_json =
+            serde_json::to_string(&self.rating.anchors.keys().collect::<Vec<_>>()).unwrap_or_default();
+
+        let (total_games, avg_game_length): (u32, f64) = self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(AVG(moves), 0.0) FROM game_results",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let (ai_games, ai_wins, human_wins): (u32, u32, u32) = self.conn.query_row(
+            "WITH ai AS (SELECT value FROM json_each(?1)),
+                results AS (
+                    SELECT winner, black IN ai AS black_ai, white IN ai AS white_ai FROM game_results
+                )
+             SELECT COUNT(*),
+                COALESCE(SUM(CASE winner WHEN 'Black' THEN black_ai WHEN 'White' THEN white_ai END), 0),
+                COALESCE(SUM(CASE winner WHEN 'Black' THEN white_ai WHEN 'White' THEN black_ai END), 0)
+             FROM results WHERE black_ai != white_ai",
+            [&ai_json],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        let active_players = self.conn.query_row(
+            "WITH ai AS (SELECT value FROM json_each(?1))
+             SELECT COUNT(DISTINCT player) FROM (
+                SELECT black AS player, finished_at FROM game_results
+                UNION ALL SELECT white, finished_at FROM game_results
+             ) WHERE finished_at >= datetime('now', '-7 days') AND player NOT IN ai",
+            [&ai_json],
+            |row| row.get(0),
+        )?;
+        let rate = |wins: u32| {
+            if ai_games == 0 {
+                0.0
+            } else {
+                f64::from(wins) / f64::from(ai_games)
+            }
+        };
+        Ok(ServerStats {
+            daily: self.activity("%Y-%m-%d", Self::ACTIVITY_DAYS, &ai_json)?,
+            weekly: self.activity("%Y-%W", Self::ACTIVITY_WEEKS, &ai_json)?,
+            active_players,
+            total_games,
+            avg_game_length,
+            ai_games,
+            ai_win_rate: rate(ai_wins),
+            human_win_rate: rate(human_wins),
+        })
+    }
+
+    /// Counts games and active human players per period, formatted with `format`, for the
+    /// latest `periods` periods with games. `ai` is a JSON array of the AI player names.
+    fn activity(&self, format: &str, periods: u32, ai: &str) -> Result<Vec<Activity>> {
+        let mut stmt = self.conn.prepare(
+            "WITH ai AS (SELECT value FROM json_each(?3))
+             SELECT period, COUNT(DISTINCT game_id),
+                COUNT(DISTINCT CASE WHEN player NOT IN ai THEN player END)
+             FROM (
+                SELECT strftime(?1, finished_at) AS period, game_id, black AS player FROM game_results
+                UNION ALL SELECT strftime(?1, finished_at), game_id, white FROM game_results
+             )
+             GROUP BY period ORDER BY period DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(rusqlite::params![format, periods, ai], |row| {
+            Ok(Activity {
+                period: row.get(0)?,
+                games: row.get(1)?,
+                active_players: row.get(2)?,
+            })
+        })?;
+        let mut activity = rows.collect::<Result<Vec<_>>>()?;
+        activity.reverse();
+        Ok(activity)
+    }
+
     /// Returns the leaderboard.
     ///
     /// # Errors
@@ -221,19 +2467,268 @@ impl Storage {
     pub fn get_leaderboard(&self) -> Result<Vec<PlayerStats>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT name, elo, wins, losses FROM players ORDER BY elo DESC")?;
-        let rows = stmt.query_map([], |row| {
-            Ok(PlayerStats {
-                name: row.get(0)?,
-                elo: row.get(1)?,
-                wins: row.get(2)?,
-                losses: row.get(3)?,
+            .prepare(&format!("SELECT {PLAYER_STATS_COLUMNS} FROM players ORDER BY elo DESC"))?;
+        let rows = stmt.query_map([], |row| self.player_stats(row))?;
+        rows.collect()
+    }
+
+    /// Returns the statistics of one player, or `None` if they have not played yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the player cannot be loaded.
+    pub fn get_player(&self, name: &str) -> Result<Option<PlayerStats>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT {PLAYER_STATS_COLUMNS} FROM players WHERE name = ?1"))?;
+        let mut rows = stmt.query_map([name], |row| self.player_stats(row))?;
+        let Some(mut stats) = rows.next().transpose()? else {
+            return Ok(None);
+        };
+        stats.achievements = self.achievements(name)?;
+        stats.casual = Some(self.casual_record(name)?);
+        Ok(Some(stats))
+    }
+
+    /// Returns a player's results in casual games.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the results cannot be read.
+    pub fn casual_record(&self, name: &str) -> Result<Record> {
+        self.conn.query_row(
+            "SELECT
+                COUNT(CASE WHEN (r.black = ?1 AND r.winner = 'Black') OR (r.white = ?1 AND r.winner = 'White') THEN 1 END),
+                COUNT(CASE WHEN (r.black = ?1 AND r.winner = 'White') OR (r.white = ?1 AND r.winner = 'Black') THEN 1 END),
+                COUNT(CASE WHEN r.winner IS NULL THEN 1 END)
+             FROM game_results r JOIN games g ON g.id = r.game_id
+             WHERE g.ranked = 0 AND (r.black = ?1 OR r.white = ?1)",
+            [name],
+            |row| {
+                Ok(Record {
+                    wins: row.get(0)?,
+                    losses: row.get(1)?,
+                    draws: row.get(2)?,
+                })
+            },
+        )
+    }
+
+    /// Returns the achievements a player has earned, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the achievements cannot be loaded or one is unknown.
+    pub fn achievements(&self, name: &str) -> Result<Vec<EarnedAchievement>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT achievement, game_id, earned_at FROM achievements
+             WHERE player = ?1 ORDER BY earned_at, rowid",
+        )?;
+        let rows = stmt.query_map([name], |row| {
+            let achievement = row.get::<_, String>(0)?.parse().map_err(|e: String| {
+                rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+            })?;
+            Ok(EarnedAchievement {
+                achievement,
+                game_id: row.get(1)?,
+                earned_at: row.get(2)?,
             })
         })?;
-        let mut stats = Vec::new();
-        for row in rows {
-            stats.push(row?);
+        rows.collect()
+    }
+
+    /// Evaluates a finished game for both players' achievements and stores the new ones.
+    /// Must be called after [`Storage::finish_game`], as win streaks are read from the
+    /// updated statistics.
+    ///
+    /// Returns the newly earned achievements with the players who earned them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the statistics cannot be read or the achievements cannot be saved.
+    pub fn award_achievements(
+        &self,
+        game_id: &str,
+        moves: &[Move],
+        result: &GameResult,
+    ) -> Result<Vec<(PlayerName, Achievement)>> {
+        let mut awarded = Vec::new();
+        for (name, color) in [(result.black, Player::Black), (result.white, Player::White)] {
+            let streak = self.get_player(name)?.map_or(0, |stats| stats.current_streak);
+            for achievement in achievements::earned(moves, result, color, streak) {
+                let added = self.conn.execute(
+                    "INSERT OR IGNORE INTO achievements (player, achievement, game_id)
+                     VALUES (?1, ?2, ?3)",
+                    [name, achievement.as_str(), game_id],
+                )?;
+                if added > 0 {
+                    awarded.push((name.to_string(), achievement));
+                }
+            }
+        }
+        Ok(awarded)
+    }
+
+    /// Loads a player's settings, or the defaults if they never changed them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the settings cannot be loaded or are malformed.
+    pub fn player_settings(&self, name: &str) -> Result<PlayerSettings> {
+        let policy: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT accept_challenges FROM player_settings WHERE name = ?1",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let accept_challenges = policy
+            .map(|policy| policy.parse())
+            .transpose()
+            .map_err(|e: String| {
+                rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+            })?
+            .unwrap_or_default();
+        Ok(PlayerSettings {
+            accept_challenges,
+            friends: self.related_players(name, "friend")?,
+            blocked: self.related_players(name, "blocked")?,
+        })
+    }
+
+    fn related_players(&self, owner: &str, kind: &str) -> Result<Vec<PlayerName>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT other FROM player_relations WHERE owner = ?1 AND kind = ?2 ORDER BY other",
+        )?;
+        let rows = stmt.query_map([owner, kind], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// Replaces a player's settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the settings cannot be saved.
+    pub fn save_player_settings(&self, name: &str, settings: &PlayerSettings) -> Result<()> {
+        let tx = self.savepoint()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO player_settings (name, accept_challenges) VALUES (?1, ?2)",
+            [name, settings.accept_challenges.as_str()],
+        )?;
+        tx.execute("DELETE FROM player_relations WHERE owner = ?1", [name])?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR IGNORE INTO player_relations (owner, other, kind) VALUES (?1, ?2, ?3)",
+            )?;
+            for friend in &settings.friends {
+                stmt.execute([name, friend, "friend"])?;
+            }
+            for blocked in &settings.blocked {
+                stmt.execute([name, blocked, "blocked"])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Loads a player's preferences, or the defaults if they never set them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the preferences cannot be loaded or are malformed.
+    pub fn player_preferences(&self, name: &str) -> Result<PlayerPreferences> {
+        let preferences: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT preferences FROM player_preferences WHERE name = ?1",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        preferences.map_or_else(
+            || Ok(PlayerPreferences::default()),
+            |preferences| {
+                serde_json::from_str(&preferences).map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+                })
+            },
+        )
+    }
+
+    /// Replaces a player's preferences.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the preferences cannot be saved.
+    pub fn save_player_preferences(&self, name: &str, preferences: &PlayerPreferences) -> Result<()> {
+        let preferences =
+            serde_json::to_string(preferences).map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO player_preferences (name, preferences) VALUES (?1, ?2)",
+            [name, &preferences],
+        )?;
+        Ok(())
+    }
+
+    /// Returns true if either player has blocked the other.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the blocklists cannot be read.
+    pub fn is_blocked(&self, a: &str, b: &str) -> Result<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM player_relations WHERE kind = 'blocked'
+                AND ((owner = ?1 AND other = ?2) OR (owner = ?2 AND other = ?1)))",
+            [a, b],
+            |row| row.get(0),
+        )
+    }
+
+    /// Returns true if `target` accepts challenges from `challenger`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the settings cannot be read.
+    pub fn accepts_challenge(&self, challenger: &str, target: &str) -> Result<bool> {
+        if self.is_blocked(challenger, target)? {
+            return Ok(false);
         }
-        Ok(stats)
+        let settings = self.player_settings(target)?;
+        Ok(match settings.accept_challenges {
+            ChallengePolicy::Anyone => true,
+            ChallengePolicy::Friends => settings.friends.iter().any(|friend| friend == challenger),
+            ChallengePolicy::Nobody => false,
+        })
+    }
+
+    fn player_stats(&self, row: &rusqlite::Row) -> Result<PlayerStats> {
+        let name: String = row.get(0)?;
+        let wins: i32 = row.get(2)?;
+        let losses: i32 = row.get(3)?;
+        let games_as_black: i32 = row.get(4)?;
+        let games_as_white: i32 = row.get(5)?;
+        let total_disc_diff: i64 = row.get(8)?;
+        let total_moves: i64 = row.get(9)?;
+        let rated_games = u32::try_from(wins + losses).unwrap_or(0);
+        let provisional =
+            !self.rating.anchors.contains_key(&name) && rated_games < self.rating.provisional_games;
+        let games = games_as_black + games_as_white;
+        #[allow(clippy::cast_precision_loss)]
+        let average = |total: i64| if games == 0 { 0.0 } else { total as f64 / f64::from(games) };
+        Ok(PlayerStats {
+            name,
+            elo: row.get(1)?,
+            wins,
+            losses,
+            provisional,
+            games_as_black,
+            games_as_white,
+            current_streak: row.get(6)?,
+            best_streak: row.get(7)?,
+            avg_disc_diff: average(total_disc_diff),
+            avg_moves: average(total_moves),
+            achievements: Vec::new(),
+            casual: None,
+        })
     }
 }
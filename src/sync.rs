@@ -0,0 +1,210 @@
+//! Coordination between servers sharing one database.
+//!
+//! With shared storage, every server treats the database as the source of truth: a game is
+//! reloaded whenever its stored version has moved on, and a change is only written if nobody
+//! changed the game since it was loaded. Each change is then announced on an [`UpdateBus`],
+//! so the other servers refresh the game and tell the sockets they hold at once instead of on
+//! the next request.
+
+use crate::analysis::MoveAnalysis;
+use crate::protocol::{Delta, Notice, Watcher};
+use crate::state::Sessions;
+use parking_lot::Mutex;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Updates a subscriber may fall behind by before it misses some.
+pub(crate) const UPDATE_BUFFER: usize = 256;
+
+/// How often a [`DatabaseBus`] looks for updates from other servers.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Updates kept in the database for servers that have not read them yet.
+const RETAINED_UPDATES: i64 = 1000;
+
+/// A change to a game, announced by the server that made it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameUpdate {
+    /// Id of the server that made the change, from [`Sessions::instance_id`].
+    pub origin: String,
+    pub game_id: String,
+    pub change: Change,
+}
+
+/// What happened to the game.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Change {
+    /// A move, pass or early end of the game, which was written to the database.
+    Delta(Delta),
+    Chat { from: String, text: String },
+    Joined(Watcher),
+    Left(Watcher),
+    /// How good a move was, for the player who placed it.
+    Coach { player: String, analysis: MoveAnalysis },
+    /// The game was deleted.
+    Removed,
+    /// A notice for every socket on every server, sent with an empty game id.
+    Broadcast(Notice),
+}
+
+/// Carries updates between the servers sharing a database.
+pub trait UpdateBus: Send + Sync {
+    /// Announces a change made by this server.
+    fn publish(&self, update: &GameUpdate);
+
+    /// Returns a receiver of the updates announced by every server, this one included.
+    fn subscribe(&self) -> broadcast::Receiver<GameUpdate>;
+}
+
+/// A bus between servers running in one process, e.g. in tests.
+#[derive(Clone, Debug)]
+pub struct LocalBus {
+    sender: broadcast::Sender<GameUpdate>,
+}
+
+impl LocalBus {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(UPDATE_BUFFER).0,
+        }
+    }
+}
+
+impl Default for LocalBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UpdateBus for LocalBus {
+    fn publish(&self, update: &GameUpdate) {
+        let _ = self.sender.send(update.clone());
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<GameUpdate> {
+        self.sender.subscribe()
+    }
+}
+
+/// A bus through a table of the shared database, which every server polls for new rows.
+/// It needs nothing but the database, at the cost of a short delay.
+pub struct DatabaseBus {
+    conn: Mutex<Connection>,
+    sender: broadcast::Sender<GameUpdate>,
+}
+
+impl DatabaseBus {
+    /// Opens the bus on the database at `path` and starts polling it. Only updates published
+    /// from now on are received.
+    ///
+    /// Must be called from within a Tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened or its update table created.
+    pub fn open(path: &str) -> rusqlite::Result<Arc<Self>> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS game_updates (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                payload TEXT NOT NULL
+            )",
+            [],
+        )?;
+        let mut last: i64 = conn.query_row("SELECT COALESCE(MAX(seq), 0) FROM game_updates", [], |row| row.get(0))?;
+        let bus = Arc::new(Self {
+            conn: Mutex::new(conn),
+            sender: broadcast::channel(UPDATE_BUFFER).0,
+        });
+        let polled = Arc::downgrade(&bus);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(bus) = polled.upgrade() else {
+                    break;
+                };
+                match bus.read_since(last) {
+                    Ok(updates) => {
+                        for (seq, update) in updates {
+                            last = seq;
+                            let _ = bus.sender.send(update);
+                        }
+                    }
+                    Err(e) => tracing::warn!("Failed to read game updates: {e}"),
+                }
+            }
+        });
+        Ok(bus)
+    }
+
+    /// Returns the updates stored after `seq`, with their sequence numbers, skipping any that
+    /// cannot be read.
+    fn read_since(&self, seq: i64) -> rusqlite::Result<Vec<(i64, GameUpdate)>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare("SELECT seq, payload FROM game_updates WHERE seq > ?1 ORDER BY seq")?;
+        let rows = stmt.query_map([seq], |row| Ok((row.get(0)?, row.get::<_, String>(1)?)))?;
+        let mut updates = Vec::new();
+        for row in rows {
+            let (seq, payload) = row?;
+            match serde_json::from_str(&payload) {
+                Ok(update) => updates.push((seq, update)),
+                Err(e) => tracing::warn!("Skipping malformed game update {seq}: {e}"),
+            }
+        }
+        Ok(updates)
+    }
+}
+
+impl UpdateBus for DatabaseBus {
+    fn publish(&self, update: &GameUpdate) {
+        let payload = match serde_json::to_string(update) {
+            Ok(payload) => payload,
+            Err(e) => {
+                tracing::warn!("Failed to encode game update: {e}");
+                return;
+            }
+        };
+        let conn = self.conn.lock();
+        let stored = conn
+            .execute("INSERT INTO game_updates (payload) VALUES (?1)", [payload])
+            .and_then(|_| {
+                conn.execute(
+                    "DELETE FROM game_updates WHERE seq <= last_insert_rowid() - ?1",
+                    [RETAINED_UPDATES],
+                )
+            });
+        if let Err(e) = stored {
+            tracing::warn!("Failed to publish game update: {e}");
+        }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<GameUpdate> {
+        self.sender.subscribe()
+    }
+}
+
+/// Applies the updates announced by other servers to `sessions` until the bus closes.
+pub fn spawn_listener(sessions: &Arc<Mutex<Sessions>>, bus: &Arc<dyn UpdateBus>) {
+    let sessions = sessions.clone();
+    let mut updates = bus.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match updates.recv().await {
+                Ok(update) => sessions.lock().apply_update(update),
+                // The games are still reloaded on their next request; only the sockets
+                // missed a push.
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::warn!("Missed {missed} game updates from other servers");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
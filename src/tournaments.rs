@@ -0,0 +1,6 @@
+//! Tournament organisation.
+//!
+//! [`pairing`] decides who plays whom in each round. It knows nothing about games or the
+//! server, so the server's tournaments and offline engine matches can share it.
+
+pub mod pairing;
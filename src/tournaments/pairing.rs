@@ -0,0 +1,422 @@
+//! Pairing players for tournament rounds.
+//!
+//! Swiss rounds pair players with equal or close scores who have not met yet, Monrad style:
+//! players are ranked by score and rating, and each one in turn is paired with the next
+//! highest-ranked player they have not played, backtracking when that leaves someone without
+//! an opponent. With an odd number of players, the lowest-ranked player who has not had a
+//! bye sits the round out. Colors go to whoever has played the other one more often.
+//!
+//! Round-robin schedules use the circle method, with colors arranged so every player gets as
+//! many blacks as whites, give or take one.
+
+use crate::game::Player;
+use std::cmp::Ordering;
+
+/// Number of pairing attempts a Swiss round may backtrack through before it gives up on
+/// avoiding rematches.
+const MAX_PAIRING_STEPS: usize = 100_000;
+
+/// A player's record going into a Swiss round.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entrant {
+    pub name: String,
+    /// Points so far, usually 1 per win or bye and 0.5 per draw.
+    pub score: f64,
+    /// Breaks ties in score; higher ranks first.
+    pub rating: f64,
+    /// Opponents already played.
+    pub opponents: Vec<String>,
+    /// Colors played so far, oldest first.
+    pub colors: Vec<Player>,
+    pub had_bye: bool,
+}
+
+impl Entrant {
+    /// Creates an entrant who has not played yet.
+    #[must_use]
+    pub fn new(name: &str, rating: f64) -> Self {
+        Self {
+            name: name.to_string(),
+            score: 0.0,
+            rating,
+            opponents: Vec::new(),
+            colors: Vec::new(),
+            had_bye: false,
+        }
+    }
+
+    /// Returns the number of games played as Black minus those played as White.
+    fn color_balance(&self) -> i64 {
+        self.colors
+            .iter()
+            .map(|color| match color {
+                Player::Black => 1,
+                Player::White => -1,
+            })
+            .sum()
+    }
+
+    fn has_played(&self, other: &Entrant) -> bool {
+        self.opponents.contains(&other.name)
+    }
+}
+
+/// One game of a round.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pairing {
+    pub black: String,
+    pub white: String,
+}
+
+/// The games of a round and the player sitting it out, if any.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Round {
+    pub pairings: Vec<Pairing>,
+    pub bye: Option<String>,
+}
+
+/// Orders entrants from the highest ranked: by score, then rating, then name.
+fn rank(a: &Entrant, b: &Entrant) -> Ordering {
+    b.score
+        .total_cmp(&a.score)
+        .then(b.rating.total_cmp(&a.rating))
+        .then_with(|| a.name.cmp(&b.name))
+}
+
+/// Pairs the next Swiss round.
+///
+/// Rematches are avoided whenever the remaining players allow it; if no pairing without
+/// them exists, or finding one takes too long, players are paired by rank regardless.
+#[must_use]
+pub fn swiss_round(entrants: &[Entrant]) -> Round {
+    let mut ranked: Vec<&Entrant> = entrants.iter().collect();
+    ranked.sort_by(|a, b| rank(a, b));
+
+    let mut bye_candidates: Vec<usize> = (0..ranked.len()).rev().collect();
+    if ranked.len().is_multiple_of(2) {
+        bye_candidates.clear();
+    } else {
+        // Players who already had a bye are only considered once everyone has had one.
+        bye_candidates.sort_by_key(|&i| ranked[i].had_bye);
+    }
+
+    let mut steps = 0;
+    let attempt = |bye: Option<usize>, steps: &mut usize| {
+        let players: Vec<&Entrant> = (0..ranked.len())
+            .filter(|&i| Some(i) != bye)
+            .map(|i| ranked[i])
+            .collect();
+        let mut paired = Vec::with_capacity(players.len() / 2);
+        pair_without_rematches(&players, &mut vec![false; players.len()], &mut paired, steps)
+            .then(|| build_round(&players, &paired, bye.map(|i| ranked[i])))
+    };
+    if bye_candidates.is_empty() {
+        if let Some(round) = attempt(None, &mut steps) {
+            return round;
+        }
+    }
+    for &bye in &bye_candidates {
+        if let Some(round) = attempt(Some(bye), &mut steps) {
+            return round;
+        }
+    }
+
+    // Every pairing needs a rematch; pair neighbours in rank instead.
+    let bye = bye_candidates.first().copied();
+    let players: Vec<&Entrant> = (0..ranked.len())
+        .filter(|&i| Some(i) != bye)
+        .map(|i| ranked[i])
+        .collect();
+    let paired: Vec<(usize, usize)> = (0..players.len() / 2).map(|i| (2 * i, 2 * i + 1)).collect();
+    build_round(&players, &paired, bye.map(|i| ranked[i]))
+}
+
+/// Pairs the unpaired `players` depth first, each with the highest-ranked opponent they have
+/// not met, appending index pairs to `paired`. Returns false if no such pairing exists or
+/// the step budget runs out.
+fn pair_without_rematches(
+    players: &[&Entrant],
+    used: &mut [bool],
+    paired: &mut Vec<(usize, usize)>,
+    steps: &mut usize,
+) -> bool {
+    let Some(first) = used.iter().position(|&used| !used) else {
+        return true;
+    };
+    used[first] = true;
+    for second in first + 1..players.len() {
+        if used[second] || players[first].has_played(players[second]) {
+            continue;
+        }
+        *steps += 1;
+        if *steps > MAX_PAIRING_STEPS {
+            break;
+        }
+        used[second] = true;
+        paired.push((first, second));
+        if pair_without_rematches(players, used, paired, steps) {
+            return true;
+        }
+        paired.pop();
+        used[second] = false;
+    }
+    used[first] = false;
+    false
+}
+
+fn build_round(players: &[&Entrant], paired: &[(usize, usize)], bye: Option<&Entrant>) -> Round {
+    Round {
+        pairings: paired
+            .iter()
+            .map(|&(higher, lower)| assign_colors(players[higher], players[lower]))
+            .collect(),
+        bye: bye.map(|entrant| entrant.name.clone()),
+    }
+}
+
+/// Gives Black to the player who has played White more often. On a tie the higher-ranked
+/// `higher` alternates from their last color, starting with Black.
+fn assign_colors(higher: &Entrant, lower: &Entrant) -> Pairing {
+    let higher_black = match higher.color_balance().cmp(&lower.color_balance()) {
+        Ordering::Less => true,
+        Ordering::Greater => false,
+        Ordering::Equal => higher.colors.last() != Some(&Player::Black),
+    };
+    let (black, white) = if higher_black {
+        (higher, lower)
+    } else {
+        (lower, higher)
+    };
+    Pairing {
+        black: black.name.clone(),
+        white: white.name.clone(),
+    }
+}
+
+/// Schedules a round robin in which every player meets every other once.
+///
+/// With an odd number of players, one player sits out each round. Every player gets as many
+/// blacks as whites, or one more of either when they play an odd number of games.
+#[must_use]
+pub fn round_robin(players: &[String]) -> Vec<Round> {
+    if players.len() < 2 {
+        return Vec::new();
+    }
+    let mut seats: Vec<Option<&String>> = players.iter().map(Some).collect();
+    if seats.len() % 2 == 1 {
+        // The empty seat stays fixed, so the others rotate evenly through both colors.
+        seats.insert(0, None);
+    }
+    let seat_count = seats.len();
+    let fixed = seats[0];
+    let mut rotating = seats[1..].to_vec();
+    let mut rounds = Vec::with_capacity(seat_count - 1);
+    for round in 0..seat_count - 1 {
+        let order: Vec<Option<&String>> = std::iter::once(fixed).chain(rotating.iter().copied()).collect();
+        let mut schedule = Round::default();
+        for table in 0..seat_count / 2 {
+            let (mut black, mut white) = (order[table], order[seat_count - 1 - table]);
+            let swap = if table == 0 { round % 2 == 1 } else { table % 2 == 1 };
+            if swap {
+                std::mem::swap(&mut black, &mut white);
+            }
+            match (black, white) {
+                (Some(black), Some(white)) => schedule.pairings.push(Pairing {
+                    black: black.clone(),
+                    white: white.clone(),
+                }),
+                (Some(player), None) | (None, Some(player)) => schedule.bye = Some(player.clone()),
+                (None, None) => {}
+            }
+        }
+        rounds.push(schedule);
+        rotating.rotate_right(1);
+    }
+    rounds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::{HashMap, HashSet};
+
+    fn names(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("P{i}")).collect()
+    }
+
+    fn entrants(count: usize) -> Vec<Entrant> {
+        (0..count)
+            .map(|i| Entrant::new(&format!("P{i}"), 2000.0 - 10.0 * f64::from(u32::try_from(i).unwrap())))
+            .collect()
+    }
+
+    /// Checks that every player appears exactly once in the round, in a game or as the bye.
+    fn assert_everyone_placed(round: &Round, players: &[String]) {
+        let mut seen: Vec<&String> = round
+            .pairings
+            .iter()
+            .flat_map(|pairing| [&pairing.black, &pairing.white])
+            .chain(round.bye.as_ref())
+            .collect();
+        seen.sort();
+        let mut expected: Vec<&String> = players.iter().collect();
+        expected.sort();
+        assert_eq!(seen, expected);
+    }
+
+    /// Records a round's games in the entrants, with results drawn from `rng`.
+    fn play(entrants: &mut [Entrant], round: &Round, rng: &mut StdRng) {
+        let index: HashMap<String, usize> =
+            entrants.iter().enumerate().map(|(i, e)| (e.name.clone(), i)).collect();
+        for pairing in &round.pairings {
+            let (b, w) = (index[&pairing.black], index[&pairing.white]);
+            let black_score = [0.0, 0.5, 1.0][rng.gen_range(0..3)];
+            entrants[b].score += black_score;
+            entrants[w].score += 1.0 - black_score;
+            entrants[b].opponents.push(pairing.white.clone());
+            entrants[w].opponents.push(pairing.black.clone());
+            entrants[b].colors.push(Player::Black);
+            entrants[w].colors.push(Player::White);
+        }
+        if let Some(bye) = &round.bye {
+            entrants[index[bye]].score += 1.0;
+            entrants[index[bye]].had_bye = true;
+        }
+    }
+
+    #[test]
+    fn test_first_round_pairs_by_rank() {
+        let round = swiss_round(&entrants(4));
+        assert_eq!(round.bye, None);
+        assert_eq!(
+            round.pairings,
+            [
+                Pairing { black: "P0".to_string(), white: "P1".to_string() },
+                Pairing { black: "P2".to_string(), white: "P3".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_leaders_meet() {
+        let mut field = entrants(6);
+        field[4].score = 2.0;
+        field[5].score = 2.0;
+        let round = swiss_round(&field);
+        let first = &round.pairings[0];
+        let leaders: HashSet<&str> = [first.black.as_str(), first.white.as_str()].into();
+        assert_eq!(leaders, ["P4", "P5"].into());
+    }
+
+    #[test]
+    fn test_avoids_rematches() {
+        let mut field = entrants(4);
+        for (a, b) in [(0, 1), (2, 3)] {
+            let (name_a, name_b) = (field[a].name.clone(), field[b].name.clone());
+            field[a].opponents.push(name_b);
+            field[b].opponents.push(name_a);
+        }
+        let round = swiss_round(&field);
+        for pairing in &round.pairings {
+            let pair: HashSet<&str> = [pairing.black.as_str(), pairing.white.as_str()].into();
+            assert_ne!(pair, ["P0", "P1"].into());
+            assert_ne!(pair, ["P2", "P3"].into());
+        }
+    }
+
+    #[test]
+    fn test_rematch_when_unavoidable() {
+        let mut field = entrants(2);
+        field[0].opponents.push("P1".to_string());
+        field[1].opponents.push("P0".to_string());
+        let round = swiss_round(&field);
+        assert_eq!(round.pairings.len(), 1);
+    }
+
+    #[test]
+    fn test_bye_goes_to_lowest_without_one() {
+        let mut field = entrants(5);
+        let round = swiss_round(&field);
+        assert_eq!(round.bye.as_deref(), Some("P4"));
+        field[4].had_bye = true;
+        let round = swiss_round(&field);
+        assert_eq!(round.bye.as_deref(), Some("P3"));
+        for entrant in &mut field {
+            entrant.had_bye = true;
+        }
+        assert_eq!(swiss_round(&field).bye.as_deref(), Some("P4"));
+    }
+
+    #[test]
+    fn test_colors_balance() {
+        let mut field = entrants(2);
+        field[0].colors = vec![Player::Black, Player::Black];
+        field[1].colors = vec![Player::Black, Player::White];
+        let round = swiss_round(&field);
+        assert_eq!(round.pairings[0].black, "P1");
+
+        // On equal balance, the higher-ranked player alternates from their last color.
+        field[0].colors = vec![Player::Black, Player::White];
+        field[1].colors = vec![Player::White, Player::Black];
+        assert_eq!(swiss_round(&field).pairings[0].black, "P0");
+    }
+
+    #[test]
+    fn test_simulated_swiss_tournament() {
+        for (players, rounds) in [(8, 5), (9, 5), (16, 6), (7, 4)] {
+            let mut rng = StdRng::seed_from_u64(u64::try_from(players).unwrap());
+            let mut field = entrants(players);
+            let names = names(players);
+            for _ in 0..rounds {
+                let round = swiss_round(&field);
+                assert_everyone_placed(&round, &names);
+                play(&mut field, &round, &mut rng);
+            }
+            for entrant in &field {
+                let unique: HashSet<&String> = entrant.opponents.iter().collect();
+                assert_eq!(unique.len(), entrant.opponents.len(), "{} had a rematch", entrant.name);
+                assert!(entrant.color_balance().abs() <= 2, "{} has unbalanced colors", entrant.name);
+            }
+            let byes = field.iter().filter(|entrant| entrant.had_bye).count();
+            assert_eq!(byes, if players % 2 == 1 { rounds } else { 0 });
+        }
+    }
+
+    #[test]
+    fn test_round_robin_meets_everyone_once() {
+        for count in 2..=11 {
+            let players = names(count);
+            let rounds = round_robin(&players);
+            assert_eq!(rounds.len(), if count % 2 == 0 { count - 1 } else { count });
+            let mut met = HashSet::new();
+            let mut balance: HashMap<&str, i32> = HashMap::new();
+            let mut byes: HashMap<&str, usize> = HashMap::new();
+            for round in &rounds {
+                assert_everyone_placed(round, &players);
+                for pairing in &round.pairings {
+                    let mut pair = [pairing.black.as_str(), pairing.white.as_str()];
+                    pair.sort_unstable();
+                    assert!(met.insert(pair), "{pair:?} met twice");
+                    *balance.entry(pairing.black.as_str()).or_default() += 1;
+                    *balance.entry(pairing.white.as_str()).or_default() -= 1;
+                }
+                if let Some(bye) = &round.bye {
+                    *byes.entry(bye.as_str()).or_default() += 1;
+                }
+            }
+            assert_eq!(met.len(), count * (count - 1) / 2);
+            assert!(balance.values().all(|b| b.abs() <= 1), "{count} players: {balance:?}");
+            if count % 2 == 1 {
+                assert!(players.iter().all(|p| byes.get(p.as_str()) == Some(&1)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_robin_too_few_players() {
+        assert!(round_robin(&[]).is_empty());
+        assert!(round_robin(&names(1)).is_empty());
+    }
+}
@@ -0,0 +1,414 @@
+//! Self-play training loop.
+//!
+//! Two persistent [`MctsAi`] instances play each other, one per colour, and keep their search
+//! trees between moves so every search builds on the previous one. Progress is written to a
+//! JSON checkpoint after every game so an interrupted run can be resumed and reported on.
+//! Optionally, every game is also stored in the database under the self-play namespace
+//! (see [`crate::storage::SELF_PLAY_PREFIX`]) for later mining.
+
+use crate::ai::{AiConfig, MctsAi};
+use crate::game::{Game, Move, Player};
+#[cfg(feature = "storage")]
+use crate::storage::Storage;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+/// Version of the checkpoint file format.
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+/// Configuration for a self-play training run.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrainingConfig {
+    pub games: u32,
+    pub black: AiConfig,
+    pub white: AiConfig,
+    /// Number of moves over which each side's temperature decays linearly to zero.
+    /// Zero keeps the configured temperature for the whole game.
+    pub temperature_decay_moves: u32,
+    /// Base seed; game `n` is played with seeds derived from `seed + n`.
+    pub seed: u64,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        Self {
+            games: 1000,
+            black: AiConfig::default(),
+            white: AiConfig::default(),
+            temperature_decay_moves: 0,
+            seed: 0,
+        }
+    }
+}
+
+/// The result of a single self-play game.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GameRecord {
+    pub game: u32,
+    pub seed: u64,
+    pub winner: Option<Player>,
+    pub black_discs: u32,
+    pub white_discs: u32,
+    pub moves: u32,
+    pub duration_ms: u64,
+}
+
+/// A finished self-play game with its full move list, serializable for export.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlayedGame {
+    pub record: GameRecord,
+    pub moves: Vec<Move>,
+    pub final_position: Game,
+}
+
+/// The persisted state of a training run.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub version: u32,
+    pub config: TrainingConfig,
+    pub games: Vec<GameRecord>,
+}
+
+impl Checkpoint {
+    /// Creates an empty checkpoint for the given configuration.
+    #[must_use]
+    pub fn new(config: TrainingConfig) -> Self {
+        Self {
+            version: CHECKPOINT_VERSION,
+            config,
+            games: Vec::new(),
+        }
+    }
+
+    /// Loads a checkpoint from disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, is not a valid checkpoint, or was written
+    /// by an unsupported format version.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let checkpoint: Checkpoint = serde_json::from_str(&content)?;
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported checkpoint version {}", checkpoint.version),
+            ));
+        }
+        Ok(checkpoint)
+    }
+
+    /// Writes the checkpoint to disk, replacing any previous file atomically.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp, path)
+    }
+
+    /// Returns the number of the next game to play.
+    #[must_use]
+    pub fn next_game(&self) -> u32 {
+        self.games.last().map_or(1, |record| record.game + 1)
+    }
+
+    /// Computes aggregate statistics over all recorded games.
+    #[must_use]
+    pub fn report(&self) -> Report {
+        let mut report = Report {
+            games: u32::try_from(self.games.len()).unwrap_or(u32::MAX),
+            ..Report::default()
+        };
+        for record in &self.games {
+            match record.winner {
+                Some(Player::Black) => report.black_wins += 1,
+                Some(Player::White) => report.white_wins += 1,
+                None => report.draws += 1,
+            }
+            report.total_moves += u64::from(record.moves);
+            report.total_duration_ms += record.duration_ms;
+            report.total_disc_differential +=
+                i64::from(record.black_discs) - i64::from(record.white_discs);
+        }
+        report
+    }
+}
+
+/// Aggregate statistics over a training run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub games: u32,
+    pub black_wins: u32,
+    pub white_wins: u32,
+    pub draws: u32,
+    pub total_moves: u64,
+    pub total_duration_ms: u64,
+    /// Sum of black discs minus white discs over all games.
+    pub total_disc_differential: i64,
+}
+
+impl fmt::Display for Report {
+    #[allow(clippy::cast_precision_loss)]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.games == 0 {
+            return writeln!(f, "No games recorded.");
+        }
+        let games = f64::from(self.games);
+        let rate = |count: u32| f64::from(count) / games * 100.0;
+        writeln!(f, "Games: {}", self.games)?;
+        writeln!(f, "Black wins: {} ({:.2}%)", self.black_wins, rate(self.black_wins))?;
+        writeln!(f, "White wins: {} ({:.2}%)", self.white_wins, rate(self.white_wins))?;
+        writeln!(f, "Draws: {} ({:.2}%)", self.draws, rate(self.draws))?;
+        writeln!(f, "Avg moves: {:.2}", self.total_moves as f64 / games)?;
+        writeln!(
+            f,
+            "Avg disc differential (Black - White): {:+.2}",
+            self.total_disc_differential as f64 / games
+        )?;
+        writeln!(
+            f,
+            "Avg game time: {:.1} ms",
+            self.total_duration_ms as f64 / games
+        )
+    }
+}
+
+/// Returns the temperature to use for the given move number.
+///
+/// Sampling with a high temperature early on varies the openings between games, while
+/// decaying to zero makes the rest of the game play the strongest move found.
+#[must_use]
+pub fn decayed_temperature(initial: f64, move_number: u32, decay_moves: u32) -> f64 {
+    if decay_moves == 0 {
+        return initial;
+    }
+    let remaining = 1.0 - f64::from(move_number) / f64::from(decay_moves);
+    initial * remaining.max(0.0)
+}
+
+/// Plays one self-play game between fresh AIs configured for each side.
+///
+/// Each side's search is seeded from `seed`, so a game can be replayed exactly.
+#[must_use]
+pub fn play_game(config: &TrainingConfig, game_number: u32, seed: u64) -> PlayedGame {
+    let started = Instant::now();
+    let mut black = MctsAi::new(AiConfig {
+        rng_seed: Some(seed),
+        ..config.black.clone()
+    });
+    let mut white = MctsAi::new(AiConfig {
+        rng_seed: Some(seed.wrapping_add(1)),
+        ..config.white.clone()
+    });
+    let mut game = Game::new();
+    let mut history = Vec::new();
+    let mut moves = 0;
+
+    while !game.is_game_over() {
+        let (ai, base_temperature) = match game.current_player {
+            Player::Black => (&mut black, config.black.temperature),
+            Player::White => (&mut white, config.white.temperature),
+        };
+        ai.set_temperature(decayed_temperature(
+            base_temperature,
+            moves,
+            config.temperature_decay_moves,
+        ));
+        let mut mv = ai.get_move(&game).unwrap_or(Move::Pass);
+        match mv {
+            Move::Place(pos) => {
+                if game.make_move(pos).is_err() {
+                    tracing::warn!("AI chose illegal move {}, passing", Game::pos_to_coord(pos));
+                    game.pass();
+                    // The history and the trees follow the pass that was played.
+                    mv = Move::Pass;
+                } else {
+                    moves += 1;
+                }
+            }
+            Move::Pass => game.pass(),
+        }
+        history.push(mv);
+        // Both trees follow the game so the next search starts from the reached subtree.
+        black.make_move(mv);
+        white.make_move(mv);
+    }
+
+    let (black_discs, white_discs) = game.disc_count();
+    PlayedGame {
+        record: GameRecord {
+            game: game_number,
+            seed,
+            winner: game.winner(),
+            black_discs,
+            white_discs,
+            moves,
+            duration_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+        },
+        moves: history,
+        final_position: game,
+    }
+}
+
+/// Stores a self-play game in the database so it can be mined later.
+///
+/// # Errors
+///
+/// Returns an error if the game cannot be saved.
+#[cfg(feature = "storage")]
+pub fn store_game(storage: &Storage, config: &TrainingConfig, played: &PlayedGame) -> rusqlite::Result<String> {
+    storage.save_self_play_game(
+        &format!("{}_{}", config.seed, played.record.game),
+        &played.final_position,
+        &played.moves,
+        &format!("mcts-{}", config.black.simulations),
+        &format!("mcts-{}", config.white.simulations),
+    )
+}
+
+/// Runs the training loop, writing the checkpoint after every game.
+///
+/// With `resume`, the run continues from an existing checkpoint using the configuration
+/// stored in it; otherwise a new checkpoint is started and an existing one is left untouched.
+/// If `storage` is given, every finished game is also stored in it.
+///
+/// # Errors
+///
+/// Returns an error if the checkpoint cannot be read or written, if a checkpoint already
+/// exists and `resume` is false, or if a game cannot be stored.
+#[cfg(feature = "storage")]
+pub fn run(
+    config: TrainingConfig,
+    checkpoint_path: &Path,
+    resume: bool,
+    storage: Option<&Storage>,
+) -> io::Result<()> {
+    let mut checkpoint = if resume {
+        let checkpoint = Checkpoint::load(checkpoint_path)?;
+        println!(
+            "Resuming from game {} of {}",
+            checkpoint.next_game(),
+            checkpoint.config.games
+        );
+        checkpoint
+    } else if checkpoint_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists; pass --resume to continue it",
+                checkpoint_path.display()
+            ),
+        ));
+    } else {
+        Checkpoint::new(config)
+    };
+
+    for game_num in checkpoint.next_game()..=checkpoint.config.games {
+        let seed = checkpoint.config.seed.wrapping_add(u64::from(game_num));
+        let played = play_game(&checkpoint.config, game_num, seed);
+        if let Some(storage) = storage {
+            store_game(storage, &checkpoint.config, &played).map_err(io::Error::other)?;
+        }
+        checkpoint.games.push(played.record);
+        checkpoint.save(checkpoint_path)?;
+
+        if game_num % 100 == 0 {
+            print!("{}", checkpoint.report());
+        }
+    }
+
+    println!("Training complete. Total games: {}", checkpoint.config.games);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "storage")]
+    use crate::game::GameReplay;
+
+    fn fast_config() -> TrainingConfig {
+        let side = AiConfig {
+            simulations: 5,
+            ..AiConfig::default()
+        };
+        TrainingConfig {
+            games: 2,
+            black: side.clone(),
+            white: side,
+            ..TrainingConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_decayed_temperature() {
+        assert!((decayed_temperature(1.0, 5, 0) - 1.0).abs() < f64::EPSILON);
+        assert!((decayed_temperature(1.0, 0, 10) - 1.0).abs() < f64::EPSILON);
+        assert!((decayed_temperature(1.0, 5, 10) - 0.5).abs() < f64::EPSILON);
+        assert!(decayed_temperature(1.0, 20, 10).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_play_game_is_reproducible() {
+        let config = fast_config();
+        let first = play_game(&config, 1, 7).record;
+        let second = play_game(&config, 1, 7).record;
+        assert!(first.moves > 0 && first.moves <= 60);
+        assert_eq!(first.moves, second.moves);
+        assert_eq!(first.black_discs, second.black_discs);
+        assert_eq!(first.white_discs, second.white_discs);
+    }
+
+    #[test]
+    #[cfg(feature = "storage")]
+    fn test_store_game() {
+        let storage = Storage::new(":memory:").unwrap();
+        let config = fast_config();
+        let played = play_game(&config, 1, 3);
+        let id = store_game(&storage, &config, &played).unwrap();
+        assert!(id.starts_with(crate::storage::SELF_PLAY_PREFIX));
+        assert_eq!(storage.self_play_game_ids().unwrap(), vec![id.clone()]);
+        assert_eq!(storage.load_moves(&id).unwrap(), played.moves);
+
+        // Replaying the stored moves reaches the stored final position.
+        let replay = GameReplay::from_moves(storage.load_moves(&id).unwrap()).unwrap();
+        assert_eq!(replay.last(), &played.final_position);
+
+        // Self-play games stay out of the live sessions.
+        let (games, _) = storage.load_all_games().unwrap();
+        assert!(games.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "storage")]
+    fn test_checkpoint_resume_and_report() {
+        let path = std::env::temp_dir().join(format!("kawio_checkpoint_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut config = fast_config();
+        config.games = 1;
+        run(config, &path, false, None).unwrap();
+        assert!(run(fast_config(), &path, false, None).is_err());
+
+        // Raise the target and resume: only the missing game is played.
+        let mut checkpoint = Checkpoint::load(&path).unwrap();
+        checkpoint.config.games = 2;
+        checkpoint.save(&path).unwrap();
+        run(fast_config(), &path, true, None).unwrap();
+
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert_eq!(checkpoint.games.len(), 2);
+        assert_eq!(checkpoint.next_game(), 3);
+        let report = checkpoint.report();
+        assert_eq!(report.games, 2);
+        assert_eq!(report.black_wins + report.white_wins + report.draws, 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
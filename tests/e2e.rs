@@ -0,0 +1,886 @@
+//! End-to-end tests of the server over HTTP and WebSocket.
+//!
+//! Each test starts the real router on a free local port, with its games kept in an in-memory
+//! database, and talks to it as the web UI would.
+
+use futures_util::{SinkExt, StreamExt};
+use kawio::api::{
+    BoardStateResponse, ClaimResponse, GameStateResponse, Job, LoginResponse, MoveResponse, NewMatchResponse, PlayerStats, Task,
+    TicketResponse,
+};
+use kawio::auth::Auth;
+use kawio::game::{Game, Player};
+use kawio::protocol::{BoardServerMsg, MessageCode, MoveRejection, Notice, RejectionReason, ServerMsg};
+use kawio::state::Sessions;
+use kawio::storage::{PlayerPreferences, Storage};
+use parking_lot::Mutex;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// Longest a test waits for a socket message.
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A server listening on a free local port, with its games in an in-memory database.
+struct TestServer {
+    address: String,
+    sessions: Arc<Mutex<Sessions>>,
+    http: reqwest::Client,
+}
+
+impl TestServer {
+    async fn start() -> Self {
+        let sessions = Arc::new(Mutex::new(Sessions::new(Storage::new(":memory:").unwrap())));
+        // Served as in production, with the web UI behind the API.
+        let web = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("web");
+        let app = kawio::network::create_router(sessions.clone()).fallback_service(kawio::assets::router(&web));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Self {
+            address,
+            sessions,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Logs the player in and returns their token.
+    async fn login(&self, player: &str) -> String {
+        let response: LoginResponse = self.post("/auth/login", None, &json!({ "player": player })).await.unwrap();
+        response.token
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, token: Option<&str>) -> Result<T, StatusCode> {
+        let request = self.http.get(format!("http://{}{path}", self.address));
+        Self::send(request, token).await
+    }
+
+    async fn post<T: DeserializeOwned>(&self, path: &str, token: Option<&str>, body: &Value) -> Result<T, StatusCode> {
+        let request = self.http.post(format!("http://{}{path}", self.address)).json(body);
+        Self::send(request, token).await
+    }
+
+    async fn put<T: DeserializeOwned>(&self, path: &str, token: Option<&str>, body: &Value) -> Result<T, StatusCode> {
+        let request = self.http.put(format!("http://{}{path}", self.address)).json(body);
+        Self::send(request, token).await
+    }
+
+    async fn send<T: DeserializeOwned>(request: reqwest::RequestBuilder, token: Option<&str>) -> Result<T, StatusCode> {
+        let request = match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        let response = request.send().await.unwrap();
+        if !response.status().is_success() {
+            return Err(response.status());
+        }
+        Ok(response.json().await.unwrap())
+    }
+
+    /// Gets `path` as a client holding the version tagged `etag`, returning the status and the
+    /// tag of the version served.
+    async fn get_if_none_match(&self, path: &str, etag: Option<&str>) -> (StatusCode, String) {
+        let mut request = self.http.get(format!("http://{}{path}", self.address));
+        if let Some(etag) = etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        let response = request.send().await.unwrap();
+        let etag = response.headers()[reqwest::header::ETAG].to_str().unwrap().to_string();
+        (response.status(), etag)
+    }
+
+    /// Plays a move with `PUT /match/:id/move/:coord`, returning the error it describes if it fails.
+    async fn put_move(&self, id: &str, coord: &str, token: Option<&str>) -> Result<MoveResponse, (StatusCode, Notice)> {
+        let mut request = self.http.put(format!("http://{}/match/{id}/move/{coord}", self.address));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await.unwrap();
+        let status = response.status();
+        if !status.is_success() {
+            return Err((status, response.json().await.unwrap()));
+        }
+        Ok(response.json().await.unwrap())
+    }
+
+    async fn create_match(&self, token: &str, opponent: &str) -> String {
+        let response: NewMatchResponse = self
+            .post("/match/new", Some(token), &json!({ "player2": opponent }))
+            .await
+            .unwrap();
+        response.id
+    }
+
+    async fn state(&self, id: &str) -> GameStateResponse {
+        self.get(&format!("/match/{id}/state"), None).await.unwrap()
+    }
+
+    /// Opens a socket on the game, as the token's player or anonymously.
+    async fn socket(&self, id: &str, token: Option<&str>) -> Socket {
+        let url = match token {
+            Some(token) => format!("ws://{}/match/{id}/ws?token={token}", self.address),
+            None => format!("ws://{}/match/{id}/ws", self.address),
+        };
+        let (stream, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        Socket(stream)
+    }
+
+    /// Opens a socket on an analysis board, as the token's player.
+    async fn board_socket(&self, id: &str, token: &str) -> Socket {
+        let url = format!("ws://{}/boards/{id}/ws?token={token}", self.address);
+        let (stream, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        Socket(stream)
+    }
+}
+
+/// A socket open on a game.
+struct Socket(WebSocketStream<MaybeTlsStream<TcpStream>>);
+
+impl Socket {
+    /// Waits for the next message, failing the test if none arrives in time.
+    async fn next(&mut self) -> ServerMsg {
+        self.receive().await
+    }
+
+    /// Waits for the next message on an analysis board's socket.
+    async fn next_board(&mut self) -> BoardServerMsg {
+        self.receive().await
+    }
+
+    /// Waits for the next board message that `pick` takes, skipping the others.
+    async fn next_board_with<T>(&mut self, mut pick: impl FnMut(BoardServerMsg) -> Option<T>) -> T {
+        loop {
+            if let Some(value) = pick(self.next_board().await) {
+                return value;
+            }
+        }
+    }
+
+    async fn receive<T: DeserializeOwned>(&mut self) -> T {
+        loop {
+            let message = tokio::time::timeout(MESSAGE_TIMEOUT, self.0.next())
+                .await
+                .expect("Timed out waiting for a message")
+                .expect("The socket closed")
+                .unwrap();
+            if let Message::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    /// Skips messages until `pick` takes one.
+    async fn next_with<T>(&mut self, mut pick: impl FnMut(ServerMsg) -> Option<T>) -> T {
+        loop {
+            if let Some(value) = pick(self.next().await) {
+                return value;
+            }
+        }
+    }
+
+    /// Waits for a move to be rejected, and returns the rejection with the state sent right
+    /// after it for the client to resync to.
+    async fn next_rejection(&mut self) -> (MoveRejection, Box<GameStateResponse>) {
+        let rejection = self
+            .next_with(|message| match message {
+                ServerMsg::Rejected(rejection) => Some(rejection),
+                _ => None,
+            })
+            .await;
+        let ServerMsg::State(state) = self.next().await else {
+            panic!("Expected the state after the rejection");
+        };
+        (rejection, state)
+    }
+
+    /// Waits for the server to close the socket, and returns the error it sent first with the
+    /// close frame's code.
+    async fn closed_with(&mut self) -> (Notice, CloseCode) {
+        let ServerMsg::Error(notice) = self.next().await else {
+            panic!("Expected an error before the socket closed");
+        };
+        let message = tokio::time::timeout(MESSAGE_TIMEOUT, self.0.next())
+            .await
+            .expect("Timed out waiting for the socket to close")
+            .expect("The socket dropped without a close frame")
+            .unwrap();
+        let Message::Close(Some(frame)) = message else {
+            panic!("Expected a close frame, got {message:?}");
+        };
+        (notice, frame.code)
+    }
+
+    async fn send(&mut self, message: &Value) {
+        self.0.send(Message::Text(message.to_string())).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_scripted_game_over_http_and_sockets() {
+    let server = TestServer::start().await;
+    let alice = server.login("Alice").await;
+    let bob = server.login("Bob").await;
+    assert_eq!(
+        server
+            .post::<NewMatchResponse>("/match/new", None, &json!({ "player2": "Bob" }))
+            .await
+            .unwrap_err(),
+        StatusCode::UNAUTHORIZED
+    );
+    let id = server.create_match(&alice, "Bob").await;
+
+    // Bob plays over his socket while a spectator watches.
+    let mut bob_socket = server.socket(&id, Some(&bob)).await;
+    let mut spectator = server.socket(&id, None).await;
+    for socket in [&mut bob_socket, &mut spectator] {
+        let ServerMsg::State(state) = socket.next().await else {
+            panic!("Expected the state on connecting");
+        };
+        assert_eq!((state.player1.as_str(), state.player2.as_str()), ("Alice", "Bob"));
+    }
+    let wrong_turn = server
+        .post::<MoveResponse>(&format!("/match/{id}/move"), Some(&bob), &json!({ "coord": "D3" }))
+        .await;
+    assert_eq!(wrong_turn.unwrap_err(), StatusCode::BAD_REQUEST);
+    // Over a socket, the move is rejected with why, then the unchanged state.
+    bob_socket.send(&json!({ "type": "move", "coord": "d3" })).await;
+    let rejection = bob_socket
+        .next_with(|message| match message {
+            ServerMsg::Rejected(rejection) => Some(rejection),
+            _ => None,
+        })
+        .await;
+    assert_eq!(rejection.coord.map(|coord| coord.to_string()).as_deref(), Some("D3"));
+    assert_eq!((rejection.reason, rejection.notice.code), (RejectionReason::NotYourTurn, MessageCode::NotYourTurn));
+    let ServerMsg::State(state) = bob_socket.next().await else {
+        panic!("Expected the state after the rejection");
+    };
+    assert_eq!(state.current_player, "Black");
+
+    // Both play the first legal move until the game ends.
+    let mut plies = 0;
+    loop {
+        let state = server.state(&id).await;
+        if state.game_over {
+            break;
+        }
+        let coord = state.legal_moves.first().expect("Passes are automatic").clone();
+        if state.current_player == "Black" {
+            let path = format!("/match/{id}/move");
+            server
+                .post::<MoveResponse>(&path, Some(&alice), &json!({ "coord": coord }))
+                .await
+                .unwrap();
+        } else {
+            bob_socket.send(&json!({ "type": "move", "coord": coord })).await;
+        }
+        // Every socket on the game hears of the move.
+        for socket in [&mut bob_socket, &mut spectator] {
+            let delta = socket
+                .next_with(|message| match message {
+                    ServerMsg::Delta(delta) => Some(delta),
+                    _ => None,
+                })
+                .await;
+            assert_eq!((delta.player.as_str(), delta.action.as_str()), (state.current_player.as_str(), coord.as_str()));
+        }
+        plies += 1;
+    }
+    assert!(plies >= 9);
+
+    let finished = spectator
+        .next_with(|message| match message {
+            ServerMsg::State(state) => Some(state),
+            _ => None,
+        })
+        .await;
+    assert!(finished.game_over);
+    let state = server.state(&id).await;
+    assert!(state.legal_moves.is_empty());
+    spectator.send(&json!({ "type": "pass" })).await;
+    let rejection = spectator
+        .next_with(|message| match message {
+            ServerMsg::Rejected(rejection) => Some(rejection),
+            _ => None,
+        })
+        .await;
+    assert_eq!((rejection.coord, rejection.reason), (None, RejectionReason::GameOver));
+    assert_eq!(rejection.notice.code, MessageCode::GameOver);
+    let (alice_wins, bob_wins) = match state.winner.as_deref() {
+        Some("Black") => (1, 0),
+        Some("White") => (0, 1),
+        _ => (0, 0),
+    };
+
+    let leaderboard: Vec<PlayerStats> = server.get("/leaderboard", None).await.unwrap();
+    let stats = |name: &str| leaderboard.iter().find(|stats| stats.name == name).unwrap();
+    assert_eq!((stats("Alice").wins, stats("Alice").losses), (alice_wins, bob_wins));
+    assert_eq!((stats("Bob").wins, stats("Bob").losses), (bob_wins, alice_wins));
+    assert_eq!(stats("Alice").games_as_black, 1);
+    assert_eq!(server.sessions.lock().storage.load_moves(&id).unwrap().len(), plies);
+}
+
+#[tokio::test]
+async fn test_sockets_reject_moves_before_resyncing_the_state() {
+    let server = TestServer::start().await;
+    let alice = server.login("Alice").await;
+    let bob = server.login("Bob").await;
+    let id = server.create_match(&alice, "Bob").await;
+    let mut alice_socket = server.socket(&id, Some(&alice)).await;
+    let mut bob_socket = server.socket(&id, Some(&bob)).await;
+    let codes = |rejection: &MoveRejection| (rejection.reason, rejection.notice.code);
+
+    bob_socket.send(&json!({ "type": "move", "coord": "D3" })).await;
+    let (rejection, state) = bob_socket.next_rejection().await;
+    assert_eq!(codes(&rejection), (RejectionReason::NotYourTurn, MessageCode::NotYourTurn));
+    assert_eq!((state.current_player.as_str(), state.ply), ("Black", 0));
+
+    alice_socket.send(&json!({ "type": "move", "coord": "A1" })).await;
+    let (rejection, state) = alice_socket.next_rejection().await;
+    assert_eq!(codes(&rejection), (RejectionReason::Illegal, MessageCode::NoFlips));
+    assert_eq!(rejection.coord.map(|coord| coord.to_string()).as_deref(), Some("A1"));
+    assert_eq!(state.ply, 0);
+
+    // A client ahead of the game is out of sync, and resyncs to the ply it has reached.
+    alice_socket.send(&json!({ "type": "move", "coord": "D3", "ply": 3 })).await;
+    let (rejection, state) = alice_socket.next_rejection().await;
+    assert_eq!(codes(&rejection), (RejectionReason::OutOfSync, MessageCode::PlyNotReached));
+    assert_eq!(state.ply, 0);
+    assert!(state.last_move.is_none());
+
+    bob_socket.send(&json!({ "type": "resign" })).await;
+    alice_socket
+        .next_with(|message| match message {
+            ServerMsg::State(state) if state.game_over => Some(()),
+            _ => None,
+        })
+        .await;
+    alice_socket.send(&json!({ "type": "move", "coord": "D3" })).await;
+    let (rejection, state) = alice_socket.next_rejection().await;
+    assert_eq!(codes(&rejection), (RejectionReason::GameOver, MessageCode::GameOver));
+    assert!(state.game_over);
+    assert_eq!(state.resigned.as_deref(), Some("White"));
+}
+
+#[tokio::test]
+async fn test_sockets_closed_by_policy_when_they_cannot_join() {
+    let server = TestServer::start().await;
+    let alice = server.login("Alice").await;
+    let mut socket = server.socket("no-such-game", Some(&alice)).await;
+    let (notice, code) = socket.closed_with().await;
+    assert_eq!((notice.code, code), (MessageCode::GameNotFound, CloseCode::Policy));
+}
+
+#[cfg(feature = "chaos")]
+#[tokio::test]
+async fn test_sockets_closed_with_an_error_when_a_message_fails() {
+    let server = TestServer::start().await;
+    let alice = server.login("Alice").await;
+    let id = server.create_match(&alice, "Bob").await;
+    let mut socket = server.socket(&id, Some(&alice)).await;
+    let ServerMsg::State(_) = socket.next().await else {
+        panic!("Expected the state on connecting");
+    };
+
+    // A message that cannot be read is answered, and the socket stays open.
+    socket.0.send(Message::Text("{\"type\":".to_string())).await.unwrap();
+    assert!(matches!(socket.next().await, ServerMsg::Error(_)));
+    socket.send(&json!({ "type": "ping" })).await;
+    assert_eq!(socket.next().await, ServerMsg::Pong);
+
+    // One that fails while it is handled closes the socket with an error, not by dropping it.
+    server.sessions.lock().faults().panic_on_messages(1);
+    socket.send(&json!({ "type": "move", "coord": "D3" })).await;
+    let (notice, code) = socket.closed_with().await;
+    assert_eq!((notice.code, code), (MessageCode::InternalError, CloseCode::Error));
+    assert!(server.state(&id).await.last_move.is_none());
+}
+
+#[tokio::test]
+async fn test_sockets_pass_only_without_legal_moves() {
+    let server = TestServer::start().await;
+    let alice = server.login("Alice").await;
+    let bob = server.login("Bob").await;
+    let id = server.create_match(&alice, "Bob").await;
+    {
+        let mut sessions = server.sessions.lock();
+        sessions.set_auto_pass(false);
+        sessions.set_strict_passes(true);
+        // Once Black takes A1, White has no legal move.
+        let square = |coord: &str| 1u64 << Game::coord_to_pos(coord).unwrap();
+        let game = sessions.get_game_mut(&id).unwrap();
+        game.black = square("A3") | square("H8");
+        game.white = square("A2") | square("G7");
+    }
+    let mut alice_socket = server.socket(&id, Some(&alice)).await;
+    let mut bob_socket = server.socket(&id, Some(&bob)).await;
+
+    alice_socket.send(&json!({ "type": "pass" })).await;
+    let (rejection, state) = alice_socket.next_rejection().await;
+    assert_eq!(rejection.coord, None);
+    assert_eq!(
+        (rejection.reason, rejection.notice.code),
+        (RejectionReason::Illegal, MessageCode::PassNotAllowed)
+    );
+    assert_eq!(state.current_player, "Black");
+
+    alice_socket.send(&json!({ "type": "move", "coord": "A1" })).await;
+    bob_socket
+        .next_with(|message| match message {
+            ServerMsg::State(state) if state.current_player == "White" => Some(()),
+            _ => None,
+        })
+        .await;
+    bob_socket.send(&json!({ "type": "pass" })).await;
+    let delta = alice_socket
+        .next_with(|message| match message {
+            ServerMsg::Delta(delta) if delta.action == "pass" => Some(delta),
+            _ => None,
+        })
+        .await;
+    assert_eq!(delta.player, "White");
+    assert_eq!(server.state(&id).await.current_player, "Black");
+}
+
+#[tokio::test]
+async fn test_sockets_broadcast_presence_and_chat() {
+    let server = TestServer::start().await;
+    let alice = server.login("Alice").await;
+    let bob = server.login("Bob").await;
+    let id = server.create_match(&alice, "Bob").await;
+    let mut alice_socket = server.socket(&id, Some(&alice)).await;
+    alice_socket.next().await;
+    let mut bob_socket = server.socket(&id, Some(&bob)).await;
+    bob_socket.next().await;
+    let joined = alice_socket
+        .next_with(|message| match message {
+            ServerMsg::Joined(watcher) => Some(watcher),
+            _ => None,
+        })
+        .await;
+    assert_eq!((joined.name.as_deref(), joined.spectator), (Some("Bob"), false));
+
+    // Chat reaches everyone, spectators included, but only players logged in can chat.
+    let mut spectator = server.socket(&id, None).await;
+    spectator.next().await;
+    bob_socket.send(&json!({ "type": "chat", "text": "Good luck" })).await;
+    for socket in [&mut alice_socket, &mut bob_socket, &mut spectator] {
+        let chat = socket
+            .next_with(|message| match message {
+                ServerMsg::Chat { from, text } => Some((from, text)),
+                _ => None,
+            })
+            .await;
+        assert_eq!(chat, ("Bob".to_string(), "Good luck".to_string()));
+    }
+    spectator.send(&json!({ "type": "chat", "text": "Hi" })).await;
+    let error = spectator
+        .next_with(|message| match message {
+            ServerMsg::Error(notice) => Some(notice),
+            _ => None,
+        })
+        .await;
+    assert_eq!(error.code, MessageCode::NotLoggedIn);
+
+    // Bob leaving starts his countdown to come back, announced to the others.
+    bob_socket.0.close(None).await.unwrap();
+    let countdown = alice_socket
+        .next_with(|message| match message {
+            ServerMsg::Countdown(countdown) => Some(countdown),
+            _ => None,
+        })
+        .await;
+    assert_eq!(countdown.player, "Bob");
+    assert!(countdown.abort);
+    let state = server.state(&id).await;
+    assert_eq!(state.presence.spectators, 1);
+    assert!(!state.presence.player2_connected);
+}
+
+#[tokio::test]
+async fn test_conditional_gets_skip_unchanged_state_and_leaderboard() {
+    let server = TestServer::start().await;
+    let alice = server.login("Alice").await;
+    let id = server.create_match(&alice, "Bob").await;
+    let path = format!("/match/{id}/state");
+    let (status, etag) = server.get_if_none_match(&path, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(server.get_if_none_match(&path, Some(&etag)).await, (StatusCode::NOT_MODIFIED, etag.clone()));
+    assert_eq!(server.get_if_none_match(&path, Some("\"stale\", *")).await.0, StatusCode::NOT_MODIFIED);
+    let hinted = format!("{path}?hints=true");
+    let (status, with_hints) = server.get_if_none_match(&hinted, Some(&etag)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(server.get_if_none_match(&hinted, Some(&with_hints)).await.0, StatusCode::NOT_MODIFIED);
+
+    // A move or a player connecting changes the state.
+    let coord = server.state(&id).await.legal_moves[0].clone();
+    server
+        .post::<MoveResponse>(&format!("/match/{id}/move"), Some(&alice), &json!({ "coord": coord }))
+        .await
+        .unwrap();
+    let (status, moved) = server.get_if_none_match(&path, Some(&etag)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(moved, etag);
+    let mut socket = server.socket(&id, Some(&alice)).await;
+    socket.next().await;
+    let (status, connected) = server.get_if_none_match(&path, Some(&moved)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(connected, moved);
+
+    // The leaderboard changes once the game ends.
+    let (status, etag) = server.get_if_none_match("/leaderboard", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(server.get_if_none_match("/leaderboard", Some(&etag)).await.0, StatusCode::NOT_MODIFIED);
+    socket.send(&json!({ "type": "resign" })).await;
+    socket
+        .next_with(|message| match message {
+            ServerMsg::State(state) if state.game_over => Some(()),
+            _ => None,
+        })
+        .await;
+    let (status, ended) = server.get_if_none_match("/leaderboard", Some(&etag)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(ended, etag);
+
+    // So does the state, though no move was played.
+    let (status, resigned) = server.get_if_none_match(&path, Some(&connected)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(resigned, connected);
+}
+
+#[tokio::test]
+async fn test_difficulties_play_and_rank_under_their_own_names() {
+    let server = TestServer::start().await;
+    let alice = server.login("Alice").await;
+    let created: NewMatchResponse = server
+        .post("/match/new", Some(&alice), &json!({ "player2": "AI", "difficulty": "hard" }))
+        .await
+        .unwrap();
+    assert_eq!(server.state(&created.id).await.player2, "AI-Hard");
+    let versus_bob = server
+        .post::<NewMatchResponse>("/match/new", Some(&alice), &json!({ "player2": "Bob", "difficulty": "easy" }))
+        .await;
+    assert_eq!(versus_bob.unwrap_err(), StatusCode::BAD_REQUEST);
+    server.sessions.lock().resign(&created.id, "Alice").unwrap();
+
+    let leaderboard: Vec<PlayerStats> = server.get("/leaderboard", None).await.unwrap();
+    let names: Vec<&str> = leaderboard.iter().map(|stats| stats.name.as_str()).collect();
+    assert_eq!(names, ["AI-Hard", "Alice"]);
+    let people: Vec<PlayerStats> = server.get("/leaderboard?bots=false", None).await.unwrap();
+    assert_eq!(people.len(), 1);
+    assert_eq!(people[0].name, "Alice");
+    let (_, all) = server.get_if_none_match("/leaderboard", None).await;
+    let (_, only_people) = server.get_if_none_match("/leaderboard?bots=false", None).await;
+    assert_ne!(all, only_people);
+}
+
+#[tokio::test]
+async fn test_match_creators_pick_their_color() {
+    let server = TestServer::start().await;
+    let alice = server.login("Alice").await;
+    let bob = server.login("Bob").await;
+    let created: NewMatchResponse = server
+        .post("/match/new", Some(&alice), &json!({ "player2": "Bob", "color": "white" }))
+        .await
+        .unwrap();
+    assert_eq!(created.color, Player::White);
+    let state = server.state(&created.id).await;
+    assert_eq!((state.player1.as_str(), state.player2.as_str()), ("Bob", "Alice"));
+    let (status, notice) = server.put_move(&created.id, "D3", Some(&alice)).await.unwrap_err();
+    assert_eq!((status, notice.code), (StatusCode::BAD_REQUEST, MessageCode::NotYourTurn));
+    server.put_move(&created.id, "D3", Some(&bob)).await.unwrap();
+    server.put_move(&created.id, "C3", Some(&alice)).await.unwrap();
+
+    let drawn: NewMatchResponse = server
+        .post("/match/new", Some(&alice), &json!({ "player2": "Bob", "color": "random" }))
+        .await
+        .unwrap();
+    let state = server.state(&drawn.id).await;
+    let seat = if drawn.color == Player::Black { &state.player1 } else { &state.player2 };
+    assert_eq!(seat, "Alice");
+    let unknown = server
+        .post::<NewMatchResponse>("/match/new", Some(&alice), &json!({ "player2": "Bob", "color": "green" }))
+        .await;
+    assert!(unknown.unwrap_err().is_client_error());
+
+    // The AI opens the game when the creator plays White.
+    let versus_ai: NewMatchResponse = server
+        .post("/match/new", Some(&alice), &json!({ "player2": "AI", "color": "white" }))
+        .await
+        .unwrap();
+    let opened = tokio::time::timeout(MESSAGE_TIMEOUT, async {
+        loop {
+            let state = server.state(&versus_ai.id).await;
+            if state.ply > 0 {
+                return state;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("the AI did not open the game");
+    assert_eq!(opened.player1, "AI");
+    assert_eq!(opened.current_player, "White");
+}
+
+#[tokio::test]
+async fn test_moves_put_by_url_are_idempotent() {
+    let server = TestServer::start().await;
+    let alice = server.login("Alice").await;
+    let bob = server.login("Bob").await;
+    let id = server.create_match(&alice, "Bob").await;
+    let code = |result: Result<MoveResponse, (StatusCode, Notice)>| {
+        let (status, notice) = result.unwrap_err();
+        (status, notice.code)
+    };
+    assert_eq!(
+        code(server.put_move(&id, "D3", None).await),
+        (StatusCode::UNAUTHORIZED, MessageCode::NotLoggedIn)
+    );
+    assert_eq!(
+        code(server.put_move(&id, "Z9", Some(&alice)).await),
+        (StatusCode::BAD_REQUEST, MessageCode::OffBoard)
+    );
+    assert_eq!(
+        code(server.put_move(&id, "D3", Some(&bob)).await),
+        (StatusCode::BAD_REQUEST, MessageCode::NotYourTurn)
+    );
+    // A ply the game has not reached means the client is out of sync, not that the move is illegal.
+    assert_eq!(
+        code(server.put_move(&id, "D3?ply=1", Some(&alice)).await),
+        (StatusCode::CONFLICT, MessageCode::PlyNotReached)
+    );
+
+    // Other verbs are refused rather than served by the web UI, and play nothing.
+    for path in [format!("/match/{id}/move/D3"), format!("/match/{id}/move")] {
+        let response = server.http.get(format!("http://{}{path}", server.address)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+    assert!(server.state(&id).await.last_move.is_none());
+
+    // Retrying a move already played succeeds without playing it again.
+    server.put_move(&id, "d3", Some(&alice)).await.unwrap();
+    server.put_move(&id, "D3", Some(&alice)).await.unwrap();
+    assert_eq!(server.sessions.lock().storage.load_moves(&id).unwrap().len(), 1);
+    assert_eq!(
+        code(server.put_move(&id, "D3", Some(&bob)).await),
+        (StatusCode::BAD_REQUEST, MessageCode::SquareOccupied)
+    );
+    assert_eq!(server.state(&id).await.current_player, "White");
+}
+
+#[tokio::test]
+async fn test_draws_claimed_over_http() {
+    let server = TestServer::start().await;
+    let alice = server.login("Alice").await;
+    let bob = server.login("Bob").await;
+    let id = server.create_match(&alice, "Bob").await;
+    server.sessions.lock().set_inactivity_draw(Some(Duration::ZERO));
+    let path = format!("/match/{id}/claim-draw");
+
+    // The player to move cannot stall the game into a draw.
+    let refused: Result<ClaimResponse, StatusCode> = server.post(&path, Some(&alice), &json!({})).await;
+    assert_eq!(refused.unwrap_err(), StatusCode::CONFLICT);
+    let claim: ClaimResponse = server.post(&path, Some(&bob), &json!({})).await.unwrap();
+    assert_eq!(claim.winner, None);
+    let state = server.state(&id).await;
+    assert!(state.drawn);
+    assert!(state.game_over);
+}
+
+#[tokio::test]
+async fn test_private_games_need_an_invite() {
+    let server = TestServer::start().await;
+    let alice = server.login("Alice").await;
+    let bob = server.login("Bob").await;
+    let carol = server.login("Carol").await;
+    let created: NewMatchResponse = server
+        .post("/match/new", Some(&alice), &json!({ "player2": "Bob", "private": true }))
+        .await
+        .unwrap();
+    let id = created.id;
+    let invite = created.invite.unwrap();
+    assert_eq!(created.invite_url.unwrap(), format!("/?game={id}&invite={invite}"));
+    let public: NewMatchResponse = server.post("/match/new", Some(&alice), &json!({ "player2": "Carol" })).await.unwrap();
+    assert!(public.invite.is_none());
+
+    // The players see the game; others only with its invite.
+    let path = format!("/match/{id}/state");
+    server.get::<GameStateResponse>(&path, Some(&alice)).await.unwrap();
+    server.get::<GameStateResponse>(&path, Some(&bob)).await.unwrap();
+    assert_eq!(server.get::<GameStateResponse>(&path, None).await.unwrap_err(), StatusCode::NOT_FOUND);
+    assert_eq!(server.get::<GameStateResponse>(&path, Some(&carol)).await.unwrap_err(), StatusCode::NOT_FOUND);
+    // Invites to other games and logins are no invites.
+    let other = Auth::generate_invite(&public.id).unwrap();
+    for invite in [other, alice.clone()] {
+        let denied = server.get::<GameStateResponse>(&format!("{path}?invite={invite}"), None).await;
+        assert_eq!(denied.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+    let state: GameStateResponse = server.get(&format!("{path}?invite={invite}"), None).await.unwrap();
+    assert_eq!(state.player2, "Bob");
+
+    // Jobs on the game do not give it away either.
+    let task = Task::Analysis { game_id: id.clone() };
+    let job_id = server.sessions.lock().storage.enqueue_job(&task, 3, i64::MAX).unwrap();
+    let job_path = format!("/jobs/{job_id}");
+    assert_eq!(server.get::<Job>(&job_path, Some(&bob)).await.unwrap().task, task);
+    assert_eq!(server.get::<Job>(&job_path, Some(&carol)).await.unwrap_err(), StatusCode::NOT_FOUND);
+    assert_eq!(server.get::<Job>(&job_path, None).await.unwrap_err(), StatusCode::UNAUTHORIZED);
+    assert_eq!(server.get::<Vec<Job>>("/jobs", Some(&alice)).await.unwrap_err(), StatusCode::FORBIDDEN);
+
+    // Sockets are refused the same way, and spectate with the invite.
+    let url = format!("ws://{}/match/{id}/ws?token={carol}", server.address);
+    assert!(tokio_tungstenite::connect_async(url).await.is_err());
+    let mut spectator = server.socket(&id, Some(&format!("{carol}&invite={invite}"))).await;
+    let ServerMsg::State(state) = spectator.next().await else {
+        panic!("Expected the state on connecting");
+    };
+    assert_eq!(state.player1, "Alice");
+    let mut bob_socket = server.socket(&id, Some(&bob)).await;
+    bob_socket.next().await;
+
+    // Browsers open sockets with a ticket instead of their token, which opens one only.
+    let unauthorized = server.post::<TicketResponse>("/auth/ws-ticket", None, &json!({})).await;
+    assert_eq!(unauthorized.unwrap_err(), StatusCode::UNAUTHORIZED);
+    let ticket: TicketResponse = server.post("/auth/ws-ticket", Some(&bob), &json!({})).await.unwrap();
+    assert_eq!(ticket.expires_in, Auth::TICKET_LIFETIME);
+    let url = format!("ws://{}/match/{id}/ws?ticket={}", server.address, ticket.ticket);
+    let (stream, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+    let ServerMsg::State(state) = Socket(stream).next().await else {
+        panic!("Expected the state on connecting");
+    };
+    assert_eq!(state.player2, "Bob");
+    assert!(tokio_tungstenite::connect_async(&url).await.is_err());
+    let fresh: TicketResponse = server.post("/auth/ws-ticket", Some(&bob), &json!({})).await.unwrap();
+    let denied = server.get::<GameStateResponse>(&format!("{path}?ticket={}", fresh.ticket), None).await;
+    assert_eq!(denied.unwrap_err(), StatusCode::NOT_FOUND);
+    let denied = server.get::<PlayerPreferences>("/me/preferences", Some(&ticket.ticket)).await;
+    assert_eq!(denied.unwrap_err(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_preferences_apply_to_the_games_created() {
+    let server = TestServer::start().await;
+    let alice = server.login("Alice").await;
+    let defaults: PlayerPreferences = server.get("/me/preferences", Some(&alice)).await.unwrap();
+    assert_eq!(defaults, PlayerPreferences::default());
+    let body = json!({ "auto_pass": false, "coach": true, "orientation": "flipped" });
+    let saved: PlayerPreferences = server.put("/me/preferences", Some(&alice), &body).await.unwrap();
+    assert!(saved.coach && saved.notifications.your_turn);
+    assert_eq!(server.get::<PlayerPreferences>("/me/preferences", Some(&alice)).await.unwrap(), saved);
+    let anonymous = server.get::<PlayerPreferences>("/me/preferences", None).await;
+    assert_eq!(anonymous.unwrap_err(), StatusCode::UNAUTHORIZED);
+
+    // Coaching is turned on where it is allowed, unless the request says otherwise.
+    let ai_game = server.create_match(&alice, "AI").await;
+    assert!(server.state(&ai_game).await.coach);
+    assert!(!server.sessions.lock().auto_passes(&ai_game));
+    let ranked = server.create_match(&alice, "Bob").await;
+    assert!(!server.state(&ranked).await.coach);
+    let uncoached: NewMatchResponse = server
+        .post("/match/new", Some(&alice), &json!({ "player2": "AI", "coach": false }))
+        .await
+        .unwrap();
+    assert!(!server.state(&uncoached.id).await.coach);
+}
+
+#[tokio::test]
+async fn test_analysis_boards_shared_live_with_members() {
+    let server = TestServer::start().await;
+    let alice = server.login("Alice").await;
+    let bob = server.login("Bob").await;
+    let carol = server.login("Carol").await;
+    let created: BoardStateResponse = server
+        .post("/boards", Some(&alice), &json!({ "name": "Opening study", "invite": ["Bob"] }))
+        .await
+        .unwrap();
+    let id = created.id;
+    assert_eq!(created.members, ["Alice", "Bob"]);
+    let listed: Vec<BoardStateResponse> = server.get("/boards", Some(&bob)).await.unwrap();
+    assert_eq!(listed[0].name, "Opening study");
+
+    // Only members see the board, over HTTP or a socket.
+    let path = format!("/boards/{id}");
+    assert_eq!(server.get::<BoardStateResponse>(&path, Some(&carol)).await.unwrap_err(), StatusCode::NOT_FOUND);
+    assert_eq!(server.get::<BoardStateResponse>(&path, None).await.unwrap_err(), StatusCode::UNAUTHORIZED);
+    let url = format!("ws://{}/boards/{id}/ws?token={carol}", server.address);
+    assert!(tokio_tungstenite::connect_async(url).await.is_err());
+
+    let mut alice_socket = server.board_socket(&id, &alice).await;
+    let BoardServerMsg::State(state) = alice_socket.next_board().await else {
+        panic!("Expected the board on connecting");
+    };
+    assert_eq!(state.current_player, "Black");
+    let mut bob_socket = server.board_socket(&id, &bob).await;
+    bob_socket.next_board().await;
+    let joined = alice_socket
+        .next_board_with(|message| match message {
+            BoardServerMsg::Joined { name } if name == "Bob" => Some(name),
+            _ => None,
+        })
+        .await;
+    assert_eq!(joined, "Bob");
+
+    // Bob plays White although Black is to move, and Alice sees it.
+    bob_socket.send(&json!({ "type": "move", "coord": "E3", "color": "White" })).await;
+    let state = alice_socket
+        .next_board_with(|message| match message {
+            BoardServerMsg::State(state) => Some(state),
+            _ => None,
+        })
+        .await;
+    assert_eq!(state.scores["W"], 4);
+    assert_eq!(state.current_player, "Black");
+    assert_eq!(state.undo_depth, 1);
+
+    alice_socket.send(&json!({ "type": "undo" })).await;
+    let state = bob_socket
+        .next_board_with(|message| match message {
+            BoardServerMsg::State(state) if state.undo_depth == 0 => Some(state),
+            _ => None,
+        })
+        .await;
+    assert_eq!(state.scores["W"], 2);
+    bob_socket.send(&json!({ "type": "undo" })).await;
+    let error = bob_socket
+        .next_board_with(|message| match message {
+            BoardServerMsg::Error(notice) => Some(notice),
+            _ => None,
+        })
+        .await;
+    assert_eq!(error, Notice::new(MessageCode::NothingToUndo));
+
+    // Evaluations go to every member.
+    alice_socket.send(&json!({ "type": "evaluate" })).await;
+    let (position, evaluation) = bob_socket
+        .next_board_with(|message| match message {
+            BoardServerMsg::Evaluation { position, evaluation } => Some((position, evaluation)),
+            _ => None,
+        })
+        .await;
+    assert_eq!(position, state.position);
+    assert!(evaluation.best.is_some());
+
+    // Only the owner closes the board, which closes its sockets.
+    let url = format!("http://{}{path}", server.address);
+    let refused = server.http.delete(&url).bearer_auth(&bob).send().await.unwrap();
+    assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+    let closed = server.http.delete(&url).bearer_auth(&alice).send().await.unwrap();
+    assert_eq!(closed.status(), StatusCode::NO_CONTENT);
+    let ended = tokio::time::timeout(MESSAGE_TIMEOUT, async {
+        while let Some(Ok(message)) = bob_socket.0.next().await {
+            if let Message::Close(_) = message {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(ended.is_ok());
+    assert_eq!(server.get::<BoardStateResponse>(&path, Some(&alice)).await.unwrap_err(), StatusCode::NOT_FOUND);
+}
@@ -1,26 +1,1175 @@
-use kawio::game::Game;
-use kawio::state::Sessions;
-use kawio::storage::Storage;
+use kawio::ai::{AiConfig, Difficulty, ENGINE_VERSION};
+use kawio::anticheat::{MoveAnalyzer, SubmittedMove, Suspicion};
+use kawio::api::{Achievement, ChallengePolicy, GameType, HeadToHead, JobStatus, Outcome, PlayerSettings, Record, Task};
+use kawio::auth::Auth;
+use kawio::cache::{MemoryCache, SessionCache};
+use kawio::clock::ManualClock;
+use kawio::engine::EngineConfig;
+use kawio::game::{Game, Move, Player};
+use kawio::import;
+use kawio::jobs::{self, Backoff};
+use kawio::protocol::{GameEvent, MessageCode, Notice, Watcher, MAX_CHAT_LEN};
+use kawio::puzzles::{self, Puzzle};
+use kawio::state::{
+    Abandonment, AiActivity, SessionError, Sessions, AI_PLAYER, LEADERBOARD_TTL, MAX_KOMI, MAX_METADATA_ENTRIES,
+    MAX_METADATA_VALUE_LEN,
+};
+use kawio::storage::{
+    Annotation, BoardOrientation, GameOptions, GameResult, MoveMark, MoveRecord, Notifications, PlayerPreferences,
+    RatingConfig, Storage, TimeControl, FORMAT_METADATA_KEY, IMPORTED_BY_METADATA_KEY, TOURNAMENT_METADATA_KEY,
+};
+use kawio::sync::{Change, DatabaseBus, GameUpdate, LocalBus, UpdateBus};
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
 
 #[test]
 fn test_sessions_create_game() {
-    let mut sessions = Sessions::new();
-    let id = sessions.create_game("Alice".to_string(), "Bob");
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
     assert!(sessions.has_game(&id));
     assert!(sessions.has_player(&id));
 }
 
 #[test]
 fn test_sessions_make_move() {
-    let mut sessions = Sessions::new();
-    let id = sessions.create_game("Alice".to_string(), "Bob");
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
     let game = sessions.get_game(&id).unwrap();
     let moves = game.legal_moves();
     assert!(!moves.is_empty());
     let pos = moves[0];
     assert!(sessions.make_move(&id, pos, "Alice").is_ok());
     let game = sessions.get_game(&id).unwrap();
-    assert_eq!(game.current_player, kawio::game::Player::White);
+    assert_eq!(game.current_player, Player::White);
+}
+
+#[test]
+fn test_sessions_arbitrate_moves_racing_for_a_turn() {
+    let sessions = Arc::new(Mutex::new(Sessions::new(Storage::new(":memory:").unwrap())));
+    let id = sessions.lock().create_game("Alice".to_string(), "Bob").unwrap();
+    let moves = sessions.lock().get_game(&id).unwrap().legal_moves();
+
+    // Two tabs send different moves for the same turn at once: exactly one is played, and
+    // the other is told which.
+    let submissions: Vec<_> = moves[..2]
+        .iter()
+        .map(|&pos| {
+            let (sessions, id) = (sessions.clone(), id.clone());
+            std::thread::spawn(move || sessions.lock().apply_move(&id, "Alice", Move::Place(pos), 0))
+        })
+        .collect();
+    let results: Vec<_> = submissions.into_iter().map(|handle| handle.join().unwrap()).collect();
+    let played = sessions.lock().history(&id).unwrap()[0].mv;
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    let lost = results.into_iter().find_map(Result::err).unwrap();
+    assert_eq!(
+        lost,
+        SessionError::Superseded {
+            ply: 0,
+            played,
+            by: "Alice".to_string()
+        }
+    );
+    assert_eq!(lost.notice().code, MessageCode::Superseded);
+    assert!(lost.to_string().starts_with("Too late: Alice already played"), "{lost}");
+
+    let mut sessions = sessions.lock();
+    assert_eq!(sessions.ply(&id), Ok(1));
+    let reply = sessions.get_game(&id).unwrap().legal_moves()[0];
+    assert!(matches!(
+        sessions.apply_move(&id, "Bob", Move::Place(reply), 2),
+        Err(SessionError::PlyNotReached(2))
+    ));
+    sessions.apply_move(&id, "Bob", Move::Place(reply), 1).unwrap();
+    assert_eq!(sessions.ply(&id), Ok(2));
+}
+
+#[test]
+fn test_sessions_keep_ai_until_game_ends() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let id = sessions.create_game("Alice".to_string(), "AI").unwrap();
+    assert!(!sessions.is_ai_turn(&id));
+    let _ = sessions.ai_for(&id);
+    let superseded = sessions.start_search(&id);
+    let search = sessions.start_search(&id);
+    assert!(superseded.is_cancelled());
+    while let Some(&pos) = sessions.get_game(&id).unwrap().legal_moves().first() {
+        assert!(sessions.has_ai(&id));
+        let player = if sessions.is_ai_turn(&id) { "AI" } else { "Alice" };
+        sessions.make_move(&id, pos, player).unwrap();
+        // The tree catches up once the sessions are released, not while they are held.
+        if !sessions.get_game(&id).unwrap().is_game_over() {
+            assert_eq!(sessions.take_unseen_moves(&id), [Move::Place(pos)]);
+        }
+    }
+    assert!(sessions.get_game(&id).unwrap().is_game_over());
+    assert!(!sessions.has_ai(&id));
+    assert!(search.is_cancelled());
+}
+
+#[test]
+fn test_sessions_record_ai_games() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    sessions.set_ai_config(AiConfig {
+        simulations: 10,
+        rng_seed: Some(1),
+        ..AiConfig::default()
+    });
+    let played = sessions.create_game("Alice".to_string(), AI_PLAYER).unwrap();
+    while !sessions.get_game(&played).unwrap().is_game_over() {
+        let game = sessions.get_game(&played).unwrap().clone();
+        if sessions.is_ai_turn(&played) {
+            let mv = sessions.ai_for(&played).lock().get_move(&game);
+            match mv {
+                Some(Move::Place(pos)) => sessions.make_move(&played, pos, AI_PLAYER).unwrap(),
+                _ => sessions.pass(&played, AI_PLAYER).unwrap(),
+            };
+        } else {
+            sessions.make_move(&played, game.legal_moves()[0], "Alice").unwrap();
+        }
+    }
+    let resigned = sessions.create_game(AI_PLAYER.to_string(), "Bob").unwrap();
+    sessions.resign(&resigned, "Bob").unwrap();
+
+    let games = sessions.storage.ai_games(None, 10).unwrap();
+    assert_eq!(games.len(), 2);
+    let played = games.iter().find(|record| record.game_id == played).unwrap();
+    assert_eq!((played.ai_color, played.opponent.as_str()), (Player::White, "Alice"));
+    assert_eq!((played.engine_version.as_str(), played.simulations), (ENGINE_VERSION, 10));
+    assert_eq!(played.config["simulations"], 10);
+    assert!(played.summary.searches > 0);
+    assert!(played.summary.simulations >= u64::from(played.summary.searches) * 10);
+    let resigned = games.iter().find(|record| record.game_id == resigned).unwrap();
+    assert_eq!((resigned.outcome, resigned.summary.searches), (Outcome::Win, 0));
+
+    let strength = sessions.storage.ai_strength().unwrap();
+    assert_eq!(strength.len(), 1);
+    assert_eq!((strength[0].games, strength[0].simulations), (2, 10));
+    assert_eq!(strength[0].wins + strength[0].losses + strength[0].draws, 2);
+    assert!(strength[0].simulations_per_search >= 10.0);
+    assert!(sessions.storage.ai_games(Some("0.0.0"), 10).unwrap().is_empty());
+}
+
+#[test]
+fn test_sessions_rate_each_difficulty_on_its_own() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let hard = Difficulty::Hard.player_name();
+    assert!(sessions.is_bot(hard) && sessions.is_bot(Difficulty::Easy.player_name()));
+    let id = sessions.create_game("Alice".to_string(), hard).unwrap();
+    // Each difficulty searches as it is set up to, whatever the server's own AI does.
+    let simulations = sessions.ai_for(&id).lock().config().simulations;
+    assert_eq!(simulations, Difficulty::Hard.ai_config().simulations);
+    let other = sessions.create_game("Alice".to_string(), AI_PLAYER).unwrap();
+    let simulations = sessions.ai_for(&other).lock().config().simulations;
+    assert_eq!(simulations, sessions.ai_config().simulations);
+    sessions.resign(&id, "Alice").unwrap();
+
+    // The AI keeps its anchor rating under its own name.
+    assert!(sessions.storage.current_rating("Alice").unwrap() < 1200.0);
+    assert_eq!(sessions.storage.current_rating(hard).unwrap(), Difficulty::Hard.anchor_rating());
+    assert!(sessions.storage.get_player(AI_PLAYER).unwrap().is_none());
+    let games = sessions.storage.ai_games(None, 10).unwrap();
+    assert_eq!((games.len(), games[0].opponent.as_str()), (1, "Alice"));
+    assert_eq!(games[0].simulations, Difficulty::Hard.ai_config().simulations);
+}
+
+#[test]
+fn test_sessions_engine_opponents() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    assert!(sessions.engines().is_empty());
+    sessions.add_engine(EngineConfig {
+        name: "Edax".to_string(),
+        command: "edax".to_string(),
+        args: vec!["-nboard".to_string()],
+        depth: 10,
+        processes: 2,
+        move_timeout_secs: 5,
+    });
+    assert_eq!(sessions.engines(), ["Edax"]);
+    assert!(sessions.is_bot("Edax") && sessions.is_bot(AI_PLAYER) && !sessions.is_bot("Alice"));
+
+    let id = sessions.create_game("Alice".to_string(), "Edax").unwrap();
+    assert!(!sessions.is_ai_turn(&id));
+    assert!(sessions.engine_for(&id).is_none());
+    let pos = sessions.get_game(&id).unwrap().legal_moves()[0];
+    sessions.make_move(&id, pos, "Alice").unwrap();
+    assert!(sessions.is_ai_turn(&id));
+    assert_eq!(sessions.engine_for(&id).unwrap().config().depth, 10);
+    assert!(sessions.is_connected(&id, "Edax"));
+    // Games against engines can be deleted any time, like games against the AI.
+    sessions.remove_game(&id, "Alice").unwrap();
+}
+
+/// Black to move with A1, after which White has no legal moves but Black still has F6.
+#[test]
+fn test_sessions_respect_blocklists() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let settings = PlayerSettings {
+        accept_challenges: ChallengePolicy::Friends,
+        friends: vec!["Friendly".to_string()],
+        blocked: vec!["Rude".to_string()],
+    };
+    sessions.storage.save_player_settings("Private", &settings).unwrap();
+    assert!(matches!(
+        sessions.challenge("Stranger".to_string(), "Private"),
+        Err(SessionError::ChallengeRefused)
+    ));
+    assert!(sessions.challenge("Friendly".to_string(), "Private").is_ok());
+
+    // Blocked players are never matched, even when they are first in the queue.
+    assert_eq!(sessions.join_matchmaking("Rude".to_string()).unwrap(), None);
+    assert_eq!(sessions.join_matchmaking("Private".to_string()).unwrap(), None);
+    assert!(sessions.join_matchmaking("Stranger".to_string()).unwrap().is_some());
+    assert!(sessions.join_matchmaking("Friendly".to_string()).unwrap().is_some());
+}
+
+/// A matchmaking queue that holds up every read until the test lets it go, and fails them
+/// while it is broken.
+struct SlowCache {
+    queue: MemoryCache,
+    reading: mpsc::Sender<()>,
+    release: Mutex<mpsc::Receiver<()>>,
+    broken: Arc<AtomicBool>,
+}
+
+impl SessionCache for SlowCache {
+    fn waiting(&self) -> Result<Vec<String>, String> {
+        self.reading.send(()).unwrap();
+        self.release.lock().recv().unwrap();
+        if self.broken.load(Ordering::SeqCst) {
+            return Err("The queue is unreachable".to_string());
+        }
+        self.queue.waiting()
+    }
+
+    fn push_waiting(&self, player: &str) -> Result<(), String> {
+        self.queue.push_waiting(player)
+    }
+
+    fn take_waiting(&self, player: &str) -> Result<bool, String> {
+        self.queue.take_waiting(player)
+    }
+
+    fn add_socket(&self, id: &str, player: Option<&str>) -> Result<usize, String> {
+        self.queue.add_socket(id, player)
+    }
+
+    fn remove_socket(&self, id: &str, player: Option<&str>) -> Result<usize, String> {
+        self.queue.remove_socket(id, player)
+    }
+
+    fn connected(&self, id: &str) -> Result<HashSet<String>, String> {
+        self.queue.connected(id)
+    }
+
+    fn spectators(&self, id: &str) -> Result<usize, String> {
+        self.queue.spectators(id)
+    }
+
+    fn forget(&self, id: &str) -> Result<(), String> {
+        self.queue.forget(id)
+    }
+}
+
+#[test]
+fn test_matchmaking_waits_on_the_queue_without_holding_up_other_sessions() {
+    let (reading_tx, reading) = mpsc::channel();
+    let (release, release_rx) = mpsc::channel();
+    let broken = Arc::new(AtomicBool::new(false));
+    let sessions = Arc::new(Mutex::new(Sessions::new(Storage::new(":memory:").unwrap())));
+    sessions.lock().set_cache(Box::new(SlowCache {
+        queue: MemoryCache::new(),
+        reading: reading_tx,
+        release: Mutex::new(release_rx),
+        broken: broken.clone(),
+    }));
+    let join = |player: &str| {
+        let sessions = sessions.clone();
+        let player = player.to_string();
+        std::thread::spawn(move || Sessions::join_shared_matchmaking(&sessions, player))
+    };
+
+    // Other games go on while the queue is read, whether the read succeeds or fails.
+    for (player, fails) in [("Alice", false), ("Bob", true)] {
+        broken.store(fails, Ordering::SeqCst);
+        let joining = join(player);
+        reading.recv().unwrap();
+        let mut guard = sessions.try_lock_for(Duration::from_secs(5)).expect("The queue read holds the lock");
+        guard.create_game("Carol".to_string(), "Dave").unwrap();
+        drop(guard);
+        release.send(()).unwrap();
+        let joined = joining.join().unwrap();
+        if fails {
+            assert!(matches!(joined, Err(SessionError::Storage(_))));
+        } else {
+            assert_eq!(joined, Ok(None));
+        }
+    }
+
+    broken.store(false, Ordering::SeqCst);
+    release.send(()).unwrap();
+    let id = join("Bob").join().unwrap().unwrap().unwrap();
+    release.send(()).unwrap();
+    assert_eq!(sessions.lock().matchmaking_queue().unwrap(), Vec::<String>::new());
+    assert!(sessions.lock().has_game(&id));
+}
+
+#[tokio::test]
+async fn test_sessions_full_analysis_job() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    assert!(matches!(sessions.start_analysis(&id), Err(SessionError::GameInProgress)));
+    while let Some(&pos) = sessions.get_game(&id).unwrap().legal_moves().first() {
+        let player = match sessions.get_game(&id).unwrap().current_player {
+            Player::Black => "Alice",
+            Player::White => "Bob",
+        };
+        sessions.make_move(&id, pos, player).unwrap();
+    }
+
+    let job_id = sessions.start_analysis(&id).unwrap();
+    assert_eq!(sessions.start_analysis(&id).unwrap(), job_id);
+    assert_eq!(sessions.analysis_report(&id).unwrap(), None);
+
+    let sessions = Arc::new(Mutex::new(sessions));
+    assert!(jobs::run_next(&sessions, &Backoff::default()).await);
+    assert!(!jobs::run_next(&sessions, &Backoff::default()).await);
+    let sessions = sessions.lock();
+    let job = sessions.storage.job(job_id).unwrap().unwrap();
+    assert_eq!((job.status, job.attempts), (JobStatus::Done, 1));
+    assert!(sessions.analysis_job(&id).unwrap().is_none());
+    let report = sessions.analysis_report(&id).unwrap().unwrap();
+    assert_eq!(report.game_id, id);
+    assert_eq!(report.moves.len(), sessions.history(&id).unwrap().len());
+}
+
+#[tokio::test]
+async fn test_sessions_prune_finished_games() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let casual = GameOptions {
+        game_type: GameType::Casual,
+        ..GameOptions::default()
+    };
+    let plain = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    let flagged = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    let tournament = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    let friendly = sessions.challenge_with("Alice".to_string(), "Bob", casual).unwrap();
+    let playing = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    let starred = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    // Aborted games have no result and end with their last move.
+    let aborted = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    let opening = sessions.get_game(&aborted).unwrap().legal_moves()[0];
+    sessions.make_move(&aborted, opening, "Alice").unwrap();
+    sessions.storage.abort_game(&aborted).unwrap();
+    let marked = BTreeMap::from([(TOURNAMENT_METADATA_KEY.to_string(), "Spring open".to_string())]);
+    for id in [&tournament, &friendly] {
+        sessions.storage.save_metadata(id, &marked).unwrap();
+    }
+    for id in [&plain, &flagged, &tournament, &friendly, &starred] {
+        sessions.resign(id, "Bob").unwrap();
+    }
+    // Starred by someone who only watched it.
+    sessions.star_game(&starred, "Carol", true).unwrap();
+    let suspicion = Suspicion {
+        score: 0.9,
+        details: "too accurate".to_string(),
+    };
+    sessions.storage.save_cheat_report(&flagged, "Alice", 0, "suspicious", &suspicion).unwrap();
+
+    // Games ended too recently are kept, and a dry run only lists what it would delete.
+    let sessions = Arc::new(Mutex::new(sessions));
+    assert!(run_retention(&sessions, 30, false).await.is_empty());
+    let mut expired = vec![plain.clone(), friendly.clone(), aborted.clone()];
+    expired.sort();
+    assert_eq!(run_retention(&sessions, 0, true).await, expired);
+    assert!(sessions.lock().get_game(&plain).is_some());
+
+    // Starred games, flagged games, rated tournament games and games still being played are
+    // always kept.
+    assert_eq!(run_retention(&sessions, 0, false).await, expired);
+    let sessions = sessions.lock();
+    for id in &expired {
+        assert!(sessions.get_game(id).is_none() && sessions.storage.load_game(id).unwrap().is_none());
+    }
+    for id in [&flagged, &tournament, &playing, &starred] {
+        assert!(sessions.get_game(id).is_some());
+    }
+    // Results outlive their games.
+    assert_eq!(sessions.storage.get_player("Alice").unwrap().unwrap().wins, 4);
+    let runs = sessions.storage.retention_runs(10).unwrap();
+    let runs: Vec<_> = runs.iter().map(|run| (run.keep_days, run.dry_run, run.games.len())).collect();
+    assert_eq!(runs, [(0, false, 3), (0, true, 3), (30, false, 0)]);
+}
+
+/// Runs the retention policy through the job queue, as the server does, returning the games
+/// the run deleted or listed.
+async fn run_retention(sessions: &Arc<Mutex<Sessions>>, keep_days: u32, dry_run: bool) -> Vec<String> {
+    let job_id = sessions.lock().enqueue_job(&Task::Prune { keep_days, dry_run }).unwrap();
+    assert!(jobs::run_next(sessions, &Backoff::default()).await);
+    let sessions = sessions.lock();
+    assert_eq!(sessions.storage.job(job_id).unwrap().unwrap().status, JobStatus::Done);
+    let mut games = sessions.storage.retention_runs(1).unwrap().remove(0).games;
+    games.sort();
+    games
+}
+
+#[test]
+fn test_sessions_star_games() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let first = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    let second = sessions.create_game("Carol".to_string(), AI_PLAYER).unwrap();
+    sessions.resign(&first, "Bob").unwrap();
+    assert!(matches!(sessions.star_game("game_99", "Alice", true), Err(SessionError::GameNotFound)));
+
+    assert!(sessions.star_game(&first, "Alice", true).unwrap());
+    assert!(!sessions.star_game(&first, "Alice", true).unwrap());
+    // Anyone may star a game they watch.
+    assert!(sessions.star_game(&second, "Alice", true).unwrap());
+    let starred = sessions.storage.starred_games("Alice").unwrap();
+    let ids: Vec<_> = starred.iter().map(|game| game.game_id.as_str()).collect();
+    assert_eq!(ids, [second.as_str(), first.as_str()]);
+    assert_eq!((starred[1].finished, starred[1].winner), (true, Some(Player::Black)));
+    assert_eq!((starred[0].finished, starred[0].winner), (false, None));
+    assert!(sessions.storage.starred_games("Bob").unwrap().is_empty());
+
+    assert!(sessions.star_game(&second, "Alice", false).unwrap());
+    assert!(!sessions.star_game(&second, "Alice", false).unwrap());
+    // Deleting a game takes it off everyone's stars.
+    sessions.remove_game(&first, "Alice").unwrap();
+    assert!(sessions.storage.starred_games("Alice").unwrap().is_empty());
+}
+
+/// Flags every move it sees.
+struct Suspicious;
+
+impl MoveAnalyzer for Suspicious {
+    fn name(&self) -> &'static str {
+        "suspicious"
+    }
+
+    fn analyze(&self, mv: &SubmittedMove) -> Result<Option<Suspicion>, String> {
+        Ok(Some(Suspicion {
+            score: 0.5,
+            details: format!("move {} of {}", mv.ply, mv.game_id),
+        }))
+    }
+}
+
+#[tokio::test]
+async fn test_sessions_cheat_checks() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    sessions.set_move_analyzers(vec![Arc::new(Suspicious)]);
+    let id = sessions.create_game("Alice".to_string(), AI_PLAYER).unwrap();
+    for player in ["Alice", AI_PLAYER, "Alice"] {
+        let pos = sessions.get_game(&id).unwrap().legal_moves()[0];
+        sessions.make_move(&id, pos, player).unwrap();
+    }
+
+    // Only the person's moves are checked, each in its own job.
+    let sessions = Arc::new(Mutex::new(sessions));
+    for _ in 0..2 {
+        assert!(jobs::run_next(&sessions, &Backoff::default()).await);
+    }
+    assert!(!jobs::run_next(&sessions, &Backoff::default()).await);
+    let sessions = sessions.lock();
+    let jobs = sessions.storage.jobs(Some(JobStatus::Done), 10).unwrap();
+    assert!(jobs.iter().all(|job| job.task.kind() == "cheat_check"));
+
+    // The analyzer keeps one report per player and game, raised by the latest move.
+    let reports = sessions.storage.cheat_reports(None, 10).unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!((reports[0].player.as_str(), reports[0].ply), ("Alice", 2));
+    assert_eq!(reports[0].analyzer, "suspicious");
+    assert_eq!(reports[0].details, format!("move 2 of {id}"));
+    assert!(sessions.storage.cheat_reports(Some(AI_PLAYER), 10).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_sessions_coach_the_moves_of_people() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let coached = GameOptions {
+        coach: true,
+        ..GameOptions::default()
+    };
+    // Coaching a ranked game against a person would be engine assistance.
+    assert_eq!(
+        sessions.create_game_with("Alice".to_string(), "Bob", coached),
+        Err(SessionError::CoachNotAllowed)
+    );
+    let casual = GameOptions {
+        game_type: GameType::Casual,
+        ..coached
+    };
+    let casual = sessions.create_game_with("Alice".to_string(), "Bob", casual).unwrap();
+    assert!(sessions.is_coached(&casual));
+    let id = sessions.create_game_with("Alice".to_string(), AI_PLAYER, coached).unwrap();
+    assert!(sessions.is_coached(&id) && sessions.storage.game_options(&id).unwrap().coach);
+    let mut events = sessions.subscribe(&id);
+    let mut played = Vec::new();
+    for player in ["Alice", AI_PLAYER, "Alice"] {
+        let pos = sessions.get_game(&id).unwrap().legal_moves()[0];
+        sessions.make_move(&id, pos, player).unwrap();
+        played.push(Game::pos_to_coord(pos));
+    }
+    while events.try_recv().is_ok() {}
+
+    // Each of the person's moves is assessed in its own job and told to them alone.
+    let sessions = Arc::new(Mutex::new(sessions));
+    for _ in 0..2 {
+        assert!(jobs::run_next(&sessions, &Backoff::default()).await);
+    }
+    assert!(!jobs::run_next(&sessions, &Backoff::default()).await);
+    for ply in [0, 2] {
+        let GameEvent::Coach { player, analysis } = events.try_recv().unwrap() else {
+            panic!("expected a coach event");
+        };
+        assert_eq!((player.as_str(), analysis.ply), ("Alice", ply));
+        assert_eq!(analysis.played, played[ply]);
+        assert!(analysis.loss >= 0);
+    }
+    assert!(events.try_recv().is_err());
+}
+
+#[test]
+fn test_storage_job_retries() {
+    let storage = Storage::new(":memory:").unwrap();
+    let task = Task::Analysis {
+        game_id: "game_1".to_string(),
+    };
+    let id = storage.enqueue_job(&task, 2, 1000).unwrap();
+    assert_eq!(storage.claim_job(999).unwrap(), None);
+    assert_eq!(storage.active_job(&task).unwrap().unwrap().id, id);
+
+    let job = storage.claim_job(1000).unwrap().unwrap();
+    assert_eq!((job.id, job.status, job.attempts), (id, JobStatus::Running, 1));
+    assert_eq!(storage.claim_job(1000).unwrap(), None);
+    storage.fail_job(id, "boom", Some(5000)).unwrap();
+    assert_eq!(storage.claim_job(4999).unwrap(), None);
+    let job = storage.claim_job(5000).unwrap().unwrap();
+    assert_eq!((job.attempts, job.last_error.as_deref()), (2, Some("boom")));
+
+    // A restart puts a running job with attempts left back in the queue, and fails one
+    // interrupted in its last attempt.
+    let other = Task::Analysis {
+        game_id: "game_2".to_string(),
+    };
+    let other_id = storage.enqueue_job(&other, 2, 1000).unwrap();
+    assert_eq!(storage.claim_job(5000).unwrap().unwrap().id, other_id);
+    assert_eq!(storage.requeue_running_jobs().unwrap(), 1);
+    let job = storage.job(id).unwrap().unwrap();
+    assert_eq!((job.status, job.attempts), (JobStatus::Failed, 2));
+    assert!(job.last_error.as_deref().unwrap().contains("last attempt"));
+    assert_eq!(storage.active_job(&task).unwrap(), None);
+    assert_eq!(storage.jobs(Some(JobStatus::Failed), 10).unwrap(), [job]);
+
+    let requeued = storage.claim_job(5000).unwrap().unwrap();
+    assert_eq!((requeued.id, requeued.attempts), (other_id, 2));
+    storage.fail_job(other_id, "boom again", None).unwrap();
+    assert_eq!(storage.job(other_id).unwrap().unwrap().status, JobStatus::Failed);
+    assert!(storage.jobs(Some(JobStatus::Queued), 10).unwrap().is_empty());
+}
+
+#[test]
+fn test_sessions_last_move_and_flipped() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    let mut events = sessions.subscribe(&id);
+    assert_eq!(sessions.last_move(&id), None);
+
+    let d3 = Game::coord_to_pos("D3").unwrap();
+    sessions.make_move(&id, d3, "Alice").unwrap();
+    let d4 = 1u64 << Game::coord_to_pos("D4").unwrap();
+    assert_eq!(sessions.last_move(&id), Some((Move::Place(d3), d4)));
+    let GameEvent::Delta(delta) = events.try_recv().unwrap() else {
+        panic!("expected a delta");
+    };
+    assert_eq!(delta.action, "D3");
+    assert_eq!(delta.flipped, vec!["D4".to_string()]);
+    assert_eq!(delta.flip_lines.len(), 1);
+    assert_eq!((delta.flip_lines[0].direction.as_str(), &delta.flip_lines[0].discs), ("N", &delta.flipped));
+
+    // Resigning is not a move, so the last one stays.
+    sessions.resign(&id, "Bob").unwrap();
+    let GameEvent::Delta(delta) = events.try_recv().unwrap() else {
+        panic!("expected a delta");
+    };
+    assert!(delta.flipped.is_empty() && delta.flip_lines.is_empty());
+    assert_eq!(sessions.last_move(&id), Some((Move::Place(d3), d4)));
+}
+
+#[test]
+fn test_sessions_resign_and_chat() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    let mut events = sessions.subscribe(&id);
+
+    sessions.chat(&id, "Carol", " hello ").unwrap();
+    assert_eq!(
+        events.try_recv().unwrap(),
+        GameEvent::Chat {
+            from: "Carol".to_string(),
+            text: "hello".to_string()
+        }
+    );
+    assert!(matches!(sessions.chat(&id, "Carol", "   "), Err(SessionError::EmptyChat)));
+    let long = "a".repeat(MAX_CHAT_LEN + 1);
+    assert!(matches!(sessions.chat(&id, "Carol", &long), Err(SessionError::ChatTooLong)));
+
+    assert!(matches!(sessions.resign(&id, "Carol"), Err(SessionError::NotAParticipant)));
+    assert_eq!(sessions.resign(&id, "Bob").unwrap(), Player::White);
+    let GameEvent::Delta(delta) = events.try_recv().unwrap() else {
+        panic!("expected a delta");
+    };
+    assert_eq!((delta.player.as_str(), delta.action.as_str()), ("White", "resign"));
+    assert!(sessions.is_over(&id));
+    assert_eq!(sessions.winner(&id), Some(Player::Black));
+    assert!(matches!(sessions.resign(&id, "Alice"), Err(SessionError::GameOver)));
+    let pos = sessions.get_game(&id).unwrap().legal_moves()[0];
+    assert!(sessions.make_move(&id, pos, "Alice").is_err());
+    assert_eq!(sessions.storage.resignations().unwrap().get(&id), Some(&Player::White));
+}
+
+#[test]
+fn test_sessions_presence() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    assert!(sessions.join("no_such_game", None).is_err());
+    let mut events = sessions.subscribe(&id);
+    let _alice = sessions.join(&id, Some("Alice")).unwrap();
+    let joined = |name: &str, spectator| Watcher {
+        name: Some(name.to_string()),
+        spectator,
+    };
+    assert_eq!(events.try_recv().unwrap(), GameEvent::Joined(joined("Alice", false)));
+    assert!(sessions.is_connected(&id, "Alice"));
+    assert!(!sessions.is_connected(&id, "Bob"));
+
+    // A second tab is not announced, and closing it leaves the player connected.
+    let _tab = sessions.join(&id, Some("Alice")).unwrap();
+    sessions.leave(&id, Some("Alice"));
+    assert!(sessions.is_connected(&id, "Alice"));
+    assert!(events.try_recv().is_err());
+
+    let _carol = sessions.join(&id, Some("Carol")).unwrap();
+    let _anonymous = sessions.join(&id, None).unwrap();
+    assert_eq!(sessions.spectators(&id), 2);
+    assert_eq!(events.try_recv().unwrap(), GameEvent::Joined(joined("Carol", true)));
+    assert_eq!(
+        events.try_recv().unwrap(),
+        GameEvent::Joined(Watcher {
+            name: None,
+            spectator: true
+        })
+    );
+    sessions.leave(&id, Some("Carol"));
+    assert_eq!(sessions.spectators(&id), 1);
+    assert_eq!(events.try_recv().unwrap(), GameEvent::Left(joined("Carol", true)));
+    sessions.leave(&id, Some("Alice"));
+    assert!(!sessions.is_connected(&id, "Alice"));
+    assert_eq!(events.try_recv().unwrap(), GameEvent::Left(joined("Alice", false)));
+
+    let ai_game = sessions.create_game("Alice".to_string(), "AI").unwrap();
+    assert!(sessions.is_connected(&ai_game, "AI"));
+}
+
+#[test]
+fn test_sessions_admin_introspection() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let human = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    let ai_game = sessions.create_game("Carol".to_string(), AI_PLAYER).unwrap();
+    let resigned = sessions.create_game("Dave".to_string(), "Erin").unwrap();
+    sessions.resign(&resigned, "Dave").unwrap();
+    let active = sessions.active_games();
+    assert!(active.contains(&human.as_str()) && active.contains(&ai_game.as_str()));
+    assert!(!active.contains(&resigned.as_str()));
+    assert!(active.is_sorted());
+
+    assert_eq!(sessions.ai_activity(&ai_game), None);
+    let ai = sessions.ai_for(&ai_game);
+    assert_eq!(sessions.ai_activity(&ai_game), Some(AiActivity::Idle));
+    let searching = ai.lock();
+    assert_eq!(sessions.ai_activity(&ai_game), Some(AiActivity::Searching));
+    drop(searching);
+    assert_eq!(sessions.version(&human), None);
+
+    assert_eq!(sessions.join_matchmaking("Frank".to_string()).unwrap(), None);
+    assert_eq!(sessions.matchmaking_queue().unwrap(), vec!["Frank".to_string()]);
+
+    let mut alice = sessions.join(&human, Some("Alice")).unwrap();
+    let mut anonymous = sessions.join(&ai_game, None).unwrap();
+    let _tab = sessions.join(&ai_game, Some("Carol")).unwrap();
+    assert_eq!(sessions.open_sockets(), 3);
+    sessions.leave(&ai_game, Some("Carol"));
+    assert_eq!(sessions.open_sockets(), 2);
+    while alice.try_recv().is_ok() {}
+    while anonymous.try_recv().is_ok() {}
+
+    let notice = Notice::new(MessageCode::Maintenance).with("message", "Restarting at noon");
+    assert_eq!(sessions.broadcast(&notice), 2);
+    assert_eq!(alice.try_recv().unwrap(), GameEvent::Notice(notice.clone()));
+    assert_eq!(anonymous.try_recv().unwrap(), GameEvent::Notice(notice.clone()));
+
+    // Notices from other servers reach every socket too.
+    sessions.apply_update(GameUpdate {
+        origin: "another server".to_string(),
+        game_id: String::new(),
+        change: Change::Broadcast(notice.clone()),
+    });
+    assert_eq!(alice.try_recv().unwrap(), GameEvent::Notice(notice));
+}
+
+#[test]
+fn test_sessions_maintenance_drains_games() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    let mut alice = sessions.join(&id, Some("Alice")).unwrap();
+    assert!(!sessions.is_drained());
+
+    sessions.start_maintenance(None, true);
+    assert_eq!(alice.try_recv().unwrap(), GameEvent::Notice(Notice::new(MessageCode::MaintenanceStarted)));
+    assert_eq!(
+        sessions.create_game("Carol".to_string(), "Dave"),
+        Err(SessionError::UnderMaintenance)
+    );
+    assert_eq!(sessions.join_matchmaking("Carol".to_string()), Err(SessionError::UnderMaintenance));
+    assert_eq!(sessions.live_games(), vec![id.as_str()]);
+    assert!(!sessions.is_drained());
+
+    // A deadline stops the game holding up the restart.
+    sessions.start_maintenance(Some(Duration::ZERO), true);
+    let GameEvent::Notice(notice) = alice.try_recv().unwrap() else {
+        panic!("expected a notice");
+    };
+    assert_eq!(notice.code, MessageCode::MaintenanceDeadline);
+    assert_eq!(notice.params["seconds"], "0");
+    assert!(sessions.is_drained());
+
+    sessions.close_sockets();
+    assert_eq!(alice.try_recv().unwrap(), GameEvent::Notice(Notice::new(MessageCode::Restarting)));
+    assert!(alice.try_recv().is_err());
+    // Leaving as the server stops does not lose the game.
+    assert_eq!(sessions.leave(&id, Some("Alice")), None);
+    assert!(sessions.live_games().is_empty());
+
+    sessions.end_maintenance();
+    assert_eq!(sessions.maintenance(), None);
+    assert!(!sessions.is_drained());
+    assert!(sessions.create_game("Carol".to_string(), "Dave").is_ok());
+}
+
+#[test]
+fn test_sessions_remove_game() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    sessions.set_admins(["Admin".to_string()].into_iter().collect());
+
+    // A game against another person cannot be deleted by its players while it is played.
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    let pos = sessions.get_game(&id).unwrap().legal_moves()[0];
+    sessions.make_move(&id, pos, "Alice").unwrap();
+    let mut watcher = sessions.join(&id, None).unwrap();
+    assert_eq!(sessions.remove_game(&id, "Mallory"), Err(SessionError::NotAParticipant));
+    assert_eq!(sessions.remove_game(&id, "Alice"), Err(SessionError::GameInProgress));
+    sessions.resign(&id, "Bob").unwrap();
+    sessions.remove_game(&id, "Alice").unwrap();
+    assert!(sessions.get_game(&id).is_none());
+    assert!(sessions.storage.load_game(&id).unwrap().is_none());
+    assert!(sessions.storage.load_moves(&id).unwrap().is_empty());
+    assert!(sessions.storage.resignations().unwrap().is_empty());
+    assert_eq!(sessions.remove_game(&id, "Alice"), Err(SessionError::GameNotFound));
+    // Sockets on the game see their event stream end.
+    while let Ok(_event) = watcher.try_recv() {}
+    assert!(matches!(watcher.try_recv(), Err(tokio::sync::broadcast::error::TryRecvError::Closed)));
+    // The rated result stays.
+    assert_eq!(sessions.storage.head_to_head("Alice", "Bob").unwrap().wins, 1);
+
+    // Admins can delete any game, and games against the AI can be deleted any time.
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    sessions.remove_game(&id, "Admin").unwrap();
+    let id = sessions.create_game("Alice".to_string(), "AI").unwrap();
+    sessions.remove_game(&id, "Alice").unwrap();
+    assert!(sessions.storage.load_all_games().unwrap().0.is_empty());
+}
+
+#[test]
+fn test_sessions_abandoned_games() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    sessions.set_disconnect_grace(Some(Duration::ZERO));
+
+    // Leaving in the opening aborts the game.
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    let mut events = sessions.subscribe(&id);
+    let _bob = sessions.join(&id, Some("Bob")).unwrap();
+    assert_eq!(sessions.leave(&id, Some("Bob")), Some(Duration::ZERO));
+    let countdowns = sessions.countdowns(&id);
+    assert_eq!((countdowns[0].player.as_str(), countdowns[0].abort), ("Bob", true));
+    assert!(matches!(events.try_recv().unwrap(), GameEvent::Joined(_)));
+    assert!(matches!(events.try_recv().unwrap(), GameEvent::Left(_)));
+    assert_eq!(events.try_recv().unwrap(), GameEvent::Countdown(countdowns[0].clone()));
+    assert_eq!(sessions.expire_disconnection(&id, "Bob").unwrap(), Some(Abandonment::Aborted));
+    assert!(sessions.is_over(&id) && sessions.is_aborted(&id));
+    assert_eq!(sessions.winner(&id), None);
+    assert!(sessions.storage.aborted_games().unwrap().contains(&id));
+    assert!(sessions.countdowns(&id).is_empty());
+
+    // Later on, the player forfeits, unless they came back in time.
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    for player in ["Alice", "Bob"] {
+        let pos = sessions.get_game(&id).unwrap().legal_moves()[0];
+        sessions.make_move(&id, pos, player).unwrap();
+    }
+    let _bob = sessions.join(&id, Some("Bob")).unwrap();
+    sessions.leave(&id, Some("Bob"));
+    let _bob = sessions.join(&id, Some("Bob")).unwrap();
+    assert_eq!(sessions.expire_disconnection(&id, "Bob").unwrap(), None);
+    assert!(!sessions.is_over(&id));
+    sessions.leave(&id, Some("Bob"));
+    assert!(!sessions.countdowns(&id)[0].abort);
+    assert_eq!(
+        sessions.expire_disconnection(&id, "Bob").unwrap(),
+        Some(Abandonment::Forfeited(Player::White))
+    );
+    assert_eq!(sessions.winner(&id), Some(Player::Black));
+
+    // Games against the AI, and spectators, are never abandoned.
+    let ai_game = sessions.create_game("Alice".to_string(), "AI").unwrap();
+    let _alice = sessions.join(&ai_game, Some("Alice")).unwrap();
+    assert_eq!(sessions.leave(&ai_game, Some("Alice")), None);
+    let _carol = sessions.join(&id, Some("Carol")).unwrap();
+    assert_eq!(sessions.leave(&id, Some("Carol")), None);
+}
+
+#[test]
+fn test_sessions_measure_time_with_their_clock() {
+    let clock = ManualClock::new();
+    let mut sessions = Sessions::with_clock(Storage::new(":memory:").unwrap(), Arc::new(clock.clone()));
+    sessions.set_disconnect_grace(Some(Duration::from_secs(30)));
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    clock.advance(Duration::from_millis(1500));
+    let pos = sessions.get_game(&id).unwrap().legal_moves()[0];
+    sessions.make_move(&id, pos, "Alice").unwrap();
+    let records = sessions.storage.load_move_records(&id).unwrap();
+    assert_eq!(records[0].elapsed_ms, Some(1500));
+
+    // The player only runs out of time once the clock passes the grace period.
+    let _bob = sessions.join(&id, Some("Bob")).unwrap();
+    sessions.leave(&id, Some("Bob"));
+    clock.advance(Duration::from_secs(29));
+    assert_eq!(sessions.expire_disconnection(&id, "Bob").unwrap(), None);
+    assert_eq!(sessions.countdowns(&id)[0].seconds, 1);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(sessions.expire_disconnection(&id, "Bob").unwrap(), Some(Abandonment::Aborted));
+
+    // A game being played holds up a restart until the deadline.
+    let id = sessions.create_game("Alice".to_string(), "Carol").unwrap();
+    let _alice = sessions.join(&id, Some("Alice")).unwrap();
+    sessions.start_maintenance(Some(Duration::from_secs(60)), false);
+    assert!(!sessions.is_drained());
+    clock.advance(Duration::from_secs(45));
+    assert_eq!(sessions.maintenance_time_left(), Some(Duration::from_secs(15)));
+    clock.advance(Duration::from_secs(15));
+    assert!(sessions.is_drained());
+    assert_eq!(sessions.maintenance_time_left(), Some(Duration::ZERO));
+}
+
+#[test]
+fn test_sessions_refuse_tickets_expired_by_their_clock() {
+    let clock = ManualClock::new();
+    let mut sessions = Sessions::with_clock(Storage::new(":memory:").unwrap(), Arc::new(clock.clone()));
+    let spent = Auth::generate_ticket("Alice").unwrap();
+    assert_eq!(sessions.redeem_ticket(&spent), Some("Alice".to_string()));
+    assert_eq!(sessions.redeem_ticket(&spent), None);
+
+    let ticket = Auth::generate_ticket("Alice").unwrap();
+    clock.advance(Duration::from_secs(Auth::TICKET_LIFETIME + 1));
+    assert_eq!(sessions.redeem_ticket(&ticket), None);
+}
+
+#[test]
+fn test_sessions_let_players_claim_games_from_opponents_out_of_time() {
+    let clock = ManualClock::new();
+    let mut sessions = Sessions::with_clock(Storage::new(":memory:").unwrap(), Arc::new(clock.clone()));
+    sessions.set_disconnect_grace(Some(Duration::from_secs(30)));
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    for player in ["Alice", "Bob"] {
+        let pos = sessions.get_game(&id).unwrap().legal_moves()[0];
+        sessions.make_move(&id, pos, player).unwrap();
+    }
+
+    // Nothing to claim while the opponent is connected or within the grace period.
+    let _bob = sessions.join(&id, Some("Bob")).unwrap();
+    assert_eq!(sessions.claim_win(&id, "Alice"), Err(SessionError::NothingToClaim));
+    sessions.leave(&id, Some("Bob"));
+    clock.advance(Duration::from_secs(29));
+    assert_eq!(sessions.claim_win(&id, "Alice"), Err(SessionError::NothingToClaim));
+    assert!(!sessions.is_over(&id));
+
+    // Once the deadline passes, only a player of the game can claim it, and only once.
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(sessions.claim_win(&id, "Carol"), Err(SessionError::NotAParticipant));
+    assert_eq!(sessions.claim_win(&id, "Alice"), Ok(Abandonment::Forfeited(Player::White)));
+    assert_eq!(sessions.winner(&id), Some(Player::Black));
+    assert_eq!(sessions.claim_win(&id, "Alice"), Err(SessionError::GameOver));
+    assert_eq!(sessions.expire_disconnection(&id, "Bob").unwrap(), None);
+    assert_eq!(sessions.claim_win("missing", "Alice"), Err(SessionError::GameNotFound));
+}
+
+#[test]
+fn test_sessions_let_players_claim_draws_of_inactive_games() {
+    let clock = ManualClock::new();
+    let mut sessions = Sessions::with_clock(Storage::new(":memory:").unwrap(), Arc::new(clock.clone()));
+    sessions.set_inactivity_draw(Some(Duration::from_secs(3600)));
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    let pos = sessions.get_game(&id).unwrap().legal_moves()[0];
+    sessions.make_move(&id, pos, "Alice").unwrap();
+
+    // Bob, to move, cannot stall the game into a draw, and Alice has to wait out the period.
+    let still_active = Err(SessionError::StillActive(3600));
+    assert_eq!(sessions.claim_draw(&id, "Bob"), still_active);
+    clock.advance(Duration::from_secs(3599));
+    assert_eq!(sessions.claim_draw(&id, "Alice"), still_active);
+    assert_eq!(SessionError::StillActive(3600).notice().params["seconds"], "3600");
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(sessions.claim_draw(&id, "Bob"), still_active);
+    assert_eq!(sessions.claim_draw(&id, "Carol"), Err(SessionError::NotAParticipant));
+
+    sessions.claim_draw(&id, "Alice").unwrap();
+    assert!(sessions.is_over(&id) && sessions.is_drawn(&id));
+    assert_eq!(sessions.winner(&id), None);
+    assert!(sessions.storage.is_drawn(&id).unwrap());
+    assert_eq!(sessions.storage.head_to_head("Alice", "Bob").unwrap().draws, 1);
+    assert_eq!(sessions.claim_draw(&id, "Alice"), Err(SessionError::GameOver));
+    assert_eq!(sessions.claim_draw("missing", "Alice"), Err(SessionError::GameNotFound));
+
+    // Games against the AI, and servers that never allow it, have no draws to claim.
+    let ai_game = sessions.create_game("Alice".to_string(), "AI").unwrap();
+    clock.advance(Duration::from_secs(3600));
+    assert_eq!(sessions.claim_draw(&ai_game, "Alice"), Err(SessionError::NothingToClaim));
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    sessions.set_inactivity_draw(None);
+    clock.advance(Duration::from_secs(3600));
+    assert_eq!(sessions.claim_draw(&id, "Bob"), Err(SessionError::NothingToClaim));
+}
+
+#[test]
+fn test_sessions_cache_the_leaderboard() {
+    let clock = ManualClock::new();
+    let mut sessions = Sessions::with_clock(Storage::new(":memory:").unwrap(), Arc::new(clock.clone()));
+    assert!(sessions.leaderboard().unwrap().is_empty());
+
+    // Changes made behind the sessions' back show once the cached leaderboard is too old.
+    let version = sessions.leaderboard_version();
+    sessions.storage.update_player("Alice", "Bob", true).unwrap();
+    assert!(sessions.leaderboard().unwrap().is_empty());
+    clock.advance(LEADERBOARD_TTL);
+    let leaderboard = sessions.leaderboard().unwrap();
+    assert_eq!(leaderboard.len(), 2);
+    assert!(Arc::ptr_eq(&leaderboard, &sessions.leaderboard().unwrap()));
+    assert_ne!(sessions.leaderboard_version(), version);
+
+    // Reading the same leaderboard again keeps its version.
+    let version = sessions.leaderboard_version();
+    clock.advance(LEADERBOARD_TTL);
+    sessions.leaderboard().unwrap();
+    assert_eq!(sessions.leaderboard_version(), version);
+
+    // A game ending here shows at once.
+    let id = sessions.create_game("Carol".to_string(), "Dave").unwrap();
+    sessions.resign(&id, "Dave").unwrap();
+    let leaderboard = sessions.leaderboard().unwrap();
+    let carol = leaderboard.iter().find(|stats| stats.name == "Carol").unwrap();
+    assert_eq!((carol.wins, carol.losses), (1, 0));
+    assert_ne!(sessions.leaderboard_version(), version);
+}
+
+#[test]
+fn test_sessions_game_metadata() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    assert!(sessions.metadata(&id).unwrap().is_empty());
+    let update = |entries: &[(&str, Option<&str>)]| -> BTreeMap<String, Option<String>> {
+        entries
+            .iter()
+            .map(|(key, value)| ((*key).to_string(), value.map(str::to_string)))
+            .collect()
+    };
+
+    let metadata = sessions
+        .update_metadata(&id, "Alice", &update(&[("theme", Some("wood")), ("round", Some("3"))]))
+        .unwrap();
+    assert_eq!(metadata.len(), 2);
+    let metadata = sessions.update_metadata(&id, "Bob", &update(&[("round", None)])).unwrap();
+    assert_eq!(metadata.into_iter().collect::<Vec<_>>(), [("theme".to_string(), "wood".to_string())]);
+    assert_eq!(sessions.metadata(&id).unwrap()["theme"], "wood");
+
+    assert!(matches!(
+        sessions.update_metadata(&id, "Carol", &update(&[("theme", Some("glass"))])),
+        Err(SessionError::NotAParticipant)
+    ));
+    let long = "x".repeat(MAX_METADATA_VALUE_LEN + 1);
+    assert!(matches!(
+        sessions.update_metadata(&id, "Alice", &update(&[("theme", Some(long.as_str()))])),
+        Err(SessionError::MetadataValueTooLong(key)) if key == "theme"
+    ));
+    assert!(matches!(
+        sessions.update_metadata(&id, "Alice", &update(&[("bad key", Some("1"))])),
+        Err(SessionError::InvalidMetadataKey)
+    ));
+    let keys: Vec<String> = (0..=MAX_METADATA_ENTRIES).map(|i| format!("key{i}")).collect();
+    let too_many: BTreeMap<_, _> = keys.iter().map(|key| (key.clone(), Some("1".to_string()))).collect();
+    assert!(matches!(
+        sessions.update_metadata(&id, "Alice", &too_many),
+        Err(SessionError::TooManyMetadataEntries)
+    ));
+    // Rejected updates change nothing.
+    assert_eq!(sessions.metadata(&id).unwrap().len(), 1);
+    assert!(matches!(sessions.metadata("missing"), Err(SessionError::GameNotFound)));
+}
+
+fn forced_pass_position() -> Game {
+    let mut game = Game::new();
+    game.black = (1u64 << Game::coord_to_pos("A3").unwrap()) | (1u64 << Game::coord_to_pos("H8").unwrap());
+    game.white = (1u64 << Game::coord_to_pos("A2").unwrap()) | (1u64 << Game::coord_to_pos("G7").unwrap());
+    game
+}
+
+#[test]
+fn test_sessions_komi_decides_winner_and_ratings() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let play_out = |sessions: &mut Sessions, id: &str| {
+        let (black, white) = sessions.get_players(id).unwrap().clone();
+        while let Some(&pos) = sessions.get_game(id).unwrap().legal_moves().first() {
+            let player = match sessions.get_game(id).unwrap().current_player {
+                Player::Black => &black,
+                Player::White => &white,
+            };
+            sessions.make_move(id, pos, player).unwrap();
+        }
+    };
+    let plain = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    assert_eq!(sessions.komi(&plain), 0);
+    play_out(&mut sessions, &plain);
+    let (black, white) = sessions.get_game(&plain).unwrap().final_scores(sessions.scoring_rule());
+    let margin = i32::try_from(black).unwrap() - i32::try_from(white).unwrap();
+    assert!(margin.abs() < MAX_KOMI);
+
+    // The same game with just enough komi for the other side to win.
+    let komi = if margin >= 0 { margin + 1 } else { margin - 1 };
+    let handicapped = sessions.create_game_with_komi("Carol".to_string(), "Dave", komi).unwrap();
+    play_out(&mut sessions, &handicapped);
+    let expected = if komi > 0 { Player::White } else { Player::Black };
+    assert_eq!(sessions.winner(&handicapped), Some(expected));
+    assert_ne!(sessions.winner(&plain), Some(expected));
+    let dave = sessions.storage.get_player("Dave").unwrap().unwrap();
+    let carol = sessions.storage.get_player("Carol").unwrap().unwrap();
+    assert_eq!(dave.elo > carol.elo, komi > 0);
+
+    // Komi is stored with the game and bounded.
+    let id = sessions.challenge_with_komi("Erin".to_string(), "Frank", -3).unwrap();
+    assert_eq!(sessions.storage.komi(&id).unwrap(), -3);
+    assert_eq!(sessions.storage.komis().unwrap().get(&id), Some(&-3));
+    assert_eq!(
+        sessions.create_game_with_komi("Erin".to_string(), "Frank", MAX_KOMI + 1),
+        Err(SessionError::InvalidKomi)
+    );
+    assert_eq!(sessions.set_default_komi(-MAX_KOMI - 1), Err(SessionError::InvalidKomi));
+    sessions.set_default_komi(2).unwrap();
+    let id = sessions.join_matchmaking("Grace".to_string()).unwrap();
+    assert!(id.is_none());
+    let id = sessions.join_matchmaking("Heidi".to_string()).unwrap().unwrap();
+    assert_eq!(sessions.komi(&id), 2);
+}
+
+#[test]
+fn test_sessions_casual_games_change_no_ratings() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let ranked = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    assert_eq!(sessions.game_type(&ranked), GameType::Ranked);
+    sessions.resign(&ranked, "Bob").unwrap();
+    let alice = sessions.storage.get_player("Alice").unwrap().unwrap();
+    let leaderboard = sessions.leaderboard().unwrap();
+
+    let options = GameOptions {
+        game_type: GameType::Casual,
+        ..GameOptions::default()
+    };
+    let casual = sessions.challenge_with("Alice".to_string(), "Bob", options).unwrap();
+    assert_eq!(sessions.game_type(&casual), GameType::Casual);
+    assert_eq!(sessions.storage.casual_games().unwrap().len(), 1);
+    let pos = sessions.get_game(&casual).unwrap().legal_moves()[0];
+    sessions.make_move(&casual, pos, "Alice").unwrap();
+    sessions.resign(&casual, "Alice").unwrap();
+    assert_eq!(sessions.winner(&casual), Some(Player::White));
+
+    // The game is archived, with its own record, but ratings and statistics stay put.
+    assert_eq!(sessions.storage.load_moves(&casual).unwrap().len(), 1);
+    assert_eq!(sessions.storage.game_type(&casual).unwrap(), GameType::Casual);
+    let profile = sessions.storage.get_player("Alice").unwrap().unwrap();
+    assert_eq!((profile.elo, profile.wins, profile.losses), (alice.elo, 1, 0));
+    assert_eq!(profile.current_streak, 1);
+    assert_eq!(profile.casual, Some(Record { wins: 0, losses: 1, draws: 0 }));
+    let bob = sessions.storage.get_player("Bob").unwrap().unwrap();
+    assert_eq!(bob.casual, Some(Record { wins: 1, losses: 0, draws: 0 }));
+    assert_eq!(sessions.storage.rating_history("Alice").unwrap().len(), 1);
+    assert_eq!(sessions.leaderboard().unwrap(), leaderboard);
+    assert!(sessions.storage.casual_games().unwrap().is_empty());
+}
+
+#[test]
+fn test_sessions_auto_pass_modes() {
+    let a1 = Game::coord_to_pos("A1").unwrap();
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    sessions.set_auto_pass(true);
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    *sessions.get_game_mut(&id).unwrap() = forced_pass_position();
+    assert_eq!(sessions.make_move(&id, a1, "Alice").unwrap(), vec![Player::White]);
+    assert_eq!(sessions.get_game(&id).unwrap().current_player, Player::Black);
+    assert_eq!(sessions.forced_passes(&id), &[Player::White]);
+
+    sessions.set_auto_pass(false);
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    *sessions.get_game_mut(&id).unwrap() = forced_pass_position();
+    assert!(sessions.make_move(&id, a1, "Alice").unwrap().is_empty());
+    assert_eq!(sessions.get_game(&id).unwrap().current_player, Player::White);
+    assert_eq!(sessions.pass(&id, "Alice"), Err(SessionError::NotYourTurn));
+    assert!(sessions.pass(&id, "Bob").unwrap().is_empty());
+    assert_eq!(sessions.pass(&id, "Alice"), Err(SessionError::PassNotAllowed));
+    assert_eq!(sessions.get_game(&id).unwrap().current_player, Player::Black);
+    // The forced pass is implied by A1, so the history replays the same either way.
+    assert_eq!(sessions.history(&id).unwrap().len(), 1);
+    assert_eq!(sessions.history("no_such_game"), Err(SessionError::GameNotFound));
+
+    // A game set up to pass automatically does so whatever the server's setting.
+    let options = GameOptions {
+        auto_pass: Some(true),
+        ..GameOptions::default()
+    };
+    let id = sessions.create_game_with("Alice".to_string(), "Bob", options).unwrap();
+    assert!(sessions.auto_passes(&id) && !sessions.auto_pass());
+    assert_eq!(sessions.storage.auto_passes().unwrap().get(&id), Some(&true));
+    *sessions.get_game_mut(&id).unwrap() = forced_pass_position();
+    assert_eq!(sessions.make_move(&id, a1, "Alice").unwrap(), vec![Player::White]);
+}
+
+#[test]
+fn test_sessions_allow_passes_only_without_legal_moves() {
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    sessions.set_auto_pass(false);
+    sessions.set_strict_passes(true);
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    assert_eq!(sessions.pass(&id, "Alice"), Err(SessionError::PassNotAllowed));
+    assert_eq!(sessions.get_game(&id).unwrap(), &Game::new());
+    assert_eq!(sessions.ply(&id), Ok(0));
+
+    *sessions.get_game_mut(&id).unwrap() = forced_pass_position();
+    sessions.make_move(&id, Game::coord_to_pos("A1").unwrap(), "Alice").unwrap();
+    assert!(!sessions.get_game(&id).unwrap().has_legal_move(Player::White));
+    assert!(sessions.pass(&id, "Bob").unwrap().is_empty());
+    assert_eq!(sessions.get_game(&id).unwrap().current_player, Player::Black);
+    // The pass is implied by A1, so it is kept out of the history.
+    assert_eq!(sessions.history(&id).unwrap().len(), 1);
 }
 
 #[test]
@@ -34,6 +1183,155 @@ fn test_storage_save_load() {
     assert_eq!(loaded_game.black, game.black);
     assert_eq!(p1, "Alice");
     assert_eq!(p2, "Bob");
+
+    // Boards too large for a float survive exactly.
+    let full = Game::from_bitboards(u64::MAX >> 1, 1 << 63, Player::White, 1);
+    storage.save_game("full", &full, "Alice", "Bob").unwrap();
+    assert_eq!(storage.load_game("full").unwrap().unwrap().0, full);
+    // A full board is a finished game, which is not loaded at startup.
+    assert!(!storage.load_all_games().unwrap().0.contains_key("full"));
+}
+
+#[test]
+fn test_storage_loads_games_saved_without_state() {
+    let path = std::env::temp_dir().join(format!("kawio_legacy_{}.db", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    let mut game = Game::new();
+    game.make_move(Game::coord_to_pos("D3").unwrap()).unwrap();
+    Storage::new(path).unwrap().save_game("old", &game, "Alice", "Bob").unwrap();
+    let conn = rusqlite::Connection::open(path).unwrap();
+    conn.execute("UPDATE games SET state = NULL", []).unwrap();
+    drop(conn);
+
+    let (loaded, _, _) = Storage::new(path).unwrap().load_game("old").unwrap().unwrap();
+    assert_eq!(loaded, game);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_sessions_load_finished_games_on_demand() {
+    let path = std::env::temp_dir().join(format!("kawio_finished_{}.db", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    let storage = Storage::new(path).unwrap();
+    let (active, aborted) = ("on_demand_active", "on_demand_aborted");
+    storage.save_game(active, &Game::new(), "Alice", "Bob").unwrap();
+    storage.save_game(aborted, &Game::new(), "Alice", "Bob").unwrap();
+    storage.abort_game(aborted).unwrap();
+    let (games, _) = storage.load_all_games().unwrap();
+    assert_eq!(games.keys().collect::<Vec<_>>(), vec![active]);
+
+    // A server only knows the finished game once asked for it.
+    let mut sessions = Sessions::new(storage);
+    assert!(!sessions.has_game(aborted));
+    sessions.load_game(aborted).unwrap();
+    assert!(sessions.is_over(aborted));
+    assert!(sessions.is_aborted(aborted));
+    assert_eq!(sessions.get_players(aborted).unwrap().0, "Alice");
+    assert_eq!(sessions.load_game("no_such_game"), Err(SessionError::GameNotFound));
+    drop(sessions);
+
+    // Databases from before the flag mark their finished games when opened.
+    let conn = rusqlite::Connection::open(path).unwrap();
+    conn.execute("DROP INDEX games_finished", []).unwrap();
+    conn.execute("ALTER TABLE games DROP COLUMN finished", []).unwrap();
+    drop(conn);
+    let (games, _) = Storage::new(path).unwrap().load_all_games().unwrap();
+    assert_eq!(games.keys().collect::<Vec<_>>(), vec![&active]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_sessions_share_games_through_storage() {
+    let path = std::env::temp_dir().join(format!("kawio_shared_{}.db", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    let bus = Arc::new(LocalBus::new());
+    let server = || {
+        let mut sessions = Sessions::new(Storage::new(path).unwrap());
+        sessions.set_shared(true);
+        sessions.set_update_bus(bus.clone());
+        sessions
+    };
+    let (mut first, mut second) = (server(), server());
+    let mut updates = bus.subscribe();
+
+    let id = first.create_game("Alice".to_string(), "Bob").unwrap();
+    second.load_game(&id).unwrap();
+    let mut watcher = second.join(&id, None).unwrap();
+    // Sockets joining are announced too, so every server counts them.
+    let joined = updates.try_recv().unwrap();
+    assert_eq!(joined.origin, second.instance_id());
+    assert!(matches!(joined.change, Change::Joined(_)));
+    let pos = first.get_game(&id).unwrap().legal_moves()[0];
+    first.make_move(&id, pos, "Alice").unwrap();
+
+    // The move is announced, and the other server tells its sockets after reloading the game.
+    let update = updates.try_recv().unwrap();
+    assert_eq!(update.origin, first.instance_id());
+    let Change::Delta(delta) = &update.change else {
+        panic!("expected a delta");
+    };
+    assert_eq!(delta.action, Game::pos_to_coord(pos));
+    second.apply_update(update.clone());
+    assert_eq!(second.get_game(&id), first.get_game(&id));
+    assert!(matches!(watcher.try_recv(), Ok(GameEvent::Delta(_))));
+    // A server ignores its own updates.
+    first.apply_update(update);
+
+    // Bob replies on the second server; the first reloads the game before acting on it.
+    let reply = second.get_game(&id).unwrap().legal_moves()[0];
+    second.make_move(&id, reply, "Bob").unwrap();
+    assert_eq!(first.make_move(&id, reply, "Bob"), Err(SessionError::NotYourTurn));
+    assert_eq!(first.get_game(&id), second.get_game(&id));
+
+    // A write based on an outdated version is refused as a whole.
+    let version = first.storage.game_version(&id).unwrap().unwrap();
+    let stale = first
+        .storage
+        .write_game_version(&id, version - 1, |storage| storage.abort_game(&id))
+        .unwrap();
+    assert!(!stale);
+    assert!(!first.storage.is_aborted(&id).unwrap());
+
+    // Resigning on one server ends the game on the other.
+    first.resign(&id, "Alice").unwrap();
+    second.load_game(&id).unwrap();
+    assert_eq!(second.resigned(&id), Some(Player::Black));
+    assert_eq!(second.resign(&id, "Bob"), Err(SessionError::GameOver));
+
+    // Deleting it on one server makes the other forget it.
+    first.remove_game(&id, "Alice").unwrap();
+    let removed = std::iter::from_fn(|| updates.try_recv().ok())
+        .find(|update| update.change == Change::Removed)
+        .unwrap();
+    second.apply_update(removed);
+    assert!(!second.has_game(&id));
+    drop((first, second));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_database_bus_carries_updates() {
+    let path = std::env::temp_dir().join(format!("kawio_bus_{}.db", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    let (sender, receiver) = (DatabaseBus::open(path).unwrap(), DatabaseBus::open(path).unwrap());
+    let mut updates = receiver.subscribe();
+    let update = GameUpdate {
+        origin: "sender".to_string(),
+        game_id: "game_1".to_string(),
+        change: Change::Chat {
+            from: "Alice".to_string(),
+            text: "hello".to_string(),
+        },
+    };
+    sender.publish(&update);
+    let received = tokio::time::timeout(Duration::from_secs(5), updates.recv()).await.unwrap().unwrap();
+    assert_eq!(received, update);
+    drop((sender, receiver));
+    std::fs::remove_file(path).unwrap();
 }
 
 #[test]
@@ -45,3 +1343,1007 @@ fn test_storage_load_all() {
     assert_eq!(games.len(), 1);
     assert_eq!(players.len(), 1);
 }
+
+#[test]
+fn test_storage_move_records() {
+    let storage = Storage::new(":memory:").unwrap();
+    let records = [
+        MoveRecord {
+            mv: "D3".parse().unwrap(),
+            player: "Alice".to_string(),
+            elapsed_ms: Some(1500),
+            simulations: None,
+        },
+        MoveRecord {
+            mv: "pass".parse().unwrap(),
+            player: "AI".to_string(),
+            elapsed_ms: None,
+            simulations: Some(100),
+        },
+    ];
+    for record in &records {
+        storage.append_move("test", record).unwrap();
+    }
+    assert_eq!(storage.load_move_records("test").unwrap(), records);
+}
+
+#[test]
+fn test_storage_annotations() {
+    let storage = Storage::new(":memory:").unwrap();
+    let engine = Annotation {
+        ply: 3,
+        author: "AI".to_string(),
+        comment: None,
+        mark: Some(MoveMark::Blunder),
+        score: Some(-0.5),
+    };
+    let comment = Annotation {
+        ply: 1,
+        author: "Alice".to_string(),
+        comment: Some("Opens the diagonal".to_string()),
+        mark: Some(MoveMark::Interesting),
+        score: None,
+    };
+    storage.add_annotation("test", &engine).unwrap();
+    storage.add_annotation("test", &comment).unwrap();
+    assert_eq!(storage.load_annotations("test").unwrap(), [comment, engine]);
+    assert!(storage.load_annotations("other").unwrap().is_empty());
+    assert_eq!("!?".parse(), Ok(MoveMark::Interesting));
+    assert!("?!?".parse::<MoveMark>().is_err());
+}
+
+#[test]
+fn test_storage_puzzles() {
+    let storage = Storage::new(":memory:").unwrap();
+    assert!(storage.puzzle_for_day(19_000).unwrap().is_none());
+    let puzzle = Puzzle {
+        ply: 0,
+        game: Game::new(),
+        solution: Game::coord_to_pos("D3").unwrap(),
+        difficulty: 2,
+    };
+    assert!(storage.save_puzzle("game_1", &puzzle).unwrap());
+    assert!(!storage.save_puzzle("game_2", &puzzle).unwrap());
+    let daily = storage.puzzle_for_day(19_000).unwrap().unwrap();
+    assert_eq!(daily.game_id, "game_1");
+    assert_eq!(daily.puzzle, puzzle);
+    assert_eq!(storage.load_puzzle(daily.id).unwrap(), Some(daily));
+    assert!(storage.load_puzzle(999).unwrap().is_none());
+    storage.record_puzzle_attempt(1, true).unwrap();
+
+    // Each finished game is mined once.
+    storage.save_self_play_game("1", &Game::new(), &[], "A", "B").unwrap();
+    assert_eq!(storage.unmined_game_ids().unwrap(), ["selfplay_1"]);
+    assert_eq!(puzzles::mine_stored_games(&storage).unwrap(), 0);
+    assert!(storage.unmined_game_ids().unwrap().is_empty());
+}
+
+#[test]
+fn test_sessions_imported_games_archived() {
+    let mut game = Game::new();
+    let mut sgf = String::from("(;GM[2]SZ[8]PB[Kasparov]");
+    while !game.is_game_over() {
+        let color = if game.current_player == Player::Black { "B" } else { "W" };
+        let pos = game.legal_moves()[0];
+        sgf.push_str(&format!(";{color}[{}]", Game::pos_to_coord(pos)));
+        game.make_move(pos).unwrap();
+    }
+    sgf.push(')');
+    let imported = import::parse_game(&sgf).unwrap();
+
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let id = sessions.storage.import_game("Alice", &imported).unwrap();
+    assert_eq!(sessions.storage.load_moves(&id).unwrap(), imported.moves);
+    let metadata = sessions.storage.load_metadata(&id).unwrap();
+    assert_eq!(metadata[IMPORTED_BY_METADATA_KEY], "Alice");
+    assert_eq!(metadata[FORMAT_METADATA_KEY], "sgf");
+    // Archived as a finished game, which changes nobody's record.
+    sessions.load_game(&id).unwrap();
+    assert!(sessions.is_over(&id) && sessions.get_game(&id) == Some(&game));
+    assert_eq!(sessions.get_players(&id), Some(&("Kasparov".to_string(), "White".to_string())));
+    assert_eq!(sessions.game_type(&id), GameType::Casual);
+    assert!(sessions.storage.get_player("Kasparov").unwrap().is_none());
+    assert!(sessions.start_analysis(&id).is_ok());
+    assert_eq!(sessions.storage.unmined_game_ids().unwrap(), [id]);
+}
+
+#[test]
+fn test_storage_player_settings() {
+    let storage = Storage::new(":memory:").unwrap();
+    assert_eq!(storage.player_settings("Alice").unwrap(), PlayerSettings::default());
+    assert!(storage.accepts_challenge("Bob", "Alice").unwrap());
+    let settings = PlayerSettings {
+        accept_challenges: ChallengePolicy::Nobody,
+        friends: vec!["Bob".to_string()],
+        blocked: vec!["Carol".to_string()],
+    };
+    storage.save_player_settings("Alice", &settings).unwrap();
+    assert_eq!(storage.player_settings("Alice").unwrap(), settings);
+    assert!(!storage.accepts_challenge("Bob", "Alice").unwrap());
+    // Blocking works both ways.
+    assert!(storage.is_blocked("Carol", "Alice").unwrap());
+    assert!(!storage.accepts_challenge("Alice", "Carol").unwrap());
+    assert!(!storage.is_blocked("Bob", "Alice").unwrap());
+}
+
+#[test]
+fn test_storage_player_preferences() {
+    let storage = Storage::new(":memory:").unwrap();
+    let defaults = storage.player_preferences("Alice").unwrap();
+    assert_eq!(defaults, PlayerPreferences::default());
+    assert!(defaults.notifications.your_turn && defaults.auto_pass.is_none());
+    let preferences = PlayerPreferences {
+        auto_pass: Some(false),
+        coach: true,
+        clock: Some(TimeControl {
+            minutes: 10,
+            increment_secs: 5,
+        }),
+        orientation: BoardOrientation::OwnColor,
+        notifications: Notifications {
+            chat: false,
+            ..Notifications::default()
+        },
+    };
+    storage.save_player_preferences("Alice", &preferences).unwrap();
+    assert_eq!(storage.player_preferences("Alice").unwrap(), preferences);
+    assert_eq!(storage.player_preferences("Bob").unwrap(), defaults);
+    // Fields left out take their defaults.
+    let partial: PlayerPreferences = serde_json::from_str(r#"{"coach": true}"#).unwrap();
+    assert_eq!(partial.orientation, BoardOrientation::Standard);
+    assert!(partial.coach && partial.notifications.sound);
+}
+
+#[test]
+fn test_storage_rating_rules() {
+    let mut storage = Storage::new(":memory:").unwrap();
+    let mut rating = storage.rating_config().clone();
+    rating.floor = 1180.0;
+    storage.set_rating_config(rating);
+    let stats = |storage: &Storage, name: &str| {
+        let stats = storage.get_leaderboard().unwrap();
+        stats.into_iter().find(|p| p.name == name).unwrap()
+    };
+
+    // Provisional players move twice as fast as established ones.
+    storage.update_player("Alice", "Bob", true).unwrap();
+    assert!((stats(&storage, "Alice").elo - 1232.0).abs() < 1e-9);
+    assert!(stats(&storage, "Alice").provisional);
+    // Ratings stop at the floor.
+    assert!((stats(&storage, "Bob").elo - 1180.0).abs() < 1e-9);
+
+    // The AI keeps its anchor rating whatever the result.
+    storage.update_player("Carol", "AI-Hard", true).unwrap();
+    let ai = stats(&storage, "AI-Hard");
+    assert!((ai.elo - 1800.0).abs() < 1e-9);
+    assert!(!ai.provisional);
+    assert!(stats(&storage, "Carol").elo > 1250.0);
+}
+
+#[test]
+fn test_storage_game_statistics() {
+    let storage = Storage::new(":memory:").unwrap();
+    let result = |winner, black_discs, white_discs| GameResult {
+        black: "Alice",
+        white: "Bob",
+        winner,
+        black_discs,
+        white_discs,
+        moves: 60,
+    };
+    let game = Game::new();
+    storage.finish_game("g1", &game, &result(Some(Player::Black), 40, 24)).unwrap();
+    storage.finish_game("g2", &game, &result(Some(Player::Black), 36, 28)).unwrap();
+    storage.finish_game("g3", &game, &result(None, 32, 32)).unwrap();
+    storage.finish_game("g4", &game, &result(Some(Player::Black), 34, 30)).unwrap();
+
+    let alice = storage.get_player("Alice").unwrap().unwrap();
+    assert_eq!((alice.games_as_black, alice.games_as_white), (4, 0));
+    assert_eq!((alice.wins, alice.losses), (3, 0));
+    assert_eq!((alice.current_streak, alice.best_streak), (1, 2));
+    assert!((alice.avg_disc_diff - 7.0).abs() < 1e-9);
+    assert!((alice.avg_moves - 60.0).abs() < 1e-9);
+
+    let bob = storage.get_player("Bob").unwrap().unwrap();
+    assert_eq!(bob.games_as_white, 4);
+    assert_eq!(bob.best_streak, 0);
+    assert!((bob.avg_disc_diff + 7.0).abs() < 1e-9);
+    assert!(storage.get_player("Carol").unwrap().is_none());
+
+    // Draws leave ratings alone, so there is one history entry per decisive game.
+    let history = storage.rating_history("Alice").unwrap();
+    let games: Vec<_> = history.iter().map(|change| change.game_id.as_str()).collect();
+    assert_eq!(games, ["g1", "g2", "g4"]);
+    assert!((history[0].elo_before - 1200.0).abs() < 1e-9);
+    assert!((history[2].elo_after - alice.elo).abs() < 1e-9);
+    assert!(storage.load_game("g4").unwrap().is_some());
+}
+
+#[test]
+fn test_storage_achievements() {
+    let storage = Storage::new(":memory:").unwrap();
+    let result = |white| GameResult {
+        black: "Alice",
+        white,
+        winner: Some(Player::Black),
+        black_discs: 64,
+        white_discs: 0,
+        moves: 60,
+    };
+    for (id, white) in [("g1", "Bob"), ("g2", "AI-Hard")] {
+        storage.finish_game(id, &Game::new(), &result(white)).unwrap();
+    }
+    let awarded = storage.award_achievements("g1", &[], &result("Bob")).unwrap();
+    let names: Vec<_> = awarded.iter().map(|(player, a)| (player.as_str(), *a)).collect();
+    assert_eq!(
+        names,
+        [("Alice", Achievement::FirstWin), ("Alice", Achievement::PerfectGame)]
+    );
+    // Each achievement is only earned once.
+    let awarded = storage.award_achievements("g2", &[], &result("AI-Hard")).unwrap();
+    assert_eq!(awarded, [("Alice".to_string(), Achievement::BeatHardAi)]);
+
+    let alice = storage.get_player("Alice").unwrap().unwrap();
+    let earned: Vec<_> = alice.achievements.iter().map(|a| (a.achievement, a.game_id.as_str())).collect();
+    assert_eq!(
+        earned,
+        [
+            (Achievement::FirstWin, "g1"),
+            (Achievement::PerfectGame, "g1"),
+            (Achievement::BeatHardAi, "g2")
+        ]
+    );
+    assert!(storage.achievements("Bob").unwrap().is_empty());
+}
+
+#[test]
+fn test_storage_server_stats() {
+    let storage = Storage::new(":memory:").unwrap();
+    let empty = storage.server_stats().unwrap();
+    assert_eq!(empty.total_games, 0);
+    assert!(empty.daily.is_empty());
+
+    let result = |black, white, winner, moves| GameResult {
+        black,
+        white,
+        winner,
+        black_discs: 40,
+        white_discs: 24,
+        moves,
+    };
+    let game = Game::new();
+    storage.finish_game("g1", &game, &result("Alice", "AI", Some(Player::Black), 60)).unwrap();
+    storage.finish_game("g2", &game, &result("Bob", "AI-Hard", Some(Player::White), 50)).unwrap();
+    storage.finish_game("g3", &game, &result("AI-Easy", "Carol", None, 58)).unwrap();
+    storage.finish_game("g4", &game, &result("Alice", "Bob", Some(Player::White), 40)).unwrap();
+
+    let stats = storage.server_stats().unwrap();
+    assert_eq!(stats.total_games, 4);
+    assert!((stats.avg_game_length - 52.0).abs() < 1e-9);
+    assert_eq!(stats.active_players, 3);
+    assert_eq!(stats.ai_games, 3);
+    assert!((stats.ai_win_rate - 1.0 / 3.0).abs() < 1e-9);
+    assert!((stats.human_win_rate - 1.0 / 3.0).abs() < 1e-9);
+    assert_eq!(stats.daily.len(), 1);
+    assert_eq!((stats.daily[0].games, stats.daily[0].active_players), (4, 3));
+    assert_eq!(stats.weekly[0].games, 4);
+}
+
+#[test]
+fn test_storage_head_to_head_and_form() {
+    let storage = Storage::new(":memory:").unwrap();
+    assert!((storage.current_rating("Alice").unwrap() - 1200.0).abs() < 1e-9);
+    assert!((storage.current_rating("AI-Hard").unwrap() - 1800.0).abs() < 1e-9);
+    assert!((RatingConfig::expected_score(1200.0, 1200.0) - 0.5).abs() < 1e-9);
+    assert!(RatingConfig::expected_score(1200.0, 1800.0) < 0.05);
+
+    let result = |black, white, winner| GameResult {
+        black,
+        white,
+        winner,
+        black_discs: 40,
+        white_discs: 24,
+        moves: 60,
+    };
+    let game = Game::new();
+    storage.finish_game("g1", &game, &result("Alice", "Bob", Some(Player::Black))).unwrap();
+    storage.finish_game("g2", &game, &result("Bob", "Alice", Some(Player::Black))).unwrap();
+    storage.finish_game("g3", &game, &result("Alice", "Bob", None)).unwrap();
+    storage.finish_game("g4", &game, &result("Alice", "Carol", Some(Player::White))).unwrap();
+    storage.finish_game("g5", &game, &result("Carol", "Alice", Some(Player::White))).unwrap();
+
+    let record = storage.head_to_head("Alice", "Bob").unwrap();
+    assert_eq!(record, HeadToHead { wins: 1, losses: 1, draws: 1 });
+    assert_eq!(storage.head_to_head("Bob", "Alice").unwrap().wins, 1);
+    assert_eq!(
+        storage.recent_form("Alice", 3).unwrap(),
+        [Outcome::Win, Outcome::Loss, Outcome::Draw]
+    );
+    assert!(storage.recent_form("Dave", 3).unwrap().is_empty());
+    assert!(storage.current_rating("Alice").unwrap() > 1200.0);
+}
+
+#[test]
+fn test_storage_game_ids_are_unique() {
+    let path = std::env::temp_dir().join(format!("kawio_ids_{}.db", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+
+    // Ids handed out before the sequence existed are skipped.
+    let first = Storage::new(path).unwrap();
+    first.save_game("game_7", &Game::new(), "Alice", "Bob").unwrap();
+    drop(first);
+    let conn = rusqlite::Connection::open(path).unwrap();
+    conn.execute("DROP TABLE id_sequences", []).unwrap();
+    drop(conn);
+
+    let first = Storage::new(path).unwrap();
+    let second = Storage::new(path).unwrap();
+    let ids = [
+        first.next_game_id().unwrap(),
+        second.next_game_id().unwrap(),
+        first.next_game_id().unwrap(),
+    ];
+    assert_eq!(ids, ["game_8", "game_9", "game_10"]);
+    drop((first, second));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_client_plays_against_ai() {
+    use kawio::api::{AnnotationRequest, BatchAnalysisRequest};
+    use kawio::client::{Client, GameStream};
+    use kawio::game::Move;
+    use kawio::protocol::{RejectionReason, ServerMsg};
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    let sessions = Arc::new(Mutex::new(Sessions::new(Storage::new(":memory:").unwrap())));
+    let app = kawio::network::create_router(sessions);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let mut client = Client::new(&format!("http://{address}"));
+    assert!(client.create_match("AI").await.is_err());
+    assert!(client.attempt_puzzle(1, Move::Pass).await.is_err());
+    client.login("ClientTester").await.unwrap();
+    let id = client.create_match("AI").await.unwrap();
+
+    let state = client.state(&id).await.unwrap();
+    assert_eq!(state.player1, "ClientTester");
+    assert_eq!(state.position, Some(Game::new()));
+    let first: Move = state.legal_moves[0].parse().unwrap();
+    client.submit_move(&id, first).await.unwrap();
+
+    let replay = client.replay(&id).await.unwrap();
+    assert_eq!(replay.moves[0].coord, first.to_string());
+    assert_eq!(replay.moves[0].player, "ClientTester");
+    assert!(replay.moves[0].elapsed_ms.is_some());
+    assert_eq!(replay.moves[1].player, "AI");
+    assert!(replay.moves[1].simulations.is_some());
+    assert_eq!(replay.ply, replay.moves.len());
+    let discs = replay.board.iter().flatten().filter(|cell| *cell != ".").count();
+    assert_eq!(discs, 4 + replay.moves.len());
+
+    let note = AnnotationRequest {
+        ply: 1,
+        comment: Some("Takes the centre".to_string()),
+        mark: Some("!".to_string()),
+        score: None,
+    };
+    client.annotate(&id, &note).await.unwrap();
+    let unplayed = AnnotationRequest { ply: 60, ..note.clone() };
+    assert!(client.annotate(&id, &unplayed).await.is_err());
+    let bad_mark = AnnotationRequest { mark: Some("!!!".to_string()), ..note };
+    assert!(client.annotate(&id, &bad_mark).await.is_err());
+    let annotations = client.replay(&id).await.unwrap().annotations;
+    assert_eq!(annotations.len(), 1);
+    assert_eq!(annotations[0].author, "ClientTester");
+    assert_eq!(annotations[0].mark.as_deref(), Some("!"));
+
+    let mut late = Game::new();
+    while late.empties() > 10 {
+        late.make_move(late.legal_moves()[0]).unwrap();
+    }
+    let batch = BatchAnalysisRequest {
+        positions: vec![Game::new().to_position_string(), late.to_position_string()],
+        ..BatchAnalysisRequest::default()
+    };
+    let evaluations = client.analyze_batch(&batch).await.unwrap();
+    assert_eq!(evaluations.len(), 2);
+    assert!(!evaluations[0].exact && evaluations[1].exact);
+    assert!(evaluations.iter().all(|evaluation| evaluation.best.is_some()));
+    let unreadable = BatchAnalysisRequest {
+        positions: vec!["XO".to_string()],
+        ..BatchAnalysisRequest::default()
+    };
+    assert!(client.analyze_batch(&unreadable).await.is_err());
+
+    // Skips the updates of moves and connections still being pushed.
+    async fn next_other(stream: &mut GameStream) -> ServerMsg {
+        loop {
+            match stream.next_event().await {
+                Some(Ok(ServerMsg::Delta(_) | ServerMsg::State(_) | ServerMsg::Joined(_) | ServerMsg::Left(_))) => {}
+                Some(Ok(message)) => return message,
+                other => panic!("expected a message, got {other:?}"),
+            }
+        }
+    }
+
+    let mut stream = client.connect(&id).await.unwrap();
+    let Some(Ok(ServerMsg::State(state))) = stream.next_event().await else {
+        panic!("expected an initial state");
+    };
+    // The AI has already replied, so it is Black's turn again.
+    assert_eq!(state.current_player, "Black");
+    assert!(state.presence.player1_connected && state.presence.player2_connected);
+    let second: Move = state.legal_moves[0].parse().unwrap();
+    stream.send_move(second).await.unwrap();
+    let Some(Ok(ServerMsg::Delta(delta))) = stream.next_event().await else {
+        panic!("expected a delta after the move");
+    };
+    assert_eq!((delta.player.as_str(), delta.action), ("Black", second.to_string()));
+    let Some(Ok(ServerMsg::State(state))) = stream.next_event().await else {
+        panic!("expected a state after the move");
+    };
+    assert!(state.scores["B"] + state.scores["W"] >= 8);
+
+    // Passing with legal moves available is rejected with a reason.
+    assert!(!state.legal_moves.is_empty());
+    stream.send_move(Move::Pass).await.unwrap();
+    let ServerMsg::Rejected(rejection) = next_other(&mut stream).await else {
+        panic!("expected the pass to be rejected");
+    };
+    assert_eq!((rejection.coord, rejection.reason), (None, RejectionReason::Illegal));
+    assert_eq!(rejection.notice.code, MessageCode::PassNotAllowed);
+    assert!(rejection.notice.to_string().contains("cannot pass"));
+
+    stream.ping().await.unwrap();
+    assert_eq!(next_other(&mut stream).await, ServerMsg::Pong);
+    stream.chat("  good luck  ").await.unwrap();
+    assert_eq!(
+        next_other(&mut stream).await,
+        ServerMsg::Chat {
+            from: "ClientTester".to_string(),
+            text: "good luck".to_string()
+        }
+    );
+    stream.close().await.unwrap();
+
+    // A socket opened without a token can watch and move, but not chat or resign.
+    let mut anonymous = Client::new(&format!("http://{address}")).connect(&id).await.unwrap();
+    anonymous.resign().await.unwrap();
+    let ServerMsg::Error(notice) = next_other(&mut anonymous).await else {
+        panic!("expected the resignation to be rejected");
+    };
+    assert_eq!(notice.code, MessageCode::NotLoggedIn);
+
+    let mut stream = client.connect(&id).await.unwrap();
+    stream.resign().await.unwrap();
+    let state = loop {
+        match stream.next_event().await {
+            Some(Ok(ServerMsg::State(state))) if state.game_over => break state,
+            Some(Ok(_)) => {}
+            other => panic!("expected the game to end, got {other:?}"),
+        }
+    };
+    assert_eq!(state.resigned.as_deref(), Some("Black"));
+    assert_eq!(state.winner.as_deref(), Some("White"));
+    assert!(state.legal_moves.is_empty());
+    stream.close().await.unwrap();
+
+    client.delete_match(&id).await.unwrap();
+    assert!(client.state(&id).await.is_err());
+    assert!(client.delete_match(&id).await.is_err());
+
+    // Unknown games get an error frame and a clean close instead of a dropped socket.
+    let mut stream = client.connect("no_such_game").await.unwrap();
+    let Some(Ok(ServerMsg::Error(notice))) = stream.next_event().await else {
+        panic!("expected an error for an unknown game");
+    };
+    assert_eq!(notice.code, MessageCode::GameNotFound);
+    assert!(stream.next_event().await.is_none());
+}
+
+#[tokio::test]
+async fn test_request_log_is_switched_by_admins() {
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use kawio::auth::Auth;
+    use tower::ServiceExt;
+    use tracing_subscriber::prelude::*;
+
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    sessions.set_admins(["Admin".to_string()].into_iter().collect());
+    let app = kawio::network::create_router(Arc::new(Mutex::new(sessions)));
+    let request = |method: &str, path: &str, player: &str, body: &str| {
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .header(header::AUTHORIZATION, format!("Bearer {}", Auth::generate_token(player).unwrap()))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    let logs = Arc::new(Mutex::new(Vec::new()));
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::registry().with(
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(move || WriterTo(writer.clone())),
+    );
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let new_match = r#"{"player2":"Bob"}"#;
+    let response = app.clone().oneshot(request("POST", "/match/new", "Alice", new_match)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!String::from_utf8_lossy(&logs.lock()).contains("/match/new"));
+
+    let switch_on = r#"{"enabled":true}"#;
+    let refused = app.clone().oneshot(request("POST", "/admin/request-log", "Alice", switch_on)).await.unwrap();
+    assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+    let response = app.clone().oneshot(request("POST", "/admin/request-log", "Admin", switch_on)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The body is still read by the handler after being logged.
+    let response = app.clone().oneshot(request("POST", "/match/new", "Alice", new_match)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let secret = r#"{"player2":"Bob","token":"hidden"}"#;
+    let response = app.clone().oneshot(request("POST", "/match/new", "Alice", secret)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let response = app.clone().oneshot(request("POST", "/match/new", "Alice", "{")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let logged = String::from_utf8_lossy(&logs.lock()).into_owned();
+    let lines: Vec<&str> = logged.lines().filter(|line| line.contains("kawio::requests")).collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].contains("path=\"/match/new\"") && lines[0].contains("player=\"Alice\""));
+    assert!(lines[0].contains("status=200"));
+    assert!(lines[1].contains("status=422") && lines[1].contains("[redacted]"));
+    assert!(!lines[1].contains("hidden"));
+    assert!(lines[2].contains("status=400"));
+}
+
+#[tokio::test]
+async fn test_request_bodies_checked_before_handlers() {
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use kawio::auth::Auth;
+    use tower::ServiceExt;
+
+    let app = kawio::network::create_router(Arc::new(Mutex::new(Sessions::new(Storage::new(":memory:").unwrap()))));
+    let token = format!("Bearer {}", Auth::generate_token("Alice").unwrap());
+    let send = |path: &str, content_type: Option<&str>, body: String| {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(path)
+            .header(header::AUTHORIZATION, &token);
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        let request = request.body(Body::from(body)).unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice::<Notice>(&body).ok())
+        }
+    };
+
+    let json = Some("application/json");
+    let (status, _) = send("/match/new", Some("application/json; charset=utf-8"), r#"{"player2":"Bob"}"#.into()).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, notice) = send("/match/new", Some("text/plain"), r#"{"player2":"Bob"}"#.into()).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(notice.unwrap().code, MessageCode::UnsupportedMediaType);
+    let (status, _) = send("/match/new", None, r#"{"player2":"Bob"}"#.into()).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // Unknown fields are refused rather than ignored, with the reason.
+    let (status, notice) = send("/match/new", json, r#"{"player2":"Bob","komy":2}"#.into()).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let notice = notice.unwrap();
+    assert_eq!(notice.code, MessageCode::InvalidRequest);
+    assert!(notice.to_string().contains("komy"), "{notice}");
+
+    // Each endpoint has its own limit.
+    let padded = format!(r#"{{"player2":"Bob","padding":"{}"}}"#, " ".repeat(20 * 1024));
+    let (status, notice) = send("/match/new", json, padded).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(notice.unwrap().code, MessageCode::BodyTooLarge);
+    let positions = vec!["---------------------------OX------XO--------------------------- X"; 300];
+    let batch = serde_json::json!({ "positions": positions, "depth": 1 }).to_string();
+    assert!(batch.len() > 16 * 1024);
+    let (status, _) = send("/analyze/batch", json, batch).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send("/import", Some("application/x-go-sgf"), "(;GM[2]SZ[8])".into()).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = send("/import", json, "(;GM[2]SZ[8])".into()).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let (status, _) = send("/import", Some("text/plain"), "x".repeat(65 * 1024)).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    // A misspelled preference would otherwise be reset to its default.
+    let request = Request::builder()
+        .method("PUT")
+        .uri("/me/preferences")
+        .header(header::AUTHORIZATION, &token)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"coach":true,"notifications":{"sond":false}}"#))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[cfg(feature = "oauth")]
+#[tokio::test]
+async fn test_oauth_logins_linked_to_players() {
+    use axum::body::Body;
+    use axum::http::{header, HeaderMap, Request, StatusCode};
+    use axum::routing::{get, post};
+    use kawio::api::OAuthProvidersResponse;
+    use kawio::auth::Auth;
+    use kawio::oauth::ProviderConfig;
+    use tower::ServiceExt;
+
+    // A provider whose codes name their user: the code `alice-1` is user 1, called Alice.
+    let provider = axum::Router::new()
+        .route(
+            "/token",
+            post(|body: String| async move {
+                let code = body.split('&').find_map(|pair| pair.strip_prefix("code=")).unwrap_or_default();
+                axum::Json(serde_json::json!({ "access_token": code, "token_type": "bearer" }))
+            }),
+        )
+        .route(
+            "/user",
+            get(|headers: HeaderMap| async move {
+                let token = headers[header::AUTHORIZATION].to_str().unwrap().trim_start_matches("Bearer ");
+                let (name, id) = token.split_once('-').unwrap();
+                axum::Json(serde_json::json!({ "sub": id, "name": name }))
+            }),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, provider).await.unwrap() });
+
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    // Carol has played without an account, by name.
+    sessions.create_game("Carol".to_string(), "AI").unwrap();
+    sessions.add_oauth_provider(
+        serde_json::from_value::<ProviderConfig>(serde_json::json!({
+            "name": "test",
+            "client_id": "kawio",
+            "client_secret": "shh",
+            "authorize_url": format!("http://{address}/authorize"),
+            "token_url": format!("http://{address}/token"),
+            "userinfo_url": format!("http://{address}/user"),
+            "redirect_url": "http://kawio.test/auth/oauth/test/callback"
+        }))
+        .unwrap(),
+    );
+    let app = kawio::network::create_router(Arc::new(Mutex::new(sessions)));
+    let get = |uri: String, cookie: Option<String>| {
+        let mut request = Request::builder().uri(uri);
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        let app = app.clone();
+        async move { app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap() }
+    };
+    let login = |code: &'static str| async move {
+        let response = get("/auth/oauth/test".to_string(), None).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(cookie.contains("HttpOnly"), "{cookie}");
+        let cookie = cookie.split(';').next().unwrap().to_string();
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        let state = reqwest::Url::parse(location)
+            .unwrap()
+            .query_pairs()
+            .find_map(|(key, value)| (key == "state").then(|| value.into_owned()))
+            .unwrap();
+
+        let callback = format!("/auth/oauth/test/callback?code={code}&state={state}");
+        // The state only counts in the browser that started the login.
+        let response = get(callback.clone(), Some("kawio_oauth=0123".to_string())).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = get(callback, Some(cookie)).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        let fragment = location.strip_prefix("/#").unwrap();
+        let params: BTreeMap<String, String> = reqwest::Url::parse(&format!("http://kawio.test/?{fragment}"))
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect();
+        assert_eq!(Auth::validate_token(&params["token"]).unwrap().sub, params["player"]);
+        params["player"].clone()
+    };
+
+    let response = get("/auth/oauth".to_string(), None).await;
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let providers: OAuthProvidersResponse = serde_json::from_slice(&body).unwrap();
+    assert_eq!(providers.providers, ["test"]);
+    assert_eq!(get("/auth/oauth/nobody".to_string(), None).await.status(), StatusCode::NOT_FOUND);
+
+    assert_eq!(login("Alice-1").await, "Alice");
+    assert_eq!(login("Alice-1").await, "Alice");
+    // Another user with the same name gets one of their own.
+    assert_eq!(login("Alice-2").await, "Alice2");
+    assert_eq!(login("AI-3").await, "test-3");
+    // A user named like someone who played by name does not take over their games.
+    assert_eq!(login("Carol-4").await, "Carol2");
+
+    // Linked names cannot be logged in as by name any more.
+    let login_by_name = |player: &str| {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/auth/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::json!({ "player": player }).to_string()))
+            .unwrap();
+        app.clone().oneshot(request)
+    };
+    assert_eq!(login_by_name("Alice").await.unwrap().status(), StatusCode::FORBIDDEN);
+    assert_eq!(login_by_name("Bob").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(login_by_name("Carol").await.unwrap().status(), StatusCode::OK);
+}
+
+#[cfg(feature = "oauth")]
+#[tokio::test]
+async fn test_openid_connect_logins_verify_the_id_token() {
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use axum::routing::{get, post};
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use kawio::auth::Auth;
+    use kawio::oauth::ProviderConfig;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    // Public half of the key in tests/fixtures/oidc_key.pem, which signs the ID tokens.
+    const MODULUS: &str = "z7tSEpEGzcRsj8Q5o0fMqbdzK96N6rfen6tiPOWQ7sGYljegjnLha1Y9Gfob9LwcyyEYAjN_sTQ7Ky9k3wa6K--RUExK4B4a7GubEpHC43fmjp8w8M_N2JJ6A3cYXY0QIaJTgCwnZ2HK97xI84atIUZ1lp2WVa2w7Bip_v8tH7Qf4mBBv2NyVSEbXU1PYxJ83QEk0yvGW4qb8_Z_bOsIdLZ-aCM525aXmK4HZxQtdW9ZrTVbxXzVpV0JNqF2yrJ8KD2x8fozuWpOF8NjzfNhQaGkDcNjI4kUJ4rdHjjn1_NKr83Q4zsNiX43YO4bbJ5ZuUUuMWEtWpw9Ts9LIKL1Ww";
+
+    // An issuer whose codes name their user, the nonce and what to get wrong: the code
+    // `Alice.1.<nonce>.` is user 1, called Alice, and `Alice.1.<nonce>.audience` gets an ID
+    // token issued to another client.
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+    let metadata = json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{issuer}/authorize"),
+        "token_endpoint": format!("{issuer}/token"),
+        "jwks_uri": format!("{issuer}/jwks"),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"]
+    });
+    let signer = issuer.clone();
+    let provider = axum::Router::new()
+        .route("/.well-known/openid-configuration", get(move || async move { axum::Json(metadata) }))
+        .route(
+            "/jwks",
+            get(|| async {
+                let key = json!({ "kty": "RSA", "use": "sig", "alg": "RS256", "kid": "test", "n": MODULUS, "e": "AQAB" });
+                axum::Json(json!({ "keys": [key] }))
+            }),
+        )
+        .route(
+            "/token",
+            post(move |body: String| async move {
+                let form = reqwest::Url::parse(&format!("http://issuer.test/?{body}")).unwrap();
+                let code = form.query_pairs().find_map(|(key, value)| (key == "code").then(|| value.into_owned()));
+                let code = code.unwrap();
+                let [name, subject, nonce, fault]: [&str; 4] = code.split('.').collect::<Vec<_>>().try_into().unwrap();
+                let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+                let mut claims = json!({
+                    "iss": signer,
+                    "aud": "kawio",
+                    "sub": subject,
+                    "preferred_username": name,
+                    "nonce": nonce,
+                    "iat": now,
+                    "exp": now + 300
+                });
+                match fault {
+                    "audience" => claims["aud"] = json!("someone-else"),
+                    "issuer" => claims["iss"] = json!("http://issuer.test"),
+                    "expired" => claims["exp"] = json!(now - 300),
+                    _ => {}
+                }
+                let header = Header {
+                    kid: Some("test".to_string()),
+                    ..Header::new(Algorithm::RS256)
+                };
+                let key = EncodingKey::from_rsa_pem(include_bytes!("fixtures/oidc_key.pem")).unwrap();
+                let id_token = jsonwebtoken::encode(&header, &claims, &key).unwrap();
+                axum::Json(json!({ "access_token": "opaque", "token_type": "bearer", "id_token": id_token }))
+            }),
+        );
+    tokio::spawn(async move { axum::serve(listener, provider).await.unwrap() });
+
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    sessions.create_game("Bob".to_string(), "AI").unwrap();
+    sessions.add_oauth_provider(
+        serde_json::from_value::<ProviderConfig>(json!({
+            "name": "test",
+            "client_id": "kawio",
+            "client_secret": "shh",
+            "issuer_url": issuer,
+            "redirect_url": "http://kawio.test/auth/oauth/test/callback",
+            "scopes": ["profile"]
+        }))
+        .unwrap(),
+    );
+    let app = kawio::network::create_router(Arc::new(Mutex::new(sessions)));
+    let get = |uri: String, cookie: Option<String>| {
+        let mut request = Request::builder().uri(uri);
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        let app = app.clone();
+        async move { app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap() }
+    };
+    let issuer = issuer.as_str();
+    // Logs in as `user`, e.g. `Alice.1`, with the nonce of another login if `replayed`.
+    let login = |user: &'static str, fault: &'static str, replayed: bool, ticket: Option<String>| async move {
+        let start = match ticket {
+            Some(ticket) => format!("/auth/oauth/test?ticket={ticket}"),
+            None => "/auth/oauth/test".to_string(),
+        };
+        let response = get(start, None).await;
+        if response.status() != StatusCode::SEE_OTHER {
+            return response.status();
+        }
+        let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        let cookie = cookie.split(';').next().unwrap().to_string();
+        let location = reqwest::Url::parse(response.headers()[header::LOCATION].to_str().unwrap()).unwrap();
+        assert!(location.as_str().starts_with(&format!("{issuer}/authorize?")), "{location}");
+        let params: BTreeMap<String, String> = location.query_pairs().into_owned().collect();
+        assert_eq!(params["scope"], "openid profile");
+        let nonce = if replayed { "0123" } else { params["nonce"].as_str() };
+        let callback = format!("/auth/oauth/test/callback?code={user}.{nonce}.{fault}&state={}", params["state"]);
+        let response = get(callback, Some(cookie)).await;
+        if response.status() != StatusCode::SEE_OTHER {
+            return response.status();
+        }
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        let fragment = location.strip_prefix("/#").unwrap();
+        let params: BTreeMap<String, String> = reqwest::Url::parse(&format!("http://kawio.test/?{fragment}"))
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect();
+        assert_eq!(Auth::validate_token(&params["token"]).unwrap().sub, params["player"]);
+        assert_eq!(params["player"], user.split('.').next().unwrap().replace("Robert", "Bob"));
+        StatusCode::OK
+    };
+
+    assert_eq!(login("Alice.1", "", false, None).await, StatusCode::OK);
+    // ID tokens for another client, from another issuer, expired or for another login are refused.
+    for fault in ["audience", "issuer", "expired"] {
+        assert_eq!(login("Alice.1", fault, false, None).await, StatusCode::BAD_GATEWAY, "{fault}");
+    }
+    assert_eq!(login("Alice.1", "", true, None).await, StatusCode::BAD_GATEWAY);
+
+    // Bob, who played by name, links an identity to his name and logs in through it from then on.
+    let ticket = Auth::generate_ticket("Bob").unwrap();
+    assert_eq!(login("Robert.7", "", false, Some(ticket.clone())).await, StatusCode::OK);
+    assert_eq!(login("Robert.7", "", false, Some(ticket)).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(login("Robert.7", "", false, None).await, StatusCode::OK);
+    // An identity linked to someone else cannot be taken over.
+    let ticket = Auth::generate_ticket("Dave").unwrap();
+    assert_eq!(login("Alice.1", "", false, Some(ticket)).await, StatusCode::CONFLICT);
+    let request = Request::builder()
+        .method("POST")
+        .uri("/auth/login")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "player": "Bob" }).to_string()))
+        .unwrap();
+    assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::FORBIDDEN);
+}
+
+#[cfg(feature = "chaos")]
+#[tokio::test]
+async fn test_chaos_jobs_retry_storage_errors() {
+    use kawio::chaos::StorageFault;
+
+    let mut sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let faults = sessions.faults().clone();
+    let id = sessions.create_game("Alice".to_string(), "Bob").unwrap();
+    while let Some(&pos) = sessions.get_game(&id).unwrap().legal_moves().first() {
+        let player = match sessions.get_game(&id).unwrap().current_player {
+            Player::Black => "Alice",
+            Player::White => "Bob",
+        };
+        sessions.make_move(&id, pos, player).unwrap();
+    }
+    let job_id = sessions.start_analysis(&id).unwrap();
+
+    // The game's moves cannot be read, so the first attempt fails and is queued again.
+    faults.fail_storage(StorageFault::Table("moves".to_string()));
+    let sessions = Arc::new(Mutex::new(sessions));
+    let backoff = Backoff {
+        base: Duration::ZERO,
+        max: Duration::ZERO,
+    };
+    assert!(jobs::run_next(&sessions, &backoff).await);
+    let job = sessions.lock().storage.job(job_id).unwrap().unwrap();
+    assert_eq!((job.status, job.attempts), (JobStatus::Queued, 1));
+    assert!(job.last_error.unwrap().contains("access to moves"));
+
+    // Nothing is claimed while the whole database fails, and the job is not lost.
+    faults.fail_storage(StorageFault::All);
+    assert!(!jobs::run_next(&sessions, &backoff).await);
+    faults.heal_storage();
+    assert!(jobs::run_next(&sessions, &backoff).await);
+    let sessions = sessions.lock();
+    let job = sessions.storage.job(job_id).unwrap().unwrap();
+    assert_eq!((job.status, job.attempts), (JobStatus::Done, 2));
+    assert!(sessions.analysis_report(&id).unwrap().is_some());
+}
+
+#[cfg(all(feature = "chaos", feature = "client"))]
+#[tokio::test]
+async fn test_chaos_client_reconnects_after_lost_frames() {
+    use kawio::client::Client;
+    use kawio::protocol::ServerMsg;
+    use std::time::Instant;
+
+    let sessions = Sessions::new(Storage::new(":memory:").unwrap());
+    let faults = sessions.faults().clone();
+    let sessions = Arc::new(Mutex::new(sessions));
+    let app = kawio::network::create_router(sessions.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let mut alice = Client::new(&format!("http://{address}"));
+    alice.login("Alice").await.unwrap();
+    let id = alice.create_match("Bob").await.unwrap();
+
+    // The state sent on connecting is lost, so the pong is the first frame to arrive.
+    faults.drop_frames(1);
+    let mut stream = alice.connect(&id).await.unwrap();
+    stream.ping().await.unwrap();
+    assert!(matches!(stream.next_event().await, Some(Ok(ServerMsg::Pong))));
+    assert_eq!(faults.frames_to_drop(), 0);
+
+    // Leaving starts Alice's countdown to come back.
+    stream.close().await.unwrap();
+    while sessions.lock().countdowns(&id).is_empty() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // Reconnecting over a slow network brings the state back and stops the countdown.
+    faults.set_latency(Duration::from_millis(50));
+    let started = Instant::now();
+    let mut stream = alice.connect(&id).await.unwrap();
+    let Some(Ok(ServerMsg::State(state))) = stream.next_event().await else {
+        panic!("Expected the game's state on reconnecting");
+    };
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert_eq!(state.player1, "Alice");
+    assert!(sessions.lock().countdowns(&id).is_empty());
+
+    let started = Instant::now();
+    assert_eq!(alice.state(&id).await.unwrap().player2, "Bob");
+    assert!(started.elapsed() >= Duration::from_millis(50));
+}
+
+/// Collects what a log layer writes.
+struct WriterTo(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for WriterTo {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
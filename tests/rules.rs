@@ -0,0 +1,149 @@
+//! Property tests of the game rules.
+//!
+//! Games are played out with random legal moves, and every step is checked by diffing the
+//! position before and after it against the rules, independently of how the engine applies
+//! them.
+
+use kawio::game::{Game, Player};
+use proptest::prelude::*;
+
+/// Offsets of the eight directions, as (row, column) steps.
+const DIRECTIONS: [(i8, i8); 8] = [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)];
+
+/// What changed on the board between two positions, from the point of view of the player who
+/// moved.
+#[derive(Debug)]
+struct StateDiff {
+    /// Squares that were empty and hold a disc now.
+    placed: u64,
+    /// Squares that changed from the opponent's color to the mover's.
+    flipped: u64,
+    /// Squares that lost their disc or went over to the opponent, which never happens.
+    lost: u64,
+}
+
+impl StateDiff {
+    fn between(before: &Game, after: &Game, mover: Player) -> Self {
+        let (own_before, opp_before) = discs(before, mover);
+        let (own_after, opp_after) = discs(after, mover);
+        StateDiff {
+            placed: after.occupied() & before.empty(),
+            flipped: opp_before & own_after,
+            lost: (before.occupied() & after.empty()) | (own_before & opp_after),
+        }
+    }
+}
+
+/// Returns the mover's discs and the opponent's.
+fn discs(game: &Game, player: Player) -> (u64, u64) {
+    match player {
+        Player::Black => (game.black, game.white),
+        Player::White => (game.white, game.black),
+    }
+}
+
+fn square(row: i8, col: i8) -> Option<u64> {
+    ((0..8).contains(&row) && (0..8).contains(&col)).then(|| 1u64 << (row * 8 + col))
+}
+
+/// Returns the discs a move at `pos` must flip, walking each direction square by square.
+fn sandwiched(own: u64, opponent: u64, pos: u8) -> u64 {
+    let (row, col) = (i8::try_from(pos / 8).unwrap(), i8::try_from(pos % 8).unwrap());
+    let mut flips = 0;
+    for (dr, dc) in DIRECTIONS {
+        let mut run = 0;
+        let (mut r, mut c) = (row + dr, col + dc);
+        while let Some(bit) = square(r, c) {
+            if opponent & bit != 0 {
+                run |= bit;
+            } else {
+                if own & bit != 0 {
+                    flips |= run;
+                }
+                break;
+            }
+            r += dr;
+            c += dc;
+        }
+    }
+    flips
+}
+
+/// Plays one disc chosen by `choice`, checks the step against the rules, and plays the
+/// passes that follow.
+fn play_checked(game: &mut Game, choice: usize) -> Result<(), TestCaseError> {
+    let moves = game.legal_moves();
+    prop_assert_eq!(game.legal_move_bitboard(), game.naive_legal_move_bitboard());
+    prop_assert!(!moves.is_empty());
+    prop_assert!(!game.is_game_over());
+
+    let pos = moves[choice % moves.len()];
+    let mover = game.current_player;
+    let before = game.clone();
+    game.place(pos).map_err(TestCaseError::fail)?;
+    let diff = StateDiff::between(&before, game, mover);
+    let (own, opponent) = discs(&before, mover);
+
+    prop_assert_eq!(diff.placed, 1u64 << pos);
+    prop_assert_eq!(diff.lost, 0);
+    prop_assert_ne!(diff.flipped, 0);
+    prop_assert_eq!(diff.flipped, sandwiched(own, opponent, pos));
+    prop_assert_eq!(game.occupied().count_ones(), before.occupied().count_ones() + 1);
+    prop_assert_eq!(game.black & game.white, 0);
+    prop_assert_eq!(game.current_player, mover.opponent());
+
+    // A side may only be passed for when it has no legal move.
+    let passed = game.auto_pass();
+    for &player in &passed {
+        prop_assert_eq!(sandwiched_moves(game, player), 0);
+    }
+    if passed.len() == 2 {
+        prop_assert!(game.is_game_over());
+    } else {
+        prop_assert_ne!(sandwiched_moves(game, game.current_player), 0);
+    }
+    Ok(())
+}
+
+/// Returns the squares where `player` could move, by the reference rules.
+fn sandwiched_moves(game: &Game, player: Player) -> u64 {
+    let (own, opponent) = discs(game, player);
+    (0..64u8)
+        .filter(|&pos| game.occupied() & (1u64 << pos) == 0 && sandwiched(own, opponent, pos) != 0)
+        .fold(0, |moves, pos| moves | (1u64 << pos))
+}
+
+proptest! {
+    #[test]
+    fn random_playouts_follow_the_rules(choices in prop::collection::vec(any::<usize>(), 60)) {
+        let mut game = Game::new();
+        for choice in choices {
+            if game.is_game_over() {
+                break;
+            }
+            play_checked(&mut game, choice)?;
+        }
+        // Every move fills a square, so 60 moves always end the game.
+        prop_assert!(game.is_game_over());
+        prop_assert!(game.legal_moves().is_empty());
+        let (black, white) = game.disc_count();
+        let expected = match black.cmp(&white) {
+            std::cmp::Ordering::Greater => Some(Player::Black),
+            std::cmp::Ordering::Less => Some(Player::White),
+            std::cmp::Ordering::Equal => None,
+        };
+        prop_assert_eq!(game.winner(), expected);
+    }
+
+    #[test]
+    fn bitboard_moves_match_naive_moves(black in any::<u64>(), white in any::<u64>(), white_to_move in any::<bool>()) {
+        let player = if white_to_move { Player::White } else { Player::Black };
+        let game = Game::from_bitboards(black, white & !black, player, 0);
+        prop_assert_eq!(game.legal_move_bitboard(), game.naive_legal_move_bitboard());
+        prop_assert_eq!(game.legal_move_bitboard(), sandwiched_moves(&game, player));
+        for pos in game.legal_moves() {
+            let (own, opponent) = discs(&game, player);
+            prop_assert_eq!(game.flips(pos), sandwiched(own, opponent, pos));
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! The kawio engine compiled to WebAssembly, so the web UI can play offline and give hints
+//! without asking the server.
+//!
+//! Build it with `wasm-pack build wasm --target web --out-dir ../web/pkg`; the web UI loads
+//! `pkg/kawio_wasm.js` when it is there and carries on without it when it is not.
+
+use kawio::ai::{AiConfig, MctsAi};
+use kawio::game::{Game, Move};
+use serde_json::json;
+use wasm_bindgen::prelude::*;
+
+/// A game against the MCTS AI, kept in the page.
+#[wasm_bindgen]
+pub struct Engine {
+    game: Game,
+    ai: MctsAi,
+}
+
+#[wasm_bindgen]
+impl Engine {
+    /// Starts a game from the opening position, the AI searching `simulations` playouts a
+    /// move. A `seed` makes its moves repeatable; without one it is seeded by the browser.
+    #[wasm_bindgen(constructor)]
+    #[must_use]
+    pub fn new(simulations: u32, seed: Option<u64>) -> Engine {
+        let config = AiConfig {
+            simulations,
+            rng_seed: seed,
+            ..AiConfig::default()
+        };
+        Engine {
+            game: Game::new(),
+            ai: MctsAi::new(config),
+        }
+    }
+
+    /// Sets up a position given as a 64-square string plus the side to move, as read by
+    /// [`Game::from_position_string`], e.g. to hint in a game played on the server.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the position cannot be read.
+    #[wasm_bindgen(js_name = setPosition)]
+    pub fn set_position(&mut self, position: &str) -> Result<(), String> {
+        self.game = Game::from_position_string(position)?;
+        Ok(())
+    }
+
+    /// Returns the position as a 64-square string plus the side to move.
+    #[must_use]
+    pub fn position(&self) -> String {
+        self.game.to_position_string()
+    }
+
+    /// Plays a square such as `"D3"`, or `"pass"`, for the side to move. A side left without
+    /// a legal move passes at once, as in games on the server.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the move cannot be read or is illegal.
+    pub fn play(&mut self, mv: &str) -> Result<(), String> {
+        let mv: Move = mv.parse()?;
+        if mv == Move::Pass && self.game.legal_move_bitboard() != 0 {
+            return Err("Cannot pass with a legal move".to_string());
+        }
+        self.game.make_move_enum(mv)?;
+        self.ai.make_move(mv);
+        Ok(())
+    }
+
+    /// Searches the position and returns the AI's move for the side to move, a square or
+    /// `"pass"`, without playing it, or `undefined` once the game is over.
+    #[wasm_bindgen(js_name = bestMove)]
+    pub fn best_move(&mut self) -> Option<String> {
+        if self.game.is_game_over() {
+            return None;
+        }
+        self.ai.get_move(&self.game).map(|mv| mv.to_string())
+    }
+
+    /// Returns the game in the shape of the server's state messages: `board`, `legal_moves`,
+    /// `current_player`, `scores`, `game_over` and `winner`, as JSON.
+    #[must_use]
+    pub fn state(&self) -> String {
+        let board: Vec<Vec<&str>> = (0..8)
+            .map(|row| {
+                (0..8)
+                    .map(|col| {
+                        let bit = 1u64 << (row * 8 + col);
+                        if self.game.black & bit != 0 {
+                            "B"
+                        } else if self.game.white & bit != 0 {
+                            "W"
+                        } else {
+                            "."
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let (black, white) = self.game.scores();
+        json!({
+            "board": board,
+            "legal_moves": self.game.legal_moves_iter().map(Game::pos_to_coord).collect::<Vec<_>>(),
+            "current_player": self.game.current_player.as_str(),
+            "scores": { "B": black, "W": white },
+            "game_over": self.game.is_game_over(),
+            "winner": self.game.winner().map(|winner| winner.as_str()),
+        })
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine_plays_a_game() {
+        let mut engine = Engine::new(20, Some(1));
+        assert!(engine.play("A1").is_err());
+        assert!(engine.play("pass").is_err());
+        let mut plies = 0;
+        while let Some(mv) = engine.best_move() {
+            engine.play(&mv).unwrap();
+            plies += 1;
+        }
+        assert!(plies >= 30);
+        let state: serde_json::Value = serde_json::from_str(&engine.state()).unwrap();
+        assert_eq!(state["game_over"], true);
+        assert_eq!(state["legal_moves"].as_array().map(Vec::len), Some(0));
+
+        let position = Game::new().to_position_string();
+        engine.set_position(&position).unwrap();
+        assert_eq!(engine.position(), position);
+        assert!(engine.set_position("X").is_err());
+    }
+}
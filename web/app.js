@@ -5,9 +5,15 @@ document.addEventListener('DOMContentLoaded', () => {
     const playerBlackElement = document.getElementById('player-black');
     const playerWhiteElement = document.getElementById('player-white');
     const newGameBtn = document.getElementById('new-game-btn');
+    const opponentSelect = document.getElementById('opponent-select');
+    const colorSelect = document.getElementById('color-select');
+    const casualCheckbox = document.getElementById('casual-checkbox');
+    const privateCheckbox = document.getElementById('private-checkbox');
+    const coachCheckbox = document.getElementById('coach-checkbox');
     const loginBtn = document.getElementById('login-btn');
     const submitLoginBtn = document.getElementById('submit-login-btn');
     const loginModal = document.getElementById('login-modal');
+    const oauthProviders = document.getElementById('oauth-providers');
     const playerNameInput = document.getElementById('player-name'); // Added for focus
     const gameOverModal = document.getElementById('game-over-modal');
     const gameOverMessage = document.getElementById('game-over-message');
@@ -16,14 +22,46 @@ document.addEventListener('DOMContentLoaded', () => {
     const logoutBtn = document.getElementById('logout-btn');
     const passMoveModal = document.getElementById('pass-move-modal'); // New modal for pass
     const passMoveBtn = document.getElementById('pass-move-btn'); // New button for pass
+    const hintBtn = document.getElementById('hint-btn');
+
+    // Opponent value of games against the engine running in the page.
+    const LOCAL_OPPONENT = 'local';
+    const LOCAL_SIMULATIONS = 2000;
 
     let token = null;
     let currentGameId = null;
+    let currentInvite = null; // The invite to the current game, if it is private
     let ws = null;
     let loggedInPlayerName = ''; // To store the logged-in player's name
+    let messages = {}; // Templates of status and error messages, by code
+    let wasm = null; // The engine compiled to WebAssembly, if web/pkg was built
+    let localEngine = null; // The game against the in-page engine, while one is played
+    let lastState = null; // The latest state shown, to hint from
+
+    fetch('/messages')
+        .then(response => response.ok ? response.json() : {})
+        .then(catalog => { messages = catalog; })
+        .catch(error => console.error('Failed to load messages:', error));
+
+    fetch('/engines')
+        .then(response => response.ok ? response.json() : { engines: [] })
+        .then(data => data.engines.forEach(name => opponentSelect.add(new Option(name, name))))
+        .catch(error => console.error('Failed to load engines:', error));
+
+    // The engine is only there if it was built with wasm-pack; without it there are no hints
+    // and no offline games.
+    import('./pkg/kawio_wasm.js')
+        .then(async module => {
+            await module.default();
+            wasm = module;
+            opponentSelect.add(new Option('AI (offline)', LOCAL_OPPONENT));
+            hintBtn.style.display = 'inline-block';
+        })
+        .catch(() => console.log('Engine not built; hints and offline play are off.'));
 
     // --- Event Listeners ---
     newGameBtn.addEventListener('click', createMatch);
+    hintBtn.addEventListener('click', showHint);
     loginBtn.addEventListener('click', () => {
         loginModal.classList.remove('hidden');
         playerNameInput.focus(); // Set focus on textbox
@@ -42,7 +80,7 @@ document.addEventListener('DOMContentLoaded', () => {
     passMoveBtn.addEventListener('click', () => {
         passMoveModal.classList.add('hidden');
         if (ws && ws.readyState === WebSocket.OPEN) {
-            ws.send(JSON.stringify({type: "pass"}));
+            ws.send(JSON.stringify({type: "pass", ply: lastState ? lastState.ply : undefined}));
         }
     });
 
@@ -60,12 +98,9 @@ document.addEventListener('DOMContentLoaded', () => {
 
             if (response.ok) {
                 const data = await response.json();
-                token = data.token;
-                loggedInPlayerName = playerName; // Store player name
-                loginModal.classList.add('hidden');
-                loginBtn.textContent = `Logged in as ${playerName}`;
-                loginBtn.disabled = true;
-                logoutBtn.style.display = 'inline-block';
+                loggedIn(data.token, playerName);
+            } else if (response.status === 403) {
+                alert('This name logs in through a provider.');
             } else {
                 alert('Login failed');
             }
@@ -75,9 +110,51 @@ document.addEventListener('DOMContentLoaded', () => {
         }
     }
 
+    function loggedIn(newToken, playerName) {
+        token = newToken;
+        loggedInPlayerName = playerName; // Store player name
+        loginModal.classList.add('hidden');
+        loginBtn.textContent = `Logged in as ${playerName}`;
+        loginBtn.disabled = true;
+        logoutBtn.style.display = 'inline-block';
+        loadPreferences();
+    }
+
+    // Offers a link to log in with each identity provider the server knows.
+    function loadOAuthProviders() {
+        fetch('/auth/oauth')
+            .then(response => response.ok ? response.json() : { providers: [] })
+            .then(({ providers }) => {
+                for (const provider of providers) {
+                    const link = document.createElement('a');
+                    link.href = `/auth/oauth/${encodeURIComponent(provider)}`;
+                    link.textContent = `Log in with ${provider}`;
+                    oauthProviders.appendChild(link);
+                }
+            })
+            .catch(() => console.log('Logging in with providers is off.'));
+    }
+
+    // Starts the game options as the player prefers them.
+    async function loadPreferences() {
+        try {
+            const response = await fetch('/me/preferences', {
+                headers: { 'Authorization': `Bearer ${token}` }
+            });
+            if (response.ok) {
+                const preferences = await response.json();
+                coachCheckbox.checked = preferences.coach;
+            }
+        } catch (error) {
+            console.error('Preferences error:', error);
+        }
+    }
+
     function logout() {
         token = null;
         currentGameId = null;
+        currentInvite = null;
+        localEngine = null;
         loggedInPlayerName = '';
         if (ws) {
             ws.close();
@@ -98,6 +175,10 @@ document.addEventListener('DOMContentLoaded', () => {
     }
 
     async function createMatch() {
+        if (opponentSelect.value === LOCAL_OPPONENT) {
+            startLocalGame();
+            return;
+        }
         if (!token) {
             alert('You must be logged in to start a new game.');
             loginModal.classList.remove('hidden');
@@ -112,14 +193,28 @@ document.addEventListener('DOMContentLoaded', () => {
                     'Content-Type': 'application/json',
                     'Authorization': `Bearer ${token}`
                 },
-                body: JSON.stringify({ player2: 'AI' }) // Or implement player selection
+                body: JSON.stringify({
+                    player2: opponentSelect.value,
+                    game_type: casualCheckbox.checked ? 'casual' : 'ranked',
+                    private: privateCheckbox.checked,
+                    coach: coachCheckbox.checked,
+                    color: colorSelect.value
+                })
             });
 
             if (response.ok) {
                 const data = await response.json();
                 currentGameId = data.id;
-                gameStatus.textContent = '';
+                currentInvite = data.invite || null;
+                localEngine = null;
+                gameStatus.textContent = data.invite_url
+                    ? `Share this link: ${new URL(data.invite_url, window.location.origin)}`
+                    : '';
                 startGame();
+            } else if (response.status === 503) {
+                alert(renderNotice({ code: 'under_maintenance' }));
+            } else if (response.status === 400 && coachCheckbox.checked && !casualCheckbox.checked) {
+                alert(renderNotice({ code: 'coach_not_allowed' }));
             } else {
                 alert('Failed to create match');
             }
@@ -133,13 +228,39 @@ document.addEventListener('DOMContentLoaded', () => {
         connectWebSocket();
     }
 
-    function connectWebSocket() {
+    // Browsers cannot send the token as a header when opening a socket, and would leave it
+    // in the URL; a ticket opens one socket and expires in seconds.
+    async function fetchSocketTicket() {
+        const response = await fetch('/auth/ws-ticket', {
+            method: 'POST',
+            headers: { 'Authorization': `Bearer ${token}` }
+        });
+        if (!response.ok) {
+            throw new Error(`Ticket refused: ${response.status}`);
+        }
+        return (await response.json()).ticket;
+    }
+
+    async function connectWebSocket() {
         if (ws) {
             ws.close();
         }
         
         const wsProtocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
-        ws = new WebSocket(`${wsProtocol}//${window.location.host}/match/${currentGameId}/ws`);
+        const params = new URLSearchParams();
+        if (token) {
+            try {
+                params.set('ticket', await fetchSocketTicket());
+            } catch (error) {
+                console.error('Socket ticket error:', error);
+                return;
+            }
+        }
+        if (currentInvite) {
+            params.set('invite', currentInvite);
+        }
+        const query = params.toString() ? `?${params}` : '';
+        ws = new WebSocket(`${wsProtocol}//${window.location.host}/match/${currentGameId}/ws${query}`);
 
         ws.onopen = () => {
             console.log('WebSocket connection established.');
@@ -148,10 +269,30 @@ document.addEventListener('DOMContentLoaded', () => {
         ws.onmessage = (event) => {
             console.log("Received message from server:", event.data);
             const message = JSON.parse(event.data);
-            if (message.type === 'status') {
-                gameStatus.textContent = message.message;
-            } else {
-                updateUI(message);
+            switch (message.type) {
+                case 'state':
+                    updateUI(message);
+                    break;
+                case 'status':
+                case 'error':
+                    gameStatus.textContent = renderNotice(message);
+                    break;
+                case 'rejected':
+                    // The unchanged state follows, undoing anything shown early.
+                    gameStatus.textContent = message.coord
+                        ? `${message.coord}: ${renderNotice(message)}`
+                        : renderNotice(message);
+                    break;
+                case 'chat':
+                    console.log(`${message.from}: ${message.text}`);
+                    break;
+                case 'coach':
+                    gameStatus.textContent = coachAdvice(message);
+                    break;
+                default:
+                    // Deltas, joins and leaves are followed by the full state; pongs need no
+                    // answer.
+                    break;
             }
         };
 
@@ -164,17 +305,98 @@ document.addEventListener('DOMContentLoaded', () => {
         };
     }
 
+    // --- Offline Games & Hints ---
+    // The player takes Black against the in-page engine; nothing is sent to the server.
+    function startLocalGame() {
+        if (ws) {
+            ws.close();
+            ws = null;
+        }
+        currentGameId = null;
+        currentInvite = null;
+        localEngine = new wasm.Engine(LOCAL_SIMULATIONS);
+        gameStatus.textContent = '';
+        showLocalState();
+    }
+
+    function showLocalState() {
+        const state = JSON.parse(localEngine.state());
+        state.player1 = loggedInPlayerName || 'You';
+        state.player2 = 'AI';
+        if (state.winner) {
+            state.winner = state.winner === 'Black' ? state.player1 : state.player2;
+        }
+        updateUI(state);
+        return state;
+    }
+
+    function playLocalMove(coord) {
+        localEngine.play(coord);
+        const state = showLocalState();
+        if (state.game_over || state.current_player !== 'White') {
+            return;
+        }
+        gameStatus.textContent = 'AI is thinking…';
+        // Let the board repaint before the search blocks the page.
+        setTimeout(() => {
+            const reply = localEngine.bestMove();
+            if (reply) {
+                playLocalMove(reply);
+            }
+        }, 50);
+    }
+
+    // Reads the rows of a state, from rank 8 down, into the 64-square string the engine
+    // takes, from A1 to H8.
+    function positionString(state) {
+        let squares = '';
+        for (let rank = 1; rank <= 8; rank++) {
+            for (const piece of state.board[8 - rank]) {
+                squares += piece === 'B' ? 'X' : piece === 'W' ? 'O' : '-';
+            }
+        }
+        return `${squares} ${state.current_player === 'Black' ? 'X' : 'O'}`;
+    }
+
+    // Marks the engine's move for the side to move, searched in the page.
+    function showHint() {
+        if (!wasm || !lastState || lastState.game_over || lastState.legal_moves.length === 0) {
+            return;
+        }
+        let engine = localEngine;
+        if (!engine) {
+            engine = new wasm.Engine(LOCAL_SIMULATIONS);
+            engine.setPosition(positionString(lastState));
+        }
+        const hint = engine.bestMove();
+        if (!hint || hint === 'pass') {
+            return;
+        }
+        const col = hint.charCodeAt(0) - 65;
+        const row = 8 - Number(hint.slice(1));
+        const cell = boardElement.querySelector(`[data-row="${row}"][data-col="${col}"]`);
+        if (cell) {
+            cell.classList.add('hint');
+        }
+    }
+
     async function makeMove(row, col) {
         console.log(`makeMove called for row: ${row}, col: ${col}`);
+        if (localEngine) {
+            playLocalMove(String.fromCharCode(65 + col) + (8 - row));
+            return;
+        }
         if (!ws || ws.readyState !== WebSocket.OPEN) {
             console.error("WebSocket is not connected.");
             return;
         }
 
         const coord = String.fromCharCode(65 + col) + (8 - row);
+        // The ply of the state shown, so a move sent for a turn already played is refused.
         const moveMessage = {
             type: 'move',
             coord: coord,
+            ply: lastState ? lastState.ply : undefined,
         };
         
         console.log("Sending move message:", moveMessage);
@@ -184,8 +406,9 @@ document.addEventListener('DOMContentLoaded', () => {
     // --- UI Update Functions ---
     function updateUI(state) {
         console.log("Updating UI with new state:", state);
+        lastState = state;
         renderBoard(state.board, state.legal_moves);
-        updateScores(state.scores);
+        updateScores(state.scores, state.komi);
         updateTurnIndicator(state.current_player, state.player1, state.player2);
 
         if (state.game_over) {
@@ -197,10 +420,55 @@ document.addEventListener('DOMContentLoaded', () => {
             // Human has no moves, show pass dialog
             passMoveModal.classList.remove('hidden');
         } else {
-            gameStatus.textContent = '';
+            gameStatus.textContent = presenceStatus(state);
         }
     }
 
+    // Warns when the opponent has lost their connection to the game.
+    function renderNotice(notice) {
+        const params = notice.params || {};
+        const template = messages[notice.code] || notice.code;
+        return template.replace(/\{(\w+)\}/g, (match, name) => params[name] ?? match);
+    }
+
+    // Says how good the player's last move was, with losses given in discs.
+    function coachAdvice(analysis) {
+        if (analysis.loss === 0) {
+            return `${analysis.played} was the best move.`;
+        }
+        const discs = (analysis.loss / 100).toFixed(1);
+        const blunder = analysis.blunder ? 'Blunder: ' : '';
+        return `${blunder}${analysis.played} lost ${discs} discs, ${analysis.best} was better.`;
+    }
+
+    function presenceStatus(state) {
+        const presence = state.presence;
+        if (!presence) {
+            return '';
+        }
+        let opponentConnected = true;
+        if (state.player1 === loggedInPlayerName) {
+            opponentConnected = presence.player2_connected;
+        } else if (state.player2 === loggedInPlayerName) {
+            opponentConnected = presence.player1_connected;
+        }
+        let status = '';
+        if (!opponentConnected) {
+            const countdown = (presence.countdowns || []).find(
+                (c) => c.player !== loggedInPlayerName);
+            status = 'Opponent disconnected… reconnecting';
+            if (countdown) {
+                const outcome = countdown.abort ? 'aborted' : 'won by forfeit';
+                status += ` (game ${outcome} in ${countdown.seconds}s)`;
+            }
+        }
+        if (presence.spectators === 0) {
+            return status;
+        }
+        const watching = `${presence.spectators} watching`;
+        return status ? `${status} · ${watching}` : watching;
+    }
+
     function renderBoard(board, legalMoves) {
         console.log("Rendering board. Legal moves:", legalMoves);
         boardElement.innerHTML = '';
@@ -235,9 +503,10 @@ document.addEventListener('DOMContentLoaded', () => {
         }
     }
 
-    function updateScores(scores) {
-        scoreBlackElement.textContent = scores.B;
-        scoreWhiteElement.textContent = scores.W;
+    // Komi is shown next to the side it is given to.
+    function updateScores(scores, komi) {
+        scoreBlackElement.textContent = komi < 0 ? `${scores.B} +${-komi}` : scores.B;
+        scoreWhiteElement.textContent = komi > 0 ? `${scores.W} +${komi}` : scores.W;
     }
 
     function updateTurnIndicator(currentPlayer, player1Name, player2Name) {
@@ -263,6 +532,20 @@ document.addEventListener('DOMContentLoaded', () => {
         // On load, we can just show the login button and wait for user action.
         // Or attempt to validate an existing token from localStorage.
         console.log('Othello UI Initialized.');
+        loadOAuthProviders();
+        // A login through a provider comes back with the token in the fragment.
+        const fragment = new URLSearchParams(window.location.hash.slice(1));
+        if (fragment.has('token')) {
+            loggedIn(fragment.get('token'), fragment.get('player'));
+            history.replaceState(null, '', window.location.pathname + window.location.search);
+        }
+        // A shared link opens its game, private ones with the invite it carries.
+        const params = new URLSearchParams(window.location.search);
+        if (params.has('game')) {
+            currentGameId = params.get('game');
+            currentInvite = params.get('invite');
+            startGame();
+        }
     }
 
     initialize();